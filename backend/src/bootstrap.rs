@@ -1,7 +1,8 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
     middleware,
     routing::{get, post},
@@ -12,19 +13,24 @@ use tokio::sync::RwLock;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::info;
 
+use crate::config::declarative::DeclarativeConfig;
 use crate::config::ConfigManager;
 use crate::db::Database;
-use crate::dns::{CacheConfig, CacheManager, DnsResolver, ProxyManager, RewriteEngine, UpstreamManager};
+use crate::dns::{CacheConfig, CacheManager, DnsResolver, ProxyManager, RewriteEngine, UpstreamManager, DynamicUpdates, ZoneTransfers};
 use crate::dns::server::DohDnsServer;
 use crate::log::{LogConfig, LogManager};
 use crate::state::AppState;
 use crate::services::alert_manager::AlertManager;
+use crate::services::config_file::{sync_file, ConfigFileManager};
+use crate::services::config_transfer::ConfigTransfer;
+use crate::services::hosts_sync::HostsSync;
 use crate::services::listener_manager::ListenerManager;
 use crate::web::{
-    auth_middleware, cache_router, dns_query_router, fallback_handler, index_handler,
-    logs_router, records_router, rewrite_router, settings_router, static_handler, status_router,
-    strategy_router, upstreams_router, AuthService, AuthState, CacheState, DnsQueryState,
-    LogsState, RecordsState, RewriteState, SettingsState, StatusState, StrategyState, UpstreamsState,
+    auth_middleware, cache_router, config_file_router, config_transfer_router, dns_query_router, fallback_handler, index_handler,
+    logs_router, passive_dns_router, records_router, rewrite_router, settings_router, static_handler, status_router,
+    strategy_router, tsig_keys_router, upstreams_router, zones_router, AuthService, AuthState, CacheState, ConfigFileState, ConfigTransferState, DnsQueryState,
+    LogsState, PassiveDnsState, RecordsState, RewriteState, SettingsState, StatusState, StrategyState, TsigKeysState, UpstreamsState,
+    ZonesState,
 };
 
 pub async fn run() -> Result<()> {
@@ -49,6 +55,17 @@ pub async fn run() -> Result<()> {
     let db = Arc::new(Database::new(&app_config.database_url).await?);
     info!("Database initialized");
 
+    // Reconcile the declarative config file before anything reads the database
+    let config_file_report = match app_config.declarative_config {
+        Some(ref path) => {
+            let report = sync_file(path, &db).await
+                .with_context(|| format!("Invalid declarative config {}", path.display()))?;
+            info!("Declarative config synced from {}", path.display());
+            Some(report)
+        }
+        None => None,
+    };
+
     // Create log manager for cleanup operations
     let log_manager = Arc::new(LogManager::new(log_config));
 
@@ -78,7 +95,20 @@ pub async fn run() -> Result<()> {
     upstream_manager.load_servers().await?;
     info!("Upstream manager initialized ({} servers loaded)", upstream_manager.server_count().await);
 
-    let proxy = Arc::new(ProxyManager::new(upstream_manager.clone()));
+    // dnstap mirrors listener and upstream exchanges to an external collector
+    let dnstap_config = match db.system_config().get("dnstap").await? {
+        Some(value) => serde_json::from_str::<crate::dns::DnstapConfig>(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|config| config.validate().map(|_| config))
+            .unwrap_or_else(|e| {
+                tracing::warn!("Invalid dnstap settings, using defaults: {}", e);
+                Default::default()
+            }),
+        None => Default::default(),
+    };
+    let dnstap = crate::dns::Dnstap::start(dnstap_config);
+
+    let proxy = Arc::new(ProxyManager::new(upstream_manager.clone()).with_dnstap(dnstap.clone()));
 
     // Load query strategy from database
     if let Some(strategy_str) = db.system_config().get("query_strategy").await? {
@@ -88,17 +118,162 @@ pub async fn run() -> Result<()> {
         }
     }
 
+    // Load anti-pollution routing settings from database
+    if let Some(value) = db.system_config().get("anti_pollution").await? {
+        match serde_json::from_str::<crate::dns::proxy::AntiPollutionConfig>(&value) {
+            Ok(ap_config) => match proxy.set_anti_pollution(&ap_config).await {
+                Ok(()) if ap_config.enabled => info!("Anti-pollution routing enabled (country: {})", ap_config.country),
+                Ok(()) => {}
+                Err(e) => tracing::warn!("Failed to enable anti-pollution routing: {}", e),
+            },
+            Err(e) => tracing::warn!("Invalid anti-pollution settings: {}", e),
+        }
+    }
+
+    // Load DoT connection pool settings from database
+    if let Some(value) = db.system_config().get("dot_pool").await? {
+        match serde_json::from_str::<crate::dns::proxy::DotPoolConfig>(&value) {
+            Ok(pool_config) => {
+                if let Err(e) = proxy.set_dot_pool(pool_config).await {
+                    tracing::warn!("Failed to apply DoT pool settings: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Invalid DoT pool settings: {}", e),
+        }
+    }
+
+    // Load UDP client settings from database
+    if let Some(value) = db.system_config().get("udp_client").await? {
+        match serde_json::from_str::<crate::dns::proxy::UdpClientConfig>(&value) {
+            Ok(udp_config) => {
+                if let Err(e) = proxy.set_udp_client(udp_config).await {
+                    tracing::warn!("Failed to apply UDP client settings: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Invalid UDP client settings: {}", e),
+        }
+    }
+
+    // Start active upstream health probes
+    Arc::new(crate::dns::proxy::HealthProber::new(proxy.clone())).start();
+
+    // Query logs are written in batches by a background writer
+    let log_writer_config = match db.system_config().get("query_log_writer").await? {
+        Some(value) => serde_json::from_str::<crate::db::QueryLogWriterConfig>(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|config| config.validate().map(|_| config))
+            .unwrap_or_else(|e| {
+                tracing::warn!("Invalid query log writer settings, using defaults: {}", e);
+                Default::default()
+            }),
+        None => Default::default(),
+    };
+    let log_writer = crate::db::QueryLogWriter::start(db.clone(), log_writer_config);
+
+    // Passive DNS keeps a deduplicated history of upstream answers
+    let passive_dns_config = match db.system_config().get("passive_dns").await? {
+        Some(value) => serde_json::from_str::<crate::db::PassiveDnsConfig>(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|config| config.validate().map(|_| config))
+            .unwrap_or_else(|e| {
+                tracing::warn!("Invalid passive DNS settings, using defaults: {}", e);
+                Default::default()
+            }),
+        None => Default::default(),
+    };
+    let passive_dns = crate::db::PassiveDnsRecorder::start(db.clone(), passive_dns_config);
+
+    // Per-minute/hour/day statistics that outlive raw query logs
+    let stats_rollup_config = match db.system_config().get("stats_rollup").await? {
+        Some(value) => serde_json::from_str::<crate::db::StatsRollupConfig>(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|config| config.validate().map(|_| config))
+            .unwrap_or_else(|e| {
+                tracing::warn!("Invalid stats rollup settings, using defaults: {}", e);
+                Default::default()
+            }),
+        None => Default::default(),
+    };
+    let stats_rollup = crate::db::StatsRollup::start(db.clone(), stats_rollup_config);
+
+    let query_stream = Arc::new(crate::db::QueryStream::new());
+
+    // Remote log shipping (syslog, NDJSON files, HTTP)
+    let log_sinks_config = match db.system_config().get("log_sinks").await? {
+        Some(value) => serde_json::from_str::<crate::db::LogSinksConfig>(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|config| config.validate().map(|_| config))
+            .unwrap_or_else(|e| {
+                tracing::warn!("Invalid log sink settings, using defaults: {}", e);
+                Default::default()
+            }),
+        None => Default::default(),
+    };
+    let log_sinks = crate::db::LogSinks::start(log_sinks_config);
+
+    // Outgoing zone transfers, NOTIFY and the secondary zone refresh loop
+    let zone_transfers = ZoneTransfers::start(db.clone());
+
     let resolver = Arc::new(DnsResolver::with_db(
         rewrite_engine.clone(),
         cache.clone(),
         proxy.clone(),
         db.clone(),
-    ));
+    ).with_log_writer(log_writer.clone())
+     .with_log_sinks(log_sinks.clone())
+     .with_passive_dns(passive_dns.clone())
+     .with_stats_rollup(stats_rollup.clone())
+     .with_query_stream(query_stream.clone())
+     .with_dnstap(dnstap.clone())
+     .with_zone_transfers(zone_transfers.clone())
+     .with_dynamic_updates(DynamicUpdates::new(db.clone())));
     info!("DNS resolver initialized");
 
+    // Serve local records and resolver settings from memory, rebuilt on every write
+    resolver.reload_local_data().await?;
+    resolver.watch_data_changes();
+
+    // Load upstream response filters from database
+    if let Some(value) = db.system_config().get("response_filter").await? {
+        match serde_json::from_str::<crate::dns::ResponseFilterConfig>(&value) {
+            Ok(filter_config) => {
+                if let Err(e) = resolver.set_response_filter(&filter_config).await {
+                    tracing::warn!("Failed to load response filters: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Invalid response filter settings: {}", e),
+        }
+    }
+
+    // Load query log privacy settings from database
+    if let Some(value) = db.system_config().get("query_privacy").await? {
+        match serde_json::from_str::<crate::dns::QueryPrivacyConfig>(&value) {
+            Ok(privacy_config) => {
+                if let Err(e) = resolver.set_privacy(&privacy_config).await {
+                    tracing::warn!("Failed to load query log privacy settings: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Invalid query log privacy settings: {}", e),
+        }
+    }
+
     // Initialize ListenerManager
     let listener_manager = Arc::new(ListenerManager::new(db.clone(), resolver.clone()));
 
+    // Track the declarative config file for reloads
+    let config_file_manager = match (app_config.declarative_config.clone(), config_file_report) {
+        (Some(path), Some(report)) => Some(Arc::new(ConfigFileManager::new(
+            path,
+            report,
+            db.clone(),
+            resolver.clone(),
+            rewrite_engine.clone(),
+            upstream_manager.clone(),
+            listener_manager.clone(),
+        ))),
+        _ => None,
+    };
+
 
     // Perform initial log cleanup
     match log_manager.cleanup_old_logs() {
@@ -160,6 +335,25 @@ pub async fn run() -> Result<()> {
     // Start enabled listeners using manager
     listener_manager.start_all_enabled().await;
 
+    // Mirror a hosts file into local records when enabled
+    let hosts_sync = Arc::new(HostsSync::new(db.clone()));
+    handles.push(hosts_sync.clone().start());
+
+    // Reload the declarative config file on SIGHUP
+    #[cfg(unix)]
+    if let Some(ref manager) = config_file_manager {
+        let manager = manager.clone();
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
+        handles.push(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading declarative config");
+                if let Err(e) = manager.reload().await {
+                    tracing::error!("Declarative config reload failed, keeping previous state: {:#}", e);
+                }
+            }
+        }));
+    }
+
     // Start DoH DNS server (integrated with web server)
     let doh_server = DohDnsServer::new(resolver.clone());
 
@@ -171,6 +365,11 @@ pub async fn run() -> Result<()> {
 
     // Create sub-routers (these have their own state types)
     let records_routes = records_router(RecordsState { db: db.clone() });
+    let zones_routes = zones_router(ZonesState {
+        db: db.clone(),
+        transfers: zone_transfers.clone(),
+    });
+    let tsig_keys_routes = tsig_keys_router(TsigKeysState { db: db.clone() });
     let rewrite_routes = rewrite_router(RewriteState {
         db: db.clone(),
         rewrite_engine: rewrite_engine.clone(),
@@ -190,12 +389,18 @@ pub async fn run() -> Result<()> {
         db: db.clone(),
         proxy_manager: proxy.clone(),
     });
-    let logs_routes = logs_router(LogsState { db: db.clone() });
+    let logs_routes = logs_router(LogsState {
+        db: db.clone(),
+        stream: query_stream.clone(),
+        resolver: resolver.clone(),
+    });
+    let passive_dns_routes = passive_dns_router(PassiveDnsState { db: db.clone() });
     let status_routes = status_router(StatusState {
         db: db.clone(),
         cache: cache.clone(),
         proxy_manager: proxy.clone(),
         upstream_manager: upstream_manager.clone(),
+        resolver: resolver.clone(),
         start_time: Arc::new(RwLock::new(std::time::Instant::now())),
     });
     let listeners_routes = crate::web::listeners_router(crate::web::ListenersState {
@@ -204,6 +409,22 @@ pub async fn run() -> Result<()> {
     });
     let settings_routes = settings_router(SettingsState {
         db: db.clone(),
+        resolver: resolver.clone(),
+        hosts_sync: hosts_sync.clone(),
+    });
+    let config_file_routes = config_file_router(ConfigFileState {
+        manager: config_file_manager.clone(),
+        db: db.clone(),
+    });
+    let config_transfer_routes = config_transfer_router(ConfigTransferState {
+        db: db.clone(),
+        transfer: Arc::new(ConfigTransfer::new(
+            db.clone(),
+            resolver.clone(),
+            rewrite_engine.clone(),
+            upstream_manager.clone(),
+            listener_manager.clone(),
+        )),
     });
     let doh_routes = doh_server.router();
     
@@ -232,15 +453,20 @@ pub async fn run() -> Result<()> {
     // Create protected API router (requires authentication)
     let protected_api = Router::new()
         .nest("/api/records", records_routes)
+        .nest("/api/zones", zones_routes)
+        .nest("/api/tsig-keys", tsig_keys_routes)
         .nest("/api/rewrite", rewrite_routes)
         .nest("/api/upstreams", upstreams_routes)
         .nest("/api/cache", cache_routes)
         .nest("/api/dns", dns_query_routes)
         .nest("/api/strategy", strategy_routes)
         .nest("/api/logs", logs_routes)
+        .nest("/api/passive-dns", passive_dns_routes)
         .nest("/api/status", status_routes)
         .nest("/api/listeners", listeners_routes)
         .nest("/api/settings", settings_routes)
+        .nest("/api/config-file", config_file_routes)
+        .nest("/api/config", config_transfer_routes)
         .nest("/api/llm", llm_routes)
         .layer(middleware::from_fn_with_state(auth_state.clone(), auth_middleware));
 
@@ -299,10 +525,52 @@ pub async fn run() -> Result<()> {
         handle.abort();
     }
 
+    // Write query logs that are still queued
+    log_writer.shutdown().await;
+    log_sinks.shutdown().await;
+    passive_dns.shutdown().await;
+    stats_rollup.shutdown().await;
+    dnstap.shutdown().await;
+
     info!("FluxDNS stopped");
     Ok(())
 }
 
+/// Validate the declarative config file without starting the server
+///
+/// Uses the file given on the command line, or `declarative_config` from the
+/// regular configuration. Returns whether the file is valid.
+pub fn check_config(path: Option<PathBuf>) -> Result<bool> {
+    let path = match path.or_else(|| ConfigManager::load().ok().and_then(|c| c.get().declarative_config)) {
+        Some(path) => path,
+        None => {
+            eprintln!("No declarative config file given (pass a path or set declarative_config)");
+            return Ok(false);
+        }
+    };
+
+    let config = match DeclarativeConfig::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {:#}", path.display(), e);
+            return Ok(false);
+        }
+    };
+    match config.validate() {
+        Ok(()) => {
+            println!("{}: OK", path.display());
+            Ok(true)
+        }
+        Err(problems) => {
+            for problem in &problems {
+                eprintln!("{}: {}", path.display(), problem);
+            }
+            eprintln!("{} problem(s) found", problems.len());
+            Ok(false)
+        }
+    }
+}
+
 /// Wait for shutdown signal (Ctrl+C or SIGTERM)
 async fn shutdown_signal() {
     let ctrl_c = async {
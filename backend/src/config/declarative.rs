@@ -0,0 +1,388 @@
+//! Declarative configuration file
+//!
+//! An optional TOML or YAML file (chosen by extension) describing upstreams,
+//! rewrite rules, local records, authoritative zones, listeners and settings. Every section that
+//! is present is reconciled into the database on startup and on reload; rows
+//! coming from the file are flagged as managed and read-only in the UI.
+//! Sections left out of the file stay under Web UI control.
+//!
+//! ```toml
+//! [[upstreams]]
+//! name = "cloudflare"
+//! address = "1.1.1.1:53"
+//! protocol = "udp"
+//!
+//! [[records]]
+//! name = "nas.home"
+//! record_type = "A"
+//! value = "192.168.1.10"
+//!
+//! [[zones]]
+//! origin = "home.arpa"
+//! primary_ns = "ns1.home.arpa"
+//! admin_email = "hostmaster@home.arpa"
+//!
+//! [[listeners]]
+//! protocol = "udp"
+//! port = 53
+//!
+//! [settings]
+//! query_strategy = "fastest"
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::web::records::CreateRecordRequest;
+use crate::web::rewrite::CreateRewriteRuleRequest;
+use crate::web::upstreams::CreateUpstreamServerRequest;
+use crate::web::zones::ZoneRequest;
+
+/// Protocols a listener can be declared for
+pub const LISTENER_PROTOCOLS: &[&str] = &["udp", "tcp", "dot", "doh", "doq", "doh3"];
+
+/// Declared DNS listener
+///
+/// Listeners always exist in the database, so listeners missing from the
+/// file are only released back to the UI, never deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeclarativeListener {
+    pub protocol: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    pub port: i32,
+    /// PEM certificate, or read from `tls_cert_file`
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    #[serde(default)]
+    pub tls_key: Option<String>,
+    /// Paths are relative to the configuration file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key_file: Option<PathBuf>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+/// Parsed declarative configuration file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeclarativeConfig {
+    pub upstreams: Option<Vec<CreateUpstreamServerRequest>>,
+    pub rewrite_rules: Option<Vec<CreateRewriteRuleRequest>>,
+    pub records: Option<Vec<CreateRecordRequest>>,
+    pub zones: Option<Vec<ZoneRequest>>,
+    pub listeners: Option<Vec<DeclarativeListener>>,
+    /// `system_config` keys; strings are stored as is, other values as JSON
+    pub settings: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+impl DeclarativeConfig {
+    /// Read and parse a configuration file
+    ///
+    /// TLS files referenced by listeners are read here, so the result is
+    /// self-contained.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read declarative config: {}", path.display()))?;
+        let mut config = Self::parse(&content, Self::is_yaml(path))
+            .with_context(|| format!("Failed to parse declarative config: {}", path.display()))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for listener in config.listeners.iter_mut().flatten() {
+            if let Some(ref file) = listener.tls_cert_file {
+                listener.tls_cert = Some(read_relative(base, file)?);
+            }
+            if let Some(ref file) = listener.tls_key_file {
+                listener.tls_key = Some(read_relative(base, file)?);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parse TOML or YAML content
+    pub fn parse(content: &str, yaml: bool) -> Result<Self> {
+        if yaml {
+            Ok(serde_yaml::from_str(content)?)
+        } else {
+            Ok(toml::from_str(content)?)
+        }
+    }
+
+    fn is_yaml(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref(),
+            Some("yaml") | Some("yml")
+        )
+    }
+
+    /// Check every entry, collecting all problems instead of stopping at the first
+    ///
+    /// Messages name the entry, e.g. `upstreams[1] (address): ...`.
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        let mut names = HashSet::new();
+        for (i, upstream) in self.upstreams.iter().flatten().enumerate() {
+            if let Err(e) = upstream.validate() {
+                problems.extend(e.errors.iter().map(|e| format!("upstreams[{}] ({}): {}", i, e.field, e.message)));
+            }
+            if !names.insert(upstream.name.trim().to_string()) {
+                problems.push(format!("upstreams[{}]: duplicate name '{}'", i, upstream.name));
+            }
+        }
+
+        let mut rules = HashSet::new();
+        for (i, rule) in self.rewrite_rules.iter().flatten().enumerate() {
+            if let Err(e) = rule.validate() {
+                problems.extend(e.errors.iter().map(|e| format!("rewrite_rules[{}] ({}): {}", i, e.field, e.message)));
+            }
+            if !rules.insert(rule_key(&rule.pattern, &rule.match_type)) {
+                problems.push(format!("rewrite_rules[{}]: duplicate {} rule '{}'", i, rule.match_type, rule.pattern));
+            }
+        }
+
+        let mut records = HashSet::new();
+        for (i, record) in self.records.iter().flatten().enumerate() {
+            if let Err(e) = record.validate() {
+                problems.extend(e.errors.iter().map(|e| format!("records[{}] ({}): {}", i, e.field, e.message)));
+            }
+            if !records.insert(record_key(&record.name, &record.record_type, &record.value)) {
+                problems.push(format!("records[{}]: duplicate {} record '{}'", i, record.record_type.to_uppercase(), record.name));
+            }
+        }
+
+        let mut origins = HashSet::new();
+        for (i, zone) in self.zones.iter().flatten().enumerate() {
+            match zone.clone().into_create_dns_zone() {
+                Ok(create) if !origins.insert(create.origin.clone()) => {
+                    problems.push(format!("zones[{}]: duplicate zone '{}'", i, create.origin));
+                }
+                Ok(_) => {}
+                Err(e) => problems.push(format!("zones[{}]: {}", i, e.message)),
+            }
+        }
+
+        let mut protocols = HashSet::new();
+        for (i, listener) in self.listeners.iter().flatten().enumerate() {
+            if let Err(e) = listener.validate() {
+                problems.push(format!("listeners[{}]: {}", i, e));
+            }
+            if !protocols.insert(listener.protocol.to_lowercase()) {
+                problems.push(format!("listeners[{}]: duplicate protocol '{}'", i, listener.protocol));
+            }
+        }
+
+        for (key, value) in self.settings.iter().flatten() {
+            if let Err(e) = validate_setting(key, value) {
+                problems.push(format!("settings.{}: {}", key, e));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+impl DeclarativeListener {
+    fn validate(&self) -> Result<()> {
+        let protocol = self.protocol.to_lowercase();
+        if !LISTENER_PROTOCOLS.contains(&protocol.as_str()) {
+            return Err(anyhow!("unknown protocol '{}', expected one of {}", self.protocol, LISTENER_PROTOCOLS.join(", ")));
+        }
+        if !(1..=65535).contains(&self.port) {
+            return Err(anyhow!("port must be between 1 and 65535"));
+        }
+        self.bind_address.parse::<std::net::IpAddr>()
+            .map_err(|_| anyhow!("invalid bind_address '{}'", self.bind_address))?;
+        if let Some(ref cert) = self.tls_cert {
+            if !cert.contains("-----BEGIN CERTIFICATE-----") {
+                return Err(anyhow!("tls_cert is not a PEM certificate"));
+            }
+        }
+        if let Some(ref key) = self.tls_key {
+            if !key.contains("-----BEGIN") {
+                return Err(anyhow!("tls_key is not a PEM private key"));
+            }
+        }
+        if self.enabled && !matches!(protocol.as_str(), "udp" | "tcp") && (self.tls_cert.is_none() || self.tls_key.is_none()) {
+            return Err(anyhow!("{} listener needs tls_cert and tls_key", protocol));
+        }
+        Ok(())
+    }
+}
+
+/// Value stored in `system_config` for a setting
+pub fn setting_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Check settings that have a known shape; other keys are stored as given
+fn validate_setting(key: &str, value: &serde_json::Value) -> Result<()> {
+    use crate::db::{LogSinksConfig, PassiveDnsConfig, QueryLogWriterConfig, StatsRollupConfig};
+    use crate::dns::proxy::{AntiPollutionConfig, DotPoolConfig, QueryStrategy, UdpClientConfig};
+    use crate::dns::{DnstapConfig, PtrSynthesisConfig, QueryPrivacy, QueryPrivacyConfig, ResponseFilter, ResponseFilterConfig};
+    use crate::services::hosts_sync::HostsSyncConfig;
+
+    fn parse<T: serde::de::DeserializeOwned>(value: &serde_json::Value) -> Result<T> {
+        Ok(serde_json::from_value(value.clone())?)
+    }
+
+    match key {
+        crate::services::config_file::MANAGED_SETTINGS_KEY => Err(anyhow!("reserved key")),
+        "cache_default_ttl" | "cache_max_entries" => setting_value(value).parse::<u64>()
+            .map(|_| ())
+            .map_err(|_| anyhow!("expected a non-negative integer")),
+        "query_strategy" => QueryStrategy::from_str(&setting_value(value))
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("unknown query strategy")),
+        "anti_pollution" => parse::<AntiPollutionConfig>(value).map(|_| ()),
+        "response_filter" => ResponseFilter::from_config(&parse::<ResponseFilterConfig>(value)?).map(|_| ()),
+        "query_privacy" => QueryPrivacy::from_config(&parse::<QueryPrivacyConfig>(value)?).map(|_| ()),
+        "dot_pool" => parse::<DotPoolConfig>(value)?.validate(),
+        "udp_client" => parse::<UdpClientConfig>(value)?.validate(),
+        "query_log_writer" => parse::<QueryLogWriterConfig>(value)?.validate(),
+        "passive_dns" => parse::<PassiveDnsConfig>(value)?.validate(),
+        "stats_rollup" => parse::<StatsRollupConfig>(value)?.validate(),
+        "dnstap" => parse::<DnstapConfig>(value)?.validate(),
+        "log_sinks" => parse::<LogSinksConfig>(value)?.validate(),
+        crate::dns::PTR_SYNTHESIS_CONFIG_KEY => parse::<PtrSynthesisConfig>(value).map(|_| ()),
+        crate::services::hosts_sync::CONFIG_KEY => parse::<HostsSyncConfig>(value)?.validate(),
+        _ => Ok(()),
+    }
+}
+
+/// Identity of a rewrite rule when matching file entries to rows
+pub fn rule_key(pattern: &str, match_type: &str) -> (String, String) {
+    (pattern.trim().to_lowercase(), match_type.to_lowercase())
+}
+
+/// Identity of a local record when matching file entries to rows
+pub fn record_key(name: &str, record_type: &str, value: &str) -> (String, String, String) {
+    (
+        name.trim().trim_end_matches('.').to_lowercase(),
+        record_type.to_uppercase(),
+        value.trim().to_string(),
+    )
+}
+
+fn read_relative(base: &Path, file: &Path) -> Result<String> {
+    let path = if file.is_absolute() { file.to_path_buf() } else { base.join(file) };
+    std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+[[upstreams]]
+name = "cloudflare"
+address = "1.1.1.1:53"
+protocol = "udp"
+
+[[rewrite_rules]]
+pattern = "ads.example.com"
+match_type = "exact"
+action_type = "block"
+
+[[records]]
+name = "nas.home"
+record_type = "A"
+value = "192.168.1.10"
+
+[[zones]]
+origin = "home.arpa"
+primary_ns = "ns1.home.arpa"
+admin_email = "hostmaster@home.arpa"
+nameservers = ["ns1.home.arpa", "ns2.home.arpa"]
+transfer_acl = ["10.0.0.0/8"]
+
+[[zones]]
+origin = "corp.example"
+kind = "secondary"
+primaries = ["192.0.2.1"]
+
+[[listeners]]
+protocol = "udp"
+port = 5353
+
+[settings]
+query_strategy = "fastest"
+cache_default_ttl = 120
+"#;
+
+    #[test]
+    fn test_parse_toml_and_yaml() {
+        let config = DeclarativeConfig::parse(TOML, false).unwrap();
+        assert_eq!(config.upstreams.as_ref().unwrap()[0].timeout, 5000);
+        assert_eq!(config.listeners.as_ref().unwrap()[0].bind_address, "0.0.0.0");
+        assert_eq!(config.zones.as_ref().unwrap()[0].nameservers.as_ref().unwrap().len(), 2);
+        assert_eq!(config.zones.as_ref().unwrap()[1].kind.as_deref(), Some("secondary"));
+        assert_eq!(setting_value(&config.settings.as_ref().unwrap()["cache_default_ttl"]), "120");
+        assert!(config.validate().is_ok());
+
+        let yaml = "records:\n  - name: nas.home\n    record_type: A\n    value: 192.168.1.10\n";
+        let config = DeclarativeConfig::parse(yaml, true).unwrap();
+        assert_eq!(config.records.unwrap().len(), 1);
+        assert!(config.upstreams.is_none());
+
+        assert!(DeclarativeConfig::parse("[[unknown]]\nx = 1\n", false).is_err());
+    }
+
+    #[test]
+    fn test_validate_collects_problems() {
+        let content = r#"
+[[upstreams]]
+name = "a"
+address = "1.1.1.1:53"
+protocol = "carrier-pigeon"
+
+[[records]]
+name = "x.home"
+record_type = "A"
+value = "10.0.0.1"
+
+[[records]]
+name = "X.home."
+record_type = "a"
+value = "10.0.0.1"
+
+[[zones]]
+origin = "home.arpa"
+
+[[listeners]]
+protocol = "dot"
+port = 853
+
+[settings]
+query_strategy = "slowest"
+"#;
+        let problems = DeclarativeConfig::parse(content, false).unwrap().validate().unwrap_err();
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(problems[0].starts_with("upstreams[0] (protocol)"));
+        assert!(problems[1].contains("duplicate A record"));
+        assert!(problems[2].starts_with("zones[0]: origin, primary_ns and admin_email are required"));
+        assert!(problems[3].contains("needs tls_cert"));
+        assert!(problems[4].starts_with("settings.query_strategy"));
+    }
+}
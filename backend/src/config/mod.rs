@@ -16,6 +16,12 @@
 //! - Upstream DNS servers
 //! - Cache settings
 //! - Query strategy
+//!
+//! They can also be described in an optional declarative file
+//! (`declarative_config`, see [`declarative`]) that is reconciled into the
+//! database on startup and on reload.
+
+pub mod declarative;
 
 use std::path::{Path, PathBuf};
 use std::sync::RwLock;
@@ -41,6 +47,9 @@ pub struct AppConfig {
     pub log_level: String,
     pub log_max_size: u64,
     pub log_retention_days: u32,
+
+    // Declarative configuration file (TOML or YAML), reconciled into the database
+    pub declarative_config: Option<PathBuf>,
 }
 
 impl Default for AppConfig {
@@ -54,6 +63,7 @@ impl Default for AppConfig {
             log_level: "warn".to_string(),
             log_max_size: 10 * 1024 * 1024, // 10MB
             log_retention_days: 30,
+            declarative_config: None,
         }
     }
 }
@@ -69,6 +79,7 @@ pub struct PartialConfig {
     pub log_level: Option<String>,
     pub log_max_size: Option<u64>,
     pub log_retention_days: Option<u32>,
+    pub declarative_config: Option<PathBuf>,
 }
 
 /// Configuration manager responsible for loading and providing access to configuration
@@ -147,6 +158,7 @@ impl ConfigManager {
             log_retention_days: std::env::var("LOG_RETENTION_DAYS")
                 .ok()
                 .and_then(|v| v.parse().ok()),
+            declarative_config: std::env::var("DECLARATIVE_CONFIG").ok().map(PathBuf::from),
         }
     }
 
@@ -185,6 +197,9 @@ impl ConfigManager {
         if let Some(v) = partial.log_retention_days {
             config.log_retention_days = v;
         }
+        if let Some(v) = partial.declarative_config {
+            config.declarative_config = Some(v);
+        }
     }
 }
 
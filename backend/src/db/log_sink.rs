@@ -0,0 +1,1069 @@
+//! Query Log Sinks
+//!
+//! Ships query logs to external systems as queries are answered: RFC 5424
+//! syslog over UDP/TCP/TLS, size-rotated NDJSON files and batched HTTP
+//! POSTs (Elasticsearch bulk, Loki push or a plain JSON array). Every sink
+//! has its own bounded queue, field selection, batching and retry policy.
+//! A slow or unreachable sink fills its own queue and drops (and counts)
+//! new entries; it never delays queries or the other sinks.
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Instant};
+use tokio_util::sync::CancellationToken;
+
+use super::CreateQueryLog;
+
+/// Hard upper bound of a sink queue
+const MAX_BUFFER_SIZE: usize = 100_000;
+/// Most sinks that can be configured
+const MAX_SINKS: usize = 16;
+/// Longest wait between two delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Connect (and TLS handshake) timeout of syslog streams
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fields a sink can ship; an empty selection ships all of them
+pub const LOG_SINK_FIELDS: &[&str] = &[
+    "timestamp",
+    "client_ip",
+    "query_name",
+    "query_type",
+    "response_code",
+    "response_time",
+    "cache_hit",
+    "upstream_used",
+    "decision",
+    "answers",
+    "source",
+    "source_id",
+    "reason",
+    "protocol",
+    "endpoint",
+];
+
+type QueuedLog = (CreateQueryLog, DateTime<Utc>);
+
+/// Where a sink ships logs to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSinkKind {
+    #[default]
+    Syslog,
+    /// Newline-delimited JSON file, rotated by size
+    File,
+    /// Batched HTTP POST
+    Http,
+}
+
+/// Syslog transport
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTransport {
+    /// One datagram per message (RFC 5426)
+    #[default]
+    Udp,
+    /// Octet-counted frames (RFC 6587)
+    Tcp,
+    /// Octet-counted frames over TLS (RFC 5425)
+    Tls,
+}
+
+/// Payload layout of the HTTP sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpSinkFormat {
+    /// JSON array of log objects
+    #[default]
+    Json,
+    /// Elasticsearch/OpenSearch `_bulk` NDJSON
+    Elasticsearch,
+    /// Grafana Loki push API
+    Loki,
+}
+
+/// Syslog sink settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogSinkConfig {
+    pub transport: SyslogTransport,
+    /// Collector as host:port
+    pub address: String,
+    /// Syslog facility (16 = local0)
+    pub facility: u8,
+    /// APP-NAME of every message
+    pub app_name: String,
+    /// HOSTNAME of every message (`-` when empty)
+    pub hostname: String,
+    /// Accept any certificate of a TLS collector
+    pub tls_skip_verify: bool,
+}
+
+impl Default for SyslogSinkConfig {
+    fn default() -> Self {
+        Self {
+            transport: SyslogTransport::Udp,
+            address: "127.0.0.1:514".to_string(),
+            facility: 16,
+            app_name: "fluxdns".to_string(),
+            hostname: String::new(),
+            tls_skip_verify: false,
+        }
+    }
+}
+
+/// NDJSON file sink settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileSinkConfig {
+    pub path: String,
+    /// Size at which the file is rotated
+    pub max_file_size_mb: u64,
+    /// Rotated files kept next to the current one
+    pub max_files: u32,
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        Self {
+            path: "query_logs.ndjson".to_string(),
+            max_file_size_mb: 100,
+            max_files: 5,
+        }
+    }
+}
+
+/// HTTP sink settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSinkConfig {
+    /// Endpoint, e.g. `http://es:9200/_bulk` or `http://loki:3100/loki/api/v1/push`
+    pub url: String,
+    pub format: HttpSinkFormat,
+    /// Target index of Elasticsearch bulk requests
+    pub index: String,
+    /// Stream labels of Loki pushes
+    pub labels: BTreeMap<String, String>,
+    /// Extra request headers (e.g. Authorization)
+    pub headers: BTreeMap<String, String>,
+    pub timeout_ms: u64,
+}
+
+impl Default for HttpSinkConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            format: HttpSinkFormat::Json,
+            index: "fluxdns-queries".to_string(),
+            labels: BTreeMap::from([("job".to_string(), "fluxdns".to_string())]),
+            headers: BTreeMap::new(),
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+/// One log sink (stored as part of `LogSinksConfig`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSinkConfig {
+    /// Unique name shown in the statistics
+    pub name: String,
+    pub enabled: bool,
+    pub kind: LogSinkKind,
+    /// Fields to ship (see `LOG_SINK_FIELDS`); empty ships all
+    pub fields: Vec<String>,
+    /// Entries the queue may hold before new ones are dropped
+    pub buffer_size: usize,
+    /// Entries shipped per batch
+    pub batch_size: usize,
+    /// Longest time an entry waits before being shipped
+    pub flush_interval_ms: u64,
+    /// Extra attempts for a failed batch before it is discarded
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub retry_backoff_ms: u64,
+    pub syslog: SyslogSinkConfig,
+    pub file: FileSinkConfig,
+    pub http: HttpSinkConfig,
+}
+
+impl Default for LogSinkConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            kind: LogSinkKind::Syslog,
+            fields: Vec::new(),
+            buffer_size: 10_000,
+            batch_size: 100,
+            flush_interval_ms: 1000,
+            max_retries: 3,
+            retry_backoff_ms: 1000,
+            syslog: SyslogSinkConfig::default(),
+            file: FileSinkConfig::default(),
+            http: HttpSinkConfig::default(),
+        }
+    }
+}
+
+impl LogSinkConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Sink name must not be empty"));
+        }
+        let prefix = |message: &str| anyhow!("Sink '{}': {}", self.name, message);
+        if !(100..=MAX_BUFFER_SIZE).contains(&self.buffer_size) {
+            return Err(prefix(&format!("buffer_size must be between 100 and {}", MAX_BUFFER_SIZE)));
+        }
+        if self.batch_size == 0 || self.batch_size > self.buffer_size.min(10_000) {
+            return Err(prefix("batch_size must be between 1 and min(buffer_size, 10000)"));
+        }
+        if !(10..=60_000).contains(&self.flush_interval_ms) {
+            return Err(prefix("flush_interval_ms must be between 10 and 60000"));
+        }
+        if self.max_retries > 10 {
+            return Err(prefix("max_retries must be at most 10"));
+        }
+        if !(10..=60_000).contains(&self.retry_backoff_ms) {
+            return Err(prefix("retry_backoff_ms must be between 10 and 60000"));
+        }
+        if let Some(field) = self.fields.iter().find(|f| !LOG_SINK_FIELDS.contains(&f.as_str())) {
+            return Err(prefix(&format!("unknown field '{}'", field)));
+        }
+
+        match self.kind {
+            LogSinkKind::Syslog => {
+                let syslog = &self.syslog;
+                if syslog.address.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).is_none() {
+                    return Err(prefix("syslog address must be host:port"));
+                }
+                if syslog.facility > 23 {
+                    return Err(prefix("syslog facility must be between 0 and 23"));
+                }
+                for (value, max) in [(&syslog.app_name, 48), (&syslog.hostname, 255)] {
+                    if value.len() > max || !value.chars().all(|c| c.is_ascii_graphic()) {
+                        return Err(prefix("syslog app_name/hostname must be printable ASCII without spaces"));
+                    }
+                }
+            }
+            LogSinkKind::File => {
+                if self.file.path.trim().is_empty() {
+                    return Err(prefix("file path must not be empty"));
+                }
+                if !(1..=10_240).contains(&self.file.max_file_size_mb) {
+                    return Err(prefix("max_file_size_mb must be between 1 and 10240"));
+                }
+                if self.file.max_files > 100 {
+                    return Err(prefix("max_files must be at most 100"));
+                }
+            }
+            LogSinkKind::Http => {
+                let http = &self.http;
+                let url = reqwest::Url::parse(&http.url).map_err(|e| prefix(&format!("invalid url: {}", e)))?;
+                if !matches!(url.scheme(), "http" | "https") {
+                    return Err(prefix("url must use http or https"));
+                }
+                if !(100..=120_000).contains(&http.timeout_ms) {
+                    return Err(prefix("timeout_ms must be between 100 and 120000"));
+                }
+                if http.format == HttpSinkFormat::Elasticsearch && http.index.trim().is_empty() {
+                    return Err(prefix("Elasticsearch index must not be empty"));
+                }
+                if http.format == HttpSinkFormat::Loki && http.labels.is_empty() {
+                    return Err(prefix("Loki needs at least one stream label"));
+                }
+                for (name, value) in &http.headers {
+                    reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| prefix(&format!("invalid header name '{}'", name)))?;
+                    reqwest::header::HeaderValue::from_str(value)
+                        .map_err(|_| prefix(&format!("invalid value of header '{}'", name)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// All configured log sinks (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSinksConfig {
+    pub sinks: Vec<LogSinkConfig>,
+}
+
+impl LogSinksConfig {
+    /// Check every sink and that names are unique
+    pub fn validate(&self) -> Result<()> {
+        if self.sinks.len() > MAX_SINKS {
+            return Err(anyhow!("At most {} log sinks can be configured", MAX_SINKS));
+        }
+        let mut names = HashSet::new();
+        for sink in &self.sinks {
+            sink.validate()?;
+            if !names.insert(sink.name.trim()) {
+                return Err(anyhow!("Duplicate sink name '{}'", sink.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counters of one sink
+#[derive(Debug, Clone, Serialize)]
+pub struct LogSinkStats {
+    pub name: String,
+    pub kind: LogSinkKind,
+    pub enabled: bool,
+    /// Entries accepted into the queue
+    pub enqueued: u64,
+    /// Entries delivered
+    pub sent: u64,
+    /// Entries rejected because the queue was full
+    pub dropped: u64,
+    /// Entries discarded after their batch ran out of retries
+    pub failed: u64,
+    /// Delivery attempts that were retried
+    pub retries: u64,
+    /// Entries waiting in the queue
+    pub pending: usize,
+    /// Most recent delivery error
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct Counters {
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+    retries: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+/// A running sink
+struct SinkHandle {
+    config: LogSinkConfig,
+    sender: mpsc::Sender<Arc<QueuedLog>>,
+    counters: Arc<Counters>,
+    shutdown: CancellationToken,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SinkHandle {
+    fn start(config: LogSinkConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.buffer_size);
+        let counters = Arc::new(Counters::default());
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(run(config.clone(), receiver, counters.clone(), shutdown.clone()));
+        Self {
+            config,
+            sender,
+            counters,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        }
+    }
+
+    fn push(&self, entry: Arc<QueuedLog>) {
+        match self.sender.try_send(entry) {
+            Ok(()) => self.counters.enqueued.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.counters.dropped.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    fn stats(&self) -> LogSinkStats {
+        let c = &self.counters;
+        LogSinkStats {
+            name: self.config.name.clone(),
+            kind: self.config.kind,
+            enabled: true,
+            enqueued: c.enqueued.load(Ordering::Relaxed),
+            sent: c.sent.load(Ordering::Relaxed),
+            dropped: c.dropped.load(Ordering::Relaxed),
+            failed: c.failed.load(Ordering::Relaxed),
+            retries: c.retries.load(Ordering::Relaxed),
+            pending: self.sender.max_capacity() - self.sender.capacity(),
+            last_error: c.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    /// Ship what is still queued and stop
+    async fn stop(&self) {
+        self.shutdown.cancel();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            if let Err(e) = task.await {
+                tracing::warn!("Log sink '{}' stopped abnormally: {}", self.config.name, e);
+            }
+        }
+    }
+}
+
+/// Fans query logs out to the configured sinks
+pub struct LogSinks {
+    config: RwLock<LogSinksConfig>,
+    sinks: RwLock<Arc<Vec<Arc<SinkHandle>>>>,
+    /// Serializes reconfiguration
+    reconfigure: tokio::sync::Mutex<()>,
+}
+
+impl LogSinks {
+    /// Start every enabled sink
+    pub fn start(config: LogSinksConfig) -> Arc<Self> {
+        let sinks = Self::start_sinks(&config);
+        Arc::new(Self {
+            config: RwLock::new(config),
+            sinks: RwLock::new(sinks),
+            reconfigure: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    fn start_sinks(config: &LogSinksConfig) -> Arc<Vec<Arc<SinkHandle>>> {
+        Arc::new(config.sinks.iter()
+            .filter(|sink| sink.enabled)
+            .map(|sink| Arc::new(SinkHandle::start(sink.clone())))
+            .collect())
+    }
+
+    /// Queue a log entry on every sink; never waits
+    pub fn log(&self, log: &CreateQueryLog) {
+        let sinks = self.sinks.read().unwrap().clone();
+        if sinks.is_empty() {
+            return;
+        }
+        let entry = Arc::new((log.clone(), Utc::now()));
+        for sink in sinks.iter() {
+            sink.push(entry.clone());
+        }
+    }
+
+    /// Current settings
+    pub fn config(&self) -> LogSinksConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace all sinks; the old ones ship what they still hold first
+    pub async fn set_config(&self, config: LogSinksConfig) -> Result<()> {
+        config.validate()?;
+        let _guard = self.reconfigure.lock().await;
+        let started = Self::start_sinks(&config);
+        let old = std::mem::replace(&mut *self.sinks.write().unwrap(), started);
+        *self.config.write().unwrap() = config;
+        for sink in old.iter() {
+            sink.stop().await;
+        }
+        Ok(())
+    }
+
+    /// Counters of every configured sink, in configuration order
+    pub fn stats(&self) -> Vec<LogSinkStats> {
+        let config = self.config.read().unwrap().clone();
+        let sinks = self.sinks.read().unwrap().clone();
+        config.sinks.iter()
+            .map(|sink| match sinks.iter().find(|running| running.config.name == sink.name) {
+                Some(running) => running.stats(),
+                None => LogSinkStats {
+                    name: sink.name.clone(),
+                    kind: sink.kind,
+                    enabled: false,
+                    enqueued: 0,
+                    sent: 0,
+                    dropped: 0,
+                    failed: 0,
+                    retries: 0,
+                    pending: 0,
+                    last_error: None,
+                },
+            })
+            .collect()
+    }
+
+    /// Ship everything still queued and stop all sinks
+    pub async fn shutdown(&self) {
+        let _guard = self.reconfigure.lock().await;
+        let sinks = self.sinks.read().unwrap().clone();
+        for sink in sinks.iter() {
+            sink.stop().await;
+        }
+    }
+}
+
+/// Sink loop: ship when a batch is full or the oldest entry is due
+async fn run(
+    config: LogSinkConfig,
+    mut receiver: mpsc::Receiver<Arc<QueuedLog>>,
+    counters: Arc<Counters>,
+    shutdown: CancellationToken,
+) {
+    let mut output = SinkOutput::new(&config);
+    let mut batch: Vec<Arc<QueuedLog>> = Vec::new();
+    let mut deadline = Instant::now();
+    let interval = Duration::from_millis(config.flush_interval_ms);
+
+    loop {
+        let room = config.batch_size.saturating_sub(batch.len()).max(1);
+        let waiting = !batch.is_empty();
+
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            received = receiver.recv_many(&mut batch, room) => {
+                if received == 0 {
+                    break;
+                }
+                if batch.len() == received {
+                    // First entry of a new batch starts its wait
+                    deadline = Instant::now() + interval;
+                }
+                if batch.len() >= config.batch_size {
+                    deliver(&config, &mut output, &mut batch, &counters, &shutdown).await;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline), if waiting => {
+                deliver(&config, &mut output, &mut batch, &counters, &shutdown).await;
+            }
+        }
+    }
+
+    // Graceful shutdown: ship what is still queued
+    receiver.close();
+    while let Some(entry) = receiver.recv().await {
+        batch.push(entry);
+        if batch.len() >= config.batch_size {
+            deliver(&config, &mut output, &mut batch, &counters, &shutdown).await;
+        }
+    }
+    deliver(&config, &mut output, &mut batch, &counters, &shutdown).await;
+    tracing::info!("Log sink '{}' stopped", config.name);
+}
+
+/// Ship one batch, retrying with exponential backoff
+///
+/// Retries stop early on shutdown; a batch that still fails is counted
+/// and discarded.
+async fn deliver(
+    config: &LogSinkConfig,
+    output: &mut SinkOutput,
+    batch: &mut Vec<Arc<QueuedLog>>,
+    counters: &Counters,
+    shutdown: &CancellationToken,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let count = batch.len() as u64;
+    let records: Vec<Map<String, Value>> = batch.iter()
+        .map(|entry| record(entry, &config.fields))
+        .collect();
+    let mut backoff = Duration::from_millis(config.retry_backoff_ms);
+
+    for attempt in 0..=config.max_retries {
+        match output.send(batch, &records).await {
+            Ok(()) => {
+                counters.sent.fetch_add(count, Ordering::Relaxed);
+                batch.clear();
+                return;
+            }
+            Err(e) => {
+                tracing::debug!("Log sink '{}' failed to ship {} entries: {}", config.name, count, e);
+                *counters.last_error.lock().unwrap() = Some(e.to_string());
+            }
+        }
+        if attempt == config.max_retries || shutdown.is_cancelled() {
+            break;
+        }
+        counters.retries.fetch_add(1, Ordering::Relaxed);
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+
+    tracing::warn!("Log sink '{}' discarded {} entries", config.name, count);
+    counters.failed.fetch_add(count, Ordering::Relaxed);
+    batch.clear();
+}
+
+/// The shipped JSON object of an entry, limited to `fields`
+fn record(entry: &QueuedLog, fields: &[String]) -> Map<String, Value> {
+    let (log, at) = entry;
+    let mut map = Map::new();
+    map.insert("timestamp".to_string(), Value::String(at.to_rfc3339_opts(SecondsFormat::Millis, true)));
+    if let Ok(Value::Object(object)) = serde_json::to_value(log) {
+        map.extend(object.into_iter().filter(|(_, value)| !value.is_null()));
+    }
+    if !fields.is_empty() {
+        map.retain(|key, _| fields.iter().any(|field| field == key));
+    }
+    map
+}
+
+/// Open connection, file or HTTP client of a sink
+enum SinkOutput {
+    Syslog(SyslogOutput),
+    File(FileOutput),
+    Http(HttpOutput),
+}
+
+impl SinkOutput {
+    fn new(config: &LogSinkConfig) -> Self {
+        match config.kind {
+            LogSinkKind::Syslog => Self::Syslog(SyslogOutput {
+                config: config.syslog.clone(),
+                socket: None,
+                stream: None,
+            }),
+            LogSinkKind::File => Self::File(FileOutput {
+                config: config.file.clone(),
+                file: None,
+                size: 0,
+            }),
+            LogSinkKind::Http => Self::Http(HttpOutput::new(config.http.clone())),
+        }
+    }
+
+    async fn send(&mut self, entries: &[Arc<QueuedLog>], records: &[Map<String, Value>]) -> Result<()> {
+        match self {
+            Self::Syslog(output) => output.send(entries, records).await,
+            Self::File(output) => output.send(records).await,
+            Self::Http(output) => output.send(entries, records).await,
+        }
+    }
+}
+
+struct SyslogOutput {
+    config: SyslogSinkConfig,
+    socket: Option<UdpSocket>,
+    stream: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+}
+
+impl SyslogOutput {
+    async fn send(&mut self, entries: &[Arc<QueuedLog>], records: &[Map<String, Value>]) -> Result<()> {
+        let messages: Vec<String> = entries.iter().zip(records)
+            .map(|(entry, record)| syslog_message(&self.config, entry, record))
+            .collect();
+
+        if self.config.transport == SyslogTransport::Udp {
+            if self.socket.is_none() {
+                let target = resolve(&self.config.address).await?;
+                let bind: SocketAddr = if target.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }.parse()?;
+                let socket = UdpSocket::bind(bind).await?;
+                socket.connect(target).await?;
+                self.socket = Some(socket);
+            }
+            let socket = self.socket.as_ref().expect("socket opened above");
+            for message in &messages {
+                socket.send(message.as_bytes()).await?;
+            }
+            return Ok(());
+        }
+
+        if self.stream.is_none() {
+            self.stream = Some(connect(&self.config).await?);
+        }
+        let stream = self.stream.as_mut().expect("stream opened above");
+        let mut frames = Vec::new();
+        for message in &messages {
+            frames.extend_from_slice(format!("{} ", message.len()).as_bytes());
+            frames.extend_from_slice(message.as_bytes());
+        }
+        let written = async {
+            stream.write_all(&frames).await?;
+            stream.flush().await
+        }.await;
+        if let Err(e) = written {
+            // Reconnect on the next attempt
+            self.stream = None;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// Open a TCP or TLS stream to the syslog collector
+async fn connect(config: &SyslogSinkConfig) -> Result<Box<dyn AsyncWrite + Send + Unpin>> {
+    let target = resolve(&config.address).await?;
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(target)).await
+        .map_err(|_| anyhow!("Connecting to {} timed out", config.address))??;
+    if config.transport == SyslogTransport::Tcp {
+        return Ok(Box::new(stream));
+    }
+
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    let tls = if config.tls_skip_verify {
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(crate::dns::proxy::NoVerifier))
+            .with_no_client_auth()
+    } else {
+        let mut root_store = RootCertStore::empty();
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth()
+    };
+    let host = config.address.rsplit_once(':')
+        .map(|(host, _)| host.trim_start_matches('[').trim_end_matches(']'))
+        .unwrap_or_default()
+        .to_string();
+    let server_name = ServerName::try_from(host.clone())
+        .map_err(|_| anyhow!("Invalid server name: {}", host))?;
+    let stream = timeout(CONNECT_TIMEOUT, TlsConnector::from(Arc::new(tls)).connect(server_name, stream)).await
+        .map_err(|_| anyhow!("TLS handshake with {} timed out", config.address))??;
+    Ok(Box::new(stream))
+}
+
+/// Resolve a host:port to its first address
+async fn resolve(address: &str) -> Result<SocketAddr> {
+    tokio::net::lookup_host(address).await?
+        .next()
+        .ok_or_else(|| anyhow!("{} did not resolve to an address", address))
+}
+
+/// RFC 5424 message carrying the record as MSG
+///
+/// Failed queries are logged with severity warning, all others as
+/// informational.
+fn syslog_message(config: &SyslogSinkConfig, entry: &QueuedLog, record: &Map<String, Value>) -> String {
+    let (log, at) = entry;
+    let failed = log.response_code.as_deref()
+        .is_some_and(|code| code.starts_with("ERROR") || code == "SERVFAIL");
+    let severity = if failed { 4 } else { 6 };
+    let nil = |value: &str| if value.is_empty() { "-".to_string() } else { value.to_string() };
+    format!(
+        "<{}>1 {} {} {} - query - {}",
+        config.facility as u32 * 8 + severity,
+        at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        nil(&config.hostname),
+        nil(&config.app_name),
+        serde_json::to_string(record).unwrap_or_default(),
+    )
+}
+
+struct FileOutput {
+    config: FileSinkConfig,
+    file: Option<tokio::fs::File>,
+    size: u64,
+}
+
+impl FileOutput {
+    async fn send(&mut self, records: &[Map<String, Value>]) -> Result<()> {
+        let max_size = self.config.max_file_size_mb * 1024 * 1024;
+        let path = PathBuf::from(&self.config.path);
+        let result = async {
+            for record in records {
+                let mut line = serde_json::to_vec(record)?;
+                line.push(b'\n');
+                if self.file.is_some() && self.size > 0 && self.size + line.len() as u64 > max_size {
+                    if let Some(mut file) = self.file.take() {
+                        file.flush().await?;
+                    }
+                    rotate_files(&path, self.config.max_files).await?;
+                }
+                if self.file.is_none() {
+                    let file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+                    self.size = file.metadata().await?.len();
+                    self.file = Some(file);
+                }
+                let file = self.file.as_mut().expect("file opened above");
+                file.write_all(&line).await?;
+                self.size += line.len() as u64;
+            }
+            if let Some(ref mut file) = self.file {
+                file.flush().await?;
+            }
+            Ok(())
+        }.await;
+        if result.is_err() {
+            // Reopen on the next attempt
+            self.file = None;
+        }
+        result
+    }
+}
+
+/// Shift `path.N` to `path.N+1` (dropping the oldest) and `path` to `path.1`
+async fn rotate_files(path: &Path, max_files: u32) -> Result<()> {
+    if max_files == 0 {
+        tokio::fs::remove_file(path).await?;
+        return Ok(());
+    }
+    let numbered = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+    let _ = tokio::fs::remove_file(numbered(max_files)).await;
+    for n in (1..max_files).rev() {
+        let from = numbered(n);
+        if tokio::fs::metadata(&from).await.is_ok() {
+            tokio::fs::rename(&from, numbered(n + 1)).await?;
+        }
+    }
+    tokio::fs::rename(path, numbered(1)).await?;
+    Ok(())
+}
+
+struct HttpOutput {
+    config: HttpSinkConfig,
+    client: reqwest::Client,
+}
+
+impl HttpOutput {
+    fn new(config: HttpSinkConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .unwrap_or_default();
+        Self { config, client }
+    }
+
+    async fn send(&self, entries: &[Arc<QueuedLog>], records: &[Map<String, Value>]) -> Result<()> {
+        let (content_type, body) = http_body(&self.config, entries, records)?;
+        let mut request = self.client.post(&self.config.url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let snippet: String = text.chars().take(200).collect();
+            return Err(anyhow!("HTTP {}: {}", status, snippet));
+        }
+        if self.config.format == HttpSinkFormat::Elasticsearch {
+            // Bulk requests succeed as a whole even when single items fail
+            let errors = serde_json::from_str::<Value>(&text).ok()
+                .and_then(|body| body.get("errors").and_then(Value::as_bool))
+                .unwrap_or(false);
+            if errors {
+                return Err(anyhow!("Elasticsearch rejected some bulk items"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Content type and body of one HTTP batch
+fn http_body(
+    config: &HttpSinkConfig,
+    entries: &[Arc<QueuedLog>],
+    records: &[Map<String, Value>],
+) -> Result<(&'static str, Vec<u8>)> {
+    match config.format {
+        HttpSinkFormat::Json => Ok(("application/json", serde_json::to_vec(records)?)),
+        HttpSinkFormat::Elasticsearch => {
+            let action = serde_json::to_string(&serde_json::json!({ "index": { "_index": config.index } }))?;
+            let mut body = Vec::new();
+            for record in records {
+                body.extend_from_slice(action.as_bytes());
+                body.push(b'\n');
+                serde_json::to_writer(&mut body, record)?;
+                body.push(b'\n');
+            }
+            Ok(("application/x-ndjson", body))
+        }
+        HttpSinkFormat::Loki => {
+            let values: Vec<Value> = entries.iter().zip(records)
+                .map(|(entry, record)| {
+                    let nanos = entry.1.timestamp_nanos_opt().unwrap_or_default();
+                    serde_json::json!([nanos.to_string(), serde_json::to_string(record).unwrap_or_default()])
+                })
+                .collect();
+            let body = serde_json::json!({
+                "streams": [{ "stream": config.labels, "values": values }]
+            });
+            Ok(("application/json", serde_json::to_vec(&body)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, rcode: &str) -> Arc<QueuedLog> {
+        Arc::new((
+            CreateQueryLog {
+                client_ip: "10.0.0.1".to_string(),
+                query_name: name.to_string(),
+                query_type: "A".to_string(),
+                response_code: Some(rcode.to_string()),
+                response_time: Some(3),
+                ..Default::default()
+            },
+            "2024-05-01T12:00:00Z".parse().unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let sink = LogSinkConfig { name: "syslog".to_string(), ..Default::default() };
+        assert!(LogSinksConfig { sinks: vec![sink.clone()] }.validate().is_ok());
+        assert!(LogSinksConfig { sinks: vec![sink.clone(), sink.clone()] }.validate().is_err());
+
+        let unnamed = LogSinkConfig::default();
+        assert!(unnamed.validate().is_err());
+        let bad_field = LogSinkConfig { fields: vec!["password".to_string()], ..sink.clone() };
+        assert!(bad_field.validate().is_err());
+        let bad_batch = LogSinkConfig { batch_size: 20_000, ..sink.clone() };
+        assert!(bad_batch.validate().is_err());
+
+        let http = LogSinkConfig { kind: LogSinkKind::Http, ..sink.clone() };
+        assert!(http.validate().is_err());
+        let mut http = http;
+        http.http.url = "http://127.0.0.1:9200/_bulk".to_string();
+        assert!(http.validate().is_ok());
+        http.http.headers.insert("Bad Header".to_string(), "x".to_string());
+        assert!(http.validate().is_err());
+
+        let config: LogSinksConfig = serde_json::from_str(
+            r#"{"sinks":[{"name":"f","kind":"file","file":{"path":"/tmp/q.ndjson"}}]}"#,
+        ).unwrap();
+        assert_eq!(config.sinks[0].kind, LogSinkKind::File);
+        assert_eq!(config.sinks[0].file.max_files, 5);
+        assert_eq!(config.sinks[0].batch_size, 100);
+    }
+
+    #[test]
+    fn test_record_and_syslog_message() {
+        let entry = entry("example.com", "SERVFAIL");
+        let all = record(&entry, &[]);
+        assert_eq!(all["timestamp"], "2024-05-01T12:00:00.000Z");
+        assert_eq!(all["query_name"], "example.com");
+        assert!(!all.contains_key("upstream_used"));
+
+        let fields = vec!["query_name".to_string(), "response_code".to_string()];
+        let selected = record(&entry, &fields);
+        assert_eq!(selected.len(), 2);
+
+        let config = SyslogSinkConfig { hostname: "dns1".to_string(), ..Default::default() };
+        let message = syslog_message(&config, &entry, &selected);
+        // local0 (16) * 8 + warning (4)
+        assert_eq!(
+            message,
+            r#"<132>1 2024-05-01T12:00:00.000Z dns1 fluxdns - query - {"query_name":"example.com","response_code":"SERVFAIL"}"#
+        );
+    }
+
+    #[test]
+    fn test_http_bodies() {
+        let entries = vec![entry("a.com", "NOERROR"), entry("b.com", "NOERROR")];
+        let fields = vec!["query_name".to_string()];
+        let records: Vec<_> = entries.iter().map(|e| record(e, &fields)).collect();
+
+        let config = HttpSinkConfig { format: HttpSinkFormat::Elasticsearch, index: "dns".to_string(), ..Default::default() };
+        let (content_type, body) = http_body(&config, &entries, &records).unwrap();
+        assert_eq!(content_type, "application/x-ndjson");
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "{\"index\":{\"_index\":\"dns\"}}\n{\"query_name\":\"a.com\"}\n{\"index\":{\"_index\":\"dns\"}}\n{\"query_name\":\"b.com\"}\n"
+        );
+
+        let config = HttpSinkConfig { format: HttpSinkFormat::Loki, ..Default::default() };
+        let (_, body) = http_body(&config, &entries, &records).unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["streams"][0]["stream"]["job"], "fluxdns");
+        assert_eq!(body["streams"][0]["values"][1][0], "1714564800000000000");
+        assert_eq!(body["streams"][0]["values"][1][1], "{\"query_name\":\"b.com\"}");
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_writes_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queries.ndjson");
+        let sink = LogSinkConfig {
+            name: "file".to_string(),
+            kind: LogSinkKind::File,
+            batch_size: 1,
+            flush_interval_ms: 10,
+            file: FileSinkConfig {
+                path: path.display().to_string(),
+                max_file_size_mb: 1,
+                max_files: 1,
+            },
+            ..Default::default()
+        };
+        let sinks = LogSinks::start(LogSinksConfig { sinks: vec![sink] });
+        for _ in 0..3 {
+            sinks.log(&entry("example.com", "NOERROR").0);
+        }
+        sinks.shutdown().await;
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 3);
+        let line: Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(line["query_name"], "example.com");
+
+        let mut output = FileOutput {
+            config: FileSinkConfig { path: path.display().to_string(), max_file_size_mb: 0, max_files: 1 },
+            file: None,
+            size: 0,
+        };
+        let records = vec![record(&entry("next.com", "NOERROR"), &[])];
+        output.send(&records).await.unwrap();
+        output.send(&records).await.unwrap();
+        assert!(std::fs::read_to_string(format!("{}.1", path.display())).unwrap().contains("next.com"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_syslog_udp_and_retries() {
+        let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sink = LogSinkConfig {
+            name: "udp".to_string(),
+            batch_size: 1,
+            flush_interval_ms: 10,
+            syslog: SyslogSinkConfig {
+                address: collector.local_addr().unwrap().to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let unreachable = LogSinkConfig {
+            name: "http".to_string(),
+            kind: LogSinkKind::Http,
+            batch_size: 1,
+            flush_interval_ms: 10,
+            max_retries: 1,
+            retry_backoff_ms: 10,
+            http: HttpSinkConfig {
+                url: "http://127.0.0.1:1/".to_string(),
+                timeout_ms: 500,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sinks = LogSinks::start(LogSinksConfig { sinks: vec![sink, unreachable] });
+        sinks.log(&entry("example.com", "NOERROR").0);
+
+        let mut buf = [0u8; 2048];
+        let len = timeout(Duration::from_secs(5), collector.recv(&mut buf)).await.unwrap().unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(message.starts_with("<134>1 "));
+        assert!(message.contains("\"query_name\":\"example.com\""));
+
+        sinks.shutdown().await;
+        let stats = sinks.stats();
+        assert_eq!(stats[0].sent, 1);
+        assert_eq!(stats[1].failed, 1);
+        assert_eq!(stats[1].retries, 1);
+        assert!(stats[1].last_error.is_some());
+    }
+}
@@ -0,0 +1,373 @@
+//! Query Log Writer
+//!
+//! Query logs are queued on a bounded channel and written by one background
+//! task in batched transactions, so logging never competes with every
+//! query for SQLite. When the queue fills up, entries are sampled or
+//! dropped (and counted) instead of piling up.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::{CreateQueryLog, Database};
+
+/// Hard upper bound of the queue; `buffer_size` limits it further
+const MAX_BUFFER_SIZE: usize = 100_000;
+
+type QueuedLog = (CreateQueryLog, DateTime<Utc>);
+
+/// What to do with new entries when the queue is under pressure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    /// Keep everything until the queue is full, then drop new entries
+    #[default]
+    Drop,
+    /// Keep only one in `sample_rate` entries once the queue is three quarters full
+    Sample,
+}
+
+/// Query log writer settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryLogWriterConfig {
+    /// Entries the queue may hold before the overflow policy kicks in
+    pub buffer_size: usize,
+    /// Entries written per transaction
+    pub flush_size: usize,
+    /// Longest time an entry waits before being written
+    pub flush_interval_ms: u64,
+    /// Behaviour when the queue is under pressure
+    pub overflow_policy: OverflowPolicy,
+    /// Keep one in this many entries when sampling
+    pub sample_rate: u32,
+}
+
+impl Default for QueryLogWriterConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: 10_000,
+            flush_size: 500,
+            flush_interval_ms: 1000,
+            overflow_policy: OverflowPolicy::Drop,
+            sample_rate: 10,
+        }
+    }
+}
+
+impl QueryLogWriterConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if !(100..=MAX_BUFFER_SIZE).contains(&self.buffer_size) {
+            return Err(anyhow!("buffer_size must be between 100 and {}", MAX_BUFFER_SIZE));
+        }
+        if self.flush_size == 0 || self.flush_size > self.buffer_size {
+            return Err(anyhow!("flush_size must be between 1 and buffer_size"));
+        }
+        if !(10..=60_000).contains(&self.flush_interval_ms) {
+            return Err(anyhow!("flush_interval_ms must be between 10 and 60000"));
+        }
+        if !(2..=1000).contains(&self.sample_rate) {
+            return Err(anyhow!("sample_rate must be between 2 and 1000"));
+        }
+        Ok(())
+    }
+}
+
+/// Writer counters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueryLogWriterStats {
+    /// Entries accepted into the queue
+    pub enqueued: u64,
+    /// Entries written to the database
+    pub written: u64,
+    /// Entries rejected because the queue was full
+    pub dropped: u64,
+    /// Entries skipped by sampling
+    pub sampled_out: u64,
+    /// Entries lost because their batch failed to write
+    pub failed: u64,
+    /// Batches written
+    pub flushes: u64,
+    /// Entries waiting in the queue
+    pub pending: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    enqueued: AtomicU64,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    sampled_out: AtomicU64,
+    failed: AtomicU64,
+    flushes: AtomicU64,
+    /// Sequence used to pick sampled entries
+    seen: AtomicU64,
+}
+
+/// Bounded, batching query log writer
+pub struct QueryLogWriter {
+    sender: mpsc::Sender<QueuedLog>,
+    config: Arc<RwLock<QueryLogWriterConfig>>,
+    counters: Arc<Counters>,
+    shutdown: CancellationToken,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl QueryLogWriter {
+    /// Start the background writer
+    pub fn start(db: Arc<Database>, config: QueryLogWriterConfig) -> Arc<Self> {
+        let (writer, receiver) = Self::unstarted(config);
+        let task = tokio::spawn(run(
+            db,
+            receiver,
+            writer.config.clone(),
+            writer.counters.clone(),
+            writer.shutdown.clone(),
+        ));
+        *writer.task.lock().unwrap() = Some(task);
+        Arc::new(writer)
+    }
+
+    /// Writer whose queue nobody drains yet
+    fn unstarted(config: QueryLogWriterConfig) -> (Self, mpsc::Receiver<QueuedLog>) {
+        let (sender, receiver) = mpsc::channel(MAX_BUFFER_SIZE);
+        let writer = Self {
+            sender,
+            config: Arc::new(RwLock::new(config)),
+            counters: Arc::new(Counters::default()),
+            shutdown: CancellationToken::new(),
+            task: Mutex::new(None),
+        };
+        (writer, receiver)
+    }
+
+    /// Queue a log entry; never waits
+    pub fn log(&self, log: CreateQueryLog) {
+        let config = *self.config.read().unwrap();
+        let pending = self.pending();
+
+        if pending >= config.buffer_size {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if config.overflow_policy == OverflowPolicy::Sample && pending >= config.buffer_size / 4 * 3 {
+            let seen = self.counters.seen.fetch_add(1, Ordering::Relaxed);
+            if !seen.is_multiple_of(config.sample_rate as u64) {
+                self.counters.sampled_out.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+
+        match self.sender.try_send((log, Utc::now())) {
+            Ok(()) => {
+                self.counters.enqueued.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Current settings
+    pub fn config(&self) -> QueryLogWriterConfig {
+        *self.config.read().unwrap()
+    }
+
+    /// Apply new settings; takes effect with the next entry
+    pub fn set_config(&self, config: QueryLogWriterConfig) -> Result<()> {
+        config.validate()?;
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> QueryLogWriterStats {
+        let c = &self.counters;
+        QueryLogWriterStats {
+            enqueued: c.enqueued.load(Ordering::Relaxed),
+            written: c.written.load(Ordering::Relaxed),
+            dropped: c.dropped.load(Ordering::Relaxed),
+            sampled_out: c.sampled_out.load(Ordering::Relaxed),
+            failed: c.failed.load(Ordering::Relaxed),
+            flushes: c.flushes.load(Ordering::Relaxed),
+            pending: self.pending(),
+        }
+    }
+
+    /// Write everything still queued and stop the writer
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            if let Err(e) = task.await {
+                tracing::warn!("Query log writer stopped abnormally: {}", e);
+            }
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+/// Writer loop: flush when a batch is full or the oldest entry is due
+async fn run(
+    db: Arc<Database>,
+    mut receiver: mpsc::Receiver<QueuedLog>,
+    config: Arc<RwLock<QueryLogWriterConfig>>,
+    counters: Arc<Counters>,
+    shutdown: CancellationToken,
+) {
+    let mut batch: Vec<QueuedLog> = Vec::new();
+    let mut deadline = Instant::now();
+
+    loop {
+        let QueryLogWriterConfig { flush_size, flush_interval_ms, .. } = *config.read().unwrap();
+        let room = flush_size.saturating_sub(batch.len()).max(1);
+        let waiting = !batch.is_empty();
+
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            received = receiver.recv_many(&mut batch, room) => {
+                if received == 0 {
+                    break;
+                }
+                if batch.len() == received {
+                    // First entry of a new batch starts its wait
+                    deadline = Instant::now() + Duration::from_millis(flush_interval_ms);
+                }
+                if batch.len() >= flush_size {
+                    flush(&db, &mut batch, &counters).await;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline), if waiting => {
+                flush(&db, &mut batch, &counters).await;
+            }
+        }
+    }
+
+    // Graceful shutdown: write what is still queued
+    receiver.close();
+    while let Some(entry) = receiver.recv().await {
+        batch.push(entry);
+        if batch.len() >= MAX_BUFFER_SIZE / 10 {
+            flush(&db, &mut batch, &counters).await;
+        }
+    }
+    flush(&db, &mut batch, &counters).await;
+    tracing::info!("Query log writer stopped");
+}
+
+/// Write one batch; a failed batch is counted and discarded
+async fn flush(db: &Database, batch: &mut Vec<QueuedLog>, counters: &Counters) {
+    if batch.is_empty() {
+        return;
+    }
+    let count = batch.len() as u64;
+    match db.query_logs().create_batch(batch).await {
+        Ok(()) => {
+            counters.written.fetch_add(count, Ordering::Relaxed);
+            counters.flushes.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => {
+            tracing::warn!("Failed to write {} query logs: {}", count, e);
+            counters.failed.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+    batch.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn setup_test_db() -> (TempDir, Arc<Database>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        let db = Arc::new(Database::new(&db_url).await.unwrap());
+        (dir, db)
+    }
+
+    fn log(name: &str, cache_hit: bool) -> CreateQueryLog {
+        CreateQueryLog {
+            client_ip: "127.0.0.1".to_string(),
+            query_name: name.to_string(),
+            query_type: "A".to_string(),
+            response_code: Some("NOERROR".to_string()),
+            response_time: Some(1),
+            cache_hit,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(QueryLogWriterConfig::default().validate().is_ok());
+        let config = QueryLogWriterConfig { flush_size: 20_000, ..Default::default() };
+        assert!(config.validate().is_err());
+        let config = QueryLogWriterConfig { buffer_size: 10, ..Default::default() };
+        assert!(config.validate().is_err());
+        let config: QueryLogWriterConfig = serde_json::from_str(r#"{"overflow_policy":"sample"}"#).unwrap();
+        assert_eq!(config.overflow_policy, OverflowPolicy::Sample);
+        assert_eq!(config.flush_size, 500);
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_written_and_counted() {
+        let (_dir, db) = setup_test_db().await;
+        let writer = QueryLogWriter::start(db.clone(), QueryLogWriterConfig {
+            flush_size: 2,
+            flush_interval_ms: 10,
+            ..Default::default()
+        });
+
+        writer.log(log("a.example", true));
+        writer.log(log("b.example", false));
+        writer.log(log("c.example", false));
+        writer.shutdown().await;
+
+        let stats = writer.stats();
+        assert_eq!((stats.enqueued, stats.written, stats.dropped), (3, 3, 0));
+        let cached = db.query_logs().get_stats().await.unwrap();
+        assert_eq!((cached.total_queries, cached.cache_hits), (3, 1));
+    }
+
+    #[test]
+    fn test_full_queue_drops_and_samples() {
+        let config = QueryLogWriterConfig {
+            buffer_size: 100,
+            flush_size: 100,
+            ..Default::default()
+        };
+
+        // Nothing drains these queues, so they really fill up
+        let (writer, _receiver) = QueryLogWriter::unstarted(config);
+        for i in 0..120 {
+            writer.log(log(&format!("{}.example", i), false));
+        }
+        let stats = writer.stats();
+        assert_eq!((stats.enqueued, stats.dropped, stats.pending), (100, 20, 100));
+
+        let (sampling, _receiver) = QueryLogWriter::unstarted(QueryLogWriterConfig {
+            overflow_policy: OverflowPolicy::Sample,
+            sample_rate: 10,
+            ..config
+        });
+        for i in 0..175 {
+            sampling.log(log(&format!("{}.example", i), false));
+        }
+        // 75 entries fill the queue to the threshold, then one in ten of the next 100 is kept
+        let stats = sampling.stats();
+        assert_eq!((stats.enqueued, stats.sampled_out, stats.dropped), (85, 90, 0));
+    }
+}
@@ -2,23 +2,49 @@
 //!
 //! Handles SQLite database connections, migrations, and CRUD operations.
 
+pub mod log_sink;
+pub mod log_writer;
 mod models;
+pub mod passive_dns;
+pub mod query_stream;
 pub mod repository;
+pub mod rollup;
 pub mod stats_cache;
 
+pub use log_sink::*;
+pub use log_writer::*;
 pub use models::*;
+pub use passive_dns::*;
+pub use query_stream::*;
 pub use repository::*;
+pub use rollup::*;
 pub use stats_cache::*;
 
 use anyhow::Result;
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 
 use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Data whose in-memory copies must be rebuilt after a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataChange {
+    /// A row of `dns_records` was created, updated or deleted
+    DnsRecords,
+    /// A `system_config` value was set or deleted
+    SystemConfig,
+    /// A row of `dns_zones` was created, updated or deleted
+    DnsZones,
+    /// A row of `rewrite_rules` was created, updated or deleted
+    RewriteRules,
+}
 
 /// Database wrapper providing connection pool and repositories
 pub struct Database {
     pool: SqlitePool,
     stats_cache: Arc<StatsCache>,
+    /// Notifies in-memory snapshots of writes
+    changes: broadcast::Sender<DataChange>,
 }
 
 impl Database {
@@ -36,7 +62,8 @@ impl Database {
             .await?;
 
         let stats_cache = Arc::new(StatsCache::empty());
-        let db = Self { pool, stats_cache };
+        let (changes, _) = broadcast::channel(64);
+        let db = Self { pool, stats_cache, changes };
         db.run_migrations().await?;
         db.init_stats_cache().await?; // Initial population from DB
 
@@ -51,12 +78,27 @@ impl Database {
 
     /// Get DNS records repository
     pub fn dns_records(&self) -> DnsRecordRepository {
-        DnsRecordRepository::new(self.pool.clone())
+        DnsRecordRepository::new(self.pool.clone()).with_changes(self.changes.clone())
+    }
+
+    /// Get authoritative zones repository
+    pub fn dns_zones(&self) -> DnsZoneRepository {
+        DnsZoneRepository::new(self.pool.clone()).with_changes(self.changes.clone())
+    }
+
+    /// Get TSIG keys repository
+    pub fn tsig_keys(&self) -> TsigKeyRepository {
+        TsigKeyRepository::new(self.pool.clone())
+    }
+
+    /// Get dynamic update audit log repository
+    pub fn dns_update_log(&self) -> DnsUpdateLogRepository {
+        DnsUpdateLogRepository::new(self.pool.clone())
     }
 
     /// Get rewrite rules repository
     pub fn rewrite_rules(&self) -> RewriteRuleRepository {
-        RewriteRuleRepository::new(self.pool.clone())
+        RewriteRuleRepository::new(self.pool.clone()).with_changes(self.changes.clone())
     }
 
     /// Get upstream servers repository
@@ -69,9 +111,30 @@ impl Database {
         QueryLogRepository::new(self.pool.clone(), self.stats_cache.clone())
     }
 
+    /// Get passive DNS repository
+    pub fn passive_dns(&self) -> PassiveDnsRepository {
+        PassiveDnsRepository::new(self.pool.clone())
+    }
+
+    /// Get statistics rollups repository
+    pub fn stats_rollups(&self) -> StatsRollupRepository {
+        StatsRollupRepository::new(self.pool.clone())
+    }
+
     /// Get system config repository
     pub fn system_config(&self) -> SystemConfigRepository {
-        SystemConfigRepository::new(self.pool.clone())
+        SystemConfigRepository::new(self.pool.clone()).with_changes(self.changes.clone())
+    }
+
+    /// Subscribe to write notifications
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<DataChange> {
+        self.changes.subscribe()
+    }
+
+    /// Announce a write made outside the repositories (e.g. raw SQL)
+    pub fn notify_changed(&self, change: DataChange) {
+        // No receivers just means nothing is cached yet
+        let _ = self.changes.send(change);
     }
 
     /// Get server listeners repository
@@ -127,6 +190,96 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Authoritative zones table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS dns_zones (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                origin VARCHAR(255) NOT NULL UNIQUE,
+                primary_ns VARCHAR(255) NOT NULL,
+                admin_email VARCHAR(255) NOT NULL,
+                serial INTEGER NOT NULL DEFAULT 1,
+                refresh INTEGER NOT NULL DEFAULT 3600,
+                retry INTEGER NOT NULL DEFAULT 600,
+                expire INTEGER NOT NULL DEFAULT 604800,
+                minimum INTEGER NOT NULL DEFAULT 300,
+                ttl INTEGER NOT NULL DEFAULT 3600,
+                nameservers TEXT NOT NULL DEFAULT '',
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                content_hash TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Zone transfers: secondary zones, transfer ACLs and NOTIFY targets
+        self.add_column_if_missing("dns_zones", "kind", "VARCHAR(16) NOT NULL DEFAULT 'primary'").await?;
+        self.add_column_if_missing("dns_zones", "primaries", "TEXT NOT NULL DEFAULT ''").await?;
+        self.add_column_if_missing("dns_zones", "transfer_acl", "TEXT NOT NULL DEFAULT ''").await?;
+        self.add_column_if_missing("dns_zones", "notify", "TEXT NOT NULL DEFAULT ''").await?;
+        self.add_column_if_missing("dns_zones", "content", "TEXT").await?;
+
+        // Serial steps of each zone for IXFR
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS zone_journal (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                zone_id INTEGER NOT NULL,
+                serial_from INTEGER NOT NULL,
+                serial_to INTEGER NOT NULL,
+                removed TEXT NOT NULL,
+                added TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_zone_journal_zone ON zone_journal(zone_id, id)"#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // TSIG keys for dynamic updates
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS tsig_keys (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name VARCHAR(255) NOT NULL UNIQUE,
+                algorithm VARCHAR(32) NOT NULL,
+                secret TEXT NOT NULL,
+                zones TEXT NOT NULL DEFAULT '',
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Audit trail of dynamic updates
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS dns_update_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                zone VARCHAR(255) NOT NULL,
+                key_name VARCHAR(255),
+                client_ip VARCHAR(45) NOT NULL,
+                rcode VARCHAR(16) NOT NULL,
+                changes TEXT NOT NULL DEFAULT '',
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Rewrite rules table
         sqlx::query(
             r#"
@@ -170,6 +323,11 @@ impl Database {
                 protocol VARCHAR(10) NOT NULL,
                 timeout INTEGER DEFAULT 5000,
                 enabled BOOLEAN DEFAULT TRUE,
+                proxy VARCHAR(255),
+                bind_address VARCHAR(64),
+                bind_interface VARCHAR(64),
+                group_name VARCHAR(16),
+                health_policy TEXT,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                 updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
             )
@@ -178,6 +336,13 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Outbound settings were added after the initial schema
+        self.add_column_if_missing("upstream_servers", "proxy", "VARCHAR(255)").await?;
+        self.add_column_if_missing("upstream_servers", "bind_address", "VARCHAR(64)").await?;
+        self.add_column_if_missing("upstream_servers", "bind_interface", "VARCHAR(64)").await?;
+        self.add_column_if_missing("upstream_servers", "group_name", "VARCHAR(16)").await?;
+        self.add_column_if_missing("upstream_servers", "health_policy", "TEXT").await?;
+
         // Index for upstream servers query: WHERE enabled = TRUE ORDER BY id
         sqlx::query(
             r#"CREATE INDEX IF NOT EXISTS idx_upstream_servers_enabled ON upstream_servers(enabled)"#,
@@ -197,12 +362,26 @@ impl Database {
                 response_time INTEGER,
                 cache_hit BOOLEAN DEFAULT FALSE,
                 upstream_used VARCHAR(100),
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                decision VARCHAR(255),
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                answers TEXT,
+                source VARCHAR(16),
+                source_id INTEGER,
+                reason VARCHAR(255),
+                protocol VARCHAR(8),
+                endpoint VARCHAR(255)
             )
             "#,
         )
         .execute(&self.pool)
         .await?;
+        self.add_column_if_missing("query_logs", "decision", "VARCHAR(255)").await?;
+        self.add_column_if_missing("query_logs", "answers", "TEXT").await?;
+        self.add_column_if_missing("query_logs", "source", "VARCHAR(16)").await?;
+        self.add_column_if_missing("query_logs", "source_id", "INTEGER").await?;
+        self.add_column_if_missing("query_logs", "reason", "VARCHAR(255)").await?;
+        self.add_column_if_missing("query_logs", "protocol", "VARCHAR(8)").await?;
+        self.add_column_if_missing("query_logs", "endpoint", "VARCHAR(255)").await?;
 
         sqlx::query(
             r#"CREATE INDEX IF NOT EXISTS idx_query_logs_created_at ON query_logs(created_at)"#,
@@ -222,6 +401,71 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Answers "why was this blocked, and by which rule"
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_query_logs_source ON query_logs(source, source_id)"#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Passive DNS: distinct upstream answers per name and type
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS passive_dns (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name VARCHAR(255) NOT NULL,
+                record_type VARCHAR(10) NOT NULL,
+                value TEXT NOT NULL,
+                ip_key CHAR(32),
+                first_seen DATETIME NOT NULL,
+                last_seen DATETIME NOT NULL,
+                count INTEGER NOT NULL DEFAULT 1,
+                UNIQUE (name, record_type, value)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // IP/CIDR searches are range scans over the hex key
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_passive_dns_ip_key ON passive_dns(ip_key)"#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_passive_dns_value ON passive_dns(value)"#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_passive_dns_last_seen ON passive_dns(last_seen)"#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Time-bucketed statistics; JSON columns hold breakdowns that merge by adding
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS stats_rollups (
+                granularity VARCHAR(8) NOT NULL,
+                bucket_start DATETIME NOT NULL,
+                queries INTEGER NOT NULL DEFAULT 0,
+                cache_hits INTEGER NOT NULL DEFAULT 0,
+                blocked INTEGER NOT NULL DEFAULT 0,
+                rcodes TEXT NOT NULL DEFAULT '{}',
+                upstreams TEXT NOT NULL DEFAULT '{}',
+                top_domains TEXT NOT NULL DEFAULT '{}',
+                top_clients TEXT NOT NULL DEFAULT '{}',
+                PRIMARY KEY (granularity, bucket_start)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Server listeners configuration table
         sqlx::query(
             r#"
@@ -247,6 +491,7 @@ impl Database {
             INSERT OR IGNORE INTO server_listeners (protocol, enabled, bind_address, port)
             VALUES 
                 ('udp', TRUE, '0.0.0.0', 10053),
+                ('tcp', TRUE, '0.0.0.0', 10053),
                 ('doh', FALSE, '0.0.0.0', 443),
                 ('dot', FALSE, '0.0.0.0', 853),
                 ('doq', FALSE, '0.0.0.0', 853),
@@ -256,6 +501,13 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Rows reconciled from the declarative config file are read-only in the UI
+        for table in ["dns_records", "rewrite_rules", "upstream_servers", "server_listeners", "dns_zones"] {
+            self.add_column_if_missing(table, "managed", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+        }
+        // Records mirrored from a watched hosts file
+        self.add_column_if_missing("dns_records", "source", "TEXT").await?;
+
         // System config table
         sqlx::query(
             r#"
@@ -355,6 +607,25 @@ impl Database {
         Ok(())
     }
 
+    /// Add a column to an existing table if it is not present yet
+    ///
+    /// `CREATE TABLE IF NOT EXISTS` leaves tables from older versions untouched,
+    /// so columns introduced later have to be added explicitly.
+    async fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let columns: Vec<(String,)> = sqlx::query_as(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .fetch_all(&self.pool)
+            .await?;
+
+        if !columns.iter().any(|(name,)| name == column) {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .execute(&self.pool)
+                .await?;
+            tracing::info!("Added column {}.{}", table, column);
+        }
+
+        Ok(())
+    }
+
     /// Seed default upstream DNS servers if the table is empty
     async fn seed_default_upstreams(&self) -> Result<()> {
         // Check if any upstream servers exist
@@ -16,10 +16,30 @@ pub struct DnsRecord {
     pub ttl: i32,
     pub priority: i32,
     pub enabled: bool,
+    /// Owned by the declarative config file
+    #[serde(default)]
+    pub managed: bool,
+    /// Kept in sync with an external file (`hosts`); read-only in the UI
+    #[serde(default)]
+    pub source: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Outcome of syncing the records of one source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SourceSyncReport {
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+impl SourceSyncReport {
+    pub fn changed(&self) -> bool {
+        self.created + self.updated + self.deleted > 0
+    }
+}
+
 /// Create DNS record request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateDnsRecord {
@@ -45,6 +65,281 @@ pub struct UpdateDnsRecord {
     pub enabled: Option<bool>,
 }
 
+/// Zone answered authoritatively from local records
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DnsZone {
+    pub id: i64,
+    /// Zone apex, lowercase without the trailing dot
+    pub origin: String,
+    /// SOA MNAME
+    pub primary_ns: String,
+    /// SOA RNAME (`hostmaster.example.com`)
+    pub admin_email: String,
+    /// Bumped whenever the zone or a record in it changes
+    pub serial: i64,
+    pub refresh: i32,
+    pub retry: i32,
+    pub expire: i32,
+    /// Negative caching TTL
+    pub minimum: i32,
+    /// TTL of the SOA and apex NS records
+    pub ttl: i32,
+    /// Comma-separated apex name servers, a list in JSON
+    #[serde(with = "comma_list")]
+    pub nameservers: String,
+    pub enabled: bool,
+    /// `primary` (served from local records) or `secondary` (pulled from `primaries`)
+    pub kind: String,
+    /// Servers a secondary zone is transferred from (`ip` or `ip:port`), a list in JSON
+    #[serde(with = "comma_list")]
+    pub primaries: String,
+    /// Addresses and CIDRs allowed to transfer the zone, a list in JSON; empty denies all
+    #[serde(with = "comma_list")]
+    pub transfer_acl: String,
+    /// Servers sent a NOTIFY when the serial changes, a list in JSON
+    #[serde(with = "comma_list")]
+    pub notify: String,
+    /// Owned by the declarative config file
+    #[serde(default)]
+    pub managed: bool,
+    /// Fingerprint of the served data the serial belongs to
+    #[serde(skip)]
+    pub content_hash: Option<String>,
+    /// Records served at the current serial as JSON; the transferred data for secondaries
+    #[serde(skip)]
+    pub content: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Kind of a zone pulled from other servers
+pub const ZONE_KIND_SECONDARY: &str = "secondary";
+
+/// Items of a comma-separated column
+fn split_list(value: &str) -> Vec<&str> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty()).collect()
+}
+
+impl DnsZone {
+    /// Apex name servers
+    pub fn nameserver_list(&self) -> Vec<&str> {
+        split_list(&self.nameservers)
+    }
+
+    /// Whether the zone is transferred in from its primaries
+    pub fn is_secondary(&self) -> bool {
+        self.kind == ZONE_KIND_SECONDARY
+    }
+
+    /// Primary servers of a secondary zone
+    pub fn primary_list(&self) -> Vec<&str> {
+        split_list(&self.primaries)
+    }
+
+    /// Entries allowed to transfer the zone
+    pub fn transfer_acl_list(&self) -> Vec<&str> {
+        split_list(&self.transfer_acl)
+    }
+
+    /// Servers to NOTIFY on changes
+    pub fn notify_list(&self) -> Vec<&str> {
+        split_list(&self.notify)
+    }
+}
+
+/// (De)serialize a comma-separated column as a JSON list
+mod comma_list {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.split(',').map(str::trim).filter(|v| !v.is_empty()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Ok(Vec::<String>::deserialize(deserializer)?.join(","))
+    }
+}
+
+/// Create zone request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateDnsZone {
+    pub origin: String,
+    pub primary_ns: String,
+    pub admin_email: String,
+    #[serde(default = "default_zone_refresh")]
+    pub refresh: i32,
+    #[serde(default = "default_zone_retry")]
+    pub retry: i32,
+    #[serde(default = "default_zone_expire")]
+    pub expire: i32,
+    #[serde(default = "default_ttl")]
+    pub minimum: i32,
+    #[serde(default = "default_zone_ttl")]
+    pub ttl: i32,
+    #[serde(default)]
+    pub nameservers: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_zone_kind")]
+    pub kind: String,
+    #[serde(default)]
+    pub primaries: String,
+    #[serde(default)]
+    pub transfer_acl: String,
+    #[serde(default)]
+    pub notify: String,
+}
+
+impl CreateDnsZone {
+    /// Zone with the default timers, served by its primary name server only
+    pub fn new(origin: String, primary_ns: String, admin_email: String) -> Self {
+        Self {
+            nameservers: primary_ns.clone(),
+            origin,
+            primary_ns,
+            admin_email,
+            refresh: default_zone_refresh(),
+            retry: default_zone_retry(),
+            expire: default_zone_expire(),
+            minimum: default_ttl(),
+            ttl: default_zone_ttl(),
+            enabled: default_enabled(),
+            kind: default_zone_kind(),
+            primaries: String::new(),
+            transfer_acl: String::new(),
+            notify: String::new(),
+        }
+    }
+}
+
+/// Update zone request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateDnsZone {
+    pub origin: Option<String>,
+    pub primary_ns: Option<String>,
+    pub admin_email: Option<String>,
+    pub refresh: Option<i32>,
+    pub retry: Option<i32>,
+    pub expire: Option<i32>,
+    pub minimum: Option<i32>,
+    pub ttl: Option<i32>,
+    pub nameservers: Option<String>,
+    pub enabled: Option<bool>,
+    pub kind: Option<String>,
+    pub primaries: Option<String>,
+    pub transfer_acl: Option<String>,
+    pub notify: Option<String>,
+}
+
+/// One serial step of a zone, kept for incremental transfers (IXFR)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ZoneJournalEntry {
+    pub id: i64,
+    pub zone_id: i64,
+    pub serial_from: i64,
+    pub serial_to: i64,
+    /// Records removed by the step, as JSON
+    pub removed: String,
+    /// Records added by the step, as JSON
+    pub added: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// New zone journal step
+#[derive(Debug, Clone)]
+pub struct CreateZoneJournalEntry {
+    pub serial_from: i64,
+    pub serial_to: i64,
+    pub removed: String,
+    pub added: String,
+}
+
+/// Shared secret authenticating dynamic updates (TSIG, RFC 8945)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TsigKey {
+    pub id: i64,
+    /// Key name as sent in the TSIG record, lowercase without the trailing dot
+    pub name: String,
+    /// `hmac-sha256`, `hmac-sha224`, `hmac-sha384` or `hmac-sha512`
+    pub algorithm: String,
+    /// Base64 secret
+    pub secret: String,
+    /// Zones the key may update, a list in JSON
+    #[serde(with = "comma_list")]
+    pub zones: String,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TsigKey {
+    /// Origins of the zones the key may update
+    pub fn zone_list(&self) -> Vec<&str> {
+        split_list(&self.zones)
+    }
+}
+
+/// Create TSIG key request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateTsigKey {
+    pub name: String,
+    pub algorithm: String,
+    pub secret: String,
+    #[serde(default)]
+    pub zones: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+/// Update TSIG key request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateTsigKey {
+    pub algorithm: Option<String>,
+    pub secret: Option<String>,
+    pub zones: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+/// Dynamic update received by the DNS server, kept for auditing
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DnsUpdateLog {
+    pub id: i64,
+    /// Zone named in the update, as sent
+    pub zone: String,
+    /// TSIG key that signed the update; `None` when unsigned
+    pub key_name: Option<String>,
+    pub client_ip: String,
+    /// Response code sent back, e.g. `NOERROR` or `NXRRSET`
+    pub rcode: String,
+    /// Applied changes, one per line in the database and a list in JSON
+    #[serde(with = "line_list")]
+    pub changes: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// New dynamic update audit entry
+#[derive(Debug, Clone)]
+pub struct CreateDnsUpdateLog {
+    pub zone: String,
+    pub key_name: Option<String>,
+    pub client_ip: String,
+    pub rcode: String,
+    pub changes: Vec<String>,
+}
+
+/// (De)serialize a newline-separated column as a JSON list
+mod line_list {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.lines().filter(|v| !v.is_empty()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Ok(Vec::<String>::deserialize(deserializer)?.join("\n"))
+    }
+}
+
 /// Rewrite rule entity
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct RewriteRule {
@@ -56,6 +351,9 @@ pub struct RewriteRule {
     pub priority: i32,
     pub enabled: bool,
     pub description: Option<String>,
+    /// Owned by the declarative config file
+    #[serde(default)]
+    pub managed: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -96,6 +394,19 @@ pub struct UpstreamServer {
     pub protocol: String,
     pub timeout: i32,
     pub enabled: bool,
+    /// Outbound proxy URL (socks5://[user:pass@]host:port or http://host:port)
+    pub proxy: Option<String>,
+    /// Local source address for outbound connections
+    pub bind_address: Option<String>,
+    /// Network interface to bind to (SO_BINDTODEVICE)
+    pub bind_interface: Option<String>,
+    /// Upstream group for anti-pollution routing ("domestic" or "trusted")
+    pub group_name: Option<String>,
+    /// Health policy as JSON (defaults when empty)
+    pub health_policy: Option<String>,
+    /// Owned by the declarative config file
+    #[serde(default)]
+    pub managed: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -110,9 +421,22 @@ pub struct CreateUpstreamServer {
     pub timeout: i32,
     #[serde(default = "default_enabled")]
     pub enabled: bool,
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub bind_address: Option<String>,
+    #[serde(default)]
+    pub bind_interface: Option<String>,
+    #[serde(default)]
+    pub group_name: Option<String>,
+    #[serde(default)]
+    pub health_policy: Option<String>,
 }
 
 /// Update upstream server request
+///
+/// For the outbound settings (`proxy`, `bind_address`, `bind_interface`)
+/// and `group_name`/`health_policy` an empty string clears the stored value.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UpdateUpstreamServer {
     pub name: Option<String>,
@@ -120,6 +444,11 @@ pub struct UpdateUpstreamServer {
     pub protocol: Option<String>,
     pub timeout: Option<i32>,
     pub enabled: Option<bool>,
+    pub proxy: Option<String>,
+    pub bind_address: Option<String>,
+    pub bind_interface: Option<String>,
+    pub group_name: Option<String>,
+    pub health_policy: Option<String>,
 }
 
 /// Query log entity
@@ -133,12 +462,26 @@ pub struct QueryLog {
     pub response_time: Option<i32>,
     pub cache_hit: bool,
     pub upstream_used: Option<String>,
+    /// Why the answer was chosen (e.g. anti-pollution routing)
+    pub decision: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// Answer records as a JSON array of `{name, type, ttl, value}`
+    pub answers: Option<String>,
+    /// What produced the answer: upstream, cache, local, rewrite, blocked, filtered, disabled, invalid or error
+    pub source: Option<String>,
+    /// Rewrite rule or local record that answered
+    pub source_id: Option<i64>,
+    /// Why the query was blocked or filtered
+    pub reason: Option<String>,
+    /// Listener protocol: udp, tcp, dot, doh or doq
+    pub protocol: Option<String>,
+    /// DoH request path or TLS server name (SNI)
+    pub endpoint: Option<String>,
 }
 
 
 /// Create query log request
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateQueryLog {
     pub client_ip: String,
     pub query_name: String,
@@ -148,6 +491,20 @@ pub struct CreateQueryLog {
     #[serde(default)]
     pub cache_hit: bool,
     pub upstream_used: Option<String>,
+    #[serde(default)]
+    pub decision: Option<String>,
+    #[serde(default)]
+    pub answers: Option<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub source_id: Option<i64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub protocol: Option<String>,
+    #[serde(default)]
+    pub endpoint: Option<String>,
 }
 
 /// System config entity
@@ -166,7 +523,50 @@ pub struct QueryLogFilter {
     pub query_type: Option<String>,
     pub client_ip: Option<String>,
     pub cache_hit: Option<bool>,
+    pub response_code: Option<String>,
+    /// Substring of the answer data (e.g. an IP address)
+    pub answer: Option<String>,
+    pub source: Option<String>,
+    pub source_id: Option<i64>,
+    /// Substring of the block/filter reason
+    pub reason: Option<String>,
+    pub protocol: Option<String>,
+    /// Substring of the DoH path or SNI
+    pub endpoint: Option<String>,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Passive DNS entry: one distinct answer seen for a name and type
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PassiveDnsEntry {
+    pub id: i64,
+    pub name: String,
+    pub record_type: String,
+    pub value: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Upstream answers that contained this value
+    pub count: i64,
+}
+
+/// Passive DNS search filter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PassiveDnsFilter {
+    pub name: Option<String>,
+    /// Also match subdomains of `name`
+    #[serde(default)]
+    pub include_subdomains: bool,
+    /// Exact answer value (e.g. a CNAME target)
+    pub value: Option<String>,
+    /// IP address or CIDR the answer must fall into
+    pub ip: Option<String>,
+    pub record_type: Option<String>,
+    /// Only entries seen at or after this time
     pub start_time: Option<DateTime<Utc>>,
+    /// Only entries seen at or before this time
     pub end_time: Option<DateTime<Utc>>,
     pub limit: Option<i64>,
     pub offset: Option<i64>,
@@ -186,6 +586,26 @@ fn default_ttl() -> i32 {
     300
 }
 
+fn default_zone_refresh() -> i32 {
+    3600
+}
+
+fn default_zone_retry() -> i32 {
+    600
+}
+
+fn default_zone_expire() -> i32 {
+    604800
+}
+
+fn default_zone_ttl() -> i32 {
+    3600
+}
+
+fn default_zone_kind() -> String {
+    "primary".to_string()
+}
+
 fn default_timeout() -> i32 {
     5000
 }
@@ -205,6 +625,9 @@ pub struct ServerListener {
     pub port: i32,
     pub tls_cert: Option<String>,
     pub tls_key: Option<String>,
+    /// Owned by the declarative config file
+    #[serde(default)]
+    pub managed: bool,
     pub created_at: String,
     pub updated_at: String,
 }
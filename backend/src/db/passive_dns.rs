@@ -0,0 +1,371 @@
+//! Passive DNS Recorder
+//!
+//! Keeps a deduplicated history of upstream answers as
+//! (name, type, value, first seen, last seen, count), so an IP can be
+//! traced back to the domains that resolved to it long after the query
+//! logs are gone. Observations are aggregated in memory and merged into
+//! the `passive_dns` table periodically; entries not seen within the
+//! retention period are purged.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::Database;
+
+/// How often aggregated observations are written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often expired entries are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Distinct answers held in memory between flushes
+const MAX_PENDING: usize = 50_000;
+
+/// Passive DNS settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassiveDnsConfig {
+    /// Record upstream answers
+    pub enabled: bool,
+    /// Days an entry is kept after it was last seen
+    pub retention_days: u32,
+}
+
+impl Default for PassiveDnsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 90,
+        }
+    }
+}
+
+impl PassiveDnsConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=3650).contains(&self.retention_days) {
+            return Err(anyhow!("retention_days must be between 1 and 3650"));
+        }
+        Ok(())
+    }
+}
+
+/// Answers aggregated since the last flush
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassiveDnsObservation {
+    pub name: String,
+    pub record_type: String,
+    pub value: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub count: i64,
+}
+
+/// Recorder counters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PassiveDnsStats {
+    /// Answer records observed
+    pub observed: u64,
+    /// Distinct answers merged into the table
+    pub written: u64,
+    /// New answers rejected because too many were pending
+    pub dropped: u64,
+    /// Distinct answers lost because their batch failed to write
+    pub failed: u64,
+    /// Entries deleted by retention
+    pub purged: u64,
+    /// Distinct answers waiting for the next flush
+    pub pending: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    observed: AtomicU64,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+    purged: AtomicU64,
+}
+
+type AnswerKey = (String, String, String);
+
+/// Aggregating passive DNS recorder
+pub struct PassiveDnsRecorder {
+    pending: Mutex<HashMap<AnswerKey, PassiveDnsObservation>>,
+    config: RwLock<PassiveDnsConfig>,
+    counters: Counters,
+    shutdown: CancellationToken,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl PassiveDnsRecorder {
+    /// Start the background flush and retention task
+    pub fn start(db: Arc<Database>, config: PassiveDnsConfig) -> Arc<Self> {
+        let recorder = Arc::new(Self {
+            pending: Mutex::new(HashMap::new()),
+            config: RwLock::new(config),
+            counters: Counters::default(),
+            shutdown: CancellationToken::new(),
+            task: Mutex::new(None),
+        });
+        let task = tokio::spawn(run(db, recorder.clone()));
+        *recorder.task.lock().unwrap() = Some(task);
+        recorder
+    }
+
+    /// Record one answer record; never waits on the database
+    pub fn observe(&self, name: &str, record_type: &str, value: &str) {
+        if !self.config.read().unwrap().enabled {
+            return;
+        }
+        let record_type = record_type.to_uppercase();
+        let value = normalize_value(&record_type, value);
+        let key = (normalize_name(name), record_type, value);
+        let now = Utc::now();
+        self.counters.observed.fetch_add(1, Ordering::Relaxed);
+
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(obs) = pending.get_mut(&key) {
+            obs.last_seen = now;
+            obs.count += 1;
+        } else if pending.len() >= MAX_PENDING {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let (name, record_type, value) = key.clone();
+            pending.insert(key, PassiveDnsObservation {
+                name,
+                record_type,
+                value,
+                first_seen: now,
+                last_seen: now,
+                count: 1,
+            });
+        }
+    }
+
+    /// Current settings
+    pub fn config(&self) -> PassiveDnsConfig {
+        *self.config.read().unwrap()
+    }
+
+    /// Apply new settings; retention applies from the next purge
+    pub fn set_config(&self, config: PassiveDnsConfig) -> Result<()> {
+        config.validate()?;
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> PassiveDnsStats {
+        let c = &self.counters;
+        PassiveDnsStats {
+            observed: c.observed.load(Ordering::Relaxed),
+            written: c.written.load(Ordering::Relaxed),
+            dropped: c.dropped.load(Ordering::Relaxed),
+            failed: c.failed.load(Ordering::Relaxed),
+            purged: c.purged.load(Ordering::Relaxed),
+            pending: self.pending.lock().unwrap().len(),
+        }
+    }
+
+    /// Write pending answers and stop the recorder
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            if let Err(e) = task.await {
+                tracing::warn!("Passive DNS recorder stopped abnormally: {}", e);
+            }
+        }
+    }
+
+    /// Merge pending answers into the table
+    async fn flush(&self, db: &Database) {
+        let batch: Vec<PassiveDnsObservation> = std::mem::take(&mut *self.pending.lock().unwrap())
+            .into_values()
+            .collect();
+        if batch.is_empty() {
+            return;
+        }
+        let count = batch.len() as u64;
+        match db.passive_dns().upsert_batch(&batch).await {
+            Ok(()) => {
+                self.counters.written.fetch_add(count, Ordering::Relaxed);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to write {} passive DNS entries: {}", count, e);
+                self.counters.failed.fetch_add(count, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Delete entries older than the retention period
+    async fn purge(&self, db: &Database) {
+        let retention_days = self.config().retention_days;
+        let before = Utc::now() - chrono::Duration::days(retention_days as i64);
+        match db.passive_dns().delete_before(before).await {
+            Ok(deleted) => {
+                if deleted > 0 {
+                    tracing::info!("Passive DNS: deleted {} entries not seen for {} days", deleted, retention_days);
+                }
+                self.counters.purged.fetch_add(deleted, Ordering::Relaxed);
+            }
+            Err(e) => tracing::warn!("Passive DNS retention cleanup failed: {}", e),
+        }
+    }
+}
+
+/// Recorder loop: flush on a timer, purge hourly, flush once more on shutdown
+async fn run(db: Arc<Database>, recorder: Arc<PassiveDnsRecorder>) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut purge = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = recorder.shutdown.cancelled() => break,
+            _ = flush.tick() => recorder.flush(&db).await,
+            _ = purge.tick() => recorder.purge(&db).await,
+        }
+    }
+
+    recorder.flush(&db).await;
+    tracing::info!("Passive DNS recorder stopped");
+}
+
+/// Lowercase a domain name and drop the trailing dot
+pub fn normalize_name(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// Canonical form of an answer value: addresses re-formatted, names normalized
+fn normalize_value(record_type: &str, value: &str) -> String {
+    match record_type {
+        "A" | "AAAA" => value.trim().parse::<IpAddr>()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|_| value.trim().to_string()),
+        "CNAME" | "NS" | "PTR" => normalize_name(value),
+        _ => value.trim().to_string(),
+    }
+}
+
+/// Sortable key of an address: 32 hex digits, IPv4 mapped into IPv6
+pub fn ip_key(ip: IpAddr) -> String {
+    let v6 = match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    };
+    format!("{:032x}", u128::from(v6))
+}
+
+/// Inclusive `ip_key` bounds of an address or CIDR
+pub fn ip_key_range(cidr: &str) -> Result<(String, String)> {
+    let (addr, prefix) = match cidr.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (cidr.trim(), None),
+    };
+    let addr: IpAddr = addr.parse()
+        .map_err(|_| anyhow!("Invalid IP or CIDR '{}': bad address", cidr))?;
+    let (max_prefix, offset) = if addr.is_ipv4() { (32, 96) } else { (128, 0) };
+    let prefix: u32 = match prefix {
+        Some(p) => p.trim().parse().ok().filter(|p| *p <= max_prefix)
+            .ok_or_else(|| anyhow!("Invalid IP or CIDR '{}': bad prefix length", cidr))?,
+        None => max_prefix,
+    };
+
+    let start = u128::from_str_radix(&ip_key(addr), 16)?;
+    let mask = u128::MAX.checked_shl(128 - (prefix + offset)).unwrap_or(0);
+    let start = start & mask;
+    Ok((format!("{:032x}", start), format!("{:032x}", start | !mask)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::PassiveDnsFilter;
+    use tempfile::TempDir;
+
+    async fn setup_test_db() -> (TempDir, Arc<Database>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        let db = Arc::new(Database::new(&db_url).await.unwrap());
+        (dir, db)
+    }
+
+    fn in_range(cidr: &str, ip: &str) -> bool {
+        let (start, end) = ip_key_range(cidr).unwrap();
+        let key = ip_key(ip.parse().unwrap());
+        start <= key && key <= end
+    }
+
+    #[test]
+    fn test_ip_key_range() {
+        assert!(in_range("10.0.0.0/8", "10.255.1.2"));
+        assert!(!in_range("10.0.0.0/8", "11.0.0.1"));
+        assert!(in_range("192.168.1.7", "192.168.1.7"));
+        assert!(!in_range("192.168.1.7", "192.168.1.8"));
+        assert!(in_range("0.0.0.0/0", "8.8.8.8"));
+        assert!(!in_range("0.0.0.0/0", "2001:db8::1"));
+        assert!(in_range("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!in_range("2001:db8::/32", "2001:db9::1"));
+        assert!(ip_key_range("10.0.0.0/33").is_err());
+        assert!(ip_key_range("example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_observations_are_merged_and_searchable() {
+        let (_dir, db) = setup_test_db().await;
+        let recorder = PassiveDnsRecorder::start(db.clone(), PassiveDnsConfig::default());
+
+        for _ in 0..3 {
+            recorder.observe("WWW.Example.com.", "A", "203.0.113.10");
+        }
+        recorder.observe("api.example.com", "A", "203.0.113.11");
+        recorder.observe("cdn.example.com", "CNAME", "Edge.CDN.net.");
+        recorder.observe("other.test", "A", "198.51.100.1");
+        recorder.shutdown().await;
+
+        let stats = recorder.stats();
+        assert_eq!((stats.observed, stats.written, stats.pending), (6, 4, 0));
+
+        let repo = db.passive_dns();
+        let by_ip = repo.search(&PassiveDnsFilter {
+            ip: Some("203.0.113.0/24".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        let mut names: Vec<_> = by_ip.items.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["api.example.com", "www.example.com"]);
+
+        let history = repo.search(&PassiveDnsFilter {
+            name: Some("www.example.com".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(history.total, 1);
+        assert_eq!(history.items[0].count, 3);
+
+        let subdomains = repo.search(&PassiveDnsFilter {
+            name: Some("example.com".to_string()),
+            include_subdomains: true,
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(subdomains.total, 3);
+
+        let by_target = repo.search(&PassiveDnsFilter {
+            value: Some("edge.cdn.net".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(by_target.items[0].name, "cdn.example.com");
+
+        assert_eq!(repo.delete_before(Utc::now() + chrono::Duration::seconds(1)).await.unwrap(), 4);
+        assert_eq!(repo.search(&PassiveDnsFilter::default()).await.unwrap().total, 0);
+    }
+}
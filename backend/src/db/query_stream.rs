@@ -0,0 +1,253 @@
+//! Live Query Stream
+//!
+//! Every resolved query is published on a bounded broadcast channel for
+//! push consumers (the SSE endpoint). Publishing never waits: a consumer
+//! that falls more than `STREAM_BUFFER` events behind skips the oldest
+//! ones and is told how many it missed.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use super::{ip_key, ip_key_range, CreateQueryLog};
+
+/// Events kept for slow consumers before they start losing them
+const STREAM_BUFFER: usize = 1024;
+
+/// One resolved query as published on the stream
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryEvent {
+    /// Monotonic event number (gaps mean the consumer missed events)
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub log: CreateQueryLog,
+}
+
+/// Live query stream counters
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct QueryStreamStats {
+    /// Events published while someone was listening
+    pub published: u64,
+    /// Events skipped by consumers that fell behind
+    pub lagged: u64,
+    /// Currently connected consumers
+    pub subscribers: usize,
+}
+
+/// Broadcasts resolved queries to live consumers
+pub struct QueryStream {
+    sender: broadcast::Sender<Arc<QueryEvent>>,
+    next_id: AtomicU64,
+    lagged: AtomicU64,
+}
+
+impl Default for QueryStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QueryStream {
+    /// Create a stream with the default buffer
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(STREAM_BUFFER);
+        Self {
+            sender,
+            next_id: AtomicU64::new(1),
+            lagged: AtomicU64::new(0),
+        }
+    }
+
+    /// Publish a resolved query (a no-op when nobody is listening)
+    pub fn publish(&self, log: &CreateQueryLog) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let event = QueryEvent {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: Utc::now(),
+            log: log.clone(),
+        };
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    /// Start receiving events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<QueryEvent>> {
+        self.sender.subscribe()
+    }
+
+    /// Count events a consumer skipped because it fell behind
+    pub fn record_lagged(&self, skipped: u64) {
+        self.lagged.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Snapshot of the counters
+    pub fn stats(&self) -> QueryStreamStats {
+        QueryStreamStats {
+            published: self.next_id.load(Ordering::Relaxed) - 1,
+            lagged: self.lagged.load(Ordering::Relaxed),
+            subscribers: self.sender.receiver_count(),
+        }
+    }
+}
+
+/// Server-side filter of a stream consumer
+#[derive(Debug, Clone, Default)]
+pub struct QueryStreamFilter {
+    /// Client IP range as hex keys (see `ip_key_range`)
+    client: Option<(String, String)>,
+    /// Lowercase domain pattern; `*` matches any run of characters,
+    /// without `*` any name containing the pattern matches
+    domain: Option<String>,
+    /// Response codes (upper case), any of which matches
+    rcodes: Vec<String>,
+}
+
+impl QueryStreamFilter {
+    /// Build a filter from the raw parameters
+    ///
+    /// `client` is an IP or CIDR, `rcode` a comma-separated list such as
+    /// `NXDOMAIN,SERVFAIL`.
+    pub fn new(client: Option<&str>, domain: Option<&str>, rcode: Option<&str>) -> Result<Self> {
+        let client = match client.map(str::trim).filter(|c| !c.is_empty()) {
+            Some(c) => Some(ip_key_range(c)?),
+            None => None,
+        };
+        let domain = domain
+            .map(|d| d.trim().trim_end_matches('.').to_lowercase())
+            .filter(|d| !d.is_empty());
+        let rcodes = rcode
+            .map(|r| {
+                r.split(',')
+                    .map(|c| c.trim().to_uppercase())
+                    .filter(|c| !c.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self { client, domain, rcodes })
+    }
+
+    /// Whether the event passes the filter
+    pub fn matches(&self, event: &QueryEvent) -> bool {
+        if let Some((ref start, ref end)) = self.client {
+            let Ok(ip) = event.log.client_ip.parse::<IpAddr>() else {
+                return false;
+            };
+            let key = ip_key(ip);
+            if key < *start || key > *end {
+                return false;
+            }
+        }
+        if let Some(ref pattern) = self.domain {
+            let name = event.log.query_name.trim_end_matches('.').to_lowercase();
+            let matched = if pattern.contains('*') {
+                glob_matches(pattern, &name)
+            } else {
+                name.contains(pattern.as_str())
+            };
+            if !matched {
+                return false;
+            }
+        }
+        if !self.rcodes.is_empty() {
+            // Errors are logged as "ERROR: <message>"
+            let rcode = event.log.response_code.as_deref().unwrap_or_default();
+            let rcode = rcode.split(':').next().unwrap_or_default().trim().to_uppercase();
+            if !self.rcodes.contains(&rcode) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Match `text` against a pattern where `*` stands for any run of characters
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(client_ip: &str, name: &str, rcode: &str) -> QueryEvent {
+        QueryEvent {
+            id: 1,
+            timestamp: Utc::now(),
+            log: CreateQueryLog {
+                client_ip: client_ip.to_string(),
+                query_name: name.to_string(),
+                query_type: "A".to_string(),
+                response_code: Some(rcode.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let all = QueryStreamFilter::new(None, None, None).unwrap();
+        assert!(all.matches(&event("10.0.0.1", "example.com", "NOERROR")));
+
+        let client = QueryStreamFilter::new(Some("10.0.0.0/24"), None, None).unwrap();
+        assert!(client.matches(&event("10.0.0.9", "example.com", "NOERROR")));
+        assert!(!client.matches(&event("10.0.1.9", "example.com", "NOERROR")));
+        assert!(!client.matches(&event("doh-client", "example.com", "NOERROR")));
+
+        let domain = QueryStreamFilter::new(None, Some("*.Example.com."), None).unwrap();
+        assert!(domain.matches(&event("10.0.0.1", "www.example.com", "NOERROR")));
+        assert!(!domain.matches(&event("10.0.0.1", "example.com", "NOERROR")));
+        let domain = QueryStreamFilter::new(None, Some("ads"), None).unwrap();
+        assert!(domain.matches(&event("10.0.0.1", "ads.tracker.net", "NOERROR")));
+
+        let rcode = QueryStreamFilter::new(None, None, Some("nxdomain, error")).unwrap();
+        assert!(rcode.matches(&event("10.0.0.1", "a.com", "NXDOMAIN")));
+        assert!(rcode.matches(&event("10.0.0.1", "a.com", "ERROR: timed out")));
+        assert!(!rcode.matches(&event("10.0.0.1", "a.com", "NOERROR")));
+
+        assert!(QueryStreamFilter::new(Some("not-an-ip"), None, None).is_err());
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*.example.com", "a.b.example.com"));
+        assert!(glob_matches("api*.example.*", "api-1.example.org"));
+        assert!(glob_matches("*", "anything"));
+        assert!(!glob_matches("a*a", "a"));
+        assert!(!glob_matches("api*.example.com", "www.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_lags_instead_of_blocking() {
+        let stream = QueryStream::new();
+        stream.publish(&CreateQueryLog::default());
+        assert_eq!(stream.stats().published, 0);
+
+        let mut rx = stream.subscribe();
+        for _ in 0..STREAM_BUFFER + 10 {
+            stream.publish(&CreateQueryLog::default());
+        }
+        assert!(matches!(rx.recv().await, Err(broadcast::error::RecvError::Lagged(10))));
+        assert_eq!(rx.recv().await.unwrap().id, 11);
+        assert_eq!(stream.stats().published, STREAM_BUFFER as u64 + 10);
+    }
+}
@@ -3,30 +3,47 @@
 //! CRUD operations for all database entities.
 
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use tokio::sync::broadcast;
 
 use super::models::*;
+use super::passive_dns::{ip_key, ip_key_range, normalize_name, PassiveDnsObservation};
+use super::rollup::{RollupBucket, RollupGranularity, TOP_KEEP};
+use super::DataChange;
 
 /// Repository for DNS records
 pub struct DnsRecordRepository {
     pool: SqlitePool,
+    changes: Option<broadcast::Sender<DataChange>>,
 }
 
 impl DnsRecordRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool, changes: None }
+    }
+
+    /// Announce writes on `changes`
+    pub fn with_changes(mut self, changes: broadcast::Sender<DataChange>) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    fn notify(&self) {
+        if let Some(ref changes) = self.changes {
+            let _ = changes.send(DataChange::DnsRecords);
+        }
     }
 
     /// Create a new DNS record
     pub async fn create(&self, record: CreateDnsRecord) -> Result<DnsRecord> {
         let now = Utc::now();
-        let result = sqlx::query_as::<_, DnsRecord>(
+        // Not `RETURNING`: the row must be committed before the snapshot reloads
+        let id = sqlx::query(
             r#"
             INSERT INTO dns_records (name, record_type, value, ttl, priority, enabled, created_at, updated_at)
             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
-            RETURNING *
             "#,
         )
         .bind(&record.name)
@@ -37,10 +54,14 @@ impl DnsRecordRepository {
         .bind(record.enabled)
         .bind(now)
         .bind(now)
-        .fetch_one(&self.pool)
-        .await?;
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
 
-        Ok(result)
+        self.notify();
+        self.get_by_id(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("DNS record {} vanished after insert", id))
     }
 
     /// Get a DNS record by ID
@@ -178,12 +199,11 @@ impl DnsRecordRepository {
         let priority = update.priority.unwrap_or(existing.priority);
         let enabled = update.enabled.unwrap_or(existing.enabled);
 
-        let result = sqlx::query_as::<_, DnsRecord>(
+        sqlx::query(
             r#"
             UPDATE dns_records 
             SET name = ?, record_type = ?, value = ?, ttl = ?, priority = ?, enabled = ?, updated_at = ?
             WHERE id = ?
-            RETURNING *
             "#,
         )
         .bind(&name)
@@ -194,10 +214,11 @@ impl DnsRecordRepository {
         .bind(enabled)
         .bind(Utc::now())
         .bind(id)
-        .fetch_optional(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        Ok(result)
+        self.notify();
+        self.get_by_id(id).await
     }
 
     /// Delete a DNS record
@@ -207,29 +228,588 @@ impl DnsRecordRepository {
             .execute(&self.pool)
             .await?;
 
+        self.notify();
         Ok(result.rows_affected() > 0)
     }
+
+    /// Flag a row as owned by the declarative config file
+    #[allow(dead_code)]
+    pub async fn set_managed(&self, id: i64, managed: bool) -> Result<()> {
+        sqlx::query("UPDATE dns_records SET managed = ? WHERE id = ?")
+            .bind(managed)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Batch create DNS records in one transaction
+    /// Returns the number of records created
+    pub async fn batch_create(&self, records: Vec<CreateDnsRecord>) -> Result<usize> {
+        if records.is_empty() {
+            return Ok(0);
+        }
+
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        for record in &records {
+            sqlx::query(
+                r#"
+                INSERT INTO dns_records (name, record_type, value, ttl, priority, enabled, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&record.name)
+            .bind(&record.record_type)
+            .bind(&record.value)
+            .bind(record.ttl)
+            .bind(record.priority)
+            .bind(record.enabled)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.notify();
+        Ok(records.len())
+    }
+
+    /// Delete, retime and create records in one transaction
+    ///
+    /// Used for dynamic updates, which must apply completely or not at all.
+    pub async fn apply_changes(
+        &self,
+        deleted: &[i64],
+        retimed: &[(i64, i32)],
+        created: &[CreateDnsRecord],
+    ) -> Result<()> {
+        if deleted.is_empty() && retimed.is_empty() && created.is_empty() {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        for id in deleted {
+            sqlx::query("DELETE FROM dns_records WHERE id = ?").bind(id).execute(&mut *tx).await?;
+        }
+        for (id, ttl) in retimed {
+            sqlx::query("UPDATE dns_records SET ttl = ?, updated_at = ? WHERE id = ?")
+                .bind(ttl)
+                .bind(now)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for record in created {
+            sqlx::query(
+                r#"
+                INSERT INTO dns_records (name, record_type, value, ttl, priority, enabled, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&record.name)
+            .bind(&record.record_type)
+            .bind(&record.value)
+            .bind(record.ttl)
+            .bind(record.priority)
+            .bind(record.enabled)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.notify();
+        Ok(())
+    }
+
+    /// Replace the records of a source with `records` in one transaction
+    ///
+    /// Rows are matched on name, type and value. A wanted record that already
+    /// exists as a row of another source (or a manual one) is left alone
+    /// instead of being duplicated.
+    pub async fn sync_source(&self, source: &str, records: Vec<CreateDnsRecord>) -> Result<SourceSyncReport> {
+        let mut report = SourceSyncReport::default();
+        let now = Utc::now();
+        // Read before the transaction so that it starts with a write and waits
+        // for other writers instead of failing to upgrade a read lock
+        let existing = self.list().await?;
+        let mut tx = self.pool.begin().await?;
+        let key = |name: &str, record_type: &str, value: &str| {
+            (name.to_lowercase(), record_type.to_uppercase(), value.to_lowercase())
+        };
+
+        let mut wanted = std::collections::HashSet::new();
+        for record in &records {
+            let record_key = key(&record.name, &record.record_type, &record.value);
+            if !wanted.insert(record_key.clone()) {
+                continue;
+            }
+            match existing.iter().find(|row| key(&row.name, &row.record_type, &row.value) == record_key) {
+                Some(row) if row.source.as_deref() == Some(source) => {
+                    if row.ttl != record.ttl || row.priority != record.priority || row.enabled != record.enabled {
+                        sqlx::query("UPDATE dns_records SET ttl = ?, priority = ?, enabled = ?, updated_at = ? WHERE id = ?")
+                            .bind(record.ttl)
+                            .bind(record.priority)
+                            .bind(record.enabled)
+                            .bind(now)
+                            .bind(row.id)
+                            .execute(&mut *tx)
+                            .await?;
+                        report.updated += 1;
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO dns_records (name, record_type, value, ttl, priority, enabled, source, created_at, updated_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(&record.name)
+                    .bind(&record.record_type)
+                    .bind(&record.value)
+                    .bind(record.ttl)
+                    .bind(record.priority)
+                    .bind(record.enabled)
+                    .bind(source)
+                    .bind(now)
+                    .bind(now)
+                    .execute(&mut *tx)
+                    .await?;
+                    report.created += 1;
+                }
+            }
+        }
+
+        for row in existing.iter().filter(|row| row.source.as_deref() == Some(source)) {
+            if !wanted.contains(&key(&row.name, &row.record_type, &row.value)) {
+                sqlx::query("DELETE FROM dns_records WHERE id = ?").bind(row.id).execute(&mut *tx).await?;
+                report.deleted += 1;
+            }
+        }
+
+        tx.commit().await?;
+        if report.changed() {
+            self.notify();
+        }
+        Ok(report)
+    }
 }
 
 
+/// First serial of the day in `YYYYMMDDnn` form
+pub fn serial_base(now: DateTime<Utc>) -> i64 {
+    now.format("%Y%m%d").to_string().parse::<i64>().unwrap_or(0) * 100
+}
+
+/// Serial following `current`, kept in `YYYYMMDDnn` form while possible
+pub fn next_zone_serial(current: i64, now: DateTime<Utc>) -> i64 {
+    let next = (current + 1).max(serial_base(now));
+    // Serials are 32-bit on the wire; wrap instead of overflowing
+    if next > u32::MAX as i64 {
+        1
+    } else {
+        next
+    }
+}
+
+/// Journal steps kept per zone for IXFR
+pub const ZONE_JOURNAL_KEEP: i64 = 100;
+
+/// Repository for authoritative zones
+pub struct DnsZoneRepository {
+    pool: SqlitePool,
+    changes: Option<broadcast::Sender<DataChange>>,
+}
+
+impl DnsZoneRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool, changes: None }
+    }
+
+    /// Announce writes on `changes`
+    pub fn with_changes(mut self, changes: broadcast::Sender<DataChange>) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    fn notify(&self) {
+        if let Some(ref changes) = self.changes {
+            let _ = changes.send(DataChange::DnsZones);
+        }
+    }
+
+    /// Create a zone, starting its serial at today's first
+    pub async fn create(&self, zone: CreateDnsZone) -> Result<DnsZone> {
+        let now = Utc::now();
+        let id = sqlx::query(
+            r#"
+            INSERT INTO dns_zones (origin, primary_ns, admin_email, serial, refresh, retry, expire, minimum, ttl, nameservers, enabled, kind, primaries, transfer_acl, notify, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&zone.origin)
+        .bind(&zone.primary_ns)
+        .bind(&zone.admin_email)
+        .bind(serial_base(now) + 1)
+        .bind(zone.refresh)
+        .bind(zone.retry)
+        .bind(zone.expire)
+        .bind(zone.minimum)
+        .bind(zone.ttl)
+        .bind(&zone.nameservers)
+        .bind(zone.enabled)
+        .bind(&zone.kind)
+        .bind(&zone.primaries)
+        .bind(&zone.transfer_acl)
+        .bind(&zone.notify)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
+
+        self.notify();
+        self.get_by_id(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Zone {} vanished after insert", id))
+    }
+
+    /// Get a zone by ID
+    pub async fn get_by_id(&self, id: i64) -> Result<Option<DnsZone>> {
+        let result = sqlx::query_as::<_, DnsZone>("SELECT * FROM dns_zones WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// Get a zone by origin
+    pub async fn get_by_origin(&self, origin: &str) -> Result<Option<DnsZone>> {
+        let result = sqlx::query_as::<_, DnsZone>("SELECT * FROM dns_zones WHERE origin = ?")
+            .bind(origin)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// List all zones
+    pub async fn list(&self) -> Result<Vec<DnsZone>> {
+        let result = sqlx::query_as::<_, DnsZone>("SELECT * FROM dns_zones ORDER BY origin")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// Update a zone
+    pub async fn update(&self, id: i64, update: UpdateDnsZone) -> Result<Option<DnsZone>> {
+        let Some(existing) = self.get_by_id(id).await? else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE dns_zones
+            SET origin = ?, primary_ns = ?, admin_email = ?, refresh = ?, retry = ?, expire = ?, minimum = ?, ttl = ?, nameservers = ?, enabled = ?,
+                kind = ?, primaries = ?, transfer_acl = ?, notify = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(update.origin.unwrap_or(existing.origin))
+        .bind(update.primary_ns.unwrap_or(existing.primary_ns))
+        .bind(update.admin_email.unwrap_or(existing.admin_email))
+        .bind(update.refresh.unwrap_or(existing.refresh))
+        .bind(update.retry.unwrap_or(existing.retry))
+        .bind(update.expire.unwrap_or(existing.expire))
+        .bind(update.minimum.unwrap_or(existing.minimum))
+        .bind(update.ttl.unwrap_or(existing.ttl))
+        .bind(update.nameservers.unwrap_or(existing.nameservers))
+        .bind(update.enabled.unwrap_or(existing.enabled))
+        .bind(update.kind.unwrap_or(existing.kind))
+        .bind(update.primaries.unwrap_or(existing.primaries))
+        .bind(update.transfer_acl.unwrap_or(existing.transfer_acl))
+        .bind(update.notify.unwrap_or(existing.notify))
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        self.notify();
+        self.get_by_id(id).await
+    }
+
+    /// Delete a zone and its journal
+    pub async fn delete(&self, id: i64) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM dns_zones WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM zone_journal WHERE zone_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.notify();
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Store a serial and the content and fingerprint it belongs to
+    ///
+    /// Does not announce a change: the serial is derived from data the
+    /// snapshots have already seen.
+    pub async fn set_serial(&self, id: i64, serial: i64, content_hash: &str, content: &str) -> Result<()> {
+        sqlx::query("UPDATE dns_zones SET serial = ?, content_hash = ?, content = ? WHERE id = ?")
+            .bind(serial)
+            .bind(content_hash)
+            .bind(content)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Move a zone to a new serial and journal the step, in one transaction
+    ///
+    /// Only the last [`ZONE_JOURNAL_KEEP`] steps of a zone are kept.
+    pub async fn record_change(
+        &self,
+        id: i64,
+        content_hash: &str,
+        content: &str,
+        change: CreateZoneJournalEntry,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE dns_zones SET serial = ?, content_hash = ?, content = ? WHERE id = ?")
+            .bind(change.serial_to)
+            .bind(content_hash)
+            .bind(content)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO zone_journal (zone_id, serial_from, serial_to, removed, added, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(change.serial_from)
+        .bind(change.serial_to)
+        .bind(&change.removed)
+        .bind(&change.added)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM zone_journal
+            WHERE zone_id = ? AND id NOT IN (SELECT id FROM zone_journal WHERE zone_id = ? ORDER BY id DESC LIMIT ?)
+            "#,
+        )
+        .bind(id)
+        .bind(id)
+        .bind(ZONE_JOURNAL_KEEP)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Store the data of a secondary zone received from its primary
+    ///
+    /// `None` drops the data once the zone expires. Like `set_serial`, this
+    /// does not announce a change; the caller does once the data is stored.
+    pub async fn store_transfer(&self, id: i64, serial: i64, content: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE dns_zones SET serial = ?, content = ? WHERE id = ?")
+            .bind(serial)
+            .bind(content)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Journal of a zone, oldest step first
+    pub async fn journal(&self, zone_id: i64) -> Result<Vec<ZoneJournalEntry>> {
+        let result = sqlx::query_as::<_, ZoneJournalEntry>("SELECT * FROM zone_journal WHERE zone_id = ? ORDER BY id")
+            .bind(zone_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+}
+
+/// Repository for TSIG keys
+pub struct TsigKeyRepository {
+    pool: SqlitePool,
+}
+
+impl TsigKeyRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Create a key
+    pub async fn create(&self, key: CreateTsigKey) -> Result<TsigKey> {
+        let now = Utc::now();
+        let id = sqlx::query(
+            r#"
+            INSERT INTO tsig_keys (name, algorithm, secret, zones, enabled, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&key.name)
+        .bind(&key.algorithm)
+        .bind(&key.secret)
+        .bind(&key.zones)
+        .bind(key.enabled)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
+
+        self.get_by_id(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("TSIG key {} vanished after insert", id))
+    }
+
+    /// Get a key by ID
+    pub async fn get_by_id(&self, id: i64) -> Result<Option<TsigKey>> {
+        let result = sqlx::query_as::<_, TsigKey>("SELECT * FROM tsig_keys WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// Get a key by name
+    pub async fn get_by_name(&self, name: &str) -> Result<Option<TsigKey>> {
+        let result = sqlx::query_as::<_, TsigKey>("SELECT * FROM tsig_keys WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// List all keys
+    pub async fn list(&self) -> Result<Vec<TsigKey>> {
+        let result = sqlx::query_as::<_, TsigKey>("SELECT * FROM tsig_keys ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    /// Update a key; the name is fixed since clients sign with it
+    pub async fn update(&self, id: i64, update: UpdateTsigKey) -> Result<Option<TsigKey>> {
+        let Some(existing) = self.get_by_id(id).await? else {
+            return Ok(None);
+        };
+
+        sqlx::query("UPDATE tsig_keys SET algorithm = ?, secret = ?, zones = ?, enabled = ?, updated_at = ? WHERE id = ?")
+            .bind(update.algorithm.unwrap_or(existing.algorithm))
+            .bind(update.secret.unwrap_or(existing.secret))
+            .bind(update.zones.unwrap_or(existing.zones))
+            .bind(update.enabled.unwrap_or(existing.enabled))
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        self.get_by_id(id).await
+    }
+
+    /// Delete a key
+    pub async fn delete(&self, id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM tsig_keys WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Dynamic updates kept in the audit log
+pub const DNS_UPDATE_LOG_KEEP: i64 = 10_000;
+
+/// Repository for the dynamic update audit log
+pub struct DnsUpdateLogRepository {
+    pool: SqlitePool,
+}
+
+impl DnsUpdateLogRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Append an entry, dropping the oldest beyond [`DNS_UPDATE_LOG_KEEP`]
+    pub async fn create(&self, entry: CreateDnsUpdateLog) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO dns_update_log (zone, key_name, client_ip, rcode, changes, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&entry.zone)
+        .bind(&entry.key_name)
+        .bind(&entry.client_ip)
+        .bind(&entry.rcode)
+        .bind(entry.changes.join("\n"))
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("DELETE FROM dns_update_log WHERE id <= (SELECT MAX(id) FROM dns_update_log) - ?")
+            .bind(DNS_UPDATE_LOG_KEEP)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Newest entries first, optionally for one zone
+    pub async fn list(&self, zone: Option<&str>, limit: i64) -> Result<Vec<DnsUpdateLog>> {
+        let result = sqlx::query_as::<_, DnsUpdateLog>(
+            "SELECT * FROM dns_update_log WHERE (? IS NULL OR zone = ?) ORDER BY id DESC LIMIT ?",
+        )
+        .bind(zone)
+        .bind(zone)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+}
+
 /// Repository for rewrite rules
 pub struct RewriteRuleRepository {
     pool: SqlitePool,
+    changes: Option<broadcast::Sender<DataChange>>,
 }
 
 impl RewriteRuleRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool, changes: None }
+    }
+
+    /// Announce writes on `changes`
+    pub fn with_changes(mut self, changes: broadcast::Sender<DataChange>) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    fn notify(&self) {
+        if let Some(ref changes) = self.changes {
+            let _ = changes.send(DataChange::RewriteRules);
+        }
     }
 
     /// Create a new rewrite rule
     pub async fn create(&self, rule: CreateRewriteRule) -> Result<RewriteRule> {
         let now = Utc::now();
-        let result = sqlx::query_as::<_, RewriteRule>(
+        let id = sqlx::query(
             r#"
             INSERT INTO rewrite_rules (pattern, match_type, action_type, action_value, priority, enabled, description, created_at, updated_at)
             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
-            RETURNING *
             "#,
         )
         .bind(&rule.pattern)
@@ -241,10 +821,14 @@ impl RewriteRuleRepository {
         .bind(&rule.description)
         .bind(now)
         .bind(now)
-        .fetch_one(&self.pool)
-        .await?;
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
 
-        Ok(result)
+        self.notify();
+        self.get_by_id(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Rewrite rule {} vanished after insert", id))
     }
 
     /// Get a rewrite rule by ID
@@ -271,7 +855,6 @@ impl RewriteRuleRepository {
     }
 
     /// List enabled rewrite rules ordered by priority
-    #[allow(dead_code)]
     pub async fn list_enabled(&self) -> Result<Vec<RewriteRule>> {
         let result = sqlx::query_as::<_, RewriteRule>(
             "SELECT * FROM rewrite_rules WHERE enabled = TRUE ORDER BY priority DESC, id ASC",
@@ -299,12 +882,11 @@ impl RewriteRuleRepository {
         let enabled = update.enabled.unwrap_or(existing.enabled);
         let description = update.description.or(existing.description);
 
-        let result = sqlx::query_as::<_, RewriteRule>(
+        sqlx::query(
             r#"
             UPDATE rewrite_rules 
             SET pattern = ?, match_type = ?, action_type = ?, action_value = ?, priority = ?, enabled = ?, description = ?, updated_at = ?
             WHERE id = ?
-            RETURNING *
             "#,
         )
         .bind(&pattern)
@@ -316,10 +898,11 @@ impl RewriteRuleRepository {
         .bind(&description)
         .bind(Utc::now())
         .bind(id)
-        .fetch_optional(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        Ok(result)
+        self.notify();
+        self.get_by_id(id).await
     }
 
     /// Delete a rewrite rule
@@ -329,7 +912,11 @@ impl RewriteRuleRepository {
             .execute(&self.pool)
             .await?;
 
-        Ok(result.rows_affected() > 0)
+        let deleted = result.rows_affected() > 0;
+        if deleted {
+            self.notify();
+        }
+        Ok(deleted)
     }
 
     /// Batch create rewrite rules
@@ -367,9 +954,17 @@ impl RewriteRuleRepository {
         }
 
         tx.commit().await?;
+        self.notify();
         Ok(count)
     }
 }
+/// Normalize an optional text column: blank values are stored as NULL
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
 pub struct UpstreamServerRepository {
     pool: SqlitePool,
 }
@@ -384,8 +979,8 @@ impl UpstreamServerRepository {
         let now = Utc::now();
         let result = sqlx::query_as::<_, UpstreamServer>(
             r#"
-            INSERT INTO upstream_servers (name, address, protocol, timeout, enabled, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO upstream_servers (name, address, protocol, timeout, enabled, proxy, bind_address, bind_interface, group_name, health_policy, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             RETURNING *
             "#,
         )
@@ -394,6 +989,11 @@ impl UpstreamServerRepository {
         .bind(&server.protocol)
         .bind(server.timeout)
         .bind(server.enabled)
+        .bind(non_empty(server.proxy))
+        .bind(non_empty(server.bind_address))
+        .bind(non_empty(server.bind_interface))
+        .bind(non_empty(server.group_name))
+        .bind(non_empty(server.health_policy))
         .bind(now)
         .bind(now)
         .fetch_one(&self.pool)
@@ -469,11 +1069,17 @@ impl UpstreamServerRepository {
         let protocol = update.protocol.unwrap_or(existing.protocol);
         let timeout = update.timeout.unwrap_or(existing.timeout);
         let enabled = update.enabled.unwrap_or(existing.enabled);
+        let proxy = update.proxy.map_or(existing.proxy, |v| non_empty(Some(v)));
+        let bind_address = update.bind_address.map_or(existing.bind_address, |v| non_empty(Some(v)));
+        let bind_interface = update.bind_interface.map_or(existing.bind_interface, |v| non_empty(Some(v)));
+        let group_name = update.group_name.map_or(existing.group_name, |v| non_empty(Some(v)));
+        let health_policy = update.health_policy.map_or(existing.health_policy, |v| non_empty(Some(v)));
 
         let result = sqlx::query_as::<_, UpstreamServer>(
             r#"
             UPDATE upstream_servers 
-            SET name = ?, address = ?, protocol = ?, timeout = ?, enabled = ?, updated_at = ?
+            SET name = ?, address = ?, protocol = ?, timeout = ?, enabled = ?,
+                proxy = ?, bind_address = ?, bind_interface = ?, group_name = ?, health_policy = ?, updated_at = ?
             WHERE id = ?
             RETURNING *
             "#,
@@ -483,6 +1089,11 @@ impl UpstreamServerRepository {
         .bind(&protocol)
         .bind(timeout)
         .bind(enabled)
+        .bind(&proxy)
+        .bind(&bind_address)
+        .bind(&bind_interface)
+        .bind(&group_name)
+        .bind(&health_policy)
         .bind(Utc::now())
         .bind(id)
         .fetch_optional(&self.pool)
@@ -504,8 +1115,15 @@ impl UpstreamServerRepository {
 
 
 use std::sync::Arc;
+use tokio::sync::mpsc;
 use crate::db::stats_cache::StatsCache;
 
+/// Rows buffered between a streaming query and its consumer
+const STREAM_CHUNK: usize = 256;
+
+/// Rows read per query while streaming query logs
+const STREAM_PAGE: i64 = 1000;
+
 /// Repository for query logs
 pub struct QueryLogRepository {
     pool: SqlitePool,
@@ -518,13 +1136,14 @@ impl QueryLogRepository {
     }
 
     /// Create a new query log entry
+    #[allow(dead_code)]
     pub async fn create(&self, log: CreateQueryLog) -> Result<QueryLog> {
         let now = Utc::now();
         let cache_hit = log.cache_hit;
         let result = sqlx::query_as::<_, QueryLog>(
             r#"
-            INSERT INTO query_logs (client_ip, query_name, query_type, response_code, response_time, cache_hit, upstream_used, created_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO query_logs (client_ip, query_name, query_type, response_code, response_time, cache_hit, upstream_used, decision, created_at, answers, source, source_id, reason, protocol, endpoint)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             RETURNING *
             "#,
         )
@@ -535,16 +1154,67 @@ impl QueryLogRepository {
         .bind(log.response_time)
         .bind(log.cache_hit)
         .bind(&log.upstream_used)
+        .bind(&log.decision)
         .bind(now)
+        .bind(&log.answers)
+        .bind(&log.source)
+        .bind(log.source_id)
+        .bind(&log.reason)
+        .bind(&log.protocol)
+        .bind(&log.endpoint)
         .fetch_one(&self.pool)
         .await?;
 
         // Update memory cache
-        self.stats_cache.record_query(cache_hit).await;
+        self.stats_cache.record_queries(1, cache_hit as i64).await;
 
         Ok(result)
     }
 
+    /// Insert query logs in one transaction
+    ///
+    /// Each entry carries the time the query was answered.
+    pub async fn create_batch(&self, logs: &[(CreateQueryLog, DateTime<Utc>)]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (log, created_at) in logs {
+            sqlx::query(
+                r#"
+                INSERT INTO query_logs (client_ip, query_name, query_type, response_code, response_time, cache_hit, upstream_used, decision, created_at, answers, source, source_id, reason, protocol, endpoint)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&log.client_ip)
+            .bind(&log.query_name)
+            .bind(&log.query_type)
+            .bind(&log.response_code)
+            .bind(log.response_time)
+            .bind(log.cache_hit)
+            .bind(&log.upstream_used)
+            .bind(&log.decision)
+            .bind(created_at)
+            .bind(&log.answers)
+            .bind(&log.source)
+            .bind(log.source_id)
+            .bind(&log.reason)
+            .bind(&log.protocol)
+            .bind(&log.endpoint)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        // Update memory cache
+        let cache_hits = logs.iter().filter(|(log, _)| log.cache_hit).count();
+        self.stats_cache.record_queries(logs.len() as i64, cache_hits as i64).await;
+
+        Ok(())
+    }
+
+    /// Count a query kept out of the logs by privacy settings
+    pub async fn record_unlogged(&self, cache_hit: bool) {
+        self.stats_cache.record_queries(1, cache_hit as i64).await;
+    }
+
     /// Get a query log by ID
     #[allow(dead_code)]
     pub async fn get_by_id(&self, id: i64) -> Result<Option<QueryLog>> {
@@ -566,49 +1236,8 @@ impl QueryLogRepository {
         let mut query_builder = sqlx::QueryBuilder::new("SELECT * FROM query_logs WHERE 1=1");
         let mut count_builder = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM query_logs WHERE 1=1");
 
-        if let Some(ref name) = filter.query_name {
-            let pattern = format!("%{}%", name);
-            query_builder.push(" AND query_name LIKE ");
-            query_builder.push_bind(pattern.clone());
-            count_builder.push(" AND query_name LIKE ");
-            count_builder.push_bind(pattern);
-        }
-
-        if let Some(ref qtype) = filter.query_type {
-            query_builder.push(" AND query_type = ");
-            query_builder.push_bind(qtype.clone());
-            count_builder.push(" AND query_type = ");
-            count_builder.push_bind(qtype);
-        }
-
-        if let Some(ref ip) = filter.client_ip {
-             let pattern = format!("%{}%", ip);
-             query_builder.push(" AND client_ip LIKE ");
-             query_builder.push_bind(pattern.clone());
-             count_builder.push(" AND client_ip LIKE ");
-             count_builder.push_bind(pattern);
-        }
-        
-        if let Some(cache_hit) = filter.cache_hit {
-            query_builder.push(" AND cache_hit = ");
-            query_builder.push_bind(cache_hit);
-            count_builder.push(" AND cache_hit = ");
-            count_builder.push_bind(cache_hit);
-        }
-
-        if let Some(ref start) = filter.start_time {
-            query_builder.push(" AND created_at >= ");
-            query_builder.push_bind(start);
-            count_builder.push(" AND created_at >= ");
-            count_builder.push_bind(start);
-        }
-
-        if let Some(ref end) = filter.end_time {
-            query_builder.push(" AND created_at <= ");
-            query_builder.push_bind(end);
-            count_builder.push(" AND created_at <= ");
-            count_builder.push_bind(end);
-        }
+        Self::push_filters(&mut query_builder, &filter);
+        Self::push_filters(&mut count_builder, &filter);
 
         // Get count first
         let count_query = count_builder.build_query_as::<(i64,)>();
@@ -631,6 +1260,106 @@ impl QueryLogRepository {
         })
     }
 
+    /// Stream every matching query log, newest (highest ID) first
+    ///
+    /// Rows are fetched by a background task in pages keyed on the ID, so a
+    /// pool connection is only held while one page is read, never while a
+    /// slow consumer drains the small bounded channel. Dropping the
+    /// receiver stops the task. `limit` and `offset` of the filter are ignored.
+    pub fn stream(&self, filter: QueryLogFilter) -> mpsc::Receiver<Result<QueryLog>> {
+        let (sender, receiver) = mpsc::channel(STREAM_CHUNK);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let mut before: Option<i64> = None;
+            loop {
+                let mut query_builder = sqlx::QueryBuilder::new("SELECT * FROM query_logs WHERE 1=1");
+                Self::push_filters(&mut query_builder, &filter);
+                if let Some(id) = before {
+                    query_builder.push(" AND id < ");
+                    query_builder.push_bind(id);
+                }
+                query_builder.push(" ORDER BY id DESC LIMIT ");
+                query_builder.push_bind(STREAM_PAGE);
+
+                let page = match query_builder.build_query_as::<QueryLog>().fetch_all(&pool).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                let full = page.len() as i64 == STREAM_PAGE;
+                before = page.last().map(|row| row.id);
+                for row in page {
+                    if sender.send(Ok(row)).await.is_err() {
+                        return;
+                    }
+                }
+                if !full {
+                    return;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Append the WHERE conditions of `filter`
+    fn push_filters(builder: &mut sqlx::QueryBuilder<'_, sqlx::Sqlite>, filter: &QueryLogFilter) {
+        let filter = filter.clone();
+        if let Some(name) = filter.query_name {
+            builder.push(" AND query_name LIKE ");
+            builder.push_bind(format!("%{}%", name));
+        }
+        if let Some(qtype) = filter.query_type {
+            builder.push(" AND query_type = ");
+            builder.push_bind(qtype);
+        }
+        if let Some(ip) = filter.client_ip {
+            builder.push(" AND client_ip LIKE ");
+            builder.push_bind(format!("%{}%", ip));
+        }
+        if let Some(cache_hit) = filter.cache_hit {
+            builder.push(" AND cache_hit = ");
+            builder.push_bind(cache_hit);
+        }
+        if let Some(rcode) = filter.response_code {
+            builder.push(" AND response_code = ");
+            builder.push_bind(rcode);
+        }
+        if let Some(answer) = filter.answer {
+            builder.push(" AND answers LIKE ");
+            builder.push_bind(format!("%{}%", answer));
+        }
+        if let Some(source) = filter.source {
+            builder.push(" AND source = ");
+            builder.push_bind(source);
+        }
+        if let Some(source_id) = filter.source_id {
+            builder.push(" AND source_id = ");
+            builder.push_bind(source_id);
+        }
+        if let Some(reason) = filter.reason {
+            builder.push(" AND reason LIKE ");
+            builder.push_bind(format!("%{}%", reason));
+        }
+        if let Some(protocol) = filter.protocol {
+            builder.push(" AND protocol = ");
+            builder.push_bind(protocol);
+        }
+        if let Some(endpoint) = filter.endpoint {
+            builder.push(" AND endpoint LIKE ");
+            builder.push_bind(format!("%{}%", endpoint));
+        }
+        if let Some(start) = filter.start_time {
+            builder.push(" AND created_at >= ");
+            builder.push_bind(start);
+        }
+        if let Some(end) = filter.end_time {
+            builder.push(" AND created_at <= ");
+            builder.push_bind(end);
+        }
+    }
+
 
     /// Delete old query logs (older than specified days)
     pub async fn delete_old(&self, days: i64) -> Result<u64> {
@@ -721,14 +1450,263 @@ pub struct QueryStats {
 }
 
 
+/// Repository for passive DNS entries
+pub struct PassiveDnsRepository {
+    pool: SqlitePool,
+}
+
+impl PassiveDnsRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Merge aggregated observations into the table in one transaction
+    pub async fn upsert_batch(&self, observations: &[PassiveDnsObservation]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for obs in observations {
+            sqlx::query(
+                r#"
+                INSERT INTO passive_dns (name, record_type, value, ip_key, first_seen, last_seen, count)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (name, record_type, value) DO UPDATE SET
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen),
+                    count = count + excluded.count
+                "#,
+            )
+            .bind(&obs.name)
+            .bind(&obs.record_type)
+            .bind(&obs.value)
+            .bind(match obs.record_type.as_str() {
+                "A" | "AAAA" => obs.value.parse().ok().map(ip_key),
+                _ => None,
+            })
+            .bind(obs.first_seen)
+            .bind(obs.last_seen)
+            .bind(obs.count)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Search entries, most recently seen first
+    pub async fn search(&self, filter: &PassiveDnsFilter) -> Result<PaginatedResult<PassiveDnsEntry>> {
+        let limit = filter.limit.unwrap_or(50).min(1000);
+        let offset = filter.offset.unwrap_or(0);
+
+        let mut query_builder = sqlx::QueryBuilder::new(
+            "SELECT id, name, record_type, value, first_seen, last_seen, count FROM passive_dns WHERE 1=1",
+        );
+        let mut count_builder = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM passive_dns WHERE 1=1");
+        Self::push_filters(&mut query_builder, filter)?;
+        Self::push_filters(&mut count_builder, filter)?;
+
+        let count = count_builder.build_query_as::<(i64,)>().fetch_one(&self.pool).await?.0;
+
+        query_builder.push(" ORDER BY last_seen DESC LIMIT ");
+        query_builder.push_bind(limit);
+        query_builder.push(" OFFSET ");
+        query_builder.push_bind(offset);
+        let items = query_builder.build_query_as::<PassiveDnsEntry>().fetch_all(&self.pool).await?;
+
+        Ok(PaginatedResult {
+            items,
+            total: count,
+            limit,
+            offset,
+        })
+    }
+
+    fn push_filters(builder: &mut sqlx::QueryBuilder<'_, sqlx::Sqlite>, filter: &PassiveDnsFilter) -> Result<()> {
+        if let Some(ref name) = filter.name {
+            let name = normalize_name(name);
+            builder.push(" AND (name = ");
+            builder.push_bind(name.clone());
+            if filter.include_subdomains {
+                builder.push(" OR name LIKE ");
+                builder.push_bind(format!("%.{}", name));
+            }
+            builder.push(")");
+        }
+
+        if let Some(ref value) = filter.value {
+            builder.push(" AND value = ");
+            builder.push_bind(normalize_name(value));
+        }
+
+        if let Some(ref ip) = filter.ip {
+            let (start, end) = ip_key_range(ip)?;
+            builder.push(" AND ip_key BETWEEN ");
+            builder.push_bind(start);
+            builder.push(" AND ");
+            builder.push_bind(end);
+        }
+
+        if let Some(ref record_type) = filter.record_type {
+            builder.push(" AND record_type = ");
+            builder.push_bind(record_type.to_uppercase());
+        }
+
+        if let Some(start) = filter.start_time {
+            builder.push(" AND last_seen >= ");
+            builder.push_bind(start);
+        }
+
+        if let Some(end) = filter.end_time {
+            builder.push(" AND first_seen <= ");
+            builder.push_bind(end);
+        }
+        Ok(())
+    }
+
+    /// Delete entries not seen since `before`
+    pub async fn delete_before(&self, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM passive_dns WHERE last_seen < ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+
+/// Stored rollup row; breakdowns are JSON
+#[derive(sqlx::FromRow)]
+struct StatsRollupRow {
+    bucket_start: DateTime<Utc>,
+    queries: i64,
+    cache_hits: i64,
+    blocked: i64,
+    rcodes: String,
+    upstreams: String,
+    top_domains: String,
+    top_clients: String,
+}
+
+impl StatsRollupRow {
+    fn into_bucket(self) -> (DateTime<Utc>, RollupBucket) {
+        let bucket = RollupBucket {
+            queries: self.queries as u64,
+            cache_hits: self.cache_hits as u64,
+            blocked: self.blocked as u64,
+            rcodes: serde_json::from_str(&self.rcodes).unwrap_or_default(),
+            upstreams: serde_json::from_str(&self.upstreams).unwrap_or_default(),
+            domains: serde_json::from_str(&self.top_domains).unwrap_or_default(),
+            clients: serde_json::from_str(&self.top_clients).unwrap_or_default(),
+        };
+        (self.bucket_start, bucket)
+    }
+}
+
+/// Repository for statistics rollups
+pub struct StatsRollupRepository {
+    pool: SqlitePool,
+}
+
+impl StatsRollupRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Add buckets to the stored ones in one transaction
+    pub async fn merge_batch(&self, buckets: &[(RollupGranularity, DateTime<Utc>, RollupBucket)]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (granularity, bucket_start, delta) in buckets {
+            let stored = sqlx::query_as::<_, StatsRollupRow>(
+                "SELECT * FROM stats_rollups WHERE granularity = ? AND bucket_start = ?",
+            )
+            .bind(granularity.as_str())
+            .bind(bucket_start)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let mut bucket = stored.map(|row| row.into_bucket().1).unwrap_or_default();
+            bucket.merge(delta);
+            bucket.truncate_top(TOP_KEEP);
+
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO stats_rollups
+                    (granularity, bucket_start, queries, cache_hits, blocked, rcodes, upstreams, top_domains, top_clients)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(granularity.as_str())
+            .bind(bucket_start)
+            .bind(bucket.queries as i64)
+            .bind(bucket.cache_hits as i64)
+            .bind(bucket.blocked as i64)
+            .bind(serde_json::to_string(&bucket.rcodes)?)
+            .bind(serde_json::to_string(&bucket.upstreams)?)
+            .bind(serde_json::to_string(&bucket.domains)?)
+            .bind(serde_json::to_string(&bucket.clients)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Buckets starting within `[start, end)`, oldest first
+    pub async fn buckets(
+        &self,
+        granularity: RollupGranularity,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, RollupBucket)>> {
+        let rows = sqlx::query_as::<_, StatsRollupRow>(
+            r#"
+            SELECT * FROM stats_rollups
+            WHERE granularity = ? AND bucket_start >= ? AND bucket_start < ?
+            ORDER BY bucket_start
+            "#,
+        )
+        .bind(granularity.as_str())
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(StatsRollupRow::into_bucket).collect())
+    }
+
+    /// Delete buckets that start before `before`
+    pub async fn delete_before(&self, granularity: RollupGranularity, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM stats_rollups WHERE granularity = ? AND bucket_start < ?")
+            .bind(granularity.as_str())
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+
 /// Repository for system configuration
 pub struct SystemConfigRepository {
     pool: SqlitePool,
+    changes: Option<broadcast::Sender<DataChange>>,
 }
 
 impl SystemConfigRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool, changes: None }
+    }
+
+    /// Announce writes on `changes`
+    pub fn with_changes(mut self, changes: broadcast::Sender<DataChange>) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+
+    fn notify(&self) {
+        if let Some(ref changes) = self.changes {
+            let _ = changes.send(DataChange::SystemConfig);
+        }
     }
 
     /// Get a config value by key
@@ -757,6 +1735,7 @@ impl SystemConfigRepository {
         .execute(&self.pool)
         .await?;
 
+        self.notify();
         Ok(())
     }
 
@@ -768,6 +1747,7 @@ impl SystemConfigRepository {
             .execute(&self.pool)
             .await?;
 
+        self.notify();
         Ok(result.rows_affected() > 0)
     }
 
@@ -882,6 +1862,11 @@ mod tests {
             protocol: "udp".to_string(),
             timeout: 5000,
             enabled: true,
+            proxy: None,
+            bind_address: None,
+            bind_interface: None,
+            group_name: None,
+            health_policy: None,
         }).await.unwrap();
 
         assert_eq!(server.name, "Cloudflare");
@@ -916,6 +1901,10 @@ mod tests {
             response_time: Some(50),
             cache_hit: false,
             upstream_used: Some("Cloudflare".to_string()),
+            decision: None,
+            source: Some("upstream".to_string()),
+            protocol: Some("udp".to_string()),
+            ..Default::default()
         }).await.unwrap();
 
         assert_eq!(log.query_name, "example.com");
@@ -930,6 +1919,120 @@ mod tests {
             ..Default::default()
         }).await.unwrap();
         assert_eq!(result.items.len(), 1);
+
+        let result = repo.list(QueryLogFilter {
+            source: Some("blocked".to_string()),
+            protocol: Some("udp".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(result.total, 0);
+    }
+
+    #[tokio::test]
+    async fn test_dns_record_sync_source() {
+        let dir = tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        let repo = db.dns_records();
+        let record = |name: &str, value: &str, ttl: i32| CreateDnsRecord {
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            value: value.to_string(),
+            ttl,
+            priority: 0,
+            enabled: true,
+        };
+
+        repo.batch_create(vec![record("manual.lan", "10.0.0.1", 300)]).await.unwrap();
+        let report = repo.sync_source("hosts", vec![
+            record("manual.lan", "10.0.0.1", 300),
+            record("nas.lan", "10.0.0.2", 300),
+            record("old.lan", "10.0.0.3", 300),
+        ]).await.unwrap();
+        assert_eq!(report, SourceSyncReport { created: 2, updated: 0, deleted: 0 });
+
+        let report = repo.sync_source("hosts", vec![
+            record("manual.lan", "10.0.0.1", 300),
+            record("nas.lan", "10.0.0.2", 600),
+        ]).await.unwrap();
+        assert_eq!(report, SourceSyncReport { created: 0, updated: 1, deleted: 1 });
+
+        let rows: Vec<(String, Option<String>, i32)> = repo.list().await.unwrap()
+            .into_iter()
+            .map(|r| (r.name, r.source, r.ttl))
+            .collect();
+        assert_eq!(rows, vec![
+            ("manual.lan".to_string(), None, 300),
+            ("nas.lan".to_string(), Some("hosts".to_string()), 600),
+        ]);
+        assert!(!repo.sync_source("hosts", vec![record("nas.lan", "10.0.0.2", 600)]).await.unwrap().changed());
+    }
+
+    #[tokio::test]
+    async fn test_query_log_stream() {
+        let dir = tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        let repo = db.query_logs();
+        let logs: Vec<_> = (0..2500).map(|i| (CreateQueryLog {
+            client_ip: "10.0.0.1".to_string(),
+            query_name: format!("host{}.{}.com", i, if i % 2 == 0 { "even" } else { "odd" }),
+            query_type: "A".to_string(),
+            ..Default::default()
+        }, Utc::now())).collect();
+        repo.create_batch(&logs).await.unwrap();
+
+        // More rows than one page, limit and offset ignored
+        let mut rows = repo.stream(QueryLogFilter {
+            query_name: Some("even".to_string()),
+            limit: Some(1),
+            offset: Some(10),
+            ..Default::default()
+        });
+        let mut ids = Vec::new();
+        while let Some(row) = rows.recv().await {
+            let row = row.unwrap();
+            assert!(row.query_name.ends_with(".even.com"));
+            ids.push(row.id);
+        }
+        assert_eq!(ids.len(), 1250);
+        assert!(ids.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_query_log_filters() {
+        let dir = tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        let repo = db.query_logs();
+        let log = |name: &str, rcode: &str, answers: Option<&str>, reason: Option<&str>, endpoint: Option<&str>| (CreateQueryLog {
+            client_ip: "10.0.0.1".to_string(),
+            query_name: name.to_string(),
+            query_type: "A".to_string(),
+            response_code: Some(rcode.to_string()),
+            answers: answers.map(str::to_string),
+            reason: reason.map(str::to_string),
+            endpoint: endpoint.map(str::to_string),
+            ..Default::default()
+        }, Utc::now());
+        repo.create_batch(&[
+            log("nas.lan", "NOERROR", Some(r#"[{"name":"nas.lan","type":"A","ttl":300,"value":"192.168.1.10"}]"#), None, Some("/dns-query")),
+            log("ads.example", "NXDOMAIN", None, Some("blocklist: ads.txt"), Some("dns.example.com")),
+            log("missing.lan", "NXDOMAIN", None, None, None),
+        ]).await.unwrap();
+
+        async fn list_names(repo: &QueryLogRepository, filter: QueryLogFilter) -> Vec<String> {
+            let mut names: Vec<String> = repo.list(filter).await.unwrap().items.into_iter().map(|l| l.query_name).collect();
+            names.sort();
+            names
+        }
+        let names = |filter| list_names(&repo, filter);
+        assert_eq!(names(QueryLogFilter { answer: Some("168.1.10".to_string()), ..Default::default() }).await, ["nas.lan"]);
+        assert_eq!(names(QueryLogFilter { reason: Some("ads.txt".to_string()), ..Default::default() }).await, ["ads.example"]);
+        assert_eq!(names(QueryLogFilter { endpoint: Some("dns-query".to_string()), ..Default::default() }).await, ["nas.lan"]);
+        assert_eq!(names(QueryLogFilter { endpoint: Some("example.com".to_string()), ..Default::default() }).await, ["ads.example"]);
+        assert_eq!(
+            names(QueryLogFilter { response_code: Some("NXDOMAIN".to_string()), ..Default::default() }).await,
+            ["ads.example", "missing.lan"]
+        );
+        assert!(names(QueryLogFilter { response_code: Some("NXDOMAIN".to_string()), answer: Some("192.168".to_string()), ..Default::default() }).await.is_empty());
     }
 
     #[tokio::test]
@@ -975,6 +2078,8 @@ mod tests {
             response_time: Some(10),
             cache_hit: true,
             upstream_used: Some("test".to_string()),
+            decision: None,
+            ..Default::default()
         }).await.unwrap();
 
         // Stats should update immediately (from cache)
@@ -992,6 +2097,8 @@ mod tests {
             response_time: Some(20),
             cache_hit: false,
             upstream_used: Some("test".to_string()),
+            decision: None,
+            ..Default::default()
         }).await.unwrap();
         
         let stats = repo.get_stats().await.unwrap();
@@ -0,0 +1,589 @@
+//! Statistics Rollups
+//!
+//! Every answered query is folded into per-minute, per-hour and per-day
+//! buckets (queries, cache hits, blocked, response codes, per-upstream
+//! latency histograms, top domains and clients). The aggregator keeps the
+//! current minute in memory and merges it into the `stats_rollups` table
+//! periodically, so dashboards read a few rows instead of scanning raw
+//! query logs, and history outlives log cleanup. Each granularity has its
+//! own retention.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::{CreateQueryLog, Database};
+
+/// How often pending minutes are merged into the table
+const FLUSH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often expired buckets are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Distinct domains/clients tracked per pending minute
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// Domains/clients kept per stored bucket; hour and day tops are approximate
+pub const TOP_KEEP: usize = 100;
+
+/// Upper bounds (ms) of the latency histogram buckets; one more bucket catches the rest
+const LATENCY_BOUNDS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// Bucket size of a rollup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RollupGranularity {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl RollupGranularity {
+    pub const ALL: [RollupGranularity; 3] = [Self::Minute, Self::Hour, Self::Day];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "minute" => Some(Self::Minute),
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            _ => None,
+        }
+    }
+
+    /// Length of one bucket
+    pub fn step(&self) -> TimeDelta {
+        match self {
+            Self::Minute => TimeDelta::minutes(1),
+            Self::Hour => TimeDelta::hours(1),
+            Self::Day => TimeDelta::days(1),
+        }
+    }
+
+    /// Start of the bucket containing `at` (UTC)
+    pub fn bucket_start(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        at.duration_trunc(self.step()).unwrap_or(at)
+    }
+
+    /// Range served when the caller gives none: the last 60 minutes, 48 hours or 30 days
+    pub fn default_span(&self) -> TimeDelta {
+        match self {
+            Self::Minute => TimeDelta::minutes(60),
+            Self::Hour => TimeDelta::hours(48),
+            Self::Day => TimeDelta::days(30),
+        }
+    }
+}
+
+/// Rollup retention settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatsRollupConfig {
+    /// Days per-minute buckets are kept
+    pub minute_retention_days: u32,
+    /// Days per-hour buckets are kept
+    pub hour_retention_days: u32,
+    /// Days per-day buckets are kept
+    pub day_retention_days: u32,
+}
+
+impl Default for StatsRollupConfig {
+    fn default() -> Self {
+        Self {
+            minute_retention_days: 2,
+            hour_retention_days: 30,
+            day_retention_days: 365,
+        }
+    }
+}
+
+impl StatsRollupConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=30).contains(&self.minute_retention_days) {
+            return Err(anyhow!("minute_retention_days must be between 1 and 30"));
+        }
+        if !(1..=365).contains(&self.hour_retention_days) {
+            return Err(anyhow!("hour_retention_days must be between 1 and 365"));
+        }
+        if !(1..=3650).contains(&self.day_retention_days) {
+            return Err(anyhow!("day_retention_days must be between 1 and 3650"));
+        }
+        Ok(())
+    }
+
+    pub fn retention_days(&self, granularity: RollupGranularity) -> u32 {
+        match granularity {
+            RollupGranularity::Minute => self.minute_retention_days,
+            RollupGranularity::Hour => self.hour_retention_days,
+            RollupGranularity::Day => self.day_retention_days,
+        }
+    }
+}
+
+/// Latency histogram that merges by adding counts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    /// Counts per bucket of `LATENCY_BOUNDS_MS`, plus the overflow bucket
+    pub counts: Vec<u64>,
+    pub total_ms: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, ms: u64) {
+        if self.counts.len() <= LATENCY_BOUNDS_MS.len() {
+            self.counts.resize(LATENCY_BOUNDS_MS.len() + 1, 0);
+        }
+        let index = LATENCY_BOUNDS_MS.iter().position(|bound| ms <= *bound).unwrap_or(LATENCY_BOUNDS_MS.len());
+        self.counts[index] += 1;
+        self.total_ms += ms;
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, add) in self.counts.iter_mut().zip(&other.counts) {
+            *count += add;
+        }
+        self.total_ms += other.total_ms;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of the bucket holding the `p`th percentile (0.0..=1.0)
+    ///
+    /// Samples above the largest bound report that bound.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((total as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BOUNDS_MS[index.min(LATENCY_BOUNDS_MS.len() - 1)];
+            }
+        }
+        LATENCY_BOUNDS_MS[LATENCY_BOUNDS_MS.len() - 1]
+    }
+}
+
+/// Aggregated statistics of one time bucket
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollupBucket {
+    pub queries: u64,
+    pub cache_hits: u64,
+    /// Queries refused by rewrite rules or response filters
+    pub blocked: u64,
+    pub rcodes: BTreeMap<String, u64>,
+    /// Latency of queries answered by each upstream
+    pub upstreams: BTreeMap<String, LatencyHistogram>,
+    pub domains: HashMap<String, u64>,
+    pub clients: HashMap<String, u64>,
+}
+
+impl RollupBucket {
+    /// Fold one query into the bucket
+    pub fn record(&mut self, log: &CreateQueryLog) {
+        self.queries += 1;
+        if log.cache_hit {
+            self.cache_hits += 1;
+        }
+        if matches!(log.source.as_deref(), Some("blocked") | Some("filtered")) {
+            self.blocked += 1;
+        }
+
+        // Errors carry their message after the colon
+        let rcode = log.response_code.as_deref()
+            .map(|code| code.split(':').next().unwrap_or(code).trim())
+            .unwrap_or("UNKNOWN");
+        *self.rcodes.entry(rcode.to_string()).or_default() += 1;
+
+        if let (Some(upstream), false) = (&log.upstream_used, log.cache_hit) {
+            let ms = log.response_time.unwrap_or(0).max(0) as u64;
+            self.upstreams.entry(upstream.clone()).or_default().record(ms);
+        }
+
+        Self::count_key(&mut self.domains, &log.query_name);
+        Self::count_key(&mut self.clients, &log.client_ip);
+    }
+
+    /// Count a key; empty keys come from clients kept out of the logs
+    fn count_key(map: &mut HashMap<String, u64>, key: &str) {
+        if key.is_empty() {
+            return;
+        }
+        if let Some(count) = map.get_mut(key) {
+            *count += 1;
+        } else if map.len() < MAX_TRACKED_KEYS {
+            map.insert(key.to_string(), 1);
+        }
+    }
+
+    pub fn merge(&mut self, other: &RollupBucket) {
+        self.queries += other.queries;
+        self.cache_hits += other.cache_hits;
+        self.blocked += other.blocked;
+        for (code, count) in &other.rcodes {
+            *self.rcodes.entry(code.clone()).or_default() += count;
+        }
+        for (name, histogram) in &other.upstreams {
+            self.upstreams.entry(name.clone()).or_default().merge(histogram);
+        }
+        for (domain, count) in &other.domains {
+            *self.domains.entry(domain.clone()).or_default() += count;
+        }
+        for (client, count) in &other.clients {
+            *self.clients.entry(client.clone()).or_default() += count;
+        }
+    }
+
+    /// Keep only the `n` most frequent domains and clients
+    pub fn truncate_top(&mut self, n: usize) {
+        for map in [&mut self.domains, &mut self.clients] {
+            if map.len() > n {
+                *map = top_entries(map, n).into_iter().map(|e| (e.name, e.count)).collect();
+            }
+        }
+    }
+}
+
+/// A ranked name and its count
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopEntry {
+    pub name: String,
+    pub count: u64,
+}
+
+/// The `n` largest counts, ties broken by name
+pub fn top_entries(map: &HashMap<String, u64>, n: usize) -> Vec<TopEntry> {
+    let mut entries: Vec<TopEntry> = map.iter()
+        .map(|(name, count)| TopEntry { name: name.clone(), count: *count })
+        .collect();
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    entries.truncate(n);
+    entries
+}
+
+/// Latency summary of one upstream in one bucket
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamLatency {
+    pub queries: u64,
+    pub avg_ms: f64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+}
+
+impl From<&LatencyHistogram> for UpstreamLatency {
+    fn from(histogram: &LatencyHistogram) -> Self {
+        let queries = histogram.count();
+        Self {
+            queries,
+            avg_ms: if queries > 0 { histogram.total_ms as f64 / queries as f64 } else { 0.0 },
+            p50_ms: histogram.percentile(0.5),
+            p90_ms: histogram.percentile(0.9),
+            p99_ms: histogram.percentile(0.99),
+        }
+    }
+}
+
+/// One point of a statistics time series
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsPoint {
+    pub bucket_start: DateTime<Utc>,
+    pub queries: u64,
+    pub cache_hits: u64,
+    pub blocked: u64,
+    pub rcodes: BTreeMap<String, u64>,
+    pub upstreams: BTreeMap<String, UpstreamLatency>,
+}
+
+/// Statistics time series with the top domains and clients of the whole range
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSeries {
+    pub granularity: RollupGranularity,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub points: Vec<StatsPoint>,
+    /// Totals over the range
+    pub queries: u64,
+    pub cache_hits: u64,
+    pub blocked: u64,
+    pub upstreams: BTreeMap<String, UpstreamLatency>,
+    pub top_domains: Vec<TopEntry>,
+    pub top_clients: Vec<TopEntry>,
+}
+
+impl StatsSeries {
+    /// Build a series from stored buckets, oldest first
+    pub fn from_buckets(
+        granularity: RollupGranularity,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        buckets: Vec<(DateTime<Utc>, RollupBucket)>,
+        top: usize,
+    ) -> Self {
+        let mut total = RollupBucket::default();
+        let points = buckets.into_iter().map(|(bucket_start, bucket)| {
+            total.merge(&bucket);
+            StatsPoint {
+                bucket_start,
+                queries: bucket.queries,
+                cache_hits: bucket.cache_hits,
+                blocked: bucket.blocked,
+                rcodes: bucket.rcodes,
+                upstreams: bucket.upstreams.iter().map(|(name, h)| (name.clone(), h.into())).collect(),
+            }
+        }).collect();
+
+        Self {
+            granularity,
+            start,
+            end,
+            points,
+            queries: total.queries,
+            cache_hits: total.cache_hits,
+            blocked: total.blocked,
+            upstreams: total.upstreams.iter().map(|(name, h)| (name.clone(), h.into())).collect(),
+            top_domains: top_entries(&total.domains, top),
+            top_clients: top_entries(&total.clients, top),
+        }
+    }
+}
+
+/// Background rollup aggregator
+pub struct StatsRollup {
+    /// Pending buckets by minute start
+    pending: Mutex<HashMap<DateTime<Utc>, RollupBucket>>,
+    config: RwLock<StatsRollupConfig>,
+    shutdown: CancellationToken,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl StatsRollup {
+    /// Start the background flush and retention task
+    pub fn start(db: Arc<Database>, config: StatsRollupConfig) -> Arc<Self> {
+        let rollup = Arc::new(Self::new(config));
+        let task = tokio::spawn(run(db, rollup.clone()));
+        *rollup.task.lock().unwrap() = Some(task);
+        rollup
+    }
+
+    fn new(config: StatsRollupConfig) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            config: RwLock::new(config),
+            shutdown: CancellationToken::new(),
+            task: Mutex::new(None),
+        }
+    }
+
+    /// Fold a finished query into the current minute; never waits
+    pub fn record(&self, log: &CreateQueryLog) {
+        self.record_at(log, Utc::now());
+    }
+
+    fn record_at(&self, log: &CreateQueryLog, at: DateTime<Utc>) {
+        let minute = RollupGranularity::Minute.bucket_start(at);
+        self.pending.lock().unwrap().entry(minute).or_default().record(log);
+    }
+
+    /// Current settings
+    pub fn config(&self) -> StatsRollupConfig {
+        *self.config.read().unwrap()
+    }
+
+    /// Apply new settings; retention applies from the next purge
+    pub fn set_config(&self, config: StatsRollupConfig) -> Result<()> {
+        config.validate()?;
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+
+    /// Write pending buckets and stop the aggregator
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            if let Err(e) = task.await {
+                tracing::warn!("Stats rollup stopped abnormally: {}", e);
+            }
+        }
+    }
+
+    /// Merge pending minutes into every granularity
+    async fn flush(&self, db: &Database) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut merged: HashMap<(RollupGranularity, DateTime<Utc>), RollupBucket> = HashMap::new();
+        for (minute, bucket) in &pending {
+            for granularity in RollupGranularity::ALL {
+                merged.entry((granularity, granularity.bucket_start(*minute)))
+                    .or_default()
+                    .merge(bucket);
+            }
+        }
+        let batch: Vec<_> = merged.into_iter().map(|((g, start), bucket)| (g, start, bucket)).collect();
+
+        if let Err(e) = db.stats_rollups().merge_batch(&batch).await {
+            tracing::warn!("Failed to write {} statistics rollups: {}", batch.len(), e);
+        }
+    }
+
+    /// Delete buckets older than their retention period
+    async fn purge(&self, db: &Database) {
+        let config = self.config();
+        for granularity in RollupGranularity::ALL {
+            let days = config.retention_days(granularity);
+            let before = Utc::now() - TimeDelta::days(days as i64);
+            match db.stats_rollups().delete_before(granularity, before).await {
+                Ok(deleted) if deleted > 0 => {
+                    tracing::info!("Stats rollup: deleted {} {} buckets older than {} days", deleted, granularity.as_str(), days);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Stats rollup retention cleanup failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Aggregator loop: flush on a timer, purge hourly, flush once more on shutdown
+async fn run(db: Arc<Database>, rollup: Arc<StatsRollup>) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut purge = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = rollup.shutdown.cancelled() => break,
+            _ = flush.tick() => rollup.flush(&db).await,
+            _ = purge.tick() => rollup.purge(&db).await,
+        }
+    }
+
+    rollup.flush(&db).await;
+    tracing::info!("Stats rollup stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    async fn setup_test_db() -> (TempDir, Arc<Database>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        let db = Arc::new(Database::new(&db_url).await.unwrap());
+        (dir, db)
+    }
+
+    fn log(name: &str, client: &str, upstream: Option<&str>, ms: i32, source: &str) -> CreateQueryLog {
+        CreateQueryLog {
+            client_ip: client.to_string(),
+            query_name: name.to_string(),
+            query_type: "A".to_string(),
+            response_code: Some("NOERROR".to_string()),
+            response_time: Some(ms),
+            cache_hit: source == "cache",
+            upstream_used: upstream.map(String::from),
+            source: Some(source.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        for ms in 1..=100 {
+            histogram.record(ms);
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(0.5), 50);
+        assert_eq!(histogram.percentile(0.99), 100);
+
+        let mut other = LatencyHistogram::default();
+        other.record(9000);
+        histogram.merge(&other);
+        assert_eq!(histogram.percentile(1.0), 5000);
+        assert_eq!(LatencyHistogram::default().percentile(0.5), 0);
+    }
+
+    #[test]
+    fn test_bucket_record_and_top() {
+        let mut bucket = RollupBucket::default();
+        bucket.record(&log("a.com", "10.0.0.1", Some("google"), 20, "upstream"));
+        bucket.record(&log("a.com", "10.0.0.2", None, 0, "cache"));
+        bucket.record(&log("ads.com", "10.0.0.1", None, 0, "blocked"));
+        bucket.record(&CreateQueryLog {
+            response_code: Some("ERROR: timeout".to_string()),
+            ..log("b.com", "10.0.0.1", None, 0, "error")
+        });
+
+        assert_eq!((bucket.queries, bucket.cache_hits, bucket.blocked), (4, 1, 1));
+        assert_eq!(bucket.rcodes.get("NOERROR"), Some(&3));
+        assert_eq!(bucket.rcodes.get("ERROR"), Some(&1));
+        assert_eq!(bucket.upstreams["google"].count(), 1);
+
+        bucket.truncate_top(1);
+        assert_eq!(top_entries(&bucket.domains, 5), vec![TopEntry { name: "a.com".to_string(), count: 2 }]);
+        assert_eq!(top_entries(&bucket.clients, 5)[0].name, "10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_flush_merges_into_all_granularities() {
+        let (_dir, db) = setup_test_db().await;
+        let rollup = StatsRollup::new(StatsRollupConfig::default());
+        let t0 = Utc.with_ymd_and_hms(2024, 5, 1, 10, 15, 30).unwrap();
+        let t1 = Utc.with_ymd_and_hms(2024, 5, 1, 10, 16, 5).unwrap();
+
+        rollup.record_at(&log("a.com", "10.0.0.1", Some("google"), 10, "upstream"), t0);
+        rollup.record_at(&log("a.com", "10.0.0.1", None, 0, "cache"), t1);
+        rollup.flush(&db).await;
+        // A second flush must add to the stored hour and day, not replace them
+        rollup.record_at(&log("b.com", "10.0.0.2", Some("google"), 30, "upstream"), t1);
+        rollup.flush(&db).await;
+
+        let repo = db.stats_rollups();
+        let day = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let range = (day, day + TimeDelta::days(1));
+
+        let minutes = repo.buckets(RollupGranularity::Minute, range.0, range.1).await.unwrap();
+        assert_eq!(minutes.len(), 2);
+        assert_eq!(minutes[1].1.queries, 2);
+
+        let hours = repo.buckets(RollupGranularity::Hour, range.0, range.1).await.unwrap();
+        assert_eq!(hours.len(), 1);
+        assert_eq!(hours[0].0, Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap());
+
+        let series = StatsSeries::from_buckets(RollupGranularity::Day, range.0, range.1,
+            repo.buckets(RollupGranularity::Day, range.0, range.1).await.unwrap(), 10);
+        assert_eq!((series.queries, series.cache_hits), (3, 1));
+        assert_eq!(series.upstreams["google"].queries, 2);
+        assert_eq!(series.top_domains[0], TopEntry { name: "a.com".to_string(), count: 2 });
+
+        let deleted = repo.delete_before(RollupGranularity::Minute, RollupGranularity::Minute.bucket_start(t1)).await.unwrap();
+        assert_eq!(deleted, 1);
+    }
+}
@@ -45,11 +45,11 @@ impl StatsCache {
         *self.current_date.write().await = Local::now().date_naive();
     }
 
-    /// Record a new query
+    /// Record a batch of queries
     /// 
-    /// Increments total_queries and queries_today.
-    /// If cache_hit is true, also increments cache_hits.
-    pub async fn record_query(&self, cache_hit: bool) {
+    /// Increments total_queries and queries_today by `queries`,
+    /// and cache_hits by `cache_hits`.
+    pub async fn record_queries(&self, queries: i64, cache_hits: i64) {
         // Check if we need to reset the daily counter
         let today = Local::now().date_naive();
         {
@@ -67,12 +67,9 @@ impl StatsCache {
         }
 
         // Increment counters
-        self.total_queries.fetch_add(1, Ordering::SeqCst);
-        self.queries_today.fetch_add(1, Ordering::SeqCst);
-        
-        if cache_hit {
-            self.cache_hits.fetch_add(1, Ordering::SeqCst);
-        }
+        self.total_queries.fetch_add(queries, Ordering::SeqCst);
+        self.queries_today.fetch_add(queries, Ordering::SeqCst);
+        self.cache_hits.fetch_add(cache_hits, Ordering::SeqCst);
     }
 
     /// Get current statistics
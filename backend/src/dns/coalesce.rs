@@ -0,0 +1,227 @@
+//! In-flight Query Coalescing
+//!
+//! Identical cache misses that arrive while an upstream query for the same
+//! key is already running wait for that query instead of sending their own.
+//!
+//! The shared query runs in its own task: a waiter that goes away (client
+//! timeout, dropped connection) never cancels it for the others, and the
+//! result still reaches the cache. The task is bounded by a timeout, after
+//! which the key is released and the next miss starts a fresh query.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use serde::Serialize;
+
+type Flight<V> = Shared<BoxFuture<'static, Result<V, Arc<anyhow::Error>>>>;
+
+/// Coalescing counters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CoalesceStats {
+    /// Queries actually sent upstream
+    pub flights: u64,
+    /// Cache misses that waited for another query instead of sending their own
+    pub coalesced: u64,
+    /// Queries currently in flight
+    pub in_flight: usize,
+}
+
+/// Shares one in-flight fetch between all callers with the same key
+pub struct QueryCoalescer<K, V> {
+    inflight: Mutex<HashMap<K, Flight<V>>>,
+    /// Upper bound for one fetch
+    timeout: Duration,
+    flights: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl<K, V> QueryCoalescer<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Create a coalescer whose fetches give up after `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+            timeout,
+            flights: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    /// Run `fetch` for `key`, or wait for the fetch already running for it
+    ///
+    /// Returns the value and whether it came from another caller's fetch.
+    pub async fn run<F, Fut>(self: &Arc<Self>, key: K, fetch: F) -> Result<(V, bool)>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V>> + Send + 'static,
+    {
+        let (flight, coalesced) = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(flight) => {
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    (flight.clone(), true)
+                }
+                None => {
+                    let flight = self.spawn_flight(key.clone(), fetch());
+                    inflight.insert(key, flight.clone());
+                    self.flights.fetch_add(1, Ordering::Relaxed);
+                    (flight, false)
+                }
+            }
+        };
+
+        flight
+            .await
+            .map(|value| (value, coalesced))
+            .map_err(|e| anyhow!("{:#}", e))
+    }
+
+    /// Start the fetch in its own task; the task releases the key when done
+    fn spawn_flight<Fut>(self: &Arc<Self>, key: K, fetch: Fut) -> Flight<V>
+    where
+        Fut: Future<Output = Result<V>> + Send + 'static,
+    {
+        let this = Arc::clone(self);
+        let handle = tokio::spawn(async move {
+            let result = match tokio::time::timeout(this.timeout, fetch).await {
+                Ok(result) => result.map_err(Arc::new),
+                Err(_) => Err(Arc::new(anyhow!("Upstream query timeout after {:?}", this.timeout))),
+            };
+            // The caller inserts the flight while holding the lock, so it is
+            // always present (and still ours) by the time we get here
+            this.inflight.lock().unwrap().remove(&key);
+            result
+        });
+
+        async move {
+            handle
+                .await
+                .unwrap_or_else(|e| Err(Arc::new(anyhow!("Upstream query task failed: {}", e))))
+        }
+        .boxed()
+        .shared()
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> CoalesceStats {
+        CoalesceStats {
+            flights: self.flights.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            in_flight: self.inflight.lock().unwrap().len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::sync::Notify;
+
+    fn coalescer(timeout_ms: u64) -> Arc<QueryCoalescer<&'static str, u32>> {
+        Arc::new(QueryCoalescer::new(Duration::from_millis(timeout_ms)))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_fetch() {
+        let coalescer = coalescer(1000);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        let mut waiters = Vec::new();
+        for _ in 0..5 {
+            let coalescer = coalescer.clone();
+            let calls = calls.clone();
+            let release = release.clone();
+            waiters.push(tokio::spawn(async move {
+                coalescer.run("example.com", move || async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    release.notified().await;
+                    Ok(7)
+                }).await
+            }));
+        }
+        while coalescer.stats().coalesced < 4 {
+            tokio::task::yield_now().await;
+        }
+        release.notify_one();
+
+        let mut shared = 0;
+        for waiter in waiters {
+            let (value, coalesced) = waiter.await.unwrap().unwrap();
+            assert_eq!(value, 7);
+            shared += coalesced as usize;
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(shared, 4);
+
+        let stats = coalescer.stats();
+        assert_eq!((stats.flights, stats.coalesced, stats.in_flight), (1, 4, 0));
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_shared_but_not_kept() {
+        let coalescer = coalescer(1000);
+
+        let err = coalescer.run("example.com", || async { Err::<u32, _>(anyhow!("SERVFAIL")) }).await;
+        assert!(err.unwrap_err().to_string().contains("SERVFAIL"));
+
+        // The failed flight is gone, so the next miss fetches again
+        let (value, coalesced) = coalescer.run("example.com", || async { Ok(1) }).await.unwrap();
+        assert_eq!((value, coalesced), (1, false));
+        assert_eq!(coalescer.stats().flights, 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_leader_does_not_cancel_followers() {
+        let coalescer = coalescer(1000);
+        let release = Arc::new(Notify::new());
+
+        let gate = release.clone();
+        let leader = tokio::spawn({
+            let coalescer = coalescer.clone();
+            async move {
+                coalescer.run("example.com", move || async move {
+                    gate.notified().await;
+                    Ok(42)
+                }).await
+            }
+        });
+        while coalescer.stats().flights == 0 {
+            tokio::task::yield_now().await;
+        }
+        let follower = tokio::spawn({
+            let coalescer = coalescer.clone();
+            async move { coalescer.run("example.com", || async { Ok(0) }).await }
+        });
+        while coalescer.stats().coalesced == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        leader.abort();
+        release.notify_one();
+        assert_eq!(follower.await.unwrap().unwrap(), (42, true));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timeout_releases_key() {
+        let coalescer = coalescer(50);
+
+        let err = coalescer.run("slow.example", std::future::pending::<Result<u32>>).await;
+        assert!(err.unwrap_err().to_string().contains("timeout"));
+        assert_eq!(coalescer.stats().in_flight, 0);
+
+        let (value, _) = coalescer.run("slow.example", || async { Ok(3) }).await.unwrap();
+        assert_eq!(value, 3);
+    }
+}
@@ -0,0 +1,855 @@
+//! dnstap Output
+//!
+//! Listener (CLIENT_*) and upstream (FORWARDER_*) exchanges are encoded as
+//! dnstap protobuf messages carrying the original wire bytes, and written
+//! as Frame Streams to a Unix socket, a TCP socket or a size-rotated file.
+//! Encoding happens on the query path; one background task does the
+//! writing behind a bounded queue, so a slow or missing collector costs
+//! dropped (and counted) messages, never query latency.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Frame Streams content type of dnstap payloads
+const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+/// Encoded messages waiting for the writer
+const QUEUE_SIZE: usize = 10_000;
+
+/// Wait between attempts to reach an unavailable collector
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Longest time written frames sit in the write buffer
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limit for Frame Streams handshakes with a collector
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Frame Streams control frame types
+const FSTRM_ACCEPT: u32 = 0x01;
+const FSTRM_START: u32 = 0x02;
+const FSTRM_STOP: u32 = 0x03;
+const FSTRM_READY: u32 = 0x04;
+const FSTRM_CONTENT_TYPE: u32 = 0x01;
+
+/// Where dnstap frames are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnstapOutput {
+    /// Bidirectional Frame Streams over a Unix socket
+    #[default]
+    Unix,
+    /// Bidirectional Frame Streams over TCP
+    Tcp,
+    /// Unidirectional Frame Streams file, rotated by size
+    File,
+}
+
+/// dnstap settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnstapConfig {
+    pub enabled: bool,
+    pub output: DnstapOutput,
+    /// Unix socket or file path
+    pub path: String,
+    /// TCP collector as host:port
+    pub address: String,
+    /// Size at which the output file is rotated
+    pub max_file_size_mb: u64,
+    /// Rotated files kept next to the current one
+    pub max_files: u32,
+    /// Log one in this many exchanges (1 logs everything)
+    pub sample_rate: u32,
+    /// Log CLIENT_QUERY/CLIENT_RESPONSE at the listeners
+    pub client_messages: bool,
+    /// Log FORWARDER_QUERY/FORWARDER_RESPONSE at the upstream clients
+    pub forwarder_messages: bool,
+    /// Server identity sent with every message (omitted when empty)
+    pub identity: String,
+}
+
+impl Default for DnstapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output: DnstapOutput::Unix,
+            path: "dnstap.sock".to_string(),
+            address: "127.0.0.1:6000".to_string(),
+            max_file_size_mb: 100,
+            max_files: 5,
+            sample_rate: 1,
+            client_messages: true,
+            forwarder_messages: true,
+            identity: String::new(),
+        }
+    }
+}
+
+impl DnstapConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=10_000).contains(&self.sample_rate) {
+            return Err(anyhow!("sample_rate must be between 1 and 10000"));
+        }
+        if !(1..=10_240).contains(&self.max_file_size_mb) {
+            return Err(anyhow!("max_file_size_mb must be between 1 and 10240"));
+        }
+        if !(1..=100).contains(&self.max_files) {
+            return Err(anyhow!("max_files must be between 1 and 100"));
+        }
+        match self.output {
+            DnstapOutput::Unix | DnstapOutput::File if self.path.trim().is_empty() => {
+                Err(anyhow!("path is required for {:?} output", self.output))
+            }
+            #[cfg(not(unix))]
+            DnstapOutput::Unix => Err(anyhow!("Unix socket output is not supported on this platform")),
+            DnstapOutput::Tcp => {
+                let port = self.address.trim().rsplit_once(':').map(|(host, port)| (host, port.parse::<u16>()));
+                match port {
+                    Some((host, Ok(port))) if !host.is_empty() && port != 0 => Ok(()),
+                    _ => Err(anyhow!("address must be host:port")),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Which side of the server an exchange happened on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnstapRole {
+    /// A client asked one of our listeners
+    Client,
+    /// We asked an upstream server
+    Forwarder,
+}
+
+/// Transport of an exchange, reported as `socket_protocol`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnstapProtocol {
+    Udp,
+    Tcp,
+    Dot,
+    Doh,
+    Doh3,
+    Doq,
+}
+
+impl DnstapProtocol {
+    /// Map a listener protocol name (see `QueryOrigin::protocol`)
+    pub fn from_listener(protocol: &str) -> Option<Self> {
+        match protocol {
+            "udp" => Some(Self::Udp),
+            "tcp" => Some(Self::Tcp),
+            "dot" => Some(Self::Dot),
+            "doh" => Some(Self::Doh),
+            "doq" => Some(Self::Doq),
+            _ => None,
+        }
+    }
+
+    fn socket_protocol(self) -> u64 {
+        match self {
+            Self::Udp => 1,
+            Self::Tcp => 2,
+            Self::Dot => 3,
+            Self::Doh | Self::Doh3 => 4,
+            Self::Doq => 7,
+        }
+    }
+}
+
+/// One query and its response (if any) as seen on the wire
+#[derive(Debug, Clone)]
+pub struct DnstapExchange<'a> {
+    pub role: DnstapRole,
+    pub protocol: DnstapProtocol,
+    /// Client address (client role) or upstream address (forwarder role)
+    pub peer: Option<SocketAddr>,
+    pub query: &'a [u8],
+    pub query_time: SystemTime,
+    /// None when no response was received or sent
+    pub response: Option<&'a [u8]>,
+}
+
+/// dnstap counters
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DnstapStats {
+    pub enabled: bool,
+    /// Whether the collector (or file) is currently open
+    pub connected: bool,
+    /// Messages accepted into the queue
+    pub queued: u64,
+    /// Messages written to the output
+    pub written: u64,
+    /// Messages lost to a full queue or an unavailable output
+    pub dropped: u64,
+    /// Messages waiting in the queue
+    pub pending: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicU64,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    connected: AtomicBool,
+    /// Sequence used to pick sampled exchanges
+    seen: AtomicU64,
+}
+
+/// Running output task
+struct TapWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    shutdown: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl TapWriter {
+    fn start(config: DnstapConfig, counters: Arc<Counters>) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(run(config, receiver, counters, shutdown.clone()));
+        Self { sender, shutdown, task }
+    }
+
+    /// Write what is queued, close the output and wait for the task
+    async fn stop(self) {
+        self.shutdown.cancel();
+        if let Err(e) = self.task.await {
+            tracing::warn!("dnstap writer stopped abnormally: {}", e);
+        }
+    }
+}
+
+/// dnstap message logger shared by listeners and upstream clients
+pub struct Dnstap {
+    config: RwLock<DnstapConfig>,
+    writer: RwLock<Option<TapWriter>>,
+    counters: Arc<Counters>,
+    /// Serializes output restarts
+    update: tokio::sync::Mutex<()>,
+}
+
+impl Dnstap {
+    /// Start logging with the given settings (idle when disabled)
+    pub fn start(config: DnstapConfig) -> Arc<Self> {
+        let counters = Arc::new(Counters::default());
+        let writer = config.enabled.then(|| TapWriter::start(config.clone(), counters.clone()));
+        Arc::new(Self {
+            config: RwLock::new(config),
+            writer: RwLock::new(writer),
+            counters,
+            update: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Whether the next exchange of `role` should be logged
+    ///
+    /// Call once per exchange and log both of its messages when it returns true.
+    pub fn sample(&self, role: DnstapRole) -> bool {
+        let config = self.config.read().unwrap();
+        let wanted = match role {
+            DnstapRole::Client => config.client_messages,
+            DnstapRole::Forwarder => config.forwarder_messages,
+        };
+        if !config.enabled || !wanted {
+            return false;
+        }
+        let rate = config.sample_rate.max(1) as u64;
+        rate == 1 || self.counters.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(rate)
+    }
+
+    /// Queue the messages of a sampled exchange; never waits
+    pub fn log(&self, exchange: &DnstapExchange<'_>) {
+        let identity = self.config.read().unwrap().identity.clone();
+        let writer = self.writer.read().unwrap();
+        let Some(ref writer) = *writer else {
+            return;
+        };
+
+        let (query_type, response_type) = match exchange.role {
+            DnstapRole::Client => (MESSAGE_CLIENT_QUERY, MESSAGE_CLIENT_RESPONSE),
+            DnstapRole::Forwarder => (MESSAGE_FORWARDER_QUERY, MESSAGE_FORWARDER_RESPONSE),
+        };
+        let mut frames = vec![encode_dnstap(identity.as_bytes(), query_type, exchange, None)];
+        if let Some(response) = exchange.response {
+            frames.push(encode_dnstap(identity.as_bytes(), response_type, exchange, Some((response, SystemTime::now()))));
+        }
+        for frame in frames {
+            match writer.sender.try_send(frame) {
+                Ok(()) => self.counters.queued.fetch_add(1, Ordering::Relaxed),
+                Err(_) => self.counters.dropped.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    /// Current settings
+    pub fn config(&self) -> DnstapConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Apply new settings, reopening the output when it changed
+    pub async fn set_config(&self, config: DnstapConfig) -> Result<()> {
+        config.validate()?;
+        let _update = self.update.lock().await;
+
+        let current = self.config();
+        let restart = current.enabled != config.enabled
+            || current.output != config.output
+            || current.path != config.path
+            || current.address != config.address
+            || current.max_file_size_mb != config.max_file_size_mb
+            || current.max_files != config.max_files;
+        if restart {
+            let old = self.writer.write().unwrap().take();
+            if let Some(old) = old {
+                old.stop().await;
+            }
+            if config.enabled {
+                let writer = TapWriter::start(config.clone(), self.counters.clone());
+                *self.writer.write().unwrap() = Some(writer);
+            }
+        }
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> DnstapStats {
+        let c = &self.counters;
+        let writer = self.writer.read().unwrap();
+        DnstapStats {
+            enabled: writer.is_some(),
+            connected: writer.is_some() && c.connected.load(Ordering::Relaxed),
+            queued: c.queued.load(Ordering::Relaxed),
+            written: c.written.load(Ordering::Relaxed),
+            dropped: c.dropped.load(Ordering::Relaxed),
+            pending: writer.as_ref()
+                .map(|w| w.sender.max_capacity() - w.sender.capacity())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Write what is queued and close the output
+    pub async fn shutdown(&self) {
+        let _update = self.update.lock().await;
+        let writer = self.writer.write().unwrap().take();
+        if let Some(writer) = writer {
+            writer.stop().await;
+        }
+    }
+}
+
+// dnstap.Message.Type values
+const MESSAGE_CLIENT_QUERY: u64 = 5;
+const MESSAGE_CLIENT_RESPONSE: u64 = 6;
+const MESSAGE_FORWARDER_QUERY: u64 = 7;
+const MESSAGE_FORWARDER_RESPONSE: u64 = 8;
+
+/// Minimal protobuf encoder for the fields dnstap uses
+#[derive(Default)]
+struct ProtoWriter(Vec<u8>);
+
+impl ProtoWriter {
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn varint(&mut self, field: u32, value: u64) {
+        self.raw_varint((field as u64) << 3);
+        self.raw_varint(value);
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.raw_varint(((field as u64) << 3) | 2);
+        self.raw_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn fixed32(&mut self, field: u32, value: u32) {
+        self.raw_varint(((field as u64) << 3) | 5);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn time(&mut self, sec_field: u32, time: SystemTime) {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.varint(sec_field, since.as_secs());
+        self.fixed32(sec_field + 1, since.subsec_nanos());
+    }
+}
+
+/// Encode one dnstap.Dnstap message
+///
+/// `response` carries the response bytes and time for *_RESPONSE messages.
+fn encode_dnstap(
+    identity: &[u8],
+    message_type: u64,
+    exchange: &DnstapExchange<'_>,
+    response: Option<(&[u8], SystemTime)>,
+) -> Vec<u8> {
+    let mut message = ProtoWriter::default();
+    message.varint(1, message_type);
+    if let Some(peer) = exchange.peer {
+        let (family, address) = match peer.ip() {
+            std::net::IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+            std::net::IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+        };
+        message.varint(2, family);
+        message.varint(3, exchange.protocol.socket_protocol());
+        // Clients are the querying side, upstreams the responding side
+        match exchange.role {
+            DnstapRole::Client => {
+                message.bytes(4, &address);
+                message.varint(6, peer.port() as u64);
+            }
+            DnstapRole::Forwarder => {
+                message.bytes(5, &address);
+                message.varint(7, peer.port() as u64);
+            }
+        }
+    } else {
+        message.varint(3, exchange.protocol.socket_protocol());
+    }
+    message.time(8, exchange.query_time);
+    match response {
+        None => message.bytes(10, exchange.query),
+        Some((bytes, time)) => {
+            message.time(12, time);
+            message.bytes(14, bytes);
+        }
+    }
+    if exchange.protocol == DnstapProtocol::Doh3 {
+        // HttpProtocol.HTTP3
+        message.varint(16, 3);
+    }
+
+    let mut dnstap = ProtoWriter::default();
+    if !identity.is_empty() {
+        dnstap.bytes(1, identity);
+    }
+    dnstap.bytes(2, concat!("fluxdns ", env!("CARGO_PKG_VERSION")).as_bytes());
+    dnstap.bytes(14, &message.0);
+    // Dnstap.Type.MESSAGE
+    dnstap.varint(15, 1);
+    dnstap.0
+}
+
+/// Frame Streams control frame, optionally carrying the content type
+fn control_frame(kind: u32, with_content_type: bool) -> Vec<u8> {
+    let mut body = kind.to_be_bytes().to_vec();
+    if with_content_type {
+        body.extend_from_slice(&FSTRM_CONTENT_TYPE.to_be_bytes());
+        body.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+        body.extend_from_slice(CONTENT_TYPE);
+    }
+    let mut frame = 0u32.to_be_bytes().to_vec();
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    frame
+}
+
+/// Read one control frame and return its type
+async fn read_control_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<u32> {
+    let escape = reader.read_u32().await?;
+    if escape != 0 {
+        return Err(anyhow!("expected a control frame"));
+    }
+    let len = reader.read_u32().await? as usize;
+    if !(4..=512).contains(&len) {
+        return Err(anyhow!("bad control frame length {}", len));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(u32::from_be_bytes([body[0], body[1], body[2], body[3]]))
+}
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Open Frame Streams output
+enum Sink {
+    Socket(BufWriter<Box<dyn Transport>>),
+    File {
+        writer: BufWriter<tokio::fs::File>,
+        path: PathBuf,
+        written: u64,
+    },
+}
+
+impl Sink {
+    async fn open(config: &DnstapConfig) -> Result<Self> {
+        match config.output {
+            #[cfg(unix)]
+            DnstapOutput::Unix => {
+                let stream = tokio::net::UnixStream::connect(&config.path).await?;
+                Self::handshake(Box::new(stream)).await
+            }
+            #[cfg(not(unix))]
+            DnstapOutput::Unix => Err(anyhow!("Unix socket output is not supported on this platform")),
+            DnstapOutput::Tcp => {
+                let stream = tokio::net::TcpStream::connect(config.address.trim()).await?;
+                stream.set_nodelay(true)?;
+                Self::handshake(Box::new(stream)).await
+            }
+            DnstapOutput::File => Self::open_file(Path::new(&config.path), config.max_files).await,
+        }
+    }
+
+    /// Bidirectional handshake: READY, ACCEPT, START
+    async fn handshake(stream: Box<dyn Transport>) -> Result<Self> {
+        let mut writer = BufWriter::new(stream);
+        timeout(HANDSHAKE_TIMEOUT, async {
+            writer.write_all(&control_frame(FSTRM_READY, true)).await?;
+            writer.flush().await?;
+            match read_control_frame(writer.get_mut()).await? {
+                FSTRM_ACCEPT => {}
+                other => return Err(anyhow!("collector answered READY with control frame {}", other)),
+            }
+            writer.write_all(&control_frame(FSTRM_START, true)).await?;
+            writer.flush().await?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| anyhow!("Frame Streams handshake timed out"))??;
+        Ok(Sink::Socket(writer))
+    }
+
+    /// Start a fresh file; an existing one is rotated away first
+    async fn open_file(path: &Path, max_files: u32) -> Result<Self> {
+        if tokio::fs::metadata(path).await.map(|m| m.len() > 0).unwrap_or(false) {
+            rotate_files(path, max_files).await?;
+        }
+        let file = tokio::fs::File::create(path).await?;
+        let mut writer = BufWriter::new(file);
+        let start = control_frame(FSTRM_START, true);
+        writer.write_all(&start).await?;
+        Ok(Sink::File {
+            writer,
+            path: path.to_path_buf(),
+            written: start.len() as u64,
+        })
+    }
+
+    async fn write(&mut self, payload: &[u8], config: &DnstapConfig) -> Result<()> {
+        let len = (payload.len() as u32).to_be_bytes();
+        match self {
+            Sink::Socket(writer) => {
+                writer.write_all(&len).await?;
+                writer.write_all(payload).await?;
+            }
+            Sink::File { writer, path, written } => {
+                writer.write_all(&len).await?;
+                writer.write_all(payload).await?;
+                *written += (len.len() + payload.len()) as u64;
+                if *written >= config.max_file_size_mb * 1024 * 1024 {
+                    let path = path.clone();
+                    Self::finish(writer).await?;
+                    *self = Self::open_file(&path, config.max_files).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Socket(writer) => writer.flush().await?,
+            Sink::File { writer, .. } => writer.flush().await?,
+        }
+        Ok(())
+    }
+
+    /// Send STOP and flush; collectors may answer FINISH, which is not awaited
+    async fn close(mut self) {
+        let result = match self {
+            Sink::Socket(ref mut writer) => Self::finish(writer).await,
+            Sink::File { ref mut writer, .. } => Self::finish(writer).await,
+        };
+        if let Err(e) = result {
+            tracing::debug!("Failed to close dnstap output cleanly: {}", e);
+        }
+    }
+
+    async fn finish<W: AsyncWrite + Unpin>(writer: &mut BufWriter<W>) -> Result<()> {
+        writer.write_all(&control_frame(FSTRM_STOP, false)).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Shift `path` to `path.1`, `path.1` to `path.2`, ... keeping `max_files`
+async fn rotate_files(path: &Path, max_files: u32) -> Result<()> {
+    let numbered = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+    let _ = tokio::fs::remove_file(numbered(max_files)).await;
+    for n in (1..max_files).rev() {
+        let from = numbered(n);
+        if tokio::fs::metadata(&from).await.is_ok() {
+            tokio::fs::rename(&from, numbered(n + 1)).await?;
+        }
+    }
+    tokio::fs::rename(path, numbered(1)).await?;
+    Ok(())
+}
+
+/// Writer loop: keep the output open, write queued frames, flush periodically
+async fn run(
+    config: DnstapConfig,
+    mut receiver: mpsc::Receiver<Vec<u8>>,
+    counters: Arc<Counters>,
+    shutdown: CancellationToken,
+) {
+    let mut sink: Option<Sink> = None;
+    let mut retry_at = Instant::now();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        if sink.is_none() && Instant::now() >= retry_at {
+            match Sink::open(&config).await {
+                Ok(opened) => {
+                    tracing::info!("dnstap output opened ({:?})", config.output);
+                    counters.connected.store(true, Ordering::Relaxed);
+                    sink = Some(opened);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to open dnstap output ({:?}): {}", config.output, e);
+                    retry_at = Instant::now() + RECONNECT_INTERVAL;
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            frame = receiver.recv() => {
+                let Some(frame) = frame else { break };
+                write_frame(&mut sink, &frame, &config, &counters, &mut retry_at).await;
+            }
+            _ = flush.tick() => {
+                if let Some(ref mut open) = sink {
+                    if let Err(e) = open.flush().await {
+                        tracing::warn!("dnstap output failed: {}", e);
+                        sink = None;
+                        counters.connected.store(false, Ordering::Relaxed);
+                        retry_at = Instant::now() + RECONNECT_INTERVAL;
+                    }
+                }
+            }
+        }
+    }
+
+    // Write what was queued before shutdown
+    while let Ok(frame) = receiver.try_recv() {
+        write_frame(&mut sink, &frame, &config, &counters, &mut retry_at).await;
+    }
+    if let Some(open) = sink {
+        open.close().await;
+    }
+    counters.connected.store(false, Ordering::Relaxed);
+}
+
+async fn write_frame(
+    sink: &mut Option<Sink>,
+    frame: &[u8],
+    config: &DnstapConfig,
+    counters: &Counters,
+    retry_at: &mut Instant,
+) {
+    let Some(open) = sink else {
+        counters.dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    match open.write(frame, config).await {
+        Ok(()) => {
+            counters.written.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => {
+            tracing::warn!("dnstap output failed: {}", e);
+            counters.dropped.fetch_add(1, Ordering::Relaxed);
+            counters.connected.store(false, Ordering::Relaxed);
+            *sink = None;
+            *retry_at = Instant::now() + RECONNECT_INTERVAL;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read a varint-prefixed field list back into (field, wire type, value bytes)
+    fn decode(mut data: &[u8]) -> Vec<(u32, u8, Vec<u8>)> {
+        fn varint(data: &mut &[u8]) -> u64 {
+            let mut value = 0u64;
+            let mut shift = 0;
+            loop {
+                let byte = data[0];
+                *data = &data[1..];
+                value |= ((byte & 0x7f) as u64) << shift;
+                if byte < 0x80 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let key = varint(&mut data);
+            let (field, wire) = ((key >> 3) as u32, (key & 7) as u8);
+            let value = match wire {
+                0 => varint(&mut data).to_le_bytes().to_vec(),
+                2 => {
+                    let len = varint(&mut data) as usize;
+                    let (value, rest) = data.split_at(len);
+                    data = rest;
+                    value.to_vec()
+                }
+                5 => {
+                    let (value, rest) = data.split_at(4);
+                    data = rest;
+                    value.to_vec()
+                }
+                _ => panic!("unexpected wire type {}", wire),
+            };
+            fields.push((field, wire, value));
+        }
+        fields
+    }
+
+    fn field(fields: &[(u32, u8, Vec<u8>)], number: u32) -> Option<Vec<u8>> {
+        fields.iter().find(|(f, _, _)| *f == number).map(|(_, _, v)| v.clone())
+    }
+
+    #[test]
+    fn test_encode_client_exchange() {
+        let exchange = DnstapExchange {
+            role: DnstapRole::Client,
+            protocol: DnstapProtocol::Dot,
+            peer: Some("192.0.2.7:5353".parse().unwrap()),
+            query: b"query-bytes",
+            query_time: UNIX_EPOCH + Duration::new(1_700_000_000, 42),
+            response: None,
+        };
+        let dnstap = decode(&encode_dnstap(b"ns1", MESSAGE_CLIENT_QUERY, &exchange, None));
+        assert_eq!(field(&dnstap, 1).unwrap(), b"ns1");
+        assert_eq!(field(&dnstap, 15).unwrap()[0], 1);
+
+        let message = decode(&field(&dnstap, 14).unwrap());
+        assert_eq!(field(&message, 1).unwrap()[0], MESSAGE_CLIENT_QUERY as u8);
+        assert_eq!(field(&message, 2).unwrap()[0], 1);
+        assert_eq!(field(&message, 3).unwrap()[0], 3);
+        assert_eq!(field(&message, 4).unwrap(), vec![192, 0, 2, 7]);
+        assert_eq!(u64::from_le_bytes(field(&message, 6).unwrap().try_into().unwrap()), 5353);
+        assert_eq!(u64::from_le_bytes(field(&message, 8).unwrap().try_into().unwrap()), 1_700_000_000);
+        assert_eq!(field(&message, 9).unwrap(), 42u32.to_le_bytes().to_vec());
+        assert_eq!(field(&message, 10).unwrap(), b"query-bytes");
+        assert!(field(&message, 14).is_none());
+    }
+
+    #[test]
+    fn test_encode_forwarder_response() {
+        let exchange = DnstapExchange {
+            role: DnstapRole::Forwarder,
+            protocol: DnstapProtocol::Doh3,
+            peer: Some("[2001:db8::1]:443".parse().unwrap()),
+            query: b"q",
+            query_time: SystemTime::now(),
+            response: Some(b"r"),
+        };
+        let dnstap = decode(&encode_dnstap(b"", MESSAGE_FORWARDER_RESPONSE, &exchange, Some((b"r", SystemTime::now()))));
+        assert!(field(&dnstap, 1).is_none());
+
+        let message = decode(&field(&dnstap, 14).unwrap());
+        assert_eq!(field(&message, 1).unwrap()[0], MESSAGE_FORWARDER_RESPONSE as u8);
+        assert_eq!(field(&message, 2).unwrap()[0], 2);
+        assert_eq!(field(&message, 3).unwrap()[0], 4);
+        assert_eq!(field(&message, 5).unwrap().len(), 16);
+        assert_eq!(field(&message, 14).unwrap(), b"r");
+        assert_eq!(field(&message, 16).unwrap()[0], 3);
+        assert!(field(&message, 10).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_output_and_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tap.fstrm");
+        let config = DnstapConfig {
+            enabled: true,
+            output: DnstapOutput::File,
+            path: path.display().to_string(),
+            max_file_size_mb: 1,
+            max_files: 2,
+            ..Default::default()
+        };
+        let dnstap = Dnstap::start(config);
+        assert!(dnstap.sample(DnstapRole::Client));
+
+        let query = vec![0u8; 300 * 1024];
+        for _ in 0..4 {
+            dnstap.log(&DnstapExchange {
+                role: DnstapRole::Client,
+                protocol: DnstapProtocol::Udp,
+                peer: Some("127.0.0.1:40000".parse().unwrap()),
+                query: &query,
+                query_time: SystemTime::now(),
+                response: Some(b"response"),
+            });
+        }
+        dnstap.shutdown().await;
+        assert_eq!(dnstap.stats().written, 8);
+
+        // Every file is a complete unidirectional stream: START ... STOP
+        let rotated = std::fs::read(format!("{}.1", path.display())).unwrap();
+        let current = std::fs::read(&path).unwrap();
+        let start = control_frame(FSTRM_START, true);
+        let stop = control_frame(FSTRM_STOP, false);
+        for data in [&rotated, &current] {
+            assert!(data.starts_with(&start));
+            assert!(data.ends_with(&stop));
+        }
+        assert!(rotated.len() as u64 >= 1024 * 1024);
+    }
+
+    #[test]
+    fn test_sampling_and_validation() {
+        let dnstap = Dnstap {
+            config: RwLock::new(DnstapConfig {
+                enabled: true,
+                sample_rate: 4,
+                forwarder_messages: false,
+                ..Default::default()
+            }),
+            writer: RwLock::new(None),
+            counters: Arc::new(Counters::default()),
+            update: tokio::sync::Mutex::new(()),
+        };
+        let sampled = (0..100).filter(|_| dnstap.sample(DnstapRole::Client)).count();
+        assert_eq!(sampled, 25);
+        assert!(!dnstap.sample(DnstapRole::Forwarder));
+
+        let tcp = |address: &str| DnstapConfig {
+            output: DnstapOutput::Tcp,
+            address: address.to_string(),
+            ..Default::default()
+        };
+        assert!(tcp("collector.local:6000").validate().is_ok());
+        assert!(tcp("collector.local").validate().is_err());
+        assert!(DnstapConfig { sample_rate: 0, ..Default::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Upstream Response Filters
+//!
+//! Inspects upstream answers before they are cached and returned:
+//! - Bogus NXDOMAIN: answers containing configured bogus IPs become NXDOMAIN
+//! - Rebinding protection: public names resolving to private addresses are blocked
+//! - IP blocklist: answers containing blocklisted IPs are blocked
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::message::{DnsResponse, RecordType};
+use super::proxy::IpRangeSet;
+
+/// Names that are expected to resolve to private addresses
+const LOCAL_SUFFIXES: &[&str] = &["localhost", "local", "lan", "localdomain", "internal", "home.arpa"];
+
+/// Response filter settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseFilterConfig {
+    /// IPs/CIDRs that turn an answer into NXDOMAIN (like dnsmasq `bogus-nxdomain`)
+    #[serde(default)]
+    pub bogus_nxdomain: Vec<String>,
+    /// Block public names resolving to RFC1918, loopback or link-local addresses
+    #[serde(default)]
+    pub rebind_protection: bool,
+    /// Domains allowed to resolve to private addresses (suffix match)
+    #[serde(default)]
+    pub rebind_allowlist: Vec<String>,
+    /// IPs/CIDRs that must never be returned
+    #[serde(default)]
+    pub ip_blocklist: Vec<String>,
+}
+
+/// Why a response was filtered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterAction {
+    /// The answer contained a configured bogus IP
+    BogusNxdomain(IpAddr),
+    /// A public name resolved to a private address
+    Rebinding(IpAddr),
+    /// The answer contained a blocklisted IP
+    Blocklisted(IpAddr),
+}
+
+impl FilterAction {
+    /// Human-readable reason for the query log
+    pub fn reason(&self) -> String {
+        match self {
+            FilterAction::BogusNxdomain(ip) => format!("response-filter: bogus-nxdomain {}", ip),
+            FilterAction::Rebinding(ip) => format!("response-filter: rebinding blocked {}", ip),
+            FilterAction::Blocklisted(ip) => format!("response-filter: ip blocklist {}", ip),
+        }
+    }
+}
+
+/// Compiled response filters
+#[derive(Debug, Default)]
+pub struct ResponseFilter {
+    bogus: IpRangeSet,
+    blocklist: IpRangeSet,
+    rebind_protection: bool,
+    rebind_allowlist: HashSet<String>,
+}
+
+impl ResponseFilter {
+    /// Compile the configuration, rejecting invalid CIDRs
+    pub fn from_config(config: &ResponseFilterConfig) -> Result<Self> {
+        Ok(Self {
+            bogus: IpRangeSet::parse(config.bogus_nxdomain.iter().map(String::as_str))?,
+            blocklist: IpRangeSet::parse(config.ip_blocklist.iter().map(String::as_str))?,
+            rebind_protection: config.rebind_protection,
+            rebind_allowlist: config.rebind_allowlist.iter()
+                .map(|d| d.trim().trim_start_matches("*.").trim_matches('.').to_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect(),
+        })
+    }
+
+    /// Whether no filter is configured
+    pub fn is_empty(&self) -> bool {
+        self.bogus.is_empty() && self.blocklist.is_empty() && !self.rebind_protection
+    }
+
+    /// Check an upstream answer for the given query name
+    pub fn check(&self, name: &str, response: &DnsResponse) -> Option<FilterAction> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let ips: Vec<IpAddr> = response.answers.iter()
+            .filter(|r| matches!(r.record_type, RecordType::A | RecordType::AAAA))
+            .filter_map(|r| r.value.parse().ok())
+            .collect();
+
+        if let Some(ip) = ips.iter().find(|ip| self.bogus.contains(**ip)) {
+            return Some(FilterAction::BogusNxdomain(*ip));
+        }
+        if let Some(ip) = ips.iter().find(|ip| self.blocklist.contains(**ip)) {
+            return Some(FilterAction::Blocklisted(*ip));
+        }
+        if self.rebind_protection && !self.rebind_allowed(name) {
+            if let Some(ip) = ips.iter().find(|ip| is_private_ip(**ip)) {
+                return Some(FilterAction::Rebinding(*ip));
+            }
+        }
+        None
+    }
+
+    /// Local names and allowlisted domains may resolve to private addresses
+    fn rebind_allowed(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_lowercase();
+        if !name.contains('.') {
+            return true;
+        }
+
+        let mut suffix = name.as_str();
+        loop {
+            if self.rebind_allowlist.contains(suffix) || LOCAL_SUFFIXES.contains(&suffix) {
+                return true;
+            }
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Check for RFC1918, loopback, link-local, unspecified and ULA addresses
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.octets()[0] == 0
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00 // Unique local (fc00::/7)
+                || (first & 0xffc0) == 0xfe80 // Link-local (fe80::/10)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::message::DnsRecordData;
+
+    fn response(ips: &[&str]) -> DnsResponse {
+        let mut response = DnsResponse::new(1);
+        for ip in ips {
+            let record = match ip.parse::<IpAddr>().unwrap() {
+                IpAddr::V4(v4) => DnsRecordData::a("example.com", v4, 60),
+                IpAddr::V6(v6) => DnsRecordData::aaaa("example.com", v6, 60),
+            };
+            response.answers.push(record);
+        }
+        response
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        let filter = ResponseFilter::from_config(&ResponseFilterConfig::default()).unwrap();
+        assert!(filter.is_empty());
+        assert_eq!(filter.check("example.com", &response(&["10.0.0.1"])), None);
+    }
+
+    #[test]
+    fn test_bogus_and_blocklist() {
+        let filter = ResponseFilter::from_config(&ResponseFilterConfig {
+            bogus_nxdomain: vec!["198.18.0.1".to_string()],
+            ip_blocklist: vec!["203.0.113.0/24".to_string()],
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(
+            filter.check("example.com", &response(&["93.184.216.34", "198.18.0.1"])),
+            Some(FilterAction::BogusNxdomain("198.18.0.1".parse().unwrap()))
+        );
+        assert_eq!(
+            filter.check("example.com", &response(&["203.0.113.7"])),
+            Some(FilterAction::Blocklisted("203.0.113.7".parse().unwrap()))
+        );
+        assert_eq!(filter.check("example.com", &response(&["93.184.216.34"])), None);
+
+        assert!(ResponseFilter::from_config(&ResponseFilterConfig {
+            ip_blocklist: vec!["bad".to_string()],
+            ..Default::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn test_rebind_protection() {
+        let filter = ResponseFilter::from_config(&ResponseFilterConfig {
+            rebind_protection: true,
+            rebind_allowlist: vec!["corp.example".to_string()],
+            ..Default::default()
+        }).unwrap();
+
+        for ip in ["192.168.1.1", "10.1.2.3", "172.16.0.1", "127.0.0.1", "169.254.1.1", "::1", "fe80::1", "fd00::1", "::ffff:10.0.0.1"] {
+            assert_eq!(
+                filter.check("evil.com", &response(&[ip])),
+                Some(FilterAction::Rebinding(ip.parse().unwrap())),
+                "{} should be blocked", ip
+            );
+        }
+        assert_eq!(filter.check("evil.com", &response(&["8.8.8.8", "2001:4860::8888"])), None);
+
+        // Allowlisted and local names may resolve to private addresses
+        assert_eq!(filter.check("git.corp.example", &response(&["10.0.0.5"])), None);
+        assert_eq!(filter.check("nas.local", &response(&["192.168.1.2"])), None);
+        assert_eq!(filter.check("router", &response(&["192.168.1.1"])), None);
+    }
+}
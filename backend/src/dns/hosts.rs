@@ -0,0 +1,124 @@
+//! Hosts Files
+//!
+//! Parses `/etc/hosts` style files into A and AAAA records. Loopback names
+//! every system defines (`localhost`, `ip6-localhost`, ...) are skipped so a
+//! stock hosts file does not shadow them for every client.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use super::zonefile::{LineError, ParsedFile, ParsedRecord};
+
+/// Names found in every stock hosts file
+const SYSTEM_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "localhost4",
+    "localhost4.localdomain4",
+    "localhost6",
+    "localhost6.localdomain6",
+    "broadcasthost",
+];
+
+fn is_system_name(name: &str) -> bool {
+    SYSTEM_NAMES.contains(&name) || name.starts_with("ip6-")
+}
+
+fn valid_host_name(name: &str) -> bool {
+    name.len() <= 255
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Parse a hosts file
+///
+/// Every name on a line becomes one record with the given TTL; repeated
+/// name and address pairs are kept once.
+pub fn parse_hosts(content: &str, ttl: u32) -> ParsedFile {
+    let mut parsed = ParsedFile::default();
+    let mut seen = HashSet::new();
+
+    for (index, raw) in content.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.split('#').next().unwrap_or_default();
+        let mut fields = text.split_whitespace();
+        let Some(address) = fields.next() else {
+            continue;
+        };
+
+        let ip: IpAddr = match address.parse() {
+            Ok(ip) => ip,
+            Err(_) => {
+                parsed.errors.push(LineError { line, message: format!("invalid IP address '{}'", address) });
+                continue;
+            }
+        };
+        let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+
+        let names: Vec<&str> = fields.collect();
+        if names.is_empty() {
+            parsed.errors.push(LineError { line, message: format!("no host names for {}", address) });
+            continue;
+        }
+
+        for name in names {
+            let name = name.trim_end_matches('.').to_lowercase();
+            if is_system_name(&name) {
+                continue;
+            }
+            if !valid_host_name(&name) {
+                parsed.errors.push(LineError { line, message: format!("invalid host name '{}'", name) });
+                continue;
+            }
+            if !seen.insert((name.clone(), ip)) {
+                continue;
+            }
+            parsed.records.push(ParsedRecord {
+                line,
+                name,
+                record_type: record_type.to_string(),
+                value: ip.to_string(),
+                ttl,
+                priority: 0,
+            });
+        }
+    }
+
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hosts() {
+        let hosts = "\
+127.0.0.1   localhost localhost.localdomain
+::1         localhost ip6-localhost ip6-loopback
+# lab machines
+192.168.1.10  nas.lab nas   # storage
+192.168.1.10  NAS.lab.
+fe80::1%eth0  router
+0.0.0.0 ads.example.com
+192.168.1.11
+192.168.1.12  bad!name
+";
+        let parsed = parse_hosts(hosts, 600);
+        let records: Vec<(usize, &str, &str, &str)> = parsed.records.iter()
+            .map(|r| (r.line, r.name.as_str(), r.record_type.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(records, vec![
+            (4, "nas.lab", "A", "192.168.1.10"),
+            (4, "nas", "A", "192.168.1.10"),
+            (7, "ads.example.com", "A", "0.0.0.0"),
+        ]);
+        assert!(parsed.records.iter().all(|r| r.ttl == 600));
+
+        let lines: Vec<usize> = parsed.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![6, 8, 9]);
+    }
+}
@@ -0,0 +1,932 @@
+//! Local Data Snapshot
+//!
+//! In-memory copy of the local DNS records, the authoritative zones and the
+//! resolver settings read on every query, so the hot path never touches
+//! SQLite. The resolver swaps in a fresh snapshot whenever the database
+//! announces a write.
+//!
+//! Names inside an enabled zone are never forwarded: a missing name gets an
+//! authoritative NXDOMAIN and a missing type an authoritative NODATA, both
+//! carrying the zone's SOA so resolvers can cache the negative answer.
+//! Secondary zones are served the same way from their last transfer.
+//! Reverse lookups without an explicit PTR record can be synthesized from
+//! the local A/AAAA records (see `ptr`).
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::db::{next_zone_serial, CreateZoneJournalEntry, Database, DnsRecord, DnsZone};
+use super::message::{DnsQuery, DnsRecordData, DnsResponse, DnsResponseCode, RecordType};
+use super::proxy::IpRangeSet;
+use super::ptr::{PtrNames, PtrSynthesisConfig, PTR_SYNTHESIS_CONFIG_KEY};
+
+/// Config key holding the disabled record types
+const CONFIG_KEY_DISABLED_RECORD_TYPES: &str = "disabled_record_types";
+
+/// CNAMEs followed inside the local data before giving up
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Whether `name` is `origin` or below it
+pub(super) fn in_zone(name: &str, origin: &str) -> bool {
+    name == origin
+        || (name.len() > origin.len()
+            && name.ends_with(origin)
+            && name.as_bytes()[name.len() - origin.len() - 1] == b'.')
+}
+
+/// Parent domains of a name, closest first
+fn ancestors(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices('.').map(move |(i, _)| &name[i + 1..])
+}
+
+/// Identity of a record, used to fingerprint and diff zone contents
+fn record_key(record: &DnsRecordData) -> String {
+    format!(
+        "{} {} {} {} {}",
+        record.name.trim_end_matches('.').to_lowercase(),
+        record.record_type,
+        record.ttl,
+        record.priority.map(|p| p.to_string()).unwrap_or_default(),
+        record.value
+    )
+}
+
+/// ID of the row behind a record; transferred records have none
+fn row_id(record: &DnsRecord) -> Option<i64> {
+    (record.id > 0).then_some(record.id)
+}
+
+/// Zone answered authoritatively
+#[derive(Debug)]
+struct Zone {
+    id: i64,
+    origin: String,
+    soa: DnsRecordData,
+    nameservers: Vec<DnsRecordData>,
+    /// TTL of the SOA in negative answers (RFC 2308)
+    negative_ttl: u32,
+    /// Everything served for the zone but the SOA, sorted, for transfers
+    records: Vec<DnsRecordData>,
+    /// Clients allowed to transfer the zone
+    transfer_acl: IpRangeSet,
+}
+
+/// SOA and apex NS records of a zone, built from its settings
+pub(super) fn apex_records(zone: &DnsZone) -> (DnsRecordData, Vec<DnsRecordData>) {
+    let ttl = zone.ttl.max(0) as u32;
+    let soa = DnsRecordData {
+        name: zone.origin.clone(),
+        record_type: RecordType::SOA,
+        value: format!(
+            "{} {} {} {} {} {} {}",
+            zone.primary_ns, zone.admin_email, zone.serial, zone.refresh, zone.retry, zone.expire, zone.minimum
+        ),
+        ttl,
+        priority: None,
+    };
+    let nameservers = zone
+        .nameserver_list()
+        .into_iter()
+        .map(|ns| DnsRecordData::ns(&zone.origin, ns, ttl))
+        .collect();
+    (soa, nameservers)
+}
+
+impl Zone {
+    fn new(zone: &DnsZone) -> Self {
+        let ttl = zone.ttl.max(0) as u32;
+        let (soa, nameservers) = apex_records(zone);
+        let transfer_acl = IpRangeSet::parse(zone.transfer_acl_list()).unwrap_or_else(|e| {
+            warn!("Invalid transfer ACL of zone {}, denying transfers: {}", zone.origin, e);
+            IpRangeSet::default()
+        });
+        Self {
+            id: zone.id,
+            origin: zone.origin.clone(),
+            soa,
+            nameservers,
+            negative_ttl: ttl.min(zone.minimum.max(0) as u32),
+            records: Vec::new(),
+            transfer_acl,
+        }
+    }
+}
+
+/// Data of a zone as sent in a transfer
+#[derive(Debug)]
+pub struct ZoneTransferData<'a> {
+    pub zone_id: i64,
+    pub soa: &'a DnsRecordData,
+    /// Everything but the SOA
+    pub records: &'a [DnsRecordData],
+    /// Whether the asking client is on the zone's transfer ACL
+    pub allowed: bool,
+}
+
+/// Zone whose serial moved when a snapshot was loaded
+#[derive(Debug, Clone)]
+pub struct ZoneChange {
+    pub origin: String,
+    pub serial: i64,
+    /// Servers to send a NOTIFY
+    pub notify: Vec<String>,
+}
+
+/// Response built from local data
+#[derive(Debug)]
+pub struct LocalAnswer {
+    pub response: DnsResponse,
+    /// First local record in the answer; `None` for zone data and negative answers
+    pub record_id: Option<i64>,
+    /// Where a local CNAME chain leaves the local data, still to be resolved
+    pub cname_target: Option<String>,
+}
+
+/// Immutable view of local records, zones and resolver settings
+#[derive(Debug, Default)]
+pub struct LocalSnapshot {
+    /// Enabled records by (lowercase name, uppercase type); wildcards keep their `*.` name
+    records: HashMap<(String, String), Vec<DnsRecord>>,
+    /// Owner names of the records and their parents, to tell NODATA from NXDOMAIN
+    names: HashSet<String>,
+    /// Enabled zones, most specific first
+    zones: Vec<Zone>,
+    /// Uppercase record types answered with NXDOMAIN
+    disabled_types: HashSet<String>,
+    /// Zones whose serial moved while loading this snapshot
+    zone_changes: Vec<ZoneChange>,
+    /// Names answered for reverse lookups; empty unless PTR synthesis is on
+    ptr_names: PtrNames,
+}
+
+impl LocalSnapshot {
+    /// Build a snapshot from records and disabled record types
+    pub fn new(records: Vec<DnsRecord>, disabled_types: &[String]) -> Self {
+        let mut by_key: HashMap<(String, String), Vec<DnsRecord>> = HashMap::new();
+        let mut names = HashSet::new();
+        for record in records.into_iter().filter(|r| r.enabled) {
+            let name = record.name.trim_end_matches('.').to_lowercase();
+            names.extend(ancestors(&name).map(str::to_string));
+            names.insert(name.clone());
+            by_key.entry((name, record.record_type.to_uppercase())).or_default().push(record);
+        }
+        Self {
+            records: by_key,
+            names,
+            zones: Vec::new(),
+            disabled_types: disabled_types.iter().map(|t| t.to_uppercase()).collect(),
+            zone_changes: Vec::new(),
+            ptr_names: PtrNames::default(),
+        }
+    }
+
+    /// Synthesize PTR answers from the A/AAAA records and `rules`
+    pub fn with_ptr_synthesis(mut self, config: &PtrSynthesisConfig, rules: &[crate::db::RewriteRule]) -> Self {
+        if config.enabled {
+            let records: Vec<&DnsRecord> = self.records.values().flatten().collect();
+            self.ptr_names = PtrNames::build(config, &records, rules);
+        }
+        self
+    }
+
+    /// Answer the enabled zones authoritatively
+    pub fn with_zones(mut self, zones: &[DnsZone]) -> Self {
+        self.zones = zones.iter().filter(|z| z.enabled).map(Zone::new).collect();
+        self.zones.sort_by_key(|z| std::cmp::Reverse(z.origin.len()));
+
+        // Each record belongs to the most specific zone containing it
+        let mut contents: Vec<Vec<DnsRecordData>> = vec![Vec::new(); self.zones.len()];
+        for ((name, record_type), records) in &self.records {
+            let Some(i) = self.zones.iter().position(|z| in_zone(name, &z.origin)) else {
+                continue;
+            };
+            match RecordType::from_str(record_type) {
+                Ok(RecordType::SOA) | Err(_) => {}
+                Ok(record_type) => {
+                    contents[i].extend(records.iter().filter_map(|r| record_data(r, name, record_type)));
+                }
+            }
+        }
+        for (zone, mut records) in self.zones.iter_mut().zip(contents) {
+            records.extend(zone.nameservers.iter().cloned());
+            records.sort_by_cached_key(record_key);
+            records.dedup_by_key(|r| record_key(r));
+            zone.records = records;
+        }
+        self
+    }
+
+    /// Read the current records, zones and settings from the database
+    pub async fn load(db: &Database) -> Result<Self> {
+        let mut records = db.dns_records().list().await?;
+        let mut zones = db.dns_zones().list().await?;
+        // Secondary zones are served from their last transfer, once there is one
+        zones.retain_mut(|zone| zone.enabled && (!zone.is_secondary() || apply_transfer(zone, &mut records)));
+
+        let disabled_types = match db.system_config().get(CONFIG_KEY_DISABLED_RECORD_TYPES).await? {
+            Some(value) => serde_json::from_str::<Vec<String>>(&value).unwrap_or_else(|e| {
+                tracing::warn!("Invalid disabled_record_types setting, ignoring: {}", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        let ptr_synthesis = match db.system_config().get(PTR_SYNTHESIS_CONFIG_KEY).await? {
+            Some(value) => serde_json::from_str::<PtrSynthesisConfig>(&value).unwrap_or_else(|e| {
+                warn!("Invalid ptr_synthesis setting, ignoring: {}", e);
+                PtrSynthesisConfig::default()
+            }),
+            None => PtrSynthesisConfig::default(),
+        };
+        let rewrite_rules = if ptr_synthesis.enabled && ptr_synthesis.rewrite_rules {
+            db.rewrite_rules().list_enabled().await?
+        } else {
+            Vec::new()
+        };
+
+        let snapshot = Self::new(records, &disabled_types).with_zones(&zones);
+        let zone_changes = refresh_serials(db, &mut zones, &snapshot).await?;
+        let mut snapshot = snapshot.with_zones(&zones).with_ptr_synthesis(&ptr_synthesis, &rewrite_rules);
+        snapshot.zone_changes = zone_changes;
+        Ok(snapshot)
+    }
+
+    /// Zones whose serial moved while loading this snapshot
+    pub fn zone_changes(&self) -> &[ZoneChange] {
+        &self.zone_changes
+    }
+
+    /// Data of the zone at `origin` for a transfer to `client`
+    pub fn zone_transfer(&self, origin: &str, client: IpAddr) -> Option<ZoneTransferData<'_>> {
+        let origin = origin.trim_end_matches('.').to_lowercase();
+        let zone = self.zones.iter().find(|z| z.origin == origin)?;
+        Some(ZoneTransferData {
+            zone_id: zone.id,
+            soa: &zone.soa,
+            records: &zone.records,
+            allowed: zone.transfer_acl.contains(client),
+        })
+    }
+
+    /// Number of enabled local records
+    pub fn record_count(&self) -> usize {
+        self.records.values().map(Vec::len).sum()
+    }
+
+    /// Number of enabled zones
+    pub fn zone_count(&self) -> usize {
+        self.zones.len()
+    }
+
+    /// Check if a record type is disabled in settings
+    pub fn is_type_disabled(&self, record_type: RecordType) -> bool {
+        self.disabled_types.contains(&record_type.to_string().to_uppercase())
+    }
+
+    /// Records for a name and type
+    ///
+    /// An exact match wins; otherwise the most specific wildcard
+    /// (`*.b.example.com` before `*.example.com`) is used.
+    pub fn lookup(&self, name: &str, record_type: RecordType) -> Option<&[DnsRecord]> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let record_type = record_type.to_string().to_uppercase();
+
+        if let Some(records) = self.records.get(&(name.clone(), record_type.clone())) {
+            return Some(records);
+        }
+
+        let wildcard = ancestors(&name).find_map(|parent| {
+            let wildcard = format!("*.{}", parent);
+            self.records.get(&(wildcard, record_type.clone())).map(Vec::as_slice)
+        });
+        wildcard
+    }
+
+    /// Most specific zone containing a name
+    fn zone_for(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|z| in_zone(name, &z.origin))
+    }
+
+    /// Whether a name owns records, has names below it or matches a wildcard
+    fn name_exists(&self, name: &str) -> bool {
+        self.names.contains(name)
+            || ancestors(name).any(|parent| self.names.contains(&format!("*.{}", parent)))
+    }
+
+    /// Whether a name sits at or below an NS record under the zone apex
+    fn is_delegated(&self, name: &str, zone: &Zone) -> bool {
+        std::iter::once(name)
+            .chain(ancestors(name))
+            .take_while(|n| *n != zone.origin)
+            .any(|n| self.records.contains_key(&(n.to_string(), "NS".to_string())))
+    }
+
+    /// Add the records of a name and type to the answers
+    ///
+    /// Returns the ID of the first record added.
+    fn add_records(&self, response: &mut DnsResponse, name: &str, record_type: RecordType) -> Option<i64> {
+        let records = self.lookup(name, record_type)?;
+        let mut first_id = None;
+
+        for record in records {
+            // For wildcard records, use the queried name instead of the record name
+            let response_name = if record.name.starts_with("*.") {
+                name
+            } else {
+                &record.name
+            };
+            let Some(data) = record_data(record, response_name, record_type) else {
+                continue;
+            };
+            let duplicate = response.answers.iter().any(|a| {
+                a.record_type == data.record_type && a.value.eq_ignore_ascii_case(&data.value)
+            });
+            if !duplicate {
+                response.add_answer(data);
+                first_id = first_id.or(row_id(record));
+            }
+        }
+
+        first_id
+    }
+
+    /// Turn a response into an authoritative negative answer for `name`
+    fn negative(&self, response: &mut DnsResponse, zone: &Zone, name: &str) {
+        if !self.name_exists(name) && name != zone.origin {
+            response.response_code = DnsResponseCode::NxDomain;
+        }
+        let mut soa = zone.soa.clone();
+        soa.ttl = zone.negative_ttl;
+        response.authority.push(soa);
+    }
+
+    /// Answer a query from local records and zones
+    ///
+    /// Returns `None` when the query should be forwarded: the name has no
+    /// matching records and is outside every zone, or it is delegated away
+    /// by an NS record inside one.
+    pub fn answer(&self, query: &DnsQuery) -> Option<LocalAnswer> {
+        let name = query.name.trim_end_matches('.').to_lowercase();
+        let zone = self.zone_for(&name);
+
+        let mut response = DnsResponse::new(query.id);
+        response.authoritative = zone.is_some();
+
+        // The apex SOA and NS set come from the zone itself
+        if let Some(zone) = zone.filter(|z| z.origin == name) {
+            match query.record_type {
+                RecordType::SOA => {
+                    response.add_answer(zone.soa.clone());
+                    return Some(LocalAnswer { response, record_id: None, cname_target: None });
+                }
+                RecordType::NS => response.answers.extend(zone.nameservers.iter().cloned()),
+                _ => {}
+            }
+        }
+
+        let record_id = self.add_records(&mut response, &query.name, query.record_type);
+        if !response.answers.is_empty() {
+            return Some(LocalAnswer { response, record_id, cname_target: None });
+        }
+
+        // Explicit PTR and CNAME records, and delegations, win over synthesis
+        if query.record_type == RecordType::PTR
+            && self.lookup(&name, RecordType::CNAME).is_none()
+            && !zone.is_some_and(|z| self.is_delegated(&name, z))
+        {
+            if let Some(names) = self.ptr_names.lookup(&name) {
+                for (target, ttl) in names {
+                    response.add_answer(DnsRecordData::ptr(query.name.clone(), target.clone(), *ttl));
+                }
+                return Some(LocalAnswer { response, record_id: None, cname_target: None });
+            }
+        }
+
+        let zone = zone?;
+        if self.is_delegated(&name, zone) {
+            return None;
+        }
+
+        // A CNAME answers every other type; follow it through the local data
+        let mut current = name.clone();
+        let mut owner = query.name.clone();
+        let mut first_id = None;
+        if query.record_type != RecordType::CNAME {
+            for _ in 0..MAX_CNAME_CHAIN {
+                let Some(cname) = self.lookup(&current, RecordType::CNAME).and_then(|r| r.first()) else {
+                    break;
+                };
+                let Some(data) = record_data(cname, &owner, RecordType::CNAME) else {
+                    break;
+                };
+                first_id = first_id.or(row_id(cname));
+                let target = data.value.trim_end_matches('.').to_lowercase();
+                response.add_answer(data);
+
+                if self.add_records(&mut response, &target, query.record_type).is_some() {
+                    return Some(LocalAnswer { response, record_id: first_id, cname_target: None });
+                }
+                if self.lookup(&target, RecordType::CNAME).is_none() {
+                    return match self.zone_for(&target) {
+                        Some(target_zone) => {
+                            self.negative(&mut response, target_zone, &target);
+                            Some(LocalAnswer { response, record_id: first_id, cname_target: None })
+                        }
+                        None if self.name_exists(&target) => Some(LocalAnswer { response, record_id: first_id, cname_target: None }),
+                        None => Some(LocalAnswer { response, record_id: first_id, cname_target: Some(target) }),
+                    };
+                }
+                owner = target.clone();
+                current = target;
+            }
+        }
+        if !response.answers.is_empty() {
+            // The chain loops or is too long; return what was followed
+            return Some(LocalAnswer { response, record_id: first_id, cname_target: None });
+        }
+
+        self.negative(&mut response, zone, &name);
+        Some(LocalAnswer { response, record_id: None, cname_target: None })
+    }
+}
+
+/// Convert a stored record into an answer of the given type
+pub(super) fn record_data(record: &DnsRecord, name: &str, record_type: RecordType) -> Option<DnsRecordData> {
+    let ttl = record.ttl.max(0) as u32;
+    match record_type {
+        RecordType::A => match Ipv4Addr::from_str(&record.value) {
+            Ok(ip) => Some(DnsRecordData::a(name, ip, ttl)),
+            Err(_) => {
+                debug!("Invalid IPv4 address in DNS record: {}", record.value);
+                None
+            }
+        },
+        RecordType::AAAA => match Ipv6Addr::from_str(&record.value) {
+            Ok(ip) => Some(DnsRecordData::aaaa(name, ip, ttl)),
+            Err(_) => {
+                debug!("Invalid IPv6 address in DNS record: {}", record.value);
+                None
+            }
+        },
+        RecordType::CNAME => Some(DnsRecordData::cname(name, &record.value, ttl)),
+        RecordType::MX => Some(DnsRecordData::mx(name, &record.value, record.priority as u16, ttl)),
+        RecordType::TXT => Some(DnsRecordData::txt(name, &record.value, ttl)),
+        RecordType::PTR => Some(DnsRecordData::ptr(name, &record.value, ttl)),
+        RecordType::NS => Some(DnsRecordData::ns(name, &record.value, ttl)),
+        RecordType::SOA => Some(DnsRecordData {
+            name: name.to_string(),
+            record_type,
+            value: record.value.clone(),
+            ttl,
+            priority: None,
+        }),
+        RecordType::SRV => {
+            // Stored as "priority weight port target", or without the priority
+            let parts: Vec<&str> = record.value.split_whitespace().collect();
+            let (priority, rest) = match parts.len() {
+                4 => (parts[0].parse().ok()?, &parts[1..]),
+                3 => (record.priority as u16, &parts[..]),
+                _ => {
+                    debug!("Invalid SRV value in DNS record: {}", record.value);
+                    return None;
+                }
+            };
+            Some(DnsRecordData {
+                name: name.to_string(),
+                record_type,
+                value: rest.join(" "),
+                ttl,
+                priority: Some(priority),
+            })
+        }
+    }
+}
+
+/// Take the SOA, apex NS set and records of a secondary zone from its last transfer
+///
+/// Returns `false` when there is no usable transfer yet.
+fn apply_transfer(zone: &mut DnsZone, records: &mut Vec<DnsRecord>) -> bool {
+    let Some(content) = zone.content.as_deref() else {
+        return false;
+    };
+    let data: Vec<DnsRecordData> = match serde_json::from_str(content) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Invalid transferred data for zone {}: {}", zone.origin, e);
+            return false;
+        }
+    };
+
+    let now = Utc::now();
+    let mut nameservers = Vec::new();
+    let mut has_soa = false;
+    for record in data {
+        let name = record.name.trim_end_matches('.').to_lowercase();
+        let apex = name == zone.origin;
+        match record.record_type {
+            RecordType::SOA if apex => {
+                let parts: Vec<&str> = record.value.split_whitespace().collect();
+                let [mname, rname, serial, refresh, retry, expire, minimum] = parts[..] else {
+                    continue;
+                };
+                zone.primary_ns = mname.to_string();
+                zone.admin_email = rname.to_string();
+                zone.serial = serial.parse().unwrap_or(zone.serial);
+                zone.refresh = refresh.parse().unwrap_or(zone.refresh);
+                zone.retry = retry.parse().unwrap_or(zone.retry);
+                zone.expire = expire.parse().unwrap_or(zone.expire);
+                zone.minimum = minimum.parse().unwrap_or(zone.minimum);
+                zone.ttl = record.ttl as i32;
+                has_soa = true;
+            }
+            RecordType::SOA => {}
+            RecordType::NS if apex => nameservers.push(record.value),
+            record_type => records.push(DnsRecord {
+                id: 0,
+                name,
+                record_type: record_type.to_string(),
+                value: record.value,
+                ttl: record.ttl as i32,
+                priority: record.priority.unwrap_or(0) as i32,
+                enabled: true,
+                managed: false,
+                source: None,
+                created_at: now,
+                updated_at: now,
+            }),
+        }
+    }
+    zone.nameservers = nameservers.join(",");
+    has_soa
+}
+
+/// Bump the serial of primary zones whose served data changed since it was set
+///
+/// The fingerprint covers the zone settings and everything the snapshot
+/// serves for it. A zone seen for the first time keeps the serial it was
+/// created with. Each bump is journaled with the records removed and added
+/// so secondaries can catch up incrementally.
+async fn refresh_serials(db: &Database, zones: &mut [DnsZone], snapshot: &LocalSnapshot) -> Result<Vec<ZoneChange>> {
+    let mut changes = Vec::new();
+    for zone in zones.iter_mut().filter(|z| !z.is_secondary()) {
+        let records = snapshot
+            .zones
+            .iter()
+            .find(|z| z.origin == zone.origin)
+            .map(|z| z.records.as_slice())
+            .unwrap_or_default();
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!(
+            "{} {} {} {} {} {} {} {} {}\n",
+            zone.origin, zone.primary_ns, zone.admin_email, zone.refresh, zone.retry,
+            zone.expire, zone.minimum, zone.ttl, zone.nameservers
+        ));
+        for record in records {
+            hasher.update(record_key(record).as_bytes());
+            hasher.update(b"\n");
+        }
+        let hash = format!("{:x}", hasher.finalize());
+        let changed = zone.content_hash.as_deref().is_some_and(|old| old != hash);
+        if !changed && zone.content_hash.is_some() && zone.content.is_some() {
+            continue;
+        }
+
+        let content = serde_json::to_string(records)?;
+        if !changed {
+            db.dns_zones().set_serial(zone.id, zone.serial, &hash, &content).await?;
+        } else {
+            let serial_from = zone.serial;
+            zone.serial = next_zone_serial(zone.serial, Utc::now());
+            debug!("Zone {} changed, serial is now {}", zone.origin, zone.serial);
+
+            let previous = zone
+                .content
+                .as_deref()
+                .and_then(|c| serde_json::from_str::<Vec<DnsRecordData>>(c).ok());
+            match previous {
+                Some(previous) => {
+                    let old_keys: HashSet<String> = previous.iter().map(record_key).collect();
+                    let new_keys: HashSet<String> = records.iter().map(record_key).collect();
+                    let removed: Vec<&DnsRecordData> = previous.iter().filter(|r| !new_keys.contains(&record_key(r))).collect();
+                    let added: Vec<&DnsRecordData> = records.iter().filter(|r| !old_keys.contains(&record_key(r))).collect();
+                    let change = CreateZoneJournalEntry {
+                        serial_from,
+                        serial_to: zone.serial,
+                        removed: serde_json::to_string(&removed)?,
+                        added: serde_json::to_string(&added)?,
+                    };
+                    db.dns_zones().record_change(zone.id, &hash, &content, change).await?;
+                }
+                // Nothing to diff against; secondaries fall back to a full transfer
+                None => db.dns_zones().set_serial(zone.id, zone.serial, &hash, &content).await?,
+            }
+            changes.push(ZoneChange {
+                origin: zone.origin.clone(),
+                serial: zone.serial,
+                notify: zone.notify_list().into_iter().map(str::to_string).collect(),
+            });
+        }
+        zone.content_hash = Some(hash);
+        zone.content = Some(content);
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn record(id: i64, name: &str, record_type: &str, value: &str, enabled: bool) -> DnsRecord {
+        DnsRecord {
+            id,
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            value: value.to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled,
+            managed: false,
+            source: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn values(snapshot: &LocalSnapshot, name: &str, record_type: RecordType) -> Vec<String> {
+        snapshot
+            .lookup(name, record_type)
+            .map(|records| records.iter().map(|r| r.value.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_exact_match_beats_wildcards() {
+        let snapshot = LocalSnapshot::new(vec![
+            record(1, "*.example.com", "A", "10.0.0.1", true),
+            record(2, "*.b.example.com", "A", "10.0.0.2", true),
+            record(3, "a.b.example.com", "A", "10.0.0.3", true),
+        ], &[]);
+
+        assert_eq!(values(&snapshot, "a.b.example.com", RecordType::A), vec!["10.0.0.3"]);
+        assert_eq!(values(&snapshot, "c.b.example.com", RecordType::A), vec!["10.0.0.2"]);
+        assert_eq!(values(&snapshot, "x.y.example.com", RecordType::A), vec!["10.0.0.1"]);
+        assert!(snapshot.lookup("example.com", RecordType::A).is_none());
+        assert!(snapshot.lookup("a.b.example.com", RecordType::AAAA).is_none());
+    }
+
+    #[test]
+    fn test_disabled_records_and_case() {
+        let snapshot = LocalSnapshot::new(vec![
+            record(1, "Host.Lan", "a", "192.168.1.10", true),
+            record(2, "host.lan", "A", "192.168.1.11", false),
+        ], &["aaaa".to_string()]);
+
+        assert_eq!(snapshot.record_count(), 1);
+        assert_eq!(values(&snapshot, "HOST.lan", RecordType::A), vec!["192.168.1.10"]);
+        assert!(snapshot.is_type_disabled(RecordType::AAAA));
+        assert!(!snapshot.is_type_disabled(RecordType::A));
+    }
+
+    #[test]
+    fn test_wildcard_answer_uses_query_name() {
+        let snapshot = LocalSnapshot::new(vec![
+            record(1, "*.lan", "A", "192.168.1.1", true),
+            record(2, "*.lan", "A", "not-an-ip", true),
+        ], &[]);
+
+        let answer = snapshot.answer(&DnsQuery::new("nas.lan", RecordType::A)).unwrap();
+        assert_eq!(answer.record_id, Some(1));
+        assert!(!answer.response.authoritative);
+        assert_eq!(answer.response.answers.len(), 1);
+        assert_eq!(answer.response.answers[0].name, "nas.lan");
+        assert_eq!(answer.response.answers[0].value, "192.168.1.1");
+    }
+
+    fn zone(id: i64, origin: &str) -> DnsZone {
+        DnsZone {
+            id,
+            origin: origin.to_string(),
+            primary_ns: format!("ns1.{}", origin),
+            admin_email: format!("hostmaster.{}", origin),
+            serial: 2026010101,
+            refresh: 3600,
+            retry: 600,
+            expire: 604800,
+            minimum: 300,
+            ttl: 3600,
+            nameservers: format!("ns1.{0}, ns2.{0}", origin),
+            enabled: true,
+            kind: "primary".to_string(),
+            primaries: String::new(),
+            transfer_acl: "10.0.0.0/8".to_string(),
+            notify: String::new(),
+            managed: false,
+            content_hash: None,
+            content: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_zone_answers_are_authoritative() {
+        let snapshot = LocalSnapshot::new(vec![
+            record(1, "www.example.com", "A", "10.0.0.1", true),
+            record(2, "host.b.example.com", "A", "10.0.0.2", true),
+            record(3, "alias.example.com", "CNAME", "www.example.com", true),
+            record(4, "cdn.example.com", "CNAME", "edge.cdn.net", true),
+            record(5, "sub.example.com", "NS", "ns.elsewhere.net", true),
+            record(6, "_sip._tcp.example.com", "SRV", "10 60 5060 sip.example.com", true),
+        ], &[]).with_zones(&[zone(1, "example.com")]);
+        let ask = |name: &str, record_type| snapshot.answer(&DnsQuery::new(name, record_type));
+
+        let answer = ask("WWW.example.com", RecordType::A).unwrap();
+        assert!(answer.response.authoritative);
+        assert_eq!(answer.record_id, Some(1));
+        assert!(answer.response.authority.is_empty());
+
+        // NODATA: the name exists without the type
+        let answer = ask("www.example.com", RecordType::AAAA).unwrap();
+        assert_eq!(answer.response.response_code, DnsResponseCode::NoError);
+        assert!(answer.response.answers.is_empty());
+        assert_eq!(answer.response.authority[0].record_type, RecordType::SOA);
+        assert_eq!(answer.response.authority[0].ttl, 300);
+        assert_eq!(answer.record_id, None);
+
+        // Empty non-terminals exist too
+        let answer = ask("b.example.com", RecordType::A).unwrap();
+        assert_eq!(answer.response.response_code, DnsResponseCode::NoError);
+
+        let answer = ask("missing.example.com", RecordType::A).unwrap();
+        assert!(answer.response.authoritative);
+        assert_eq!(answer.response.response_code, DnsResponseCode::NxDomain);
+        assert_eq!(
+            answer.response.authority[0].value,
+            "ns1.example.com hostmaster.example.com 2026010101 3600 600 604800 300"
+        );
+
+        let answer = ask("example.com", RecordType::NS).unwrap();
+        let values: Vec<&str> = answer.response.answers.iter().map(|a| a.value.as_str()).collect();
+        assert_eq!(values, vec!["ns1.example.com", "ns2.example.com"]);
+        let answer = ask("example.com", RecordType::SOA).unwrap();
+        assert_eq!(answer.response.answers[0].ttl, 3600);
+
+        // CNAMEs are followed locally, or handed back when they leave the local data
+        let answer = ask("alias.example.com", RecordType::A).unwrap();
+        let types: Vec<RecordType> = answer.response.answers.iter().map(|a| a.record_type).collect();
+        assert_eq!(types, vec![RecordType::CNAME, RecordType::A]);
+        assert_eq!(answer.record_id, Some(3));
+        let answer = ask("cdn.example.com", RecordType::A).unwrap();
+        assert_eq!(answer.cname_target.as_deref(), Some("edge.cdn.net"));
+
+        let answer = ask("_sip._tcp.example.com", RecordType::SRV).unwrap();
+        assert_eq!(answer.response.answers[0].priority, Some(10));
+        assert_eq!(answer.response.answers[0].value, "60 5060 sip.example.com");
+
+        // Delegated names and names outside every zone are forwarded
+        assert!(ask("host.sub.example.com", RecordType::A).is_none());
+        assert!(ask("www.example.org", RecordType::A).is_none());
+
+        // Transfers carry the apex NS set and every record, and follow the ACL
+        let transfer = snapshot.zone_transfer("Example.com.", "10.1.2.3".parse().unwrap()).unwrap();
+        assert!(transfer.allowed);
+        assert_eq!(transfer.records.len(), 8);
+        assert!(transfer.records.iter().all(|r| r.record_type != RecordType::SOA));
+        assert!(!snapshot.zone_transfer("example.com", "192.168.1.1".parse().unwrap()).unwrap().allowed);
+        assert!(snapshot.zone_transfer("b.example.com", "10.1.2.3".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_synthesized_ptr_answers() {
+        let records = vec![
+            record(1, "nas.lan", "A", "192.168.1.10", true),
+            record(2, "printer.lan", "A", "192.168.1.20", true),
+            record(3, "20.1.168.192.in-addr.arpa", "PTR", "office-printer.lan", true),
+            record(4, "30.1.168.192.in-addr.arpa", "CNAME", "30.0-63.1.168.192.in-addr.arpa", true),
+            record(5, "tv.lan", "A", "192.168.1.30", true),
+            record(6, "phone.lan", "A", "192.168.1.40", true),
+        ];
+        let ask = |snapshot: &LocalSnapshot, name: &str| snapshot.answer(&DnsQuery::new(name, RecordType::PTR));
+
+        let snapshot = LocalSnapshot::new(records.clone(), &[]);
+        assert!(ask(&snapshot, "10.1.168.192.in-addr.arpa").is_none());
+
+        let config = PtrSynthesisConfig { enabled: true, ..Default::default() };
+        let snapshot = LocalSnapshot::new(records, &[])
+            .with_zones(&[zone(1, "1.168.192.in-addr.arpa")])
+            .with_ptr_synthesis(&config, &[]);
+        let answer = ask(&snapshot, "10.1.168.192.in-addr.arpa").unwrap();
+        assert!(answer.response.authoritative);
+        assert_eq!(answer.record_id, None);
+        assert_eq!(answer.response.answers[0].name, "10.1.168.192.in-addr.arpa");
+        assert_eq!(answer.response.answers[0].value, "nas.lan");
+        assert_eq!(answer.response.answers[0].ttl, 300);
+
+        // Explicit PTR and CNAME records take precedence
+        let answer = ask(&snapshot, "20.1.168.192.in-addr.arpa").unwrap();
+        assert_eq!(answer.record_id, Some(3));
+        assert_eq!(answer.response.answers[0].value, "office-printer.lan");
+        let answer = ask(&snapshot, "30.1.168.192.in-addr.arpa").unwrap();
+        assert_eq!(answer.response.answers[0].record_type, RecordType::CNAME);
+
+        // Addresses without a name still get the zone's negative answer
+        let answer = ask(&snapshot, "99.1.168.192.in-addr.arpa").unwrap();
+        assert_eq!(answer.response.response_code, DnsResponseCode::NxDomain);
+        let answer = snapshot.answer(&DnsQuery::new("40.1.168.192.in-addr.arpa", RecordType::A)).unwrap();
+        assert!(answer.response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_secondary_zone_served_from_transfer() {
+        let mut secondary = zone(2, "example.net");
+        secondary.kind = "secondary".to_string();
+        assert!(!apply_transfer(&mut secondary.clone(), &mut Vec::new()));
+
+        secondary.content = Some(serde_json::to_string(&vec![
+            DnsRecordData {
+                name: "example.net".to_string(),
+                record_type: RecordType::SOA,
+                value: "ns.primary.net admin.example.net 42 1200 300 86400 60".to_string(),
+                ttl: 600,
+                priority: None,
+            },
+            DnsRecordData::ns("example.net", "ns.primary.net", 600),
+            DnsRecordData::a("www.example.net", Ipv4Addr::new(10, 0, 0, 9), 120),
+            DnsRecordData::mx("example.net", "mail.example.net", 5, 120),
+        ]).unwrap());
+        let mut records = Vec::new();
+        assert!(apply_transfer(&mut secondary, &mut records));
+        assert_eq!(secondary.serial, 42);
+        assert_eq!(secondary.nameservers, "ns.primary.net");
+        assert_eq!(records.len(), 2);
+
+        let snapshot = LocalSnapshot::new(records, &[]).with_zones(&[secondary]);
+        let answer = snapshot.answer(&DnsQuery::new("www.example.net", RecordType::A)).unwrap();
+        assert!(answer.response.authoritative);
+        assert_eq!(answer.record_id, None);
+        assert_eq!(answer.response.answers[0].value, "10.0.0.9");
+        let answer = snapshot.answer(&DnsQuery::new("example.net", RecordType::MX)).unwrap();
+        assert_eq!(answer.response.answers[0].priority, Some(5));
+        let answer = snapshot.answer(&DnsQuery::new("nope.example.net", RecordType::A)).unwrap();
+        assert_eq!(answer.response.response_code, DnsResponseCode::NxDomain);
+        assert_eq!(answer.response.authority[0].ttl, 60);
+    }
+
+    #[tokio::test]
+    async fn test_zone_serial_follows_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        let created = db.dns_zones().create(crate::db::CreateDnsZone::new(
+            "example.com".to_string(),
+            "ns1.example.com".to_string(),
+            "hostmaster.example.com".to_string(),
+        )).await.unwrap();
+
+        let serial = |snapshot: &LocalSnapshot| {
+            let answer = snapshot.answer(&DnsQuery::new("example.com", RecordType::SOA)).unwrap();
+            answer.response.answers[0].value.split(' ').nth(2).unwrap().parse::<i64>().unwrap()
+        };
+
+        // First load only records the fingerprint
+        assert_eq!(serial(&LocalSnapshot::load(&db).await.unwrap()), created.serial);
+        assert_eq!(serial(&LocalSnapshot::load(&db).await.unwrap()), created.serial);
+
+        db.dns_records().batch_create(vec![crate::db::CreateDnsRecord {
+            name: "www.example.com".to_string(),
+            record_type: "A".to_string(),
+            value: "10.0.0.1".to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled: true,
+        }]).await.unwrap();
+        let snapshot = LocalSnapshot::load(&db).await.unwrap();
+        assert_eq!(serial(&snapshot), created.serial + 1);
+        assert_eq!(snapshot.zone_changes().len(), 1);
+
+        // The step is journaled for IXFR
+        let journal = db.dns_zones().journal(created.id).await.unwrap();
+        assert_eq!(journal.len(), 1);
+        assert_eq!((journal[0].serial_from, journal[0].serial_to), (created.serial, created.serial + 1));
+        assert!(journal[0].added.contains("www.example.com"));
+        assert_eq!(journal[0].removed, "[]");
+
+        // Records outside the zone leave the serial alone
+        db.dns_records().batch_create(vec![crate::db::CreateDnsRecord {
+            name: "www.example.org".to_string(),
+            record_type: "A".to_string(),
+            value: "10.0.0.2".to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled: true,
+        }]).await.unwrap();
+        assert_eq!(serial(&LocalSnapshot::load(&db).await.unwrap()), created.serial + 1);
+    }
+}
@@ -28,6 +28,9 @@ pub enum DnsError {
     #[error("Invalid domain name: {0}")]
     InvalidDomainName(String),
 
+    #[error("Unsupported opcode: {0}")]
+    UnsupportedOpCode(String),
+
     #[error("Query failed: {0}")]
     #[allow(dead_code)]
     QueryFailed(String),
@@ -171,6 +174,10 @@ impl DnsQuery {
     pub fn from_bytes(data: &[u8]) -> Result<Self, DnsError> {
         let message = Message::from_bytes(data)
             .map_err(|e| DnsError::ParseError(e.to_string()))?;
+        // NOTIFY and UPDATE carry a question too, but are not lookups
+        if message.op_code() != OpCode::Query {
+            return Err(DnsError::UnsupportedOpCode(format!("{:?}", message.op_code())));
+        }
 
         let query = message
             .queries()
@@ -235,6 +242,16 @@ pub struct DnsRecordData {
 }
 
 impl DnsRecordData {
+    /// Convert to a hickory-proto record, if the value is valid for its type
+    pub fn to_record(&self) -> Option<Record> {
+        data_to_record(self)
+    }
+
+    /// Convert a hickory-proto record of a supported type
+    pub fn from_record(record: &Record) -> Option<Self> {
+        record_to_data(record)
+    }
+
     /// Create a new A record
     pub fn a(name: impl Into<String>, ip: Ipv4Addr, ttl: u32) -> Self {
         Self {
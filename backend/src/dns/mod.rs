@@ -3,14 +3,35 @@
 //! Contains DNS server implementations and related functionality.
 
 mod cache;
+mod coalesce;
+mod dnstap;
+mod filter;
+mod hosts;
+mod local;
 mod message;
+mod privacy;
+mod ptr;
 pub mod proxy;
 mod resolver;
 mod rewrite;
 pub mod server;
+mod transfer;
+mod tsig;
+mod update;
+mod zonefile;
 
 pub use cache::*;
+pub use coalesce::*;
+pub use dnstap::*;
+pub use filter::*;
+pub use hosts::*;
 pub use message::*;
+pub use privacy::*;
+pub use ptr::*;
 pub use proxy::*;
 pub use resolver::*;
 pub use rewrite::*;
+pub use transfer::*;
+pub use tsig::*;
+pub use update::*;
+pub use zonefile::*;
@@ -0,0 +1,391 @@
+//! Query Log Privacy
+//!
+//! Applied to every answered query before it is logged anywhere: the query
+//! log table, the log sinks, the live stream, the statistics rollups and the
+//! resolver's own log lines. Exports and the AI assistant functions apply it
+//! again on read, so rows stored before a change are covered too. dnstap
+//! client frames get the client IP mode and skip unlogged clients, but carry
+//! the DNS messages, and so the query names, unchanged.
+//! - Client IPs kept as is, truncated to a network prefix, or replaced by a
+//!   keyed hash whose key is random and changes every UTC day
+//! - Query names kept as is or reduced to their registrable domain
+//! - Client groups whose queries only count towards aggregate statistics
+//! - Raw query logs switched off entirely while aggregates keep being collected
+
+use std::borrow::Cow;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::db::{CreateQueryLog, QueryLog};
+use super::proxy::IpRangeSet;
+
+/// Public suffixes with more than one label that are common enough to
+/// matter; every other name keeps its last two labels
+const MULTI_LABEL_SUFFIXES: &[&str] = &[
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "me.uk", "net.uk", "ltd.uk", "plc.uk",
+    "com.cn", "net.cn", "org.cn", "gov.cn", "edu.cn", "ac.cn",
+    "com.hk", "net.hk", "org.hk", "com.tw", "net.tw", "org.tw", "com.sg", "com.my",
+    "co.jp", "ne.jp", "or.jp", "ac.jp", "go.jp", "co.kr", "or.kr", "ac.kr",
+    "com.au", "net.au", "org.au", "edu.au", "gov.au", "co.nz", "org.nz", "net.nz",
+    "com.br", "net.br", "org.br", "gov.br", "com.ar", "com.mx", "com.co", "com.tr",
+    "co.in", "net.in", "org.in", "co.id", "co.th", "in.th", "com.vn", "com.ph",
+    "co.za", "org.za", "co.il", "com.ua", "com.pl", "com.ru", "com.es", "co.at", "or.at",
+    "github.io", "gitlab.io", "pages.dev", "workers.dev", "netlify.app", "vercel.app",
+    "herokuapp.com", "azurewebsites.net", "cloudfront.net", "blogspot.com",
+    "in-addr.arpa", "ip6.arpa", "home.arpa",
+];
+
+/// How client IPs are logged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientIpMode {
+    #[default]
+    Full,
+    /// Keep only the network prefix (`ipv4_prefix` / `ipv6_prefix`)
+    Truncate,
+    /// Keyed hash; the key is random and replaced every UTC day
+    Hash,
+}
+
+/// How query names are logged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainMode {
+    #[default]
+    Full,
+    /// Only the registrable domain (`www.news.example.co.uk` -> `example.co.uk`)
+    Registrable,
+}
+
+/// Clients whose queries are never logged individually
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacyClientGroup {
+    pub name: String,
+    /// IPs or CIDRs
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+/// Query log privacy settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryPrivacyConfig {
+    pub client_ip: ClientIpMode,
+    /// Prefix kept of IPv4 clients when truncating
+    pub ipv4_prefix: u8,
+    /// Prefix kept of IPv6 clients when truncating
+    pub ipv6_prefix: u8,
+    pub domain: DomainMode,
+    /// Client groups only counted in aggregate statistics
+    pub unlogged_groups: Vec<PrivacyClientGroup>,
+    /// Keep per-query logs; when off only aggregate statistics are collected
+    pub raw_logs: bool,
+}
+
+impl Default for QueryPrivacyConfig {
+    fn default() -> Self {
+        Self {
+            client_ip: ClientIpMode::Full,
+            ipv4_prefix: 24,
+            ipv6_prefix: 48,
+            domain: DomainMode::Full,
+            unlogged_groups: Vec::new(),
+            raw_logs: true,
+        }
+    }
+}
+
+/// Compiled privacy settings
+pub struct QueryPrivacy {
+    config: QueryPrivacyConfig,
+    unlogged: IpRangeSet,
+    /// Hash key and the day it belongs to
+    hash_key: Mutex<Option<(NaiveDate, [u8; 32])>>,
+}
+
+impl Default for QueryPrivacy {
+    fn default() -> Self {
+        Self {
+            config: QueryPrivacyConfig::default(),
+            unlogged: IpRangeSet::default(),
+            hash_key: Mutex::new(None),
+        }
+    }
+}
+
+impl QueryPrivacy {
+    /// Compile the settings, rejecting bad prefixes and CIDRs
+    pub fn from_config(config: &QueryPrivacyConfig) -> Result<Self> {
+        if !(8..=32).contains(&config.ipv4_prefix) {
+            return Err(anyhow!("ipv4_prefix must be between 8 and 32"));
+        }
+        if !(16..=128).contains(&config.ipv6_prefix) {
+            return Err(anyhow!("ipv6_prefix must be between 16 and 128"));
+        }
+        let mut clients = Vec::new();
+        for group in &config.unlogged_groups {
+            if group.name.trim().is_empty() {
+                return Err(anyhow!("Client group name must not be empty"));
+            }
+            clients.extend(group.clients.iter().map(String::as_str));
+        }
+        let unlogged = IpRangeSet::parse(clients)?;
+        Ok(Self {
+            config: config.clone(),
+            unlogged,
+            hash_key: Mutex::new(None),
+        })
+    }
+
+    /// Settings the privacy was compiled from
+    pub fn config(&self) -> &QueryPrivacyConfig {
+        &self.config
+    }
+
+    /// Whether queries of this client may be logged individually
+    pub fn logs_client(&self, client_ip: &str) -> bool {
+        if !self.config.raw_logs {
+            return false;
+        }
+        match client_ip.parse::<IpAddr>() {
+            Ok(ip) => !self.unlogged.contains(ip),
+            Err(_) => true,
+        }
+    }
+
+    /// Client IP as it may be logged
+    ///
+    /// Values that are not IPs (e.g. a missing DoH client address) are kept.
+    pub fn client_ip<'a>(&self, client_ip: &'a str) -> Cow<'a, str> {
+        let Ok(ip) = client_ip.parse::<IpAddr>() else {
+            return Cow::Borrowed(client_ip);
+        };
+        match self.config.client_ip {
+            ClientIpMode::Full => Cow::Borrowed(client_ip),
+            ClientIpMode::Truncate => Cow::Owned(self.truncate(ip)),
+            ClientIpMode::Hash => Cow::Owned(self.hash(ip)),
+        }
+    }
+
+    /// Client address as it may be exported in dnstap frames
+    ///
+    /// Truncated clients keep only their network address (port 0); hashed
+    /// ones are left out, as dnstap frames only carry real addresses.
+    pub fn client_addr(&self, client: SocketAddr) -> Option<SocketAddr> {
+        match self.config.client_ip {
+            ClientIpMode::Full => Some(client),
+            ClientIpMode::Truncate => Some(SocketAddr::new(self.network(client.ip()).0, 0)),
+            ClientIpMode::Hash => None,
+        }
+    }
+
+    /// Query name as it may be logged
+    pub fn domain<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self.config.domain {
+            DomainMode::Full => Cow::Borrowed(name),
+            DomainMode::Registrable => match registrable_domain(name) {
+                reduced if reduced.len() == name.len() => Cow::Borrowed(name),
+                reduced => Cow::Owned(reduced.to_string()),
+            },
+        }
+    }
+
+    /// Anonymize a query log in place
+    ///
+    /// Answer names are reduced like the query name.
+    pub fn apply(&self, log: &mut CreateQueryLog) {
+        self.anonymize(&mut log.client_ip, &mut log.query_name, &mut log.answers);
+    }
+
+    /// Anonymize a stored query log, e.g. one written before privacy was enabled
+    ///
+    /// Values that are already anonymized stay unchanged.
+    pub fn apply_stored(&self, log: &mut QueryLog) {
+        self.anonymize(&mut log.client_ip, &mut log.query_name, &mut log.answers);
+    }
+
+    fn anonymize(&self, client_ip: &mut String, query_name: &mut String, answers: &mut Option<String>) {
+        if let Cow::Owned(ip) = self.client_ip(client_ip) {
+            *client_ip = ip;
+        }
+        if self.config.domain == DomainMode::Full {
+            return;
+        }
+        if let Cow::Owned(name) = self.domain(query_name) {
+            *query_name = name;
+        }
+        let parsed = answers.as_deref()
+            .and_then(|answers| serde_json::from_str::<Vec<serde_json::Value>>(answers).ok());
+        if let Some(mut parsed) = parsed {
+            for answer in &mut parsed {
+                if let Some(name) = answer.get("name").and_then(|n| n.as_str()).map(registrable_domain) {
+                    answer["name"] = serde_json::Value::String(name.to_string());
+                }
+            }
+            *answers = serde_json::to_string(&parsed).ok();
+        }
+    }
+
+    /// Strip everything after the configured prefix, e.g. `192.168.1.0/24`
+    fn truncate(&self, ip: IpAddr) -> String {
+        let (network, prefix) = self.network(ip);
+        format!("{}/{}", network, prefix)
+    }
+
+    /// Network address and prefix length of a client under the configured prefixes
+    fn network(&self, ip: IpAddr) -> (IpAddr, u8) {
+        match ip.to_canonical() {
+            IpAddr::V4(v4) => {
+                let prefix = self.config.ipv4_prefix;
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                (IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask)), prefix)
+            }
+            IpAddr::V6(v6) => {
+                let prefix = self.config.ipv6_prefix;
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                (IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask)), prefix)
+            }
+        }
+    }
+
+    /// HMAC-SHA256 of the address under today's key, shortened to 64 bits
+    ///
+    /// Keys only live in memory, so hashes of earlier days (and of earlier
+    /// runs) cannot be linked to addresses or to each other.
+    fn hash(&self, ip: IpAddr) -> String {
+        let today = Utc::now().date_naive();
+        let key = {
+            let mut current = self.hash_key.lock().unwrap();
+            match *current {
+                Some((day, key)) if day == today => key,
+                _ => {
+                    let mut key = [0u8; 32];
+                    rand::thread_rng().fill_bytes(&mut key);
+                    *current = Some((today, key));
+                    key
+                }
+            }
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts any key length");
+        mac.update(ip.to_canonical().to_string().as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("anon-{}", hex)
+    }
+}
+
+/// Registrable domain of a name (public suffix plus one label)
+///
+/// Uses the common multi-label suffixes above instead of the full Public
+/// Suffix List, so rare suffixes keep one label too few.
+pub fn registrable_domain(name: &str) -> &str {
+    let name = name.trim_end_matches('.');
+    let labels: Vec<&str> = name.split('.').collect();
+    if labels.len() <= 2 {
+        return name;
+    }
+    let last_two = &labels[labels.len() - 2..].join(".").to_ascii_lowercase();
+    let keep = if MULTI_LABEL_SUFFIXES.contains(&last_two.as_str()) { 3 } else { 2 };
+    if labels.len() <= keep {
+        return name;
+    }
+    let skipped: usize = labels[..labels.len() - keep].iter().map(|label| label.len() + 1).sum();
+    &name[skipped..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn privacy(config: QueryPrivacyConfig) -> QueryPrivacy {
+        QueryPrivacy::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        assert_eq!(registrable_domain("www.example.com"), "example.com");
+        assert_eq!(registrable_domain("a.b.example.com."), "example.com");
+        assert_eq!(registrable_domain("example.com"), "example.com");
+        assert_eq!(registrable_domain("news.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(registrable_domain("user.GitHub.io"), "user.GitHub.io");
+        assert_eq!(registrable_domain("10.1.168.192.in-addr.arpa"), "192.in-addr.arpa");
+        assert_eq!(registrable_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn test_client_ip_modes() {
+        let full = QueryPrivacy::default();
+        assert_eq!(full.client_ip("192.168.1.77"), "192.168.1.77");
+
+        let truncate = privacy(QueryPrivacyConfig { client_ip: ClientIpMode::Truncate, ..Default::default() });
+        assert_eq!(truncate.client_ip("192.168.1.77"), "192.168.1.0/24");
+        assert_eq!(truncate.client_ip("2001:db8:1:2::5"), "2001:db8:1::/48");
+        assert_eq!(truncate.client_ip("::ffff:10.0.0.9"), "10.0.0.0/24");
+        assert_eq!(truncate.client_ip("doh-client"), "doh-client");
+
+        let hash = privacy(QueryPrivacyConfig { client_ip: ClientIpMode::Hash, ..Default::default() });
+        let first = hash.client_ip("192.168.1.77").into_owned();
+        assert!(first.starts_with("anon-") && first.len() == 21);
+        assert_eq!(hash.client_ip("192.168.1.77"), first);
+        assert_ne!(hash.client_ip("192.168.1.78"), first);
+        // A different key (another day or run) gives unrelated hashes
+        let other = privacy(QueryPrivacyConfig { client_ip: ClientIpMode::Hash, ..Default::default() });
+        assert_ne!(other.client_ip("192.168.1.77"), first);
+    }
+
+    #[test]
+    fn test_dnstap_client_addr() {
+        let client: SocketAddr = "192.168.1.77:5353".parse().unwrap();
+        assert_eq!(QueryPrivacy::default().client_addr(client), Some(client));
+
+        let truncate = privacy(QueryPrivacyConfig { client_ip: ClientIpMode::Truncate, ..Default::default() });
+        assert_eq!(truncate.client_addr(client), Some("192.168.1.0:0".parse().unwrap()));
+        assert_eq!(truncate.client_addr("[2001:db8:1:2::5]:443".parse().unwrap()), Some("[2001:db8:1::]:0".parse().unwrap()));
+
+        let hash = privacy(QueryPrivacyConfig { client_ip: ClientIpMode::Hash, ..Default::default() });
+        assert_eq!(hash.client_addr(client), None);
+    }
+
+    #[test]
+    fn test_apply_and_groups() {
+        let policy = privacy(QueryPrivacyConfig {
+            client_ip: ClientIpMode::Truncate,
+            domain: DomainMode::Registrable,
+            unlogged_groups: vec![PrivacyClientGroup {
+                name: "staff".to_string(),
+                clients: vec!["10.1.0.0/16".to_string()],
+            }],
+            ..Default::default()
+        });
+        let mut log = CreateQueryLog {
+            client_ip: "192.168.1.77".to_string(),
+            query_name: "mail.example.com".to_string(),
+            answers: Some(r#"[{"name":"mail.example.com","type":"A","ttl":60,"value":"1.2.3.4"}]"#.to_string()),
+            ..Default::default()
+        };
+        policy.apply(&mut log);
+        assert_eq!(log.client_ip, "192.168.1.0/24");
+        assert_eq!(log.query_name, "example.com");
+        assert!(log.answers.unwrap().contains(r#""name":"example.com""#));
+
+        assert!(policy.logs_client("192.168.1.77"));
+        assert!(!policy.logs_client("10.1.2.3"));
+        let aggregate_only = privacy(QueryPrivacyConfig { raw_logs: false, ..Default::default() });
+        assert!(!aggregate_only.logs_client("192.168.1.77"));
+
+        let bad = QueryPrivacyConfig { ipv4_prefix: 40, ..Default::default() };
+        assert!(QueryPrivacy::from_config(&bad).is_err());
+        let bad = QueryPrivacyConfig {
+            unlogged_groups: vec![PrivacyClientGroup { name: "x".to_string(), clients: vec!["nope".to_string()] }],
+            ..Default::default()
+        };
+        assert!(QueryPrivacy::from_config(&bad).is_err());
+    }
+}
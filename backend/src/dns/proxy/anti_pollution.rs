@@ -0,0 +1,360 @@
+//! Anti-Pollution Routing
+//!
+//! Queries a "domestic" and a "trusted" upstream group together. The
+//! domestic answer is only accepted when every returned IP is local, as
+//! decided by a CIDR list and/or a MaxMind-format GeoIP database for the
+//! configured country. Otherwise the trusted answer is used. Domain lists
+//! can force a name to either group.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+
+use crate::dns::message::{DnsResponse, RecordType};
+use super::upstream::UpstreamGroup;
+
+/// Anti-pollution settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AntiPollutionConfig {
+    /// Whether anti-pollution routing is enabled
+    #[serde(default)]
+    pub enabled: bool,
+    /// ISO country code treated as domestic for GeoIP lookups
+    #[serde(default = "default_country")]
+    pub country: String,
+    /// Domestic CIDR ranges
+    #[serde(default)]
+    pub cidrs: Vec<String>,
+    /// File with one domestic CIDR per line (e.g. china_ip_list.txt)
+    #[serde(default)]
+    pub cidr_file: Option<String>,
+    /// MaxMind-format GeoIP database (GeoLite2-Country.mmdb)
+    #[serde(default)]
+    pub geoip_path: Option<String>,
+    /// Domains always resolved by the domestic group (suffix match)
+    #[serde(default)]
+    pub domestic_domains: Vec<String>,
+    /// Domains always resolved by the trusted group (suffix match)
+    #[serde(default)]
+    pub trusted_domains: Vec<String>,
+}
+
+fn default_country() -> String {
+    "CN".to_string()
+}
+
+impl Default for AntiPollutionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            country: default_country(),
+            cidrs: Vec::new(),
+            cidr_file: None,
+            geoip_path: None,
+            domestic_domains: Vec::new(),
+            trusted_domains: Vec::new(),
+        }
+    }
+}
+
+/// Sorted, merged set of IP ranges for fast membership checks
+#[derive(Debug, Clone, Default)]
+pub struct IpRangeSet {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl IpRangeSet {
+    /// Parse CIDR lines, ignoring blank lines and `#` comments
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut set = Self::default();
+        for line in lines {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            set.insert(line)?;
+        }
+        merge_ranges(&mut set.v4);
+        merge_ranges(&mut set.v6);
+        Ok(set)
+    }
+
+    /// Insert a CIDR (or a single address); call `parse` to keep the set merged
+    fn insert(&mut self, cidr: &str) -> Result<()> {
+        let (addr, prefix) = match cidr.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (cidr, None),
+        };
+        let addr: IpAddr = addr.trim().parse()
+            .map_err(|_| anyhow!("Invalid CIDR '{}': bad address", cidr))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix: u32 = match prefix {
+            Some(p) => p.trim().parse().ok().filter(|p| *p <= max_prefix)
+                .ok_or_else(|| anyhow!("Invalid CIDR '{}': bad prefix length", cidr))?,
+            None => max_prefix,
+        };
+
+        match addr {
+            IpAddr::V4(v4) => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                let start = u32::from(v4) & mask;
+                self.v4.push((start, start | !mask));
+            }
+            IpAddr::V6(v6) => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                let start = u128::from(v6) & mask;
+                self.v6.push((start, start | !mask));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether an address falls inside any range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match ip.to_canonical() {
+            IpAddr::V4(v4) => range_contains(&self.v4, u32::from(v4)),
+            IpAddr::V6(v6) => range_contains(&self.v6, u128::from(v6)),
+        }
+    }
+
+    /// Number of merged ranges
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    /// Whether the set has no ranges
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn merge_ranges<T: Ord + Copy>(ranges: &mut Vec<(T, T)>) {
+    ranges.sort_unstable();
+    let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+    for &(start, end) in ranges.iter() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    *ranges = merged;
+}
+
+fn range_contains<T: Ord + Copy>(ranges: &[(T, T)], value: T) -> bool {
+    let idx = ranges.partition_point(|r| r.0 <= value);
+    idx > 0 && ranges[idx - 1].1 >= value
+}
+
+/// Verdict on a domestic upstream answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomesticVerdict {
+    /// All returned IPs are domestic
+    Accept,
+    /// The answer carries no A/AAAA records to judge
+    NoAddresses,
+    /// At least one returned IP is not domestic
+    Foreign(IpAddr),
+}
+
+/// Compiled anti-pollution rules
+pub struct AntiPollution {
+    country: String,
+    ranges: IpRangeSet,
+    geoip: Option<Reader<Vec<u8>>>,
+    domestic_domains: HashSet<String>,
+    trusted_domains: HashSet<String>,
+}
+
+impl AntiPollution {
+    /// Compile the configuration, loading the CIDR file and GeoIP database
+    pub fn from_config(config: &AntiPollutionConfig) -> Result<Self> {
+        let country = config.country.trim().to_uppercase();
+
+        let file_contents = match config.cidr_file.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read CIDR file {}", path))?,
+            None => String::new(),
+        };
+        let ranges = IpRangeSet::parse(
+            config.cidrs.iter().map(String::as_str).chain(file_contents.lines()),
+        )?;
+
+        let geoip = match config.geoip_path.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+            Some(path) => {
+                if country.len() != 2 {
+                    return Err(anyhow!("Country must be a two-letter ISO code when using GeoIP"));
+                }
+                Some(Self::open_geoip(Path::new(path))?)
+            }
+            None => None,
+        };
+
+        if ranges.is_empty() && geoip.is_none() {
+            return Err(anyhow!("Anti-pollution needs a CIDR list or a GeoIP database"));
+        }
+
+        Ok(Self {
+            country,
+            ranges,
+            geoip,
+            domestic_domains: normalize_domains(&config.domestic_domains),
+            trusted_domains: normalize_domains(&config.trusted_domains),
+        })
+    }
+
+    fn open_geoip(path: &Path) -> Result<Reader<Vec<u8>>> {
+        Reader::open_readfile(path)
+            .map_err(|e| anyhow!("Failed to open GeoIP database {}: {}", path.display(), e))
+    }
+
+    /// Find a domain-list override; the most specific matching suffix wins
+    pub fn match_domain(&self, name: &str) -> Option<UpstreamGroup> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let mut suffix = name.as_str();
+        loop {
+            if self.domestic_domains.contains(suffix) {
+                return Some(UpstreamGroup::Domestic);
+            }
+            if self.trusted_domains.contains(suffix) {
+                return Some(UpstreamGroup::Trusted);
+            }
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => return None,
+            }
+        }
+    }
+
+    /// Check whether an address is domestic (CIDR list first, then GeoIP)
+    pub fn is_domestic_ip(&self, ip: IpAddr) -> bool {
+        if self.ranges.contains(ip) {
+            return true;
+        }
+        let Some(reader) = &self.geoip else {
+            return false;
+        };
+        match reader.lookup::<geoip2::Country>(ip.to_canonical()) {
+            Ok(record) => record.country
+                .or(record.registered_country)
+                .and_then(|c| c.iso_code)
+                .is_some_and(|code| code.eq_ignore_ascii_case(&self.country)),
+            Err(_) => false,
+        }
+    }
+
+    /// Judge a domestic upstream answer
+    pub fn judge(&self, response: &DnsResponse) -> DomesticVerdict {
+        let ips: Vec<IpAddr> = response.answers.iter()
+            .filter(|r| matches!(r.record_type, RecordType::A | RecordType::AAAA))
+            .filter_map(|r| r.value.parse().ok())
+            .collect();
+
+        if ips.is_empty() {
+            return DomesticVerdict::NoAddresses;
+        }
+        match ips.into_iter().find(|ip| !self.is_domestic_ip(*ip)) {
+            Some(ip) => DomesticVerdict::Foreign(ip),
+            None => DomesticVerdict::Accept,
+        }
+    }
+}
+
+fn normalize_domains(domains: &[String]) -> HashSet<String> {
+    domains.iter()
+        .map(|d| d.trim().trim_start_matches("*.").trim_matches('.').to_lowercase())
+        .filter(|d| !d.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::message::DnsRecordData;
+
+    fn config(cidrs: &[&str]) -> AntiPollutionConfig {
+        AntiPollutionConfig {
+            enabled: true,
+            cidrs: cidrs.iter().map(|s| s.to_string()).collect(),
+            domestic_domains: vec!["cn".to_string(), "*.qq.com".to_string()],
+            trusted_domains: vec!["google.cn".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ip_range_set() {
+        let set = IpRangeSet::parse([
+            "1.0.1.0/24", "1.0.2.0/23", "1.0.2.0/24 # overlap", "", "# comment",
+            "240e::/20", "8.8.8.8",
+        ]).unwrap();
+
+        assert!(set.contains("1.0.1.1".parse().unwrap()));
+        assert!(set.contains("1.0.3.255".parse().unwrap()));
+        assert!(!set.contains("1.0.4.0".parse().unwrap()));
+        assert!(set.contains("8.8.8.8".parse().unwrap()));
+        assert!(!set.contains("8.8.8.9".parse().unwrap()));
+        assert!(set.contains("240e:1::1".parse().unwrap()));
+        assert!(set.contains("::ffff:1.0.1.1".parse().unwrap()));
+        assert!(!set.contains("2001:db8::1".parse().unwrap()));
+        assert_eq!(set.len(), 4);
+
+        assert!(IpRangeSet::parse(["1.2.3.4/33"]).is_err());
+        assert!(IpRangeSet::parse(["not-an-ip/8"]).is_err());
+        assert!(IpRangeSet::parse(["0.0.0.0/0"]).unwrap().contains("9.9.9.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_from_config_requires_source() {
+        assert!(AntiPollution::from_config(&config(&[])).is_err());
+
+        let mut cfg = config(&[]);
+        cfg.geoip_path = Some("/nonexistent/GeoLite2-Country.mmdb".to_string());
+        assert!(AntiPollution::from_config(&cfg).is_err());
+
+        cfg.geoip_path = None;
+        cfg.cidr_file = Some("/nonexistent/china_ip_list.txt".to_string());
+        assert!(AntiPollution::from_config(&cfg).is_err());
+    }
+
+    #[test]
+    fn test_cidr_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"# list\n114.114.0.0/16\n").unwrap();
+
+        let mut cfg = config(&[]);
+        cfg.cidr_file = Some(file.path().to_string_lossy().to_string());
+        let ap = AntiPollution::from_config(&cfg).unwrap();
+        assert!(ap.is_domestic_ip("114.114.114.114".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_match_domain() {
+        let ap = AntiPollution::from_config(&config(&["1.0.0.0/8"])).unwrap();
+
+        assert_eq!(ap.match_domain("www.baidu.cn"), Some(UpstreamGroup::Domestic));
+        assert_eq!(ap.match_domain("im.QQ.com."), Some(UpstreamGroup::Domestic));
+        assert_eq!(ap.match_domain("qq.com"), Some(UpstreamGroup::Domestic));
+        assert_eq!(ap.match_domain("www.google.cn"), Some(UpstreamGroup::Trusted));
+        assert_eq!(ap.match_domain("example.com"), None);
+    }
+
+    #[test]
+    fn test_judge() {
+        let ap = AntiPollution::from_config(&config(&["1.0.0.0/8", "240e::/20"])).unwrap();
+
+        let mut response = DnsResponse::new(1);
+        assert_eq!(ap.judge(&response), DomesticVerdict::NoAddresses);
+
+        response.answers.push(DnsRecordData::a("a.example", "1.2.3.4".parse().unwrap(), 60));
+        response.answers.push(DnsRecordData::aaaa("a.example", "240e::1".parse().unwrap(), 60));
+        assert_eq!(ap.judge(&response), DomesticVerdict::Accept);
+
+        response.answers.push(DnsRecordData::a("a.example", "8.8.8.8".parse().unwrap(), 60));
+        assert_eq!(ap.judge(&response), DomesticVerdict::Foreign("8.8.8.8".parse().unwrap()));
+    }
+}
@@ -2,15 +2,16 @@
 //!
 //! Provides client implementations for querying upstream DNS servers
 //! using different protocols (UDP, DoT, DoH, DoQ).
+//! Every client honours the upstream's outbound settings (proxy and
+//! source binding), see [`OutboundConfig`].
 
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use tokio::net::UdpSocket;
-use tokio::sync::OnceCell;
 use tokio::time::timeout;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use h3::client::SendRequest;
@@ -19,8 +20,16 @@ use bytes::Bytes;
 
 type H3SendRequest = SendRequest<OpenStreams, Bytes>;
 
+use crate::dns::dnstap::{Dnstap, DnstapExchange, DnstapProtocol, DnstapRole};
 use crate::dns::message::{DnsQuery, DnsResponse};
-use super::upstream::{UpstreamServer, UpstreamProtocol};
+use hickory_proto::op::{Message, MessageType, OpCode, Query};
+use hickory_proto::rr::{Name, RecordType as TrustRecordType};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use super::outbound::{OutboundConfig, Socks5QuicSocket, Socks5UdpRelay};
+use super::dot::{DotConnectionManager, DotPoolConfig};
+use super::recursive::{randomize_case, RecursiveDnsClient};
+use super::upstream::{UpstreamManager, UpstreamServer, UpstreamProtocol};
 
 /// Parse an address string that may contain IPv6 in bracket notation.
 /// Supports formats:
@@ -28,7 +37,7 @@ use super::upstream::{UpstreamServer, UpstreamProtocol};
 /// - IPv6: "[2001:4860:4860::8888]:53" or "[::1]:853"
 /// - Hostname: "dns.google:853" or "dns.google"
 /// Returns (host, port) tuple where host has brackets stripped for IPv6
-fn parse_host_port(address: &str, default_port: u16) -> Result<(String, u16)> {
+pub(super) fn parse_host_port(address: &str, default_port: u16) -> Result<(String, u16)> {
     // Check for IPv6 in brackets: [::1]:port or [2001:db8::1]:port
     if address.starts_with('[') {
         if let Some(bracket_end) = address.find(']') {
@@ -63,114 +72,175 @@ fn parse_host_port(address: &str, default_port: u16) -> Result<(String, u16)> {
     Ok((address.to_string(), default_port))
 }
 
-/// Global QUIC endpoint cache for DoQ clients
+/// Global QUIC endpoint cache for DoQ and DoH3 clients
 /// Reusing endpoints significantly improves performance by avoiding
 /// repeated socket binding and configuration overhead.
 ///
 /// We use a pool of endpoints to distribute load and avoid contention.
 const ENDPOINT_POOL_SIZE: usize = 20;
 
-static DOQ_ENDPOINTS_V4: OnceCell<Vec<quinn::Endpoint>> = OnceCell::const_new();
-static DOQ_INDEX_V4: AtomicUsize = AtomicUsize::new(0);
+/// Endpoints behind a SOCKS5 proxy each hold a UDP association, so fewer are kept
+const PROXIED_ENDPOINT_POOL_SIZE: usize = 4;
 
-static DOQ_ENDPOINTS_V6: OnceCell<Vec<quinn::Endpoint>> = OnceCell::const_new();
-static DOQ_INDEX_V6: AtomicUsize = AtomicUsize::new(0);
-
-/// Global QUIC endpoint cache for DoH3 clients
-static DOH3_ENDPOINTS_V4: OnceCell<Vec<quinn::Endpoint>> = OnceCell::const_new();
-static DOH3_INDEX_V4: AtomicUsize = AtomicUsize::new(0);
-
-static DOH3_ENDPOINTS_V6: OnceCell<Vec<quinn::Endpoint>> = OnceCell::const_new();
-static DOH3_INDEX_V6: AtomicUsize = AtomicUsize::new(0);
-
-/// Global DoT connection pool
-/// Key: "host:port", Value: TLS stream
 use std::collections::HashMap;
 use std::sync::OnceLock;
 use tokio::sync::Mutex;
-use tokio_rustls::client::TlsStream;
-use tokio::net::TcpStream;
-
-type DotConnection = TlsStream<TcpStream>;
-static DOT_POOL: OnceLock<Mutex<HashMap<String, DotConnection>>> = OnceLock::new();
-
-fn get_dot_pool() -> &'static Mutex<HashMap<String, DotConnection>> {
-    DOT_POOL.get_or_init(|| Mutex::new(HashMap::new()))
-}
 
 /// QUIC protocol type for endpoint caching
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 enum QuicProtocol {
     Doq,
     Doh3,
 }
 
-/// Get or create a cached QUIC endpoint for the given protocol and address family
-fn get_quic_endpoint(protocol: QuicProtocol, is_ipv6: bool) -> Result<&'static quinn::Endpoint> {
-    let (cell, index) = match (protocol, is_ipv6) {
-        (QuicProtocol::Doq, false) => (&DOQ_ENDPOINTS_V4, &DOQ_INDEX_V4),
-        (QuicProtocol::Doq, true) => (&DOQ_ENDPOINTS_V6, &DOQ_INDEX_V6),
-        (QuicProtocol::Doh3, false) => (&DOH3_ENDPOINTS_V4, &DOH3_INDEX_V4),
-        (QuicProtocol::Doh3, true) => (&DOH3_ENDPOINTS_V6, &DOH3_INDEX_V6),
+/// Endpoints are only shared between upstreams with identical outbound settings
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct QuicEndpointKey {
+    protocol: QuicProtocol,
+    is_ipv6: bool,
+    outbound: OutboundConfig,
+}
+
+/// A pool of QUIC endpoints used round-robin
+struct QuicEndpointPool {
+    endpoints: Vec<quinn::Endpoint>,
+    /// SOCKS5 relay sockets backing the endpoints, if proxied
+    relays: Vec<Arc<Socks5QuicSocket>>,
+    index: AtomicUsize,
+}
+
+impl QuicEndpointPool {
+    /// A proxied pool is unusable once the proxy drops a UDP association
+    fn is_usable(&self) -> bool {
+        self.relays.iter().all(|relay| !relay.is_closed())
+    }
+
+    fn next(&self) -> quinn::Endpoint {
+        let idx = self.index.fetch_add(1, Ordering::Relaxed) % self.endpoints.len();
+        self.endpoints[idx].clone()
+    }
+}
+
+static QUIC_ENDPOINTS: OnceLock<Mutex<HashMap<QuicEndpointKey, Arc<QuicEndpointPool>>>> = OnceLock::new();
+
+/// Get or create a cached QUIC endpoint for the given protocol, address family
+/// and outbound settings
+async fn get_quic_endpoint(
+    protocol: QuicProtocol,
+    is_ipv6: bool,
+    outbound: &OutboundConfig,
+    connect_timeout: Duration,
+) -> Result<quinn::Endpoint> {
+    let key = QuicEndpointKey {
+        protocol,
+        is_ipv6,
+        outbound: outbound.clone(),
     };
-    
+
+    let pools = QUIC_ENDPOINTS.get_or_init(|| Mutex::new(HashMap::new()));
+
     // Try to get existing endpoint pool
-    if let Some(endpoints) = cell.get() {
-        let idx = index.fetch_add(1, Ordering::Relaxed) % endpoints.len();
-        return Ok(&endpoints[idx]);
+    if let Some(pool) = pools.lock().await.get(&key) {
+        if pool.is_usable() {
+            return Ok(pool.next());
+        }
+        tracing::debug!("QUIC endpoint pool lost its SOCKS5 association, recreating");
     }
-    
-    // Create new endpoint pool
-    let mut eps = Vec::with_capacity(ENDPOINT_POOL_SIZE);
-    
-    for _ in 0..ENDPOINT_POOL_SIZE {
-        // Create new endpoint with appropriate config
-        let bind_addr: SocketAddr = if is_ipv6 {
-            "[::]:0".parse()?
+
+    // Create new endpoint pool without holding the lock (binding may include
+    // a SOCKS5 handshake); keep a usable pool created meanwhile by another query
+    let created = Arc::new(create_quic_endpoint_pool(protocol, is_ipv6, outbound, connect_timeout).await?);
+    let mut pools = pools.lock().await;
+    let pool = match pools.get(&key) {
+        Some(pool) if pool.is_usable() => pool.clone(),
+        _ => {
+            pools.insert(key, created.clone());
+            created
+        }
+    };
+    Ok(pool.next())
+}
+
+/// Create a pool of QUIC endpoints bound according to the outbound settings
+async fn create_quic_endpoint_pool(
+    protocol: QuicProtocol,
+    is_ipv6: bool,
+    outbound: &OutboundConfig,
+    connect_timeout: Duration,
+) -> Result<QuicEndpointPool> {
+    // Create TLS config with certificate verification disabled (for IP-based connections)
+    let mut crypto = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoVerifier))
+        .with_no_client_auth();
+
+    // Set ALPN protocol based on QUIC protocol type
+    crypto.alpn_protocols = match protocol {
+        QuicProtocol::Doq => vec![b"doq".to_vec()],
+        QuicProtocol::Doh3 => vec![b"h3".to_vec()],
+    };
+
+    let quic_crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
+        .map_err(|e| anyhow!("Failed to create QUIC client config: {}", e))?;
+
+    // Configure Transport (Keep-Alive)
+    let mut transport = quinn::TransportConfig::default();
+    // Send keep-alive every 5 seconds to maintain NAT mappings
+    transport.keep_alive_interval(Some(Duration::from_secs(5)));
+    // Set max idle timeout to 20 seconds
+    transport.max_idle_timeout(Some(quinn::VarInt::from_u32(20_000).into()));
+
+    let mut client_config = quinn::ClientConfig::new(Arc::new(quic_crypto));
+    client_config.transport_config(Arc::new(transport));
+
+    let pool_size = if outbound.proxy.is_some() {
+        PROXIED_ENDPOINT_POOL_SIZE
+    } else {
+        ENDPOINT_POOL_SIZE
+    };
+
+    let mut endpoints = Vec::with_capacity(pool_size);
+    let mut relays = Vec::new();
+
+    for _ in 0..pool_size {
+        let mut endpoint = if outbound.proxy.is_some() {
+            // Tunnel QUIC datagrams through a SOCKS5 UDP association
+            let relay = outbound.udp_associate(connect_timeout).await?;
+            let socket = Arc::new(Socks5QuicSocket::new(relay, is_ipv6));
+            relays.push(socket.clone());
+            quinn::Endpoint::new_with_abstract_socket(
+                quinn::EndpointConfig::default(),
+                None,
+                socket,
+                Arc::new(quinn::TokioRuntime),
+            )?
+        } else if outbound.is_direct() {
+            let bind_addr: SocketAddr = if is_ipv6 {
+                "[::]:0".parse()?
+            } else {
+                "0.0.0.0:0".parse()?
+            };
+            quinn::Endpoint::client(bind_addr)?
         } else {
-            "0.0.0.0:0".parse()?
-        };
-        
-        // Create TLS config with certificate verification disabled (for IP-based connections)
-        let mut crypto = rustls::ClientConfig::builder()
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(NoVerifier))
-            .with_no_client_auth();
-        
-        // Set ALPN protocol based on QUIC protocol type
-        crypto.alpn_protocols = match protocol {
-            QuicProtocol::Doq => vec![b"doq".to_vec()],
-            QuicProtocol::Doh3 => vec![b"h3".to_vec()],
+            // Source address and/or interface binding
+            let socket = outbound.bind_udp(is_ipv6).await?.into_std()?;
+            quinn::Endpoint::new(
+                quinn::EndpointConfig::default(),
+                None,
+                socket,
+                Arc::new(quinn::TokioRuntime),
+            )?
         };
-        
-        let quic_crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
-            .map_err(|e| anyhow!("Failed to create QUIC client config: {}", e))?;
-            
-        // Configure Transport (Keep-Alive)
-        let mut transport = quinn::TransportConfig::default();
-        // Send keep-alive every 5 seconds to maintain NAT mappings
-        transport.keep_alive_interval(Some(Duration::from_secs(5)));
-        // Set max idle timeout to 20 seconds
-        transport.max_idle_timeout(Some(quinn::VarInt::from_u32(20_000).into()));
-        
-        let mut client_config = quinn::ClientConfig::new(Arc::new(quic_crypto));
-        client_config.transport_config(Arc::new(transport));
-        
-        let mut endpoint = quinn::Endpoint::client(bind_addr)?;
-        endpoint.set_default_client_config(client_config);
-        
-        eps.push(endpoint);
+
+        endpoint.set_default_client_config(client_config.clone());
+        endpoints.push(endpoint);
     }
-    
-    // Try to store it
-    // If another thread beat us to it, the result will be Err(value), but we just discard our created value
-    // and use the one in the cell.
-    let _ = cell.set(eps);
-    
-    // Get from cell (guaranteed to be Some now)
-    let endpoints = cell.get().unwrap();
-    let idx = index.fetch_add(1, Ordering::Relaxed) % endpoints.len();
-    Ok(&endpoints[idx])
+
+    Ok(QuicEndpointPool {
+        endpoints,
+        relays,
+        index: AtomicUsize::new(0),
+    })
 }
 
 /// Result of a DNS query to an upstream server
@@ -184,6 +254,32 @@ pub struct QueryResult {
     pub server_id: i64,
     /// Server name
     pub server_name: String,
+    /// Why this answer was chosen (set by routing, e.g. anti-pollution)
+    pub decision: Option<String>,
+}
+
+/// Sample an upstream exchange for dnstap
+fn sample_forwarder(dnstap: &Option<Arc<Dnstap>>) -> Option<&Dnstap> {
+    dnstap.as_deref().filter(|tap| tap.sample(DnstapRole::Forwarder))
+}
+
+/// Log a sampled upstream exchange; `response` is None when none arrived
+fn log_forwarder(
+    tap: &Dnstap,
+    protocol: DnstapProtocol,
+    peer: Option<SocketAddr>,
+    query: &[u8],
+    query_time: SystemTime,
+    response: Option<&[u8]>,
+) {
+    tap.log(&DnstapExchange {
+        role: DnstapRole::Forwarder,
+        protocol,
+        peer,
+        query,
+        query_time,
+        response,
+    });
 }
 
 /// Trait for DNS upstream clients
@@ -201,24 +297,155 @@ pub trait DnsClient: Send + Sync {
     async fn health_check(&self) -> Result<Duration>;
 }
 
+/// UDP client settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UdpClientConfig {
+    /// Extra attempts after a timeout; the upstream timeout is split across all attempts
+    #[serde(default = "default_udp_retries")]
+    pub retries: u32,
+    /// Randomize the case of the query name (DNS 0x20) and require it echoed back
+    #[serde(default)]
+    pub randomize_case: bool,
+}
+
+fn default_udp_retries() -> u32 {
+    1
+}
+
+impl Default for UdpClientConfig {
+    fn default() -> Self {
+        Self {
+            retries: default_udp_retries(),
+            randomize_case: false,
+        }
+    }
+}
+
+impl UdpClientConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if self.retries > 5 {
+            return Err(anyhow!("retries must be between 0 and 5"));
+        }
+        Ok(())
+    }
+}
+
+/// Why a datagram was not accepted as the response to a UDP query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpMismatch {
+    /// Sent from an address other than the upstream
+    Source,
+    /// Not a parseable DNS response
+    Malformed,
+    /// Message ID differs from the query
+    Id,
+    /// Question section does not echo the query (including 0x20 case)
+    Question,
+}
+
+/// Check a datagram against the query it should answer
+fn check_udp_response(
+    bytes: &[u8],
+    from: SocketAddr,
+    server_addr: SocketAddr,
+    id: u16,
+    name: &Name,
+    query_type: TrustRecordType,
+    case_sensitive: bool,
+) -> std::result::Result<(), UdpMismatch> {
+    if from != server_addr {
+        return Err(UdpMismatch::Source);
+    }
+    let message = Message::from_vec(bytes).map_err(|_| UdpMismatch::Malformed)?;
+    if message.message_type() != MessageType::Response {
+        return Err(UdpMismatch::Malformed);
+    }
+    if message.id() != id {
+        return Err(UdpMismatch::Id);
+    }
+    let question_matches = match message.queries() {
+        [question] => {
+            let same_name = if case_sensitive {
+                question.name().eq_case(name)
+            } else {
+                question.name() == name
+            };
+            same_name && question.query_type() == query_type
+        }
+        _ => false,
+    };
+    if !question_matches {
+        return Err(UdpMismatch::Question);
+    }
+    Ok(())
+}
+
+/// Socket a UDP query is exchanged over
+enum UdpTransport {
+    Direct(UdpSocket),
+    Relay(Socks5UdpRelay),
+}
+
+impl UdpTransport {
+    async fn send_to(&self, payload: &[u8], target: SocketAddr) -> Result<()> {
+        match self {
+            UdpTransport::Direct(socket) => socket.send_to(payload, target).await.map(|_| ())?,
+            UdpTransport::Relay(relay) => relay.send_to(payload, target).await?,
+        }
+        Ok(())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        match self {
+            UdpTransport::Direct(socket) => Ok(socket.recv_from(buf).await?),
+            UdpTransport::Relay(relay) => relay.recv_from(buf).await,
+        }
+    }
+}
+
 /// UDP DNS Client
 ///
 /// Queries upstream DNS servers using standard UDP protocol.
+/// Datagrams whose source, ID or question do not match the query are
+/// dropped and counted, and the client keeps waiting for the real answer.
 pub struct UdpDnsClient {
     server: UpstreamServer,
-    #[allow(dead_code)]
-    socket: Option<UdpSocket>,
+    config: UdpClientConfig,
+    /// Where mismatched responses are counted
+    stats: Option<Arc<UpstreamManager>>,
+    /// Where sent queries and received responses are logged
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl UdpDnsClient {
-    /// Create a new UDP DNS client
+    /// Create a new UDP DNS client with the default settings
     pub fn new(server: UpstreamServer) -> Self {
+        Self::with_config(server, UdpClientConfig::default())
+    }
+
+    /// Create a new UDP DNS client with the given settings
+    pub fn with_config(server: UpstreamServer, config: UdpClientConfig) -> Self {
         Self {
             server,
-            socket: None,
+            config,
+            stats: None,
+            dnstap: None,
         }
     }
 
+    /// Count mismatched responses in the upstream's statistics
+    pub fn with_stats(mut self, stats: Arc<UpstreamManager>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Log every attempt through dnstap
+    pub fn with_dnstap(mut self, dnstap: Option<Arc<Dnstap>>) -> Self {
+        self.dnstap = dnstap;
+        self
+    }
+
     /// Parse the server address with IPv6 support
     /// Supports formats: "1.1.1.1:53", "[2001:4860:4860::8888]:53", "dns.google:53"
     fn parse_address(&self) -> Result<SocketAddr> {
@@ -235,34 +462,79 @@ impl UdpDnsClient {
             .map_err(|e| anyhow!("Invalid address format '{}': {}", self.server.address, e))
     }
 
-    /// Send a query and receive response
-    async fn send_query(&self, query_bytes: &[u8], server_addr: SocketAddr) -> Result<Vec<u8>> {
-        use tracing::debug;
-        
+    /// Open a fresh socket (and thus a fresh source port) for one attempt
+    async fn open_transport(&self, server_addr: SocketAddr) -> Result<UdpTransport> {
+        let outbound = &self.server.outbound;
+        if outbound.proxy.is_some() {
+            let relay = outbound.udp_associate(self.server.timeout).await?;
+            return Ok(UdpTransport::Relay(relay));
+        }
         // Bind to appropriate address family based on target
-        let bind_addr = if server_addr.is_ipv6() {
-            "[::]:0"
+        Ok(UdpTransport::Direct(outbound.bind_udp(server_addr.is_ipv6()).await?))
+    }
+
+    /// Send one attempt and wait for a matching response
+    async fn send_query(
+        &self,
+        query: &DnsQuery,
+        name: &Name,
+        server_addr: SocketAddr,
+        wait: Duration,
+        tap: Option<&Dnstap>,
+    ) -> Result<Vec<u8>> {
+        use tracing::debug;
+
+        let id: u16 = rand::random();
+        let sent_name = if self.config.randomize_case {
+            randomize_case(name)
         } else {
-            "0.0.0.0:0"
+            name.clone()
         };
-        let socket = UdpSocket::bind(bind_addr).await
-            .map_err(|e| anyhow!("Failed to bind UDP socket to {}: {}", bind_addr, e))?;
-        
-        debug!("Sending UDP query to {} ({} bytes)", server_addr, query_bytes.len());
-        
-        // Send query
-        let sent = socket.send_to(query_bytes, server_addr).await?;
-        debug!("Sent {} bytes to {}", sent, server_addr);
-        
-        // Receive response
-        let mut buf = vec![0u8; 4096];
-        debug!("Waiting for response from {} (timeout: {:?})", server_addr, self.server.timeout);
-        let (len, from) = timeout(self.server.timeout, socket.recv_from(&mut buf)).await
-            .map_err(|_| anyhow!("Query timeout after {:?}", self.server.timeout))??;
-        
-        debug!("Received {} bytes from {}", len, from);
-        buf.truncate(len);
-        Ok(buf)
+        let query_type = query.record_type.to_trust_dns();
+
+        let mut request = Message::new();
+        request.set_id(id);
+        request.set_message_type(MessageType::Query);
+        request.set_op_code(OpCode::Query);
+        request.set_recursion_desired(query.recursion_desired);
+        request.add_query(Query::query(sent_name.clone(), query_type));
+        let request = request.to_vec()
+            .map_err(|e| anyhow!("Failed to encode query: {}", e))?;
+
+        let transport = self.open_transport(server_addr).await?;
+        debug!("Sending UDP query to {} ({} bytes)", server_addr, request.len());
+        let query_time = SystemTime::now();
+        transport.send_to(&request, server_addr).await?;
+
+        let response = timeout(wait, async {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let (len, from) = transport.recv_from(&mut buf).await?;
+                match check_udp_response(
+                    &buf[..len], from, server_addr, id, &sent_name, query_type, self.config.randomize_case,
+                ) {
+                    Ok(()) => {
+                        debug!("Received {} bytes from {}", len, from);
+                        buf.truncate(len);
+                        return Ok(buf);
+                    }
+                    Err(mismatch) => {
+                        debug!("Dropping {:?}-mismatched UDP response from {} for {}", mismatch, from, query.name);
+                        if let Some(stats) = &self.stats {
+                            stats.record_mismatch(self.server.id).await;
+                        }
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("Query timeout after {:?}", wait))
+        .and_then(|response| response);
+
+        if let Some(tap) = tap {
+            log_forwarder(tap, DnstapProtocol::Udp, Some(server_addr), &request, query_time, response.as_deref().ok());
+        }
+        response
     }
 }
 
@@ -275,26 +547,54 @@ impl DnsClient for UdpDnsClient {
                query.name, query.record_type, self.server.name, self.server.address);
         
         let server_addr = self.parse_address()?;
-        debug!("Parsed server address: {}", server_addr);
-        
-        let query_bytes = query.to_bytes()
+        let mut name = Name::from_ascii(&query.name)
+            .or_else(|_| Name::from_str(&query.name))
             .map_err(|e| anyhow!("Failed to encode query: {}", e))?;
-        debug!("Encoded query: {} bytes", query_bytes.len());
+        name.set_fqdn(true);
+
+        // Split the upstream timeout across all attempts
+        let attempts = self.config.retries + 1;
+        let wait = (self.server.timeout / attempts).max(Duration::from_millis(50));
         
+        let tap = sample_forwarder(&self.dnstap);
         let start = Instant::now();
-        let response_bytes = match self.send_query(&query_bytes, server_addr).await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                warn!("UDP query to {} failed: {}", server_addr, e);
-                return Err(e);
+        let mut last_error = anyhow!("No attempts made");
+        let mut response_bytes = None;
+        for attempt in 1..=attempts {
+            match self.send_query(query, &name, server_addr, wait, tap).await {
+                Ok(bytes) => {
+                    response_bytes = Some(bytes);
+                    break;
+                }
+                Err(e) => {
+                    debug!("UDP attempt {}/{} to {} failed: {}", attempt, attempts, server_addr, e);
+                    last_error = e;
+                }
             }
+        }
+        let Some(response_bytes) = response_bytes else {
+            warn!("UDP query to {} failed: {}", server_addr, last_error);
+            return Err(last_error);
         };
         let response_time = start.elapsed();
         
         debug!("Received response: {} bytes in {:?}", response_bytes.len(), response_time);
         
-        let response = DnsResponse::from_bytes(&response_bytes)
+        let mut response = DnsResponse::from_bytes(&response_bytes)
             .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
+        response.id = query.id;
+        if self.config.randomize_case {
+            // Answers echo the randomized owner name; restore the name as asked
+            let asked = query.name.trim_end_matches('.');
+            for record in response.answers.iter_mut()
+                .chain(response.authority.iter_mut())
+                .chain(response.additional.iter_mut())
+            {
+                if record.name.eq_ignore_ascii_case(asked) {
+                    record.name = asked.to_string();
+                }
+            }
+        }
         
         debug!("Parsed response: {} answers, code={}", response.answers.len(), response.response_code);
         
@@ -303,6 +603,7 @@ impl DnsClient for UdpDnsClient {
             response_time_ms: response_time.as_millis() as u64,
             server_id: self.server.id,
             server_name: self.server.name.clone(),
+            decision: None,
         })
     }
 
@@ -325,143 +626,48 @@ impl DnsClient for UdpDnsClient {
 /// DoT (DNS over TLS) Client
 ///
 /// Queries upstream DNS servers using DNS over TLS protocol.
-/// Supports connection reuse for better performance.
+/// Queries are pipelined over pooled connections, see [`DotConnectionManager`].
 pub struct DotDnsClient {
     server: UpstreamServer,
+    connections: DotConnectionManager,
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl DotDnsClient {
-    /// Create a new DoT DNS client
+    /// Create a new DoT DNS client with the default pool settings
     pub fn new(server: UpstreamServer) -> Self {
-        Self { server }
+        Self::with_pool(server, DotPoolConfig::default())
     }
 
-    /// Parse the server address with IPv6 support
-    /// Supports formats: "dns.google:853", "[2001:4860:4860::8888]:853", "1.1.1.1:853"
-    fn parse_address(&self) -> Result<(String, u16)> {
-        parse_host_port(&self.server.address, UpstreamProtocol::Dot.default_port())
+    /// Create a new DoT DNS client with the given pool settings
+    pub fn with_pool(server: UpstreamServer, config: DotPoolConfig) -> Self {
+        let connections = DotConnectionManager::new(&server, config);
+        Self { server, connections, dnstap: None }
     }
 
-    /// Create a new TLS connection with IPv6 support
-    async fn create_connection(&self, host: &str, port: u16) -> Result<DotConnection> {
-        use tokio_rustls::TlsConnector;
-        use rustls::{ClientConfig, RootCertStore};
-        use rustls::pki_types::ServerName;
-
-        // Format address for connection - use brackets for IPv6
-        let addr = if host.contains(':') {
-            format!("[{}]:{}", host, port)
-        } else {
-            format!("{}:{}", host, port)
-        };
-        
-        // Create TLS config with system root certificates
-        let mut root_store = RootCertStore::empty();
-        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-        
-        let config = ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
-        
-        let connector = TlsConnector::from(Arc::new(config));
-        
-        // For SNI, use the host directly (without brackets) if it's a hostname
-        // For IP addresses, we still need a ServerName but TLS doesn't verify it
-        let server_name = ServerName::try_from(host.to_string())
-            .map_err(|_| anyhow!("Invalid server name: {}", host))?;
-        
-        // Connect with timeout
-        let stream = timeout(self.server.timeout, TcpStream::connect(&addr)).await
-            .map_err(|_| anyhow!("Connection timeout to {}", addr))??;
-        
-        let tls_stream = timeout(self.server.timeout, connector.connect(server_name, stream)).await
-            .map_err(|_| anyhow!("TLS handshake timeout"))??;
-        
-        Ok(tls_stream)
-    }
-
-    /// Send query over an existing connection, returns None if connection is broken
-    async fn send_query_on_conn(
-        &self,
-        conn: &mut DotConnection,
-        query: &DnsQuery,
-    ) -> Result<Vec<u8>> {
-        use tokio::io::{AsyncReadExt, AsyncWriteExt};
-
-        // Encode query with length prefix (TCP DNS format)
-        let query_bytes = query.to_bytes()
-            .map_err(|e| anyhow!("Failed to encode query: {}", e))?;
-        let len = (query_bytes.len() as u16).to_be_bytes();
-        
-        conn.write_all(&len).await?;
-        conn.write_all(&query_bytes).await?;
-        conn.flush().await?;
-        
-        // Read response length
-        let mut len_buf = [0u8; 2];
-        timeout(self.server.timeout, conn.read_exact(&mut len_buf)).await
-            .map_err(|_| anyhow!("Read timeout"))??;
-        let response_len = u16::from_be_bytes(len_buf) as usize;
-        
-        // Read response
-        let mut response_bytes = vec![0u8; response_len];
-        timeout(self.server.timeout, conn.read_exact(&mut response_bytes)).await
-            .map_err(|_| anyhow!("Read timeout"))??;
-        
-        Ok(response_bytes)
+    /// Log exchanges through dnstap
+    pub fn with_dnstap(mut self, dnstap: Option<Arc<Dnstap>>) -> Self {
+        self.dnstap = dnstap;
+        self
     }
 }
 
 #[async_trait]
 impl DnsClient for DotDnsClient {
     async fn query(&self, query: &DnsQuery) -> Result<QueryResult> {
-        use tracing::debug;
+        let query_bytes = query.to_bytes()
+            .map_err(|e| anyhow!("Failed to encode query: {}", e))?;
 
-        let (host, port) = self.parse_address()?;
-        let pool_key = format!("{}:{}", host, port);
-        
+        let tap = sample_forwarder(&self.dnstap);
+        let query_time = SystemTime::now();
         let start = Instant::now();
-        
-        // Try to reuse existing connection
-        let pool = get_dot_pool();
-        let mut conn_opt = {
-            let mut pool_guard = pool.lock().await;
-            pool_guard.remove(&pool_key)
-        };
-        
-        let response_bytes = if let Some(ref mut conn) = conn_opt {
-            // Try to use existing connection
-            match self.send_query_on_conn(conn, query).await {
-                Ok(bytes) => {
-                    debug!("DoT query succeeded on reused connection to {}", pool_key);
-                    // Put connection back to pool
-                    let mut pool_guard = pool.lock().await;
-                    pool_guard.insert(pool_key.clone(), conn_opt.take().unwrap());
-                    bytes
-                }
-                Err(e) => {
-                    debug!("DoT reused connection failed: {}, creating new connection", e);
-                    // Connection broken, create new one
-                    let mut new_conn = self.create_connection(&host, port).await?;
-                    let bytes = self.send_query_on_conn(&mut new_conn, query).await?;
-                    // Put new connection to pool
-                    let mut pool_guard = pool.lock().await;
-                    pool_guard.insert(pool_key.clone(), new_conn);
-                    bytes
-                }
-            }
-        } else {
-            // No existing connection, create new one
-            debug!("DoT creating new connection to {}", pool_key);
-            let mut new_conn = self.create_connection(&host, port).await?;
-            let bytes = self.send_query_on_conn(&mut new_conn, query).await?;
-            // Put connection to pool
-            let mut pool_guard = pool.lock().await;
-            pool_guard.insert(pool_key.clone(), new_conn);
-            bytes
-        };
-        
+        let response_bytes = self.connections.query(&query_bytes).await;
         let response_time = start.elapsed();
+        if let Some(tap) = tap {
+            let peer = self.server.address.parse().ok();
+            log_forwarder(tap, DnstapProtocol::Dot, peer, &query_bytes, query_time, response_bytes.as_deref().ok());
+        }
+        let response_bytes = response_bytes?;
         
         let response = DnsResponse::from_bytes(&response_bytes)
             .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
@@ -471,6 +677,7 @@ impl DnsClient for DotDnsClient {
             response_time_ms: response_time.as_millis() as u64,
             server_id: self.server.id,
             server_name: self.server.name.clone(),
+            decision: None,
         })
     }
 
@@ -487,12 +694,31 @@ impl DnsClient for DotDnsClient {
 }
 
 
+/// HTTP connection used by DoH clients with outbound settings
+enum DohTunnel {
+    Http1(hyper::client::conn::http1::SendRequest<http_body_util::Full<Bytes>>),
+    Http2(hyper::client::conn::http2::SendRequest<http_body_util::Full<Bytes>>),
+}
+
+impl DohTunnel {
+    fn is_closed(&self) -> bool {
+        match self {
+            DohTunnel::Http1(sender) => sender.is_closed(),
+            DohTunnel::Http2(sender) => sender.is_closed(),
+        }
+    }
+}
+
 /// DoH (DNS over HTTPS) Client
 ///
 /// Queries upstream DNS servers using DNS over HTTPS protocol.
+/// Upstreams with a proxy or source binding use a dedicated connection
+/// opened through [`OutboundConfig::connect_tcp`] instead of reqwest.
 pub struct DohDnsClient {
     server: UpstreamServer,
     client: reqwest::Client,
+    tunnel: Mutex<Option<DohTunnel>>,
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl DohDnsClient {
@@ -503,7 +729,18 @@ impl DohDnsClient {
             .build()
             .unwrap_or_default();
         
-        Self { server, client }
+        Self {
+            server,
+            client,
+            tunnel: Mutex::new(None),
+            dnstap: None,
+        }
+    }
+
+    /// Log exchanges through dnstap
+    pub fn with_dnstap(mut self, dnstap: Option<Arc<Dnstap>>) -> Self {
+        self.dnstap = dnstap;
+        self
     }
 
     /// Get the DoH URL
@@ -514,20 +751,12 @@ impl DohDnsClient {
             format!("https://{}/dns-query", self.server.address)
         }
     }
-}
 
-#[async_trait]
-impl DnsClient for DohDnsClient {
-    async fn query(&self, query: &DnsQuery) -> Result<QueryResult> {
-        let url = self.get_url();
-        let query_bytes = query.to_bytes()
-            .map_err(|e| anyhow!("Failed to encode query: {}", e))?;
-        
-        let start = Instant::now();
-        
+    /// Send the query with reqwest (direct connections)
+    async fn send_direct(&self, url: &str, query_bytes: Vec<u8>) -> Result<Bytes> {
         // Use POST method with application/dns-message content type
         let response = self.client
-            .post(&url)
+            .post(url)
             .header("Content-Type", "application/dns-message")
             .header("Accept", "application/dns-message")
             .body(query_bytes)
@@ -538,8 +767,165 @@ impl DnsClient for DohDnsClient {
             return Err(anyhow!("DoH query failed with status: {}", response.status()));
         }
         
-        let response_bytes = response.bytes().await?;
+        Ok(response.bytes().await?)
+    }
+
+    /// Send the query over a connection opened through the outbound settings
+    async fn send_via_outbound(&self, url: &str, query_bytes: Vec<u8>) -> Result<Bytes> {
+        use http::{header, Method, Request};
+        use http_body_util::{BodyExt, Full};
+
+        let url = reqwest::Url::parse(url)
+            .map_err(|e| anyhow!("Invalid DoH URL '{}': {}", url, e))?;
+        let authority = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(anyhow!("DoH URL has no host: {}", url)),
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "application/dns-message")
+            .header(header::ACCEPT, "application/dns-message")
+            .header(header::CONTENT_LENGTH, query_bytes.len());
+
+        // HTTP/2 connections are shared, HTTP/1.1 ones are taken out while in use
+        let cached = {
+            let mut guard = self.tunnel.lock().await;
+            match guard.take() {
+                Some(DohTunnel::Http2(sender)) if !sender.is_closed() => {
+                    *guard = Some(DohTunnel::Http2(sender.clone()));
+                    Some(DohTunnel::Http2(sender))
+                }
+                Some(tunnel) if !tunnel.is_closed() => Some(tunnel),
+                _ => None,
+            }
+        };
+        let tunnel = match cached {
+            Some(tunnel) => tunnel,
+            None => self.open_tunnel(&url).await?,
+        };
+
+        let body = Full::new(Bytes::from(query_bytes));
+        let (tunnel, response) = match tunnel {
+            DohTunnel::Http1(mut sender) => {
+                sender.ready().await?;
+                let request = request
+                    .uri(path)
+                    .header(header::HOST, authority)
+                    .body(body)?;
+                let response = timeout(self.server.timeout, sender.send_request(request)).await
+                    .map_err(|_| anyhow!("DoH request timeout"))??;
+                (DohTunnel::Http1(sender), response)
+            }
+            DohTunnel::Http2(mut sender) => {
+                sender.ready().await?;
+                let request = request.uri(url.as_str()).body(body)?;
+                let response = timeout(self.server.timeout, sender.send_request(request)).await
+                    .map_err(|_| anyhow!("DoH request timeout"))??;
+                (DohTunnel::Http2(sender), response)
+            }
+        };
+
+        if !response.status().is_success() {
+            return Err(anyhow!("DoH query failed with status: {}", response.status()));
+        }
+
+        let response_bytes = timeout(self.server.timeout, response.into_body().collect()).await
+            .map_err(|_| anyhow!("DoH response timeout"))??
+            .to_bytes();
+
+        *self.tunnel.lock().await = Some(tunnel);
+        Ok(response_bytes)
+    }
+
+    /// Open an HTTP connection to the DoH server through the outbound settings
+    async fn open_tunnel(&self, url: &reqwest::Url) -> Result<DohTunnel> {
+        use hyper_util::rt::{TokioExecutor, TokioIo};
+        use rustls::pki_types::ServerName;
+        use rustls::{ClientConfig, RootCertStore};
+        use tokio_rustls::TlsConnector;
+        use tracing::debug;
+
+        let host = url.host_str()
+            .ok_or_else(|| anyhow!("DoH URL has no host: {}", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url.port_or_known_default().unwrap_or(443);
+
+        debug!("DoH opening connection to {}:{} via outbound settings", host, port);
+        let stream = self.server.outbound
+            .connect_tcp(&host, port, self.server.timeout)
+            .await?;
+
+        if url.scheme() == "http" {
+            let (sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+            tokio::spawn(async move {
+                let _ = conn.await;
+            });
+            return Ok(DohTunnel::Http1(sender));
+        }
+
+        let mut root_store = RootCertStore::empty();
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let mut config = ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|_| anyhow!("Invalid server name: {}", host))?;
+        let tls_stream = timeout(
+            self.server.timeout,
+            TlsConnector::from(Arc::new(config)).connect(server_name, stream),
+        ).await
+            .map_err(|_| anyhow!("TLS handshake timeout"))??;
+
+        let is_h2 = tls_stream.get_ref().1.alpn_protocol() == Some(b"h2".as_slice());
+        let io = TokioIo::new(tls_stream);
+
+        if is_h2 {
+            let (sender, conn) = hyper::client::conn::http2::handshake(TokioExecutor::new(), io).await?;
+            tokio::spawn(async move {
+                let _ = conn.await;
+            });
+            Ok(DohTunnel::Http2(sender))
+        } else {
+            let (sender, conn) = hyper::client::conn::http1::handshake(io).await?;
+            tokio::spawn(async move {
+                let _ = conn.await;
+            });
+            Ok(DohTunnel::Http1(sender))
+        }
+    }
+}
+
+#[async_trait]
+impl DnsClient for DohDnsClient {
+    async fn query(&self, query: &DnsQuery) -> Result<QueryResult> {
+        let url = self.get_url();
+        let query_bytes = query.to_bytes()
+            .map_err(|e| anyhow!("Failed to encode query: {}", e))?;
+        
+        let tapped_query = sample_forwarder(&self.dnstap).map(|tap| (tap, query_bytes.clone()));
+        let query_time = SystemTime::now();
+        let start = Instant::now();
+        
+        let response_bytes = if self.server.outbound.is_direct() {
+            self.send_direct(&url, query_bytes).await
+        } else {
+            self.send_via_outbound(&url, query_bytes).await
+        };
         let response_time = start.elapsed();
+        if let Some((tap, query_bytes)) = tapped_query {
+            log_forwarder(tap, DnstapProtocol::Doh, None, &query_bytes, query_time, response_bytes.as_deref().ok());
+        }
+        let response_bytes = response_bytes?;
         
         let dns_response = DnsResponse::from_bytes(&response_bytes)
             .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
@@ -549,6 +935,7 @@ impl DnsClient for DohDnsClient {
             response_time_ms: response_time.as_millis() as u64,
             server_id: self.server.id,
             server_name: self.server.name.clone(),
+            decision: None,
         })
     }
 
@@ -572,6 +959,7 @@ pub struct DoqDnsClient {
     connections: Vec<Arc<tokio::sync::RwLock<Option<quinn::Connection>>>>,
     connect_locks: Vec<Arc<tokio::sync::Mutex<()>>>,
     index: AtomicUsize,
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl DoqDnsClient {
@@ -590,9 +978,16 @@ impl DoqDnsClient {
             connections,
             connect_locks,
             index: AtomicUsize::new(0),
+            dnstap: None,
         }
     }
 
+    /// Log exchanges through dnstap
+    pub fn with_dnstap(mut self, dnstap: Option<Arc<Dnstap>>) -> Self {
+        self.dnstap = dnstap;
+        self
+    }
+
     /// Parse the server address and resolve hostname if needed
     /// Prefers IPv4 addresses over IPv6 for better compatibility
     /// 
@@ -650,9 +1045,10 @@ impl DoqDnsClient {
             return Err(anyhow!("No addresses found for {}", host));
         }
 
-        // Prefer IPv4 addresses
+        // Prefer IPv4 addresses, unless bound to an IPv6 source address
+        let prefer_ipv6 = self.server.outbound.prefers_ipv6();
         let addr = addrs.iter()
-            .find(|a| a.is_ipv4())
+            .find(|a| a.is_ipv6() == prefer_ipv6)
             .or_else(|| addrs.first())
             .cloned()
             .ok_or_else(|| anyhow!("No addresses found for {}", host))?;
@@ -667,6 +1063,7 @@ impl DnsClient for DoqDnsClient {
         use tracing::debug;
 
         let (addr, sni_host) = self.resolve_address().await?;
+        let tap = sample_forwarder(&self.dnstap);
         
         // Loop to allow one retry if cached connection fails
         let mut attempts = 0;
@@ -712,7 +1109,12 @@ impl DnsClient for DoqDnsClient {
                     debug!("DoQ creating new connection to {} (SNI: {}, slot {})", addr, sni_host, idx);
                     
                     // Get or create cached endpoint
-                    let endpoint = get_quic_endpoint(QuicProtocol::Doq, addr.is_ipv6())?;
+                    let endpoint = get_quic_endpoint(
+                        QuicProtocol::Doq,
+                        addr.is_ipv6(),
+                        &self.server.outbound,
+                        self.server.timeout,
+                    ).await?;
                     let connect_sni = sni_host.as_str();
                     
                     match timeout(self.server.timeout, endpoint.connect(addr, connect_sni)?).await {
@@ -730,6 +1132,8 @@ impl DnsClient for DoqDnsClient {
             };
 
             // 2. Perform Query
+            // Query bytes and send time, kept until the exchange is logged
+            let mut sent = None;
             let query_result = async {
                 // Open stream
                 let (mut send, mut recv) = timeout(self.server.timeout, connection.open_bi()).await
@@ -742,11 +1146,15 @@ impl DnsClient for DoqDnsClient {
                 let len = (query_bytes.len() as u16).to_be_bytes();
                 
                 let start = Instant::now();
+                let query_time = SystemTime::now();
                 debug!("DoQ sending {} bytes query", query_bytes.len());
                 
                 send.write_all(&len).await?;
                 send.write_all(&query_bytes).await?;
                 send.finish().map_err(|e| anyhow!("Failed to finish stream: {}", e))?;
+                if tap.is_some() {
+                    sent = Some((query_bytes.clone(), query_time));
+                }
                 
                 // Read response
                 let mut len_buf = [0u8; 2];
@@ -764,6 +1172,9 @@ impl DnsClient for DoqDnsClient {
                     .map_err(|e| anyhow!("Failed to read response body: {}", e))?;
                     
                 let response_time = start.elapsed();
+                if let (Some(tap), Some((query_bytes, query_time))) = (tap, sent.take()) {
+                    log_forwarder(tap, DnstapProtocol::Doq, Some(addr), &query_bytes, query_time, Some(&response_bytes));
+                }
                 let response = DnsResponse::from_bytes(&response_bytes)
                     .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
                     
@@ -772,9 +1183,15 @@ impl DnsClient for DoqDnsClient {
                     response_time_ms: response_time.as_millis() as u64,
                     server_id: self.server.id,
                     server_name: self.server.name.clone(),
+                    decision: None,
                 })
             }.await;
 
+            // The query went out but no response came back
+            if let (Some(tap), Some((query_bytes, query_time))) = (tap, sent) {
+                log_forwarder(tap, DnstapProtocol::Doq, Some(addr), &query_bytes, query_time, None);
+            }
+
             match query_result {
                 Ok(res) => return Ok(res),
                 Err(e) => {
@@ -809,7 +1226,7 @@ impl DnsClient for DoqDnsClient {
 
 /// Certificate verifier that accepts any certificate (for IP-based connections)
 #[derive(Debug)]
-struct NoVerifier;
+pub(crate) struct NoVerifier;
 
 impl rustls::client::danger::ServerCertVerifier for NoVerifier {
     fn verify_server_cert(
@@ -868,6 +1285,7 @@ pub struct Doh3DnsClient {
     connections: Vec<Arc<tokio::sync::RwLock<Option<H3SendRequest>>>>,
     connect_locks: Vec<Arc<tokio::sync::Mutex<()>>>,
     index: AtomicUsize,
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl Doh3DnsClient {
@@ -886,9 +1304,16 @@ impl Doh3DnsClient {
             connections,
             connect_locks,
             index: AtomicUsize::new(0),
+            dnstap: None,
         }
     }
 
+    /// Log exchanges through dnstap
+    pub fn with_dnstap(mut self, dnstap: Option<Arc<Dnstap>>) -> Self {
+        self.dnstap = dnstap;
+        self
+    }
+
     /// Get the DoH3 URL and parse host/port with IPv6 support
     /// Returns (sni_host, host, port, path)
     /// Supports formats:
@@ -968,8 +1393,10 @@ impl Doh3DnsClient {
             .collect();
         
         // Prefer IPv4 for better compatibility, but accept IPv6
+        // (or prefer IPv6 when bound to an IPv6 source address)
+        let prefer_ipv6 = self.server.outbound.prefers_ipv6();
         addrs.iter()
-            .find(|a| a.is_ipv4())
+            .find(|a| a.is_ipv6() == prefer_ipv6)
             .or_else(|| addrs.first())
             .cloned()
             .ok_or_else(|| anyhow!("No addresses found for {}", host))
@@ -1028,7 +1455,12 @@ impl DnsClient for Doh3DnsClient {
                     drop(guard);
                     
                     // Get or create cached endpoint
-                    let endpoint = get_quic_endpoint(QuicProtocol::Doh3, addr.is_ipv6())?;
+                    let endpoint = get_quic_endpoint(
+                        QuicProtocol::Doh3,
+                        addr.is_ipv6(),
+                        &self.server.outbound,
+                        self.server.timeout,
+                    ).await?;
                     let connect_sni = sni_host.as_str();
 
                     debug!("DoH3 creating new connection to {} (slot {})", addr, idx);
@@ -1092,35 +1524,44 @@ impl DnsClient for Doh3DnsClient {
             }
         };
 
-        // Send body
-        request_stream.send_data(Bytes::from(query_bytes)).await
-            .map_err(|e| anyhow!("Failed to send body: {}", e))?;
-            
-        request_stream.finish().await
-            .map_err(|e| anyhow!("Failed to finish request: {}", e))?;
+        let tapped_query = sample_forwarder(&self.dnstap).map(|tap| (tap, query_bytes.clone()));
+        let query_time = SystemTime::now();
+        let response_bytes = async {
+            // Send body
+            request_stream.send_data(Bytes::from(query_bytes)).await
+                .map_err(|e| anyhow!("Failed to send body: {}", e))?;
+                
+            request_stream.finish().await
+                .map_err(|e| anyhow!("Failed to finish request: {}", e))?;
 
-        // Receive response
-        let response = request_stream.recv_response().await
-            .map_err(|e| anyhow!("Failed to receive response: {}", e))?;
+            // Receive response
+            let response = request_stream.recv_response().await
+                .map_err(|e| anyhow!("Failed to receive response: {}", e))?;
 
-        debug!("DoH3 response status: {}", response.status());
+            debug!("DoH3 response status: {}", response.status());
 
-        if !response.status().is_success() {
-            return Err(anyhow!("DoH3 query failed with status: {}", response.status()));
-        }
+            if !response.status().is_success() {
+                return Err(anyhow!("DoH3 query failed with status: {}", response.status()));
+            }
 
-        // Read response body
-        let mut response_bytes = Vec::new();
-        while let Some(mut chunk) = request_stream.recv_data().await
-            .map_err(|e| anyhow!("Failed to read response body: {}", e))? 
-        {
-            while chunk.has_remaining() {
-                use bytes::Buf; // Import Buf trait
-                let bytes = chunk.chunk();
-                response_bytes.extend_from_slice(bytes);
-                chunk.advance(bytes.len());
+            // Read response body
+            let mut response_bytes = Vec::new();
+            while let Some(mut chunk) = request_stream.recv_data().await
+                .map_err(|e| anyhow!("Failed to read response body: {}", e))? 
+            {
+                while chunk.has_remaining() {
+                    use bytes::Buf; // Import Buf trait
+                    let bytes = chunk.chunk();
+                    response_bytes.extend_from_slice(bytes);
+                    chunk.advance(bytes.len());
+                }
             }
+            Ok(response_bytes)
+        }.await;
+        if let Some((tap, query_bytes)) = tapped_query {
+            log_forwarder(tap, DnstapProtocol::Doh3, Some(addr), &query_bytes, query_time, response_bytes.as_deref().ok());
         }
+        let response_bytes = response_bytes?;
 
         let response_time = start.elapsed();
         debug!("DoH3 received {} bytes in {:?}", response_bytes.len(), response_time);
@@ -1133,6 +1574,7 @@ impl DnsClient for Doh3DnsClient {
             response_time_ms: response_time.as_millis() as u64,
             server_id: self.server.id,
             server_name: self.server.name.clone(),
+            decision: None,
         })
     }
 
@@ -1157,6 +1599,7 @@ pub fn create_client(server: UpstreamServer) -> Box<dyn DnsClient> {
         UpstreamProtocol::Doh => Box::new(DohDnsClient::new(server)),
         UpstreamProtocol::Doq => Box::new(DoqDnsClient::new(server)),
         UpstreamProtocol::Doh3 => Box::new(Doh3DnsClient::new(server)),
+        UpstreamProtocol::Recursive => Box::new(RecursiveDnsClient::new(server)),
     }
 }
 
@@ -1223,4 +1666,109 @@ mod tests {
         let client2 = DohDnsClient::new(server2);
         assert_eq!(client2.get_url(), "https://cloudflare-dns.com/dns-query");
     }
+
+    /// Stand-in UDP server that sends `forge`d datagrams before each real answer
+    async fn spawn_udp_server(
+        forge: fn(&Message) -> Vec<Message>,
+        echo_case: bool,
+    ) -> SocketAddr {
+        use hickory_proto::rr::{RData, Record};
+        use hickory_proto::rr::rdata::A;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                for forged in forge(&request) {
+                    let _ = socket.send_to(&forged.to_vec().unwrap(), from).await;
+                }
+                let mut question = request.queries()[0].clone();
+                if !echo_case {
+                    let swapped: String = question.name().to_ascii().chars()
+                        .map(|c| if c.is_ascii_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+                        .collect();
+                    question.set_name(Name::from_ascii(swapped).unwrap());
+                }
+                let mut response = Message::new();
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                response.add_query(question.clone());
+                response.add_answer(Record::from_rdata(
+                    question.name().clone(), 60, RData::A(A::new(192, 0, 2, 1)),
+                ));
+                let _ = socket.send_to(&response.to_vec().unwrap(), from).await;
+            }
+        });
+        addr
+    }
+
+    fn forged_reply(request: &Message, id: u16, name: &str) -> Message {
+        let mut message = Message::new();
+        message.set_id(id);
+        message.set_message_type(MessageType::Response);
+        message.add_query(Query::query(Name::from_ascii(name).unwrap(), request.queries()[0].query_type()));
+        message
+    }
+
+    async fn udp_client(addr: SocketAddr, config: UdpClientConfig) -> (UdpDnsClient, Arc<UpstreamManager>) {
+        let server = UpstreamServer::new(1, "Test", addr.to_string(), UpstreamProtocol::Udp, 1000);
+        let manager = Arc::new(UpstreamManager::new());
+        manager.add_server(server.clone()).await;
+        let client = UdpDnsClient::with_config(server, config).with_stats(manager.clone());
+        (client, manager)
+    }
+
+    #[test]
+    fn test_check_udp_response() {
+        let server: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let name = Name::from_ascii("ExAmple.com.").unwrap();
+        let mut message = Message::new();
+        message.set_id(7);
+        message.set_message_type(MessageType::Response);
+        message.add_query(Query::query(Name::from_ascii("example.com.").unwrap(), TrustRecordType::A));
+        let bytes = message.to_vec().unwrap();
+
+        assert_eq!(check_udp_response(&bytes, server, server, 7, &name, TrustRecordType::A, false), Ok(()));
+        assert_eq!(
+            check_udp_response(&bytes, "127.0.0.2:53".parse().unwrap(), server, 7, &name, TrustRecordType::A, false),
+            Err(UdpMismatch::Source)
+        );
+        assert_eq!(check_udp_response(&bytes, server, server, 8, &name, TrustRecordType::A, false), Err(UdpMismatch::Id));
+        assert_eq!(check_udp_response(&bytes, server, server, 7, &name, TrustRecordType::AAAA, false), Err(UdpMismatch::Question));
+        assert_eq!(check_udp_response(&bytes, server, server, 7, &name, TrustRecordType::A, true), Err(UdpMismatch::Question));
+        assert_eq!(check_udp_response(&[1, 2, 3], server, server, 7, &name, TrustRecordType::A, false), Err(UdpMismatch::Malformed));
+    }
+
+    #[tokio::test]
+    async fn test_udp_ignores_forged_responses() {
+        let addr = spawn_udp_server(
+            |request| vec![
+                forged_reply(request, request.id().wrapping_add(1), "example.com."),
+                forged_reply(request, request.id(), "evil.example."),
+            ],
+            true,
+        ).await;
+        let config = UdpClientConfig { retries: 0, randomize_case: true };
+        let (client, manager) = udp_client(addr, config).await;
+
+        let query = DnsQuery::new("example.com", crate::dns::message::RecordType::A);
+        let result = client.query(&query).await.unwrap();
+        assert_eq!(result.response.id, query.id);
+        assert_eq!(result.response.answers.len(), 1);
+        assert_eq!(result.response.answers[0].name, "example.com");
+        assert_eq!(manager.get_stats(1).await.unwrap().mismatches, 2);
+    }
+
+    #[tokio::test]
+    async fn test_udp_rejects_lost_case_and_retries() {
+        let addr = spawn_udp_server(|_| Vec::new(), false).await;
+        let config = UdpClientConfig { retries: 1, randomize_case: true };
+        let (client, manager) = udp_client(addr, config).await;
+
+        let query = DnsQuery::new("example.com", crate::dns::message::RecordType::A);
+        assert!(client.query(&query).await.is_err());
+        assert_eq!(manager.get_stats(1).await.unwrap().mismatches, 2);
+    }
 }
@@ -0,0 +1,750 @@
+//! DoT Connection Manager
+//!
+//! Per-upstream pool of DNS over TLS connections:
+//! - Pipelines concurrent queries on one connection, matching responses
+//!   by message ID (RFC 7766)
+//! - Keeps a small pool, opening extra connections only under load
+//! - Probes idle connections with EDNS TCP keepalive (RFC 7828) and
+//!   honours the idle timeout the server advertises
+//! - Re-establishes lost connections in the background
+//! - Shares one TLS client config so reconnects resume the TLS session
+//!   instead of doing a full handshake
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use hickory_proto::op::Message;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::dns::message::{DnsQuery, RecordType};
+use super::client::parse_host_port;
+use super::outbound::OutboundConfig;
+use super::upstream::{UpstreamProtocol, UpstreamServer};
+
+/// EDNS OPT record carrying an empty TCP keepalive option (RFC 7828)
+const KEEPALIVE_OPT: [u8; 15] = [
+    0x00,       // root name
+    0x00, 0x29, // type OPT
+    0x04, 0xd0, // UDP payload size 1232
+    0x00, 0x00, 0x00, 0x00, // extended RCODE and flags
+    0x00, 0x04, // RDATA length
+    0x00, 0x0b, // option code: edns-tcp-keepalive
+    0x00, 0x00, // option length
+];
+
+/// Frames buffered for the writer task of one connection
+const QUEUE_DEPTH: usize = 64;
+
+/// DoT connection pool settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DotPoolConfig {
+    /// Maximum connections per upstream
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// Queries in flight on one connection before another connection is opened
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+    /// Interval between keepalive probes on idle connections (seconds)
+    #[serde(default = "default_keepalive_secs")]
+    pub keepalive_secs: u64,
+    /// Close connections without queries for this long (seconds)
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+}
+
+fn default_max_connections() -> usize {
+    2
+}
+
+fn default_max_in_flight() -> usize {
+    32
+}
+
+fn default_keepalive_secs() -> u64 {
+    15
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    120
+}
+
+impl Default for DotPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: default_max_connections(),
+            max_in_flight: default_max_in_flight(),
+            keepalive_secs: default_keepalive_secs(),
+            idle_timeout_secs: default_idle_timeout_secs(),
+        }
+    }
+}
+
+impl DotPoolConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=16).contains(&self.max_connections) {
+            bail!("max_connections must be between 1 and 16");
+        }
+        if !(1..=1024).contains(&self.max_in_flight) {
+            bail!("max_in_flight must be between 1 and 1024");
+        }
+        if !(1..=3600).contains(&self.keepalive_secs) {
+            bail!("keepalive_secs must be between 1 and 3600");
+        }
+        if self.idle_timeout_secs < self.keepalive_secs {
+            bail!("idle_timeout_secs must not be shorter than keepalive_secs");
+        }
+        Ok(())
+    }
+}
+
+/// TLS client config shared by all DoT upstreams, so its session cache
+/// outlives individual connections and clients
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut root_store = rustls::RootCertStore::empty();
+            root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+            let mut config = rustls::ClientConfig::builder()
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+            config.resumption = rustls::client::Resumption::in_memory_sessions(256);
+            Arc::new(config)
+        })
+        .clone()
+}
+
+/// Transport a connection is opened with
+enum Connector {
+    Tls,
+    /// Plain TCP, used by tests against stand-in servers
+    #[cfg(test)]
+    Plain,
+}
+
+/// One connection carrying many in-flight queries
+struct Multiplexed {
+    frames: mpsc::Sender<Vec<u8>>,
+    pending: StdMutex<HashMap<u16, oneshot::Sender<Vec<u8>>>>,
+    shutdown: CancellationToken,
+    closed: AtomicBool,
+    last_used: StdMutex<Instant>,
+    /// Idle timeout advertised by the server through EDNS TCP keepalive
+    server_idle: StdMutex<Option<Duration>>,
+}
+
+impl Multiplexed {
+    /// Start reader and writer tasks on an established stream
+    fn spawn<S>(stream: S) -> Arc<Self>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (frames, mut outgoing) = mpsc::channel::<Vec<u8>>(QUEUE_DEPTH);
+        let conn = Arc::new(Self {
+            frames,
+            pending: StdMutex::new(HashMap::new()),
+            shutdown: CancellationToken::new(),
+            closed: AtomicBool::new(false),
+            last_used: StdMutex::new(Instant::now()),
+            server_idle: StdMutex::new(None),
+        });
+
+        let writer_conn = conn.clone();
+        tokio::spawn(async move {
+            loop {
+                let frame = tokio::select! {
+                    _ = writer_conn.shutdown.cancelled() => break,
+                    frame = outgoing.recv() => match frame {
+                        Some(frame) => frame,
+                        None => break,
+                    },
+                };
+                if let Err(e) = writer.write_all(&frame).await {
+                    debug!("DoT write failed: {}", e);
+                    break;
+                }
+            }
+            writer_conn.close();
+            let _ = writer.shutdown().await;
+        });
+
+        let reader_conn = conn.clone();
+        tokio::spawn(async move {
+            let result: Result<()> = async {
+                loop {
+                    let len = tokio::select! {
+                        _ = reader_conn.shutdown.cancelled() => return Ok(()),
+                        len = reader.read_u16() => len? as usize,
+                    };
+                    let mut response = vec![0u8; len];
+                    reader.read_exact(&mut response).await?;
+                    reader_conn.deliver(response);
+                }
+            }
+            .await;
+            if let Err(e) = result {
+                debug!("DoT connection closed: {}", e);
+            }
+            reader_conn.close();
+        });
+
+        conn
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Stop both tasks and fail every outstanding query
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.shutdown.cancel();
+        self.pending.lock().unwrap().clear();
+    }
+
+    fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+
+    /// Hand a response to the query waiting for its ID
+    fn deliver(&self, response: Vec<u8>) {
+        if response.len() < 2 {
+            return;
+        }
+        if let Some(idle) = keepalive_timeout(&response) {
+            *self.server_idle.lock().unwrap() = Some(idle);
+        }
+
+        let id = u16::from_be_bytes([response[0], response[1]]);
+        match self.pending.lock().unwrap().remove(&id) {
+            Some(waiter) => {
+                let _ = waiter.send(response);
+            }
+            None => debug!("Dropping DoT response with unknown ID {}", id),
+        }
+    }
+
+    /// Send a query on this connection and wait for the response with its ID
+    async fn exchange(&self, request: &[u8], wait: Duration) -> Result<Vec<u8>> {
+        if request.len() < 12 {
+            bail!("DNS message too short");
+        }
+
+        // Give the query an ID unique on this connection; the caller's ID is restored later
+        let (id, receiver) = {
+            let mut pending = self.pending.lock().unwrap();
+            let id = loop {
+                let id: u16 = rand::random();
+                if !pending.contains_key(&id) {
+                    break id;
+                }
+            };
+            let (sender, receiver) = oneshot::channel();
+            pending.insert(id, sender);
+            (id, receiver)
+        };
+        let mut registered = Registered { conn: self, id: Some(id) };
+
+        let mut message = request.to_vec();
+        message[..2].copy_from_slice(&id.to_be_bytes());
+        add_keepalive_option(&mut message);
+
+        let mut frame = Vec::with_capacity(message.len() + 2);
+        frame.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&message);
+
+        if self.frames.send(frame).await.is_err() {
+            bail!("DoT connection closed");
+        }
+
+        let result = timeout(wait, receiver).await;
+        if result.is_ok() {
+            // The entry was taken by `deliver` or `close`; its ID may be reused already
+            registered.id = None;
+        }
+        match result {
+            Ok(Ok(mut response)) => {
+                response[..2].copy_from_slice(&request[..2]);
+                Ok(response)
+            }
+            Ok(Err(_)) => bail!("DoT connection closed before the response arrived"),
+            Err(_) => bail!("Read timeout"),
+        }
+    }
+}
+
+/// Query ID registered in `pending`, removed again unless a reply took it
+///
+/// Covers cancelled queries: hedged and concurrent strategies drop the
+/// losers, which must not stay counted as in flight.
+struct Registered<'a> {
+    conn: &'a Multiplexed,
+    id: Option<u16>,
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.conn.pending.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+/// Append an EDNS TCP keepalive option unless the query already carries EDNS
+fn add_keepalive_option(message: &mut Vec<u8>) {
+    let arcount = u16::from_be_bytes([message[10], message[11]]);
+    if arcount != 0 {
+        return;
+    }
+    message[10..12].copy_from_slice(&1u16.to_be_bytes());
+    message.extend_from_slice(&KEEPALIVE_OPT);
+}
+
+/// Idle timeout advertised in a response's EDNS TCP keepalive option
+fn keepalive_timeout(response: &[u8]) -> Option<Duration> {
+    let message = Message::from_vec(response).ok()?;
+    let edns = message.extensions().as_ref()?;
+    match edns.options().get(EdnsCode::Keepalive)? {
+        EdnsOption::Unknown(_, data) if data.len() == 2 => {
+            // Units of 100 milliseconds
+            let units = u16::from_be_bytes([data[0], data[1]]);
+            Some(Duration::from_millis(units as u64 * 100))
+        }
+        _ => None,
+    }
+}
+
+struct ManagerInner {
+    address: String,
+    outbound: OutboundConfig,
+    timeout: Duration,
+    config: DotPoolConfig,
+    connector: Connector,
+    connections: StdMutex<Vec<Arc<Multiplexed>>>,
+    /// Serializes connection setup so a burst of queries opens one connection
+    connecting: Mutex<()>,
+    last_query: StdMutex<Option<Instant>>,
+    maintenance_started: AtomicBool,
+    connects: AtomicUsize,
+}
+
+impl ManagerInner {
+    /// Open a new connection and add it to the pool
+    async fn connect(&self) -> Result<Arc<Multiplexed>> {
+        let (host, port) = parse_host_port(&self.address, UpstreamProtocol::Dot.default_port())?;
+        let stream = self.outbound.connect_tcp(&host, port, self.timeout).await?;
+        stream.set_nodelay(true).ok();
+
+        let conn = match self.connector {
+            Connector::Tls => {
+                use rustls::pki_types::ServerName;
+
+                // For IP addresses, we still need a ServerName but TLS doesn't verify it
+                let server_name = ServerName::try_from(host.clone())
+                    .map_err(|_| anyhow!("Invalid server name: {}", host))?;
+                let connector = tokio_rustls::TlsConnector::from(tls_config());
+                let tls = timeout(self.timeout, connector.connect(server_name, stream))
+                    .await
+                    .map_err(|_| anyhow!("TLS handshake timeout"))??;
+                debug!(
+                    "DoT connected to {}:{} ({:?} handshake)",
+                    host, port, tls.get_ref().1.handshake_kind()
+                );
+                Multiplexed::spawn(tls)
+            }
+            #[cfg(test)]
+            Connector::Plain => Multiplexed::spawn(stream),
+        };
+
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        self.connections.lock().unwrap().push(conn.clone());
+        Ok(conn)
+    }
+
+    /// Pick the least loaded open connection, unless all of them are busy
+    /// and the pool may still grow
+    fn pick(&self) -> Option<Arc<Multiplexed>> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|conn| !conn.is_closed());
+
+        let best = connections.iter().min_by_key(|conn| conn.in_flight())?;
+        if best.in_flight() < self.config.max_in_flight
+            || connections.len() >= self.config.max_connections
+        {
+            return Some(best.clone());
+        }
+        None
+    }
+
+    async fn acquire(&self) -> Result<Arc<Multiplexed>> {
+        if let Some(conn) = self.pick() {
+            return Ok(conn);
+        }
+
+        let _guard = self.connecting.lock().await;
+        if let Some(conn) = self.pick() {
+            return Ok(conn);
+        }
+        self.connect().await
+    }
+
+    /// Periodic upkeep: drop idle connections, probe the rest, reconnect
+    async fn maintain(&self) {
+        let idle_timeout = Duration::from_secs(self.config.idle_timeout_secs);
+        let recently_used = self
+            .last_query
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < idle_timeout);
+
+        let idle: Vec<Arc<Multiplexed>> = {
+            let mut connections = self.connections.lock().unwrap();
+            connections.retain(|conn| {
+                let expired = conn.in_flight() == 0
+                    && (!recently_used || conn.idle_for() >= idle_timeout);
+                if expired {
+                    conn.close();
+                }
+                !conn.is_closed()
+            });
+            connections.iter().filter(|c| c.in_flight() == 0).cloned().collect()
+        };
+
+        if !recently_used {
+            return;
+        }
+
+        for conn in idle {
+            // Reconnect early rather than probing past the server's idle timeout
+            if conn.server_idle.lock().unwrap().is_some_and(|t| conn.idle_for() >= t) {
+                conn.close();
+                continue;
+            }
+            let probe = DnsQuery::new(".", RecordType::NS);
+            if let Ok(bytes) = probe.to_bytes() {
+                if let Err(e) = conn.exchange(&bytes, self.timeout).await {
+                    debug!("DoT keepalive probe to {} failed: {}", self.address, e);
+                    conn.close();
+                }
+            }
+        }
+
+        let empty = self.connections.lock().unwrap().iter().all(|c| c.is_closed());
+        if empty {
+            let _guard = self.connecting.lock().await;
+            if self.pick().is_none() {
+                match self.connect().await {
+                    Ok(_) => debug!("DoT reconnected to {} in the background", self.address),
+                    Err(e) => debug!("DoT background reconnect to {} failed: {}", self.address, e),
+                }
+            }
+        }
+    }
+}
+
+/// Pooled, pipelined DoT connections to one upstream
+pub struct DotConnectionManager {
+    inner: Arc<ManagerInner>,
+}
+
+impl DotConnectionManager {
+    /// Create a manager for the given upstream
+    pub fn new(server: &UpstreamServer, config: DotPoolConfig) -> Self {
+        Self::with_connector(server, config, Connector::Tls)
+    }
+
+    fn with_connector(server: &UpstreamServer, config: DotPoolConfig, connector: Connector) -> Self {
+        Self {
+            inner: Arc::new(ManagerInner {
+                address: server.address.clone(),
+                outbound: server.outbound.clone(),
+                timeout: server.timeout,
+                config,
+                connector,
+                connections: StdMutex::new(Vec::new()),
+                connecting: Mutex::new(()),
+                last_query: StdMutex::new(None),
+                maintenance_started: AtomicBool::new(false),
+                connects: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Number of open connections
+    #[allow(dead_code)]
+    pub fn connection_count(&self) -> usize {
+        self.inner.connections.lock().unwrap().iter().filter(|c| !c.is_closed()).count()
+    }
+
+    /// Number of connections opened so far
+    #[allow(dead_code)]
+    pub fn connects(&self) -> usize {
+        self.inner.connects.load(Ordering::Relaxed)
+    }
+
+    /// Send an encoded query and return the encoded response
+    ///
+    /// The response carries the ID of `request`.
+    pub async fn query(&self, request: &[u8]) -> Result<Vec<u8>> {
+        self.start_maintenance();
+        *self.inner.last_query.lock().unwrap() = Some(Instant::now());
+
+        let conn = self.inner.acquire().await?;
+        *conn.last_used.lock().unwrap() = Instant::now();
+        match conn.exchange(request, self.inner.timeout).await {
+            // The connection died under us: retry once on a fresh one
+            Err(e) if conn.is_closed() => {
+                debug!("DoT connection to {} lost ({}), retrying", self.inner.address, e);
+                let conn = self.inner.acquire().await?;
+                conn.exchange(request, self.inner.timeout).await
+            }
+            result => result,
+        }
+    }
+
+    /// Spawn the keepalive/reconnect task on first use
+    fn start_maintenance(&self) {
+        if self.inner.maintenance_started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let weak: Weak<ManagerInner> = Arc::downgrade(&self.inner);
+        let period = Duration::from_secs(self.inner.config.keepalive_secs);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match weak.upgrade() {
+                    Some(inner) => inner.maintain().await,
+                    None => break,
+                }
+            }
+        });
+    }
+}
+
+impl Drop for DotConnectionManager {
+    fn drop(&mut self) {
+        for conn in self.inner.connections.lock().unwrap().iter() {
+            conn.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, MessageType};
+    use hickory_proto::rr::{RData, Record};
+    use hickory_proto::rr::rdata::A;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::dns::message::DnsResponse;
+
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        /// Answer once `n` queries are buffered, in reverse order
+        Reversed(usize),
+        /// Answer one query, then hang up
+        OneShot,
+    }
+
+    struct StandIn {
+        address: String,
+        accepted: Arc<AtomicUsize>,
+        keepalive_seen: Arc<AtomicBool>,
+    }
+
+    fn answer(request: &Message) -> Vec<u8> {
+        let query = request.queries()[0].clone();
+        let mut response = Message::new();
+        response.set_id(request.id());
+        response.set_message_type(MessageType::Response);
+        response.add_query(query.clone());
+        // Encode the query name length into the address to match answers to questions
+        let marker = query.name().to_ascii().len() as u8;
+        response.add_answer(Record::from_rdata(
+            query.name().clone(), 60, RData::A(A::new(192, 0, 2, marker)),
+        ));
+        let mut edns = Edns::new();
+        edns.options_mut().insert(EdnsOption::Unknown(11, 50u16.to_be_bytes().to_vec()));
+        response.set_edns(edns);
+        response.to_vec().unwrap()
+    }
+
+    async fn read_query(stream: &mut TcpStream) -> Option<Message> {
+        let len = stream.read_u16().await.ok()? as usize;
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await.ok()?;
+        Message::from_vec(&buf).ok()
+    }
+
+    async fn write_frame(stream: &mut TcpStream, bytes: &[u8]) {
+        let mut frame = (bytes.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(bytes);
+        let _ = stream.write_all(&frame).await;
+    }
+
+    async fn stand_in(behaviour: Behaviour) -> StandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let keepalive_seen = Arc::new(AtomicBool::new(false));
+        let (accepted_task, keepalive_task) = (accepted.clone(), keepalive_seen.clone());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                accepted_task.fetch_add(1, Ordering::SeqCst);
+                let keepalive = keepalive_task.clone();
+                tokio::spawn(async move {
+                    let mut buffered = Vec::new();
+                    while let Some(request) = read_query(&mut stream).await {
+                        let has_keepalive = request.extensions().as_ref()
+                            .is_some_and(|e| e.options().get(EdnsCode::Keepalive).is_some());
+                        if has_keepalive {
+                            keepalive.store(true, Ordering::SeqCst);
+                        }
+
+                        match behaviour {
+                            Behaviour::Reversed(n) => {
+                                buffered.push(request);
+                                if buffered.len() == n {
+                                    for request in buffered.drain(..).rev() {
+                                        write_frame(&mut stream, &answer(&request)).await;
+                                    }
+                                }
+                            }
+                            Behaviour::OneShot => {
+                                write_frame(&mut stream, &answer(&request)).await;
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        StandIn { address, accepted, keepalive_seen }
+    }
+
+    fn manager(address: &str, config: DotPoolConfig) -> DotConnectionManager {
+        let server = UpstreamServer::new(1, "DoT", address, UpstreamProtocol::Dot, 2000);
+        DotConnectionManager::with_connector(&server, config, Connector::Plain)
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(DotPoolConfig::default().validate().is_ok());
+        assert!(DotPoolConfig { max_connections: 0, ..Default::default() }.validate().is_err());
+        assert!(DotPoolConfig { keepalive_secs: 60, idle_timeout_secs: 30, ..Default::default() }.validate().is_err());
+
+        let config: DotPoolConfig = serde_json::from_str(r#"{"max_connections": 4}"#).unwrap();
+        assert_eq!(config.max_connections, 4);
+        assert_eq!(config.max_in_flight, 32);
+    }
+
+    #[tokio::test]
+    async fn test_pipelines_concurrent_queries_on_one_connection() {
+        let server = stand_in(Behaviour::Reversed(5)).await;
+        let manager = Arc::new(manager(&server.address, DotPoolConfig::default()));
+
+        let names = ["a.test", "bb.test", "ccc.test", "dddd.test", "eeeee.test"];
+        let tasks: Vec<_> = names.iter().map(|name| {
+            let manager = manager.clone();
+            let name = name.to_string();
+            tokio::spawn(async move {
+                // Same caller ID everywhere: the manager must keep them apart
+                let request = DnsQuery::with_id(7, &name, RecordType::A).to_bytes().unwrap();
+                let response = manager.query(&request).await.unwrap();
+                (name, DnsResponse::from_bytes(&response).unwrap())
+            })
+        }).collect();
+
+        for task in tasks {
+            let (name, response) = task.await.unwrap();
+            assert_eq!(response.id, 7);
+            assert_eq!(response.answers[0].name, name);
+            assert_eq!(response.answers[0].value, format!("192.0.2.{}", name.len() + 1));
+        }
+
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+        assert_eq!(manager.connection_count(), 1);
+        assert!(server.keepalive_seen.load(Ordering::SeqCst));
+
+        let conn = manager.inner.pick().unwrap();
+        assert_eq!(*conn.server_idle.lock().unwrap(), Some(Duration::from_secs(5)));
+    }
+
+    #[tokio::test]
+    async fn test_opens_more_connections_under_load() {
+        let server = stand_in(Behaviour::Reversed(4)).await;
+        let config = DotPoolConfig { max_connections: 2, max_in_flight: 2, ..Default::default() };
+        let manager = Arc::new(manager(&server.address, config));
+
+        let mut tasks = Vec::new();
+        for i in 0..4 {
+            let manager = manager.clone();
+            tasks.push(tokio::spawn(async move {
+                let request = DnsQuery::new(format!("q{}.test", i), RecordType::A).to_bytes().unwrap();
+                manager.query(&request).await
+            }));
+            // Let the query register before the next one picks a connection
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // Each connection holds two queries the server is waiting to batch
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 2);
+        for task in tasks {
+            task.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_query_is_not_left_in_flight() {
+        // The server waits for two queries, so a single one is never answered
+        let server = stand_in(Behaviour::Reversed(2)).await;
+        let manager = manager(&server.address, DotPoolConfig::default());
+
+        let request = DnsQuery::new("lost.test", RecordType::A).to_bytes().unwrap();
+        assert!(timeout(Duration::from_millis(100), manager.query(&request)).await.is_err());
+        assert_eq!(manager.inner.pick().unwrap().in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_server_hangs_up() {
+        let server = stand_in(Behaviour::OneShot).await;
+        let manager = manager(&server.address, DotPoolConfig::default());
+
+        for name in ["one.test", "two.test"] {
+            let request = DnsQuery::new(name, RecordType::A).to_bytes().unwrap();
+            let response = DnsResponse::from_bytes(&manager.query(&request).await.unwrap()).unwrap();
+            assert_eq!(response.answers[0].name, name);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 2);
+
+        // Background upkeep restores a connection for a recently used upstream
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(manager.connection_count(), 0);
+        manager.inner.maintain().await;
+        assert_eq!(manager.connection_count(), 1);
+        assert_eq!(manager.connects(), 3);
+    }
+}
@@ -0,0 +1,252 @@
+//! Upstream Health Probing
+//!
+//! Per-upstream health policy, active probes and health transition events.
+//! Probes run in the background independently of user traffic: a server
+//! whose probes keep failing is taken out of rotation, and only comes back
+//! once probes succeed again.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, Instant};
+
+use crate::dns::message::{DnsQuery, RecordType};
+use super::strategy::ProxyManager;
+use super::upstream::UpstreamServer;
+
+/// Response codes a probe may expect
+const PROBE_RCODES: &[&str] = &["NOERROR", "NXDOMAIN", "SERVFAIL", "REFUSED", "NOTIMP", "FORMERR"];
+
+/// Health policy of one upstream (stored as JSON in `upstream_servers.health_policy`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthPolicy {
+    /// Queries needed before the success rate can suspend the server
+    pub min_queries: u32,
+    /// Success rate (percent) below which user traffic suspends the server
+    pub min_success_percent: u8,
+    /// First suspension length; doubled on each further suspension
+    pub base_suspension_secs: u64,
+    /// Upper bound of the suspension backoff
+    pub max_suspension_secs: u64,
+    /// Send active probes to this server
+    pub probe_enabled: bool,
+    /// Name queried by probes
+    pub probe_name: String,
+    /// Record type queried by probes
+    pub probe_type: RecordType,
+    /// Response code a probe must return to count as a success
+    pub expected_rcode: String,
+    /// Seconds between probes
+    pub probe_interval_secs: u64,
+    /// Consecutive probe failures that take the server down
+    pub failure_threshold: u32,
+    /// Consecutive probe successes that bring a down or suspended server back
+    pub recovery_threshold: u32,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self {
+            min_queries: 5,
+            min_success_percent: 50,
+            base_suspension_secs: 30,
+            max_suspension_secs: 300,
+            probe_enabled: false,
+            probe_name: ".".to_string(),
+            probe_type: RecordType::NS,
+            expected_rcode: "NOERROR".to_string(),
+            probe_interval_secs: 30,
+            failure_threshold: 3,
+            recovery_threshold: 2,
+        }
+    }
+}
+
+impl HealthPolicy {
+    /// Parse a stored policy; an empty value means the defaults
+    pub fn from_json(value: Option<&str>) -> Result<Self> {
+        match value.map(str::trim) {
+            None | Some("") => Ok(Self::default()),
+            Some(json) => {
+                let policy: Self = serde_json::from_str(json)
+                    .map_err(|e| anyhow!("Invalid health policy: {}", e))?;
+                policy.validate()?;
+                Ok(policy)
+            }
+        }
+    }
+
+    /// Check that the policy is within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if self.min_queries == 0 {
+            return Err(anyhow!("min_queries must be at least 1"));
+        }
+        if self.min_success_percent > 100 {
+            return Err(anyhow!("min_success_percent must be between 0 and 100"));
+        }
+        if self.base_suspension_secs == 0 || self.base_suspension_secs > self.max_suspension_secs {
+            return Err(anyhow!("base_suspension_secs must be between 1 and max_suspension_secs"));
+        }
+        if self.max_suspension_secs > 86400 {
+            return Err(anyhow!("max_suspension_secs cannot exceed 86400"));
+        }
+        if self.probe_name.trim().is_empty() {
+            return Err(anyhow!("probe_name cannot be empty"));
+        }
+        if !PROBE_RCODES.contains(&self.expected_rcode.as_str()) {
+            return Err(anyhow!("expected_rcode must be one of {}", PROBE_RCODES.join(", ")));
+        }
+        if !(5..=3600).contains(&self.probe_interval_secs) {
+            return Err(anyhow!("probe_interval_secs must be between 5 and 3600"));
+        }
+        if !(1..=100).contains(&self.failure_threshold) {
+            return Err(anyhow!("failure_threshold must be between 1 and 100"));
+        }
+        if !(1..=100).contains(&self.recovery_threshold) {
+            return Err(anyhow!("recovery_threshold must be between 1 and 100"));
+        }
+        Ok(())
+    }
+}
+
+/// Health state of an upstream as seen by query routing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    /// Available for queries
+    #[default]
+    Healthy,
+    /// Temporarily skipped because user traffic failed too often
+    Suspended,
+    /// Out of rotation because active probes keep failing
+    Down,
+}
+
+impl HealthState {
+    /// Convert to string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthState::Healthy => "healthy",
+            HealthState::Suspended => "suspended",
+            HealthState::Down => "down",
+        }
+    }
+}
+
+impl std::fmt::Display for HealthState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A change of an upstream's health state
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthEvent {
+    pub server_id: i64,
+    pub server_name: String,
+    pub from: HealthState,
+    pub to: HealthState,
+    /// What caused the transition
+    pub reason: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Background task sending probe queries to upstreams with probing enabled
+pub struct HealthProber {
+    proxy: Arc<ProxyManager>,
+    /// Servers with a probe currently in flight
+    in_flight: Mutex<HashSet<i64>>,
+}
+
+impl HealthProber {
+    /// Create a prober using the proxy's upstream clients
+    pub fn new(proxy: Arc<ProxyManager>) -> Self {
+        Self {
+            proxy,
+            in_flight: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Start the probe loop
+    pub fn start(self: Arc<Self>) {
+        tracing::info!("Upstream health prober started");
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(1));
+            let mut next_probe: HashMap<i64, Instant> = HashMap::new();
+            loop {
+                ticker.tick().await;
+                let manager = self.proxy.upstream_manager();
+                // Suspensions that ran out do not pass through a stats update
+                manager.sweep_health().await;
+
+                let now = Instant::now();
+                let servers = manager.get_servers().await;
+                next_probe.retain(|id, _| servers.iter().any(|s| s.id == *id));
+                for server in servers {
+                    if !server.enabled || !server.health.probe_enabled {
+                        next_probe.remove(&server.id);
+                        continue;
+                    }
+                    let due = next_probe.get(&server.id).is_none_or(|at| *at <= now);
+                    if !due || !self.in_flight.lock().unwrap().insert(server.id) {
+                        continue;
+                    }
+                    next_probe.insert(server.id, now + Duration::from_secs(server.health.probe_interval_secs));
+
+                    let prober = self.clone();
+                    tokio::spawn(async move {
+                        let outcome = prober.probe(&server).await;
+                        prober.proxy.upstream_manager().record_probe(server.id, outcome).await;
+                        prober.in_flight.lock().unwrap().remove(&server.id);
+                    });
+                }
+            }
+        });
+    }
+
+    /// Send one probe; returns the response time or why the probe failed
+    pub async fn probe(&self, server: &UpstreamServer) -> std::result::Result<u64, String> {
+        let policy = &server.health;
+        let query = DnsQuery::new(policy.probe_name.clone(), policy.probe_type);
+        match self.proxy.probe(server, &query).await {
+            Ok(result) => {
+                let rcode = result.response.response_code.to_string();
+                if rcode == policy.expected_rcode {
+                    Ok(result.response_time_ms)
+                } else {
+                    Err(format!("unexpected rcode {} (expected {})", rcode, policy.expected_rcode))
+                }
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_policy_json() {
+        assert_eq!(HealthPolicy::from_json(None).unwrap(), HealthPolicy::default());
+        assert_eq!(HealthPolicy::from_json(Some("")).unwrap(), HealthPolicy::default());
+
+        let policy = HealthPolicy::from_json(Some(
+            r#"{"probe_enabled":true,"probe_name":"example.com","probe_type":"A","failure_threshold":5}"#,
+        )).unwrap();
+        assert!(policy.probe_enabled);
+        assert_eq!(policy.probe_type, RecordType::A);
+        assert_eq!(policy.failure_threshold, 5);
+        assert_eq!(policy.recovery_threshold, 2);
+
+        assert!(HealthPolicy::from_json(Some(r#"{"expected_rcode":"BOGUS"}"#)).is_err());
+        assert!(HealthPolicy::from_json(Some(r#"{"probe_interval_secs":1}"#)).is_err());
+        assert!(HealthPolicy::from_json(Some(r#"{"base_suspension_secs":600}"#)).is_err());
+        assert!(HealthPolicy::from_json(Some("not json")).is_err());
+    }
+}
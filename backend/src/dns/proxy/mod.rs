@@ -3,11 +3,20 @@
 //! Provides DNS proxy functionality including:
 //! - Upstream server management
 //! - Multiple protocol support (UDP, DoT, DoH, DoQ)
-//! - Query strategies (concurrent, fastest, round-robin, random)
+//! - Pipelined, pooled DoT connections
+//! - Recursive resolution from the root servers
+//! - Outbound proxies (SOCKS5, HTTP CONNECT) and source binding
+//! - Query strategies (concurrent, fastest, round-robin, random, hedged)
+//! - Anti-pollution routing between domestic and trusted upstream groups
 //! - Failover handling
 
 mod upstream;
 mod client;
+mod outbound;
+mod dot;
+mod health;
+mod recursive;
+mod anti_pollution;
 mod strategy;
 
 #[cfg(test)]
@@ -16,4 +25,9 @@ mod forwarding_tests;
 pub use upstream::*;
 #[allow(unused_imports)]
 pub use client::*;
+pub use outbound::*;
+pub use dot::*;
+pub use health::*;
+pub use recursive::*;
+pub use anti_pollution::*;
 pub use strategy::*;
@@ -0,0 +1,953 @@
+//! Recursive Resolver
+//!
+//! Iterative resolution from the root servers, exposed as a [`DnsClient`]
+//! so it can be configured like any other upstream:
+//! - Follows referrals using in-bailiwick glue, resolving glueless NS names
+//! - Caches delegations (zone cuts and nameserver addresses) separately
+//!   from answers, which stay in the resolver's answer cache
+//! - QNAME minimization (RFC 9156)
+//! - Spoofing protection: random source ports and IDs, 0x20 case
+//!   randomization and strict source/ID/question matching
+//!
+//! The upstream address is either `root` for the built-in root hints or a
+//! comma-separated list of root server addresses (`ip` or `ip:port`).
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::dns::message::{DnsQuery, DnsResponse};
+use super::client::{parse_host_port, DnsClient, QueryResult};
+use super::upstream::{UpstreamProtocol, UpstreamServer};
+
+/// Address keyword selecting the built-in root hints
+pub const ROOT_HINTS_KEYWORD: &str = "root";
+
+/// IANA root server addresses (a.root-servers.net through m.root-servers.net)
+const ROOT_HINTS: &[&str] = &[
+    "198.41.0.4", "170.247.170.2", "192.33.4.12", "199.7.91.13",
+    "192.203.230.10", "192.5.5.241", "192.112.36.4", "198.97.190.53",
+    "192.36.148.17", "192.58.128.30", "193.0.14.129", "199.7.83.42",
+    "202.12.27.33",
+    "2001:503:ba3e::2:30", "2801:1b8:10::b", "2001:500:2::c", "2001:500:2d::d",
+    "2001:500:a8::e", "2001:500:2f::f", "2001:500:12::d0d", "2001:500:1::53",
+    "2001:7fe::53", "2001:503:c27::2:30", "2001:7fd::1", "2001:500:9f::42",
+    "2001:dc3::35",
+];
+
+/// Maximum referrals followed for a single name
+const MAX_REFERRALS: usize = 30;
+/// Maximum CNAME hops followed across zones
+const MAX_CNAME_CHAIN: usize = 8;
+/// Maximum nesting when resolving glueless nameserver names
+const MAX_DEPTH: usize = 4;
+/// Maximum upstream exchanges for one client query
+const MAX_EXCHANGES: usize = 64;
+/// Nameservers tried per zone before giving up
+const SERVERS_PER_ZONE: usize = 3;
+/// Timeout of a single exchange with an authoritative server
+const EXCHANGE_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Upper bound for cached delegations
+const MAX_DELEGATION_TTL: u32 = 86400;
+/// Maximum number of cached zone cuts
+const MAX_CACHED_ZONES: usize = 10_000;
+/// Advertised EDNS UDP payload size
+const EDNS_PAYLOAD: u16 = 1232;
+
+/// Parse an upstream address into root server addresses
+///
+/// A non-standard port on the hints also applies to every delegated
+/// nameserver, which is only useful for lab setups.
+pub fn parse_root_hints(address: &str) -> Result<Vec<SocketAddr>> {
+    let address = address.trim();
+    if address.is_empty() || address.eq_ignore_ascii_case(ROOT_HINTS_KEYWORD) {
+        return Ok(ROOT_HINTS
+            .iter()
+            .map(|ip| SocketAddr::new(ip.parse().unwrap(), UpstreamProtocol::Recursive.default_port()))
+            .collect());
+    }
+
+    let mut hints = Vec::new();
+    for part in address.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (host, port) = parse_host_port(part, UpstreamProtocol::Recursive.default_port())?;
+        let ip: IpAddr = host
+            .parse()
+            .map_err(|_| anyhow!("Root hint '{}' must be an IP address", part))?;
+        hints.push(SocketAddr::new(ip, port));
+    }
+    if hints.is_empty() {
+        bail!("No root hints given");
+    }
+    Ok(hints)
+}
+
+/// A zone cut and the nameservers serving it
+#[derive(Debug, Clone)]
+struct Delegation {
+    zone: Name,
+    nameservers: Vec<Name>,
+    addrs: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+/// Outcome of resolving one name
+#[derive(Debug)]
+struct Resolution {
+    rcode: ResponseCode,
+    answers: Vec<Record>,
+    authority: Vec<Record>,
+}
+
+impl Resolution {
+    /// Convert into the proxy's response model
+    fn into_response(self, id: u16) -> Result<DnsResponse> {
+        let mut message = Message::new();
+        message.set_id(id);
+        message.set_message_type(MessageType::Response);
+        message.set_recursion_available(true);
+        message.set_response_code(self.rcode);
+        message.add_answers(self.answers);
+        message.add_name_servers(self.authority);
+
+        let bytes = message.to_vec().map_err(|e| anyhow!("Failed to encode response: {}", e))?;
+        DnsResponse::from_bytes(&bytes).map_err(|e| anyhow!("Failed to convert response: {}", e))
+    }
+}
+
+/// Upstream exchanges left for the current client query
+struct Budget(usize);
+
+impl Budget {
+    fn spend(&mut self) -> Result<()> {
+        if self.0 == 0 {
+            bail!("Resolution exceeded {} upstream queries", MAX_EXCHANGES);
+        }
+        self.0 -= 1;
+        Ok(())
+    }
+}
+
+type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Result<Resolution>> + Send + 'a>>;
+
+/// Recursive DNS Client
+///
+/// Resolves names iteratively from the configured root hints.
+pub struct RecursiveDnsClient {
+    server: UpstreamServer,
+    roots: Vec<SocketAddr>,
+    /// Port used for delegated nameservers
+    port: u16,
+    delegations: Mutex<HashMap<Name, Delegation>>,
+}
+
+impl RecursiveDnsClient {
+    /// Create a new recursive client
+    pub fn new(server: UpstreamServer) -> Self {
+        let roots = parse_root_hints(&server.address).unwrap_or_else(|e| {
+            tracing::warn!("Invalid root hints for upstream {}: {}", server.name, e);
+            Vec::new()
+        });
+        let port = roots
+            .first()
+            .map(|addr| addr.port())
+            .unwrap_or(UpstreamProtocol::Recursive.default_port());
+
+        Self {
+            server,
+            roots,
+            port,
+            delegations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of cached zone cuts
+    #[allow(dead_code)]
+    pub fn cached_zones(&self) -> usize {
+        self.delegations.lock().unwrap().len()
+    }
+
+    /// Resolve a name, following CNAMEs across zones
+    fn resolve<'a>(
+        &'a self,
+        qname: Name,
+        qtype: RecordType,
+        depth: usize,
+        budget: &'a mut Budget,
+    ) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let mut answers = Vec::new();
+            let mut target = qname.clone();
+
+            for _ in 0..=MAX_CNAME_CHAIN {
+                let step = self.iterate(&target, qtype, depth, budget).await?;
+                let (chain, next) = follow_chain(&target, qtype, &step.answers);
+                answers.extend(chain);
+
+                match next {
+                    Some(alias) => target = alias,
+                    None => {
+                        return Ok(Resolution {
+                            rcode: step.rcode,
+                            answers,
+                            authority: step.authority,
+                        })
+                    }
+                }
+            }
+
+            bail!("CNAME chain too long for {}", qname)
+        })
+    }
+
+    /// Walk the delegation tree down to the servers authoritative for `qname`
+    async fn iterate(
+        &self,
+        qname: &Name,
+        qtype: RecordType,
+        depth: usize,
+        budget: &mut Budget,
+    ) -> Result<Resolution> {
+        let mut zone = self.closest_delegation(qname);
+        let mut labels = zone.zone.num_labels() as usize + 1;
+        let mut minimize = true;
+
+        for _ in 0..MAX_REFERRALS {
+            let minimized = minimize && labels < qname.num_labels() as usize;
+            let (ask, ask_type) = if minimized {
+                (qname.trim_to(labels), RecordType::NS)
+            } else {
+                (qname.clone(), qtype)
+            };
+
+            let response = self.query_zone(&zone, &ask, ask_type, depth, budget).await?;
+
+            if let Some(child) = self.referral(&zone, &ask, &response, minimized) {
+                debug!("Referral for {}: {} -> {}", qname, zone.zone, child.zone);
+                labels = child.zone.num_labels() as usize + 1;
+                self.store_delegation(child.clone());
+                zone = child;
+                continue;
+            }
+
+            if minimized {
+                match response.response_code() {
+                    // Nothing exists below a non-existent name (RFC 8020)
+                    ResponseCode::NXDomain => {
+                        return Ok(Resolution {
+                            rcode: ResponseCode::NXDomain,
+                            answers: Vec::new(),
+                            authority: in_zone(response.name_servers(), &zone.zone, RecordType::SOA),
+                        });
+                    }
+                    // No zone cut at this label, add the next one
+                    ResponseCode::NoError if response.answers().is_empty() => labels += 1,
+                    // Aliases and unexpected answers: ask for the full name
+                    _ => minimize = false,
+                }
+                continue;
+            }
+
+            return match response.response_code() {
+                ResponseCode::NoError | ResponseCode::NXDomain => Ok(Resolution {
+                    rcode: response.response_code(),
+                    answers: response
+                        .answers()
+                        .iter()
+                        .filter(|r| zone.zone.zone_of(r.name()))
+                        .map(normalize)
+                        .collect(),
+                    authority: in_zone(response.name_servers(), &zone.zone, RecordType::SOA),
+                }),
+                code => bail!("Nameservers of '{}' answered {} for {}", zone.zone, code, qname),
+            };
+        }
+
+        bail!("Too many referrals resolving {}", qname)
+    }
+
+    /// Extract a delegation to a zone below `zone` from a response
+    fn referral(&self, zone: &Delegation, ask: &Name, response: &Message, minimized: bool) -> Option<Delegation> {
+        // Authoritative servers for both parent and child answer NS queries directly
+        let records = if response.answers().is_empty() {
+            response.name_servers()
+        } else if minimized {
+            response.answers()
+        } else {
+            return None;
+        };
+
+        let ns_records: Vec<&Record> = records
+            .iter()
+            .filter(|r| r.record_type() == RecordType::NS)
+            .collect();
+        let child = ns_records.first()?.name().to_lowercase();
+
+        // Only accept cuts strictly below the current zone on the path to the name
+        if child.num_labels() <= zone.zone.num_labels() || !zone.zone.zone_of(&child) || !child.zone_of(ask) {
+            return None;
+        }
+
+        let mut nameservers = Vec::new();
+        let mut ttl = MAX_DELEGATION_TTL;
+        for record in ns_records.iter().filter(|r| r.name().to_lowercase() == child) {
+            if let RData::NS(ns) = record.data() {
+                nameservers.push(ns.0.to_lowercase());
+                ttl = ttl.min(record.ttl());
+            }
+        }
+        if nameservers.is_empty() {
+            return None;
+        }
+
+        // Glue is only trusted for names inside the zone of the referring server
+        let addrs = response
+            .additionals()
+            .iter()
+            .filter(|r| zone.zone.zone_of(r.name()) && nameservers.contains(&r.name().to_lowercase()))
+            .filter_map(|r| match r.data() {
+                RData::A(a) => Some(SocketAddr::new(IpAddr::V4(a.0), self.port)),
+                RData::AAAA(aaaa) => Some(SocketAddr::new(IpAddr::V6(aaaa.0), self.port)),
+                _ => None,
+            })
+            .collect();
+
+        Some(Delegation {
+            zone: child,
+            nameservers,
+            addrs,
+            expires: Instant::now() + Duration::from_secs(ttl as u64),
+        })
+    }
+
+    /// Find the deepest cached zone cut above `qname`, falling back to the roots
+    fn closest_delegation(&self, qname: &Name) -> Delegation {
+        let cache = self.delegations.lock().unwrap();
+        let now = Instant::now();
+        let mut name = qname.to_lowercase();
+
+        while !name.is_root() {
+            if let Some(delegation) = cache.get(&name) {
+                if delegation.expires > now {
+                    return delegation.clone();
+                }
+            }
+            name = name.base_name();
+        }
+
+        Delegation {
+            zone: Name::root(),
+            nameservers: Vec::new(),
+            addrs: self.roots.clone(),
+            expires: now,
+        }
+    }
+
+    /// Cache a zone cut
+    fn store_delegation(&self, delegation: Delegation) {
+        let mut cache = self.delegations.lock().unwrap();
+        if cache.len() >= MAX_CACHED_ZONES {
+            let now = Instant::now();
+            cache.retain(|_, d| d.expires > now);
+            if cache.len() >= MAX_CACHED_ZONES {
+                cache.clear();
+            }
+        }
+        cache.insert(delegation.zone.clone(), delegation);
+    }
+
+    /// Addresses of a zone's nameservers, resolving glueless NS names if needed
+    async fn zone_addresses(&self, zone: &Delegation, depth: usize, budget: &mut Budget) -> Result<Vec<SocketAddr>> {
+        if !zone.addrs.is_empty() {
+            return Ok(zone.addrs.clone());
+        }
+        if depth >= MAX_DEPTH {
+            bail!("Nameserver lookup for '{}' nested too deeply", zone.zone);
+        }
+
+        let mut addrs = Vec::new();
+        for ns in &zone.nameservers {
+            // A glueless NS inside its own zone can never be resolved
+            if zone.zone.zone_of(ns) {
+                continue;
+            }
+            match self.resolve(ns.clone(), RecordType::A, depth + 1, budget).await {
+                Ok(resolution) => addrs.extend(resolution.answers.iter().filter_map(|r| match r.data() {
+                    RData::A(a) => Some(SocketAddr::new(IpAddr::V4(a.0), self.port)),
+                    _ => None,
+                })),
+                Err(e) => debug!("Failed to resolve nameserver {}: {}", ns, e),
+            }
+            if !addrs.is_empty() {
+                break;
+            }
+        }
+
+        if addrs.is_empty() {
+            bail!("No reachable nameservers for '{}'", zone.zone);
+        }
+
+        let mut resolved = zone.clone();
+        resolved.addrs = addrs.clone();
+        self.store_delegation(resolved);
+        Ok(addrs)
+    }
+
+    /// Ask the nameservers of a zone, trying a few of them in random order
+    async fn query_zone(
+        &self,
+        zone: &Delegation,
+        name: &Name,
+        qtype: RecordType,
+        depth: usize,
+        budget: &mut Budget,
+    ) -> Result<Message> {
+        let mut addrs = self.zone_addresses(zone, depth, budget).await?;
+        addrs.shuffle(&mut rand::thread_rng());
+        // Prefer the address family of the source binding
+        let prefer_v6 = self.server.outbound.prefers_ipv6();
+        addrs.sort_by_key(|addr| addr.is_ipv6() != prefer_v6);
+
+        let mut last_error = anyhow!("No nameservers for '{}'", zone.zone);
+        for addr in addrs.into_iter().take(SERVERS_PER_ZONE) {
+            budget.spend()?;
+            match self.exchange(addr, name, qtype).await {
+                Ok(response) if matches!(
+                    response.response_code(),
+                    ResponseCode::NoError | ResponseCode::NXDomain
+                ) => return Ok(response),
+                Ok(response) => {
+                    last_error = anyhow!("{} answered {} for {}", addr, response.response_code(), name);
+                }
+                Err(e) => last_error = e,
+            }
+            debug!("Nameserver of '{}' failed: {}", zone.zone, last_error);
+        }
+
+        Err(last_error)
+    }
+
+    /// Send one query over UDP, retrying over TCP when truncated
+    async fn exchange(&self, addr: SocketAddr, name: &Name, qtype: RecordType) -> Result<Message> {
+        let sent_name = randomize_case(name);
+        let id: u16 = rand::random();
+        let request = build_query(id, &sent_name, qtype)?;
+        let wait = EXCHANGE_TIMEOUT.min(self.server.timeout);
+
+        // A fresh socket per query gives every query its own random source port
+        let socket = self.server.outbound.bind_udp(addr.is_ipv6()).await?;
+        socket.send_to(&request, addr).await?;
+
+        let response = timeout(wait, async {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await?;
+                if from != addr {
+                    debug!("Ignoring response from unexpected source {} (expected {})", from, addr);
+                    continue;
+                }
+                match Message::from_vec(&buf[..len]) {
+                    Ok(message) if is_response_to(&message, id, &sent_name, qtype) => return Ok::<_, anyhow::Error>(message),
+                    _ => debug!("Ignoring mismatched response from {}", addr),
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("Query timeout to {} after {:?}", addr, wait))??;
+
+        if response.truncated() {
+            return self.exchange_tcp(addr, &request, id, &sent_name, qtype, wait).await;
+        }
+        Ok(response)
+    }
+
+    /// Repeat a truncated query over TCP
+    async fn exchange_tcp(
+        &self,
+        addr: SocketAddr,
+        request: &[u8],
+        id: u16,
+        sent_name: &Name,
+        qtype: RecordType,
+        wait: Duration,
+    ) -> Result<Message> {
+        let mut stream = self
+            .server
+            .outbound
+            .connect_tcp(&addr.ip().to_string(), addr.port(), wait)
+            .await?;
+
+        timeout(wait, async {
+            stream.write_all(&(request.len() as u16).to_be_bytes()).await?;
+            stream.write_all(request).await?;
+
+            let len = stream.read_u16().await? as usize;
+            let mut buf = vec![0u8; len];
+            stream.read_exact(&mut buf).await?;
+
+            let message = Message::from_vec(&buf)?;
+            if !is_response_to(&message, id, sent_name, qtype) {
+                bail!("Mismatched TCP response from {}", addr);
+            }
+            Ok(message)
+        })
+        .await
+        .map_err(|_| anyhow!("TCP query timeout to {} after {:?}", addr, wait))?
+    }
+}
+
+/// Collect the answer chain for `name`, returning an alias still to resolve
+fn follow_chain(name: &Name, qtype: RecordType, records: &[Record]) -> (Vec<Record>, Option<Name>) {
+    let mut chain = Vec::new();
+    let mut current = name.clone();
+
+    for _ in 0..=MAX_CNAME_CHAIN {
+        let matching: Vec<Record> = records
+            .iter()
+            .filter(|r| r.name() == &current && r.record_type() == qtype)
+            .cloned()
+            .collect();
+        if !matching.is_empty() {
+            chain.extend(matching);
+            return (chain, None);
+        }
+
+        let alias = records.iter().find_map(|r| match r.data() {
+            RData::CNAME(cname) if r.name() == &current && qtype != RecordType::CNAME => {
+                Some((r.clone(), cname.0.to_lowercase()))
+            }
+            _ => None,
+        });
+        match alias {
+            Some((record, target)) => {
+                chain.push(record);
+                current = target;
+            }
+            None => break,
+        }
+    }
+
+    let next = (&current != name).then_some(current);
+    (chain, next)
+}
+
+/// Records of one type inside a zone, with normalized owner names
+fn in_zone(records: &[Record], zone: &Name, record_type: RecordType) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|r| r.record_type() == record_type && zone.zone_of(r.name()))
+        .map(normalize)
+        .collect()
+}
+
+/// Lowercase the owner name echoed back with randomized case
+fn normalize(record: &Record) -> Record {
+    let mut record = record.clone();
+    let name = record.name().to_lowercase();
+    record.set_name(name);
+    record
+}
+
+/// Randomize the case of every letter in a name (DNS 0x20)
+pub(super) fn randomize_case(name: &Name) -> Name {
+    let mut rng = rand::thread_rng();
+    let mixed: String = name
+        .to_ascii()
+        .chars()
+        .map(|c| if rng.gen::<bool>() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+        .collect();
+    Name::from_ascii(&mixed).unwrap_or_else(|_| name.clone())
+}
+
+/// Build a non-recursive query message
+fn build_query(id: u16, name: &Name, qtype: RecordType) -> Result<Vec<u8>> {
+    let mut edns = Edns::new();
+    edns.set_max_payload(EDNS_PAYLOAD);
+
+    let mut message = Message::new();
+    message.set_id(id);
+    message.set_message_type(MessageType::Query);
+    message.set_op_code(OpCode::Query);
+    message.set_recursion_desired(false);
+    message.add_query(Query::query(name.clone(), qtype));
+    message.set_edns(edns);
+
+    message.to_vec().map_err(|e| anyhow!("Failed to encode query: {}", e))
+}
+
+/// Check that a message answers exactly the question we sent, case included
+fn is_response_to(message: &Message, id: u16, name: &Name, qtype: RecordType) -> bool {
+    message.id() == id
+        && message.message_type() == MessageType::Response
+        && message.op_code() == OpCode::Query
+        && message.queries().len() == 1
+        && message.queries()[0].name().eq_case(name)
+        && message.queries()[0].query_type() == qtype
+}
+
+#[async_trait]
+impl DnsClient for RecursiveDnsClient {
+    async fn query(&self, query: &DnsQuery) -> Result<QueryResult> {
+        if self.roots.is_empty() {
+            bail!("No usable root hints configured for {}", self.server.name);
+        }
+
+        let mut fqdn = query.name.clone();
+        if !fqdn.ends_with('.') {
+            fqdn.push('.');
+        }
+        let name = Name::from_ascii(&fqdn)
+            .map_err(|e| anyhow!("Invalid query name '{}': {}", query.name, e))?
+            .to_lowercase();
+
+        let start = Instant::now();
+        let mut budget = Budget(MAX_EXCHANGES);
+        let resolution = timeout(
+            self.server.timeout,
+            self.resolve(name, query.record_type.to_trust_dns(), 0, &mut budget),
+        )
+        .await
+        .map_err(|_| anyhow!("Recursive resolution timeout after {:?}", self.server.timeout))??;
+
+        Ok(QueryResult {
+            response: resolution.into_response(query.id)?,
+            response_time_ms: start.elapsed().as_millis() as u64,
+            server_id: self.server.id,
+            server_name: self.server.name.clone(),
+            decision: None,
+        })
+    }
+
+    fn server(&self) -> &UpstreamServer {
+        &self.server
+    }
+
+    async fn health_check(&self) -> Result<Duration> {
+        // Priming query: the root NS set
+        let query = DnsQuery::new(".", crate::dns::message::RecordType::NS);
+        let start = Instant::now();
+
+        let _ = self.query(&query).await?;
+
+        Ok(start.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use hickory_proto::rr::rdata::{A, CNAME, NS, SOA};
+    use tokio::net::UdpSocket;
+
+    use crate::dns::message::{DnsResponseCode, RecordType as QueryType};
+
+    const ROOT_IP: &str = "127.0.0.11";
+    const TLD_IP: &str = "127.0.0.12";
+    const ZONE_IP: &str = "127.0.0.13";
+
+    type QueryLog = Arc<Mutex<Vec<String>>>;
+    type Handler = fn(&Name, RecordType) -> Message;
+
+    fn name(s: &str) -> Name {
+        Name::from_ascii(s).unwrap()
+    }
+
+    fn soa(zone: &str) -> Record {
+        Record::from_rdata(
+            name(zone),
+            60,
+            RData::SOA(SOA::new(name(zone), name(zone), 1, 3600, 600, 86400, 60)),
+        )
+    }
+
+    fn a(owner: &str, ip: &str) -> Record {
+        Record::from_rdata(name(owner), 60, RData::A(A(ip.parse().unwrap())))
+    }
+
+    fn referral(zone: &str, ns: &str, glue: Option<&str>) -> Message {
+        let mut message = Message::new();
+        message.add_name_server(Record::from_rdata(name(zone), 3600, RData::NS(NS(name(ns)))));
+        if let Some(ip) = glue {
+            message.add_additional(a(ns, ip));
+        }
+        message
+    }
+
+    fn negative(rcode: ResponseCode, zone: &str) -> Message {
+        let mut message = Message::new();
+        message.set_authoritative(true);
+        message.set_response_code(rcode);
+        message.add_name_server(soa(zone));
+        message
+    }
+
+    fn root_zone(qname: &Name, _qtype: RecordType) -> Message {
+        if name("test.").zone_of(qname) {
+            return referral("test.", "ns.test.", Some(TLD_IP));
+        }
+        negative(ResponseCode::NXDomain, ".")
+    }
+
+    fn tld_zone(qname: &Name, _qtype: RecordType) -> Message {
+        if name("example.test.").zone_of(qname) {
+            return referral("example.test.", "ns1.example.test.", Some(ZONE_IP));
+        }
+        if name("other.test.").zone_of(qname) {
+            // Glueless: the nameserver lives in a sibling zone
+            return referral("other.test.", "ns1.example.test.", None);
+        }
+        negative(ResponseCode::NXDomain, "test.")
+    }
+
+    fn authoritative_zone(qname: &Name, qtype: RecordType) -> Message {
+        let mut message = Message::new();
+        message.set_authoritative(true);
+
+        let owner = qname.to_ascii();
+        match (owner.as_str(), qtype) {
+            ("www.example.test.", RecordType::A) => {
+                message.add_answer(a("www.example.test.", "192.0.2.1"));
+            }
+            ("ns1.example.test.", RecordType::A) => {
+                message.add_answer(a("ns1.example.test.", ZONE_IP));
+            }
+            ("host.other.test.", RecordType::A) => {
+                message.add_answer(a("host.other.test.", "192.0.2.2"));
+            }
+            ("alias.example.test.", _) => {
+                message.add_answer(Record::from_rdata(
+                    name("alias.example.test."),
+                    60,
+                    RData::CNAME(CNAME(name("www.example.test."))),
+                ));
+                message.add_answer(a("www.example.test.", "192.0.2.1"));
+            }
+            ("alias.other.test.", _) => {
+                // Target in another zone, left for the resolver to chase
+                message.add_answer(Record::from_rdata(
+                    name("alias.other.test."),
+                    60,
+                    RData::CNAME(CNAME(name("www.example.test."))),
+                ));
+            }
+            ("www.example.test.", _) | ("example.test.", _) | ("other.test.", _) => {
+                return negative(ResponseCode::NoError, "example.test.");
+            }
+            _ => return negative(ResponseCode::NXDomain, "example.test."),
+        }
+        message
+    }
+
+    /// Forged answer carrying a poisoned address
+    fn forged(id: u16, query: &Query) -> Vec<u8> {
+        let mut message = Message::new();
+        message.set_id(id);
+        message.set_message_type(MessageType::Response);
+        message.add_query(query.clone());
+        message.add_answer(a(&query.name().to_lowercase().to_ascii(), "6.6.6.6"));
+        message.to_vec().unwrap()
+    }
+
+    /// Spawn a stand-in authoritative server that logs every question
+    async fn spawn_server(ip: &str, port: u16, handler: Handler, forge: bool) -> (u16, QueryLog) {
+        let socket = UdpSocket::bind(SocketAddr::new(ip.parse().unwrap(), port)).await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let log: QueryLog = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let Ok((len, from)) = socket.recv_from(&mut buf).await else { break };
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                assert!(!request.recursion_desired());
+                server_log.lock().unwrap().push(format!(
+                    "{} {}",
+                    query.name().to_lowercase().to_ascii(),
+                    query.query_type()
+                ));
+
+                if forge {
+                    // Wrong ID, then right ID with a different question
+                    let _ = socket.send_to(&forged(request.id().wrapping_add(1), &query), from).await;
+                    let mut other = query.clone();
+                    other.set_name(name("evil.example.test."));
+                    let _ = socket.send_to(&forged(request.id(), &other), from).await;
+                }
+
+                let mut response = handler(&query.name().to_lowercase(), query.query_type());
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                response.add_query(query);
+                let _ = socket.send_to(&response.to_vec().unwrap(), from).await;
+            }
+        });
+
+        (port, log)
+    }
+
+    struct Hierarchy {
+        client: RecursiveDnsClient,
+        root_log: QueryLog,
+        tld_log: QueryLog,
+        zone_log: QueryLog,
+    }
+
+    async fn hierarchy(forge: bool) -> Hierarchy {
+        let (port, root_log) = spawn_server(ROOT_IP, 0, root_zone, false).await;
+        let (_, tld_log) = spawn_server(TLD_IP, port, tld_zone, false).await;
+        let (_, zone_log) = spawn_server(ZONE_IP, port, authoritative_zone, forge).await;
+
+        let server = UpstreamServer::new(
+            1, "Recursive", format!("{}:{}", ROOT_IP, port), UpstreamProtocol::Recursive, 5000,
+        );
+        Hierarchy {
+            client: RecursiveDnsClient::new(server),
+            root_log,
+            tld_log,
+            zone_log,
+        }
+    }
+
+    fn addresses(result: &QueryResult) -> Vec<String> {
+        result.response.answers.iter()
+            .filter(|r| r.record_type == QueryType::A)
+            .map(|r| r.value.clone())
+            .collect()
+    }
+
+    fn logged(log: &QueryLog) -> Vec<String> {
+        log.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_parse_root_hints() {
+        assert_eq!(parse_root_hints("root").unwrap().len(), ROOT_HINTS.len());
+        assert_eq!(
+            parse_root_hints("127.0.0.1:5353, [::1]").unwrap(),
+            vec!["127.0.0.1:5353".parse().unwrap(), "[::1]:53".parse().unwrap()]
+        );
+        assert!(parse_root_hints("a.root-servers.net").is_err());
+    }
+
+    #[test]
+    fn test_question_matching() {
+        let qname = name("www.example.test.");
+        let sent = randomize_case(&qname);
+        assert_eq!(sent, qname);
+
+        let mut message = Message::new();
+        message.set_id(7);
+        message.set_message_type(MessageType::Response);
+        message.add_query(Query::query(sent.clone(), RecordType::A));
+        assert!(is_response_to(&message, 7, &sent, RecordType::A));
+        assert!(!is_response_to(&message, 8, &sent, RecordType::A));
+        assert!(!is_response_to(&message, 7, &sent, RecordType::AAAA));
+
+        // A reply that does not echo the randomized case is rejected
+        let upper = Name::from_ascii("WWW.EXAMPLE.TEST.").unwrap();
+        let lower = Name::from_ascii("www.example.test.").unwrap();
+        let mut message = Message::new();
+        message.set_id(7);
+        message.set_message_type(MessageType::Response);
+        message.add_query(Query::query(lower, RecordType::A));
+        assert!(!is_response_to(&message, 7, &upper, RecordType::A));
+    }
+
+    #[test]
+    fn test_follow_chain() {
+        let records = vec![
+            Record::from_rdata(name("a.test."), 60, RData::CNAME(CNAME(name("b.test.")))),
+            Record::from_rdata(name("b.test."), 60, RData::CNAME(CNAME(name("c.other.")))),
+        ];
+        let (chain, next) = follow_chain(&name("a.test."), RecordType::A, &records);
+        assert_eq!(chain.len(), 2);
+        assert_eq!(next, Some(name("c.other.")));
+
+        let records = vec![a("c.other.", "192.0.2.9")];
+        let (chain, next) = follow_chain(&name("c.other."), RecordType::A, &records);
+        assert_eq!(chain.len(), 1);
+        assert_eq!(next, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolves_through_referrals_with_minimization() {
+        let h = hierarchy(false).await;
+
+        let result = h.client.query(&DnsQuery::new("www.example.test", QueryType::A)).await.unwrap();
+        assert_eq!(result.response.response_code, DnsResponseCode::NoError);
+        assert_eq!(addresses(&result), vec!["192.0.2.1"]);
+
+        // Each level only sees the labels it needs
+        assert_eq!(logged(&h.root_log), vec!["test. NS"]);
+        assert_eq!(logged(&h.tld_log), vec!["example.test. NS"]);
+        assert_eq!(logged(&h.zone_log), vec!["www.example.test. A"]);
+        assert_eq!(h.client.cached_zones(), 2);
+
+        // Delegations are cached: later queries go straight to the zone
+        let result = h.client.query(&DnsQuery::new("alias.example.test", QueryType::A)).await.unwrap();
+        assert_eq!(addresses(&result), vec!["192.0.2.1"]);
+        assert_eq!(result.response.answers[0].record_type, QueryType::CNAME);
+        assert_eq!(logged(&h.root_log).len(), 1);
+        assert_eq!(logged(&h.tld_log).len(), 1);
+
+        let result = h.client.query(&DnsQuery::new("missing.example.test", QueryType::A)).await.unwrap();
+        assert_eq!(result.response.response_code, DnsResponseCode::NxDomain);
+        assert_eq!(result.response.authority[0].record_type, QueryType::SOA);
+    }
+
+    #[tokio::test]
+    async fn test_minimized_nxdomain_stops_early() {
+        let h = hierarchy(false).await;
+
+        let result = h.client.query(&DnsQuery::new("a.b.nowhere", QueryType::A)).await.unwrap();
+        assert_eq!(result.response.response_code, DnsResponseCode::NxDomain);
+        assert_eq!(logged(&h.root_log), vec!["nowhere. NS"]);
+        assert!(logged(&h.tld_log).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_glueless_delegation_and_cross_zone_cname() {
+        let h = hierarchy(false).await;
+
+        let result = h.client.query(&DnsQuery::new("host.other.test", QueryType::A)).await.unwrap();
+        assert_eq!(addresses(&result), vec!["192.0.2.2"]);
+        assert!(logged(&h.zone_log).contains(&"ns1.example.test. A".to_string()));
+
+        let result = h.client.query(&DnsQuery::new("alias.other.test", QueryType::A)).await.unwrap();
+        assert_eq!(result.response.answers.len(), 2);
+        assert_eq!(addresses(&result), vec!["192.0.2.1"]);
+    }
+
+    #[tokio::test]
+    async fn test_ignores_forged_responses() {
+        let h = hierarchy(true).await;
+
+        let result = h.client.query(&DnsQuery::new("www.example.test", QueryType::A)).await.unwrap();
+        assert_eq!(addresses(&result), vec!["192.0.2.1"]);
+        assert_eq!(result.response.answers[0].name, "www.example.test");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_roots_fail() {
+        let server = UpstreamServer::new(
+            1, "Recursive", "127.0.0.1:1", UpstreamProtocol::Recursive, 500,
+        );
+        let client = RecursiveDnsClient::new(server);
+        assert!(client.query(&DnsQuery::new("example.com", QueryType::A)).await.is_err());
+    }
+}
@@ -5,9 +5,11 @@
 //! - Fastest: Use the server with the best historical response time
 //! - RoundRobin: Rotate through servers sequentially
 //! - Random: Select a random server for each query
+//! - Hedged: Query the best server, hedge to the next-best if it is slow
 
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use rand::Rng;
@@ -16,14 +18,32 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::dns::message::DnsQuery;
-use super::client::{create_client, DnsClient, QueryResult};
-use super::upstream::{UpstreamManager, UpstreamServer};
+use super::anti_pollution::{AntiPollution, AntiPollutionConfig, DomesticVerdict};
+use super::client::{create_client, DnsClient, Doh3DnsClient, DohDnsClient, DoqDnsClient, DotDnsClient, QueryResult, UdpClientConfig, UdpDnsClient};
+use crate::dns::dnstap::Dnstap;
+use super::dot::DotPoolConfig;
+use super::upstream::{UpstreamGroup, UpstreamManager, UpstreamProtocol, UpstreamServer};
 use std::collections::HashMap;
 use tokio::sync::Mutex;
 
 /// Global counter for query failures
 static TOTAL_FAILURE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
+/// Hedge delay used when the primary server has no latency data yet
+const HEDGE_DEFAULT_DELAY_MS: u64 = 100;
+/// Lower bound for the hedge delay
+const HEDGE_MIN_DELAY_MS: u64 = 10;
+/// Upper bound for the hedge delay
+const HEDGE_MAX_DELAY_MS: u64 = 1000;
+
+/// Compute the hedge delay from the primary server's recent p90 latency
+fn hedge_delay(p90_ms: Option<u64>) -> Duration {
+    let ms = p90_ms
+        .map(|p90| p90.clamp(HEDGE_MIN_DELAY_MS, HEDGE_MAX_DELAY_MS))
+        .unwrap_or(HEDGE_DEFAULT_DELAY_MS);
+    Duration::from_millis(ms)
+}
+
 /// Query strategy types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -36,6 +56,8 @@ pub enum QueryStrategy {
     RoundRobin,
     /// Select a random server for each query
     Random,
+    /// Query the best server, hedge to the next-best after its p90 latency
+    Hedged,
 }
 
 impl QueryStrategy {
@@ -46,6 +68,7 @@ impl QueryStrategy {
             "fastest" | "fastest_first" => Some(QueryStrategy::Fastest),
             "round_robin" | "roundrobin" => Some(QueryStrategy::RoundRobin),
             "random" => Some(QueryStrategy::Random),
+            "hedged" => Some(QueryStrategy::Hedged),
             _ => None,
         }
     }
@@ -57,6 +80,7 @@ impl QueryStrategy {
             QueryStrategy::Fastest => "fastest",
             QueryStrategy::RoundRobin => "round_robin",
             QueryStrategy::Random => "random",
+            QueryStrategy::Hedged => "hedged",
         }
     }
 }
@@ -86,6 +110,14 @@ pub struct ProxyManager {
     round_robin_counter: AtomicUsize,
     /// Upstream client cache (keyed by UpstreamServer)
     client_cache: Mutex<HashMap<UpstreamServer, Arc<dyn DnsClient>>>,
+    /// Anti-pollution routing rules (None when disabled)
+    anti_pollution: RwLock<Option<Arc<AntiPollution>>>,
+    /// Connection pool settings for DoT upstreams
+    dot_pool: RwLock<DotPoolConfig>,
+    /// Retry and 0x20 settings for UDP upstreams
+    udp_client: RwLock<UdpClientConfig>,
+    /// dnstap logger handed to upstream clients
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 #[allow(dead_code)]
@@ -97,9 +129,19 @@ impl ProxyManager {
             strategy: RwLock::new(QueryStrategy::default()),
             round_robin_counter: AtomicUsize::new(0),
             client_cache: Mutex::new(HashMap::new()),
+            anti_pollution: RwLock::new(None),
+            dot_pool: RwLock::new(DotPoolConfig::default()),
+            udp_client: RwLock::new(UdpClientConfig::default()),
+            dnstap: None,
         }
     }
 
+    /// Log upstream exchanges through `dnstap`
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.dnstap = Some(dnstap);
+        self
+    }
+
     /// Create a new proxy manager wrapped in Arc
     pub fn new_shared(upstream_manager: Arc<UpstreamManager>) -> Arc<Self> {
         Arc::new(Self::new(upstream_manager))
@@ -116,11 +158,63 @@ impl ProxyManager {
         *current = strategy;
     }
 
+    /// Apply anti-pollution settings; a disabled config turns routing off
+    pub async fn set_anti_pollution(&self, config: &AntiPollutionConfig) -> Result<()> {
+        let rules = if config.enabled {
+            Some(Arc::new(AntiPollution::from_config(config)?))
+        } else {
+            None
+        };
+        *self.anti_pollution.write().await = rules;
+        Ok(())
+    }
+
+    /// Check whether anti-pollution routing is active
+    pub async fn anti_pollution_enabled(&self) -> bool {
+        self.anti_pollution.read().await.is_some()
+    }
+
+    /// Apply DoT connection pool settings; existing DoT clients are replaced
+    pub async fn set_dot_pool(&self, config: DotPoolConfig) -> Result<()> {
+        config.validate()?;
+        *self.dot_pool.write().await = config;
+        self.client_cache.lock().await
+            .retain(|server, _| server.protocol != UpstreamProtocol::Dot);
+        Ok(())
+    }
+
+    /// Get the DoT connection pool settings
+    pub async fn dot_pool(&self) -> DotPoolConfig {
+        *self.dot_pool.read().await
+    }
+
+    /// Apply UDP client settings; existing UDP clients are replaced
+    pub async fn set_udp_client(&self, config: UdpClientConfig) -> Result<()> {
+        config.validate()?;
+        *self.udp_client.write().await = config;
+        self.client_cache.lock().await
+            .retain(|server, _| server.protocol != UpstreamProtocol::Udp);
+        Ok(())
+    }
+
+    /// Get the UDP client settings
+    pub async fn udp_client(&self) -> UdpClientConfig {
+        *self.udp_client.read().await
+    }
+
     /// Get the upstream manager
     pub fn upstream_manager(&self) -> &Arc<UpstreamManager> {
         &self.upstream_manager
     }
 
+    /// Send a health probe to one server, bypassing strategy and statistics
+    pub async fn probe(&self, server: &UpstreamServer, query: &DnsQuery) -> Result<QueryResult> {
+        let client = self.get_client(server).await;
+        tokio::time::timeout(server.timeout, client.query(query))
+            .await
+            .map_err(|_| anyhow!("Probe timeout after {:?}", server.timeout))?
+    }
+
     /// Get or create a client for the given server
     async fn get_client(&self, server: &UpstreamServer) -> Arc<dyn DnsClient> {
         let mut cache = self.client_cache.lock().await;
@@ -129,7 +223,23 @@ impl ProxyManager {
             return client.clone();
         }
 
-        let client: Arc<dyn DnsClient> = Arc::from(create_client(server.clone()));
+        let dnstap = self.dnstap.clone();
+        let client: Arc<dyn DnsClient> = match server.protocol {
+            UpstreamProtocol::Dot => {
+                let config = *self.dot_pool.read().await;
+                Arc::new(DotDnsClient::with_pool(server.clone(), config).with_dnstap(dnstap))
+            }
+            UpstreamProtocol::Udp => {
+                let config = *self.udp_client.read().await;
+                Arc::new(UdpDnsClient::with_config(server.clone(), config)
+                    .with_stats(self.upstream_manager.clone())
+                    .with_dnstap(dnstap))
+            }
+            UpstreamProtocol::Doh => Arc::new(DohDnsClient::new(server.clone()).with_dnstap(dnstap)),
+            UpstreamProtocol::Doq => Arc::new(DoqDnsClient::new(server.clone()).with_dnstap(dnstap)),
+            UpstreamProtocol::Doh3 => Arc::new(Doh3DnsClient::new(server.clone()).with_dnstap(dnstap)),
+            UpstreamProtocol::Recursive => Arc::from(create_client(server.clone())),
+        };
         cache.insert(server.clone(), client.clone());
         client
     }
@@ -142,11 +252,10 @@ impl ProxyManager {
         let strategy = self.get_strategy().await;
         info!("[{}] Query start: {} {} using {}", trace_id, query.name, query.record_type, strategy);
         
-        let result = match strategy {
-            QueryStrategy::Concurrent => self.query_concurrent(query, &trace_id).await,
-            QueryStrategy::Fastest => self.query_fastest(query, &trace_id).await,
-            QueryStrategy::RoundRobin => self.query_round_robin(query, &trace_id).await,
-            QueryStrategy::Random => self.query_random(query, &trace_id).await,
+        let anti_pollution = self.anti_pollution.read().await.clone();
+        let result = match anti_pollution {
+            Some(rules) => self.query_anti_pollution(&rules, strategy, query, &trace_id).await,
+            None => self.query_with_strategy(strategy, query, &trace_id).await,
         };
         
         match &result {
@@ -161,15 +270,115 @@ impl ProxyManager {
         result
     }
 
+    /// Dispatch a query to the given strategy
+    async fn query_with_strategy(&self, strategy: QueryStrategy, query: &DnsQuery, trace_id: &str) -> Result<QueryResult> {
+        match strategy {
+            QueryStrategy::Concurrent => self.query_concurrent(query, trace_id).await,
+            QueryStrategy::Fastest => self.query_fastest(query, trace_id).await,
+            QueryStrategy::RoundRobin => self.query_round_robin(query, trace_id).await,
+            QueryStrategy::Random => self.query_random(query, trace_id).await,
+            QueryStrategy::Hedged => self.query_hedged(query, trace_id).await,
+        }
+    }
+
+    /// Query the domestic and trusted groups together and pick an answer
+    ///
+    /// Falls back to the configured strategy when either group has no
+    /// healthy servers.
+    async fn query_anti_pollution(
+        &self,
+        rules: &AntiPollution,
+        strategy: QueryStrategy,
+        query: &DnsQuery,
+        trace_id: &str,
+    ) -> Result<QueryResult> {
+        use tracing::{info, warn};
+
+        let servers = self.upstream_manager.get_healthy_servers().await;
+        let (domestic, trusted): (Vec<_>, Vec<_>) = servers.into_iter()
+            .filter(|s| s.group != UpstreamGroup::Default)
+            .partition(|s| s.group == UpstreamGroup::Domestic);
+
+        if domestic.is_empty() || trusted.is_empty() {
+            warn!(
+                "[{}] [AntiPollution] Need healthy domestic and trusted servers (have {} / {}), using {}",
+                trace_id, domestic.len(), trusted.len(), strategy
+            );
+            return self.query_with_strategy(strategy, query, trace_id).await;
+        }
+
+        // Domain-list override: only query the forced group
+        if let Some(group) = rules.match_domain(&query.name) {
+            let servers = if group == UpstreamGroup::Domestic { domestic } else { trusted };
+            info!("[{}] [AntiPollution] Domain list forces {} group", trace_id, group);
+            let mut result = self.query_concurrent_servers(servers, query, trace_id).await?;
+            result.decision = Some(format!("anti-pollution: domain list -> {}", group));
+            return Ok(result);
+        }
+
+        let domestic_fut = self.query_concurrent_servers(domestic, query, trace_id);
+        let trusted_fut = self.query_concurrent_servers(trusted, query, trace_id);
+        tokio::pin!(domestic_fut);
+        tokio::pin!(trusted_fut);
+
+        // Wait for the domestic answer, keeping the trusted one if it arrives first
+        let mut trusted_result = None;
+        let domestic_result = loop {
+            tokio::select! {
+                result = &mut domestic_fut => break result,
+                result = &mut trusted_fut, if trusted_result.is_none() => trusted_result = Some(result),
+            }
+        };
+
+        let reason = match &domestic_result {
+            Ok(result) => match rules.judge(&result.response) {
+                DomesticVerdict::Accept => None,
+                DomesticVerdict::Foreign(ip) => Some(format!("domestic answer {} is not local", ip)),
+                DomesticVerdict::NoAddresses => Some("domestic answer has no A/AAAA records".to_string()),
+            },
+            Err(e) => Some(format!("domestic group failed: {}", e)),
+        };
+
+        let Some(reason) = reason else {
+            let mut result = domestic_result?;
+            info!("[{}] [AntiPollution] Domestic answer accepted from {}", trace_id, result.server_name);
+            result.decision = Some("anti-pollution: domestic answer accepted (all IPs local)".to_string());
+            return Ok(result);
+        };
+
+        let trusted_result = match trusted_result {
+            Some(result) => result,
+            None => trusted_fut.await,
+        };
+
+        match (trusted_result, domestic_result) {
+            (Ok(mut result), _) => {
+                info!("[{}] [AntiPollution] Using trusted answer from {}: {}", trace_id, result.server_name, reason);
+                result.decision = Some(format!("anti-pollution: trusted answer used ({})", reason));
+                Ok(result)
+            }
+            (Err(e), Ok(mut result)) => {
+                warn!("[{}] [AntiPollution] Trusted group failed ({}), falling back to domestic answer", trace_id, e);
+                result.decision = Some(format!("anti-pollution: trusted group failed, domestic answer used ({})", reason));
+                Ok(result)
+            }
+            (Err(e), Err(_)) => Err(anyhow!("All upstream groups failed: {}; trusted group failed: {}", reason, e)),
+        }
+    }
+
     /// Query all servers concurrently, return first successful response and cancel others
     async fn query_concurrent(&self, query: &DnsQuery, trace_id: &str) -> Result<QueryResult> {
+        let servers = self.upstream_manager.get_healthy_servers().await;
+        self.query_concurrent_servers(servers, query, trace_id).await
+    }
+
+    /// Query the given servers concurrently, return first successful response and cancel others
+    async fn query_concurrent_servers(&self, servers: Vec<UpstreamServer>, query: &DnsQuery, trace_id: &str) -> Result<QueryResult> {
         use tracing::{debug, info, warn};
         use crate::dns::message::DnsResponseCode;
         use tokio::select;
         use tokio_util::sync::CancellationToken;
-        
-        let servers = self.upstream_manager.get_healthy_servers().await;
-        
+
         if servers.is_empty() {
             return Err(anyhow!("No healthy upstream servers available"));
         }
@@ -179,8 +388,9 @@ impl ProxyManager {
             .collect();
         info!("[{}] [Concurrent] Querying {} servers: {}", trace_id, servers.len(), server_info.join(", "));
 
-        // Create cancellation token for all tasks
+        // Create cancellation token for all tasks (also cancelled if this future is dropped)
         let cancel_token = CancellationToken::new();
+        let _cancel_guard = cancel_token.clone().drop_guard();
         let mut handles = Vec::with_capacity(servers.len());
 
         // Spawn concurrent queries to all servers
@@ -380,6 +590,135 @@ impl ProxyManager {
         self.query_server(server, query, trace_id).await
     }
 
+    /// Query the best server and hedge to the next-best one if no answer
+    /// arrives within the primary's recent p90 latency; first valid answer wins
+    async fn query_hedged(&self, query: &DnsQuery, trace_id: &str) -> Result<QueryResult> {
+        use tracing::info;
+
+        let servers = self.upstream_manager.get_ranked_servers().await;
+
+        if servers.is_empty() {
+            return Err(anyhow!("No healthy upstream servers available"));
+        }
+        if servers.len() == 1 {
+            return self.query_server(servers[0].clone(), query, trace_id).await;
+        }
+
+        let primary = servers[0].clone();
+        let secondary = servers[1].clone();
+        let delay = hedge_delay(
+            self.upstream_manager.get_stats(primary.id).await
+                .and_then(|s| s.p90_response_time_ms()),
+        );
+
+        info!(
+            "[{}] [Hedged] Primary: {} (addr: {}, protocol: {}), hedge: {} after {}ms",
+            trace_id, primary.name, primary.address, primary.protocol,
+            secondary.name, delay.as_millis()
+        );
+
+        let primary_client = self.get_client(&primary).await;
+        let secondary_client = self.get_client(&secondary).await;
+
+        let primary_fut = primary_client.query(query);
+        tokio::pin!(primary_fut);
+
+        // Phase 1: give the primary server until the hedge delay to answer
+        let early = tokio::select! {
+            result = &mut primary_fut => Some(result),
+            _ = tokio::time::sleep(delay) => None,
+        };
+
+        if let Some(result) = early {
+            self.upstream_manager.record_hedge_primary(primary.id, false).await;
+            let primary_error = match self.accept_result(result, primary.id, trace_id).await {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+            // The primary failed outright, so fall through to the next-best immediately
+            info!("[{}] [Hedged] Primary failed early, querying {}", trace_id, secondary.name);
+            let result = secondary_client.query(query).await;
+            return self.accept_result(result, secondary.id, trace_id).await
+                .map_err(|e| anyhow!("All upstream servers failed: {}; {}", primary_error, e));
+        }
+
+        // Phase 2: fire the hedge and race both servers
+        self.upstream_manager.record_hedge_primary(primary.id, true).await;
+        info!(
+            "[{}] [Hedged] No answer from {} within {}ms, hedging to {}",
+            trace_id, primary.name, delay.as_millis(), secondary.name
+        );
+
+        let secondary_fut = secondary_client.query(query);
+        tokio::pin!(secondary_fut);
+
+        let mut primary_done = false;
+        let mut secondary_done = false;
+        let mut last_error: Option<anyhow::Error> = None;
+
+        while !(primary_done && secondary_done) {
+            tokio::select! {
+                result = &mut primary_fut, if !primary_done => {
+                    primary_done = true;
+                    match self.accept_result(result, primary.id, trace_id).await {
+                        Ok(result) => return Ok(result),
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                result = &mut secondary_fut, if !secondary_done => {
+                    secondary_done = true;
+                    match self.accept_result(result, secondary.id, trace_id).await {
+                        Ok(result) => {
+                            info!("[{}] [Hedged] Hedge {} won", trace_id, secondary.name);
+                            self.upstream_manager.record_hedge_win(secondary.id).await;
+                            return Ok(result);
+                        }
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+            }
+        }
+
+        Err(anyhow!("All upstream servers failed: {}",
+            last_error.map(|e| e.to_string()).unwrap_or_else(|| "unknown error".to_string())))
+    }
+
+    /// Record the outcome of a single upstream answer, accepting NoError and NxDomain
+    async fn accept_result(&self, result: Result<QueryResult>, server_id: i64, trace_id: &str) -> Result<QueryResult> {
+        use tracing::{info, warn};
+        use crate::dns::message::DnsResponseCode;
+
+        match result {
+            Ok(result) => {
+                let response_code = result.response.response_code;
+                if response_code == DnsResponseCode::NoError || response_code == DnsResponseCode::NxDomain {
+                    info!(
+                        "[{}] Server {} responded: {} in {}ms",
+                        trace_id, result.server_name, response_code, result.response_time_ms
+                    );
+                    self.upstream_manager
+                        .record_success(result.server_id, result.response_time_ms)
+                        .await;
+                    Ok(result)
+                } else {
+                    let fail_count = TOTAL_FAILURE_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+                    warn!(
+                        "[{}] Server {} returned error: {}, 当前失败总数: {}",
+                        trace_id, result.server_name, response_code, fail_count
+                    );
+                    self.upstream_manager.record_failure(server_id).await;
+                    Err(anyhow!("{} returned {}", result.server_name, response_code))
+                }
+            }
+            Err(e) => {
+                let fail_count = TOTAL_FAILURE_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("[{}] Server {} failed: {}, 当前失败总数: {}", trace_id, server_id, e, fail_count);
+                self.upstream_manager.record_failure(server_id).await;
+                Err(e)
+            }
+        }
+    }
+
     /// Query a specific server with failover
     async fn query_server(&self, server: UpstreamServer, query: &DnsQuery, trace_id: &str) -> Result<QueryResult> {
         use tracing::{info, warn};
@@ -454,7 +793,77 @@ impl ProxyManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dns::message::{DnsResponse, DnsResponseCode, RecordType};
     use crate::dns::proxy::upstream::UpstreamProtocol;
+    use async_trait::async_trait;
+    use std::time::Instant;
+
+    /// Upstream that answers with `response_code` after `delay`
+    struct StubClient {
+        server: UpstreamServer,
+        delay: Duration,
+        response_code: DnsResponseCode,
+        /// When the query was sent
+        queried_at: std::sync::Mutex<Option<Instant>>,
+    }
+
+    impl StubClient {
+        fn new(id: i64, delay_ms: u64, response_code: DnsResponseCode) -> Arc<Self> {
+            Arc::new(Self {
+                server: UpstreamServer::new(id, format!("Stub{}", id), format!("192.0.2.{}:53", id), UpstreamProtocol::Udp, 5000),
+                delay: Duration::from_millis(delay_ms),
+                response_code,
+                queried_at: std::sync::Mutex::new(None),
+            })
+        }
+
+        fn queried_at(&self) -> Option<Instant> {
+            *self.queried_at.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl DnsClient for StubClient {
+        async fn query(&self, query: &DnsQuery) -> Result<QueryResult> {
+            *self.queried_at.lock().unwrap() = Some(Instant::now());
+            tokio::time::sleep(self.delay).await;
+            let mut response = DnsResponse::new(query.id);
+            response.response_code = self.response_code;
+            Ok(QueryResult {
+                response,
+                response_time_ms: self.delay.as_millis() as u64,
+                server_id: self.server.id,
+                server_name: self.server.name.clone(),
+                decision: None,
+            })
+        }
+
+        fn server(&self) -> &UpstreamServer {
+            &self.server
+        }
+
+        async fn health_check(&self) -> Result<Duration> {
+            Ok(self.delay)
+        }
+    }
+
+    /// Hedged proxy over stub upstreams; the first one is the primary with a 50ms hedge delay
+    async fn hedged_proxy(stubs: &[&Arc<StubClient>]) -> ProxyManager {
+        let upstream_manager = Arc::new(UpstreamManager::new());
+        for stub in stubs {
+            upstream_manager.add_server(stub.server.clone()).await;
+        }
+        upstream_manager.record_success(stubs[0].server.id, 50).await;
+        let proxy_manager = ProxyManager::new(upstream_manager);
+        proxy_manager.set_strategy(QueryStrategy::Hedged).await;
+        let mut cache = proxy_manager.client_cache.lock().await;
+        for stub in stubs {
+            let client: Arc<dyn DnsClient> = (*stub).clone();
+            cache.insert(stub.server.clone(), client);
+        }
+        drop(cache);
+        proxy_manager
+    }
 
     #[test]
     fn test_strategy_from_str() {
@@ -465,6 +874,7 @@ mod tests {
         assert_eq!(QueryStrategy::from_str("round_robin"), Some(QueryStrategy::RoundRobin));
         assert_eq!(QueryStrategy::from_str("roundrobin"), Some(QueryStrategy::RoundRobin));
         assert_eq!(QueryStrategy::from_str("random"), Some(QueryStrategy::Random));
+        assert_eq!(QueryStrategy::from_str("hedged"), Some(QueryStrategy::Hedged));
         assert_eq!(QueryStrategy::from_str("invalid"), None);
     }
 
@@ -474,6 +884,15 @@ mod tests {
         assert_eq!(QueryStrategy::Fastest.as_str(), "fastest");
         assert_eq!(QueryStrategy::RoundRobin.as_str(), "round_robin");
         assert_eq!(QueryStrategy::Random.as_str(), "random");
+        assert_eq!(QueryStrategy::Hedged.as_str(), "hedged");
+    }
+
+    #[test]
+    fn test_hedge_delay() {
+        assert_eq!(hedge_delay(None), Duration::from_millis(HEDGE_DEFAULT_DELAY_MS));
+        assert_eq!(hedge_delay(Some(1)), Duration::from_millis(HEDGE_MIN_DELAY_MS));
+        assert_eq!(hedge_delay(Some(45)), Duration::from_millis(45));
+        assert_eq!(hedge_delay(Some(60_000)), Duration::from_millis(HEDGE_MAX_DELAY_MS));
     }
 
     #[test]
@@ -503,6 +922,49 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_hedged_slow_primary() {
+        let primary = StubClient::new(1, 2000, DnsResponseCode::NoError);
+        let secondary = StubClient::new(2, 0, DnsResponseCode::NoError);
+        let proxy_manager = hedged_proxy(&[&primary, &secondary]).await;
+
+        let start = Instant::now();
+        let result = proxy_manager.query(&DnsQuery::new("example.com", RecordType::A)).await.unwrap();
+        assert_eq!(result.server_id, 2);
+        assert!(start.elapsed() < Duration::from_millis(1000));
+        let hedged_after = secondary.queried_at().unwrap() - primary.queried_at().unwrap();
+        assert!(hedged_after >= Duration::from_millis(50), "hedge fired after {:?}", hedged_after);
+
+        let manager = proxy_manager.upstream_manager();
+        let primary_stats = manager.get_stats(1).await.unwrap();
+        assert_eq!((primary_stats.hedged_queries, primary_stats.hedges), (1, 1));
+        assert_eq!(manager.get_stats(2).await.unwrap().hedge_wins, 1);
+    }
+
+    #[tokio::test]
+    async fn test_hedged_failed_primary() {
+        // The primary fails after the hedge fired but before the hedge answers
+        let primary = StubClient::new(1, 100, DnsResponseCode::ServFail);
+        let secondary = StubClient::new(2, 150, DnsResponseCode::NoError);
+        let proxy_manager = hedged_proxy(&[&primary, &secondary]).await;
+
+        let result = proxy_manager.query(&DnsQuery::new("example.com", RecordType::A)).await.unwrap();
+        assert_eq!((result.server_id, result.response.response_code), (2, DnsResponseCode::NoError));
+        let manager = proxy_manager.upstream_manager();
+        assert_eq!(manager.get_stats(1).await.unwrap().failures, 1);
+        assert_eq!(manager.get_stats(2).await.unwrap().hedge_wins, 1);
+
+        // A primary failing before the delay goes straight to the next-best without a hedge
+        let primary = StubClient::new(1, 0, DnsResponseCode::ServFail);
+        let secondary = StubClient::new(2, 0, DnsResponseCode::NoError);
+        let proxy_manager = hedged_proxy(&[&primary, &secondary]).await;
+
+        let result = proxy_manager.query(&DnsQuery::new("example.com", RecordType::A)).await.unwrap();
+        assert_eq!((result.server_id, result.response.response_code), (2, DnsResponseCode::NoError));
+        let primary_stats = proxy_manager.upstream_manager().get_stats(1).await.unwrap();
+        assert_eq!((primary_stats.hedged_queries, primary_stats.hedges), (1, 0));
+    }
+
     #[tokio::test]
     async fn test_round_robin_counter() {
         let upstream_manager = Arc::new(UpstreamManager::new());
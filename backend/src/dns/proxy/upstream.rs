@@ -3,14 +3,19 @@
 //! Manages upstream DNS servers with support for multiple protocols,
 //! health checking, and statistics tracking.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 use crate::db::{Database, UpstreamServer as DbUpstreamServer};
+use super::health::{HealthEvent, HealthPolicy, HealthState};
+use super::outbound::OutboundConfig;
+
+/// Health events kept for the API
+const RECENT_HEALTH_EVENTS: usize = 100;
 
 /// Supported upstream DNS protocols
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -26,6 +31,8 @@ pub enum UpstreamProtocol {
     Doq,
     /// DNS over HTTP/3 (port 443)
     Doh3,
+    /// Iterative resolution from the root servers (port 53)
+    Recursive,
 }
 
 impl UpstreamProtocol {
@@ -37,6 +44,7 @@ impl UpstreamProtocol {
             "doh" => Some(UpstreamProtocol::Doh),
             "doq" => Some(UpstreamProtocol::Doq),
             "doh3" | "h3" => Some(UpstreamProtocol::Doh3),
+            "recursive" => Some(UpstreamProtocol::Recursive),
             _ => None,
         }
     }
@@ -49,6 +57,7 @@ impl UpstreamProtocol {
             UpstreamProtocol::Doh => "doh",
             UpstreamProtocol::Doq => "doq",
             UpstreamProtocol::Doh3 => "doh3",
+            UpstreamProtocol::Recursive => "recursive",
         }
     }
 
@@ -60,6 +69,7 @@ impl UpstreamProtocol {
             UpstreamProtocol::Doh => 443,
             UpstreamProtocol::Doq => 853,  // RFC 9250: DoQ uses UDP port 853
             UpstreamProtocol::Doh3 => 443, // DoH3 uses UDP port 443
+            UpstreamProtocol::Recursive => 53,
         }
     }
 }
@@ -70,6 +80,45 @@ impl std::fmt::Display for UpstreamProtocol {
     }
 }
 
+/// Upstream group used by anti-pollution routing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamGroup {
+    /// Not part of any group
+    #[default]
+    Default,
+    /// Domestic resolvers (fast, but answers may be polluted)
+    Domestic,
+    /// Trusted resolvers (clean answers, possibly slower or off-CDN)
+    Trusted,
+}
+
+impl UpstreamGroup {
+    /// Parse from string (empty means no group)
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "default" => Some(UpstreamGroup::Default),
+            "domestic" => Some(UpstreamGroup::Domestic),
+            "trusted" => Some(UpstreamGroup::Trusted),
+            _ => None,
+        }
+    }
+
+    /// Convert to string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpstreamGroup::Default => "default",
+            UpstreamGroup::Domestic => "domestic",
+            UpstreamGroup::Trusted => "trusted",
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
 
 /// Upstream server configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -86,6 +135,15 @@ pub struct UpstreamServer {
     pub timeout: Duration,
     /// Whether this server is enabled
     pub enabled: bool,
+    /// Outbound proxy and source binding settings
+    #[serde(default)]
+    pub outbound: OutboundConfig,
+    /// Anti-pollution upstream group
+    #[serde(default)]
+    pub group: UpstreamGroup,
+    /// Suspension thresholds and active probe settings
+    #[serde(default)]
+    pub health: HealthPolicy,
 }
 
 #[allow(dead_code)]
@@ -105,12 +163,56 @@ impl UpstreamServer {
             protocol,
             timeout: Duration::from_millis(timeout_ms as u64),
             enabled: true,
+            outbound: OutboundConfig::default(),
+            group: UpstreamGroup::Default,
+            health: HealthPolicy::default(),
         }
     }
 
+    /// Set the outbound proxy and source binding settings
+    pub fn with_outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// Set the anti-pollution upstream group
+    pub fn with_group(mut self, group: UpstreamGroup) -> Self {
+        self.group = group;
+        self
+    }
+
+    /// Set the health policy
+    pub fn with_health(mut self, health: HealthPolicy) -> Self {
+        self.health = health;
+        self
+    }
+
     /// Create from database model
+    ///
+    /// Servers with invalid outbound settings are skipped rather than
+    /// silently bypassing the configured proxy.
     pub fn from_db(db_server: &DbUpstreamServer) -> Option<Self> {
         let protocol = UpstreamProtocol::from_str(&db_server.protocol)?;
+        let outbound = OutboundConfig::from_settings(
+            db_server.proxy.as_deref(),
+            db_server.bind_address.as_deref(),
+            db_server.bind_interface.as_deref(),
+        )
+        .and_then(|outbound| outbound.check_protocol(protocol).map(|_| outbound));
+
+        let outbound = match outbound {
+            Ok(outbound) => outbound,
+            Err(e) => {
+                tracing::warn!("Skipping upstream server {} ({}): {}", db_server.name, db_server.id, e);
+                return None;
+            }
+        };
+
+        let health = HealthPolicy::from_json(db_server.health_policy.as_deref()).unwrap_or_else(|e| {
+            tracing::warn!("Upstream server {} ({}) uses the default health policy: {}", db_server.name, db_server.id, e);
+            HealthPolicy::default()
+        });
+
         Some(Self {
             id: db_server.id,
             name: db_server.name.clone(),
@@ -118,6 +220,11 @@ impl UpstreamServer {
             protocol,
             timeout: Duration::from_millis(db_server.timeout as u64),
             enabled: db_server.enabled,
+            outbound,
+            group: db_server.group_name.as_deref()
+                .and_then(UpstreamGroup::from_str)
+                .unwrap_or_default(),
+            health,
         })
     }
 
@@ -157,6 +264,30 @@ pub struct UpstreamStats {
     /// Current suspension duration in seconds (for exponential backoff)
     #[serde(skip)]
     pub suspension_duration_secs: u64,
+    /// Number of hedged-strategy queries where this server was the primary
+    pub hedged_queries: u64,
+    /// Number of times a hedge was fired because this server was too slow
+    pub hedges: u64,
+    /// Number of times this server answered first as the hedge target
+    pub hedge_wins: u64,
+    /// Number of UDP responses dropped for a wrong source, ID or question
+    pub mismatches: u64,
+    /// Consecutive failed probes
+    pub probe_failures: u32,
+    /// Consecutive successful probes
+    pub probe_successes: u32,
+    /// Whether probes have taken the server out of rotation
+    pub probe_down: bool,
+    /// Response time of the last successful probe
+    pub last_probe_ms: Option<u64>,
+    /// Why the last probe failed
+    pub last_probe_error: Option<String>,
+    /// Thresholds applied to this server
+    #[serde(skip)]
+    pub policy: HealthPolicy,
+    /// State last reported as a health event
+    #[serde(skip)]
+    reported_state: HealthState,
 }
 
 impl Default for UpstreamStats {
@@ -173,6 +304,17 @@ impl Default for UpstreamStats {
             healthy: true,
             suspended_until: None,
             suspension_duration_secs: 0,
+            hedged_queries: 0,
+            hedges: 0,
+            hedge_wins: 0,
+            mismatches: 0,
+            probe_failures: 0,
+            probe_successes: 0,
+            probe_down: false,
+            last_probe_ms: None,
+            last_probe_error: None,
+            policy: HealthPolicy::default(),
+            reported_state: HealthState::Healthy,
         }
     }
 }
@@ -210,6 +352,45 @@ impl UpstreamStats {
         }
     }
 
+    /// Get the 90th percentile of recent response times (None if no data)
+    pub fn p90_response_time_ms(&self) -> Option<u64> {
+        if self.recent_response_times.is_empty() {
+            return None;
+        }
+        let mut sorted = self.recent_response_times.clone();
+        sorted.sort_unstable();
+        // Nearest-rank percentile
+        let rank = (sorted.len() * 9).div_ceil(10);
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    /// Fraction of hedged queries (as primary) that needed a hedge (0.0 to 1.0)
+    pub fn hedge_rate(&self) -> f64 {
+        if self.hedged_queries == 0 {
+            0.0
+        } else {
+            self.hedges as f64 / self.hedged_queries as f64
+        }
+    }
+
+    /// Record that this server was the primary of a hedged query
+    pub fn record_hedge_primary(&mut self, hedged: bool) {
+        self.hedged_queries += 1;
+        if hedged {
+            self.hedges += 1;
+        }
+    }
+
+    /// Record that this server won a query as the hedge target
+    pub fn record_hedge_win(&mut self) {
+        self.hedge_wins += 1;
+    }
+
+    /// Record a dropped response that did not match its query
+    pub fn record_mismatch(&mut self) {
+        self.mismatches += 1;
+    }
+
     /// Calculate success rate (0.0 to 1.0)
     pub fn success_rate(&self) -> f64 {
         if self.queries == 0 {
@@ -259,14 +440,15 @@ impl UpstreamStats {
         self.failures += 1;
         self.last_failure = Some(Instant::now());
         
-        // Mark as unhealthy if failure rate is too high (< 50% success rate after 5+ queries)
-        if self.success_rate() < 0.5 && self.queries >= 5 {
+        // Mark as unhealthy if failure rate is too high (per the server's health policy)
+        let min_success_rate = self.policy.min_success_percent as f64 / 100.0;
+        if self.success_rate() < min_success_rate && self.queries >= self.policy.min_queries as u64 {
             self.healthy = false;
             
             // Calculate suspension duration based on success rate
             // Lower success rate = longer suspension
-            let base_duration = 30u64; // Base: 30 seconds
-            let max_duration = 300u64; // Max: 5 minutes
+            let base_duration = self.policy.base_suspension_secs;
+            let max_duration = self.policy.max_suspension_secs;
             
             // Exponential backoff: double the previous duration, capped at max
             if self.suspension_duration_secs == 0 {
@@ -320,6 +502,11 @@ impl UpstreamStats {
 
     /// Check if server should be considered healthy and available
     pub fn is_healthy(&self) -> bool {
+        // Only probes can bring back a server that probes took down
+        if self.probe_down {
+            return false;
+        }
+
         // If suspended, check if suspension period has ended
         if self.is_suspended() {
             return false;
@@ -333,12 +520,65 @@ impl UpstreamStats {
         self.healthy
     }
 
+    /// Current health state
+    pub fn health_state(&self) -> HealthState {
+        if self.probe_down {
+            HealthState::Down
+        } else if self.is_healthy() {
+            HealthState::Healthy
+        } else {
+            HealthState::Suspended
+        }
+    }
+
+    /// Record the outcome of an active probe (does not count as a query)
+    pub fn record_probe(&mut self, outcome: &Result<u64, String>) {
+        match outcome {
+            Ok(response_time_ms) => {
+                self.probe_failures = 0;
+                self.probe_successes = self.probe_successes.saturating_add(1);
+                self.last_probe_ms = Some(*response_time_ms);
+                self.last_probe_error = None;
+
+                let unavailable = self.probe_down || !self.is_healthy();
+                if unavailable && self.probe_successes >= self.policy.recovery_threshold {
+                    self.probe_down = false;
+                    self.healthy = true;
+                    self.suspended_until = None;
+                    self.suspension_duration_secs = 0;
+                }
+            }
+            Err(error) => {
+                self.probe_successes = 0;
+                self.probe_failures = self.probe_failures.saturating_add(1);
+                self.last_probe_error = Some(error.clone());
+
+                if self.probe_failures >= self.policy.failure_threshold {
+                    self.probe_down = true;
+                }
+            }
+        }
+    }
+
     /// Reset health status (for manual recovery)
     pub fn reset_health(&mut self) {
         self.healthy = true;
         self.failures = 0;
         self.suspended_until = None;
         self.suspension_duration_secs = 0;
+        self.probe_down = false;
+        self.probe_failures = 0;
+        self.probe_successes = 0;
+    }
+
+    /// Note a state change since the last call, if any
+    fn take_transition(&mut self) -> Option<(HealthState, HealthState)> {
+        let state = self.health_state();
+        if state == self.reported_state {
+            return None;
+        }
+        let from = std::mem::replace(&mut self.reported_state, state);
+        Some((from, state))
     }
 
     /// Get recent response times for debugging
@@ -377,6 +617,10 @@ pub struct UpstreamManager {
     /// Health check interval
     #[allow(dead_code)]
     health_check_interval: Duration,
+    /// Health transition events
+    health_events: broadcast::Sender<HealthEvent>,
+    /// Most recent health events (newest last)
+    recent_events: std::sync::Mutex<VecDeque<HealthEvent>>,
 }
 
 #[allow(dead_code)]
@@ -388,6 +632,8 @@ impl UpstreamManager {
             stats: RwLock::new(HashMap::new()),
             db: None,
             health_check_interval: Duration::from_secs(30),
+            health_events: broadcast::channel(64).0,
+            recent_events: std::sync::Mutex::new(VecDeque::new()),
         }
     }
 
@@ -398,6 +644,8 @@ impl UpstreamManager {
             stats: RwLock::new(HashMap::new()),
             db: Some(db),
             health_check_interval: Duration::from_secs(30),
+            health_events: broadcast::channel(64).0,
+            recent_events: std::sync::Mutex::new(VecDeque::new()),
         }
     }
 
@@ -418,7 +666,7 @@ impl UpstreamManager {
             // Initialize stats for each server
             let mut stats = self.stats.write().await;
             for server in &servers {
-                stats.entry(server.id).or_insert_with(UpstreamStats::new);
+                stats.entry(server.id).or_insert_with(UpstreamStats::new).policy = server.health.clone();
             }
 
             let mut current_servers = self.servers.write().await;
@@ -444,7 +692,7 @@ impl UpstreamManager {
         // Initialize stats for each server
         let mut stats = self.stats.write().await;
         for server in &servers {
-            stats.entry(server.id).or_insert_with(UpstreamStats::new);
+            stats.entry(server.id).or_insert_with(UpstreamStats::new).policy = server.health.clone();
         }
 
         let mut current_servers = self.servers.write().await;
@@ -481,7 +729,7 @@ impl UpstreamManager {
         let mut servers = self.servers.write().await;
         let mut stats = self.stats.write().await;
         
-        stats.entry(server.id).or_insert_with(UpstreamStats::new);
+        stats.entry(server.id).or_insert_with(UpstreamStats::new).policy = server.health.clone();
         servers.push(server);
     }
 
@@ -506,28 +754,120 @@ impl UpstreamManager {
 
     /// Record a successful query for a server
     pub async fn record_success(&self, id: i64, response_time_ms: u64) {
+        self.update_health(id, "query succeeded", |st| st.record_success(response_time_ms)).await;
+    }
+
+    /// Record a failed query for a server
+    pub async fn record_failure(&self, id: i64) {
+        self.update_health(id, "success rate below threshold", |st| st.record_failure()).await;
+    }
+
+    /// Record the outcome of an active probe for a server
+    pub async fn record_probe(&self, id: i64, outcome: Result<u64, String>) {
+        let reason = match &outcome {
+            Ok(ms) => format!("probe succeeded in {}ms", ms),
+            Err(e) => format!("probe failed: {}", e),
+        };
+        self.update_health(id, &reason, |st| st.record_probe(&outcome)).await;
+    }
+
+    /// Record that a server was the primary of a hedged query
+    pub async fn record_hedge_primary(&self, id: i64, hedged: bool) {
         let mut stats = self.stats.write().await;
         if let Some(server_stats) = stats.get_mut(&id) {
-            server_stats.record_success(response_time_ms);
+            server_stats.record_hedge_primary(hedged);
         }
     }
 
-    /// Record a failed query for a server
-    pub async fn record_failure(&self, id: i64) {
+    /// Record that a server won a query as the hedge target
+    pub async fn record_hedge_win(&self, id: i64) {
         let mut stats = self.stats.write().await;
         if let Some(server_stats) = stats.get_mut(&id) {
-            server_stats.record_failure();
+            server_stats.record_hedge_win();
         }
     }
 
-    /// Reset health status for a server
-    pub async fn reset_health(&self, id: i64) {
+    /// Record a dropped UDP response that did not match its query
+    pub async fn record_mismatch(&self, id: i64) {
         let mut stats = self.stats.write().await;
         if let Some(server_stats) = stats.get_mut(&id) {
-            server_stats.reset_health();
+            server_stats.record_mismatch();
         }
     }
 
+    /// Reset health status for a server
+    pub async fn reset_health(&self, id: i64) {
+        self.update_health(id, "manual reset", |st| st.reset_health()).await;
+    }
+
+    /// Report suspensions that expired without any stats update
+    pub async fn sweep_health(&self) {
+        let transitions: Vec<_> = {
+            let mut stats = self.stats.write().await;
+            stats.iter_mut()
+                .filter_map(|(id, st)| st.take_transition().map(|t| (*id, t)))
+                .collect()
+        };
+        for (id, (from, to)) in transitions {
+            self.emit_health_event(id, from, to, "suspension expired").await;
+        }
+    }
+
+    /// Subscribe to health transition events
+    pub fn subscribe_health(&self) -> broadcast::Receiver<HealthEvent> {
+        self.health_events.subscribe()
+    }
+
+    /// Most recent health transition events (newest first)
+    pub fn recent_health_events(&self) -> Vec<HealthEvent> {
+        self.recent_events.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Apply a stats update and report a resulting health transition
+    async fn update_health(&self, id: i64, reason: &str, update: impl FnOnce(&mut UpstreamStats)) {
+        let transition = {
+            let mut stats = self.stats.write().await;
+            let Some(server_stats) = stats.get_mut(&id) else {
+                return;
+            };
+            update(server_stats);
+            server_stats.take_transition()
+        };
+        if let Some((from, to)) = transition {
+            self.emit_health_event(id, from, to, reason).await;
+        }
+    }
+
+    /// Log, record and broadcast a health transition
+    async fn emit_health_event(&self, id: i64, from: HealthState, to: HealthState, reason: &str) {
+        let server_name = self.get_server(id).await
+            .map(|s| s.name)
+            .unwrap_or_else(|| id.to_string());
+        if to == HealthState::Healthy {
+            tracing::info!("Upstream {} ({}) is {} again (was {}): {}", server_name, id, to, from, reason);
+        } else {
+            tracing::warn!("Upstream {} ({}) is {} (was {}): {}", server_name, id, to, from, reason);
+        }
+
+        let event = HealthEvent {
+            server_id: id,
+            server_name,
+            from,
+            to,
+            reason: reason.to_string(),
+            timestamp: chrono::Utc::now(),
+        };
+        {
+            let mut recent = self.recent_events.lock().unwrap();
+            if recent.len() >= RECENT_HEALTH_EVENTS {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+        // No subscribers is fine
+        let _ = self.health_events.send(event);
+    }
+
     /// Get the server with the fastest average response time
     pub async fn get_fastest_server(&self) -> Option<UpstreamServer> {
         let servers = self.get_healthy_servers().await;
@@ -543,6 +883,20 @@ impl UpstreamManager {
             })
     }
 
+    /// Get healthy servers ordered from fastest to slowest (servers without data last)
+    pub async fn get_ranked_servers(&self) -> Vec<UpstreamServer> {
+        let mut servers = self.get_healthy_servers().await;
+        let stats = self.stats.read().await;
+
+        servers.sort_by_key(|s| {
+            stats
+                .get(&s.id)
+                .map(|st| st.avg_response_time_for_sorting())
+                .unwrap_or(u64::MAX)
+        });
+        servers
+    }
+
     /// Check if any server has historical stats (at least one successful query)
     pub async fn has_any_stats(&self) -> bool {
         let stats = self.stats.read().await;
@@ -626,6 +980,7 @@ mod tests {
         assert_eq!(UpstreamProtocol::from_str("doq"), Some(UpstreamProtocol::Doq));
         assert_eq!(UpstreamProtocol::from_str("doh3"), Some(UpstreamProtocol::Doh3));
         assert_eq!(UpstreamProtocol::from_str("h3"), Some(UpstreamProtocol::Doh3));
+        assert_eq!(UpstreamProtocol::from_str("recursive"), Some(UpstreamProtocol::Recursive));
         assert_eq!(UpstreamProtocol::from_str("invalid"), None);
     }
 
@@ -654,6 +1009,15 @@ mod tests {
         assert_eq!(server.protocol, UpstreamProtocol::Udp);
         assert_eq!(server.timeout_ms(), 5000);
         assert!(server.enabled);
+        assert_eq!(server.group, UpstreamGroup::Default);
+    }
+
+    #[test]
+    fn test_group_from_str() {
+        assert_eq!(UpstreamGroup::from_str(""), Some(UpstreamGroup::Default));
+        assert_eq!(UpstreamGroup::from_str("Domestic"), Some(UpstreamGroup::Domestic));
+        assert_eq!(UpstreamGroup::from_str("trusted"), Some(UpstreamGroup::Trusted));
+        assert_eq!(UpstreamGroup::from_str("foreign"), None);
     }
 
     #[test]
@@ -709,6 +1073,93 @@ mod tests {
         assert!(stats.is_healthy());
     }
 
+    #[test]
+    fn test_upstream_stats_policy_thresholds() {
+        let mut stats = UpstreamStats::new();
+        stats.policy = HealthPolicy {
+            min_queries: 10,
+            base_suspension_secs: 60,
+            ..Default::default()
+        };
+
+        for _ in 0..9 {
+            stats.record_failure();
+        }
+        assert!(stats.is_healthy());
+
+        stats.record_failure();
+        assert_eq!(stats.health_state(), HealthState::Suspended);
+        assert!(stats.suspension_remaining_secs().unwrap() > 30);
+    }
+
+    #[test]
+    fn test_upstream_stats_probe_down_and_recovery() {
+        let mut stats = UpstreamStats::new();
+        stats.policy = HealthPolicy {
+            failure_threshold: 2,
+            recovery_threshold: 2,
+            ..Default::default()
+        };
+
+        stats.record_probe(&Err("timeout".to_string()));
+        assert_eq!(stats.health_state(), HealthState::Healthy);
+        stats.record_probe(&Err("timeout".to_string()));
+        assert_eq!(stats.health_state(), HealthState::Down);
+
+        // User traffic does not bring a probed-down server back
+        stats.record_success(10);
+        assert!(!stats.is_healthy());
+
+        stats.record_probe(&Ok(12));
+        assert_eq!(stats.health_state(), HealthState::Down);
+        stats.record_probe(&Ok(11));
+        assert_eq!(stats.health_state(), HealthState::Healthy);
+        assert_eq!(stats.last_probe_ms, Some(11));
+        assert_eq!(stats.queries, 1);
+    }
+
+    #[test]
+    fn test_upstream_stats_probe_ends_suspension() {
+        let mut stats = UpstreamStats::new();
+        for _ in 0..5 {
+            stats.record_failure();
+        }
+        assert_eq!(stats.health_state(), HealthState::Suspended);
+
+        stats.record_probe(&Ok(5));
+        assert_eq!(stats.health_state(), HealthState::Suspended);
+        stats.record_probe(&Ok(5));
+        assert_eq!(stats.health_state(), HealthState::Healthy);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_manager_health_events() {
+        let manager = UpstreamManager::new();
+        let policy = HealthPolicy { failure_threshold: 1, recovery_threshold: 1, ..Default::default() };
+        manager.add_server(UpstreamServer::new(
+            1, "Probed", "8.8.8.8:53", UpstreamProtocol::Udp, 5000,
+        ).with_health(policy)).await;
+        let mut events = manager.subscribe_health();
+
+        manager.record_probe(1, Err("timeout".to_string())).await;
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.from, event.to), (HealthState::Healthy, HealthState::Down));
+        assert_eq!(event.server_name, "Probed");
+        assert!(manager.get_healthy_servers().await.is_empty());
+
+        manager.record_probe(1, Ok(20)).await;
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.from, event.to), (HealthState::Down, HealthState::Healthy));
+
+        // No transition, no event
+        manager.record_probe(1, Ok(20)).await;
+        assert!(events.try_recv().is_err());
+
+        let recent = manager.recent_health_events();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].to, HealthState::Healthy);
+    }
+
     #[tokio::test]
     async fn test_upstream_manager_add_server() {
         let manager = UpstreamManager::new();
@@ -802,6 +1253,47 @@ mod tests {
         assert_eq!(fastest.id, 2);
     }
 
+    #[test]
+    fn test_upstream_stats_p90_and_hedge_rate() {
+        let mut stats = UpstreamStats::new();
+        assert_eq!(stats.p90_response_time_ms(), None);
+        assert_eq!(stats.hedge_rate(), 0.0);
+
+        for ms in [10, 20, 30, 40, 50, 60, 70, 80, 90, 500] {
+            stats.record_success(ms);
+        }
+        assert_eq!(stats.p90_response_time_ms(), Some(90));
+
+        stats.record_hedge_primary(false);
+        stats.record_hedge_primary(true);
+        stats.record_hedge_win();
+        assert_eq!(stats.hedged_queries, 2);
+        assert_eq!(stats.hedges, 1);
+        assert_eq!(stats.hedge_wins, 1);
+        assert_eq!(stats.hedge_rate(), 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_manager_ranked_servers() {
+        let manager = UpstreamManager::new();
+
+        manager.add_server(UpstreamServer::new(
+            1, "NoData", "1.1.1.1:53", UpstreamProtocol::Udp, 5000,
+        )).await;
+        manager.add_server(UpstreamServer::new(
+            2, "Slow", "8.8.8.8:53", UpstreamProtocol::Udp, 5000,
+        )).await;
+        manager.add_server(UpstreamServer::new(
+            3, "Fast", "8.8.4.4:53", UpstreamProtocol::Udp, 5000,
+        )).await;
+
+        manager.record_success(2, 100).await;
+        manager.record_success(3, 20).await;
+
+        let ids: Vec<i64> = manager.get_ranked_servers().await.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+    }
+
     #[tokio::test]
     async fn test_upstream_manager_clear() {
         let manager = UpstreamManager::new();
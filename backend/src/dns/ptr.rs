@@ -0,0 +1,204 @@
+//! PTR Synthesis
+//!
+//! Answers reverse lookups (`in-addr.arpa` / `ip6.arpa`) from the names
+//! that local A/AAAA records, and optionally exact `map_ip` rewrite rules,
+//! point at an address. Explicit PTR records always win; synthesis only
+//! fills in names that have none.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DnsRecord, RewriteRule as DbRewriteRule};
+use super::rewrite::{MatchType, RewriteAction, RewriteRule};
+
+/// `system_config` key of the settings
+pub const PTR_SYNTHESIS_CONFIG_KEY: &str = "ptr_synthesis";
+
+/// TTL of PTR answers synthesized from rewrite rules, as their A/AAAA answers
+const REWRITE_TTL: u32 = 300;
+
+/// Which names to answer when several point at the same address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PtrConflict {
+    /// Only the oldest name: local records by creation, then rewrite rules by priority
+    #[default]
+    First,
+    /// Every name, in the same order
+    All,
+}
+
+/// PTR synthesis settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PtrSynthesisConfig {
+    pub enabled: bool,
+    /// Also use enabled exact-match `map_ip` rewrite rules
+    pub rewrite_rules: bool,
+    pub conflict: PtrConflict,
+}
+
+/// Address encoded in a reverse lookup name, if it is a complete one
+pub fn reverse_name_ip(name: &str) -> Option<IpAddr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if let Some(labels) = name.strip_suffix(".in-addr.arpa") {
+        let octets: Vec<u8> = labels
+            .split('.')
+            .map(|l| if l.len() > 1 && l.starts_with('0') { None } else { l.parse().ok() })
+            .collect::<Option<_>>()?;
+        let [d, c, b, a] = octets.try_into().ok()?;
+        return Some(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+    }
+    if let Some(labels) = name.strip_suffix(".ip6.arpa") {
+        let nibbles: Vec<u8> = labels
+            .split('.')
+            .map(|l| if l.len() == 1 { u8::from_str_radix(l, 16).ok() } else { None })
+            .collect::<Option<_>>()?;
+        if nibbles.len() != 32 {
+            return None;
+        }
+        let bits = nibbles.iter().rev().fold(0u128, |acc, n| (acc << 4) | *n as u128);
+        return Some(IpAddr::V6(Ipv6Addr::from(bits)));
+    }
+    None
+}
+
+/// Names pointing at each address, in answer order
+#[derive(Debug, Default)]
+pub struct PtrNames {
+    names: HashMap<IpAddr, Vec<(String, u32)>>,
+}
+
+impl PtrNames {
+    /// Collect the names of A/AAAA records and, if configured, rewrite rules
+    pub fn build(config: &PtrSynthesisConfig, records: &[&DnsRecord], rules: &[DbRewriteRule]) -> Self {
+        let mut records: Vec<&&DnsRecord> = records
+            .iter()
+            .filter(|r| r.enabled && !r.name.starts_with("*."))
+            .filter(|r| matches!(r.record_type.to_uppercase().as_str(), "A" | "AAAA"))
+            .collect();
+        // Stored rows by age; transferred records (no row) after them, by name
+        records.sort_by(|a, b| (a.id <= 0, a.id, &a.name).cmp(&(b.id <= 0, b.id, &b.name)));
+        let mut candidates: Vec<(IpAddr, String, u32)> = records
+            .into_iter()
+            .filter_map(|r| Some((r.value.trim().parse().ok()?, r.name.clone(), r.ttl.max(0) as u32)))
+            .collect();
+
+        if config.rewrite_rules {
+            let mut rules: Vec<RewriteRule> = rules.iter().filter_map(RewriteRule::from_db).filter(|r| r.enabled).collect();
+            rules.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.id.cmp(&b.id)));
+            candidates.extend(rules.into_iter().filter_map(|rule| match rule.action {
+                RewriteAction::MapToIp(ip) if rule.match_type == MatchType::Exact && !ip.is_unspecified() => {
+                    Some((ip, rule.pattern, REWRITE_TTL))
+                }
+                _ => None,
+            }));
+        }
+
+        let mut names: HashMap<IpAddr, Vec<(String, u32)>> = HashMap::new();
+        for (ip, name, ttl) in candidates {
+            let name = name.trim().trim_end_matches('.').to_lowercase();
+            let entry = names.entry(ip).or_default();
+            if entry.iter().any(|(n, _)| *n == name) || (config.conflict == PtrConflict::First && !entry.is_empty()) {
+                continue;
+            }
+            entry.push((name, ttl));
+        }
+        Self { names }
+    }
+
+    /// Names and TTLs for the address of a reverse lookup name
+    pub fn lookup(&self, name: &str) -> Option<&[(String, u32)]> {
+        self.names.get(&reverse_name_ip(name)?).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn record(id: i64, name: &str, record_type: &str, value: &str) -> DnsRecord {
+        DnsRecord {
+            id,
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            value: value.to_string(),
+            ttl: 600,
+            priority: 0,
+            enabled: true,
+            managed: false,
+            source: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn rule(id: i64, pattern: &str, match_type: &str, ip: &str, priority: i32) -> DbRewriteRule {
+        DbRewriteRule {
+            id,
+            pattern: pattern.to_string(),
+            match_type: match_type.to_string(),
+            action_type: "map_ip".to_string(),
+            action_value: Some(ip.to_string()),
+            priority,
+            enabled: true,
+            description: None,
+            managed: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_reverse_name_ip() {
+        assert_eq!(reverse_name_ip("10.1.168.192.in-addr.arpa."), Some("192.168.1.10".parse().unwrap()));
+        assert_eq!(
+            reverse_name_ip("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.B.D.0.1.0.0.2.ip6.arpa"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(reverse_name_ip("1.168.192.in-addr.arpa"), None);
+        assert_eq!(reverse_name_ip("010.1.168.192.in-addr.arpa"), None);
+        assert_eq!(reverse_name_ip("256.1.168.192.in-addr.arpa"), None);
+        assert_eq!(reverse_name_ip("1.0.ip6.arpa"), None);
+        assert_eq!(reverse_name_ip("nas.lan"), None);
+    }
+
+    #[test]
+    fn test_ptr_names_conflicts() {
+        let records = [
+            record(7, "media.lan", "A", "192.168.1.10"),
+            record(3, "NAS.lan.", "A", "192.168.1.10"),
+            record(4, "nas.lan", "AAAA", "fd00::10"),
+            record(5, "*.lan", "A", "192.168.1.1"),
+            record(6, "alias.lan", "CNAME", "nas.lan"),
+        ];
+        let records: Vec<&DnsRecord> = records.iter().collect();
+        let rules = [
+            rule(1, "printer.lan", "exact", "192.168.1.20", 0),
+            rule(2, "*.ads.example", "wildcard", "192.168.1.30", 0),
+            rule(3, "router.lan", "exact", "192.168.1.10", 10),
+        ];
+        let reverse = "10.1.168.192.in-addr.arpa";
+        let names = |config: &PtrSynthesisConfig, name: &str| -> Vec<String> {
+            PtrNames::build(config, &records, &rules)
+                .lookup(name)
+                .map(|n| n.iter().map(|(name, _)| name.clone()).collect())
+                .unwrap_or_default()
+        };
+
+        let mut config = PtrSynthesisConfig { enabled: true, ..Default::default() };
+        assert_eq!(names(&config, reverse), ["nas.lan"]);
+        assert_eq!(names(&config, "0.1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa"), ["nas.lan"]);
+        assert!(names(&config, "1.1.168.192.in-addr.arpa").is_empty());
+        assert!(names(&config, "20.1.168.192.in-addr.arpa").is_empty());
+
+        config.conflict = PtrConflict::All;
+        config.rewrite_rules = true;
+        assert_eq!(names(&config, reverse), ["nas.lan", "media.lan", "router.lan"]);
+        assert_eq!(names(&config, "20.1.168.192.in-addr.arpa"), ["printer.lan"]);
+        assert!(names(&config, "30.1.168.192.in-addr.arpa").is_empty());
+    }
+}
@@ -3,18 +3,26 @@
 //! The DNS Resolver integrates the rewrite engine, cache, and proxy manager
 //! to provide a complete DNS resolution pipeline.
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Result;
+use tokio::sync::{broadcast, RwLock};
 use tracing::debug;
 
-use crate::db::{Database, CreateQueryLog};
+use crate::db::{Database, CreateQueryLog, LogSinkStats, LogSinks, PassiveDnsRecorder, PassiveDnsStats, QueryLogWriter, QueryLogWriterStats, QueryStream, QueryStreamStats, StatsRollup};
 use super::cache::{CacheKey, CacheManager};
+use super::coalesce::{CoalesceStats, QueryCoalescer};
+use super::dnstap::{Dnstap, DnstapExchange, DnstapProtocol, DnstapRole, DnstapStats};
+use super::filter::{ResponseFilter, ResponseFilterConfig};
+use super::local::{LocalAnswer, LocalSnapshot};
+use super::privacy::{QueryPrivacy, QueryPrivacyConfig};
 use super::message::{DnsQuery, DnsRecordData, DnsResponse, DnsResponseCode, RecordType};
 use super::proxy::ProxyManager;
 use super::rewrite::{RewriteAction, RewriteEngine};
+use super::transfer::{is_zone_message, ZoneTransfers};
+use super::update::{is_update_message, DynamicUpdates};
 
 /// Query metadata returned alongside the DNS response
 #[derive(Debug, Clone)]
@@ -29,6 +37,14 @@ pub struct QueryMetadata {
     pub rewrite_applied: bool,
     /// The rewrite rule ID that was applied (if any)
     pub rewrite_rule_id: Option<i64>,
+    /// Why the upstream answer was chosen (e.g. anti-pollution routing)
+    pub decision: Option<String>,
+    /// What produced the answer
+    pub source: QuerySource,
+    /// Rewrite rule or local record that answered (if any)
+    pub source_id: Option<i64>,
+    /// Why the query was blocked or filtered (if it was)
+    pub reason: Option<String>,
 }
 
 impl Default for QueryMetadata {
@@ -39,10 +55,82 @@ impl Default for QueryMetadata {
             upstream_used: None,
             rewrite_applied: false,
             rewrite_rule_id: None,
+            decision: None,
+            source: QuerySource::Upstream,
+            source_id: None,
+            reason: None,
         }
     }
 }
 
+/// What produced the answer to a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuerySource {
+    /// Answered by an upstream server
+    Upstream,
+    /// Served from cache
+    Cache,
+    /// Answered from a local DNS record
+    Local,
+    /// Rewritten to an IP or another domain
+    Rewrite,
+    /// Blocked by a rewrite rule
+    Blocked,
+    /// Upstream answer replaced by a response filter
+    Filtered,
+    /// Record type disabled in settings
+    Disabled,
+    /// Rejected as an invalid domain name
+    Invalid,
+    /// Resolution failed
+    Error,
+}
+
+impl QuerySource {
+    /// Convert to string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuerySource::Upstream => "upstream",
+            QuerySource::Cache => "cache",
+            QuerySource::Local => "local",
+            QuerySource::Rewrite => "rewrite",
+            QuerySource::Blocked => "blocked",
+            QuerySource::Filtered => "filtered",
+            QuerySource::Disabled => "disabled",
+            QuerySource::Invalid => "invalid",
+            QuerySource::Error => "error",
+        }
+    }
+}
+
+/// Where a query arrived, for query logs
+#[derive(Debug, Clone)]
+pub struct QueryOrigin {
+    /// Client IP address
+    pub client_ip: String,
+    /// Listener protocol: udp, tcp, dot, doh or doq
+    pub protocol: &'static str,
+    /// DoH request path or TLS server name (SNI)
+    pub endpoint: Option<String>,
+}
+
+impl QueryOrigin {
+    /// Create an origin without endpoint details
+    pub fn new(client_ip: impl Into<String>, protocol: &'static str) -> Self {
+        Self {
+            client_ip: client_ip.into(),
+            protocol,
+            endpoint: None,
+        }
+    }
+
+    /// Set the DoH path or SNI
+    pub fn with_endpoint(mut self, endpoint: Option<String>) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+}
+
 /// Result of a DNS resolution
 #[derive(Debug, Clone)]
 pub struct ResolveResult {
@@ -52,6 +140,19 @@ pub struct ResolveResult {
     pub metadata: QueryMetadata,
 }
 
+/// Upper bound for one upstream query shared by coalesced cache misses
+const UPSTREAM_QUERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Upstream answer shared by coalesced cache misses
+#[derive(Debug, Clone)]
+struct UpstreamAnswer {
+    response: DnsResponse,
+    server_name: String,
+    decision: Option<String>,
+    /// Set when a response filter replaced the answer
+    filter_reason: Option<String>,
+}
+
 /// DNS Resolver
 ///
 /// Integrates rewrite engine, cache, and proxy manager to provide
@@ -63,8 +164,32 @@ pub struct DnsResolver {
     cache: Arc<CacheManager>,
     /// Proxy manager for upstream queries
     proxy: Arc<ProxyManager>,
-    /// Database for query logging (optional)
+    /// Database for local records and settings (optional)
     db: Option<Arc<Database>>,
+    /// Batched query log writer (optional)
+    log_writer: Option<Arc<QueryLogWriter>>,
+    /// Remote log shipping (optional)
+    log_sinks: Option<Arc<LogSinks>>,
+    /// Passive DNS recorder fed with upstream answers (optional)
+    passive_dns: Option<Arc<PassiveDnsRecorder>>,
+    /// Time-bucketed statistics aggregator (optional)
+    stats_rollup: Option<Arc<StatsRollup>>,
+    /// Live query stream for push consumers (optional)
+    query_stream: Option<Arc<QueryStream>>,
+    /// dnstap logger for listener exchanges (optional)
+    dnstap: Option<Arc<Dnstap>>,
+    /// Filters applied to upstream answers
+    response_filter: RwLock<Arc<ResponseFilter>>,
+    /// Privacy applied to query logs and log lines
+    privacy: RwLock<Arc<QueryPrivacy>>,
+    /// Shares upstream queries between identical in-flight cache misses
+    coalescer: Arc<QueryCoalescer<CacheKey, UpstreamAnswer>>,
+    /// Local records and resolver settings, rebuilt on database writes
+    local: RwLock<Arc<LocalSnapshot>>,
+    /// Zone transfers, NOTIFY and secondary zones (optional)
+    zone_transfers: Option<Arc<ZoneTransfers>>,
+    /// Dynamic updates of local zones (optional)
+    dynamic_updates: Option<Arc<DynamicUpdates>>,
 }
 
 
@@ -81,6 +206,18 @@ impl DnsResolver {
             cache,
             proxy,
             db: None,
+            log_writer: None,
+            log_sinks: None,
+            passive_dns: None,
+            stats_rollup: None,
+            query_stream: None,
+            dnstap: None,
+            response_filter: RwLock::new(Arc::new(ResponseFilter::default())),
+            privacy: RwLock::new(Arc::new(QueryPrivacy::default())),
+            coalescer: Arc::new(QueryCoalescer::new(UPSTREAM_QUERY_TIMEOUT)),
+            local: RwLock::new(Arc::new(LocalSnapshot::default())),
+            zone_transfers: None,
+            dynamic_updates: None,
         }
     }
 
@@ -96,9 +233,69 @@ impl DnsResolver {
             cache,
             proxy,
             db: Some(db),
+            log_writer: None,
+            log_sinks: None,
+            passive_dns: None,
+            stats_rollup: None,
+            query_stream: None,
+            dnstap: None,
+            response_filter: RwLock::new(Arc::new(ResponseFilter::default())),
+            privacy: RwLock::new(Arc::new(QueryPrivacy::default())),
+            coalescer: Arc::new(QueryCoalescer::new(UPSTREAM_QUERY_TIMEOUT)),
+            local: RwLock::new(Arc::new(LocalSnapshot::default())),
+            zone_transfers: None,
+            dynamic_updates: None,
         }
     }
 
+    /// Log answered queries through `writer`
+    pub fn with_log_writer(mut self, writer: Arc<QueryLogWriter>) -> Self {
+        self.log_writer = Some(writer);
+        self
+    }
+
+    /// Ship answered queries to the remote log sinks
+    pub fn with_log_sinks(mut self, sinks: Arc<LogSinks>) -> Self {
+        self.log_sinks = Some(sinks);
+        self
+    }
+
+    /// Record upstream answers through `recorder`
+    pub fn with_passive_dns(mut self, recorder: Arc<PassiveDnsRecorder>) -> Self {
+        self.passive_dns = Some(recorder);
+        self
+    }
+
+    /// Aggregate answered queries into statistics rollups through `rollup`
+    pub fn with_stats_rollup(mut self, rollup: Arc<StatsRollup>) -> Self {
+        self.stats_rollup = Some(rollup);
+        self
+    }
+
+    /// Publish answered queries on `stream`
+    pub fn with_query_stream(mut self, stream: Arc<QueryStream>) -> Self {
+        self.query_stream = Some(stream);
+        self
+    }
+
+    /// Log listener exchanges through `dnstap`
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.dnstap = Some(dnstap);
+        self
+    }
+
+    /// Serve zone transfers and NOTIFY through `transfers`
+    pub fn with_zone_transfers(mut self, transfers: Arc<ZoneTransfers>) -> Self {
+        self.zone_transfers = Some(transfers);
+        self
+    }
+
+    /// Accept dynamic updates through `updates`
+    pub fn with_dynamic_updates(mut self, updates: Arc<DynamicUpdates>) -> Self {
+        self.dynamic_updates = Some(updates);
+        self
+    }
+
     /// Create a new DNS resolver wrapped in Arc
     pub fn new_shared(
         rewrite_engine: Arc<RewriteEngine>,
@@ -123,6 +320,232 @@ impl DnsResolver {
         &self.proxy
     }
 
+    /// Apply response filter settings
+    pub async fn set_response_filter(&self, config: &ResponseFilterConfig) -> Result<()> {
+        let filter = ResponseFilter::from_config(config)?;
+        *self.response_filter.write().await = Arc::new(filter);
+        Ok(())
+    }
+
+    /// Apply query log privacy settings
+    pub async fn set_privacy(&self, config: &QueryPrivacyConfig) -> Result<()> {
+        let privacy = QueryPrivacy::from_config(config)?;
+        *self.privacy.write().await = Arc::new(privacy);
+        Ok(())
+    }
+
+    /// Get the query log privacy settings in effect
+    pub async fn privacy(&self) -> Arc<QueryPrivacy> {
+        self.privacy.read().await.clone()
+    }
+
+    /// Rebuild the local records and settings snapshot from the database
+    pub async fn reload_local_data(&self) -> Result<()> {
+        let Some(ref db) = self.db else {
+            return Ok(());
+        };
+        let snapshot = LocalSnapshot::load(db).await?;
+        debug!(
+            "Loaded {} local DNS records and {} zones into memory",
+            snapshot.record_count(),
+            snapshot.zone_count()
+        );
+        if let Some(ref transfers) = self.zone_transfers {
+            transfers.send_notifies(snapshot.zone_changes());
+        }
+        *self.local.write().await = Arc::new(snapshot);
+        Ok(())
+    }
+
+    /// Answer a zone transfer request, NOTIFY or dynamic update from a listener
+    ///
+    /// Returns `None` for every other message, which is then resolved as
+    /// a normal query.
+    pub async fn handle_zone_message(&self, data: &[u8], peer: IpAddr, tcp: bool) -> Option<Vec<Vec<u8>>> {
+        if is_update_message(data) {
+            let updates = self.dynamic_updates.as_ref()?;
+            return updates.handle(data, peer).await.map(|response| vec![response]);
+        }
+        let transfers = self.zone_transfers.as_ref()?;
+        if !is_zone_message(data) {
+            return None;
+        }
+        let snapshot = self.local.read().await.clone();
+        transfers.handle(data, peer, &snapshot, tcp).await
+    }
+
+    /// Get the zone transfer service, if configured
+    pub fn zone_transfers(&self) -> Option<&Arc<ZoneTransfers>> {
+        self.zone_transfers.as_ref()
+    }
+
+    /// Keep the snapshot in sync with database writes
+    ///
+    /// Bursts of writes (e.g. a batch import) are folded into one reload.
+    pub fn watch_data_changes(self: &Arc<Self>) {
+        let Some(ref db) = self.db else {
+            return;
+        };
+        let mut changes = db.subscribe_changes();
+        let resolver = Arc::clone(self);
+        tokio::spawn(async move {
+            // A lagged receiver still knows something changed, so it reloads too
+            while !matches!(changes.recv().await, Err(broadcast::error::RecvError::Closed)) {
+                while changes.try_recv().is_ok() {}
+                if let Err(e) = resolver.reload_local_data().await {
+                    tracing::warn!("Failed to reload local DNS data: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Get the query log writer, if logging is enabled
+    pub fn log_writer(&self) -> Option<&Arc<QueryLogWriter>> {
+        self.log_writer.as_ref()
+    }
+
+    /// Get the query log writer counters
+    pub fn query_log_stats(&self) -> Option<QueryLogWriterStats> {
+        self.log_writer.as_ref().map(|writer| writer.stats())
+    }
+
+    /// Get the remote log sinks, if enabled
+    pub fn log_sinks(&self) -> Option<&Arc<LogSinks>> {
+        self.log_sinks.as_ref()
+    }
+
+    /// Get the counters of every log sink
+    pub fn log_sinks_stats(&self) -> Option<Vec<LogSinkStats>> {
+        self.log_sinks.as_ref().map(|sinks| sinks.stats())
+    }
+
+    /// Get the passive DNS recorder, if enabled
+    pub fn passive_dns(&self) -> Option<&Arc<PassiveDnsRecorder>> {
+        self.passive_dns.as_ref()
+    }
+
+    /// Get the passive DNS recorder counters
+    pub fn passive_dns_stats(&self) -> Option<PassiveDnsStats> {
+        self.passive_dns.as_ref().map(|recorder| recorder.stats())
+    }
+
+    /// Get the statistics rollup aggregator, if enabled
+    pub fn stats_rollup(&self) -> Option<&Arc<StatsRollup>> {
+        self.stats_rollup.as_ref()
+    }
+
+    /// Get the live query stream, if enabled
+    pub fn query_stream(&self) -> Option<&Arc<QueryStream>> {
+        self.query_stream.as_ref()
+    }
+
+    /// Get the live query stream counters
+    pub fn query_stream_stats(&self) -> Option<QueryStreamStats> {
+        self.query_stream.as_ref().map(|stream| stream.stats())
+    }
+
+    /// Get the dnstap logger, if configured
+    pub fn dnstap(&self) -> Option<&Arc<Dnstap>> {
+        self.dnstap.as_ref()
+    }
+
+    /// Get the dnstap counters
+    pub fn dnstap_stats(&self) -> Option<DnstapStats> {
+        self.dnstap.as_ref().map(|dnstap| dnstap.stats())
+    }
+
+    /// Log a listener exchange through dnstap when configured and sampled
+    ///
+    /// `protocol` is the listener protocol (see [`QueryOrigin::protocol`]).
+    /// Clients kept out of the logs are skipped and the client address is
+    /// anonymized like in query logs.
+    pub async fn tap_client_exchange(
+        &self,
+        protocol: &str,
+        client: SocketAddr,
+        query: &[u8],
+        query_time: SystemTime,
+        response: Option<&[u8]>,
+    ) {
+        let Some(ref dnstap) = self.dnstap else {
+            return;
+        };
+        let Some(protocol) = DnstapProtocol::from_listener(protocol) else {
+            return;
+        };
+        let privacy = self.privacy().await;
+        if !privacy.logs_client(&client.ip().to_string()) {
+            return;
+        }
+        if dnstap.sample(DnstapRole::Client) {
+            dnstap.log(&DnstapExchange {
+                role: DnstapRole::Client,
+                protocol,
+                peer: privacy.client_addr(client),
+                query,
+                query_time,
+                response,
+            });
+        }
+    }
+
+    /// Get the in-flight query coalescing counters
+    pub fn coalesce_stats(&self) -> CoalesceStats {
+        self.coalescer.stats()
+    }
+
+    /// Run the response filters on an upstream answer
+    ///
+    /// Returns the NXDOMAIN replacement and the reason when a filter matched.
+    fn filter_response(filter: &ResponseFilter, privacy: &QueryPrivacy, query: &DnsQuery, response: &DnsResponse) -> Option<(DnsResponse, String)> {
+        let action = filter.check(&query.name, response)?;
+        tracing::info!("Filtered upstream answer for {} {}: {}", privacy.domain(&query.name), query.record_type, action.reason());
+        Some((DnsResponse::nxdomain(query.id), action.reason()))
+    }
+
+    /// Query upstream for a cache miss, then filter and cache the answer
+    ///
+    /// Identical misses in flight at the same time share one upstream query;
+    /// the flag tells whether this caller joined another caller's query.
+    async fn query_upstream(&self, query: &DnsQuery, cache_key: CacheKey) -> Result<(UpstreamAnswer, bool)> {
+        let proxy = self.proxy.clone();
+        let cache = self.cache.clone();
+        let filter = self.response_filter.read().await.clone();
+        let privacy = self.privacy().await;
+        let passive_dns = self.passive_dns.clone();
+        let query = query.clone();
+
+        self.coalescer.run(cache_key.clone(), move || async move {
+            let query_result = proxy.query(&query).await?;
+            let mut answer = UpstreamAnswer {
+                response: query_result.response,
+                server_name: query_result.server_name,
+                decision: query_result.decision,
+                filter_reason: None,
+            };
+
+            // Passive DNS keeps what upstream really answered, even if it gets filtered
+            if let Some(recorder) = passive_dns {
+                for record in &answer.response.answers {
+                    recorder.observe(&record.name, &record.record_type.to_string(), &record.value);
+                }
+            }
+
+            // Filtered responses are never cached
+            if let Some((filtered, reason)) = Self::filter_response(&filter, &privacy, &query, &answer.response) {
+                answer.response = filtered;
+                answer.decision = Some(match answer.decision.take() {
+                    Some(previous) => format!("{}; {}", previous, reason),
+                    None => reason.clone(),
+                });
+                answer.filter_reason = Some(reason);
+            } else if answer.response.response_code == DnsResponseCode::NoError {
+                cache.set(cache_key, answer.response.clone()).await;
+            }
+            Ok(answer)
+        }).await
+    }
+
     /// Resolve a DNS query
     ///
     /// This is the main entry point for DNS resolution. It follows this flow:
@@ -130,48 +553,57 @@ impl DnsResolver {
     /// 2. Check if record type is disabled
     /// 3. Check rewrite rules
     /// 4. If rewrite matches, apply the action
-    /// 5. Check local DNS records from database
+    /// 5. Check local DNS records (in-memory snapshot)
     /// 6. Otherwise, check cache
-    /// 7. If cache miss, query upstream via proxy
-    /// 8. Cache the response
+    /// 7. If cache miss, query upstream via proxy (shared with identical in-flight misses)
+    /// 8. Record upstream answers for passive DNS, then apply response filters
+    ///    (bogus NXDOMAIN, rebinding, IP blocklist)
+    /// 9. Cache the response unless it was filtered
     pub async fn resolve(&self, query: &DnsQuery) -> Result<ResolveResult> {
         let start = Instant::now();
         let mut metadata = QueryMetadata::default();
+        let privacy = self.privacy().await;
+        let name = privacy.domain(&query.name);
 
         // Step 0: Validate domain name - reject invalid domains
         if !Self::is_valid_domain(&query.name) {
             debug!(
                 "[DNS Result] {} {} | Invalid domain (rejected) | {}ms",
-                query.name, query.record_type, start.elapsed().as_millis()
+                name, query.record_type, start.elapsed().as_millis()
             );
             metadata.response_time_ms = start.elapsed().as_millis() as u64;
+            metadata.source = QuerySource::Invalid;
+            metadata.reason = Some("invalid domain name".to_string());
             return Ok(ResolveResult {
                 response: DnsResponse::refused(query.id),
                 metadata,
             });
         }
 
-        debug!("[DNS Query] {} {} (ID: {})", query.name, query.record_type, query.id);
+        debug!("[DNS Query] {} {} (ID: {})", name, query.record_type, query.id);
+
+        let local = self.local.read().await.clone();
 
         // Step 1: Check if record type is disabled
-        if let Some(ref db) = self.db {
-            if self.is_record_type_disabled(db, &query.record_type.to_string()).await {
-                debug!(
-                    "[DNS Result] {} {} | Disabled record type | {}ms",
-                    query.name, query.record_type, start.elapsed().as_millis()
-                );
-                metadata.response_time_ms = start.elapsed().as_millis() as u64;
-                return Ok(ResolveResult {
-                    response: DnsResponse::nxdomain(query.id),
-                    metadata,
-                });
-            }
+        if local.is_type_disabled(query.record_type) {
+            debug!(
+                "[DNS Result] {} {} | Disabled record type | {}ms",
+                name, query.record_type, start.elapsed().as_millis()
+            );
+            metadata.response_time_ms = start.elapsed().as_millis() as u64;
+            metadata.source = QuerySource::Disabled;
+            metadata.reason = Some(format!("record type {} is disabled", query.record_type));
+            return Ok(ResolveResult {
+                response: DnsResponse::nxdomain(query.id),
+                metadata,
+            });
         }
 
         // Step 2: Check rewrite rules
         if let Some(rewrite_result) = self.rewrite_engine.check(&query.name).await {
             metadata.rewrite_applied = true;
             metadata.rewrite_rule_id = Some(rewrite_result.rule_id);
+            Self::mark_rewrite(&mut metadata, rewrite_result.rule_id, &rewrite_result.action);
 
             let response = self.apply_rewrite_action(query, &rewrite_result.action).await?;
             metadata.response_time_ms = start.elapsed().as_millis() as u64;
@@ -183,29 +615,31 @@ impl DnsResolver {
             };
             debug!(
                 "[DNS Result] {} {} | Rewrite(rule_id={}) {} | {}ms",
-                query.name, query.record_type, rewrite_result.rule_id, action_desc, metadata.response_time_ms
+                name, query.record_type, rewrite_result.rule_id, action_desc, metadata.response_time_ms
             );
 
             return Ok(ResolveResult { response, metadata });
         }
 
-        // Step 2: Check local DNS records from database
-        if let Some(ref db) = self.db {
-            if let Some(response) = self.check_local_records(db, query).await? {
-                metadata.response_time_ms = start.elapsed().as_millis() as u64;
-                let answers: Vec<String> = response.answers.iter().map(|a| a.value.clone()).collect();
-                debug!(
-                    "[DNS Result] {} {} | LocalRecord | {} | {}ms",
-                    query.name, query.record_type, answers.join(", "), metadata.response_time_ms
-                );
-                return Ok(ResolveResult { response, metadata });
-            }
+        // Step 2: Check local DNS records
+        if let Some(answer) = local.answer(query) {
+            metadata.source = QuerySource::Local;
+            metadata.source_id = answer.record_id;
+            let response = self.complete_local_answer(query, answer, 0).await;
+            metadata.response_time_ms = start.elapsed().as_millis() as u64;
+            let answers: Vec<String> = response.answers.iter().map(|a| a.value.clone()).collect();
+            debug!(
+                "[DNS Result] {} {} | LocalRecord | {} | {}ms",
+                name, query.record_type, answers.join(", "), metadata.response_time_ms
+            );
+            return Ok(ResolveResult { response, metadata });
         }
 
         // Step 3: Check cache
         let cache_key = CacheKey::from_query(query);
         if let Some(cached_response) = self.cache.get(&cache_key).await {
             metadata.cache_hit = true;
+            metadata.source = QuerySource::Cache;
             metadata.response_time_ms = start.elapsed().as_millis() as u64;
 
             // Update response ID to match query
@@ -215,29 +649,26 @@ impl DnsResolver {
             let answers: Vec<String> = response.answers.iter().map(|a| a.value.clone()).collect();
             debug!(
                 "[DNS Result] {} {} | Cache | {} | {}ms",
-                query.name, query.record_type, answers.join(", "), metadata.response_time_ms
+                name, query.record_type, answers.join(", "), metadata.response_time_ms
             );
 
             return Ok(ResolveResult { response, metadata });
         }
 
-        debug!("Cache miss for {} {}", query.name, query.record_type);
+        debug!("Cache miss for {} {}", name, query.record_type);
 
-        // Step 4: Query upstream via proxy
-        let query_result = self.proxy.query(query).await?;
+        // Step 4-6: Query upstream via proxy, filter and cache the answer
+        let (answer, coalesced) = self.query_upstream(query, cache_key).await?;
         
-        metadata.upstream_used = Some(query_result.server_name.clone());
+        metadata.upstream_used = Some(answer.server_name.clone());
+        metadata.decision = Self::coalesced_decision(answer.decision, coalesced);
+        Self::mark_filtered(&mut metadata, answer.filter_reason);
         metadata.response_time_ms = start.elapsed().as_millis() as u64;
 
         // Restore original query ID in response (important for DoQ which uses ID=0)
-        let mut response = query_result.response;
+        let mut response = answer.response;
         response.id = query.id;
 
-        // Step 5: Cache the response (only if successful)
-        if response.response_code == DnsResponseCode::NoError {
-            self.cache.set(cache_key, response.clone()).await;
-        }
-
         let answers: Vec<String> = response.answers.iter().map(|a| a.value.clone()).collect();
         let result_str = if answers.is_empty() {
             format!("{}", response.response_code)
@@ -246,7 +677,7 @@ impl DnsResolver {
         };
         debug!(
             "[DNS Result] {} {} | Upstream({}) | {} | {}ms",
-            query.name, query.record_type, query_result.server_name, result_str, metadata.response_time_ms
+            name, query.record_type, answer.server_name, result_str, metadata.response_time_ms
         );
 
         Ok(ResolveResult {
@@ -255,6 +686,36 @@ impl DnsResolver {
         })
     }
 
+    /// Record which rewrite rule answered and whether it blocked the query
+    fn mark_rewrite(metadata: &mut QueryMetadata, rule_id: i64, action: &RewriteAction) {
+        metadata.source_id = Some(rule_id);
+        if matches!(action, RewriteAction::Block) {
+            metadata.source = QuerySource::Blocked;
+            metadata.reason = Some(format!("blocked by rewrite rule #{}", rule_id));
+        } else {
+            metadata.source = QuerySource::Rewrite;
+        }
+    }
+
+    /// Record that a response filter replaced the upstream answer
+    fn mark_filtered(metadata: &mut QueryMetadata, filter_reason: Option<String>) {
+        if let Some(reason) = filter_reason {
+            metadata.source = QuerySource::Filtered;
+            metadata.reason = Some(reason);
+        }
+    }
+
+    /// Note in the decision that the answer came from another client's query
+    fn coalesced_decision(decision: Option<String>, coalesced: bool) -> Option<String> {
+        if !coalesced {
+            return decision;
+        }
+        Some(match decision {
+            Some(previous) => format!("{}; coalesced with in-flight query", previous),
+            None => "coalesced with in-flight query".to_string(),
+        })
+    }
+
     /// Check if domain name is valid according to DNS standards
     /// 
     /// Valid domain names must:
@@ -320,130 +781,89 @@ impl DnsResolver {
         true
     }
 
-    /// Check if a record type is disabled in settings
-    async fn is_record_type_disabled(&self, db: &Database, record_type: &str) -> bool {
-        match db.system_config().get("disabled_record_types").await {
-            Ok(Some(value)) => {
-                if let Ok(disabled_types) = serde_json::from_str::<Vec<String>>(&value) {
-                    let upper = record_type.to_uppercase();
-                    return disabled_types.iter().any(|t| t.to_uppercase() == upper);
-                }
-                false
-            }
-            _ => false,
-        }
-    }
-
-    /// Resolve a DNS query with client IP for logging
+    /// Resolve a DNS query and log it with where it came from
     ///
-    /// This method wraps resolve() and saves the query log to database.
-    pub async fn resolve_with_client(&self, query: &DnsQuery, client_ip: &str) -> Result<ResolveResult> {
+    /// This method wraps resolve(), folds the query into the statistics
+    /// rollups, publishes it on the live stream, ships it to the log sinks
+    /// and queues the query log for the batched writer.
+    pub async fn resolve_with_client(&self, query: &DnsQuery, origin: &QueryOrigin) -> Result<ResolveResult> {
         let result = self.resolve(query).await;
         
-        // Save query log to database (fire and forget)
-        if let Some(ref db) = self.db {
-            let log = match &result {
+        // Queue query log (never blocks the query)
+        if self.log_writer.is_some() || self.log_sinks.is_some() || self.stats_rollup.is_some() || self.query_stream.is_some() {
+            let base = CreateQueryLog {
+                client_ip: origin.client_ip.clone(),
+                query_name: query.name.clone(),
+                query_type: query.record_type.to_string(),
+                protocol: Some(origin.protocol.to_string()),
+                endpoint: origin.endpoint.clone(),
+                ..Default::default()
+            };
+            let mut log = match &result {
                 Ok(r) => CreateQueryLog {
-                    client_ip: client_ip.to_string(),
-                    query_name: query.name.clone(),
-                    query_type: query.record_type.to_string(),
                     response_code: Some(r.response.response_code.to_string()),
                     response_time: Some(r.metadata.response_time_ms as i32),
                     cache_hit: r.metadata.cache_hit,
                     upstream_used: r.metadata.upstream_used.clone(),
+                    decision: r.metadata.decision.clone(),
+                    answers: Self::answers_json(&r.response),
+                    source: Some(r.metadata.source.as_str().to_string()),
+                    source_id: r.metadata.source_id,
+                    reason: r.metadata.reason.clone(),
+                    ..base
                 },
                 Err(e) => CreateQueryLog {
-                    client_ip: client_ip.to_string(),
-                    query_name: query.name.clone(),
-                    query_type: query.record_type.to_string(),
                     response_code: Some(format!("ERROR: {}", e)),
-                    response_time: None,
-                    cache_hit: false,
-                    upstream_used: None,
+                    source: Some(QuerySource::Error.as_str().to_string()),
+                    ..base
                 },
             };
-            
-            let db = db.clone();
-            tokio::spawn(async move {
-                if let Err(e) = db.query_logs().create(log).await {
-                    tracing::warn!("Failed to save query log: {}", e);
+            let privacy = self.privacy().await;
+            privacy.apply(&mut log);
+
+            // Clients kept out of the logs still count in aggregate statistics
+            if !privacy.logs_client(&origin.client_ip) {
+                log.client_ip.clear();
+                log.query_name.clear();
+                log.answers = None;
+                if let Some(ref rollup) = self.stats_rollup {
+                    rollup.record(&log);
                 }
-            });
-        }
-        
-        result
-    }
-
-    /// Check local DNS records from database
-    async fn check_local_records(&self, db: &Database, query: &DnsQuery) -> Result<Option<DnsResponse>> {
-        use std::net::{Ipv4Addr, Ipv6Addr};
-        use std::str::FromStr;
-
-        let record_type_str = query.record_type.to_string();
-        let records = db.dns_records().get_by_name_and_type_with_wildcard(&query.name, &record_type_str).await?;
-        if records.is_empty() {
-            return Ok(None);
-        }
-
-        let mut response = DnsResponse::new(query.id);
-
-        for record in records {
-            if !record.enabled {
-                continue;
-            }
-
-            // For wildcard records, use the queried name instead of the record name
-            let response_name = if record.name.starts_with("*.") {
-                &query.name
-            } else {
-                &record.name
-            };
-
-            let dns_record = match query.record_type {
-                RecordType::A => {
-                    if let Ok(ip) = Ipv4Addr::from_str(&record.value) {
-                        Some(DnsRecordData::a(response_name, ip, record.ttl as u32))
-                    } else {
-                        debug!("Invalid IPv4 address in DNS record: {}", record.value);
-                        None
-                    }
-                }
-                RecordType::AAAA => {
-                    if let Ok(ip) = Ipv6Addr::from_str(&record.value) {
-                        Some(DnsRecordData::aaaa(response_name, ip, record.ttl as u32))
-                    } else {
-                        debug!("Invalid IPv6 address in DNS record: {}", record.value);
-                        None
-                    }
-                }
-                RecordType::CNAME => {
-                    Some(DnsRecordData::cname(response_name, &record.value, record.ttl as u32))
-                }
-                RecordType::MX => {
-                    Some(DnsRecordData::mx(response_name, &record.value, record.priority as u16, record.ttl as u32))
+                if let (Some(_), Some(db)) = (&self.log_writer, &self.db) {
+                    db.query_logs().record_unlogged(log.cache_hit).await;
                 }
-                RecordType::TXT => {
-                    Some(DnsRecordData::txt(response_name, &record.value, record.ttl as u32))
-                }
-                RecordType::PTR => {
-                    Some(DnsRecordData::ptr(response_name, &record.value, record.ttl as u32))
-                }
-                RecordType::NS => {
-                    Some(DnsRecordData::ns(response_name, &record.value, record.ttl as u32))
-                }
-                _ => None,
-            };
+                return result;
+            }
 
-            if let Some(dns_record) = dns_record {
-                response.add_answer(dns_record);
+            if let Some(ref rollup) = self.stats_rollup {
+                rollup.record(&log);
+            }
+            if let Some(ref stream) = self.query_stream {
+                stream.publish(&log);
+            }
+            if let Some(ref sinks) = self.log_sinks {
+                sinks.log(&log);
+            }
+            if let Some(ref writer) = self.log_writer {
+                writer.log(log);
             }
         }
+        
+        result
+    }
 
+    /// Answer records as stored in query logs
+    fn answers_json(response: &DnsResponse) -> Option<String> {
         if response.answers.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(response))
+            return None;
         }
+        let answers: Vec<serde_json::Value> = response.answers.iter().map(|a| serde_json::json!({
+            "name": a.name,
+            "type": a.record_type.to_string(),
+            "ttl": a.ttl,
+            "value": a.value,
+        })).collect();
+        serde_json::to_string(&answers).ok()
     }
 
     /// Resolve a DNS query by name and record type
@@ -536,8 +956,11 @@ impl DnsResolver {
         Box::pin(async move {
             const MAX_DEPTH: u32 = 10;
             
+            let privacy = self.privacy().await;
+            let name = privacy.domain(&query.name);
+
             if depth > MAX_DEPTH {
-                debug!("Max rewrite depth {} exceeded for {}", MAX_DEPTH, query.name);
+                debug!("Max rewrite depth {} exceeded for {}", MAX_DEPTH, name);
                 return Err(anyhow::anyhow!("Max rewrite depth exceeded, possible circular reference"));
             }
 
@@ -546,17 +969,18 @@ impl DnsResolver {
 
             debug!(
                 "Resolving DNS query (depth {}): {} {} (ID: {})",
-                depth, query.name, query.record_type, query.id
+                depth, name, query.record_type, query.id
             );
 
             // Step 1: Check rewrite rules (allow chaining)
             if let Some(rewrite_result) = self.rewrite_engine.check(&query.name).await {
                 debug!(
                     "Rewrite rule {} matched for {} (depth {})",
-                    rewrite_result.rule_id, query.name, depth
+                    rewrite_result.rule_id, name, depth
                 );
                 metadata.rewrite_applied = true;
                 metadata.rewrite_rule_id = Some(rewrite_result.rule_id);
+                Self::mark_rewrite(&mut metadata, rewrite_result.rule_id, &rewrite_result.action);
 
                 let response = self.apply_rewrite_action_with_depth(query, &rewrite_result.action, depth).await?;
                 metadata.response_time_ms = start.elapsed().as_millis() as u64;
@@ -564,19 +988,22 @@ impl DnsResolver {
                 return Ok(ResolveResult { response, metadata });
             }
 
-            // Step 2: Check local DNS records from database
-            if let Some(ref db) = self.db {
-                if let Some(response) = self.check_local_records(db, query).await? {
-                    debug!("Local DNS record found for {} {} (depth {})", query.name, query.record_type, depth);
-                    metadata.response_time_ms = start.elapsed().as_millis() as u64;
-                    return Ok(ResolveResult { response, metadata });
-                }
+            // Step 2: Check local DNS records
+            let local_answer = self.local.read().await.answer(query);
+            if let Some(answer) = local_answer {
+                debug!("Local DNS record found for {} {} (depth {})", name, query.record_type, depth);
+                metadata.source = QuerySource::Local;
+                metadata.source_id = answer.record_id;
+                let response = self.complete_local_answer(query, answer, depth).await;
+                metadata.response_time_ms = start.elapsed().as_millis() as u64;
+                return Ok(ResolveResult { response, metadata });
             }
 
             // Step 3: Check cache
             let cache_key = CacheKey::from_query(query);
             if let Some(cached_response) = self.cache.get(&cache_key).await {
                 metadata.cache_hit = true;
+                metadata.source = QuerySource::Cache;
                 metadata.response_time_ms = start.elapsed().as_millis() as u64;
 
                 let mut response = cached_response;
@@ -585,21 +1012,18 @@ impl DnsResolver {
                 return Ok(ResolveResult { response, metadata });
             }
 
-            // Step 4: Query upstream
-            let query_result = self.proxy.query(query).await?;
+            // Step 4: Query upstream (filtered and cached by query_upstream)
+            let (answer, coalesced) = self.query_upstream(query, cache_key).await?;
             
-            metadata.upstream_used = Some(query_result.server_name);
+            metadata.upstream_used = Some(answer.server_name);
+            metadata.decision = Self::coalesced_decision(answer.decision, coalesced);
+            Self::mark_filtered(&mut metadata, answer.filter_reason);
             metadata.response_time_ms = start.elapsed().as_millis() as u64;
 
             // Restore original query ID in response (important for DoQ which uses ID=0)
-            let mut response = query_result.response;
+            let mut response = answer.response;
             response.id = query.id;
 
-            // Cache the response
-            if response.response_code == DnsResponseCode::NoError {
-                self.cache.set(cache_key, response.clone()).await;
-            }
-
             Ok(ResolveResult {
                 response,
                 metadata,
@@ -607,6 +1031,27 @@ impl DnsResolver {
         })
     }
 
+    /// Resolve the CNAME target a local answer leaves open
+    ///
+    /// The target's answers are appended after the local CNAME chain; when
+    /// it has none, its response code is passed on.
+    async fn complete_local_answer(&self, query: &DnsQuery, answer: LocalAnswer, depth: u32) -> DnsResponse {
+        let mut response = answer.response;
+        let Some(target) = answer.cname_target else {
+            return response;
+        };
+
+        let target_query = DnsQuery::with_id(query.id, target, query.record_type);
+        match self.resolve_with_depth(&target_query, depth + 1).await {
+            Ok(result) if result.response.answers.is_empty() => {
+                response.response_code = result.response.response_code;
+            }
+            Ok(result) => response.answers.extend(result.response.answers),
+            Err(e) => debug!("Failed to resolve CNAME target {}: {}", target_query.name, e),
+        }
+        response
+    }
+
     /// Apply a rewrite action with depth tracking
     fn apply_rewrite_action_with_depth<'a>(
         &'a self,
@@ -683,6 +1128,87 @@ mod tests {
         assert_eq!(result.response.response_code, DnsResponseCode::NxDomain);
         assert!(result.metadata.rewrite_applied);
         assert_eq!(result.metadata.rewrite_rule_id, Some(1));
+        assert_eq!(result.metadata.source, QuerySource::Blocked);
+        assert_eq!(result.metadata.source_id, Some(1));
+        assert_eq!(result.metadata.reason.as_deref(), Some("blocked by rewrite rule #1"));
+    }
+
+    #[tokio::test]
+    async fn test_resolver_local_snapshot() {
+        let resolver = create_test_resolver();
+        let now = chrono::Utc::now();
+        let record = crate::db::DnsRecord {
+            id: 1,
+            name: "nas.lan".to_string(),
+            record_type: "A".to_string(),
+            value: "192.168.1.20".to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled: true,
+            managed: false,
+            source: None,
+            created_at: now,
+            updated_at: now,
+        };
+        *resolver.local.write().await = Arc::new(LocalSnapshot::new(vec![record], &["AAAA".to_string()]));
+
+        let result = resolver.resolve(&DnsQuery::new("nas.lan", RecordType::A)).await.unwrap();
+        assert_eq!(result.response.answers[0].value, "192.168.1.20");
+        assert!(!result.metadata.cache_hit);
+        assert_eq!((result.metadata.source, result.metadata.source_id), (QuerySource::Local, Some(1)));
+
+        let result = resolver.resolve(&DnsQuery::new("nas.lan", RecordType::AAAA)).await.unwrap();
+        assert_eq!(result.response.response_code, DnsResponseCode::NxDomain);
+        assert_eq!(result.metadata.source, QuerySource::Disabled);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_client_logs_details() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap());
+        let writer = QueryLogWriter::start(db.clone(), Default::default());
+        let test = create_test_resolver();
+        let resolver = DnsResolver::with_db(test.rewrite_engine, test.cache, test.proxy, db.clone())
+            .with_log_writer(writer.clone());
+        resolver.rewrite_engine.add_rule(RewriteRule::new(
+            7,
+            "nas.test".to_string(),
+            MatchType::Exact,
+            RewriteAction::MapToIp(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10))),
+            10,
+        )).await;
+        resolver.rewrite_engine.add_rule(RewriteRule::new(
+            8,
+            "ads.test".to_string(),
+            MatchType::Exact,
+            RewriteAction::Block,
+            10,
+        )).await;
+
+        let origin = QueryOrigin::new("10.0.0.5", "doh").with_endpoint(Some("/dns-query".to_string()));
+        resolver.resolve_with_client(&DnsQuery::new("nas.test", RecordType::A), &origin).await.unwrap();
+        resolver.resolve_with_client(&DnsQuery::new("ads.test", RecordType::A), &QueryOrigin::new("10.0.0.6", "udp")).await.unwrap();
+        writer.shutdown().await;
+
+        let logs = db.query_logs().list(Default::default()).await.unwrap().items;
+        let log = |name: &str| logs.iter().find(|l| l.query_name == name).unwrap();
+
+        let nas = log("nas.test");
+        assert_eq!(nas.client_ip, "10.0.0.5");
+        assert_eq!(nas.protocol.as_deref(), Some("doh"));
+        assert_eq!(nas.endpoint.as_deref(), Some("/dns-query"));
+        assert_eq!((nas.source.as_deref(), nas.source_id), (Some("rewrite"), Some(7)));
+        let answers: serde_json::Value = serde_json::from_str(nas.answers.as_deref().unwrap()).unwrap();
+        assert_eq!(answers[0]["value"], "192.168.1.10");
+        assert_eq!(answers[0]["type"], "A");
+
+        let ads = log("ads.test");
+        assert_eq!(ads.protocol.as_deref(), Some("udp"));
+        assert_eq!(ads.endpoint, None);
+        assert_eq!(ads.response_code.as_deref(), Some("NXDOMAIN"));
+        assert_eq!((ads.source.as_deref(), ads.source_id), (Some("blocked"), Some(8)));
+        assert_eq!(ads.reason.as_deref(), Some("blocked by rewrite rule #8"));
+        assert_eq!(ads.answers, None);
     }
 
     #[tokio::test]
@@ -7,6 +7,7 @@
 
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use axum::{
     extract::{Query, State, ConnectInfo},
@@ -20,7 +21,7 @@ use serde::Deserialize;
 use tracing::{debug, warn};
 
 use crate::dns::message::{DnsQuery, DnsResponse};
-use crate::dns::resolver::DnsResolver;
+use crate::dns::resolver::{DnsResolver, QueryOrigin};
 
 /// DoH server state
 #[derive(Clone)]
@@ -81,7 +82,8 @@ async fn handle_get_query(
     debug!("DoH GET request received");
 
     // Get client IP from request headers or connection
-    let client_ip = get_client_ip(&request, Some(addr));
+    let origin = QueryOrigin::new(get_client_ip(&request, Some(addr)), "doh")
+        .with_endpoint(Some(request.uri().path().to_string()));
 
     // Decode base64url-encoded DNS query
     let query_bytes = match URL_SAFE_NO_PAD.decode(&params.dns) {
@@ -96,7 +98,7 @@ async fn handle_get_query(
         }
     };
 
-    process_dns_query(&state.resolver, &query_bytes, &client_ip).await
+    process_dns_query(&state.resolver, &query_bytes, &origin, addr).await
 }
 
 /// Handle POST requests for DNS queries
@@ -110,7 +112,8 @@ async fn handle_post_query(
     debug!("DoH POST request received");
 
     // Get client IP from request headers or connection
-    let client_ip = get_client_ip(&request, Some(addr));
+    let origin = QueryOrigin::new(get_client_ip(&request, Some(addr)), "doh")
+        .with_endpoint(Some(request.uri().path().to_string()));
 
     // Extract body
     let body = match axum::body::to_bytes(request.into_body(), 65536).await {
@@ -121,7 +124,7 @@ async fn handle_post_query(
         }
     };
 
-    process_dns_query(&state.resolver, &body, &client_ip).await
+    process_dns_query(&state.resolver, &body, &origin, addr).await
 }
 
 /// Get client IP from request headers or connection
@@ -153,14 +156,34 @@ fn get_client_ip(request: &axum::http::Request<axum::body::Body>, conn_addr: Opt
 
 
 /// Process a DNS query and return an HTTP response
-async fn process_dns_query(resolver: &DnsResolver, query_bytes: &[u8], client_ip: &str) -> Response {
+async fn process_dns_query(resolver: &DnsResolver, query_bytes: &[u8], origin: &QueryOrigin, peer: SocketAddr) -> Response {
+    let query_time = SystemTime::now();
+    let (response, query) = resolve_dns_query(resolver, query_bytes, origin).await;
+    match response.to_bytes(&query) {
+        Ok(bytes) => {
+            resolver.tap_client_exchange(origin.protocol, peer, query_bytes, query_time, Some(&bytes)).await;
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/dns-message")],
+                bytes,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            warn!("Failed to encode DNS response: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode DNS response").into_response()
+        }
+    }
+}
+
+/// Resolve a wire-format query, answering SERVFAIL when it cannot be parsed or resolved
+async fn resolve_dns_query(resolver: &DnsResolver, query_bytes: &[u8], origin: &QueryOrigin) -> (DnsResponse, DnsQuery) {
     // Parse the DNS query
     let query = match DnsQuery::from_bytes(query_bytes) {
         Ok(q) => q,
         Err(e) => {
             warn!("Failed to parse DNS query: {}", e);
-            let response = DnsResponse::servfail(0);
-            return create_dns_response(&response, &DnsQuery::new(".", crate::dns::message::RecordType::A));
+            return (DnsResponse::servfail(0), DnsQuery::new(".", crate::dns::message::RecordType::A));
         }
     };
 
@@ -170,12 +193,11 @@ async fn process_dns_query(resolver: &DnsResolver, query_bytes: &[u8], client_ip
     );
 
     // Resolve the query with client IP for logging
-    let result = match resolver.resolve_with_client(&query, client_ip).await {
+    let result = match resolver.resolve_with_client(&query, origin).await {
         Ok(r) => r,
         Err(e) => {
             warn!("Failed to resolve query for {}: {}", query.name, e);
-            let response = DnsResponse::servfail(query.id);
-            return create_dns_response(&response, &query);
+            return (DnsResponse::servfail(query.id), query);
         }
     };
 
@@ -188,23 +210,7 @@ async fn process_dns_query(resolver: &DnsResolver, query_bytes: &[u8], client_ip
         result.metadata.response_time_ms
     );
 
-    create_dns_response(&result.response, &query)
-}
-
-/// Create an HTTP response with DNS message content
-fn create_dns_response(response: &DnsResponse, query: &DnsQuery) -> Response {
-    match response.to_bytes(query) {
-        Ok(bytes) => (
-            StatusCode::OK,
-            [(header::CONTENT_TYPE, "application/dns-message")],
-            bytes,
-        )
-            .into_response(),
-        Err(e) => {
-            warn!("Failed to encode DNS response: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode DNS response").into_response()
-        }
-    }
+    (result.response, query)
 }
 
 /// DoH JSON response format (alternative format)
@@ -9,6 +9,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Result};
 use quinn::{Endpoint, ServerConfig};
@@ -17,7 +18,7 @@ use rustls_pemfile::{certs, private_key};
 use tracing::{debug, info, warn};
 
 use crate::dns::message::{DnsQuery, DnsResponse};
-use crate::dns::resolver::DnsResolver;
+use crate::dns::resolver::{DnsResolver, QueryOrigin};
 use super::dot::TlsConfig;
 
 /// DNS over QUIC Server
@@ -143,6 +144,11 @@ impl DoqDnsServer {
         connection: quinn::Connection,
     ) -> Result<()> {
         let peer_addr = connection.remote_address();
+        let sni = connection
+            .handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+            .and_then(|data| data.server_name);
+        let origin = QueryOrigin::new(peer_addr.ip().to_string(), "doq").with_endpoint(sni);
 
         // Handle multiple streams on the same connection
         loop {
@@ -150,9 +156,10 @@ impl DoqDnsServer {
                 Ok((send, recv)) => {
                     let resolver = resolver.clone();
                     let peer = peer_addr;
+                    let origin = origin.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_stream(resolver, send, recv, peer).await {
+                        if let Err(e) = Self::handle_stream(resolver, send, recv, peer, &origin).await {
                             debug!("Error handling DoQ stream from {}: {}", peer, e);
                         }
                     });
@@ -176,7 +183,8 @@ impl DoqDnsServer {
         resolver: Arc<DnsResolver>,
         mut send: quinn::SendStream,
         mut recv: quinn::RecvStream,
-        peer_addr: SocketAddr,
+        peer: SocketAddr,
+        origin: &QueryOrigin,
     ) -> Result<()> {
         // Read query length (2 bytes, big-endian)
         let mut len_buf = [0u8; 2];
@@ -192,10 +200,11 @@ impl DoqDnsServer {
         let mut query_buf = vec![0u8; query_len];
         recv.read_exact(&mut query_buf).await
             .map_err(|e| anyhow!("Failed to read query data: {}", e))?;
+        let query_time = SystemTime::now();
 
         // Process the query
-        let client_ip = peer_addr.ip().to_string();
-        let response_bytes = Self::handle_query(&resolver, &query_buf, &client_ip).await?;
+        let response_bytes = Self::handle_query(&resolver, &query_buf, &origin).await?;
+        resolver.tap_client_exchange("doq", peer, &query_buf, query_time, Some(&response_bytes)).await;
 
         // Write response length
         let response_len = (response_bytes.len() as u16).to_be_bytes();
@@ -214,7 +223,7 @@ impl DoqDnsServer {
     }
 
     /// Handle a DNS query and return the response bytes
-    async fn handle_query(resolver: &DnsResolver, data: &[u8], client_ip: &str) -> Result<Vec<u8>> {
+    async fn handle_query(resolver: &DnsResolver, data: &[u8], origin: &QueryOrigin) -> Result<Vec<u8>> {
         // Parse the query
         let query = match DnsQuery::from_bytes(data) {
             Ok(q) => q,
@@ -232,7 +241,7 @@ impl DoqDnsServer {
         );
 
         // Resolve the query with client IP for logging
-        let result = match resolver.resolve_with_client(&query, client_ip).await {
+        let result = match resolver.resolve_with_client(&query, origin).await {
             Ok(r) => r,
             Err(e) => {
                 warn!("Failed to resolve query for {}: {}", query.name, e);
@@ -8,6 +8,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Result};
 use rustls::ServerConfig;
@@ -19,7 +20,7 @@ use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info, warn};
 
 use crate::dns::message::{DnsQuery, DnsResponse};
-use crate::dns::resolver::DnsResolver;
+use crate::dns::resolver::{DnsResolver, QueryOrigin};
 
 /// TLS configuration for the DoT server
 #[derive(Clone)]
@@ -166,6 +167,9 @@ impl DotDnsServer {
 
         debug!("TLS handshake completed with {}", peer_addr);
 
+        let sni = tls_stream.get_ref().1.server_name().map(str::to_string);
+        let origin = QueryOrigin::new(peer_addr.ip().to_string(), "dot").with_endpoint(sni);
+
         // Handle multiple queries on the same connection (TCP DNS allows this)
         loop {
             // Read query length (2 bytes, big-endian)
@@ -190,10 +194,11 @@ impl DotDnsServer {
             let mut query_buf = vec![0u8; query_len];
             tls_stream.read_exact(&mut query_buf).await
                 .map_err(|e| anyhow!("Failed to read query data: {}", e))?;
+            let query_time = SystemTime::now();
 
             // Process the query
-            let client_ip = peer_addr.ip().to_string();
-            let response_bytes = Self::handle_query(&resolver, &query_buf, &client_ip).await?;
+            let response_bytes = Self::handle_query(&resolver, &query_buf, &origin).await?;
+            resolver.tap_client_exchange("dot", peer_addr, &query_buf, query_time, Some(&response_bytes)).await;
 
             // Write response length
             let response_len = (response_bytes.len() as u16).to_be_bytes();
@@ -212,7 +217,7 @@ impl DotDnsServer {
     }
 
     /// Handle a DNS query and return the response bytes
-    async fn handle_query(resolver: &DnsResolver, data: &[u8], client_ip: &str) -> Result<Vec<u8>> {
+    async fn handle_query(resolver: &DnsResolver, data: &[u8], origin: &QueryOrigin) -> Result<Vec<u8>> {
         // Parse the query
         let query = match DnsQuery::from_bytes(data) {
             Ok(q) => q,
@@ -230,7 +235,7 @@ impl DotDnsServer {
         );
 
         // Resolve the query with client IP for logging
-        let result = match resolver.resolve_with_client(&query, client_ip).await {
+        let result = match resolver.resolve_with_client(&query, origin).await {
             Ok(r) => r,
             Err(e) => {
                 warn!("Failed to resolve query for {}: {}", query.name, e);
@@ -2,11 +2,13 @@
 //!
 //! Provides DNS server implementations for multiple protocols:
 //! - UDP: Standard DNS over UDP (port 53)
+//! - TCP: Standard DNS over TCP (port 53), also used for zone transfers
 //! - DoT: DNS over TLS (port 853)
 //! - DoH: DNS over HTTPS (port 443)
 //! - DoQ: DNS over QUIC (port 8853)
 
 mod udp;
+mod tcp;
 mod dot;
 mod doh;
 mod doq;
@@ -16,6 +18,8 @@ mod protocol_consistency_tests;
 
 pub use udp::*;
 #[allow(unused_imports)]
+pub use tcp::*;
+#[allow(unused_imports)]
 pub use dot::*;
 pub use doh::*;
 #[allow(unused_imports)]
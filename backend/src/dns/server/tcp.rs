@@ -0,0 +1,225 @@
+//! TCP DNS Server
+//!
+//! Implements standard DNS over TCP (port 53): length-prefixed messages,
+//! several per connection. Besides normal queries it carries zone
+//! transfers (AXFR/IXFR), which do not fit in UDP.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tracing::{debug, error, info, warn};
+
+use crate::dns::message::{DnsQuery, DnsResponse};
+use crate::dns::resolver::{DnsResolver, QueryOrigin};
+
+/// How long a connection may sit idle, or stall mid-message, before it is
+/// closed (RFC 7766 §6.2.3)
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// TCP DNS Server
+///
+/// Handles DNS queries and zone transfers over plain TCP.
+pub struct TcpDnsServer {
+    /// TCP listener
+    listener: TcpListener,
+    /// DNS resolver
+    resolver: Arc<DnsResolver>,
+    /// Server bind address
+    bind_addr: SocketAddr,
+}
+
+impl TcpDnsServer {
+    /// Create a new TCP DNS server
+    pub async fn new(bind_addr: SocketAddr, resolver: Arc<DnsResolver>) -> Result<Self> {
+        let listener = TcpListener::bind(bind_addr).await
+            .map_err(|e| anyhow!("Failed to bind TCP listener to {}: {}", bind_addr, e))?;
+
+        info!("TCP DNS server bound to {}", bind_addr);
+
+        Ok(Self {
+            listener,
+            resolver,
+            bind_addr,
+        })
+    }
+
+    /// Get the server's bind address
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.bind_addr
+    }
+
+    /// Get the local address the server is actually bound to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+            .map_err(|e| anyhow!("Failed to get local address: {}", e))
+    }
+
+    /// Run the TCP DNS server
+    ///
+    /// This method runs indefinitely, processing incoming connections.
+    pub async fn run(&self) -> Result<()> {
+        info!("TCP DNS server starting on {}", self.bind_addr);
+
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    let resolver = self.resolver.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(e) = Self::handle_connection(resolver, stream, peer_addr).await {
+                            warn!("Error handling TCP connection from {}: {}", peer_addr, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Error accepting TCP connection: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Handle a single TCP connection
+    async fn handle_connection(
+        resolver: Arc<DnsResolver>,
+        mut stream: TcpStream,
+        peer_addr: SocketAddr,
+    ) -> Result<()> {
+        debug!("New TCP connection from {}", peer_addr);
+        let origin = QueryOrigin::new(peer_addr.ip().to_string(), "tcp");
+
+        while let Some(query_buf) = read_message(&mut stream, IDLE_TIMEOUT).await? {
+            let query_time = SystemTime::now();
+
+            // Zone transfers and NOTIFY may answer with several messages
+            let responses = match resolver.handle_zone_message(&query_buf, peer_addr.ip(), true).await {
+                Some(responses) => responses,
+                None => vec![Self::handle_query(&resolver, &query_buf, &origin).await?],
+            };
+            resolver.tap_client_exchange("tcp", peer_addr, &query_buf, query_time, responses.first().map(Vec::as_slice)).await;
+
+            for response_bytes in responses {
+                stream.write_all(&length_prefix(response_bytes.len())?).await
+                    .map_err(|e| anyhow!("Failed to write response length: {}", e))?;
+                stream.write_all(&response_bytes).await
+                    .map_err(|e| anyhow!("Failed to write response data: {}", e))?;
+            }
+            stream.flush().await
+                .map_err(|e| anyhow!("Failed to flush response: {}", e))?;
+        }
+
+        debug!("TCP connection from {} closed", peer_addr);
+        Ok(())
+    }
+
+    /// Handle a DNS query and return the response bytes
+    async fn handle_query(resolver: &DnsResolver, data: &[u8], origin: &QueryOrigin) -> Result<Vec<u8>> {
+        // Parse the query
+        let query = match DnsQuery::from_bytes(data) {
+            Ok(q) => q,
+            Err(e) => {
+                debug!("Failed to parse DNS query: {}", e);
+                let response = DnsResponse::servfail(0);
+                return response.to_bytes(&DnsQuery::new(".", crate::dns::message::RecordType::A))
+                    .map_err(|e| anyhow!("Failed to encode error response: {}", e));
+            }
+        };
+
+        debug!(
+            "Received TCP query: {} {} (ID: {})",
+            query.name, query.record_type, query.id
+        );
+
+        // Resolve the query with client IP for logging
+        let result = match resolver.resolve_with_client(&query, origin).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Failed to resolve query for {}: {}", query.name, e);
+                let response = DnsResponse::servfail(query.id);
+                return response.to_bytes(&query)
+                    .map_err(|e| anyhow!("Failed to encode error response: {}", e));
+            }
+        };
+
+        debug!(
+            "Resolved {} {}: {} answers, cache_hit={}, time={}ms",
+            query.name,
+            query.record_type,
+            result.response.answers.len(),
+            result.metadata.cache_hit,
+            result.metadata.response_time_ms
+        );
+
+        // Encode the response
+        result.response.to_bytes(&query)
+            .map_err(|e| anyhow!("Failed to encode response: {}", e))
+    }
+}
+
+/// Read one length-prefixed message
+///
+/// Returns `None` once the client closes the connection or stays idle for
+/// `idle`; a message that stalls half-way is an error.
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S, idle: Duration) -> Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 2];
+    match timeout(idle, stream.read_exact(&mut len_buf)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Ok(Err(e)) => return Err(anyhow!("Failed to read query length: {}", e)),
+        Err(_) => return Ok(None),
+    }
+
+    let query_len = u16::from_be_bytes(len_buf) as usize;
+    if query_len == 0 {
+        return Err(anyhow!("Invalid query length: {}", query_len));
+    }
+
+    let mut query_buf = vec![0u8; query_len];
+    timeout(idle, stream.read_exact(&mut query_buf)).await
+        .map_err(|_| anyhow!("Timed out reading query data"))?
+        .map_err(|e| anyhow!("Failed to read query data: {}", e))?;
+    Ok(Some(query_buf))
+}
+
+/// Two-byte length prefix of a response
+///
+/// Messages over 65535 bytes cannot be framed and are refused rather than
+/// sent with a truncated length.
+fn length_prefix(len: usize) -> Result<[u8; 2]> {
+    u16::try_from(len)
+        .map(u16::to_be_bytes)
+        .map_err(|_| anyhow!("Response of {} bytes exceeds the 65535-byte TCP message limit", len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_message_times_out() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&[0, 3, 1, 2, 3]).await.unwrap();
+        assert_eq!(read_message(&mut server, Duration::from_millis(50)).await.unwrap(), Some(vec![1, 2, 3]));
+
+        // An idle connection is closed quietly, a stalled message is an error
+        assert_eq!(read_message(&mut server, Duration::from_millis(50)).await.unwrap(), None);
+        client.write_all(&[0, 3, 1]).await.unwrap();
+        assert!(read_message(&mut server, Duration::from_millis(50)).await.is_err());
+
+        drop(client);
+        assert_eq!(read_message(&mut server, Duration::from_millis(50)).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_length_prefix() {
+        assert_eq!(length_prefix(512).unwrap(), [2, 0]);
+        assert_eq!(length_prefix(65535).unwrap(), [255, 255]);
+        assert!(length_prefix(70_000).is_err());
+    }
+}
@@ -6,13 +6,14 @@
 
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Result};
 use tokio::net::UdpSocket;
 use tracing::{debug, error, info, warn};
 
 use crate::dns::message::{DnsQuery, DnsResponse};
-use crate::dns::resolver::DnsResolver;
+use crate::dns::resolver::{DnsResolver, QueryOrigin};
 
 /// UDP DNS Server
 ///
@@ -93,8 +94,16 @@ impl UdpDnsServer {
         src: SocketAddr,
     ) -> Result<()> {
         debug!("Processing query from {}", src);
+        let query_time = SystemTime::now();
         let client_ip = src.ip().to_string();
-        let response_bytes = Self::handle_query_internal(&self.resolver, &data, &client_ip).await?;
+        // Transfers over UDP only ever get one message back (the SOA or TC)
+        let zone_response = self.resolver.handle_zone_message(&data, src.ip(), false).await
+            .and_then(|responses| responses.into_iter().next());
+        let response_bytes = match zone_response {
+            Some(response) => response,
+            None => Self::handle_query_internal(&self.resolver, &data, &client_ip).await?,
+        };
+        self.resolver.tap_client_exchange("udp", src, &data, query_time, Some(&response_bytes)).await;
         
         debug!("Sending {} byte response to {}", response_bytes.len(), src);
         self.socket.send_to(&response_bytes, src).await
@@ -128,7 +137,8 @@ impl UdpDnsServer {
         );
 
         // Resolve the query with client IP for logging
-        let result = match resolver.resolve_with_client(&query, client_ip).await {
+        let origin = QueryOrigin::new(client_ip, "udp");
+        let result = match resolver.resolve_with_client(&query, &origin).await {
             Ok(r) => r,
             Err(e) => {
                 warn!("Failed to resolve query for {}: {}", query.name, e);
@@ -0,0 +1,824 @@
+//! Zone Transfers
+//!
+//! Sends local zones to secondary servers with AXFR (RFC 5936) and IXFR
+//! (RFC 1995, from the zone journal) and announces serial changes with
+//! NOTIFY (RFC 1996).
+//!
+//! In the other direction, secondary zones are kept in sync with their
+//! primaries: the SOA serial is checked every refresh interval or when a
+//! primary sends a NOTIFY, and the zone is pulled again with AXFR when it
+//! moved. Transferred data is stored on the zone and served from the
+//! in-memory snapshot like local zones. A secondary that cannot reach any
+//! primary for the SOA expire time stops answering for the zone.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType as WireRecordType};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, info, warn};
+
+use crate::db::{DataChange, Database, DnsZone};
+use super::local::{LocalSnapshot, ZoneChange, ZoneTransferData};
+use super::message::{DnsRecordData, RecordType};
+
+/// Port used when a primary or NOTIFY target has none
+const DNS_PORT: u16 = 53;
+
+/// Records per outgoing transfer message
+const RECORDS_PER_MESSAGE: usize = 100;
+
+/// Limit for one exchange with a primary, a whole AXFR included
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Wait for a NOTIFY answer before sending it again
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// NOTIFY sends per target
+const NOTIFY_ATTEMPTS: usize = 3;
+
+/// Longest sleep of the refresh loop
+const MAX_IDLE: Duration = Duration::from_secs(60);
+
+/// Parse `ip`, `ip:port` or `[ipv6]:port`, defaulting to port 53
+pub fn parse_endpoint(value: &str) -> Option<SocketAddr> {
+    let value = value.trim();
+    value
+        .parse::<SocketAddr>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+}
+
+/// Whether serial `a` is newer than `b` in serial number arithmetic (RFC 1982)
+fn serial_newer(a: u32, b: u32) -> bool {
+    a != b && (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Serial of an SOA record
+fn soa_serial(soa: &DnsRecordData) -> Option<u32> {
+    soa.value.split_whitespace().nth(2)?.parse().ok()
+}
+
+/// Copy of an SOA record with another serial
+fn soa_with_serial(soa: &DnsRecordData, serial: i64) -> DnsRecordData {
+    let mut parts: Vec<String> = soa.value.split_whitespace().map(str::to_string).collect();
+    if let Some(part) = parts.get_mut(2) {
+        *part = serial.to_string();
+    }
+    DnsRecordData {
+        value: parts.join(" "),
+        ..soa.clone()
+    }
+}
+
+/// Refresh, retry and expire timers of an SOA record
+fn soa_timers(soa: &DnsRecordData) -> Option<[Duration; 3]> {
+    let parts: Vec<&str> = soa.value.split_whitespace().collect();
+    let timer = |i: usize| parts.get(i)?.parse::<u64>().ok().map(|s| Duration::from_secs(s.max(1)));
+    Some([timer(3)?, timer(4)?, timer(5)?])
+}
+
+/// Whether raw bytes hold a transfer request or a NOTIFY
+///
+/// Only peeks at the header and question, so ordinary queries skip a
+/// second parse.
+pub fn is_zone_message(data: &[u8]) -> bool {
+    if data.len() < 12 || data[2] & 0x80 != 0 {
+        return false;
+    }
+    match (data[2] >> 3) & 0x0f {
+        // NOTIFY
+        4 => true,
+        // QUERY: look at the question type after the name
+        0 => {
+            let mut pos = 12;
+            while let Some(&len) = data.get(pos) {
+                if len == 0 || len & 0xc0 != 0 {
+                    break;
+                }
+                pos += len as usize + 1;
+            }
+            data.get(pos) == Some(&0) && matches!(data.get(pos + 1..pos + 3), Some([0, 251 | 252]))
+        }
+        _ => false,
+    }
+}
+
+/// Query for `origin` with a fresh ID
+fn request_message(origin: &str, record_type: WireRecordType) -> Result<Message> {
+    let name = Name::from_str(origin).map_err(|e| anyhow!("Invalid zone name {}: {}", origin, e))?;
+    let mut message = Message::new();
+    message
+        .set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query);
+    message.add_query(Query::query(name, record_type));
+    Ok(message)
+}
+
+/// Response to `request` carrying its question
+fn reply(request: &Message, code: ResponseCode) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_authoritative(code == ResponseCode::NoError)
+        .set_response_code(code);
+    response.add_queries(request.queries().iter().cloned());
+    response
+}
+
+/// Split transfer records over as many messages as needed
+fn transfer_messages(request: &Message, records: &[DnsRecordData]) -> Vec<Message> {
+    let records: Vec<_> = records.iter().filter_map(DnsRecordData::to_record).collect();
+    records
+        .chunks(RECORDS_PER_MESSAGE)
+        .map(|chunk| {
+            let mut response = reply(request, ResponseCode::NoError);
+            response.add_answers(chunk.iter().cloned());
+            response
+        })
+        .collect()
+}
+
+/// Whole zone as sent by AXFR: SOA, records, SOA
+fn full_transfer(zone: &ZoneTransferData<'_>) -> Vec<DnsRecordData> {
+    let mut records = Vec::with_capacity(zone.records.len() + 2);
+    records.push(zone.soa.clone());
+    records.extend(zone.records.iter().cloned());
+    records.push(zone.soa.clone());
+    records
+}
+
+/// Send a request over TCP and read responses until `done` returns true
+async fn tcp_exchange(
+    server: SocketAddr,
+    request: &Message,
+    mut done: impl FnMut(&Message) -> Result<bool>,
+) -> Result<()> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let bytes = request.to_vec()?;
+        stream.write_all(&(bytes.len() as u16).to_be_bytes()).await?;
+        stream.write_all(&bytes).await?;
+
+        loop {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await?;
+            let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut buf).await?;
+
+            let response = Message::from_vec(&buf)?;
+            if response.id() != request.id() {
+                bail!("Response ID does not match the request");
+            }
+            if response.response_code() != ResponseCode::NoError {
+                bail!("Server answered {}", response.response_code());
+            }
+            if done(&response)? {
+                return Ok(());
+            }
+        }
+    };
+    tokio::time::timeout(TRANSFER_TIMEOUT, exchange)
+        .await
+        .map_err(|_| anyhow!("Timed out after {}s", TRANSFER_TIMEOUT.as_secs()))?
+}
+
+/// Current SOA of a zone on a primary
+async fn query_soa(primary: SocketAddr, origin: &str) -> Result<DnsRecordData> {
+    let request = request_message(origin, WireRecordType::SOA)?;
+    let mut soa = None;
+    tcp_exchange(primary, &request, |response| {
+        soa = response
+            .answers()
+            .iter()
+            .filter_map(DnsRecordData::from_record)
+            .find(|r| r.record_type == RecordType::SOA);
+        Ok(true)
+    })
+    .await?;
+    soa.ok_or_else(|| anyhow!("No SOA for {}", origin))
+}
+
+/// Pull a whole zone from a primary; the closing SOA is dropped
+async fn axfr(primary: SocketAddr, origin: &str) -> Result<Vec<DnsRecordData>> {
+    let request = request_message(origin, WireRecordType::AXFR)?;
+    let suffix = format!(".{}", origin);
+    let mut records: Vec<DnsRecordData> = Vec::new();
+    let mut skipped = 0usize;
+
+    tcp_exchange(primary, &request, |response| {
+        for record in response.answers() {
+            let Some(mut data) = DnsRecordData::from_record(record) else {
+                skipped += 1;
+                continue;
+            };
+            data.name = data.name.to_lowercase();
+            let is_soa = data.record_type == RecordType::SOA && data.name == origin;
+            match (records.is_empty(), is_soa) {
+                (true, false) => bail!("Transfer does not start with the zone SOA"),
+                (false, true) => return Ok(true),
+                _ if data.name == origin || data.name.ends_with(&suffix) => records.push(data),
+                _ => skipped += 1,
+            }
+        }
+        Ok(false)
+    })
+    .await?;
+
+    if skipped > 0 {
+        debug!("Skipped {} unsupported or out-of-zone records transferring {}", skipped, origin);
+    }
+    Ok(records)
+}
+
+/// Send one NOTIFY over UDP and wait for the answer
+async fn send_notify(origin: &str, target: SocketAddr) -> Result<()> {
+    let mut request = request_message(origin, WireRecordType::SOA)?;
+    request.set_op_code(OpCode::Notify).set_authoritative(true);
+    let bytes = request.to_vec()?;
+
+    let bind: SocketAddr = if target.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(target).await?;
+
+    let mut buf = vec![0u8; 4096];
+    for _ in 0..NOTIFY_ATTEMPTS {
+        socket.send(&bytes).await?;
+        let Ok(received) = tokio::time::timeout(NOTIFY_TIMEOUT, socket.recv(&mut buf)).await else {
+            continue;
+        };
+        let response = Message::from_vec(&buf[..received?])?;
+        if response.id() != request.id() {
+            continue;
+        }
+        return match response.response_code() {
+            ResponseCode::NoError => Ok(()),
+            code => Err(anyhow!("Answered {}", code)),
+        };
+    }
+    bail!("No answer after {} attempts", NOTIFY_ATTEMPTS)
+}
+
+/// NOTIFY every target about a zone in the background
+fn spawn_notifies<S: AsRef<str>>(origin: &str, targets: &[S]) {
+    for target in targets {
+        let Some(addr) = parse_endpoint(target.as_ref()) else {
+            warn!("Invalid NOTIFY target '{}' for zone {}", target.as_ref(), origin);
+            continue;
+        };
+        let origin = origin.to_string();
+        tokio::spawn(async move {
+            match send_notify(&origin, addr).await {
+                Ok(()) => debug!("Sent NOTIFY for {} to {}", origin, addr),
+                Err(e) => warn!("NOTIFY for {} to {} failed: {}", origin, addr, e),
+            }
+        });
+    }
+}
+
+/// Refresh state of a secondary zone
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SecondaryStatus {
+    /// Last SOA check against a primary
+    pub last_check: Option<DateTime<Utc>>,
+    /// Last time the zone was pulled
+    pub last_transfer: Option<DateTime<Utc>>,
+    /// Primary that answered the last check
+    pub primary: Option<String>,
+    /// Why the last check failed
+    pub error: Option<String>,
+    pub next_refresh: Option<DateTime<Utc>>,
+    /// The data was dropped after no primary answered for the expire time
+    pub expired: bool,
+}
+
+/// Refresh bookkeeping of a secondary zone
+struct Secondary {
+    status: SecondaryStatus,
+    /// Primaries the state was built for; editing them triggers a refresh
+    primaries: String,
+    due: Instant,
+    /// Last time a primary confirmed the data
+    confirmed: Option<Instant>,
+    /// Refresh, retry and expire from the primary's SOA, or the zone settings
+    timers: [Duration; 3],
+}
+
+/// Outgoing transfers, NOTIFY handling and the secondary zone refresh loop
+pub struct ZoneTransfers {
+    db: Arc<Database>,
+    secondaries: Mutex<HashMap<i64, Secondary>>,
+    wake: Notify,
+}
+
+impl ZoneTransfers {
+    /// Create without the refresh loop
+    pub fn new(db: Arc<Database>) -> Self {
+        Self {
+            db,
+            secondaries: Mutex::new(HashMap::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Create and start refreshing secondary zones in the background
+    pub fn start(db: Arc<Database>) -> Arc<Self> {
+        let transfers = Arc::new(Self::new(db));
+        tokio::spawn(transfers.clone().run());
+        transfers
+    }
+
+    /// Refresh state of a secondary zone, once the loop has seen it
+    pub fn status(&self, zone_id: i64) -> Option<SecondaryStatus> {
+        self.secondaries.lock().unwrap().get(&zone_id).map(|s| s.status.clone())
+    }
+
+    /// Check a secondary zone against its primaries right away
+    pub fn refresh_now(&self, zone_id: i64) {
+        if let Some(secondary) = self.secondaries.lock().unwrap().get_mut(&zone_id) {
+            secondary.due = Instant::now();
+        }
+        self.wake.notify_one();
+    }
+
+    /// NOTIFY the configured servers of zones whose serial moved
+    pub fn send_notifies(&self, changes: &[ZoneChange]) {
+        for change in changes.iter().filter(|c| !c.notify.is_empty()) {
+            debug!("Zone {} is now at serial {}, notifying {:?}", change.origin, change.serial, change.notify);
+            spawn_notifies(&change.origin, &change.notify);
+        }
+    }
+
+    /// Answer an AXFR, IXFR or NOTIFY message from `peer`
+    ///
+    /// Returns the response messages, or `None` when `data` is none of
+    /// those and should be resolved as a normal query.
+    pub async fn handle(&self, data: &[u8], peer: IpAddr, snapshot: &LocalSnapshot, tcp: bool) -> Option<Vec<Vec<u8>>> {
+        if !is_zone_message(data) {
+            return None;
+        }
+        let request = Message::from_vec(data).ok()?;
+        let query = request.queries().first()?.clone();
+        let origin = query.name().to_string().trim_end_matches('.').to_lowercase();
+
+        let messages = if request.op_code() == OpCode::Notify {
+            vec![self.handle_notify(&request, &origin, peer).await]
+        } else {
+            self.handle_transfer(&request, &query, &origin, peer, snapshot, tcp).await
+        };
+        match messages.iter().map(Message::to_vec).collect::<Result<Vec<_>, _>>() {
+            Ok(encoded) => Some(encoded),
+            Err(e) => {
+                warn!("Failed to encode transfer of {} for {}: {}", origin, peer, e);
+                None
+            }
+        }
+    }
+
+    /// Answer a transfer request for a local or secondary zone
+    async fn handle_transfer(
+        &self,
+        request: &Message,
+        query: &Query,
+        origin: &str,
+        peer: IpAddr,
+        snapshot: &LocalSnapshot,
+        tcp: bool,
+    ) -> Vec<Message> {
+        let Some(zone) = snapshot.zone_transfer(origin, peer) else {
+            return vec![reply(request, ResponseCode::NotAuth)];
+        };
+        if !zone.allowed {
+            debug!("Refused transfer of {} to {}", origin, peer);
+            return vec![reply(request, ResponseCode::Refused)];
+        }
+
+        let ixfr = query.query_type() == WireRecordType::IXFR;
+        let current = soa_serial(zone.soa).unwrap_or(0);
+        let client_serial = request
+            .name_servers()
+            .iter()
+            .filter_map(DnsRecordData::from_record)
+            .find(|r| r.record_type == RecordType::SOA)
+            .and_then(|soa| soa_serial(&soa));
+
+        // Over UDP, or when the client is current, IXFR gets the SOA alone
+        if ixfr && (!tcp || client_serial.is_some_and(|serial| !serial_newer(current, serial))) {
+            return transfer_messages(request, std::slice::from_ref(zone.soa));
+        }
+        if !tcp {
+            let mut response = reply(request, ResponseCode::NoError);
+            response.set_truncated(true);
+            return vec![response];
+        }
+
+        let incremental = match client_serial {
+            Some(serial) if ixfr => self.incremental_transfer(&zone, serial, current).await,
+            _ => None,
+        };
+        let kind = if incremental.is_some() { "IXFR" } else { "AXFR" };
+        let records = incremental.unwrap_or_else(|| full_transfer(&zone));
+        info!("{} of zone {} serial {} to {} ({} records)", kind, origin, current, peer, records.len());
+        transfer_messages(request, &records)
+    }
+
+    /// IXFR body from the journal, if it covers `from` up to `current`
+    async fn incremental_transfer(&self, zone: &ZoneTransferData<'_>, from: u32, current: u32) -> Option<Vec<DnsRecordData>> {
+        let journal = match self.db.dns_zones().journal(zone.zone_id).await {
+            Ok(journal) => journal,
+            Err(e) => {
+                warn!("Failed to read zone journal: {}", e);
+                return None;
+            }
+        };
+        let start = journal.iter().position(|step| step.serial_from == from as i64)?;
+        let steps = &journal[start..];
+        let chained = steps.windows(2).all(|w| w[0].serial_to == w[1].serial_from);
+        if !chained || steps.last()?.serial_to != current as i64 {
+            return None;
+        }
+
+        let mut records = vec![zone.soa.clone()];
+        for step in steps {
+            let removed: Vec<DnsRecordData> = serde_json::from_str(&step.removed).ok()?;
+            let added: Vec<DnsRecordData> = serde_json::from_str(&step.added).ok()?;
+            records.push(soa_with_serial(zone.soa, step.serial_from));
+            records.extend(removed);
+            records.push(soa_with_serial(zone.soa, step.serial_to));
+            records.extend(added);
+        }
+        records.push(zone.soa.clone());
+        Some(records)
+    }
+
+    /// Accept a NOTIFY from a primary of a secondary zone
+    async fn handle_notify(&self, request: &Message, origin: &str, peer: IpAddr) -> Message {
+        let zone = match self.db.dns_zones().get_by_origin(origin).await {
+            Ok(Some(zone)) if zone.enabled && zone.is_secondary() => zone,
+            Ok(_) => return reply(request, ResponseCode::NotAuth),
+            Err(e) => {
+                warn!("Failed to look up zone {} for NOTIFY: {}", origin, e);
+                return reply(request, ResponseCode::ServFail);
+            }
+        };
+        let from_primary = zone
+            .primary_list()
+            .into_iter()
+            .filter_map(parse_endpoint)
+            .any(|primary| primary.ip().to_canonical() == peer.to_canonical());
+        if !from_primary {
+            debug!("Ignored NOTIFY for {} from {}, not a primary", origin, peer);
+            return reply(request, ResponseCode::Refused);
+        }
+
+        debug!("NOTIFY for {} from {}", origin, peer);
+        self.refresh_now(zone.id);
+        reply(request, ResponseCode::NoError)
+    }
+
+    /// Refresh secondary zones until the database goes away
+    async fn run(self: Arc<Self>) {
+        let mut changes = self.db.subscribe_changes();
+        loop {
+            let idle = self.refresh_due().await;
+            tokio::select! {
+                _ = tokio::time::sleep(idle) => {}
+                _ = self.wake.notified() => {}
+                change = changes.recv() => {
+                    if matches!(change, Err(broadcast::error::RecvError::Closed)) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Refresh the secondary zones that are due; returns how long to sleep
+    async fn refresh_due(&self) -> Duration {
+        let zones = match self.db.dns_zones().list().await {
+            Ok(zones) => zones,
+            Err(e) => {
+                warn!("Failed to list zones for refresh: {}", e);
+                return MAX_IDLE;
+            }
+        };
+        let zones: Vec<DnsZone> = zones.into_iter().filter(|z| z.enabled && z.is_secondary()).collect();
+
+        let now = Instant::now();
+        let due: Vec<DnsZone> = {
+            let mut secondaries = self.secondaries.lock().unwrap();
+            secondaries.retain(|id, _| zones.iter().any(|z| z.id == *id));
+            for zone in &zones {
+                let secondary = secondaries.entry(zone.id).or_insert_with(|| Secondary {
+                    status: SecondaryStatus::default(),
+                    primaries: zone.primaries.clone(),
+                    due: now,
+                    // Data kept across a restart counts from now
+                    confirmed: zone.content.is_some().then_some(now),
+                    timers: [zone.refresh, zone.retry, zone.expire]
+                        .map(|s| Duration::from_secs(s.max(1) as u64)),
+                });
+                if secondary.primaries != zone.primaries {
+                    secondary.primaries = zone.primaries.clone();
+                    secondary.due = now;
+                }
+            }
+            zones.into_iter().filter(|z| secondaries[&z.id].due <= now).collect()
+        };
+
+        for zone in &due {
+            self.refresh(zone).await;
+        }
+
+        let secondaries = self.secondaries.lock().unwrap();
+        let next = secondaries.values().map(|s| s.due).min();
+        next.map_or(MAX_IDLE, |due| due.saturating_duration_since(Instant::now()).min(MAX_IDLE))
+    }
+
+    /// Check one secondary zone and pull it when its primary moved ahead
+    async fn refresh(&self, zone: &DnsZone) {
+        let result = self.pull(zone).await;
+        let now = Instant::now();
+        let mut expired = false;
+        {
+            let mut secondaries = self.secondaries.lock().unwrap();
+            let Some(secondary) = secondaries.get_mut(&zone.id) else {
+                return;
+            };
+            secondary.status.last_check = Some(Utc::now());
+            let wait = match result {
+                Ok((primary, soa, transferred)) => {
+                    secondary.confirmed = Some(now);
+                    secondary.timers = soa_timers(&soa).unwrap_or(secondary.timers);
+                    secondary.status.primary = Some(primary.to_string());
+                    secondary.status.error = None;
+                    secondary.status.expired = false;
+                    if transferred {
+                        secondary.status.last_transfer = Some(Utc::now());
+                    }
+                    secondary.timers[0]
+                }
+                Err(e) => {
+                    warn!("Refresh of secondary zone {} failed: {}", zone.origin, e);
+                    secondary.status.error = Some(e.to_string());
+                    if secondary.confirmed.is_some_and(|c| now.duration_since(c) > secondary.timers[2]) {
+                        secondary.confirmed = None;
+                        secondary.status.expired = true;
+                        expired = true;
+                    }
+                    secondary.timers[1]
+                }
+            };
+            secondary.due = now + wait;
+            secondary.status.next_refresh = Some(Utc::now() + TimeDelta::from_std(wait).unwrap_or_default());
+        }
+
+        if expired {
+            warn!("Secondary zone {} expired, no longer answering for it", zone.origin);
+            match self.db.dns_zones().store_transfer(zone.id, zone.serial, None).await {
+                Ok(()) => self.db.notify_changed(DataChange::DnsZones),
+                Err(e) => warn!("Failed to drop expired zone {}: {}", zone.origin, e),
+            }
+        }
+    }
+
+    /// Try each primary in turn; returns the one that answered, its SOA and
+    /// whether the zone was transferred
+    async fn pull(&self, zone: &DnsZone) -> Result<(SocketAddr, DnsRecordData, bool)> {
+        let primaries: Vec<SocketAddr> = zone.primary_list().into_iter().filter_map(parse_endpoint).collect();
+        if primaries.is_empty() {
+            bail!("No valid primary configured");
+        }
+
+        let mut last_error = None;
+        for primary in primaries {
+            match self.pull_from(zone, primary).await {
+                Ok((soa, transferred)) => return Ok((primary, soa, transferred)),
+                Err(e) => {
+                    debug!("Refresh of {} from {} failed: {}", zone.origin, primary, e);
+                    last_error = Some(anyhow!("{}: {}", primary, e));
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No primary answered")))
+    }
+
+    /// Check the serial on one primary and transfer the zone if it is newer
+    async fn pull_from(&self, zone: &DnsZone, primary: SocketAddr) -> Result<(DnsRecordData, bool)> {
+        let soa = query_soa(primary, &zone.origin).await?;
+        let serial = soa_serial(&soa).ok_or_else(|| anyhow!("Invalid SOA: {}", soa.value))?;
+        if zone.content.is_some() && !serial_newer(serial, zone.serial as u32) {
+            return Ok((soa, false));
+        }
+
+        let records = axfr(primary, &zone.origin).await?;
+        let soa = records[0].clone();
+        let serial = soa_serial(&soa).unwrap_or(serial);
+        let content = serde_json::to_string(&records)?;
+        self.db.dns_zones().store_transfer(zone.id, serial as i64, Some(&content)).await?;
+        self.db.notify_changed(DataChange::DnsZones);
+        info!("Transferred zone {} serial {} from {} ({} records)", zone.origin, serial, primary, records.len());
+
+        spawn_notifies(&zone.origin, &zone.notify_list());
+        Ok((soa, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{CreateDnsRecord, CreateDnsZone};
+    use crate::dns::cache::{CacheConfig, CacheManager};
+    use crate::dns::message::DnsQuery;
+    use crate::dns::proxy::{ProxyManager, UpstreamManager};
+    use crate::dns::resolver::DnsResolver;
+    use crate::dns::rewrite::RewriteEngine;
+    use crate::dns::server::TcpDnsServer;
+
+    async fn test_db(dir: &tempfile::TempDir, name: &str) -> Arc<Database> {
+        let path = dir.path().join(format!("{}.db", name));
+        Arc::new(Database::new(&format!("sqlite:{}?mode=rwc", path.display())).await.unwrap())
+    }
+
+    fn record(name: &str, value: &str) -> CreateDnsRecord {
+        CreateDnsRecord {
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            value: value.to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled: true,
+        }
+    }
+
+    /// Primary instance with `example.com` open to transfers from localhost
+    async fn start_primary(dir: &tempfile::TempDir) -> (Arc<Database>, Arc<DnsResolver>, DnsZone, SocketAddr) {
+        let db = test_db(dir, "primary").await;
+        let zone = db.dns_zones().create(CreateDnsZone {
+            transfer_acl: "127.0.0.1".to_string(),
+            ..CreateDnsZone::new(
+                "example.com".to_string(),
+                "ns1.example.com".to_string(),
+                "hostmaster.example.com".to_string(),
+            )
+        }).await.unwrap();
+        db.dns_records().batch_create(vec![record("www.example.com", "10.0.0.1")]).await.unwrap();
+
+        let cache = Arc::new(CacheManager::with_config(CacheConfig { default_ttl: 60, max_entries: 100 }));
+        let proxy = Arc::new(ProxyManager::new(Arc::new(UpstreamManager::new())));
+        let resolver = Arc::new(
+            DnsResolver::with_db(Arc::new(RewriteEngine::new()), cache, proxy, db.clone())
+                .with_zone_transfers(Arc::new(ZoneTransfers::new(db.clone()))),
+        );
+        resolver.reload_local_data().await.unwrap();
+
+        let server = TcpDnsServer::new("127.0.0.1:0".parse().unwrap(), resolver.clone()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.run().await });
+        (db, resolver, zone, addr)
+    }
+
+    fn soa_serial_of(snapshot: &LocalSnapshot) -> u32 {
+        let answer = snapshot.answer(&DnsQuery::new("example.com", RecordType::SOA)).unwrap();
+        soa_serial(&answer.response.answers[0]).unwrap()
+    }
+
+    #[test]
+    fn test_endpoints_and_serials() {
+        assert_eq!(parse_endpoint("192.0.2.1"), Some("192.0.2.1:53".parse().unwrap()));
+        assert_eq!(parse_endpoint(" 192.0.2.1:5353 "), Some("192.0.2.1:5353".parse().unwrap()));
+        assert_eq!(parse_endpoint("2001:db8::1"), Some("[2001:db8::1]:53".parse().unwrap()));
+        assert_eq!(parse_endpoint("[2001:db8::1]:54"), Some("[2001:db8::1]:54".parse().unwrap()));
+        assert_eq!(parse_endpoint("ns1.example.com"), None);
+
+        assert!(serial_newer(2026101802, 2026101801));
+        assert!(!serial_newer(2026101801, 2026101802));
+        assert!(serial_newer(1, u32::MAX));
+        assert!(!serial_newer(7, 7));
+
+        assert!(!is_zone_message(&DnsQuery::new("example.com", RecordType::A).to_bytes().unwrap()));
+        assert!(is_zone_message(&request_message("example.com", WireRecordType::AXFR).unwrap().to_vec().unwrap()));
+        assert!(is_zone_message(&request_message("example.com", WireRecordType::IXFR).unwrap().to_vec().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_secondary_follows_primary() {
+        let dir = tempfile::tempdir().unwrap();
+        let (primary_db, primary, zone, addr) = start_primary(&dir).await;
+
+        let db = test_db(&dir, "secondary").await;
+        let mut create = CreateDnsZone::new("example.com".to_string(), String::new(), String::new());
+        create.kind = "secondary".to_string();
+        create.primaries = addr.to_string();
+        let secondary = db.dns_zones().create(create).await.unwrap();
+        let transfers = ZoneTransfers::new(db.clone());
+
+        // Nothing is served before the first transfer
+        let snapshot = LocalSnapshot::load(&db).await.unwrap();
+        assert!(snapshot.answer(&DnsQuery::new("www.example.com", RecordType::A)).is_none());
+
+        transfers.refresh_due().await;
+        let status = transfers.status(secondary.id).unwrap();
+        assert_eq!(status.error, None);
+        assert!(status.last_transfer.is_some());
+
+        let snapshot = LocalSnapshot::load(&db).await.unwrap();
+        let answer = snapshot.answer(&DnsQuery::new("www.example.com", RecordType::A)).unwrap();
+        assert!(answer.response.authoritative);
+        assert_eq!(answer.response.answers[0].value, "10.0.0.1");
+        assert_eq!(soa_serial_of(&snapshot), zone.serial as u32);
+        let answer = snapshot.answer(&DnsQuery::new("missing.example.com", RecordType::A)).unwrap();
+        assert_eq!(answer.response.response_code, crate::dns::DnsResponseCode::NxDomain);
+
+        // A change on the primary reaches the secondary on its next refresh
+        primary_db.dns_records().batch_create(vec![record("mail.example.com", "10.0.0.2")]).await.unwrap();
+        primary.reload_local_data().await.unwrap();
+        transfers.refresh_now(secondary.id);
+        transfers.refresh_due().await;
+        let snapshot = LocalSnapshot::load(&db).await.unwrap();
+        assert_eq!(soa_serial_of(&snapshot), zone.serial as u32 + 1);
+        assert!(snapshot.answer(&DnsQuery::new("mail.example.com", RecordType::A)).unwrap().record_id.is_none());
+
+        // NOTIFY is accepted from the primary only
+        let mut notify = request_message("example.com", WireRecordType::SOA).unwrap();
+        notify.set_op_code(OpCode::Notify);
+        let notify = notify.to_vec().unwrap();
+        let answer = |peer: &str| {
+            let transfers = &transfers;
+            let notify = &notify;
+            let peer: IpAddr = peer.parse().unwrap();
+            async move {
+                let out = transfers.handle(notify, peer, &LocalSnapshot::default(), false).await.unwrap();
+                Message::from_vec(&out[0]).unwrap().response_code()
+            }
+        };
+        assert_eq!(answer("127.0.0.1").await, ResponseCode::NoError);
+        assert_eq!(answer("192.0.2.7").await, ResponseCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn test_ixfr_and_transfer_acl() {
+        let dir = tempfile::tempdir().unwrap();
+        let (primary_db, primary, zone, addr) = start_primary(&dir).await;
+        primary_db.dns_records().batch_create(vec![record("mail.example.com", "10.0.0.2")]).await.unwrap();
+        primary.reload_local_data().await.unwrap();
+
+        // IXFR from the first serial carries only the step
+        let old_soa = DnsRecordData {
+            name: "example.com".to_string(),
+            record_type: RecordType::SOA,
+            value: format!("ns1.example.com hostmaster.example.com {} 3600 600 604800 300", zone.serial),
+            ttl: 3600,
+            priority: None,
+        };
+        let mut request = request_message("example.com", WireRecordType::IXFR).unwrap();
+        request.add_name_server(old_soa.to_record().unwrap());
+        let mut answers = Vec::new();
+        tcp_exchange(addr, &request, |response| {
+            answers.extend(response.answers().iter().filter_map(DnsRecordData::from_record));
+            Ok(true)
+        }).await.unwrap();
+        let summary: Vec<String> = answers.iter().map(|r| format!("{} {}", r.record_type, r.name)).collect();
+        assert_eq!(summary, vec![
+            "SOA example.com", "SOA example.com", "SOA example.com", "A mail.example.com", "SOA example.com",
+        ]);
+        assert_eq!(soa_serial(&answers[1]), Some(zone.serial as u32));
+        assert_eq!(soa_serial(&answers[2]), Some(zone.serial as u32 + 1));
+
+        // AXFR sends the whole zone between two SOAs
+        let records = axfr(addr, "example.com").await.unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].record_type, RecordType::SOA);
+
+        // Clients off the ACL are refused, unknown zones are not ours, UDP AXFR is truncated
+        let axfr_request = |origin: &str| request_message(origin, WireRecordType::AXFR).unwrap().to_vec().unwrap();
+        let ask = |data: Vec<u8>, peer: &str, tcp: bool| {
+            let primary = primary.clone();
+            let peer: IpAddr = peer.parse().unwrap();
+            async move {
+                let out = primary.handle_zone_message(&data, peer, tcp).await.unwrap();
+                Message::from_vec(&out[0]).unwrap()
+            }
+        };
+        assert_eq!(ask(axfr_request("example.com"), "192.0.2.1", true).await.response_code(), ResponseCode::Refused);
+        assert_eq!(ask(axfr_request("example.org"), "127.0.0.1", true).await.response_code(), ResponseCode::NotAuth);
+        assert!(ask(axfr_request("example.com"), "127.0.0.1", false).await.truncated());
+        assert!(primary.handle_zone_message(&DnsQuery::new("example.com", RecordType::A).to_bytes().unwrap(), "127.0.0.1".parse().unwrap(), true).await.is_none());
+    }
+}
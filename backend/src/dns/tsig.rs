@@ -0,0 +1,494 @@
+//! TSIG Message Authentication
+//!
+//! Verifies and produces the TSIG record (RFC 8945) that authenticates
+//! dynamic updates with a shared secret. Only the HMAC-SHA2 family is
+//! supported; HMAC-MD5 and HMAC-SHA1 are deprecated and rejected as
+//! unknown algorithms.
+//!
+//! The record is handled on the raw message bytes: it has to be the last
+//! record of the additional section, and the MAC covers the message as
+//! it was before the record was appended.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use hickory_proto::rr::Name;
+use hickory_proto::serialize::binary::{BinDecodable, BinDecoder};
+use hmac::{Hmac, Mac};
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+
+/// TSIG record type
+const TSIG_TYPE: u16 = 250;
+
+/// Class of TSIG records (ANY)
+const TSIG_CLASS: u16 = 255;
+
+/// Allowed clock skew when signing responses
+pub const TSIG_FUDGE: u16 = 300;
+
+/// TSIG error: the MAC does not verify
+pub const TSIG_BADSIG: u16 = 16;
+/// TSIG error: the key is unknown or its algorithm does not match
+pub const TSIG_BADKEY: u16 = 17;
+/// TSIG error: the signing time is outside the fudge
+pub const TSIG_BADTIME: u16 = 18;
+/// TSIG error: the MAC is truncated below what is accepted
+pub const TSIG_BADTRUNC: u16 = 22;
+
+/// HMAC algorithm of a TSIG key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha224,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    /// Names accepted for keys, as used in `nsupdate` key files
+    pub const NAMES: [&'static str; 4] = ["hmac-sha256", "hmac-sha224", "hmac-sha384", "hmac-sha512"];
+
+    /// Name as it appears in the TSIG record, without the trailing dot
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HmacSha224 => "hmac-sha224",
+            Self::HmacSha256 => "hmac-sha256",
+            Self::HmacSha384 => "hmac-sha384",
+            Self::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    /// Full length of the MAC in bytes
+    fn mac_len(&self) -> usize {
+        match self {
+            Self::HmacSha224 => 28,
+            Self::HmacSha256 => 32,
+            Self::HmacSha384 => 48,
+            Self::HmacSha512 => 64,
+        }
+    }
+
+    fn mac(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        macro_rules! hmac {
+            ($digest:ty) => {{
+                let mut mac = Hmac::<$digest>::new_from_slice(secret).expect("HMAC accepts any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }};
+        }
+        match self {
+            Self::HmacSha224 => hmac!(Sha224),
+            Self::HmacSha256 => hmac!(Sha256),
+            Self::HmacSha384 => hmac!(Sha384),
+            Self::HmacSha512 => hmac!(Sha512),
+        }
+    }
+}
+
+impl FromStr for TsigAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.trim().trim_end_matches('.').to_lowercase().as_str() {
+            "hmac-sha224" => Ok(Self::HmacSha224),
+            "hmac-sha256" => Ok(Self::HmacSha256),
+            "hmac-sha384" => Ok(Self::HmacSha384),
+            "hmac-sha512" => Ok(Self::HmacSha512),
+            other => Err(anyhow!("Unsupported TSIG algorithm: {}", other)),
+        }
+    }
+}
+
+/// TSIG record taken off a message
+#[derive(Debug, Clone)]
+pub struct TsigRecord {
+    /// Key name, lowercase without the trailing dot
+    pub key_name: String,
+    /// Algorithm name, lowercase without the trailing dot
+    pub algorithm: String,
+    /// Seconds since the epoch (48 bits on the wire)
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    pub original_id: u16,
+    pub error: u16,
+    pub other: Vec<u8>,
+}
+
+/// Message split into the bytes the MAC covers and its TSIG record
+#[derive(Debug, Clone)]
+pub struct SignedMessage {
+    /// Message without the TSIG record, with the original ID restored
+    pub unsigned: Vec<u8>,
+    pub tsig: TsigRecord,
+}
+
+/// Why a TSIG record was rejected; maps onto the TSIG error field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum TsigError {
+    BadKey,
+    BadSig,
+    BadTime,
+    BadTrunc,
+}
+
+impl TsigError {
+    /// Value of the TSIG error field
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::BadKey => TSIG_BADKEY,
+            Self::BadSig => TSIG_BADSIG,
+            Self::BadTime => TSIG_BADTIME,
+            Self::BadTrunc => TSIG_BADTRUNC,
+        }
+    }
+
+    /// Mnemonic for logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BadKey => "BADKEY",
+            Self::BadSig => "BADSIG",
+            Self::BadTime => "BADTIME",
+            Self::BadTrunc => "BADTRUNC",
+        }
+    }
+}
+
+/// Canonical wire form of a name: lowercase and uncompressed
+fn name_wire(name: &str) -> Result<Vec<u8>> {
+    let name = Name::from_str(name).map_err(|e| anyhow!("Invalid name {}: {}", name, e))?;
+    let mut wire = Vec::new();
+    for label in name.iter() {
+        wire.push(label.len() as u8);
+        wire.extend(label.iter().map(u8::to_ascii_lowercase));
+    }
+    wire.push(0);
+    Ok(wire)
+}
+
+/// Name as text, lowercase without the trailing dot
+fn name_text(name: &Name) -> String {
+    name.to_string().trim_end_matches('.').to_lowercase()
+}
+
+fn read_u16(decoder: &mut BinDecoder<'_>) -> Result<u16> {
+    Ok(decoder.read_u16()?.unverified())
+}
+
+/// Skip one resource record, returning its type
+fn skip_record(decoder: &mut BinDecoder<'_>) -> Result<u16> {
+    Name::read(decoder)?;
+    let record_type = read_u16(decoder)?;
+    decoder.read_slice(6)?;
+    let length = read_u16(decoder)? as usize;
+    decoder.read_slice(length)?;
+    Ok(record_type)
+}
+
+/// Take the TSIG record off the end of a message
+///
+/// Returns `None` for unsigned messages. A TSIG record anywhere but last
+/// is an error.
+pub fn split_tsig(data: &[u8]) -> Result<Option<SignedMessage>> {
+    if data.len() < 12 {
+        bail!("Message too short");
+    }
+    let count = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]) as usize;
+    let (questions, records) = (count(4), count(6) + count(8) + count(10));
+    if records == 0 || count(10) == 0 {
+        return Ok(None);
+    }
+
+    let mut decoder = BinDecoder::new(data);
+    decoder.read_slice(12)?;
+    for _ in 0..questions {
+        Name::read(&mut decoder)?;
+        decoder.read_slice(4)?;
+    }
+    for _ in 0..records - 1 {
+        if skip_record(&mut decoder)? == TSIG_TYPE {
+            bail!("TSIG record is not the last record");
+        }
+    }
+
+    let start = decoder.index();
+    let key_name = Name::read(&mut decoder)?;
+    if read_u16(&mut decoder)? != TSIG_TYPE {
+        return Ok(None);
+    }
+    if read_u16(&mut decoder)? != TSIG_CLASS {
+        bail!("TSIG record is not of class ANY");
+    }
+    decoder.read_slice(4)?;
+    let length = read_u16(&mut decoder)? as usize;
+    let rdata_end = decoder.index() + length;
+
+    let algorithm = Name::read(&mut decoder)?;
+    let time_high = read_u16(&mut decoder)? as u64;
+    let time_low = decoder.read_u32()?.unverified() as u64;
+    let fudge = read_u16(&mut decoder)?;
+    let mac_len = read_u16(&mut decoder)? as usize;
+    let mac = decoder.read_slice(mac_len)?.unverified().to_vec();
+    let original_id = read_u16(&mut decoder)?;
+    let error = read_u16(&mut decoder)?;
+    let other_len = read_u16(&mut decoder)? as usize;
+    let other = decoder.read_slice(other_len)?.unverified().to_vec();
+    if decoder.index() != rdata_end || !decoder.is_empty() {
+        bail!("Malformed TSIG record");
+    }
+
+    let mut unsigned = data[..start].to_vec();
+    unsigned[..2].copy_from_slice(&original_id.to_be_bytes());
+    unsigned[10..12].copy_from_slice(&((count(10) - 1) as u16).to_be_bytes());
+    Ok(Some(SignedMessage {
+        unsigned,
+        tsig: TsigRecord {
+            key_name: name_text(&key_name),
+            algorithm: name_text(&algorithm),
+            time_signed: (time_high << 32) | time_low,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        },
+    }))
+}
+
+/// TSIG variables covered by the MAC after the message itself
+fn variables(tsig: &TsigRecord) -> Result<Vec<u8>> {
+    let mut data = name_wire(&tsig.key_name)?;
+    data.extend(TSIG_CLASS.to_be_bytes());
+    data.extend(0u32.to_be_bytes());
+    data.extend(name_wire(&tsig.algorithm)?);
+    data.extend(&tsig.time_signed.to_be_bytes()[2..]);
+    data.extend(tsig.fudge.to_be_bytes());
+    data.extend(tsig.error.to_be_bytes());
+    data.extend((tsig.other.len() as u16).to_be_bytes());
+    data.extend(&tsig.other);
+    Ok(data)
+}
+
+/// Compare two byte strings without an early exit
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl SignedMessage {
+    /// Check the MAC of a request against a key and the signing time against `now`
+    pub fn verify(&self, algorithm: TsigAlgorithm, secret: &[u8], now: u64) -> Result<(), TsigError> {
+        if TsigAlgorithm::from_str(&self.tsig.algorithm).ok() != Some(algorithm) {
+            return Err(TsigError::BadKey);
+        }
+        let full = algorithm.mac_len();
+        let received = self.tsig.mac.len();
+        if received > full {
+            return Err(TsigError::BadSig);
+        }
+        // Truncation is allowed down to half the MAC and at least 10 bytes
+        if received < (full / 2).max(10) {
+            return Err(TsigError::BadTrunc);
+        }
+
+        let mut data = self.unsigned.clone();
+        data.extend(variables(&self.tsig).map_err(|_| TsigError::BadSig)?);
+        let expected = algorithm.mac(secret, &data);
+        if !constant_time_eq(&expected[..received], &self.tsig.mac) {
+            return Err(TsigError::BadSig);
+        }
+        if now.abs_diff(self.tsig.time_signed) > self.tsig.fudge as u64 {
+            return Err(TsigError::BadTime);
+        }
+        Ok(())
+    }
+}
+
+/// Append a TSIG record to a message and count it in the header
+fn append_tsig(mut message: Vec<u8>, tsig: &TsigRecord) -> Result<Vec<u8>> {
+    let mut rdata = name_wire(&tsig.algorithm)?;
+    rdata.extend(&tsig.time_signed.to_be_bytes()[2..]);
+    rdata.extend(tsig.fudge.to_be_bytes());
+    rdata.extend((tsig.mac.len() as u16).to_be_bytes());
+    rdata.extend(&tsig.mac);
+    rdata.extend(tsig.original_id.to_be_bytes());
+    rdata.extend(tsig.error.to_be_bytes());
+    rdata.extend((tsig.other.len() as u16).to_be_bytes());
+    rdata.extend(&tsig.other);
+
+    message.extend(name_wire(&tsig.key_name)?);
+    message.extend(TSIG_TYPE.to_be_bytes());
+    message.extend(TSIG_CLASS.to_be_bytes());
+    message.extend(0u32.to_be_bytes());
+    message.extend((rdata.len() as u16).to_be_bytes());
+    message.extend(rdata);
+
+    if message.len() < 12 {
+        bail!("Message too short");
+    }
+    let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+    Ok(message)
+}
+
+/// Sign a request, as a client would
+#[cfg(test)]
+pub fn sign_request(
+    message: Vec<u8>,
+    key_name: &str,
+    algorithm: TsigAlgorithm,
+    secret: &[u8],
+    now: u64,
+) -> Result<Vec<u8>> {
+    if message.len() < 12 {
+        bail!("Message too short");
+    }
+    let mut tsig = TsigRecord {
+        key_name: key_name.trim_end_matches('.').to_lowercase(),
+        algorithm: algorithm.name().to_string(),
+        time_signed: now,
+        fudge: TSIG_FUDGE,
+        mac: Vec::new(),
+        original_id: u16::from_be_bytes([message[0], message[1]]),
+        error: 0,
+        other: Vec::new(),
+    };
+    let mut data = message.clone();
+    data.extend(variables(&tsig)?);
+    tsig.mac = algorithm.mac(secret, &data);
+    append_tsig(message, &tsig)
+}
+
+/// Sign the response to a TSIG request
+///
+/// With `key` the response carries a MAC over the request MAC, the
+/// response and the TSIG variables. Without it (BADKEY, BADSIG) the TSIG
+/// record only reports `error`. BADTIME responses carry the server time
+/// in the other data field.
+pub fn sign_response(
+    response: Vec<u8>,
+    request: &TsigRecord,
+    key: Option<(TsigAlgorithm, &[u8])>,
+    error: u16,
+    now: u64,
+) -> Result<Vec<u8>> {
+    let mut tsig = TsigRecord {
+        key_name: request.key_name.clone(),
+        algorithm: request.algorithm.clone(),
+        time_signed: now,
+        fudge: TSIG_FUDGE,
+        mac: Vec::new(),
+        original_id: request.original_id,
+        error,
+        other: if error == TSIG_BADTIME { now.to_be_bytes()[2..].to_vec() } else { Vec::new() },
+    };
+    if error == TSIG_BADTIME {
+        // The client checks the MAC with the time it signed at
+        tsig.time_signed = request.time_signed;
+    }
+    if let Some((algorithm, secret)) = key {
+        let mut data = (request.mac.len() as u16).to_be_bytes().to_vec();
+        data.extend(&request.mac);
+        data.extend(&response);
+        data.extend(variables(&tsig)?);
+        tsig.mac = algorithm.mac(secret, &data);
+    }
+    append_tsig(response, &tsig)
+}
+
+/// Check a signed response to a request signed with `request_mac`, as a client would
+#[cfg(test)]
+pub fn verify_response(
+    signed: &SignedMessage,
+    request_mac: &[u8],
+    algorithm: TsigAlgorithm,
+    secret: &[u8],
+) -> Result<(), TsigError> {
+    if signed.tsig.mac.is_empty() {
+        return Err(TsigError::BadSig);
+    }
+    let mut data = (request_mac.len() as u16).to_be_bytes().to_vec();
+    data.extend(request_mac);
+    data.extend(&signed.unsigned);
+    data.extend(variables(&signed.tsig).map_err(|_| TsigError::BadSig)?);
+    let expected = algorithm.mac(secret, &data);
+    if constant_time_eq(&expected[..signed.tsig.mac.len().min(expected.len())], &signed.tsig.mac) {
+        Ok(())
+    } else {
+        Err(TsigError::BadSig)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, MessageType, OpCode, Query};
+    use hickory_proto::rr::RecordType;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn update() -> Vec<u8> {
+        let mut message = Message::new();
+        message
+            .set_id(4321)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Update);
+        message.add_query(Query::query(Name::from_str("example.com.").unwrap(), RecordType::SOA));
+        message.to_vec().unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let message = update();
+        assert!(split_tsig(&message).unwrap().is_none());
+
+        let signed = sign_request(message.clone(), "Update-Key.", TsigAlgorithm::HmacSha256, SECRET, 1_000_000).unwrap();
+        let split = split_tsig(&signed).unwrap().unwrap();
+        assert_eq!(split.unsigned, message);
+        assert_eq!(split.tsig.key_name, "update-key");
+        assert_eq!(split.tsig.algorithm, "hmac-sha256");
+        assert_eq!(split.tsig.original_id, 4321);
+        assert_eq!(split.tsig.mac.len(), 32);
+        assert_eq!(split.verify(TsigAlgorithm::HmacSha256, SECRET, 1_000_100), Ok(()));
+
+        // Wrong secret, wrong algorithm, stale time
+        assert_eq!(split.verify(TsigAlgorithm::HmacSha256, b"other", 1_000_000), Err(TsigError::BadSig));
+        assert_eq!(split.verify(TsigAlgorithm::HmacSha512, SECRET, 1_000_000), Err(TsigError::BadKey));
+        assert_eq!(split.verify(TsigAlgorithm::HmacSha256, SECRET, 1_000_301), Err(TsigError::BadTime));
+
+        // A changed ID is fine (original ID is signed), a changed body is not
+        let mut forwarded = signed.clone();
+        forwarded[0] = 0;
+        assert_eq!(split_tsig(&forwarded).unwrap().unwrap().verify(TsigAlgorithm::HmacSha256, SECRET, 1_000_000), Ok(()));
+        let mut tampered = signed.clone();
+        tampered[2] ^= 0x01;
+        assert_eq!(split_tsig(&tampered).unwrap().unwrap().verify(TsigAlgorithm::HmacSha256, SECRET, 1_000_000), Err(TsigError::BadSig));
+
+        // Responses chain on the request MAC
+        let response = sign_response(message.clone(), &split.tsig, Some((TsigAlgorithm::HmacSha256, SECRET)), 0, 1_000_001).unwrap();
+        let response = split_tsig(&response).unwrap().unwrap();
+        assert_eq!(verify_response(&response, &split.tsig.mac, TsigAlgorithm::HmacSha256, SECRET), Ok(()));
+        assert_eq!(verify_response(&response, &[0; 32], TsigAlgorithm::HmacSha256, SECRET), Err(TsigError::BadSig));
+
+        let rejected = sign_response(message, &split.tsig, None, TSIG_BADKEY, 1_000_001).unwrap();
+        let rejected = split_tsig(&rejected).unwrap().unwrap();
+        assert_eq!(rejected.tsig.error, TSIG_BADKEY);
+        assert!(rejected.tsig.mac.is_empty());
+    }
+
+    #[test]
+    fn test_algorithms_and_truncation() {
+        assert_eq!("HMAC-SHA512.".parse::<TsigAlgorithm>().unwrap(), TsigAlgorithm::HmacSha512);
+        assert!("hmac-md5.sig-alg.reg.int".parse::<TsigAlgorithm>().is_err());
+        for name in TsigAlgorithm::NAMES {
+            assert_eq!(name.parse::<TsigAlgorithm>().unwrap().name(), name);
+        }
+
+        let signed = sign_request(update(), "k", TsigAlgorithm::HmacSha384, SECRET, 50).unwrap();
+        let mut split = split_tsig(&signed).unwrap().unwrap();
+        split.tsig.mac.truncate(24);
+        assert_eq!(split.verify(TsigAlgorithm::HmacSha384, SECRET, 50), Ok(()));
+        split.tsig.mac.truncate(16);
+        assert_eq!(split.verify(TsigAlgorithm::HmacSha384, SECRET, 50), Err(TsigError::BadTrunc));
+    }
+}
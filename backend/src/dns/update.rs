@@ -0,0 +1,823 @@
+//! Dynamic Updates
+//!
+//! Applies DNS UPDATE messages (RFC 2136) to the local records of primary
+//! zones, e.g. from DHCP servers or ACME clients. Every update has to be
+//! signed with an enabled TSIG key that lists the zone. Prerequisites are
+//! checked against the records in the database and the update section is
+//! written in one transaction, so an update applies completely or not at
+//! all. The zone serial then moves on the next snapshot reload, which also
+//! notifies secondaries. Each signed update is written to the audit log
+//! with its outcome; unsigned and badly signed requests only get a
+//! rate-limited debug line, so spoofed packets cannot flood the database.
+//!
+//! The SOA and the apex NS set come from the zone settings, so updates to
+//! them are ignored, as are changes to records synced from a hosts file or
+//! declared in the configuration file.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use base64::Engine;
+use chrono::Utc;
+use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use hickory_proto::rr::{DNSClass, Record, RecordData, RecordType as WireRecordType};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::db::{CreateDnsRecord, CreateDnsUpdateLog, Database, DnsRecord, DnsZone, TsigKey};
+use super::local::{apex_records, in_zone, record_data};
+use super::message::{DnsRecordData, RecordType};
+use super::tsig::{sign_response, split_tsig, SignedMessage, TsigAlgorithm, TsigError};
+
+/// Shortest time between two log lines about rejected requests
+const REJECTED_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether raw bytes hold an UPDATE request
+pub fn is_update_message(data: &[u8]) -> bool {
+    data.len() >= 12 && data[2] & 0x80 == 0 && (data[2] >> 3) & 0x0f == 5
+}
+
+/// Mnemonic of a response code for the audit log
+fn rcode_name(code: ResponseCode) -> &'static str {
+    match code {
+        ResponseCode::NoError => "NOERROR",
+        ResponseCode::FormErr => "FORMERR",
+        ResponseCode::ServFail => "SERVFAIL",
+        ResponseCode::NXDomain => "NXDOMAIN",
+        ResponseCode::NotImp => "NOTIMP",
+        ResponseCode::Refused => "REFUSED",
+        ResponseCode::YXDomain => "YXDOMAIN",
+        ResponseCode::YXRRSet => "YXRRSET",
+        ResponseCode::NXRRSet => "NXRRSET",
+        ResponseCode::NotAuth => "NOTAUTH",
+        ResponseCode::NotZone => "NOTZONE",
+        _ => "OTHER",
+    }
+}
+
+/// Name as text, lowercase without the trailing dot
+fn owner(record: &Record) -> String {
+    record.name().to_string().trim_end_matches('.').to_lowercase()
+}
+
+/// Identity of a record within its RRset; TTLs do not count (RFC 2136 1.1.1)
+fn rr_key(record: &DnsRecordData) -> String {
+    let value = match record.record_type {
+        RecordType::CNAME | RecordType::MX | RecordType::NS | RecordType::PTR | RecordType::SRV => {
+            record.value.trim_end_matches('.').to_lowercase()
+        }
+        _ => record.value.clone(),
+    };
+    format!(
+        "{} {} {} {}",
+        record.name.trim_end_matches('.').to_lowercase(),
+        record.record_type,
+        record.priority.map(|p| p.to_string()).unwrap_or_default(),
+        value
+    )
+}
+
+/// Audit line for a record
+fn describe(action: &str, record: &DnsRecordData) -> String {
+    let priority = record.priority.map(|p| format!("{} ", p)).unwrap_or_default();
+    format!("{} {} {} {} {}{}", action, record.name, record.ttl, record.record_type, priority, record.value)
+}
+
+/// Query and meta types (RFC 6895), which may not appear as data in an update
+fn is_meta_type(record_type: WireRecordType) -> bool {
+    record_type == WireRecordType::OPT || (128..=255).contains(&u16::from(record_type))
+}
+
+/// Where a record of the working set comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// Row of `dns_records` with the TTL it had
+    Row { id: i64, ttl: u32, read_only: bool },
+    /// SOA or apex NS from the zone settings
+    Zone,
+    /// Added by this update
+    New,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    data: DnsRecordData,
+    key: String,
+    source: Source,
+}
+
+impl Entry {
+    fn new(data: DnsRecordData, source: Source) -> Self {
+        Self { key: rr_key(&data), data, source }
+    }
+
+    /// Whether the update section may remove or retime it
+    fn is_writable(&self) -> bool {
+        matches!(self.source, Source::Row { read_only: false, .. } | Source::New)
+    }
+}
+
+/// Records of one zone while an update is checked and applied
+struct ZoneData {
+    origin: String,
+    /// Origins of enabled zones below this one, whose names are not ours
+    children: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+impl ZoneData {
+    fn new(zone: &DnsZone, zones: &[DnsZone], rows: &[DnsRecord]) -> Self {
+        let origin = zone.origin.clone();
+        let children: Vec<String> = zones
+            .iter()
+            .filter(|z| z.enabled && z.origin != origin && in_zone(&z.origin, &origin))
+            .map(|z| z.origin.clone())
+            .collect();
+
+        let (soa, nameservers) = apex_records(zone);
+        let mut entries: Vec<Entry> = std::iter::once(soa)
+            .chain(nameservers)
+            .map(|data| Entry::new(data, Source::Zone))
+            .collect();
+        let mut data = Self { origin, children, entries: Vec::new() };
+        for row in rows.iter().filter(|r| r.enabled) {
+            let name = row.name.trim_end_matches('.').to_lowercase();
+            let Ok(record_type) = RecordType::from_str(&row.record_type) else {
+                continue;
+            };
+            if record_type == RecordType::SOA || !data.contains(&name) {
+                continue;
+            }
+            if let Some(record) = record_data(row, &name, record_type) {
+                let source = Source::Row { id: row.id, ttl: record.ttl, read_only: row.managed || row.source.is_some() };
+                entries.push(Entry::new(record, source));
+            }
+        }
+        data.entries = entries;
+        data
+    }
+
+    /// Whether a name belongs to this zone and not to a zone below it
+    fn contains(&self, name: &str) -> bool {
+        in_zone(name, &self.origin) && !self.children.iter().any(|child| in_zone(name, child))
+    }
+
+    /// IDs of the rows in the working set
+    fn row_ids(&self) -> HashSet<i64> {
+        self.entries
+            .iter()
+            .filter_map(|e| match e.source {
+                Source::Row { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn name_in_use(&self, name: &str) -> bool {
+        self.entries.iter().any(|e| e.data.name == name)
+    }
+
+    fn rrset(&self, name: &str, record_type: RecordType) -> impl Iterator<Item = &Entry> + '_ {
+        let name = name.to_string();
+        self.entries
+            .iter()
+            .filter(move |e| e.data.name == name && e.data.record_type == record_type)
+    }
+
+    /// Remove the writable entries matching `filter`, returning audit lines
+    fn remove(&mut self, filter: impl Fn(&Entry) -> bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.entries.retain(|e| {
+            if e.is_writable() && filter(e) {
+                lines.push(describe("delete", &e.data));
+                false
+            } else {
+                true
+            }
+        });
+        lines
+    }
+}
+
+/// Outcome of an authenticated update
+struct Applied {
+    rcode: ResponseCode,
+    changes: Vec<String>,
+}
+
+impl Applied {
+    fn rejected(rcode: ResponseCode) -> Self {
+        Self { rcode, changes: Vec::new() }
+    }
+}
+
+/// Check the prerequisite section (RFC 2136 3.2)
+fn check_prerequisites(data: &ZoneData, prerequisites: &[Record]) -> Option<ResponseCode> {
+    let mut wanted: HashMap<(String, RecordType), HashSet<String>> = HashMap::new();
+    for record in prerequisites {
+        if record.ttl() != 0 {
+            return Some(ResponseCode::FormErr);
+        }
+        let name = owner(record);
+        if !data.contains(&name) {
+            return Some(ResponseCode::NotZone);
+        }
+        let record_type = record.record_type();
+        match record.dns_class() {
+            DNSClass::ANY | DNSClass::NONE if !record.data().is_update() => return Some(ResponseCode::FormErr),
+            DNSClass::ANY if record_type == WireRecordType::ANY => {
+                if !data.name_in_use(&name) {
+                    return Some(ResponseCode::NXDomain);
+                }
+            }
+            DNSClass::ANY => {
+                let exists = RecordType::from_trust_dns(record_type)
+                    .is_some_and(|t| data.rrset(&name, t).next().is_some());
+                if !exists {
+                    return Some(ResponseCode::NXRRSet);
+                }
+            }
+            DNSClass::NONE if record_type == WireRecordType::ANY => {
+                if data.name_in_use(&name) {
+                    return Some(ResponseCode::YXDomain);
+                }
+            }
+            DNSClass::NONE => {
+                let exists = RecordType::from_trust_dns(record_type)
+                    .is_some_and(|t| data.rrset(&name, t).next().is_some());
+                if exists {
+                    return Some(ResponseCode::YXRRSet);
+                }
+            }
+            DNSClass::IN => {
+                // Value-dependent: the RRset must match exactly
+                let Some(mut rr) = DnsRecordData::from_record(record) else {
+                    return Some(ResponseCode::NXRRSet);
+                };
+                rr.name = name.clone();
+                wanted.entry((name, rr.record_type)).or_default().insert(rr_key(&rr));
+            }
+            _ => return Some(ResponseCode::FormErr),
+        }
+    }
+
+    for ((name, record_type), keys) in wanted {
+        let actual: HashSet<String> = data.rrset(&name, record_type).map(|e| e.key.clone()).collect();
+        if actual != keys {
+            return Some(ResponseCode::NXRRSet);
+        }
+    }
+    None
+}
+
+/// Check the update section before touching anything (RFC 2136 3.4.1)
+fn prescan(data: &ZoneData, updates: &[Record]) -> Option<ResponseCode> {
+    for record in updates {
+        if !data.contains(&owner(record)) {
+            return Some(ResponseCode::NotZone);
+        }
+        let record_type = record.record_type();
+        let valid = match record.dns_class() {
+            DNSClass::IN => !is_meta_type(record_type) && !record.data().is_update(),
+            DNSClass::ANY => {
+                record.ttl() == 0
+                    && record.data().is_update()
+                    && (record_type == WireRecordType::ANY || !is_meta_type(record_type))
+            }
+            DNSClass::NONE => record.ttl() == 0 && !is_meta_type(record_type) && !record.data().is_update(),
+            _ => false,
+        };
+        if !valid {
+            return Some(ResponseCode::FormErr);
+        }
+        // Only types that can be stored as local records
+        if record.dns_class() == DNSClass::IN && DnsRecordData::from_record(record).is_none() {
+            return Some(ResponseCode::Refused);
+        }
+    }
+    None
+}
+
+/// Apply the update section to the working set (RFC 2136 3.4.2)
+fn apply_updates(data: &mut ZoneData, updates: &[Record]) -> Vec<String> {
+    let mut changes = Vec::new();
+    for record in updates {
+        let name = owner(record);
+        let apex = name == data.origin;
+        let record_type = RecordType::from_trust_dns(record.record_type());
+        // The SOA and apex NS set belong to the zone settings
+        if record_type == Some(RecordType::SOA) || (apex && record_type == Some(RecordType::NS)) {
+            continue;
+        }
+
+        match record.dns_class() {
+            DNSClass::IN => {
+                let Some(mut rr) = DnsRecordData::from_record(record) else {
+                    continue;
+                };
+                rr.name = name.clone();
+                let has_cname = data.rrset(&name, RecordType::CNAME).next().is_some();
+                let has_other = data
+                    .entries
+                    .iter()
+                    .any(|e| e.data.name == name && e.data.record_type != RecordType::CNAME);
+                match rr.record_type {
+                    // A CNAME cannot share its name with other data
+                    RecordType::CNAME if has_other => continue,
+                    RecordType::CNAME => {
+                        changes.extend(data.remove(|e| e.data.name == name && e.data.record_type == RecordType::CNAME));
+                    }
+                    _ if has_cname => continue,
+                    _ => {}
+                }
+
+                let key = rr_key(&rr);
+                match data.entries.iter_mut().find(|e| e.key == key) {
+                    Some(entry) if entry.data.ttl != rr.ttl && entry.is_writable() => {
+                        entry.data.ttl = rr.ttl;
+                        changes.push(describe("ttl", &entry.data));
+                    }
+                    Some(_) => {}
+                    None => {
+                        changes.push(describe("add", &rr));
+                        data.entries.push(Entry::new(rr, Source::New));
+                    }
+                }
+            }
+            DNSClass::ANY if record.record_type() == WireRecordType::ANY => {
+                changes.extend(data.remove(|e| e.data.name == name));
+            }
+            DNSClass::ANY => {
+                if let Some(record_type) = record_type {
+                    changes.extend(data.remove(|e| e.data.name == name && e.data.record_type == record_type));
+                }
+            }
+            DNSClass::NONE => {
+                if let Some(mut rr) = DnsRecordData::from_record(record) {
+                    rr.name = name.clone();
+                    let key = rr_key(&rr);
+                    changes.extend(data.remove(|e| e.key == key));
+                }
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// Accepts dynamic updates for local primary zones
+pub struct DynamicUpdates {
+    db: Arc<Database>,
+    /// Updates are checked and applied one at a time
+    lock: Mutex<()>,
+    /// Last log line about a rejected request and the requests rejected since
+    rejected: StdMutex<(Option<Instant>, u64)>,
+}
+
+impl DynamicUpdates {
+    pub fn new(db: Arc<Database>) -> Arc<Self> {
+        Arc::new(Self { db, lock: Mutex::new(()), rejected: StdMutex::new((None, 0)) })
+    }
+
+    /// Log a request that failed before authentication, at most once per interval
+    fn log_rejected(&self, peer: IpAddr, reason: &str) {
+        let mut rejected = self.rejected.lock().unwrap();
+        let (last, suppressed) = &mut *rejected;
+        if last.is_some_and(|at| at.elapsed() < REJECTED_LOG_INTERVAL) {
+            *suppressed += 1;
+            return;
+        }
+        debug!("Rejected dynamic update from {}: {} ({} more since the last report)", peer, reason, suppressed);
+        *last = Some(Instant::now());
+        *suppressed = 0;
+    }
+
+    /// Answer an UPDATE request from `peer`
+    ///
+    /// Returns `None` when the message is too broken to answer.
+    pub async fn handle(&self, data: &[u8], peer: IpAddr) -> Option<Vec<u8>> {
+        let signed = match split_tsig(data) {
+            Ok(signed) => signed,
+            Err(e) => {
+                self.log_rejected(peer, &format!("malformed TSIG: {}", e));
+                return header_reply(data, ResponseCode::FormErr);
+            }
+        };
+        let unsigned = signed.as_ref().map_or(data, |s| s.unsigned.as_slice());
+        let request = match Message::from_vec(unsigned) {
+            Ok(request) => request,
+            Err(e) => {
+                self.log_rejected(peer, &format!("malformed message: {}", e));
+                return header_reply(data, ResponseCode::FormErr);
+            }
+        };
+        let zone = request
+            .queries()
+            .first()
+            .map(|q| q.name().to_string().trim_end_matches('.').to_lowercase())
+            .unwrap_or_default();
+
+        let now = Utc::now().timestamp().max(0) as u64;
+        let (rcode, changes, key, tsig_error) = match &signed {
+            None => (ResponseCode::Refused, Vec::new(), None, None),
+            Some(signed) => match self.authenticate(signed, now).await {
+                Err(error) => (ResponseCode::NotAuth, Vec::new(), None, Some(error)),
+                Ok(key) => {
+                    let applied = self.apply(&request, &key).await.unwrap_or_else(|e| {
+                        warn!("Failed to apply update of {} from {}: {}", zone, peer, e);
+                        Applied::rejected(ResponseCode::ServFail)
+                    });
+                    (applied.rcode, applied.changes, Some(key), None)
+                }
+            },
+        };
+        let outcome = tsig_error.map_or(rcode_name(rcode), |e| e.name());
+        if key.is_none() {
+            self.log_rejected(peer, &format!("{} for {}", outcome, zone));
+        } else if changes.is_empty() {
+            info!("Dynamic update of {} from {}: {}", zone, peer, outcome);
+        } else {
+            info!("Dynamic update of {} from {}: {}, {} change(s)", zone, peer, outcome, changes.len());
+        }
+        // Only requests that passed TSIG reach the audit log
+        if key.is_some() {
+            let entry = CreateDnsUpdateLog {
+                zone: zone.clone(),
+                key_name: signed.as_ref().map(|s| s.tsig.key_name.clone()),
+                client_ip: peer.to_string(),
+                rcode: outcome.to_string(),
+                changes,
+            };
+            if let Err(e) = self.db.dns_update_log().create(entry).await {
+                warn!("Failed to write update audit entry: {}", e);
+            }
+        }
+
+        // A bad time is only reported once the MAC checked out
+        let key = match (&signed, tsig_error) {
+            (Some(signed), Some(TsigError::BadTime)) => self.key(&signed.tsig.key_name).await,
+            _ => key,
+        };
+
+        let response = reply(&request, rcode).to_vec().ok()?;
+        let Some(signed) = signed else {
+            return Some(response);
+        };
+        let secret = key.as_ref().and_then(|k| decode_secret(&k.secret));
+        let signing_key = key
+            .as_ref()
+            .and_then(|k| TsigAlgorithm::from_str(&k.algorithm).ok())
+            .zip(secret.as_deref());
+        let error = tsig_error.map_or(0, |e| e.code());
+        sign_response(response, &signed.tsig, signing_key, error, now)
+            .inspect_err(|e| warn!("Failed to sign update response: {}", e))
+            .ok()
+    }
+
+    async fn key(&self, name: &str) -> Option<TsigKey> {
+        match self.db.tsig_keys().get_by_name(name).await {
+            Ok(key) => key.filter(|k| k.enabled),
+            Err(e) => {
+                warn!("Failed to read TSIG key {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Find the key of a signed request and check its MAC
+    async fn authenticate(&self, signed: &SignedMessage, now: u64) -> Result<TsigKey, TsigError> {
+        let key = self.key(&signed.tsig.key_name).await.ok_or(TsigError::BadKey)?;
+        let algorithm = TsigAlgorithm::from_str(&key.algorithm).map_err(|_| TsigError::BadKey)?;
+        let secret = decode_secret(&key.secret).ok_or(TsigError::BadKey)?;
+        signed.verify(algorithm, &secret, now)?;
+        Ok(key)
+    }
+
+    /// Check and apply an authenticated update
+    async fn apply(&self, request: &Message, key: &TsigKey) -> Result<Applied> {
+        let [zone_query] = request.queries() else {
+            return Ok(Applied::rejected(ResponseCode::FormErr));
+        };
+        if zone_query.query_type() != WireRecordType::SOA {
+            return Ok(Applied::rejected(ResponseCode::FormErr));
+        }
+        let origin = zone_query.name().to_string().trim_end_matches('.').to_lowercase();
+
+        let _guard = self.lock.lock().await;
+        let zones = self.db.dns_zones().list().await?;
+        let Some(zone) = zones.iter().find(|z| z.origin == origin && z.enabled && !z.is_secondary()) else {
+            return Ok(Applied::rejected(ResponseCode::NotAuth));
+        };
+        if zone_query.query_class() != DNSClass::IN {
+            return Ok(Applied::rejected(ResponseCode::NotAuth));
+        }
+        if !key.zone_list().contains(&origin.as_str()) {
+            return Ok(Applied::rejected(ResponseCode::Refused));
+        }
+
+        let rows = self.db.dns_records().list().await?;
+        let mut data = ZoneData::new(zone, &zones, &rows);
+        if let Some(code) = check_prerequisites(&data, request.answers()) {
+            return Ok(Applied::rejected(code));
+        }
+        if let Some(code) = prescan(&data, request.name_servers()) {
+            return Ok(Applied::rejected(code));
+        }
+        let before = data.row_ids();
+        let changes = apply_updates(&mut data, request.name_servers());
+
+        let kept = data.row_ids();
+        let deleted: Vec<i64> = before.difference(&kept).copied().collect();
+        let mut retimed = Vec::new();
+        let mut created = Vec::new();
+        for entry in &data.entries {
+            match entry.source {
+                Source::Row { id, ttl, .. } if ttl != entry.data.ttl => retimed.push((id, entry.data.ttl as i32)),
+                Source::New => created.push(CreateDnsRecord {
+                    name: entry.data.name.clone(),
+                    record_type: entry.data.record_type.to_string(),
+                    value: entry.data.value.clone(),
+                    ttl: entry.data.ttl as i32,
+                    priority: entry.data.priority.unwrap_or(0) as i32,
+                    enabled: true,
+                }),
+                _ => {}
+            }
+        }
+        self.db.dns_records().apply_changes(&deleted, &retimed, &created).await?;
+        Ok(Applied { rcode: ResponseCode::NoError, changes })
+    }
+}
+
+/// Decode a base64 TSIG secret
+pub fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD.decode(secret.trim()).ok()
+}
+
+/// Response to an update carrying its zone section
+fn reply(request: &Message, code: ResponseCode) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(OpCode::Update)
+        .set_response_code(code);
+    response.add_queries(request.queries().iter().cloned());
+    response
+}
+
+/// Bare response for a request that could not be parsed
+fn header_reply(data: &[u8], code: ResponseCode) -> Option<Vec<u8>> {
+    let id = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+    let mut response = Message::new();
+    response
+        .set_id(id)
+        .set_message_type(MessageType::Response)
+        .set_op_code(OpCode::Update)
+        .set_response_code(code);
+    response.to_vec().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{rdata, Name, RData};
+
+    use crate::db::{CreateDnsZone, CreateTsigKey};
+    use crate::dns::tsig::{sign_request, verify_response};
+
+    const SECRET: &str = "c2VjcmV0LXNlY3JldC1zZWNyZXQtc2VjcmV0IQ==";
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn message(zone: &str, prerequisites: Vec<Record>, updates: Vec<Record>) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Update);
+        message.add_query(Query::query(name(zone), WireRecordType::SOA));
+        for record in prerequisites {
+            message.add_answer(record);
+        }
+        for record in updates {
+            message.add_name_server(record);
+        }
+        message
+    }
+
+    fn a(owner: &str, ttl: u32, ip: [u8; 4]) -> Record {
+        Record::from_rdata(name(owner), ttl, RData::A(Ipv4Addr::from(ip).into()))
+    }
+
+    fn txt(owner: &str, text: &str) -> Record {
+        Record::from_rdata(name(owner), 60, RData::TXT(rdata::TXT::new(vec![text.to_string()])))
+    }
+
+    fn with_class(record_type: WireRecordType, owner: &str, class: DNSClass) -> Record {
+        let mut record = Record::update0(name(owner), 0, record_type);
+        record.set_dns_class(class);
+        record
+    }
+
+    async fn send(updates: &DynamicUpdates, message: Message, key: &str) -> (ResponseCode, u16) {
+        let request = sign_request(message.to_vec().unwrap(), key, TsigAlgorithm::HmacSha256, &decode_secret(SECRET).unwrap(), Utc::now().timestamp() as u64).unwrap();
+        let response = updates.handle(&request, "192.0.2.7".parse().unwrap()).await.unwrap();
+        let signed = split_tsig(&response).unwrap().unwrap();
+        if signed.tsig.error == 0 {
+            let request_mac = split_tsig(&request).unwrap().unwrap().tsig.mac;
+            verify_response(&signed, &request_mac, TsigAlgorithm::HmacSha256, &decode_secret(SECRET).unwrap()).unwrap();
+        }
+        (Message::from_vec(&signed.unsigned).unwrap().response_code(), signed.tsig.error)
+    }
+
+    async fn values(db: &Database, owner: &str) -> Vec<String> {
+        let mut values: Vec<String> = db
+            .dns_records()
+            .get_by_name(owner)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| format!("{} {} {}", r.record_type, r.ttl, r.value))
+            .collect();
+        values.sort();
+        values
+    }
+
+    /// Zones and the `ddns` key for example.com and sub.example.com
+    async fn setup(dir: &std::path::Path) -> (Arc<Database>, Arc<DynamicUpdates>) {
+        let db = Arc::new(Database::new(&format!("sqlite:{}?mode=rwc", dir.join("test.db").display())).await.unwrap());
+        for origin in ["example.com", "sub.example.com", "other.com"] {
+            db.dns_zones()
+                .create(CreateDnsZone::new(origin.to_string(), format!("ns1.{}", origin), format!("hostmaster.{}", origin)))
+                .await
+                .unwrap();
+        }
+        db.tsig_keys()
+            .create(CreateTsigKey {
+                name: "ddns".to_string(),
+                algorithm: "hmac-sha256".to_string(),
+                secret: SECRET.to_string(),
+                zones: "example.com,sub.example.com".to_string(),
+                enabled: true,
+            })
+            .await
+            .unwrap();
+        let updates = DynamicUpdates::new(db.clone());
+        (db, updates)
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, updates) = setup(dir.path()).await;
+
+        // Unsigned and badly signed updates change nothing
+        let unsigned = message("example.com", vec![], vec![a("host.example.com", 300, [10, 0, 0, 1])]);
+        let response = updates.handle(&unsigned.to_vec().unwrap(), "192.0.2.7".parse().unwrap()).await.unwrap();
+        assert_eq!(Message::from_vec(&response).unwrap().response_code(), ResponseCode::Refused);
+        let add = message("example.com", vec![], vec![a("host.example.com", 300, [10, 0, 0, 1])]);
+        assert_eq!(send(&updates, add.clone(), "unknown").await, (ResponseCode::NotAuth, TsigError::BadKey.code()));
+        assert!(values(&db, "host.example.com").await.is_empty());
+
+        // Add only if the name is free
+        let free = with_class(WireRecordType::ANY, "host.example.com", DNSClass::NONE);
+        let add = message("example.com", vec![free.clone()], vec![a("host.example.com", 300, [10, 0, 0, 1])]);
+        assert_eq!(send(&updates, add.clone(), "ddns").await, (ResponseCode::NoError, 0));
+        assert_eq!(values(&db, "host.example.com").await, ["A 300 10.0.0.1"]);
+        assert_eq!(send(&updates, add, "ddns").await, (ResponseCode::YXDomain, 0));
+
+        // Value-dependent prerequisite, then an atomic replace with a new TTL
+        let replace = message(
+            "example.com",
+            vec![a("host.example.com", 0, [10, 0, 0, 1])],
+            vec![
+                with_class(WireRecordType::A, "host.example.com", DNSClass::ANY),
+                a("host.example.com", 120, [10, 0, 0, 2]),
+                a("host.example.com", 120, [10, 0, 0, 3]),
+            ],
+        );
+        assert_eq!(send(&updates, replace.clone(), "ddns").await, (ResponseCode::NoError, 0));
+        assert_eq!(values(&db, "host.example.com").await, ["A 120 10.0.0.2", "A 120 10.0.0.3"]);
+        assert_eq!(send(&updates, replace, "ddns").await, (ResponseCode::NXRRSet, 0));
+
+        // Delete one record; a CNAME cannot join other data
+        let mut delete = a("host.example.com", 0, [10, 0, 0, 3]);
+        delete.set_dns_class(DNSClass::NONE);
+        let cname = Record::from_rdata(name("host.example.com"), 60, RData::CNAME(rdata::CNAME(name("www.example.com"))));
+        let change = message("example.com", vec![], vec![delete, cname]);
+        assert_eq!(send(&updates, change, "ddns").await, (ResponseCode::NoError, 0));
+        assert_eq!(values(&db, "host.example.com").await, ["A 120 10.0.0.2"]);
+
+        // ACME challenge in the child zone
+        let challenge = message("sub.example.com", vec![], vec![txt("_acme-challenge.sub.example.com", "token")]);
+        assert_eq!(send(&updates, challenge, "ddns").await, (ResponseCode::NoError, 0));
+        assert_eq!(values(&db, "_acme-challenge.sub.example.com").await, ["TXT 60 token"]);
+
+        // Names of other zones, zones the key does not list, unknown zones
+        let child = message("example.com", vec![], vec![txt("x.sub.example.com", "token")]);
+        assert_eq!(send(&updates, child, "ddns").await, (ResponseCode::NotZone, 0));
+        let other = message("other.com", vec![], vec![txt("other.com", "token")]);
+        assert_eq!(send(&updates, other, "ddns").await, (ResponseCode::Refused, 0));
+        let unknown = message("unknown.com", vec![], vec![txt("unknown.com", "token")]);
+        assert_eq!(send(&updates, unknown, "ddns").await, (ResponseCode::NotAuth, 0));
+
+        // The SOA and apex NS set are left to the zone settings
+        let apex = message(
+            "example.com",
+            vec![],
+            vec![
+                with_class(WireRecordType::ANY, "example.com", DNSClass::ANY),
+                Record::from_rdata(name("example.com"), 60, RData::NS(rdata::NS(name("ns9.example.com")))),
+            ],
+        );
+        assert_eq!(send(&updates, apex, "ddns").await, (ResponseCode::NoError, 0));
+        assert!(values(&db, "example.com").await.is_empty());
+        let ns = message("example.com", vec![with_class(WireRecordType::NS, "example.com", DNSClass::ANY)], vec![]);
+        assert_eq!(send(&updates, ns, "ddns").await, (ResponseCode::NoError, 0));
+
+        // Unsigned and badly signed requests stay out of the audit log
+        let log = db.dns_update_log().list(None, 100).await.unwrap();
+        assert_eq!(log.len(), 11);
+        assert!(log.iter().all(|e| e.key_name.as_deref() == Some("ddns")));
+        assert_eq!(log[log.len() - 1].rcode, "NOERROR");
+        let replaced = log.iter().find(|e| e.changes.lines().count() == 3).unwrap();
+        assert_eq!(replaced.key_name.as_deref(), Some("ddns"));
+        assert_eq!(replaced.changes.lines().next(), Some("delete host.example.com 300 A 10.0.0.1"));
+        assert_eq!(db.dns_update_log().list(Some("sub.example.com"), 100).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_managed_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, updates) = setup(dir.path()).await;
+        let record = |value: &str| CreateDnsRecord {
+            name: "host.example.com".to_string(),
+            record_type: "A".to_string(),
+            value: value.to_string(),
+            ttl: 300,
+            priority: 0,
+            enabled: true,
+        };
+        let managed = db.dns_records().create(record("10.0.0.1")).await.unwrap();
+        db.dns_records().set_managed(managed.id, true).await.unwrap();
+        db.dns_records().create(record("10.0.0.2")).await.unwrap();
+
+        // Deleting every RRset of the name only removes the row the config file does not own
+        let delete = message("example.com", vec![], vec![with_class(WireRecordType::ANY, "host.example.com", DNSClass::ANY)]);
+        assert_eq!(send(&updates, delete, "ddns").await, (ResponseCode::NoError, 0));
+        assert_eq!(values(&db, "host.example.com").await, ["A 300 10.0.0.1"]);
+    }
+
+    #[test]
+    fn test_update_prescan() {
+        let zone = DnsZone {
+            id: 1,
+            origin: "example.com".to_string(),
+            primary_ns: "ns1.example.com".to_string(),
+            admin_email: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 604800,
+            minimum: 300,
+            ttl: 3600,
+            nameservers: "ns1.example.com".to_string(),
+            enabled: true,
+            kind: "primary".to_string(),
+            primaries: String::new(),
+            transfer_acl: String::new(),
+            notify: String::new(),
+            managed: false,
+            content_hash: None,
+            content: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let data = ZoneData::new(&zone, std::slice::from_ref(&zone), &[]);
+        assert!(data.name_in_use("example.com"));
+
+        let mut delete_with_ttl = with_class(WireRecordType::A, "a.example.com", DNSClass::ANY);
+        delete_with_ttl.set_ttl(60);
+        assert_eq!(prescan(&data, &[delete_with_ttl]), Some(ResponseCode::FormErr));
+        assert_eq!(prescan(&data, &[with_class(WireRecordType::AXFR, "a.example.com", DNSClass::NONE)]), Some(ResponseCode::FormErr));
+        assert_eq!(prescan(&data, &[a("a.example.org", 60, [10, 0, 0, 1])]), Some(ResponseCode::NotZone));
+        let caa = Record::from_rdata(name("a.example.com"), 60, RData::Unknown {
+            code: WireRecordType::Unknown(65280),
+            rdata: rdata::NULL::with(vec![1]),
+        });
+        assert_eq!(prescan(&data, &[caa]), Some(ResponseCode::Refused));
+        assert_eq!(prescan(&data, &[a("a.example.com", 60, [10, 0, 0, 1])]), None);
+        assert_eq!(check_prerequisites(&data, &[with_class(WireRecordType::SOA, "example.com", DNSClass::ANY)]), None);
+        assert_eq!(check_prerequisites(&data, &[with_class(WireRecordType::ANY, "a.example.com", DNSClass::ANY)]), Some(ResponseCode::NXDomain));
+    }
+}
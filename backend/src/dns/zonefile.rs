@@ -0,0 +1,548 @@
+//! RFC 1035 Zone Files
+//!
+//! Parses master files into local records and renders local records back as
+//! zone files. Supports `$ORIGIN`, `$TTL`, `@`, relative names, owners
+//! inherited from the previous record, TTL units (`1h30m`) and records split
+//! over several lines with parentheses. Only class IN and the record types
+//! local records can hold are accepted; every other line yields an error
+//! carrying its line number instead of stopping the parse.
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::Serialize;
+
+use crate::db::DnsRecord;
+use super::privacy::registrable_domain;
+
+/// A problem with one line of an imported file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// A record read from an imported file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParsedRecord {
+    pub line: usize,
+    pub name: String,
+    pub record_type: String,
+    pub value: String,
+    pub ttl: u32,
+    pub priority: u16,
+}
+
+/// Records and per-line errors of an imported file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ParsedFile {
+    pub records: Vec<ParsedRecord>,
+    pub errors: Vec<LineError>,
+}
+
+/// One token of a logical line
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// A logical line: one directive or record, possibly spanning parentheses
+struct Entry {
+    line: usize,
+    /// Starts with whitespace, so the owner is inherited
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+/// Split a zone file into logical lines, dropping comments
+fn entries(content: &str, errors: &mut Vec<LineError>) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut indented = false;
+    let mut tokens: Vec<Token> = Vec::new();
+
+    fn flush(current: &mut Option<String>, tokens: &mut Vec<Token>) {
+        if let Some(text) = current.take() {
+            tokens.push(Token { text, quoted: false });
+        }
+    }
+
+    for (index, raw) in content.lines().enumerate() {
+        let line = index + 1;
+        if depth == 0 {
+            start = line;
+            indented = raw.starts_with([' ', '\t']);
+            tokens.clear();
+        }
+
+        let mut chars = raw.chars();
+        let mut current: Option<String> = None;
+        let mut quoted: Option<String> = None;
+
+        while let Some(c) = chars.next() {
+            if let Some(ref mut text) = quoted {
+                match c {
+                    '\\' => text.extend(chars.next()),
+                    '"' => tokens.push(Token { text: quoted.take().unwrap_or_default(), quoted: true }),
+                    _ => text.push(c),
+                }
+                continue;
+            }
+            match c {
+                ';' => break,
+                '"' => {
+                    flush(&mut current, &mut tokens);
+                    quoted = Some(String::new());
+                }
+                '(' => {
+                    flush(&mut current, &mut tokens);
+                    depth += 1;
+                }
+                ')' => {
+                    flush(&mut current, &mut tokens);
+                    if depth == 0 {
+                        errors.push(LineError { line, message: "unbalanced ')'".to_string() });
+                    } else {
+                        depth -= 1;
+                    }
+                }
+                '\\' => current.get_or_insert_with(String::new).extend(chars.next()),
+                c if c.is_whitespace() => flush(&mut current, &mut tokens),
+                c => current.get_or_insert_with(String::new).push(c),
+            }
+        }
+        if quoted.is_some() {
+            errors.push(LineError { line, message: "unterminated quoted string".to_string() });
+        }
+        flush(&mut current, &mut tokens);
+
+        if depth == 0 && !tokens.is_empty() {
+            entries.push(Entry { line: start, indented, tokens: std::mem::take(&mut tokens) });
+        }
+    }
+
+    if depth > 0 {
+        errors.push(LineError { line: start, message: "'(' is never closed".to_string() });
+    }
+    entries
+}
+
+/// Parse a TTL in seconds or with BIND units (`1h30m`, `2d`, `1w`)
+pub fn parse_ttl(text: &str) -> Option<u32> {
+    if text.is_empty() {
+        return None;
+    }
+    if text.bytes().all(|b| b.is_ascii_digit()) {
+        return text.parse().ok();
+    }
+
+    let mut total: u64 = 0;
+    let mut number: Option<u64> = None;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit as u64)?);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+    if number.is_some() {
+        return None;
+    }
+    u32::try_from(total).ok()
+}
+
+/// Normalize an origin given by the caller: lower case, no trailing dot
+fn normalize_origin(origin: &str) -> Option<String> {
+    let origin = origin.trim().trim_end_matches('.').to_lowercase();
+    (!origin.is_empty()).then_some(origin)
+}
+
+/// Resolve a possibly relative name against the current origin
+fn resolve_name(text: &str, origin: Option<&str>) -> Result<String, String> {
+    if text == "@" {
+        return origin.map(str::to_string).ok_or_else(|| "'@' used without $ORIGIN".to_string());
+    }
+    if let Some(absolute) = text.strip_suffix('.') {
+        if absolute.is_empty() {
+            return Err("the root name cannot hold local records".to_string());
+        }
+        return Ok(absolute.to_lowercase());
+    }
+    match origin {
+        Some(origin) => Ok(format!("{}.{}", text, origin).to_lowercase()),
+        None => Err(format!("relative name '{}' used without $ORIGIN", text)),
+    }
+}
+
+fn parse_u16(text: &str, field: &str) -> Result<u16, String> {
+    text.parse().map_err(|_| format!("invalid {} '{}'", field, text))
+}
+
+/// Parse the RDATA of one record into the local record value and priority
+fn parse_rdata(record_type: &str, rdata: &[Token], origin: Option<&str>) -> Result<(String, u16), String> {
+    let expect = |count: usize| {
+        if rdata.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{} record expects {} field(s), got {}", record_type, count, rdata.len()))
+        }
+    };
+
+    match record_type {
+        "A" => {
+            expect(1)?;
+            let ip: Ipv4Addr = rdata[0].text.parse().map_err(|_| format!("invalid IPv4 address '{}'", rdata[0].text))?;
+            Ok((ip.to_string(), 0))
+        }
+        "AAAA" => {
+            expect(1)?;
+            let ip: Ipv6Addr = rdata[0].text.parse().map_err(|_| format!("invalid IPv6 address '{}'", rdata[0].text))?;
+            Ok((ip.to_string(), 0))
+        }
+        "CNAME" | "NS" | "PTR" => {
+            expect(1)?;
+            Ok((resolve_name(&rdata[0].text, origin)?, 0))
+        }
+        "MX" => {
+            expect(2)?;
+            let preference = parse_u16(&rdata[0].text, "preference")?;
+            Ok((resolve_name(&rdata[1].text, origin)?, preference))
+        }
+        "TXT" => {
+            if rdata.is_empty() {
+                return Err("TXT record has no text".to_string());
+            }
+            Ok((rdata.iter().map(|t| t.text.as_str()).collect(), 0))
+        }
+        "SRV" => {
+            expect(4)?;
+            let priority = parse_u16(&rdata[0].text, "priority")?;
+            let weight = parse_u16(&rdata[1].text, "weight")?;
+            let port = parse_u16(&rdata[2].text, "port")?;
+            let target = resolve_name(&rdata[3].text, origin)?;
+            Ok((format!("{} {} {} {}", priority, weight, port, target), priority))
+        }
+        "SOA" => {
+            expect(7)?;
+            let mname = resolve_name(&rdata[0].text, origin)?;
+            let rname = resolve_name(&rdata[1].text, origin)?;
+            let serial: u32 = rdata[2].text.parse().map_err(|_| format!("invalid serial '{}'", rdata[2].text))?;
+            let mut timers = Vec::with_capacity(4);
+            for (token, field) in rdata[3..].iter().zip(["refresh", "retry", "expire", "minimum"]) {
+                timers.push(parse_ttl(&token.text).ok_or_else(|| format!("invalid {} '{}'", field, token.text))?);
+            }
+            Ok((format!("{} {} {} {} {} {} {}", mname, rname, serial, timers[0], timers[1], timers[2], timers[3]), 0))
+        }
+        other => Err(format!("unsupported record type {}", other)),
+    }
+}
+
+/// Parse a zone file
+///
+/// `origin` is the initial `$ORIGIN` and `default_ttl` applies until a
+/// `$TTL` directive. Names are stored lower case without the trailing dot.
+pub fn parse_zone(content: &str, origin: Option<&str>, default_ttl: u32) -> ParsedFile {
+    let mut parsed = ParsedFile::default();
+    let mut origin = origin.and_then(normalize_origin);
+    let mut zone_ttl = default_ttl;
+    let mut last_owner: Option<String> = None;
+
+    for entry in entries(content, &mut parsed.errors) {
+        let line = entry.line;
+        let result = (|| -> Result<Option<ParsedRecord>, String> {
+            let first = &entry.tokens[0];
+            if !entry.indented && !first.quoted && first.text.starts_with('$') {
+                let directive = first.text.to_uppercase();
+                let argument = entry.tokens.get(1).map(|t| t.text.as_str());
+                match (directive.as_str(), argument) {
+                    ("$ORIGIN", Some(name)) => origin = Some(resolve_name(name, origin.as_deref())?),
+                    ("$TTL", Some(ttl)) => zone_ttl = parse_ttl(ttl).ok_or_else(|| format!("invalid $TTL '{}'", ttl))?,
+                    ("$ORIGIN" | "$TTL", None) => return Err(format!("{} needs an argument", directive)),
+                    ("$INCLUDE" | "$GENERATE", _) => return Err(format!("{} is not supported", directive)),
+                    _ => return Err(format!("unknown directive {}", first.text)),
+                }
+                return Ok(None);
+            }
+
+            let mut tokens = entry.tokens.iter();
+            let owner = if entry.indented {
+                last_owner.clone().ok_or_else(|| "record has no owner name".to_string())?
+            } else {
+                let name = tokens.next().map(|t| t.text.as_str()).unwrap_or_default();
+                resolve_name(name, origin.as_deref())?
+            };
+            last_owner = Some(owner.clone());
+
+            // TTL and class may come in either order before the type
+            let mut ttl = None;
+            let mut class_seen = false;
+            let record_type = loop {
+                let token = tokens.next().ok_or_else(|| "missing record type".to_string())?;
+                if ttl.is_none() && !token.quoted {
+                    if let Some(value) = parse_ttl(&token.text) {
+                        ttl = Some(value);
+                        continue;
+                    }
+                }
+                let upper = token.text.to_uppercase();
+                if !class_seen && matches!(upper.as_str(), "IN" | "CH" | "CS" | "HS") {
+                    if upper != "IN" {
+                        return Err(format!("class {} is not supported", upper));
+                    }
+                    class_seen = true;
+                    continue;
+                }
+                break upper;
+            };
+
+            let (value, priority) = parse_rdata(&record_type, tokens.as_slice(), origin.as_deref())?;
+            Ok(Some(ParsedRecord {
+                line,
+                name: owner,
+                record_type,
+                value,
+                ttl: ttl.unwrap_or(zone_ttl),
+                priority,
+            }))
+        })();
+
+        match result {
+            Ok(Some(record)) => parsed.records.push(record),
+            Ok(None) => {}
+            Err(message) => parsed.errors.push(LineError { line, message }),
+        }
+    }
+
+    parsed.errors.sort_by_key(|e| e.line);
+    parsed
+}
+
+/// Group records by the zone they belong to
+///
+/// A record belongs to the closest enclosing SOA owner among the records;
+/// names outside every SOA fall back to their registrable domain.
+pub fn group_by_zone(records: Vec<DnsRecord>) -> BTreeMap<String, Vec<DnsRecord>> {
+    let apexes: Vec<String> = records
+        .iter()
+        .filter(|r| r.record_type.eq_ignore_ascii_case("SOA"))
+        .map(|r| r.name.trim_end_matches('.').to_lowercase())
+        .collect();
+
+    let mut zones: BTreeMap<String, Vec<DnsRecord>> = BTreeMap::new();
+    for record in records {
+        let name = record.name.trim_end_matches('.').to_lowercase();
+        let zone = apexes
+            .iter()
+            .filter(|apex| name == **apex || name.ends_with(&format!(".{}", apex)))
+            .max_by_key(|apex| apex.len())
+            .cloned()
+            .unwrap_or_else(|| registrable_domain(&name).to_lowercase());
+        zones.entry(zone).or_default().push(record);
+    }
+    zones
+}
+
+/// Owner name relative to the origin
+fn relative_name(name: &str, origin: &str) -> String {
+    let name = name.trim_end_matches('.').to_lowercase();
+    if name == origin {
+        "@".to_string()
+    } else if let Some(label) = name.strip_suffix(&format!(".{}", origin)) {
+        label.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+fn absolute(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Quote TXT data, split into strings of at most 255 bytes
+fn quote_txt(text: &str) -> String {
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        if current.len() + c.len_utf8() > 255 {
+            strings.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    strings.push(current);
+    strings
+        .iter()
+        .map(|s| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Render the RDATA of a local record
+fn render_rdata(record: &DnsRecord) -> String {
+    let fields: Vec<&str> = record.value.split_whitespace().collect();
+    match record.record_type.to_uppercase().as_str() {
+        "CNAME" | "NS" | "PTR" => absolute(&record.value),
+        "MX" => format!("{} {}", record.priority, absolute(&record.value)),
+        "TXT" => quote_txt(&record.value),
+        "SRV" if fields.len() == 4 => format!("{} {} {} {}", fields[0], fields[1], fields[2], absolute(fields[3])),
+        "SOA" if fields.len() == 7 => format!(
+            "{} {} {}",
+            absolute(fields[0]),
+            absolute(fields[1]),
+            fields[2..].join(" ")
+        ),
+        _ => record.value.clone(),
+    }
+}
+
+/// Render local records as a zone file for `origin`
+///
+/// SOA records come first; disabled records are written as comments so
+/// that importing the file again does not enable them.
+pub fn render_zone(origin: &str, records: &[DnsRecord]) -> String {
+    let origin = origin.trim_end_matches('.').to_lowercase();
+    let mut sorted: Vec<&DnsRecord> = records.iter().collect();
+    sorted.sort_by_key(|r| (!r.record_type.eq_ignore_ascii_case("SOA"), relative_name(&r.name, &origin), r.record_type.to_uppercase()));
+
+    let mut out = format!("$ORIGIN {}\n", absolute(&origin));
+    for record in sorted {
+        let line = format!(
+            "{}\t{}\tIN\t{}\t{}",
+            relative_name(&record.name, &origin),
+            record.ttl,
+            record.record_type.to_uppercase(),
+            render_rdata(record)
+        );
+        if record.enabled {
+            out.push_str(&line);
+        } else {
+            out.push_str("; disabled: ");
+            out.push_str(&line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn record(name: &str, record_type: &str, value: &str, priority: i32) -> DnsRecord {
+        DnsRecord {
+            id: 0,
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            value: value.to_string(),
+            ttl: 300,
+            priority,
+            enabled: true,
+            managed: false,
+            source: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_parse_zone() {
+        let zone = r#"
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            2h 30m 1w 300 )
+    IN  NS  ns1
+ns1     A   192.0.2.1
+www 600 IN A 192.0.2.10
+        IN AAAA 2001:db8::10
+mail    MX  10 mx.example.net.
+txt     TXT "v=spf1 -all; strict" "more"
+_sip._tcp SRV 10 5 5060 sip
+"#;
+        let parsed = parse_zone(zone, None, 300);
+        assert!(parsed.errors.is_empty(), "{:?}", parsed.errors);
+
+        let summary: Vec<(usize, &str, &str, &str, u32, u16)> = parsed.records.iter()
+            .map(|r| (r.line, r.name.as_str(), r.record_type.as_str(), r.value.as_str(), r.ttl, r.priority))
+            .collect();
+        assert_eq!(summary, vec![
+            (4, "example.com", "SOA", "ns1.example.com hostmaster.example.com 2024010101 7200 1800 604800 300", 3600, 0),
+            (7, "example.com", "NS", "ns1.example.com", 3600, 0),
+            (8, "ns1.example.com", "A", "192.0.2.1", 3600, 0),
+            (9, "www.example.com", "A", "192.0.2.10", 600, 0),
+            (10, "www.example.com", "AAAA", "2001:db8::10", 3600, 0),
+            (11, "mail.example.com", "MX", "mx.example.net", 3600, 10),
+            (12, "txt.example.com", "TXT", "v=spf1 -all; strictmore", 3600, 0),
+            (13, "_sip._tcp.example.com", "SRV", "10 5 5060 sip.example.com", 3600, 10),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_zone_reports_line_errors() {
+        let zone = "www A 192.0.2.1\n$ORIGIN example.com.\nbad A 999.0.0.1\nok A 192.0.2.2\nhinfo HINFO a b\nch CH A 192.0.2.3\n$INCLUDE other.zone\nopen A (\n";
+        let parsed = parse_zone(zone, None, 300);
+
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].name, "ok.example.com");
+        let lines: Vec<usize> = parsed.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 3, 5, 6, 7, 8]);
+        assert!(parsed.errors[0].message.contains("without $ORIGIN"));
+        assert!(parsed.errors[2].message.contains("HINFO"));
+    }
+
+    #[test]
+    fn test_parse_ttl_units() {
+        assert_eq!(parse_ttl("300"), Some(300));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("1h30"), None);
+        assert_eq!(parse_ttl("IN"), None);
+    }
+
+    #[test]
+    fn test_render_zone_round_trip() {
+        let records = vec![
+            record("www.example.com", "A", "192.0.2.10", 0),
+            record("example.com", "SOA", "ns1.example.com hostmaster.example.com 1 7200 1800 604800 300", 0),
+            record("example.com", "MX", "mail.example.com", 10),
+            record("example.com", "TXT", "say \"hi\"", 0),
+            record("alias.other.org", "CNAME", "www.example.com", 0),
+        ];
+        let text = render_zone("example.com", &records);
+        assert!(text.starts_with("$ORIGIN example.com.\n@\t300\tIN\tSOA\tns1.example.com. hostmaster.example.com. 1"));
+
+        let parsed = parse_zone(&text, None, 3600);
+        assert!(parsed.errors.is_empty(), "{:?}", parsed.errors);
+        let mut round_trip: Vec<(String, String, String, u16)> = parsed.records.into_iter()
+            .map(|r| (r.name, r.record_type, r.value, r.priority))
+            .collect();
+        let mut expected: Vec<(String, String, String, u16)> = records.iter()
+            .map(|r| (r.name.clone(), r.record_type.clone(), r.value.clone(), r.priority as u16))
+            .collect();
+        round_trip.sort();
+        expected.sort();
+        assert_eq!(round_trip, expected);
+    }
+
+    #[test]
+    fn test_group_by_zone() {
+        let zones = group_by_zone(vec![
+            record("lab.example.com", "SOA", "ns.lab.example.com h.lab.example.com 1 1 1 1 1", 0),
+            record("a.lab.example.com", "A", "192.0.2.1", 0),
+            record("www.example.com", "A", "192.0.2.2", 0),
+            record("nas", "A", "192.168.1.2", 0),
+        ]);
+        let names: Vec<(&str, usize)> = zones.iter().map(|(zone, records)| (zone.as_str(), records.len())).collect();
+        assert_eq!(names, vec![("example.com", 1), ("lab.example.com", 2), ("nas", 1)]);
+    }
+}
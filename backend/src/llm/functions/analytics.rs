@@ -33,8 +33,9 @@ impl LlmFunction for GetClientStatsFunction {
             "SELECT client_ip, COUNT(*) as count FROM query_logs GROUP BY client_ip ORDER BY count DESC LIMIT ?"
         ).bind(limit).fetch_all(state.db.pool()).await {
             Ok(clients) => {
+                let privacy = state.resolver.privacy().await;
                 let stats: Vec<Value> = clients.iter().map(|(ip, count)| {
-                    json!({"client_ip": ip, "query_count": count})
+                    json!({"client_ip": privacy.client_ip(ip), "query_count": count})
                 }).collect();
                 FunctionResult::success(json!({"clients": stats}))
             }
@@ -72,13 +73,14 @@ impl LlmFunction for DetectDnsTunnelingFunction {
             "SELECT COUNT(*) FROM query_logs WHERE query_type = 'TXT'"
         ).fetch_one(state.db.pool()).await.map(|r| r.0).unwrap_or(0);
 
+        let privacy = state.resolver.privacy().await;
         let mut findings: Vec<Value> = Vec::new();
         
         for (domain, len) in long_domains {
             findings.push(json!({
                 "type": "long_domain",
                 "severity": "medium",
-                "domain": domain,
+                "domain": privacy.domain(&domain),
                 "length": len
             }));
         }
@@ -133,9 +135,10 @@ impl LlmFunction for SuggestBlockingRulesFunction {
             "#
         ).bind(limit).fetch_all(state.db.pool()).await.unwrap_or_default();
 
+        let privacy = state.resolver.privacy().await;
         let suggestions: Vec<Value> = suspicious.iter().map(|(domain, count)| {
             json!({
-                "domain": domain,
+                "domain": privacy.domain(domain),
                 "nxdomain_count": count,
                 "suggested_rule": {
                     "pattern": domain,
@@ -5,6 +5,7 @@ use serde_json::{json, Value};
 
 use super::LlmFunction;
 use crate::llm::types::{FunctionDefinition, FunctionResult};
+use crate::services::config_transfer::{self, ConfigSnapshot, ConfigTransfer, ImportMode};
 use crate::state::AppState;
 
 pub struct ExportConfigFunction;
@@ -14,40 +15,22 @@ impl LlmFunction for ExportConfigFunction {
     fn definition(&self) -> FunctionDefinition {
         FunctionDefinition {
             name: "export_config".to_string(),
-            description: "导出当前配置为 JSON".to_string(),
+            description: "导出当前全部配置 (上游、重写规则、本地记录、监听器和设置) 为 JSON".to_string(),
             parameters: json!({"type": "object", "properties": {}, "required": []}),
         }
     }
 
     async fn execute(&self, _args: Value, state: &AppState) -> FunctionResult {
-        // Export DNS records
-        let records = sqlx::query_as::<_, (i64, String, String, String, i32, Option<i32>, bool)>(
-            "SELECT id, name, record_type, value, ttl, priority, enabled FROM dns_records"
-        ).fetch_all(state.db.pool()).await.unwrap_or_default();
-
-        // Export rewrite rules  
-        let rules = sqlx::query_as::<_, (i64, String, String, String, Option<String>, i32, bool)>(
-            "SELECT id, pattern, match_type, action_type, action_value, priority, enabled FROM rewrite_rules"
-        ).fetch_all(state.db.pool()).await.unwrap_or_default();
-
-        // Export upstreams
-        let upstreams = sqlx::query_as::<_, (i64, String, String, String, i32, bool)>(
-            "SELECT id, name, address, protocol, timeout, enabled FROM upstream_servers"
-        ).fetch_all(state.db.pool()).await.unwrap_or_default();
-
-        FunctionResult::success(json!({
-            "version": "1.0",
-            "exported_at": chrono::Utc::now().to_rfc3339(),
-            "dns_records": records.iter().map(|r| json!({
-                "name": r.1, "type": r.2, "value": r.3, "ttl": r.4, "priority": r.5, "enabled": r.6
-            })).collect::<Vec<_>>(),
-            "rewrite_rules": rules.iter().map(|r| json!({
-                "pattern": r.1, "match_type": r.2, "action_type": r.3, "action_value": r.4, "priority": r.5, "enabled": r.6
-            })).collect::<Vec<_>>(),
-            "upstreams": upstreams.iter().map(|u| json!({
-                "name": u.1, "address": u.2, "protocol": u.3, "timeout": u.4, "enabled": u.5
-            })).collect::<Vec<_>>()
-        }))
+        match config_transfer::export(&state.db).await {
+            Ok(mut snapshot) => {
+                // Private keys are not sent to the model provider
+                for listener in snapshot.listeners.iter_mut().flatten() {
+                    listener.tls_key = None;
+                }
+                FunctionResult::success(json!(snapshot))
+            }
+            Err(e) => FunctionResult::error(format!("导出失败: {}", e)),
+        }
     }
 }
 
@@ -58,13 +41,22 @@ impl LlmFunction for ImportConfigFunction {
     fn definition(&self) -> FunctionDefinition {
         FunctionDefinition {
             name: "import_config".to_string(),
-            description: "从 JSON 导入配置".to_string(),
+            description: "从 export_config 导出的 JSON 导入配置，默认只预览变更".to_string(),
             parameters: json!({
                 "type": "object",
                 "properties": {
                     "config": {
                         "type": "object",
-                        "description": "配置对象，包含 dns_records, rewrite_rules, upstreams"
+                        "description": "配置快照，包含 version 以及 upstreams, rewrite_rules, records, listeners, settings 中的任意部分"
+                    },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["merge", "replace"],
+                        "description": "merge 只新增和更新；replace 还会删除快照中已有分段里不存在的条目"
+                    },
+                    "dry_run": {
+                        "type": "boolean",
+                        "description": "只返回变更预览，默认 true；确认后传 false 执行"
                     }
                 },
                 "required": ["config"]
@@ -72,18 +64,34 @@ impl LlmFunction for ImportConfigFunction {
         }
     }
 
-    async fn execute(&self, args: Value, _state: &AppState) -> FunctionResult {
-        let config = args.get("config");
-        
-        if config.is_none() {
+    async fn execute(&self, args: Value, state: &AppState) -> FunctionResult {
+        let Some(config) = args.get("config") else {
             return FunctionResult::error("Missing config parameter");
+        };
+        let snapshot: ConfigSnapshot = match serde_json::from_value(config.clone()) {
+            Ok(snapshot) => snapshot,
+            Err(e) => return FunctionResult::error(format!("配置格式无效: {}", e)),
+        };
+        if let Err(problems) = snapshot.validate() {
+            return FunctionResult::error(format!("配置校验失败: {}", problems.join("; ")));
         }
+        let mode = match args.get("mode").and_then(|v| v.as_str()) {
+            Some("replace") => ImportMode::Replace,
+            _ => ImportMode::Merge,
+        };
+        let dry_run = args.get("dry_run").and_then(|v| v.as_bool()).unwrap_or(true);
 
-        // TODO: Implement actual import logic
-        FunctionResult::success(json!({
-            "success": true,
-            "message": "配置导入功能待实现，需要事务支持确保原子性"
-        }))
+        let transfer = ConfigTransfer::new(
+            state.db.clone(),
+            state.resolver.clone(),
+            state.rewrite_engine.clone(),
+            state.upstream_manager.clone(),
+            state.listener_manager.clone(),
+        );
+        match transfer.import(snapshot, mode, dry_run).await {
+            Ok(result) => FunctionResult::success(json!(result)),
+            Err(e) => FunctionResult::error(format!("导入失败: {}", e)),
+        }
     }
 }
 
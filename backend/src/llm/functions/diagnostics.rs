@@ -9,11 +9,25 @@ use super::LlmFunction;
 use crate::llm::types::{FunctionDefinition, FunctionResult};
 use crate::state::AppState;
 use crate::dns::{DnsQuery, RecordType, CacheKey};
-use crate::dns::proxy::{UpstreamServer, UpstreamProtocol};
+use crate::db::UpstreamServer as DbUpstreamServer;
+use crate::dns::proxy::{OutboundConfig, UpstreamServer, UpstreamProtocol};
 use crate::dns::proxy::{
-    UdpDnsClient, DotDnsClient, DohDnsClient, DoqDnsClient, Doh3DnsClient, DnsClient
+    UdpDnsClient, DotDnsClient, DohDnsClient, DoqDnsClient, Doh3DnsClient, RecursiveDnsClient, DnsClient
 };
 
+/// Outbound settings of a stored upstream, so diagnostics take the same path as real queries
+fn outbound_of(db_server: &DbUpstreamServer) -> OutboundConfig {
+    OutboundConfig::from_settings(
+        db_server.proxy.as_deref(),
+        db_server.bind_address.as_deref(),
+        db_server.bind_interface.as_deref(),
+    )
+    .unwrap_or_else(|e| {
+        tracing::warn!("Ignoring invalid outbound settings of upstream {}: {}", db_server.name, e);
+        OutboundConfig::default()
+    })
+}
+
 pub struct TraceDnsResolutionFunction;
 
 #[async_trait]
@@ -157,7 +171,7 @@ impl LlmFunction for TraceDnsResolutionFunction {
                 db_server.address.clone(),
                 protocol,
                 db_server.timeout as u32,
-            );
+            ).with_outbound(outbound_of(&db_server));
 
             let client: Box<dyn DnsClient> = match protocol {
                 UpstreamProtocol::Udp => Box::new(UdpDnsClient::new(server_config)),
@@ -165,6 +179,7 @@ impl LlmFunction for TraceDnsResolutionFunction {
                 UpstreamProtocol::Doh => Box::new(DohDnsClient::new(server_config)),
                 UpstreamProtocol::Doq => Box::new(DoqDnsClient::new(server_config)),
                 UpstreamProtocol::Doh3 => Box::new(Doh3DnsClient::new(server_config)),
+                UpstreamProtocol::Recursive => Box::new(RecursiveDnsClient::new(server_config)),
             };
 
             let query_start = Instant::now();
@@ -252,7 +267,7 @@ impl LlmFunction for TestUpstreamConnectivityFunction {
             db_server.address.clone(),
             protocol,
             db_server.timeout as u32,
-        );
+        ).with_outbound(outbound_of(&db_server));
 
         // 2. Instantiate Client
         let client: Box<dyn DnsClient> = match protocol {
@@ -261,6 +276,7 @@ impl LlmFunction for TestUpstreamConnectivityFunction {
             UpstreamProtocol::Doh => Box::new(DohDnsClient::new(server_config)),
             UpstreamProtocol::Doq => Box::new(DoqDnsClient::new(server_config)),
             UpstreamProtocol::Doh3 => Box::new(Doh3DnsClient::new(server_config)),
+            UpstreamProtocol::Recursive => Box::new(RecursiveDnsClient::new(server_config)),
         };
 
         // 3. Perform Health Check
@@ -337,7 +353,7 @@ impl LlmFunction for CompareUpstreamResponsesFunction {
                         db_server.address.clone(),
                         protocol,
                         db_server.timeout as u32,
-                    );
+                    ).with_outbound(outbound_of(&db_server));
                     
                     let client: Box<dyn DnsClient> = match protocol {
                         UpstreamProtocol::Udp => Box::new(UdpDnsClient::new(server_config)),
@@ -345,6 +361,7 @@ impl LlmFunction for CompareUpstreamResponsesFunction {
                         UpstreamProtocol::Doh => Box::new(DohDnsClient::new(server_config)),
                         UpstreamProtocol::Doq => Box::new(DoqDnsClient::new(server_config)),
                         UpstreamProtocol::Doh3 => Box::new(Doh3DnsClient::new(server_config)),
+                        UpstreamProtocol::Recursive => Box::new(RecursiveDnsClient::new(server_config)),
                     };
 
                     let start = Instant::now();
@@ -499,7 +516,7 @@ impl LlmFunction for QuerySingleUpstreamFunction {
             db_server.address.clone(),
             protocol,
             db_server.timeout as u32,
-        );
+        ).with_outbound(outbound_of(&db_server));
 
         let client: Box<dyn DnsClient> = match protocol {
             UpstreamProtocol::Udp => Box::new(UdpDnsClient::new(server_config)),
@@ -507,6 +524,7 @@ impl LlmFunction for QuerySingleUpstreamFunction {
             UpstreamProtocol::Doh => Box::new(DohDnsClient::new(server_config)),
             UpstreamProtocol::Doq => Box::new(DoqDnsClient::new(server_config)),
             UpstreamProtocol::Doh3 => Box::new(Doh3DnsClient::new(server_config)),
+            UpstreamProtocol::Recursive => Box::new(RecursiveDnsClient::new(server_config)),
         };
 
         let query = DnsQuery::new(domain, record_type);
@@ -4,6 +4,7 @@ use async_trait::async_trait;
 use serde_json::{json, Value};
 
 use super::LlmFunction;
+use crate::db::DataChange;
 use crate::llm::types::{FunctionDefinition, FunctionResult};
 use crate::state::AppState;
 
@@ -103,6 +104,10 @@ impl LlmFunction for BatchAddDnsRecordsFunction {
             }
         }
 
+        if !added.is_empty() {
+            state.db.notify_changed(DataChange::DnsRecords);
+        }
+
         FunctionResult::success(json!({
             "added_count": added.len(),
             "error_count": errors.len(),
@@ -184,7 +189,7 @@ impl LlmFunction for EditDnsRecordFunction {
         }
 
         let query = format!(
-            "UPDATE dns_records SET {} WHERE id = {}",
+            "UPDATE dns_records SET {} WHERE id = {} AND managed = FALSE AND source IS NULL",
             set_clauses.join(", "),
             id
         );
@@ -192,9 +197,10 @@ impl LlmFunction for EditDnsRecordFunction {
         match sqlx::query(&query).execute(state.db.pool()).await {
             Ok(result) => {
                 if result.rows_affected() > 0 {
+                    state.db.notify_changed(DataChange::DnsRecords);
                     FunctionResult::success(json!({"success": true, "id": id, "message": "记录已更新"}))
                 } else {
-                    FunctionResult::error(format!("未找到 ID 为 {} 的记录", id))
+                    FunctionResult::error(format!("未找到 ID 为 {} 的记录，或该记录由配置文件管理或从 hosts 文件同步", id))
                 }
             }
             Err(e) => FunctionResult::error(format!("更新失败: {}", e)),
@@ -230,16 +236,17 @@ impl LlmFunction for DeleteDnsRecordFunction {
             None => return FunctionResult::error("Missing required parameter: id"),
         };
 
-        match sqlx::query("DELETE FROM dns_records WHERE id = ?")
+        match sqlx::query("DELETE FROM dns_records WHERE id = ? AND managed = FALSE AND source IS NULL")
             .bind(id)
             .execute(state.db.pool())
             .await
         {
             Ok(result) => {
                 if result.rows_affected() > 0 {
+                    state.db.notify_changed(DataChange::DnsRecords);
                     FunctionResult::success(json!({"success": true, "id": id, "message": "记录已删除"}))
                 } else {
-                    FunctionResult::error(format!("未找到 ID 为 {} 的记录", id))
+                    FunctionResult::error(format!("未找到 ID 为 {} 的记录，或该记录由配置文件管理或从 hosts 文件同步", id))
                 }
             }
             Err(e) => FunctionResult::error(format!("删除失败: {}", e)),
@@ -86,7 +86,9 @@ impl LlmFunction for GetHelpFunction {
                     {"name": "get_high_frequency_queries", "description": "获取高频查询排行"},
                     {"name": "detect_anomalies", "description": "检测异常流量"},
                     {"name": "get_domain_query_count", "description": "统计指定域名的查询次数"},
-                    {"name": "get_query_ranking", "description": "获取域名查询排名"}
+                    {"name": "get_query_ranking", "description": "获取域名查询排名"},
+                    {"name": "search_domains_by_ip", "description": "被动 DNS 反查：哪些域名解析到过某个 IP/网段"},
+                    {"name": "get_domain_ip_history", "description": "被动 DNS 历史：域名曾经解析到的 IP"}
                 ]
             }),
             Some("settings") => json!({
@@ -107,8 +107,9 @@ impl LlmFunction for GetHighFrequencyQueriesFunction {
         .await
         {
             Ok(results) => {
+                let privacy = state.resolver.privacy().await;
                 let queries: Vec<Value> = results.iter().map(|(name, qtype, count)| {
-                    json!({"domain": name, "type": qtype, "count": count})
+                    json!({"domain": privacy.domain(name), "type": qtype, "count": count})
                 }).collect();
 
                 FunctionResult::success(json!({
@@ -171,6 +172,7 @@ impl LlmFunction for DetectAnomaliesFunction {
         .await
         .unwrap_or_default();
 
+        let privacy = state.resolver.privacy().await;
         let mut anomalies = Vec::new();
         
         if nxdomain > 100 {
@@ -185,7 +187,7 @@ impl LlmFunction for DetectAnomaliesFunction {
             anomalies.push(json!({
                 "type": "client_flood",
                 "severity": "medium",
-                "description": format!("客户端 {} 产生了 {} 次查询", ip, count)
+                "description": format!("客户端 {} 产生了 {} 次查询", privacy.client_ip(&ip), count)
             }));
         }
 
@@ -224,6 +226,9 @@ impl LlmFunction for GetDomainQueryCountFunction {
         };
         let time_range = args.get("time_range").and_then(|v| v.as_str()).unwrap_or("24h");
         let interval = parse_time_range(time_range);
+        // Logged names may be reduced to their registrable domain
+        let privacy = state.resolver.privacy().await;
+        let domain = privacy.domain(domain);
 
         match sqlx::query_as::<_, (i64,)>(
             "SELECT COUNT(*) FROM query_logs WHERE query_name LIKE ? AND created_at > datetime('now', ?)"
@@ -290,8 +295,9 @@ impl LlmFunction for GetQueryRankingFunction {
             .await
         {
             Ok(results) => {
+                let privacy = state.resolver.privacy().await;
                 let ranking: Vec<Value> = results.iter().enumerate().map(|(i, (name, count))| {
-                    json!({"rank": i + 1, "domain": name, "count": count})
+                    json!({"rank": i + 1, "domain": privacy.domain(name), "count": count})
                 }).collect();
 
                 FunctionResult::success(json!({
@@ -8,6 +8,7 @@ pub mod rewrite_rules;
 pub mod upstreams;
 pub mod dns_query;
 pub mod logs;
+pub mod passive_dns;
 pub mod settings;
 pub mod cache;
 pub mod listeners;
@@ -91,6 +92,10 @@ impl FunctionRegistry {
         self.register(Arc::new(logs::GetDomainQueryCountFunction));
         self.register(Arc::new(logs::GetQueryRankingFunction));
         
+        // Passive DNS functions
+        self.register(Arc::new(passive_dns::SearchDomainsByIpFunction));
+        self.register(Arc::new(passive_dns::GetDomainIpHistoryFunction));
+        
         // System settings functions
         self.register(Arc::new(settings::GetSystemStatusFunction));
         self.register(Arc::new(settings::UpdateQueryStrategyFunction));
@@ -0,0 +1,120 @@
+// Passive DNS Functions - Search the history of upstream answers
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::LlmFunction;
+use crate::db::{ip_key_range, PassiveDnsEntry, PassiveDnsFilter};
+use crate::llm::types::{FunctionDefinition, FunctionResult};
+use crate::state::AppState;
+
+/// Find domains that resolved to an IP or CIDR
+pub struct SearchDomainsByIpFunction;
+
+#[async_trait]
+impl LlmFunction for SearchDomainsByIpFunction {
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: "search_domains_by_ip".to_string(),
+            description: "被动 DNS 反查：查找曾经解析到指定 IP 或网段的域名，以及首次/最后出现时间".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "ip": {"type": "string", "description": "IP 地址或 CIDR 网段，如 '203.0.113.10' 或 '203.0.113.0/24'"},
+                    "days": {"type": "integer", "description": "只看最近 N 天内出现过的记录，不填则不限"},
+                    "limit": {"type": "integer", "description": "返回数量，默认 50"}
+                },
+                "required": ["ip"]
+            }),
+        }
+    }
+
+    async fn execute(&self, args: Value, state: &AppState) -> FunctionResult {
+        let Some(ip) = args.get("ip").and_then(|v| v.as_str()) else {
+            return FunctionResult::error("缺少参数 ip");
+        };
+        if let Err(e) = ip_key_range(ip) {
+            return FunctionResult::error(format!("IP 或网段无效: {}", e));
+        }
+
+        let filter = PassiveDnsFilter {
+            ip: Some(ip.to_string()),
+            start_time: since(&args),
+            limit: Some(args.get("limit").and_then(|v| v.as_i64()).unwrap_or(50)),
+            ..Default::default()
+        };
+        match state.db.passive_dns().search(&filter).await {
+            Ok(result) => FunctionResult::success(json!({
+                "ip": ip,
+                "total": result.total,
+                "domains": result.items.iter().map(entry_json).collect::<Vec<_>>()
+            })),
+            Err(e) => FunctionResult::error(format!("查询失败: {}", e)),
+        }
+    }
+}
+
+/// IP (and other answer) history of a domain
+pub struct GetDomainIpHistoryFunction;
+
+#[async_trait]
+impl LlmFunction for GetDomainIpHistoryFunction {
+    fn definition(&self) -> FunctionDefinition {
+        FunctionDefinition {
+            name: "get_domain_ip_history".to_string(),
+            description: "被动 DNS 历史：查看域名曾经解析到的 IP（及 CNAME 等）和首次/最后出现时间".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "domain": {"type": "string", "description": "域名"},
+                    "include_subdomains": {"type": "boolean", "description": "是否包含子域名，默认 false"},
+                    "record_type": {"type": "string", "description": "记录类型，如 A、AAAA、CNAME，不填则全部"},
+                    "days": {"type": "integer", "description": "只看最近 N 天内出现过的记录，不填则不限"},
+                    "limit": {"type": "integer", "description": "返回数量，默认 50"}
+                },
+                "required": ["domain"]
+            }),
+        }
+    }
+
+    async fn execute(&self, args: Value, state: &AppState) -> FunctionResult {
+        let Some(domain) = args.get("domain").and_then(|v| v.as_str()) else {
+            return FunctionResult::error("缺少参数 domain");
+        };
+
+        let filter = PassiveDnsFilter {
+            name: Some(domain.to_string()),
+            include_subdomains: args.get("include_subdomains").and_then(|v| v.as_bool()).unwrap_or(false),
+            record_type: args.get("record_type").and_then(|v| v.as_str()).map(String::from),
+            start_time: since(&args),
+            limit: Some(args.get("limit").and_then(|v| v.as_i64()).unwrap_or(50)),
+            ..Default::default()
+        };
+        match state.db.passive_dns().search(&filter).await {
+            Ok(result) => FunctionResult::success(json!({
+                "domain": domain,
+                "total": result.total,
+                "history": result.items.iter().map(entry_json).collect::<Vec<_>>()
+            })),
+            Err(e) => FunctionResult::error(format!("查询失败: {}", e)),
+        }
+    }
+}
+
+/// Start of the `days` window, if given
+fn since(args: &Value) -> Option<chrono::DateTime<chrono::Utc>> {
+    args.get("days")
+        .and_then(|v| v.as_i64())
+        .map(|days| chrono::Utc::now() - chrono::Duration::days(days))
+}
+
+fn entry_json(entry: &PassiveDnsEntry) -> Value {
+    json!({
+        "domain": entry.name,
+        "type": entry.record_type,
+        "value": entry.value,
+        "first_seen": entry.first_seen.to_rfc3339(),
+        "last_seen": entry.last_seen.to_rfc3339(),
+        "count": entry.count
+    })
+}
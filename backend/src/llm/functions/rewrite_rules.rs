@@ -194,7 +194,7 @@ impl LlmFunction for EditRewriteRuleFunction {
         }
 
         let query = format!(
-            "UPDATE rewrite_rules SET {} WHERE id = {}",
+            "UPDATE rewrite_rules SET {} WHERE id = {} AND managed = FALSE",
             set_clauses.join(", "),
             id
         );
@@ -204,7 +204,7 @@ impl LlmFunction for EditRewriteRuleFunction {
                 if result.rows_affected() > 0 {
                     FunctionResult::success(json!({"success": true, "id": id, "message": "规则已更新"}))
                 } else {
-                    FunctionResult::error(format!("未找到 ID 为 {} 的规则", id))
+                    FunctionResult::error(format!("未找到 ID 为 {} 的规则，或该规则由配置文件管理", id))
                 }
             }
             Err(e) => FunctionResult::error(format!("更新失败: {}", e)),
@@ -240,7 +240,7 @@ impl LlmFunction for DeleteRewriteRuleFunction {
             None => return FunctionResult::error("Missing required parameter: id"),
         };
 
-        match sqlx::query("DELETE FROM rewrite_rules WHERE id = ?")
+        match sqlx::query("DELETE FROM rewrite_rules WHERE id = ? AND managed = FALSE")
             .bind(id)
             .execute(state.db.pool())
             .await
@@ -249,7 +249,7 @@ impl LlmFunction for DeleteRewriteRuleFunction {
                 if result.rows_affected() > 0 {
                     FunctionResult::success(json!({"success": true, "id": id, "message": "规则已删除"}))
                 } else {
-                    FunctionResult::error(format!("未找到 ID 为 {} 的规则", id))
+                    FunctionResult::error(format!("未找到 ID 为 {} 的规则，或该规则由配置文件管理", id))
                 }
             }
             Err(e) => FunctionResult::error(format!("删除失败: {}", e)),
@@ -57,7 +57,7 @@ impl LlmFunction for UpdateQueryStrategyFunction {
                 "properties": {
                     "strategy": {
                         "type": "string",
-                        "enum": ["concurrent", "fastest", "round_robin", "random"]
+                        "enum": ["concurrent", "fastest", "round_robin", "random", "hedged"]
                     }
                 },
                 "required": ["strategy"]
@@ -27,7 +27,7 @@ impl LlmFunction for BatchImportUpstreamsFunction {
                             "properties": {
                                 "name": {"type": "string", "description": "服务器名称"},
                                 "address": {"type": "string", "description": "服务器地址"},
-                                "protocol": {"type": "string", "enum": ["udp", "tcp", "dot", "doh", "doq", "doh3"]},
+                                "protocol": {"type": "string", "enum": ["udp", "tcp", "dot", "doh", "doq", "doh3", "recursive"]},
                                 "timeout": {"type": "integer", "description": "超时时间（毫秒），默认 5000"}
                             },
                             "required": ["name", "address", "protocol"]
@@ -145,13 +145,13 @@ impl LlmFunction for EditUpstreamFunction {
             return FunctionResult::error("No valid updates provided");
         }
 
-        let query = format!("UPDATE upstream_servers SET {} WHERE id = {}", set_clauses.join(", "), id);
+        let query = format!("UPDATE upstream_servers SET {} WHERE id = {} AND managed = FALSE", set_clauses.join(", "), id);
         match sqlx::query(&query).execute(state.db.pool()).await {
             Ok(result) => {
                 if result.rows_affected() > 0 {
                     FunctionResult::success(json!({"success": true, "id": id}))
                 } else {
-                    FunctionResult::error(format!("未找到 ID 为 {} 的服务器", id))
+                    FunctionResult::error(format!("未找到 ID 为 {} 的服务器，或该服务器由配置文件管理", id))
                 }
             }
             Err(e) => FunctionResult::error(format!("更新失败: {}", e)),
@@ -184,12 +184,12 @@ impl LlmFunction for DeleteUpstreamFunction {
             None => return FunctionResult::error("Missing required parameter: id"),
         };
 
-        match sqlx::query("DELETE FROM upstream_servers WHERE id = ?").bind(id).execute(state.db.pool()).await {
+        match sqlx::query("DELETE FROM upstream_servers WHERE id = ? AND managed = FALSE").bind(id).execute(state.db.pool()).await {
             Ok(result) => {
                 if result.rows_affected() > 0 {
                     FunctionResult::success(json!({"success": true, "id": id, "message": "服务器已删除"}))
                 } else {
-                    FunctionResult::error(format!("未找到 ID 为 {} 的服务器", id))
+                    FunctionResult::error(format!("未找到 ID 为 {} 的服务器，或该服务器由配置文件管理", id))
                 }
             }
             Err(e) => FunctionResult::error(format!("删除失败: {}", e)),
@@ -18,5 +18,11 @@ use anyhow::Result;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("--check-config") {
+        let valid = bootstrap::check_config(args.next().map(Into::into))?;
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
     bootstrap::run().await
 }
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 use tokio::time::{interval, Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
+use crate::dns::proxy::{HealthEvent, HealthState};
 use crate::state::AppState;
 use serde_json::json;
 
@@ -19,6 +20,26 @@ impl AlertManager {
 
     pub async fn start(self: Arc<Self>) {
         tracing::info!("AlertManager background task started");
+
+        // Upstream health transitions are alerted as they happen
+        let mut health_events = self.state.upstream_manager.subscribe_health();
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match health_events.recv().await {
+                    Ok(event) => {
+                        if let Err(e) = manager.alert_health_event(&event).await {
+                            tracing::error!("Failed to send health alert: {}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Skipped {} upstream health events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
         tokio::spawn(async move {
             let mut interval = interval(Duration::from_secs(30)); // Check every 30s
             loop {
@@ -91,6 +112,24 @@ impl AlertManager {
         Ok(())
     }
 
+    async fn alert_health_event(&self, event: &HealthEvent) -> anyhow::Result<()> {
+        let config = self.state.db.system_config();
+        if config.get("alert_enabled").await?.unwrap_or_default() != "true" {
+            return Ok(());
+        }
+        let webhook = match config.get("alert_webhook_url").await? {
+            Some(url) if !url.is_empty() => url,
+            _ => return Ok(()),
+        };
+
+        let icon = if event.to == HealthState::Healthy { "✅" } else { "🚨" };
+        let message = format!(
+            "{} **Upstream Health Change**\n\nServer: **{}**\nState: {} → **{}**\nReason: {}",
+            icon, event.server_name, event.from, event.to, event.reason
+        );
+        self.send_alert(&webhook, &message).await
+    }
+
     async fn send_alert(&self, webhook: &str, message: &str) -> anyhow::Result<()> {
         let client = reqwest::Client::new();
         // Webhook payload compatible with Slack, Discord, etc.
@@ -0,0 +1,705 @@
+//! Declarative Config File Sync
+//!
+//! Reconciles the declarative configuration file (see
+//! [`crate::config::declarative`]) into the database: entries are created or
+//! updated and flagged as managed, managed rows that left the file are
+//! deleted. Runs on startup before anything reads the database, and again on
+//! reload (SIGHUP or `POST /api/config-file/reload`), after which the
+//! in-memory engines are refreshed.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+use crate::config::declarative::{record_key, rule_key, setting_value, DeclarativeConfig};
+use crate::db::{serial_base, DataChange, Database, DnsRecord, DnsZone, RewriteRule, ServerListener, UpstreamServer};
+use crate::dns::{DnsResolver, RewriteEngine, UpstreamManager};
+use crate::services::config_reload::{apply_setting, restart_listener};
+use crate::services::listener_manager::ListenerManager;
+
+/// `system_config` key listing the settings owned by the file
+pub const MANAGED_SETTINGS_KEY: &str = "managed_settings";
+
+/// Changes made to one section
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SectionReport {
+    pub created: usize,
+    pub updated: usize,
+    /// Managed rows removed from the file (listeners are released instead)
+    pub deleted: usize,
+}
+
+impl SectionReport {
+    fn is_changed(&self) -> bool {
+        self.created + self.updated + self.deleted > 0
+    }
+}
+
+/// Changes made by one reconciliation
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub upstreams: SectionReport,
+    pub rewrite_rules: SectionReport,
+    pub records: SectionReport,
+    pub zones: SectionReport,
+    pub listeners: SectionReport,
+    pub settings: SectionReport,
+    /// Listeners whose configuration changed and need a restart
+    #[serde(skip)]
+    pub changed_listeners: Vec<String>,
+    /// Settings whose stored value changed
+    #[serde(skip)]
+    pub changed_settings: Vec<String>,
+}
+
+/// State of the config file for the API
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigFileStatus {
+    pub path: String,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub report: Option<SyncReport>,
+    /// Problems of the last failed reload; the previous state stays applied
+    pub errors: Vec<String>,
+}
+
+/// Load, validate and reconcile a config file
+///
+/// Nothing is written when the file has problems.
+pub async fn sync_file(path: &Path, db: &Database) -> Result<SyncReport> {
+    let config = DeclarativeConfig::load(path)?;
+    if let Err(problems) = config.validate() {
+        return Err(anyhow!("{}", problems.join("\n")));
+    }
+    apply(db, &config).await
+}
+
+/// Settings owned by the config file
+pub async fn managed_settings(db: &Database) -> Result<Vec<String>> {
+    Ok(db.system_config().get(MANAGED_SETTINGS_KEY).await?
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default())
+}
+
+/// Reconcile a validated config into the database
+///
+/// All writes share one transaction, so a failure leaves the previous state
+/// untouched. Caches are notified only after the commit.
+pub async fn apply(db: &Database, config: &DeclarativeConfig) -> Result<SyncReport> {
+    let mut report = SyncReport::default();
+    let mut tx = db.pool().begin().await?;
+    if let Some(ref upstreams) = config.upstreams {
+        apply_upstreams(&mut tx, upstreams, &mut report.upstreams).await?;
+    }
+    if let Some(ref rules) = config.rewrite_rules {
+        apply_rewrite_rules(&mut tx, rules, &mut report.rewrite_rules).await?;
+    }
+    if let Some(ref records) = config.records {
+        apply_records(&mut tx, records, &mut report.records).await?;
+    }
+    if let Some(ref zones) = config.zones {
+        apply_zones(&mut tx, zones, &mut report.zones).await?;
+    }
+    if let Some(ref listeners) = config.listeners {
+        report.changed_listeners = apply_listeners(&mut tx, listeners, &mut report.listeners).await?;
+    }
+    report.changed_settings = apply_settings(&mut tx, config, &mut report.settings).await?;
+    tx.commit().await?;
+
+    if report.records.is_changed() {
+        db.notify_changed(DataChange::DnsRecords);
+    }
+    if report.zones.is_changed() {
+        db.notify_changed(DataChange::DnsZones);
+    }
+    if report.rewrite_rules.is_changed() {
+        db.notify_changed(DataChange::RewriteRules);
+    }
+    if report.settings.is_changed() {
+        db.notify_changed(DataChange::SystemConfig);
+    }
+    Ok(report)
+}
+
+/// Trimmed value of an optional text column, `None` when blank
+fn text(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+async fn apply_upstreams(
+    conn: &mut SqliteConnection,
+    entries: &[crate::web::upstreams::CreateUpstreamServerRequest],
+    report: &mut SectionReport,
+) -> Result<()> {
+    let existing: Vec<UpstreamServer> = sqlx::query_as("SELECT * FROM upstream_servers").fetch_all(&mut *conn).await?;
+    let now = Utc::now();
+    let mut wanted = HashSet::new();
+
+    for entry in entries {
+        let server = entry.clone().into_create_upstream_server();
+        wanted.insert(server.name.clone());
+        let Some(row) = existing.iter().find(|row| row.name == server.name) else {
+            sqlx::query(
+                r#"
+                INSERT INTO upstream_servers (name, address, protocol, timeout, enabled, proxy, bind_address, bind_interface, group_name, health_policy, managed, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRUE, ?, ?)
+                "#,
+            )
+            .bind(&server.name)
+            .bind(&server.address)
+            .bind(&server.protocol)
+            .bind(server.timeout)
+            .bind(server.enabled)
+            .bind(text(&server.proxy))
+            .bind(text(&server.bind_address))
+            .bind(text(&server.bind_interface))
+            .bind(text(&server.group_name))
+            .bind(text(&server.health_policy))
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+            report.created += 1;
+            continue;
+        };
+
+        let changed = row.address != server.address
+            || row.protocol != server.protocol
+            || row.timeout != server.timeout
+            || row.enabled != server.enabled
+            || text(&row.proxy) != text(&server.proxy)
+            || text(&row.bind_address) != text(&server.bind_address)
+            || text(&row.bind_interface) != text(&server.bind_interface)
+            || text(&row.group_name) != text(&server.group_name)
+            || text(&row.health_policy) != text(&server.health_policy);
+        if changed {
+            sqlx::query(
+                r#"
+                UPDATE upstream_servers
+                SET address = ?, protocol = ?, timeout = ?, enabled = ?, proxy = ?, bind_address = ?, bind_interface = ?, group_name = ?, health_policy = ?, updated_at = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(&server.address)
+            .bind(&server.protocol)
+            .bind(server.timeout)
+            .bind(server.enabled)
+            .bind(text(&server.proxy))
+            .bind(text(&server.bind_address))
+            .bind(text(&server.bind_interface))
+            .bind(text(&server.group_name))
+            .bind(text(&server.health_policy))
+            .bind(now)
+            .bind(row.id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        if changed || !row.managed {
+            sqlx::query("UPDATE upstream_servers SET managed = TRUE WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+            report.updated += 1;
+        }
+    }
+
+    for row in existing.iter().filter(|row| row.managed && !wanted.contains(&row.name)) {
+        sqlx::query("DELETE FROM upstream_servers WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+        report.deleted += 1;
+    }
+    Ok(())
+}
+
+async fn apply_rewrite_rules(
+    conn: &mut SqliteConnection,
+    entries: &[crate::web::rewrite::CreateRewriteRuleRequest],
+    report: &mut SectionReport,
+) -> Result<()> {
+    let existing: Vec<RewriteRule> = sqlx::query_as("SELECT * FROM rewrite_rules").fetch_all(&mut *conn).await?;
+    let now = Utc::now();
+    let mut wanted = HashSet::new();
+
+    for entry in entries {
+        let rule = entry.clone().into_create_rewrite_rule();
+        let key = rule_key(&rule.pattern, &rule.match_type);
+        wanted.insert(key.clone());
+        let Some(row) = existing.iter().find(|row| rule_key(&row.pattern, &row.match_type) == key) else {
+            sqlx::query(
+                r#"
+                INSERT INTO rewrite_rules (pattern, match_type, action_type, action_value, priority, enabled, description, managed, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, TRUE, ?, ?)
+                "#,
+            )
+            .bind(&rule.pattern)
+            .bind(&rule.match_type)
+            .bind(&rule.action_type)
+            .bind(&rule.action_value)
+            .bind(rule.priority)
+            .bind(rule.enabled)
+            .bind(&rule.description)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+            report.created += 1;
+            continue;
+        };
+
+        let changed = row.action_type != rule.action_type
+            || text(&row.action_value) != text(&rule.action_value)
+            || row.priority != rule.priority
+            || row.enabled != rule.enabled
+            || text(&row.description) != text(&rule.description);
+        if changed {
+            sqlx::query(
+                r#"
+                UPDATE rewrite_rules
+                SET action_type = ?, action_value = ?, priority = ?, enabled = ?, description = ?, updated_at = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(&rule.action_type)
+            .bind(text(&rule.action_value))
+            .bind(rule.priority)
+            .bind(rule.enabled)
+            .bind(text(&rule.description))
+            .bind(now)
+            .bind(row.id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        if changed || !row.managed {
+            sqlx::query("UPDATE rewrite_rules SET managed = TRUE WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+            report.updated += 1;
+        }
+    }
+
+    for row in existing.iter().filter(|row| row.managed && !wanted.contains(&rule_key(&row.pattern, &row.match_type))) {
+        sqlx::query("DELETE FROM rewrite_rules WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+        report.deleted += 1;
+    }
+    Ok(())
+}
+
+async fn apply_records(
+    conn: &mut SqliteConnection,
+    entries: &[crate::web::records::CreateRecordRequest],
+    report: &mut SectionReport,
+) -> Result<()> {
+    // Rows mirrored from a hosts file belong to the hosts sync
+    let existing: Vec<DnsRecord> = sqlx::query_as("SELECT * FROM dns_records WHERE source IS NULL").fetch_all(&mut *conn).await?;
+    let now = Utc::now();
+    let mut wanted = HashSet::new();
+
+    for entry in entries {
+        let record = entry.clone().into_create_dns_record();
+        let key = record_key(&record.name, &record.record_type, &record.value);
+        wanted.insert(key.clone());
+        let Some(row) = existing.iter().find(|row| record_key(&row.name, &row.record_type, &row.value) == key) else {
+            sqlx::query(
+                r#"
+                INSERT INTO dns_records (name, record_type, value, ttl, priority, enabled, managed, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, TRUE, ?, ?)
+                "#,
+            )
+            .bind(&record.name)
+            .bind(&record.record_type)
+            .bind(&record.value)
+            .bind(record.ttl)
+            .bind(record.priority)
+            .bind(record.enabled)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+            report.created += 1;
+            continue;
+        };
+
+        let changed = row.ttl != record.ttl || row.priority != record.priority || row.enabled != record.enabled;
+        if changed {
+            sqlx::query("UPDATE dns_records SET ttl = ?, priority = ?, enabled = ?, updated_at = ? WHERE id = ?")
+                .bind(record.ttl)
+                .bind(record.priority)
+                .bind(record.enabled)
+                .bind(now)
+                .bind(row.id)
+                .execute(&mut *conn)
+                .await?;
+        }
+        if changed || !row.managed {
+            sqlx::query("UPDATE dns_records SET managed = TRUE WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+            report.updated += 1;
+        }
+    }
+
+    for row in existing.iter().filter(|row| row.managed && !wanted.contains(&record_key(&row.name, &row.record_type, &row.value))) {
+        sqlx::query("DELETE FROM dns_records WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+        report.deleted += 1;
+    }
+    Ok(())
+}
+
+async fn apply_zones(
+    conn: &mut SqliteConnection,
+    entries: &[crate::web::zones::ZoneRequest],
+    report: &mut SectionReport,
+) -> Result<()> {
+    let existing: Vec<DnsZone> = sqlx::query_as("SELECT * FROM dns_zones").fetch_all(&mut *conn).await?;
+    let now = Utc::now();
+    let mut wanted = HashSet::new();
+
+    for entry in entries {
+        let zone = entry.clone().into_create_dns_zone().map_err(|e| anyhow!(e.message))?;
+        wanted.insert(zone.origin.clone());
+        let Some(row) = existing.iter().find(|row| row.origin == zone.origin) else {
+            sqlx::query(
+                r#"
+                INSERT INTO dns_zones (origin, primary_ns, admin_email, serial, refresh, retry, expire, minimum, ttl, nameservers, enabled, kind, primaries, transfer_acl, notify, managed, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, TRUE, ?, ?)
+                "#,
+            )
+            .bind(&zone.origin)
+            .bind(&zone.primary_ns)
+            .bind(&zone.admin_email)
+            .bind(serial_base(now) + 1)
+            .bind(zone.refresh)
+            .bind(zone.retry)
+            .bind(zone.expire)
+            .bind(zone.minimum)
+            .bind(zone.ttl)
+            .bind(&zone.nameservers)
+            .bind(zone.enabled)
+            .bind(&zone.kind)
+            .bind(&zone.primaries)
+            .bind(&zone.transfer_acl)
+            .bind(&zone.notify)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+            report.created += 1;
+            continue;
+        };
+
+        // The serial follows the served data and is bumped by the resolver
+        let changed = row.primary_ns != zone.primary_ns
+            || row.admin_email != zone.admin_email
+            || row.refresh != zone.refresh
+            || row.retry != zone.retry
+            || row.expire != zone.expire
+            || row.minimum != zone.minimum
+            || row.ttl != zone.ttl
+            || row.nameservers != zone.nameservers
+            || row.enabled != zone.enabled
+            || row.kind != zone.kind
+            || row.primaries != zone.primaries
+            || row.transfer_acl != zone.transfer_acl
+            || row.notify != zone.notify;
+        if changed {
+            sqlx::query(
+                r#"
+                UPDATE dns_zones
+                SET primary_ns = ?, admin_email = ?, refresh = ?, retry = ?, expire = ?, minimum = ?, ttl = ?, nameservers = ?, enabled = ?,
+                    kind = ?, primaries = ?, transfer_acl = ?, notify = ?, updated_at = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(&zone.primary_ns)
+            .bind(&zone.admin_email)
+            .bind(zone.refresh)
+            .bind(zone.retry)
+            .bind(zone.expire)
+            .bind(zone.minimum)
+            .bind(zone.ttl)
+            .bind(&zone.nameservers)
+            .bind(zone.enabled)
+            .bind(&zone.kind)
+            .bind(&zone.primaries)
+            .bind(&zone.transfer_acl)
+            .bind(&zone.notify)
+            .bind(now)
+            .bind(row.id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        if changed || !row.managed {
+            sqlx::query("UPDATE dns_zones SET managed = TRUE WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+            report.updated += 1;
+        }
+    }
+
+    for row in existing.iter().filter(|row| row.managed && !wanted.contains(&row.origin)) {
+        sqlx::query("DELETE FROM dns_zones WHERE id = ?").bind(row.id).execute(&mut *conn).await?;
+        sqlx::query("DELETE FROM zone_journal WHERE zone_id = ?").bind(row.id).execute(&mut *conn).await?;
+        report.deleted += 1;
+    }
+    Ok(())
+}
+
+/// Returns the protocols whose listener settings changed
+async fn apply_listeners(
+    conn: &mut SqliteConnection,
+    entries: &[crate::config::declarative::DeclarativeListener],
+    report: &mut SectionReport,
+) -> Result<Vec<String>> {
+    let existing: Vec<ServerListener> = sqlx::query_as("SELECT * FROM server_listeners").fetch_all(&mut *conn).await?;
+    let mut changed_protocols = Vec::new();
+    let mut wanted = HashSet::new();
+
+    for entry in entries {
+        let protocol = entry.protocol.to_lowercase();
+        let Some(row) = existing.iter().find(|row| row.protocol == protocol) else {
+            return Err(anyhow!("Listener '{}' does not exist", protocol));
+        };
+        wanted.insert(protocol.clone());
+
+        let changed = row.enabled != entry.enabled
+            || row.bind_address != entry.bind_address
+            || row.port != entry.port
+            || text(&row.tls_cert) != text(&entry.tls_cert)
+            || text(&row.tls_key) != text(&entry.tls_key);
+        if changed {
+            sqlx::query(
+                r#"
+                UPDATE server_listeners
+                SET enabled = ?, bind_address = ?, port = ?, tls_cert = ?, tls_key = ?, updated_at = CURRENT_TIMESTAMP
+                WHERE protocol = ?
+                "#,
+            )
+            .bind(entry.enabled)
+            .bind(&entry.bind_address)
+            .bind(entry.port)
+            .bind(text(&entry.tls_cert))
+            .bind(text(&entry.tls_key))
+            .bind(&protocol)
+            .execute(&mut *conn)
+            .await?;
+            changed_protocols.push(protocol.clone());
+        }
+        if changed || !row.managed {
+            sqlx::query("UPDATE server_listeners SET managed = TRUE WHERE protocol = ?").bind(&protocol).execute(&mut *conn).await?;
+            report.updated += 1;
+        }
+    }
+
+    for row in existing.iter().filter(|row| row.managed && !wanted.contains(&row.protocol)) {
+        sqlx::query("UPDATE server_listeners SET managed = FALSE WHERE protocol = ?").bind(&row.protocol).execute(&mut *conn).await?;
+        report.deleted += 1;
+    }
+    Ok(changed_protocols)
+}
+
+/// Returns the keys whose stored value changed
+///
+/// Settings that leave the file keep their value and become editable again.
+async fn apply_settings(conn: &mut SqliteConnection, config: &DeclarativeConfig, report: &mut SectionReport) -> Result<Vec<String>> {
+    let stored: Vec<(String, String)> = sqlx::query_as("SELECT key, value FROM system_config").fetch_all(&mut *conn).await?;
+    let current = |key: &str| stored.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    let previous: Vec<String> = current(MANAGED_SETTINGS_KEY)
+        .and_then(|value| serde_json::from_str(value).ok())
+        .unwrap_or_default();
+    let settings = config.settings.clone().unwrap_or_default();
+    let mut changed = Vec::new();
+
+    for (key, value) in &settings {
+        let value = setting_value(value);
+        match current(key) {
+            Some(old) if old == value => continue,
+            Some(_) => report.updated += 1,
+            None => report.created += 1,
+        }
+        set_config(conn, key, &value).await?;
+        changed.push(key.clone());
+    }
+
+    let managed: Vec<String> = settings.keys().cloned().collect();
+    report.deleted += previous.iter().filter(|key| !settings.contains_key(*key)).count();
+    if managed != previous {
+        if managed.is_empty() {
+            sqlx::query("DELETE FROM system_config WHERE key = ?").bind(MANAGED_SETTINGS_KEY).execute(&mut *conn).await?;
+        } else {
+            set_config(conn, MANAGED_SETTINGS_KEY, &serde_json::to_string(&managed)?).await?;
+        }
+    }
+    Ok(changed)
+}
+
+async fn set_config(conn: &mut SqliteConnection, key: &str, value: &str) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO system_config (key, value, updated_at)
+        VALUES (?, ?, CURRENT_TIMESTAMP)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Reloads the config file and refreshes the running engines
+pub struct ConfigFileManager {
+    path: PathBuf,
+    db: Arc<Database>,
+    resolver: Arc<DnsResolver>,
+    rewrite_engine: Arc<RewriteEngine>,
+    upstream_manager: Arc<UpstreamManager>,
+    listener_manager: Arc<ListenerManager>,
+    status: RwLock<ConfigFileStatus>,
+}
+
+impl ConfigFileManager {
+    /// Track a file that was already synced on startup
+    pub fn new(
+        path: PathBuf,
+        startup_report: SyncReport,
+        db: Arc<Database>,
+        resolver: Arc<DnsResolver>,
+        rewrite_engine: Arc<RewriteEngine>,
+        upstream_manager: Arc<UpstreamManager>,
+        listener_manager: Arc<ListenerManager>,
+    ) -> Self {
+        let status = ConfigFileStatus {
+            path: path.display().to_string(),
+            last_sync: Some(Utc::now()),
+            report: Some(startup_report),
+            errors: Vec::new(),
+        };
+        Self {
+            path,
+            db,
+            resolver,
+            rewrite_engine,
+            upstream_manager,
+            listener_manager,
+            status: RwLock::new(status),
+        }
+    }
+
+    /// Current sync state
+    pub fn status(&self) -> ConfigFileStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// Re-read the file, reconcile it and refresh the engines
+    ///
+    /// On failure the previous state stays in effect and the problems are
+    /// kept in the status.
+    pub async fn reload(&self) -> Result<SyncReport> {
+        let report = match sync_file(&self.path, &self.db).await {
+            Ok(report) => report,
+            Err(e) => {
+                let mut status = self.status.write().unwrap();
+                status.errors = format!("{:#}", e).lines().map(str::to_string).collect();
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = self.rewrite_engine.reload_rules().await {
+            tracing::warn!("Failed to reload rewrite rules: {}", e);
+        }
+        if let Err(e) = self.upstream_manager.reload_from_db(&self.db).await {
+            tracing::warn!("Failed to reload upstream servers: {}", e);
+        }
+        // Local records and zones follow database writes on their own
+        for protocol in &report.changed_listeners {
+            restart_listener(&self.db, &self.listener_manager, protocol).await;
+        }
+        self.apply_settings(&report.changed_settings).await;
+
+        tracing::info!("Declarative config reloaded from {}", self.path.display());
+        let mut status = self.status.write().unwrap();
+        status.last_sync = Some(Utc::now());
+        status.report = Some(report.clone());
+        status.errors.clear();
+        Ok(report)
+    }
+
+    /// Apply changed settings to the running engines
+    async fn apply_settings(&self, keys: &[String]) {
+        let repo = self.db.system_config();
+        for key in keys {
+            let value = match repo.get(key).await {
+                Ok(value) => value,
+                Err(e) => {
+                    tracing::warn!("Failed to read setting {}: {}", key, e);
+                    continue;
+                }
+            };
+            if let Err(e) = apply_setting(&self.resolver, key, value.as_deref()).await {
+                tracing::warn!("Failed to apply setting {}: {}", key, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[[upstreams]]
+name = "lab"
+address = "10.0.0.53:53"
+protocol = "udp"
+
+[[records]]
+name = "nas.home"
+record_type = "A"
+value = "192.168.1.10"
+
+[[zones]]
+origin = "home.arpa"
+primary_ns = "ns1.home.arpa"
+admin_email = "hostmaster@home.arpa"
+transfer_acl = ["10.0.0.0/8"]
+notify = ["10.0.0.2"]
+
+[settings]
+query_strategy = "fastest"
+"#;
+
+    #[tokio::test]
+    async fn test_apply_marks_rows_managed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        let config = DeclarativeConfig::parse(CONFIG, false).unwrap();
+
+        let report = apply(&db, &config).await.unwrap();
+        assert_eq!(report.upstreams.created, 1);
+        assert_eq!(report.records.created, 1);
+        assert_eq!(report.zones.created, 1);
+        assert_eq!(report.changed_settings, vec!["query_strategy".to_string()]);
+        assert!(db.upstream_servers().list().await.unwrap().iter().any(|row| row.name == "lab" && row.managed));
+        assert!(db.dns_records().list().await.unwrap().iter().all(|row| row.managed));
+        let zone = db.dns_zones().get_by_origin("home.arpa").await.unwrap().unwrap();
+        assert!(zone.managed);
+        assert_eq!(zone.transfer_acl_list(), ["10.0.0.0/8"]);
+        assert_eq!(zone.notify_list(), ["10.0.0.2"]);
+        assert_eq!(managed_settings(&db).await.unwrap(), vec!["query_strategy".to_string()]);
+
+        // A second pass finds nothing to do
+        let report = apply(&db, &config).await.unwrap();
+        assert!(!report.upstreams.is_changed() && !report.records.is_changed() && !report.zones.is_changed());
+        assert!(!report.settings.is_changed());
+    }
+
+    #[tokio::test]
+    async fn test_failed_apply_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        // Listeners are reconciled after upstreams and records
+        let content = format!("{}\n[[listeners]]\nprotocol = \"gopher\"\nport = 70\n", CONFIG);
+        let config = DeclarativeConfig::parse(&content, false).unwrap();
+
+        assert!(apply(&db, &config).await.is_err());
+        assert!(db.upstream_servers().list().await.unwrap().iter().all(|row| row.name != "lab"));
+        assert!(db.dns_records().list().await.unwrap().is_empty());
+        assert!(db.dns_zones().list().await.unwrap().is_empty());
+        assert!(managed_settings(&db).await.unwrap().is_empty());
+    }
+}
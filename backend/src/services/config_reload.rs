@@ -0,0 +1,91 @@
+//! Runtime Config Reload
+//!
+//! Pushes configuration that was written straight to the database (by the
+//! declarative config file sync or a config import) into the running
+//! listeners and engines, the same way the settings API applies its changes.
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+
+use crate::db::Database;
+use crate::dns::proxy::QueryStrategy;
+use crate::dns::{CacheConfig, DnsResolver};
+use crate::services::listener_manager::ListenerManager;
+
+/// Stop a listener and start it again with its stored settings
+pub async fn restart_listener(db: &Database, listener_manager: &ListenerManager, protocol: &str) {
+    listener_manager.stop_listener(protocol).await;
+    match db.server_listeners().get_by_protocol(protocol).await {
+        Ok(Some(listener)) if listener.enabled => {
+            if let Err(e) = listener_manager.start_listener(protocol).await {
+                tracing::error!("Failed to start {} listener: {}", protocol, e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to read {} listener: {}", protocol, e),
+    }
+}
+
+/// Apply a stored `system_config` value to the running engines
+///
+/// `None` means the key was deleted and the default applies. Settings read
+/// from the database on use (alerts, log cleanup, disabled record types,
+/// PTR synthesis) need nothing here.
+pub async fn apply_setting(resolver: &DnsResolver, key: &str, value: Option<&str>) -> Result<()> {
+    fn parse<T: DeserializeOwned + Default>(value: Option<&str>) -> Result<T> {
+        Ok(value.map(serde_json::from_str).transpose()?.unwrap_or_default())
+    }
+
+    match key {
+        "cache_default_ttl" | "cache_max_entries" => {
+            let cache = resolver.cache();
+            let mut config = cache.get_config().await;
+            let defaults = CacheConfig::default();
+            if key == "cache_default_ttl" {
+                config.default_ttl = value.map(str::parse).transpose()?.unwrap_or(defaults.default_ttl);
+            } else {
+                config.max_entries = value.map(str::parse).transpose()?.unwrap_or(defaults.max_entries);
+            }
+            cache.update_config(config).await;
+        }
+        "query_strategy" => {
+            let strategy = match value {
+                Some(value) => QueryStrategy::from_str(value).ok_or_else(|| anyhow!("unknown query strategy '{}'", value))?,
+                None => QueryStrategy::default(),
+            };
+            resolver.proxy().set_strategy(strategy).await;
+        }
+        "anti_pollution" => resolver.proxy().set_anti_pollution(&parse(value)?).await?,
+        "dot_pool" => resolver.proxy().set_dot_pool(parse(value)?).await?,
+        "udp_client" => resolver.proxy().set_udp_client(parse(value)?).await?,
+        "response_filter" => resolver.set_response_filter(&parse(value)?).await?,
+        "query_privacy" => resolver.set_privacy(&parse(value)?).await?,
+        "query_log_writer" => {
+            if let Some(writer) = resolver.log_writer() {
+                writer.set_config(parse(value)?)?;
+            }
+        }
+        "passive_dns" => {
+            if let Some(recorder) = resolver.passive_dns() {
+                recorder.set_config(parse(value)?)?;
+            }
+        }
+        "stats_rollup" => {
+            if let Some(rollup) = resolver.stats_rollup() {
+                rollup.set_config(parse(value)?)?;
+            }
+        }
+        "log_sinks" => {
+            if let Some(sinks) = resolver.log_sinks() {
+                sinks.set_config(parse(value)?).await?;
+            }
+        }
+        "dnstap" => {
+            if let Some(dnstap) = resolver.dnstap() {
+                dnstap.set_config(parse(value)?).await?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,1169 @@
+//! Configuration Export / Import
+//!
+//! A versioned JSON snapshot of all configuration: upstreams, rewrite rules,
+//! local records, authoritative zones, listeners and settings (cache, query strategy and the other
+//! `system_config` settings). LLM provider settings are not included since
+//! they hold API keys.
+//!
+//! Imports are schema-checked and validated before anything is written. They
+//! either merge into the current configuration or replace every section they
+//! contain (sections left out stay as they are), can be previewed as a diff,
+//! and are applied in one transaction with the running engines refreshed
+//! afterwards. Rows owned by the declarative config file are never touched.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::SqliteConnection;
+
+use crate::config::declarative::{record_key, rule_key, setting_value, DeclarativeConfig, DeclarativeListener};
+use crate::db::{
+    serial_base, CreateDnsRecord, CreateDnsZone, CreateRewriteRule, CreateUpstreamServer, DataChange, Database,
+    DnsRecord, DnsZone, RewriteRule, ServerListener, UpstreamServer,
+};
+use crate::dns::proxy::{HealthPolicy, UpstreamManager};
+use crate::dns::{DnsResolver, RewriteEngine};
+use crate::services::config_file::managed_settings;
+use crate::services::config_reload::{apply_setting, restart_listener};
+use crate::services::listener_manager::ListenerManager;
+use crate::web::records::CreateRecordRequest;
+use crate::web::rewrite::CreateRewriteRuleRequest;
+use crate::web::upstreams::CreateUpstreamServerRequest;
+use crate::web::zones::ZoneRequest;
+
+/// Current snapshot format version
+pub const FORMAT_VERSION: u32 = 1;
+
+/// `system_config` keys that are part of the configuration
+pub const SETTING_KEYS: &[&str] = &[
+    "cache_default_ttl",
+    "cache_max_entries",
+    "query_strategy",
+    "anti_pollution",
+    "response_filter",
+    "disabled_record_types",
+    "ptr_synthesis",
+    "dot_pool",
+    "udp_client",
+    "query_log_writer",
+    "passive_dns",
+    "stats_rollup",
+    "log_sinks",
+    "dnstap",
+    "query_privacy",
+    "hosts_sync",
+    "alert_enabled",
+    "alert_webhook_url",
+    "alert_latency_threshold_ms",
+    "log_auto_cleanup_enabled",
+    "log_retention_days",
+];
+
+/// Exported configuration
+///
+/// Entries use the same shape as the create APIs and the declarative file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSnapshot {
+    pub version: u32,
+    #[serde(default)]
+    pub exported_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstreams: Option<Vec<CreateUpstreamServerRequest>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite_rules: Option<Vec<CreateRewriteRuleRequest>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub records: Option<Vec<CreateRecordRequest>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zones: Option<Vec<ZoneRequest>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listeners: Option<Vec<DeclarativeListener>>,
+    /// JSON settings are exported as objects, the others as strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<BTreeMap<String, Value>>,
+}
+
+/// How an import treats existing entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Create and update entries, keep everything else
+    #[default]
+    Merge,
+    /// Also delete entries missing from the sections present in the import
+    Replace,
+}
+
+/// One changed field of an entry
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+/// An entry that would be updated
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryChange {
+    pub key: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Changes to one section, entries named by their identity
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SectionDiff {
+    pub create: Vec<String>,
+    pub update: Vec<EntryChange>,
+    pub delete: Vec<String>,
+    /// Entries owned by the declarative config file, left untouched
+    pub skipped: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl SectionDiff {
+    fn is_empty(&self) -> bool {
+        self.create.is_empty() && self.update.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// Changes an import makes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportDiff {
+    pub upstreams: SectionDiff,
+    pub rewrite_rules: SectionDiff,
+    pub records: SectionDiff,
+    pub zones: SectionDiff,
+    pub listeners: SectionDiff,
+    pub settings: SectionDiff,
+}
+
+/// Result of an import or its dry run
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportResult {
+    pub mode: ImportMode,
+    pub dry_run: bool,
+    pub diff: ImportDiff,
+}
+
+impl ConfigSnapshot {
+    /// Check the version and every entry, collecting all problems
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        if self.version == 0 || self.version > FORMAT_VERSION {
+            problems.push(format!("version: unsupported version {}, expected {}", self.version, FORMAT_VERSION));
+        }
+        for (i, listener) in self.listeners.iter().flatten().enumerate() {
+            if listener.tls_cert_file.is_some() || listener.tls_key_file.is_some() {
+                problems.push(format!("listeners[{}]: tls_cert_file and tls_key_file are only supported in the declarative config file", i));
+            }
+        }
+        for key in self.settings.iter().flat_map(|s| s.keys()) {
+            if !SETTING_KEYS.contains(&key.as_str()) {
+                problems.push(format!("settings.{}: unknown setting", key));
+            }
+        }
+
+        let entries = DeclarativeConfig {
+            upstreams: self.upstreams.clone(),
+            rewrite_rules: self.rewrite_rules.clone(),
+            records: self.records.clone(),
+            zones: self.zones.clone(),
+            listeners: self.listeners.clone(),
+            settings: self.settings.clone(),
+        };
+        if let Err(entry_problems) = entries.validate() {
+            problems.extend(entry_problems);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// Export the current configuration
+pub async fn export(db: &Database) -> Result<ConfigSnapshot> {
+    let upstreams = db.upstream_servers().list().await?
+        .into_iter()
+        .map(|row| CreateUpstreamServerRequest {
+            name: row.name,
+            address: row.address,
+            protocol: row.protocol,
+            timeout: row.timeout,
+            enabled: row.enabled,
+            proxy: row.proxy,
+            bind_address: row.bind_address,
+            bind_interface: row.bind_interface,
+            group_name: row.group_name,
+            health_policy: row.health_policy.and_then(|p| serde_json::from_str::<HealthPolicy>(&p).ok()),
+        })
+        .collect();
+
+    let rewrite_rules = db.rewrite_rules().list().await?
+        .into_iter()
+        .map(|row| CreateRewriteRuleRequest {
+            pattern: row.pattern,
+            match_type: row.match_type,
+            action_type: row.action_type,
+            action_value: row.action_value,
+            priority: row.priority,
+            enabled: row.enabled,
+            description: row.description,
+        })
+        .collect();
+
+    // Records mirrored from a hosts file come back from the file itself
+    let records = db.dns_records().list().await?
+        .into_iter()
+        .filter(|row| row.source.is_none())
+        .map(|row| CreateRecordRequest {
+            name: row.name,
+            record_type: row.record_type,
+            value: row.value,
+            ttl: row.ttl,
+            priority: row.priority,
+            enabled: row.enabled,
+        })
+        .collect();
+
+    // Empty fields are left out; secondaries have no SOA of their own
+    let list = |items: Vec<&str>| (!items.is_empty()).then(|| items.into_iter().map(str::to_string).collect());
+    let zones = db.dns_zones().list().await?
+        .into_iter()
+        .map(|row| ZoneRequest {
+            nameservers: list(row.nameserver_list()),
+            primaries: list(row.primary_list()),
+            transfer_acl: list(row.transfer_acl_list()),
+            notify: list(row.notify_list()),
+            origin: Some(row.origin),
+            primary_ns: Some(row.primary_ns).filter(|v| !v.is_empty()),
+            admin_email: Some(row.admin_email).filter(|v| !v.is_empty()),
+            refresh: Some(row.refresh),
+            retry: Some(row.retry),
+            expire: Some(row.expire),
+            minimum: Some(row.minimum),
+            ttl: Some(row.ttl),
+            enabled: Some(row.enabled),
+            kind: Some(row.kind),
+        })
+        .collect();
+
+    let listeners = db.server_listeners().list().await?
+        .into_iter()
+        .map(|row| DeclarativeListener {
+            protocol: row.protocol,
+            enabled: row.enabled,
+            bind_address: row.bind_address,
+            port: row.port,
+            tls_cert: row.tls_cert,
+            tls_key: row.tls_key,
+            tls_cert_file: None,
+            tls_key_file: None,
+        })
+        .collect();
+
+    let repo = db.system_config();
+    let mut settings = BTreeMap::new();
+    for key in SETTING_KEYS {
+        if let Some(value) = repo.get(key).await? {
+            settings.insert(key.to_string(), export_setting(value));
+        }
+    }
+
+    Ok(ConfigSnapshot {
+        version: FORMAT_VERSION,
+        exported_at: Some(Utc::now()),
+        upstreams: Some(upstreams),
+        rewrite_rules: Some(rewrite_rules),
+        records: Some(records),
+        zones: Some(zones),
+        listeners: Some(listeners),
+        settings: Some(settings),
+    })
+}
+
+/// Stored JSON settings become objects, everything else stays a string
+fn export_setting(value: String) -> Value {
+    match serde_json::from_str::<Value>(&value) {
+        Ok(json) if json.is_object() || json.is_array() => json,
+        _ => Value::String(value),
+    }
+}
+
+/// Engines refreshed after an import
+pub struct ConfigTransfer {
+    db: Arc<Database>,
+    resolver: Arc<DnsResolver>,
+    rewrite_engine: Arc<RewriteEngine>,
+    upstream_manager: Arc<UpstreamManager>,
+    listener_manager: Arc<ListenerManager>,
+}
+
+impl ConfigTransfer {
+    pub fn new(
+        db: Arc<Database>,
+        resolver: Arc<DnsResolver>,
+        rewrite_engine: Arc<RewriteEngine>,
+        upstream_manager: Arc<UpstreamManager>,
+        listener_manager: Arc<ListenerManager>,
+    ) -> Self {
+        Self {
+            db,
+            resolver,
+            rewrite_engine,
+            upstream_manager,
+            listener_manager,
+        }
+    }
+
+    /// Diff a validated snapshot against the database and apply it unless `dry_run`
+    ///
+    /// The diff is computed inside the transaction that applies it, so the
+    /// preview and the applied changes cannot drift apart.
+    pub async fn import(&self, snapshot: ConfigSnapshot, mode: ImportMode, dry_run: bool) -> Result<ImportResult> {
+        let managed = managed_settings(&self.db).await?;
+        let mut tx = self.db.pool().begin().await?;
+        let plan = Plan::build(&mut tx, snapshot, mode, &managed).await?;
+
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            plan.write(&mut tx).await?;
+            tx.commit().await?;
+            self.refresh(&plan).await;
+            tracing::info!("Configuration imported ({:?} mode)", mode);
+        }
+
+        Ok(ImportResult {
+            mode,
+            dry_run,
+            diff: plan.diff,
+        })
+    }
+
+    async fn refresh(&self, plan: &Plan) {
+        let diff = &plan.diff;
+        if !diff.records.is_empty() {
+            self.db.notify_changed(DataChange::DnsRecords);
+        }
+        if !diff.zones.is_empty() {
+            self.db.notify_changed(DataChange::DnsZones);
+        }
+        if !diff.settings.is_empty() {
+            self.db.notify_changed(DataChange::SystemConfig);
+        }
+        if !diff.rewrite_rules.is_empty() {
+            if let Err(e) = self.rewrite_engine.reload_rules().await {
+                tracing::warn!("Failed to reload rewrite rules: {}", e);
+            }
+        }
+        if !diff.upstreams.is_empty() {
+            if let Err(e) = self.upstream_manager.reload_from_db(&self.db).await {
+                tracing::warn!("Failed to reload upstream servers: {}", e);
+            }
+        }
+        for listener in &plan.listeners {
+            restart_listener(&self.db, &self.listener_manager, &listener.protocol).await;
+        }
+        for (key, value) in &plan.settings {
+            if let Err(e) = apply_setting(&self.resolver, key, value.as_deref()).await {
+                tracing::warn!("Failed to apply setting {}: {}", key, e);
+            }
+        }
+    }
+}
+
+/// A row change in one table
+enum Change<T> {
+    Create(T),
+    Update(i64, T),
+    Delete(i64),
+}
+
+/// Writes an import makes, with the matching diff
+struct Plan {
+    upstreams: Vec<Change<CreateUpstreamServer>>,
+    rewrite_rules: Vec<Change<CreateRewriteRule>>,
+    records: Vec<Change<CreateDnsRecord>>,
+    zones: Vec<Change<ZoneFields>>,
+    /// Listeners to update
+    listeners: Vec<DeclarativeListener>,
+    /// Settings to write, `None` deletes
+    settings: Vec<(String, Option<String>)>,
+    diff: ImportDiff,
+}
+
+/// Database row an import entry is matched against
+trait ConfigRow {
+    type Entry: Serialize;
+    fn id(&self) -> i64;
+    fn managed(&self) -> bool;
+    fn entry(&self) -> Self::Entry;
+}
+
+/// Identity used to match entries to rows
+trait Keyed {
+    fn key(&self) -> String;
+}
+
+impl ConfigRow for UpstreamServer {
+    type Entry = CreateUpstreamServer;
+    fn id(&self) -> i64 {
+        self.id
+    }
+    fn managed(&self) -> bool {
+        self.managed
+    }
+    fn entry(&self) -> CreateUpstreamServer {
+        CreateUpstreamServer {
+            name: self.name.clone(),
+            address: self.address.clone(),
+            protocol: self.protocol.clone(),
+            timeout: self.timeout,
+            enabled: self.enabled,
+            proxy: self.proxy.clone(),
+            bind_address: self.bind_address.clone(),
+            bind_interface: self.bind_interface.clone(),
+            group_name: self.group_name.clone(),
+            // Re-serialized so key order and defaults match imported policies
+            health_policy: self.health_policy.as_deref()
+                .and_then(|p| serde_json::from_str::<HealthPolicy>(p).ok())
+                .map(|p| serde_json::to_string(&p).unwrap_or_default()),
+        }
+    }
+}
+
+impl Keyed for CreateUpstreamServer {
+    fn key(&self) -> String {
+        self.name.trim().to_string()
+    }
+}
+
+impl ConfigRow for RewriteRule {
+    type Entry = CreateRewriteRule;
+    fn id(&self) -> i64 {
+        self.id
+    }
+    fn managed(&self) -> bool {
+        self.managed
+    }
+    fn entry(&self) -> CreateRewriteRule {
+        CreateRewriteRule {
+            pattern: self.pattern.clone(),
+            match_type: self.match_type.clone(),
+            action_type: self.action_type.clone(),
+            action_value: self.action_value.clone(),
+            priority: self.priority,
+            enabled: self.enabled,
+            description: self.description.clone(),
+        }
+    }
+}
+
+impl Keyed for CreateRewriteRule {
+    fn key(&self) -> String {
+        let (pattern, match_type) = rule_key(&self.pattern, &self.match_type);
+        format!("{} {}", match_type, pattern)
+    }
+}
+
+impl ConfigRow for DnsRecord {
+    type Entry = CreateDnsRecord;
+    fn id(&self) -> i64 {
+        self.id
+    }
+    /// Rows mirrored from a hosts file are owned by the sync as well
+    fn managed(&self) -> bool {
+        self.managed || self.source.is_some()
+    }
+    fn entry(&self) -> CreateDnsRecord {
+        CreateDnsRecord {
+            name: self.name.clone(),
+            record_type: self.record_type.clone(),
+            value: self.value.clone(),
+            ttl: self.ttl,
+            priority: self.priority,
+            enabled: self.enabled,
+        }
+    }
+}
+
+impl Keyed for CreateDnsRecord {
+    fn key(&self) -> String {
+        let (name, record_type, value) = record_key(&self.name, &self.record_type, &self.value);
+        format!("{} {} {}", name, record_type, value)
+    }
+}
+
+/// Zone settings compared and written by an import
+///
+/// The serial follows the served data and is never imported.
+#[derive(Serialize)]
+struct ZoneFields {
+    origin: String,
+    primary_ns: String,
+    admin_email: String,
+    refresh: i32,
+    retry: i32,
+    expire: i32,
+    minimum: i32,
+    ttl: i32,
+    nameservers: String,
+    enabled: bool,
+    kind: String,
+    primaries: String,
+    transfer_acl: String,
+    notify: String,
+}
+
+impl From<CreateDnsZone> for ZoneFields {
+    fn from(zone: CreateDnsZone) -> Self {
+        Self {
+            origin: zone.origin,
+            primary_ns: zone.primary_ns,
+            admin_email: zone.admin_email,
+            refresh: zone.refresh,
+            retry: zone.retry,
+            expire: zone.expire,
+            minimum: zone.minimum,
+            ttl: zone.ttl,
+            nameservers: zone.nameservers,
+            enabled: zone.enabled,
+            kind: zone.kind,
+            primaries: zone.primaries,
+            transfer_acl: zone.transfer_acl,
+            notify: zone.notify,
+        }
+    }
+}
+
+impl ConfigRow for DnsZone {
+    type Entry = ZoneFields;
+    fn id(&self) -> i64 {
+        self.id
+    }
+    fn managed(&self) -> bool {
+        self.managed
+    }
+    fn entry(&self) -> ZoneFields {
+        ZoneFields {
+            origin: self.origin.clone(),
+            primary_ns: self.primary_ns.clone(),
+            admin_email: self.admin_email.clone(),
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minimum: self.minimum,
+            ttl: self.ttl,
+            nameservers: self.nameservers.clone(),
+            enabled: self.enabled,
+            kind: self.kind.clone(),
+            primaries: self.primaries.clone(),
+            transfer_acl: self.transfer_acl.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+}
+
+impl Keyed for ZoneFields {
+    fn key(&self) -> String {
+        self.origin.clone()
+    }
+}
+
+/// Listener settings compared by an import
+#[derive(Serialize)]
+struct ListenerFields<'a> {
+    enabled: bool,
+    bind_address: &'a str,
+    port: i32,
+    tls_cert: Option<&'a str>,
+    tls_key: Option<&'a str>,
+}
+
+impl<'a> From<&'a ServerListener> for ListenerFields<'a> {
+    fn from(row: &'a ServerListener) -> Self {
+        Self {
+            enabled: row.enabled,
+            bind_address: &row.bind_address,
+            port: row.port,
+            tls_cert: row.tls_cert.as_deref(),
+            tls_key: row.tls_key.as_deref(),
+        }
+    }
+}
+
+impl<'a> From<&'a DeclarativeListener> for ListenerFields<'a> {
+    fn from(entry: &'a DeclarativeListener) -> Self {
+        Self {
+            enabled: entry.enabled,
+            bind_address: &entry.bind_address,
+            port: entry.port,
+            tls_cert: entry.tls_cert.as_deref(),
+            tls_key: entry.tls_key.as_deref(),
+        }
+    }
+}
+
+impl Plan {
+    async fn build(conn: &mut SqliteConnection, snapshot: ConfigSnapshot, mode: ImportMode, managed_settings: &[String]) -> Result<Self> {
+        let mut diff = ImportDiff::default();
+
+        let upstreams = match snapshot.upstreams {
+            Some(entries) => {
+                let rows: Vec<UpstreamServer> = sqlx::query_as("SELECT * FROM upstream_servers").fetch_all(&mut *conn).await?;
+                let entries = entries.into_iter().map(|e| e.into_create_upstream_server()).collect();
+                plan_section(&rows, entries, mode, &mut diff.upstreams)
+            }
+            None => Vec::new(),
+        };
+        let rewrite_rules = match snapshot.rewrite_rules {
+            Some(entries) => {
+                let rows: Vec<RewriteRule> = sqlx::query_as("SELECT * FROM rewrite_rules").fetch_all(&mut *conn).await?;
+                let entries = entries.into_iter().map(|e| e.into_create_rewrite_rule()).collect();
+                plan_section(&rows, entries, mode, &mut diff.rewrite_rules)
+            }
+            None => Vec::new(),
+        };
+        let records = match snapshot.records {
+            Some(entries) => {
+                let rows: Vec<DnsRecord> = sqlx::query_as("SELECT * FROM dns_records").fetch_all(&mut *conn).await?;
+                let entries = entries.into_iter().map(|e| e.into_create_dns_record()).collect();
+                plan_section(&rows, entries, mode, &mut diff.records)
+            }
+            None => Vec::new(),
+        };
+        let zones = match snapshot.zones {
+            Some(entries) => {
+                let rows: Vec<DnsZone> = sqlx::query_as("SELECT * FROM dns_zones").fetch_all(&mut *conn).await?;
+                let entries = entries
+                    .into_iter()
+                    .map(|e| e.into_create_dns_zone().map(ZoneFields::from).map_err(|e| anyhow::anyhow!(e.message)))
+                    .collect::<Result<Vec<_>>>()?;
+                plan_section(&rows, entries, mode, &mut diff.zones)
+            }
+            None => Vec::new(),
+        };
+
+        // Listeners always exist, so they are only ever updated
+        let mut listeners = Vec::new();
+        if let Some(entries) = snapshot.listeners {
+            let rows: Vec<ServerListener> = sqlx::query_as("SELECT * FROM server_listeners").fetch_all(&mut *conn).await?;
+            for mut entry in entries {
+                entry.protocol = entry.protocol.to_lowercase();
+                let Some(row) = rows.iter().find(|row| row.protocol == entry.protocol) else {
+                    anyhow::bail!("Listener '{}' does not exist", entry.protocol);
+                };
+                if row.managed {
+                    diff.listeners.skipped.push(entry.protocol.clone());
+                    continue;
+                }
+                let changes = field_changes(&ListenerFields::from(row), &ListenerFields::from(&entry));
+                if changes.is_empty() {
+                    diff.listeners.unchanged += 1;
+                } else {
+                    diff.listeners.update.push(EntryChange { key: entry.protocol.clone(), changes });
+                    listeners.push(entry);
+                }
+            }
+        }
+
+        let mut settings = Vec::new();
+        if let Some(entries) = snapshot.settings {
+            let rows: Vec<(String, String)> = sqlx::query_as("SELECT key, value FROM system_config").fetch_all(&mut *conn).await?;
+            let current = |key: &str| rows.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+            let section = &mut diff.settings;
+
+            for (key, value) in &entries {
+                let value = setting_value(value);
+                if managed_settings.contains(key) {
+                    section.skipped.push(key.clone());
+                    continue;
+                }
+                match current(key) {
+                    Some(old) if old == value => {
+                        section.unchanged += 1;
+                        continue;
+                    }
+                    Some(old) => section.update.push(EntryChange {
+                        key: key.clone(),
+                        changes: vec![FieldChange {
+                            field: "value".to_string(),
+                            from: export_setting(old),
+                            to: export_setting(value.clone()),
+                        }],
+                    }),
+                    None => section.create.push(key.clone()),
+                }
+                settings.push((key.clone(), Some(value)));
+            }
+
+            if mode == ImportMode::Replace {
+                for key in SETTING_KEYS.iter().filter(|key| !entries.contains_key(**key) && current(key).is_some()) {
+                    if managed_settings.iter().any(|m| m == key) {
+                        section.skipped.push(key.to_string());
+                    } else {
+                        section.delete.push(key.to_string());
+                        settings.push((key.to_string(), None));
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            upstreams,
+            rewrite_rules,
+            records,
+            zones,
+            listeners,
+            settings,
+            diff,
+        })
+    }
+
+    async fn write(&self, conn: &mut SqliteConnection) -> Result<()> {
+        let now = Utc::now();
+
+        for change in &self.upstreams {
+            match change {
+                Change::Create(server) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO upstream_servers (name, address, protocol, timeout, enabled, proxy, bind_address, bind_interface, group_name, health_policy, created_at, updated_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(&server.name)
+                    .bind(&server.address)
+                    .bind(&server.protocol)
+                    .bind(server.timeout)
+                    .bind(server.enabled)
+                    .bind(non_empty(&server.proxy))
+                    .bind(non_empty(&server.bind_address))
+                    .bind(non_empty(&server.bind_interface))
+                    .bind(non_empty(&server.group_name))
+                    .bind(non_empty(&server.health_policy))
+                    .bind(now)
+                    .bind(now)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Update(id, server) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE upstream_servers
+                        SET name = ?, address = ?, protocol = ?, timeout = ?, enabled = ?, proxy = ?, bind_address = ?, bind_interface = ?, group_name = ?, health_policy = ?, updated_at = ?
+                        WHERE id = ?
+                        "#,
+                    )
+                    .bind(&server.name)
+                    .bind(&server.address)
+                    .bind(&server.protocol)
+                    .bind(server.timeout)
+                    .bind(server.enabled)
+                    .bind(non_empty(&server.proxy))
+                    .bind(non_empty(&server.bind_address))
+                    .bind(non_empty(&server.bind_interface))
+                    .bind(non_empty(&server.group_name))
+                    .bind(non_empty(&server.health_policy))
+                    .bind(now)
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Delete(id) => {
+                    sqlx::query("DELETE FROM upstream_servers WHERE id = ?").bind(id).execute(&mut *conn).await?;
+                }
+            }
+        }
+
+        for change in &self.rewrite_rules {
+            match change {
+                Change::Create(rule) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO rewrite_rules (pattern, match_type, action_type, action_value, priority, enabled, description, created_at, updated_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(&rule.pattern)
+                    .bind(&rule.match_type)
+                    .bind(&rule.action_type)
+                    .bind(&rule.action_value)
+                    .bind(rule.priority)
+                    .bind(rule.enabled)
+                    .bind(&rule.description)
+                    .bind(now)
+                    .bind(now)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Update(id, rule) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE rewrite_rules
+                        SET pattern = ?, match_type = ?, action_type = ?, action_value = ?, priority = ?, enabled = ?, description = ?, updated_at = ?
+                        WHERE id = ?
+                        "#,
+                    )
+                    .bind(&rule.pattern)
+                    .bind(&rule.match_type)
+                    .bind(&rule.action_type)
+                    .bind(&rule.action_value)
+                    .bind(rule.priority)
+                    .bind(rule.enabled)
+                    .bind(&rule.description)
+                    .bind(now)
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Delete(id) => {
+                    sqlx::query("DELETE FROM rewrite_rules WHERE id = ?").bind(id).execute(&mut *conn).await?;
+                }
+            }
+        }
+
+        for change in &self.records {
+            match change {
+                Change::Create(record) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO dns_records (name, record_type, value, ttl, priority, enabled, created_at, updated_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(&record.name)
+                    .bind(&record.record_type)
+                    .bind(&record.value)
+                    .bind(record.ttl)
+                    .bind(record.priority)
+                    .bind(record.enabled)
+                    .bind(now)
+                    .bind(now)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Update(id, record) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE dns_records
+                        SET name = ?, record_type = ?, value = ?, ttl = ?, priority = ?, enabled = ?, updated_at = ?
+                        WHERE id = ?
+                        "#,
+                    )
+                    .bind(&record.name)
+                    .bind(&record.record_type)
+                    .bind(&record.value)
+                    .bind(record.ttl)
+                    .bind(record.priority)
+                    .bind(record.enabled)
+                    .bind(now)
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Delete(id) => {
+                    sqlx::query("DELETE FROM dns_records WHERE id = ?").bind(id).execute(&mut *conn).await?;
+                }
+            }
+        }
+
+        for change in &self.zones {
+            match change {
+                Change::Create(zone) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO dns_zones (origin, primary_ns, admin_email, serial, refresh, retry, expire, minimum, ttl, nameservers, enabled, kind, primaries, transfer_acl, notify, created_at, updated_at)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(&zone.origin)
+                    .bind(&zone.primary_ns)
+                    .bind(&zone.admin_email)
+                    .bind(serial_base(now) + 1)
+                    .bind(zone.refresh)
+                    .bind(zone.retry)
+                    .bind(zone.expire)
+                    .bind(zone.minimum)
+                    .bind(zone.ttl)
+                    .bind(&zone.nameservers)
+                    .bind(zone.enabled)
+                    .bind(&zone.kind)
+                    .bind(&zone.primaries)
+                    .bind(&zone.transfer_acl)
+                    .bind(&zone.notify)
+                    .bind(now)
+                    .bind(now)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Update(id, zone) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE dns_zones
+                        SET primary_ns = ?, admin_email = ?, refresh = ?, retry = ?, expire = ?, minimum = ?, ttl = ?, nameservers = ?, enabled = ?,
+                            kind = ?, primaries = ?, transfer_acl = ?, notify = ?, updated_at = ?
+                        WHERE id = ?
+                        "#,
+                    )
+                    .bind(&zone.primary_ns)
+                    .bind(&zone.admin_email)
+                    .bind(zone.refresh)
+                    .bind(zone.retry)
+                    .bind(zone.expire)
+                    .bind(zone.minimum)
+                    .bind(zone.ttl)
+                    .bind(&zone.nameservers)
+                    .bind(zone.enabled)
+                    .bind(&zone.kind)
+                    .bind(&zone.primaries)
+                    .bind(&zone.transfer_acl)
+                    .bind(&zone.notify)
+                    .bind(now)
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                Change::Delete(id) => {
+                    sqlx::query("DELETE FROM dns_zones WHERE id = ?").bind(id).execute(&mut *conn).await?;
+                    sqlx::query("DELETE FROM zone_journal WHERE zone_id = ?").bind(id).execute(&mut *conn).await?;
+                }
+            }
+        }
+
+        for listener in &self.listeners {
+            sqlx::query(
+                r#"
+                UPDATE server_listeners
+                SET enabled = ?, bind_address = ?, port = ?, tls_cert = ?, tls_key = ?, updated_at = CURRENT_TIMESTAMP
+                WHERE protocol = ?
+                "#,
+            )
+            .bind(listener.enabled)
+            .bind(&listener.bind_address)
+            .bind(listener.port)
+            .bind(non_empty(&listener.tls_cert))
+            .bind(non_empty(&listener.tls_key))
+            .bind(&listener.protocol)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        for (key, value) in &self.settings {
+            match value {
+                Some(value) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO system_config (key, value, updated_at)
+                        VALUES (?, ?, CURRENT_TIMESTAMP)
+                        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP
+                        "#,
+                    )
+                    .bind(key)
+                    .bind(value)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                None => {
+                    sqlx::query("DELETE FROM system_config WHERE key = ?").bind(key).execute(&mut *conn).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Match entries to rows by identity and record the changes
+fn plan_section<R>(rows: &[R], entries: Vec<R::Entry>, mode: ImportMode, diff: &mut SectionDiff) -> Vec<Change<R::Entry>>
+where
+    R: ConfigRow,
+    R::Entry: Keyed,
+{
+    let mut changes = Vec::new();
+    let mut wanted = HashSet::new();
+
+    for entry in entries {
+        let key = entry.key();
+        wanted.insert(key.clone());
+        match rows.iter().find(|row| row.entry().key() == key) {
+            None => {
+                diff.create.push(key);
+                changes.push(Change::Create(entry));
+            }
+            Some(row) if row.managed() => diff.skipped.push(key),
+            Some(row) => {
+                let fields = field_changes(&row.entry(), &entry);
+                if fields.is_empty() {
+                    diff.unchanged += 1;
+                } else {
+                    diff.update.push(EntryChange { key, changes: fields });
+                    changes.push(Change::Update(row.id(), entry));
+                }
+            }
+        }
+    }
+
+    if mode == ImportMode::Replace {
+        for row in rows {
+            let key = row.entry().key();
+            if wanted.contains(&key) {
+                continue;
+            }
+            if row.managed() {
+                diff.skipped.push(key);
+            } else {
+                diff.delete.push(key);
+                changes.push(Change::Delete(row.id()));
+            }
+        }
+    }
+    changes
+}
+
+/// Fields that differ, with blank strings treated as unset
+fn field_changes<T: Serialize>(current: &T, wanted: &T) -> Vec<FieldChange> {
+    let (Value::Object(current), Value::Object(wanted)) = (normalized(current), normalized(wanted)) else {
+        return Vec::new();
+    };
+    wanted
+        .into_iter()
+        .filter_map(|(field, to)| {
+            let from = current.get(&field).cloned().unwrap_or(Value::Null);
+            (from != to).then_some(FieldChange { field, from, to })
+        })
+        .collect()
+}
+
+fn normalized<T: Serialize>(value: &T) -> Value {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(fields)) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(field, value)| match value {
+                    Value::String(s) if s.trim().is_empty() => (field, Value::Null),
+                    Value::String(s) => (field, Value::String(s.trim().to_string())),
+                    other => (field, other),
+                })
+                .collect(),
+        ),
+        Ok(other) => other,
+        Err(_) => Value::Null,
+    }
+}
+
+/// Blank optional text is stored as NULL
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, value: &str, ttl: i32, managed: bool) -> DnsRecord {
+        DnsRecord {
+            id: 1,
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            value: value.to_string(),
+            ttl,
+            priority: 0,
+            enabled: true,
+            managed,
+            source: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn entry(name: &str, value: &str, ttl: i32) -> CreateDnsRecord {
+        CreateDnsRecord {
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            value: value.to_string(),
+            ttl,
+            priority: 0,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_plan_section_modes() {
+        let rows = vec![
+            record("a.home", "10.0.0.1", 300, false),
+            record("b.home", "10.0.0.2", 300, false),
+            record("c.home", "10.0.0.3", 300, true),
+        ];
+        let entries = || vec![entry("A.home.", "10.0.0.1", 600), entry("d.home", "10.0.0.4", 300)];
+
+        let mut diff = SectionDiff::default();
+        let changes = plan_section(&rows, entries(), ImportMode::Merge, &mut diff);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(diff.create, vec!["d.home A 10.0.0.4"]);
+        assert_eq!(diff.update.len(), 1);
+        let fields: Vec<_> = diff.update[0].changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, vec!["name", "ttl"]);
+        assert!(diff.delete.is_empty());
+
+        let mut diff = SectionDiff::default();
+        let changes = plan_section(&rows, entries(), ImportMode::Replace, &mut diff);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(diff.delete, vec!["b.home A 10.0.0.2"]);
+        assert_eq!(diff.skipped, vec!["c.home A 10.0.0.3"]);
+    }
+
+    #[test]
+    fn test_snapshot_schema() {
+        let snapshot: ConfigSnapshot = serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "records": [{"name": "x.home", "record_type": "A", "value": "not-an-ip"}],
+            "settings": {"query_strategy": "fastest", "admin_password": "x"}
+        }))
+        .unwrap();
+        let problems = snapshot.validate().unwrap_err();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("version"));
+        assert!(problems[1].starts_with("settings.admin_password"));
+        assert!(problems[2].starts_with("records[0] (value)"));
+
+        assert!(serde_json::from_value::<ConfigSnapshot>(serde_json::json!({"version": 1, "plugins": []})).is_err());
+        assert_eq!(export_setting("[\"AAAA\"]".to_string()), serde_json::json!(["AAAA"]));
+        assert_eq!(export_setting("120".to_string()), Value::String("120".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_exported_zones_import_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap();
+        let mut zone = CreateDnsZone::new("home.arpa".to_string(), "ns1.home.arpa".to_string(), "hostmaster.home.arpa".to_string());
+        zone.nameservers = "ns1.home.arpa,ns2.home.arpa".to_string();
+        zone.transfer_acl = "10.0.0.0/8".to_string();
+        db.dns_zones().create(zone).await.unwrap();
+        let mut secondary = CreateDnsZone::new("corp.example".to_string(), String::new(), String::new());
+        secondary.kind = "secondary".to_string();
+        secondary.primaries = "192.0.2.1".to_string();
+        db.dns_zones().create(secondary).await.unwrap();
+
+        let mut snapshot = export(&db).await.unwrap();
+        assert!(snapshot.validate().is_ok(), "{:?}", snapshot.validate());
+        let mut conn = db.pool().acquire().await.unwrap();
+        let plan = Plan::build(&mut conn, snapshot.clone(), ImportMode::Replace, &[]).await.unwrap();
+        assert!(plan.zones.is_empty());
+        assert_eq!(plan.diff.zones.unchanged, 2);
+
+        // Zones are exported by origin
+        let zones = snapshot.zones.as_mut().unwrap();
+        zones[0].primaries = Some(vec!["192.0.2.2".to_string()]);
+        zones[1].notify = Some(vec!["10.0.0.2".to_string()]);
+        let plan = Plan::build(&mut conn, snapshot, ImportMode::Merge, &[]).await.unwrap();
+        let updates: Vec<_> = plan.diff.zones.update.iter().map(|u| (u.key.as_str(), u.changes[0].field.as_str())).collect();
+        assert_eq!(updates, [("corp.example", "primaries"), ("home.arpa", "notify")]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Hosts File Sync
+//!
+//! Keeps local records in step with a hosts file. The file is checked every
+//! `interval_secs` and whenever a setting changes; its A/AAAA records replace
+//! the rows with `source = 'hosts'` in one transaction. Turning the sync off
+//! removes those rows, while a file that cannot be read leaves them as they
+//! are.
+
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+use crate::db::{CreateDnsRecord, DataChange, Database, SourceSyncReport};
+use crate::dns::{parse_hosts, LineError};
+
+/// `system_config` key of the settings
+pub const CONFIG_KEY: &str = "hosts_sync";
+
+/// `dns_records.source` of the synced rows
+pub const SOURCE: &str = "hosts";
+
+/// Parse errors kept for the status
+const MAX_STATUS_ERRORS: usize = 50;
+
+/// Hosts file sync settings (stored as JSON in `system_config`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostsSyncConfig {
+    pub enabled: bool,
+    /// Hosts file to mirror
+    pub path: String,
+    /// Seconds between checks of the file
+    pub interval_secs: u64,
+    /// TTL of the synced records
+    pub ttl: u32,
+}
+
+impl Default for HostsSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "/etc/hosts".to_string(),
+            interval_secs: 30,
+            ttl: 300,
+        }
+    }
+}
+
+impl HostsSyncConfig {
+    /// Check that the settings are within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if self.path.trim().is_empty() {
+            return Err(anyhow!("path cannot be empty"));
+        }
+        if !(5..=86400).contains(&self.interval_secs) {
+            return Err(anyhow!("interval_secs must be between 5 and 86400"));
+        }
+        if self.ttl > i32::MAX as u32 {
+            return Err(anyhow!("ttl is too large"));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of the last sync
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HostsSyncStatus {
+    pub last_sync: Option<DateTime<Utc>>,
+    /// Records read from the file
+    pub records: usize,
+    /// Lines of the file that were skipped
+    pub errors: Vec<LineError>,
+    /// Why the last sync failed, if it did
+    pub last_error: Option<String>,
+}
+
+/// Background sync of a hosts file into local records
+pub struct HostsSync {
+    db: Arc<Database>,
+    status: RwLock<HostsSyncStatus>,
+}
+
+impl HostsSync {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self {
+            db,
+            status: RwLock::new(HostsSyncStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> HostsSyncStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// Read the stored settings, falling back to the defaults
+    pub async fn load_config(db: &Database) -> HostsSyncConfig {
+        match db.system_config().get(CONFIG_KEY).await {
+            Ok(Some(value)) => serde_json::from_str(&value).unwrap_or_else(|e| {
+                tracing::warn!("Invalid hosts sync settings, using defaults: {}", e);
+                HostsSyncConfig::default()
+            }),
+            Ok(None) => HostsSyncConfig::default(),
+            Err(e) => {
+                tracing::warn!("Failed to read hosts sync settings: {}", e);
+                HostsSyncConfig::default()
+            }
+        }
+    }
+
+    /// Read the hosts file and replace the synced records with its entries
+    pub async fn sync(&self, config: &HostsSyncConfig) -> Result<SourceSyncReport> {
+        let result = self.read_and_apply(config).await;
+        let mut status = self.status.write().unwrap();
+        match result {
+            Ok((report, records, errors)) => {
+                *status = HostsSyncStatus {
+                    last_sync: Some(Utc::now()),
+                    records,
+                    errors,
+                    last_error: None,
+                };
+                Ok(report)
+            }
+            Err(e) => {
+                status.last_error = Some(format!("{:#}", e));
+                Err(e)
+            }
+        }
+    }
+
+    async fn read_and_apply(&self, config: &HostsSyncConfig) -> Result<(SourceSyncReport, usize, Vec<LineError>)> {
+        let content = tokio::fs::read_to_string(&config.path)
+            .await
+            .with_context(|| format!("Failed to read {}", config.path))?;
+        let mut parsed = parse_hosts(&content, config.ttl);
+        parsed.errors.truncate(MAX_STATUS_ERRORS);
+
+        let count = parsed.records.len();
+        let records = parsed.records
+            .into_iter()
+            .map(|r| CreateDnsRecord {
+                name: r.name,
+                record_type: r.record_type,
+                value: r.value,
+                ttl: r.ttl as i32,
+                priority: 0,
+                enabled: true,
+            })
+            .collect();
+        let report = self.db.dns_records().sync_source(SOURCE, records).await?;
+        Ok((report, count, parsed.errors))
+    }
+
+    /// Remove every synced record
+    async fn clear(&self) -> Result<SourceSyncReport> {
+        let report = self.db.dns_records().sync_source(SOURCE, Vec::new()).await?;
+        *self.status.write().unwrap() = HostsSyncStatus::default();
+        Ok(report)
+    }
+
+    /// Start the background task
+    pub fn start(self: Arc<Self>) -> JoinHandle<()> {
+        let mut changes = self.db.subscribe_changes();
+        tokio::spawn(async move {
+            // Settings and file fingerprint of the last sync
+            let mut last: Option<(HostsSyncConfig, Option<(SystemTime, u64)>)> = None;
+            let mut cleared = false;
+
+            loop {
+                let config = Self::load_config(&self.db).await;
+                if config.enabled {
+                    cleared = false;
+                    let fingerprint = tokio::fs::metadata(&config.path)
+                        .await
+                        .ok()
+                        .and_then(|m| Some((m.modified().ok()?, m.len())));
+                    let state = (config.clone(), fingerprint);
+                    if last.as_ref() != Some(&state) {
+                        match self.sync(&config).await {
+                            Ok(report) if report.changed() => tracing::info!(
+                                "Synced {}: {} created, {} updated, {} deleted",
+                                config.path, report.created, report.updated, report.deleted
+                            ),
+                            Ok(_) => {}
+                            Err(e) => tracing::warn!("Hosts file sync failed: {:#}", e),
+                        }
+                        last = Some(state);
+                    }
+                } else if !cleared {
+                    match self.clear().await {
+                        Ok(report) if report.deleted > 0 => {
+                            tracing::info!("Hosts sync disabled, removed {} synced records", report.deleted)
+                        }
+                        Ok(_) => {}
+                        Err(e) => tracing::warn!("Failed to remove synced hosts records: {}", e),
+                    }
+                    cleared = true;
+                    last = None;
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(config.interval_secs.max(1))) => {}
+                    _ = settings_changed(&mut changes) => {}
+                }
+            }
+        })
+    }
+}
+
+/// Wait until a `system_config` value is written
+async fn settings_changed(changes: &mut broadcast::Receiver<DataChange>) {
+    loop {
+        match changes.recv().await {
+            Ok(DataChange::SystemConfig) | Err(RecvError::Lagged(_)) => return,
+            Ok(_) => {}
+            Err(RecvError::Closed) => std::future::pending::<()>().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sync_hosts_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(Database::new(&format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display())).await.unwrap());
+        let path = dir.path().join("hosts");
+        std::fs::write(&path, "127.0.0.1 localhost\n192.168.1.2 nas.lan nas\nbogus line\n").unwrap();
+
+        let sync = HostsSync::new(db.clone());
+        let config = HostsSyncConfig {
+            enabled: true,
+            path: path.display().to_string(),
+            ..Default::default()
+        };
+        let report = sync.sync(&config).await.unwrap();
+        assert_eq!(report.created, 2);
+        let status = sync.status();
+        assert_eq!(status.records, 2);
+        assert_eq!(status.errors.len(), 1);
+
+        std::fs::write(&path, "192.168.1.3 nas.lan\n").unwrap();
+        let report = sync.sync(&config).await.unwrap();
+        assert_eq!(report, SourceSyncReport { created: 1, updated: 0, deleted: 2 });
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(sync.sync(&config).await.is_err());
+        assert!(sync.status().last_error.is_some());
+        assert_eq!(db.dns_records().list().await.unwrap().len(), 1);
+
+        sync.clear().await.unwrap();
+        assert!(db.dns_records().list().await.unwrap().is_empty());
+    }
+}
@@ -13,7 +13,7 @@ use chrono::Local;
 
 use crate::db::Database;
 use crate::dns::DnsResolver;
-use crate::dns::server::{UdpDnsServer, DohDnsServer, DotDnsServer, DoqDnsServer, TlsConfig};
+use crate::dns::server::{UdpDnsServer, TcpDnsServer, DohDnsServer, DotDnsServer, DoqDnsServer, TlsConfig};
 
 /// Listener Manager
 ///
@@ -122,6 +122,28 @@ impl ListenerManager {
                     }
                 }
             }
+            "tcp" => {
+                match TcpDnsServer::new(addr, resolver).await {
+                    Ok(server) => {
+                        let msg = format!("✅ TCP listener started on {}", addr);
+                        info!("{}", msg);
+                        let time = Local::now().format("%Y-%m-%d %H:%M:%S");
+                        println!("{} {}", time, msg);
+
+                        let task = tokio::spawn(async move {
+                            if let Err(e) = server.run().await {
+                                error!("TCP DNS server error: {}", e);
+                            }
+                            info!("TCP listener stopped");
+                        });
+                        task.abort_handle()
+                    }
+                    Err(e) => {
+                        error!("Failed to bind TCP server: {}", e);
+                        return Err(e);
+                    }
+                }
+            }
             "dot" => {
                 if let (Some(cert), Some(key)) = (listener.tls_cert, listener.tls_key) {
                      let cert_path = format!("/tmp/fluxdns_{}_cert.pem", protocol);
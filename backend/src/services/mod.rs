@@ -1,3 +1,6 @@
 pub mod alert_manager;
+pub mod config_file;
+pub mod config_reload;
+pub mod config_transfer;
+pub mod hosts_sync;
 pub mod listener_manager;
-
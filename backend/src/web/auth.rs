@@ -160,6 +160,35 @@ pub struct ApiError {
     pub details: Option<serde_json::Value>,
 }
 
+impl ApiError {
+    /// Rejection for rows and settings owned by the declarative config file
+    pub fn managed_by_file(what: impl std::fmt::Display) -> Self {
+        Self {
+            code: "FORBIDDEN".to_string(),
+            message: format!("{} is managed by the configuration file and cannot be changed here", what),
+            details: None,
+        }
+    }
+
+    /// Rejection for invalid input
+    pub fn bad_request(message: impl std::fmt::Display) -> Self {
+        Self {
+            code: "BAD_REQUEST".to_string(),
+            message: message.to_string(),
+            details: None,
+        }
+    }
+
+    /// Failure on the server side, e.g. a database error
+    pub fn internal(message: impl std::fmt::Display) -> Self {
+        Self {
+            code: "INTERNAL_ERROR".to_string(),
+            message: message.to_string(),
+            details: None,
+        }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status = match self.code.as_str() {
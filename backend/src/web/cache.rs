@@ -171,6 +171,15 @@ pub async fn update_cache_config(
         });
     }
 
+    let mut keys = Vec::new();
+    if request.default_ttl.is_some() {
+        keys.push("cache_default_ttl");
+    }
+    if request.max_entries.is_some() {
+        keys.push("cache_max_entries");
+    }
+    super::settings::ensure_unmanaged(&state.db, &keys).await?;
+
     let mut config = state.cache.get_config().await;
 
     if let Some(ttl) = request.default_ttl {
@@ -0,0 +1,85 @@
+//! Config File API module
+//!
+//! Implements REST API endpoints for the declarative configuration file:
+//! the state of the last sync and a manual reload.
+
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::Serialize;
+
+use crate::db::Database;
+use crate::services::config_file::{managed_settings, ConfigFileManager, ConfigFileStatus, SyncReport};
+use crate::web::ApiError;
+
+/// Application state for config file API
+#[derive(Clone)]
+pub struct ConfigFileState {
+    /// None when no config file is configured
+    pub manager: Option<Arc<ConfigFileManager>>,
+    pub db: Arc<Database>,
+}
+
+/// Config file status response
+#[derive(Debug, Serialize)]
+pub struct ConfigFileResponse {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub status: Option<ConfigFileStatus>,
+    /// Settings owned by the file
+    pub managed_settings: Vec<String>,
+}
+
+/// Get config file status
+///
+/// GET /api/config-file
+pub async fn get_config_file(
+    State(state): State<ConfigFileState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let managed_settings = managed_settings(&state.db).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to get managed settings: {}", e),
+        details: None,
+    })?;
+
+    Ok(Json(ConfigFileResponse {
+        enabled: state.manager.is_some(),
+        status: state.manager.as_ref().map(|m| m.status()),
+        managed_settings,
+    }))
+}
+
+/// Reload the config file
+///
+/// POST /api/config-file/reload
+pub async fn reload_config_file(
+    State(state): State<ConfigFileState>,
+) -> Result<Json<SyncReport>, ApiError> {
+    let manager = state.manager.ok_or_else(|| ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: "No configuration file is configured".to_string(),
+        details: None,
+    })?;
+
+    match manager.reload().await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => {
+            tracing::warn!("Config file reload failed: {:#}", e);
+            Err(ApiError {
+                code: "BAD_REQUEST".to_string(),
+                message: "Configuration file is invalid; the previous state stays applied".to_string(),
+                details: Some(serde_json::json!({ "errors": manager.status().errors })),
+            })
+        }
+    }
+}
+
+/// Create the config file router
+pub fn config_file_router(state: ConfigFileState) -> axum::Router {
+    use axum::routing::{get, post};
+
+    axum::Router::new()
+        .route("/", get(get_config_file))
+        .route("/reload", post(reload_config_file))
+        .with_state(state)
+}
@@ -0,0 +1,90 @@
+//! Config Export/Import API module
+//!
+//! Implements REST API endpoints for exporting all configuration as a
+//! versioned snapshot and importing it again, with a dry-run diff preview.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+use crate::db::Database;
+use crate::services::config_transfer::{export, ConfigSnapshot, ConfigTransfer, ImportMode};
+use crate::web::ApiError;
+
+/// Application state for config export/import API
+#[derive(Clone)]
+pub struct ConfigTransferState {
+    pub db: Arc<Database>,
+    pub transfer: Arc<ConfigTransfer>,
+}
+
+/// Query parameters for import
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub mode: ImportMode,
+    /// Only return the diff
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Export all configuration
+///
+/// GET /api/config/export
+pub async fn export_config(
+    State(state): State<ConfigTransferState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let snapshot = export(&state.db).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to export configuration: {}", e),
+        details: None,
+    })?;
+    Ok(Json(snapshot))
+}
+
+/// Import a configuration snapshot
+///
+/// POST /api/config/import?mode=merge|replace&dry_run=true
+///
+/// The body is checked against the snapshot schema and validated entry by
+/// entry before anything is written; problems are listed in `details`.
+pub async fn import_config(
+    State(state): State<ConfigTransferState>,
+    Query(query): Query<ImportQuery>,
+    Json(body): Json<serde_json::Value>,
+) -> Result<impl IntoResponse, ApiError> {
+    let snapshot: ConfigSnapshot = serde_json::from_value(body).map_err(|e| ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: format!("Invalid configuration snapshot: {}", e),
+        details: None,
+    })?;
+    if let Err(problems) = snapshot.validate() {
+        return Err(ApiError {
+            code: "BAD_REQUEST".to_string(),
+            message: "Validation failed".to_string(),
+            details: Some(serde_json::json!({ "errors": problems })),
+        });
+    }
+
+    let result = state.transfer.import(snapshot, query.mode, query.dry_run).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to import configuration: {}", e),
+        details: None,
+    })?;
+    Ok(Json(result))
+}
+
+/// Create the config export/import router
+pub fn config_transfer_router(state: ConfigTransferState) -> axum::Router {
+    use axum::routing::{get, post};
+
+    axum::Router::new()
+        .route("/export", get(export_config))
+        .route("/import", post(import_config))
+        .with_state(state)
+}
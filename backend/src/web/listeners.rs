@@ -38,12 +38,14 @@ pub struct ListenerResponse {
     pub description: String,
     pub tls_cert: Option<String>,
     pub tls_key: Option<String>,
+    pub managed: bool,
 }
 
 impl From<ServerListener> for ListenerResponse {
     fn from(l: ServerListener) -> Self {
         let (requires_tls, description) = match l.protocol.as_str() {
             "udp" => (false, "标准 UDP DNS (端口 53)".to_string()),
+            "tcp" => (false, "标准 TCP DNS，支持区域传送 (端口 53)".to_string()),
             "dot" => (true, "DNS over TLS (端口 853)".to_string()),
             "doh" => (true, "DNS over HTTPS (端口 443)".to_string()),
             "doq" => (true, "DNS over QUIC (端口 853)".to_string()),
@@ -62,6 +64,7 @@ impl From<ServerListener> for ListenerResponse {
             description,
             tls_cert: l.tls_cert,
             tls_key: l.tls_key,
+            managed: l.managed,
         }
     }
 }
@@ -145,6 +148,15 @@ async fn update_listener(
     Path(protocol): Path<String>,
     Json(request): Json<UpdateListenerRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
+    let existing = state.db.server_listeners().get_by_protocol(&protocol).await.map_err(|e| ApiError {
+        code: "DATABASE_ERROR".to_string(),
+        message: format!("Failed to get listener: {}", e),
+        details: None,
+    })?;
+    if existing.is_some_and(|l| l.managed) {
+        return Err(ApiError::managed_by_file(format!("Listener '{}'", protocol)));
+    }
+
     // Validate port
     if let Some(port) = request.port {
         if port < 1 || port > 65535 {
@@ -6,22 +6,30 @@
 //!
 //! - 4.5: Provide query log viewing functionality
 
+use std::convert::Infallible;
 use std::sync::Arc;
 
 use axum::{
     extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
     response::IntoResponse,
     Json,
 };
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::wrappers::ReceiverStream;
 
-use crate::db::{Database, PaginatedResult, QueryLog, QueryLogFilter, QueryStats};
+use crate::db::{Database, PaginatedResult, QueryLog, QueryLogFilter, QueryStats, QueryStream, QueryStreamFilter, RollupGranularity, StatsSeries};
+use crate::dns::DnsResolver;
 use crate::web::ApiError;
 
 /// Application state for logs API
 #[derive(Clone)]
 pub struct LogsState {
     pub db: Arc<Database>,
+    pub stream: Arc<QueryStream>,
+    pub resolver: Arc<DnsResolver>,
 }
 
 /// Query parameters for log listing
@@ -31,6 +39,13 @@ pub struct LogsQueryParams {
     pub query_type: Option<String>,
     pub client_ip: Option<String>,
     pub cache_hit: Option<bool>,
+    pub response_code: Option<String>,
+    pub answer: Option<String>,
+    pub source: Option<String>,
+    pub source_id: Option<i64>,
+    pub reason: Option<String>,
+    pub protocol: Option<String>,
+    pub endpoint: Option<String>,
     pub start_time: Option<String>,
     pub end_time: Option<String>,
     pub limit: Option<i64>,
@@ -45,6 +60,13 @@ impl From<LogsQueryParams> for QueryLogFilter {
             query_type: params.query_type,
             client_ip: params.client_ip,
             cache_hit: params.cache_hit,
+            response_code: params.response_code,
+            answer: params.answer,
+            source: params.source,
+            source_id: params.source_id,
+            reason: params.reason,
+            protocol: params.protocol,
+            endpoint: params.endpoint,
             start_time: params.start_time.and_then(|t| chrono::DateTime::parse_from_rfc3339(&t).ok().map(|dt| dt.with_timezone(&chrono::Utc))),
             end_time: params.end_time.and_then(|t| chrono::DateTime::parse_from_rfc3339(&t).ok().map(|dt| dt.with_timezone(&chrono::Utc))),
             limit: params.limit,
@@ -120,6 +142,63 @@ pub async fn list_logs(
     Ok(Json(LogsListResponse::from(result)))
 }
 
+/// Server-side filters of the live query stream
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogStreamParams {
+    /// Client IP or CIDR
+    pub client: Option<String>,
+    /// Domain pattern (`*` wildcards, otherwise substring)
+    pub domain: Option<String>,
+    /// Comma-separated response codes
+    pub rcode: Option<String>,
+}
+
+/// Stream resolved queries as Server-Sent Events
+///
+/// GET /api/logs/stream
+///
+/// Each query is sent as a `query` event. A consumer that falls behind
+/// gets a `lagged` event with the number of queries it missed instead of
+/// slowing down resolution.
+pub async fn stream_logs(
+    State(state): State<LogsState>,
+    Query(params): Query<LogStreamParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = QueryStreamFilter::new(
+        params.client.as_deref(),
+        params.domain.as_deref(),
+        params.rcode.as_deref(),
+    ).map_err(|e| ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: e.to_string(),
+        details: None,
+    })?;
+
+    let receiver = state.stream.subscribe();
+    let events = futures::stream::unfold((receiver, state.stream, filter), |(mut receiver, stream, filter)| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) if filter.matches(&event) => Event::default()
+                    .event("query")
+                    .id(event.id.to_string())
+                    .json_data(&*event)
+                    .ok()?,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    stream.record_lagged(skipped);
+                    Event::default()
+                        .event("lagged")
+                        .data(serde_json::json!({ "skipped": skipped }).to_string())
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), (receiver, stream, filter)));
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 /// Get query statistics
 ///
 /// GET /api/logs/stats
@@ -137,6 +216,77 @@ pub async fn get_stats(
     Ok(Json(QueryStatsResponse::from(stats)))
 }
 
+/// Most buckets one series request may span
+const MAX_SERIES_POINTS: i64 = 10_000;
+
+/// Query parameters for statistics time series
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StatsSeriesParams {
+    /// `minute`, `hour` or `day` (default `minute`)
+    pub granularity: Option<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    /// Number of top domains/clients to return (default 10)
+    pub top: Option<usize>,
+}
+
+/// Load a statistics time series from the rollup tables
+///
+/// Defaults to the recent span of the granularity; the range is widened
+/// to whole buckets. Raw query logs are never read.
+pub async fn load_stats_series(db: &Database, params: StatsSeriesParams) -> Result<StatsSeries, ApiError> {
+    let bad_request = |message: String| ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message,
+        details: None,
+    };
+    let parse_time = |value: Option<String>| -> Result<Option<chrono::DateTime<chrono::Utc>>, ApiError> {
+        value.map(|t| chrono::DateTime::parse_from_rfc3339(&t)
+            .map(|dt| dt.with_timezone(&chrono::Utc))
+            .map_err(|_| bad_request(format!("Invalid time: {}", t))))
+            .transpose()
+    };
+
+    let granularity = match params.granularity.as_deref() {
+        Some(value) => RollupGranularity::parse(value)
+            .ok_or_else(|| bad_request(format!("Invalid granularity: {}", value)))?,
+        None => RollupGranularity::Minute,
+    };
+    let end = parse_time(params.end_time)?.unwrap_or_else(chrono::Utc::now);
+    let start = parse_time(params.start_time)?.unwrap_or(end - granularity.default_span());
+    if start > end {
+        return Err(bad_request("start_time must be before end_time".to_string()));
+    }
+
+    let start = granularity.bucket_start(start);
+    let end = granularity.bucket_start(end) + granularity.step();
+    if (end - start).num_seconds() / granularity.step().num_seconds() > MAX_SERIES_POINTS {
+        return Err(bad_request(format!(
+            "Range too long for {} granularity (at most {} points)",
+            granularity.as_str(),
+            MAX_SERIES_POINTS
+        )));
+    }
+
+    let buckets = db.stats_rollups().buckets(granularity, start, end).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to load statistics: {}", e),
+        details: None,
+    })?;
+
+    Ok(StatsSeries::from_buckets(granularity, start, end, buckets, params.top.unwrap_or(10).min(100)))
+}
+
+/// Get a statistics time series
+///
+/// GET /api/logs/stats/series
+pub async fn get_stats_series(
+    State(state): State<LogsState>,
+    Query(params): Query<StatsSeriesParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(load_stats_series(&state.db, params).await?))
+}
+
 /// Delete old query logs
 ///
 /// DELETE /api/logs/cleanup
@@ -225,55 +375,82 @@ pub async fn cleanup_all_logs(
 /// Export logs
 ///
 /// GET /api/logs/export
+///
+/// Rows are streamed to the client as they are read, so exports are not
+/// limited by memory. `format` is `csv` (default), `json` (one array) or
+/// `ndjson` (one object per line); `limit` caps the number of rows.
+/// Client IPs and names follow the current privacy settings.
 pub async fn export_logs(
     State(state): State<LogsState>,
     Query(params): Query<LogsQueryParams>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let repo = state.db.query_logs();
-    // Increase limit for export, or set to a large number
-    let mut filter = QueryLogFilter::from(params.clone());
-    filter.limit = Some(10000); // Limit export to 10k rows for now to prevent OOM
-    filter.offset = Some(0);
-
-    let result = repo.list(filter).await.map_err(|e| ApiError {
-        code: "INTERNAL_ERROR".to_string(),
-        message: format!("Failed to list logs for export: {}", e),
-        details: None,
-    })?;
-
-    let format = params.format.unwrap_or_else(|| "csv".to_string());
-
-    if format.to_lowercase() == "json" {
-        return Ok(Json(result.items).into_response());
-    }
-
-    // Default to CSV
-    let mut csv = String::new();
-    csv.push_str("Time,Client IP,Domain,Type,Response Code,Response Time(ms),Cache Hit,Upstream\n");
-
-    for log in result.items {
-        csv.push_str(&format!(
-            "{},{},{},{},{},{},{},{}\n",
-            log.created_at.to_rfc3339(),
-            log.client_ip,
-            log.query_name,
-            log.query_type,
-            log.response_code.unwrap_or_default(),
-            log.response_time.unwrap_or(0),
-            log.cache_hit,
-            log.upstream_used.unwrap_or_default()
-        ));
-    }
+    let format = params.format.clone().unwrap_or_else(|| "csv".to_string()).to_lowercase();
+    let (content_type, filename, header, separator, footer) = match format.as_str() {
+        "csv" => ("text/csv", "query_logs.csv", CSV_HEADER, "", ""),
+        "json" => ("application/json", "query_logs.json", "[", ",", "]"),
+        "ndjson" => ("application/x-ndjson", "query_logs.ndjson", "", "", ""),
+        other => {
+            return Err(ApiError {
+                code: "BAD_REQUEST".to_string(),
+                message: format!("Unsupported export format: {}", other),
+                details: None,
+            })
+        }
+    };
+
+    // Rows written before privacy settings changed are anonymized on the way out
+    let privacy = state.resolver.privacy().await;
+    let limit = params.limit.filter(|l| *l > 0).map(|l| l as usize).unwrap_or(usize::MAX);
+    let rows = ReceiverStream::new(state.db.query_logs().stream(QueryLogFilter::from(params)))
+        .take(limit)
+        .enumerate()
+        .map(move |(index, row)| {
+            let mut log = row?;
+            privacy.apply_stored(&mut log);
+            let line = match format.as_str() {
+                "csv" => csv_row(&log),
+                "json" => format!("{}{}", if index == 0 { "" } else { separator }, serde_json::to_string(&log)?),
+                _ => format!("{}\n", serde_json::to_string(&log)?),
+            };
+            Ok::<_, anyhow::Error>(line)
+        });
+    let body = futures::stream::once(async move { Ok(header.to_string()) })
+        .chain(rows)
+        .chain(futures::stream::once(async move { Ok(footer.to_string()) }));
 
     Ok((
         [
-            (axum::http::header::CONTENT_TYPE, "text/csv"),
-            (axum::http::header::CONTENT_DISPOSITION, "attachment; filename=\"query_logs.csv\""),
+            (axum::http::header::CONTENT_TYPE, content_type.to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
         ],
-        csv
+        axum::body::Body::from_stream(body),
     ).into_response())
 }
 
+const CSV_HEADER: &str = "Time,Client IP,Protocol,Endpoint,Domain,Type,Response Code,Response Time(ms),Cache Hit,Upstream,Source,Source ID,Reason,Answers,Decision\n";
+
+/// One exported CSV line
+fn csv_row(log: &QueryLog) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},\"{}\",\"{}\",\"{}\"\n",
+        log.created_at.to_rfc3339(),
+        log.client_ip,
+        log.protocol.as_deref().unwrap_or_default(),
+        log.endpoint.as_deref().unwrap_or_default(),
+        log.query_name,
+        log.query_type,
+        log.response_code.as_deref().unwrap_or_default(),
+        log.response_time.unwrap_or(0),
+        log.cache_hit,
+        log.upstream_used.as_deref().unwrap_or_default(),
+        log.source.as_deref().unwrap_or_default(),
+        log.source_id.map(|id| id.to_string()).unwrap_or_default(),
+        log.reason.as_deref().unwrap_or_default().replace('"', "\"\""),
+        log.answers.as_deref().unwrap_or_default().replace('"', "\"\""),
+        log.decision.as_deref().unwrap_or_default().replace('"', "\"\"")
+    )
+}
+
 /// Get log retention settings
 ///
 /// GET /api/logs/retention
@@ -366,6 +543,8 @@ pub fn logs_router(state: LogsState) -> axum::Router {
         .route("/", get(list_logs))
         .route("/export", get(export_logs))
         .route("/stats", get(get_stats))
+        .route("/stats/series", get(get_stats_series))
+        .route("/stream", get(stream_logs))
         .route("/cleanup", delete(cleanup_logs))
         .route("/cleanup/before", delete(cleanup_logs_before_date))
         .route("/cleanup/all", delete(cleanup_all_logs))
@@ -385,6 +564,13 @@ mod tests {
             query_type: Some("A".to_string()),
             client_ip: None,
             cache_hit: Some(true),
+            response_code: None,
+            answer: None,
+            source: Some("blocked".to_string()),
+            source_id: Some(3),
+            reason: None,
+            protocol: Some("doh".to_string()),
+            endpoint: None,
             start_time: None,
             end_time: None,
             limit: Some(50),
@@ -395,6 +581,9 @@ mod tests {
         assert_eq!(filter.query_name, Some("example.com".to_string()));
         assert_eq!(filter.query_type, Some("A".to_string()));
         assert_eq!(filter.cache_hit, Some(true));
+        assert_eq!(filter.source, Some("blocked".to_string()));
+        assert_eq!(filter.source_id, Some(3));
+        assert_eq!(filter.protocol, Some("doh".to_string()));
         assert_eq!(filter.limit, Some(50));
         assert_eq!(filter.offset, Some(0));
     }
@@ -438,7 +627,14 @@ mod tests {
             response_time: Some(10),
             cache_hit: false,
             upstream_used: None,
+            decision: None,
             created_at: Utc::now(),
+            answers: None,
+            source: None,
+            source_id: None,
+            reason: None,
+            protocol: None,
+            endpoint: None,
         };
 
         // Case 1: 50 items returned, total is 100, so has_more should be true
@@ -4,26 +4,34 @@
 
 pub mod auth;
 pub mod cache;
+pub mod config_file;
+pub mod config_transfer;
 pub mod dns_query;
 pub mod listeners;
 pub mod llm;
 pub mod logs;
+pub mod passive_dns;
 pub mod records;
 pub mod rewrite;
 pub mod settings;
 pub mod static_files;
 pub mod status;
 pub mod strategy;
+pub mod tsig_keys;
 pub mod upstreams;
+pub mod zones;
 
 
 pub use auth::{
     auth_middleware, ApiError, AuthService, AuthState,
 };
 pub use cache::{cache_router, CacheState};
+pub use config_file::{config_file_router, ConfigFileState};
+pub use config_transfer::{config_transfer_router, ConfigTransferState};
 pub use dns_query::{dns_query_router, DnsQueryState};
 pub use listeners::{listeners_router, ListenersState};
 pub use logs::{logs_router, LogsState};
+pub use passive_dns::{passive_dns_router, PassiveDnsState};
 pub use records::{
     records_router, RecordsState,
 };
@@ -32,6 +40,8 @@ pub use settings::{settings_router, SettingsState};
 pub use static_files::{fallback_handler, index_handler, static_handler};
 pub use status::{status_router, StatusState};
 pub use strategy::{strategy_router, StrategyState};
+pub use tsig_keys::{tsig_keys_router, TsigKeysState};
 pub use upstreams::{upstreams_router, UpstreamsState};
+pub use zones::{zones_router, ZonesState};
 pub use llm::{llm_router, LlmState};
 
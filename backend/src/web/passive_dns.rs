@@ -0,0 +1,136 @@
+//! Passive DNS API module
+//!
+//! Implements REST API endpoints for searching the passive DNS history:
+//! which domains resolved to an IP or CIDR, and which answers a domain had.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{ip_key_range, Database, PaginatedResult, PassiveDnsEntry, PassiveDnsFilter};
+use crate::web::ApiError;
+
+/// Application state for passive DNS API
+#[derive(Clone)]
+pub struct PassiveDnsState {
+    pub db: Arc<Database>,
+}
+
+/// Query parameters for passive DNS search
+#[derive(Debug, Clone, Deserialize)]
+pub struct PassiveDnsQueryParams {
+    pub name: Option<String>,
+    pub include_subdomains: Option<bool>,
+    pub value: Option<String>,
+    /// IP address or CIDR
+    pub ip: Option<String>,
+    pub record_type: Option<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl From<PassiveDnsQueryParams> for PassiveDnsFilter {
+    fn from(params: PassiveDnsQueryParams) -> Self {
+        let non_empty = |v: Option<String>| v.filter(|v| !v.trim().is_empty());
+        Self {
+            name: non_empty(params.name),
+            include_subdomains: params.include_subdomains.unwrap_or(false),
+            value: non_empty(params.value),
+            ip: non_empty(params.ip),
+            record_type: non_empty(params.record_type),
+            start_time: params.start_time.and_then(|t| chrono::DateTime::parse_from_rfc3339(&t).ok().map(|dt| dt.with_timezone(&chrono::Utc))),
+            end_time: params.end_time.and_then(|t| chrono::DateTime::parse_from_rfc3339(&t).ok().map(|dt| dt.with_timezone(&chrono::Utc))),
+            limit: params.limit,
+            offset: params.offset,
+        }
+    }
+}
+
+/// Paginated passive DNS response
+#[derive(Debug, Serialize)]
+pub struct PassiveDnsListResponse {
+    pub data: Vec<PassiveDnsEntry>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl From<PaginatedResult<PassiveDnsEntry>> for PassiveDnsListResponse {
+    fn from(result: PaginatedResult<PassiveDnsEntry>) -> Self {
+        Self {
+            data: result.items,
+            total: result.total,
+            limit: result.limit,
+            offset: result.offset,
+        }
+    }
+}
+
+/// Search the passive DNS history
+///
+/// GET /api/passive-dns
+pub async fn search_passive_dns(
+    State(state): State<PassiveDnsState>,
+    Query(params): Query<PassiveDnsQueryParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let filter = PassiveDnsFilter::from(params);
+
+    if let Some(ref ip) = filter.ip {
+        ip_key_range(ip).map_err(|e| ApiError {
+            code: "BAD_REQUEST".to_string(),
+            message: e.to_string(),
+            details: None,
+        })?;
+    }
+
+    let result = state.db.passive_dns().search(&filter).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to search passive DNS: {}", e),
+        details: None,
+    })?;
+
+    Ok(Json(PassiveDnsListResponse::from(result)))
+}
+
+/// Build the passive DNS API router
+pub fn passive_dns_router(state: PassiveDnsState) -> axum::Router {
+    use axum::routing::get;
+
+    axum::Router::new()
+        .route("/", get(search_passive_dns))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_to_filter() {
+        let params = PassiveDnsQueryParams {
+            name: Some(" ".to_string()),
+            include_subdomains: Some(true),
+            value: None,
+            ip: Some("10.0.0.0/8".to_string()),
+            record_type: Some("A".to_string()),
+            start_time: Some("2024-01-01T00:00:00Z".to_string()),
+            end_time: None,
+            limit: Some(20),
+            offset: None,
+        };
+
+        let filter = PassiveDnsFilter::from(params);
+        assert_eq!(filter.name, None);
+        assert!(filter.include_subdomains);
+        assert_eq!(filter.ip, Some("10.0.0.0/8".to_string()));
+        assert!(filter.start_time.is_some());
+        assert_eq!(filter.limit, Some(20));
+    }
+}
@@ -9,14 +9,16 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::IntoResponse,
     Json,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::config::declarative::record_key;
 use crate::db::{CreateDnsRecord, Database, DnsRecord, UpdateDnsRecord};
+use crate::dns::{group_by_zone, parse_hosts, parse_zone, render_zone, LineError, ParsedFile};
 use crate::web::ApiError;
 
 /// Application state for DNS records API
@@ -41,7 +43,7 @@ pub struct ValidationError {
 }
 
 /// Create DNS record request with validation
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateRecordRequest {
     pub name: String,
     pub record_type: String,
@@ -86,6 +88,61 @@ pub struct RecordsListResponse {
     pub total: usize,
 }
 
+/// Zone file import request
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportZoneRequest {
+    pub content: String,
+    /// Initial `$ORIGIN` for relative names
+    #[serde(default)]
+    pub origin: Option<String>,
+    /// TTL of records without one until a `$TTL` directive
+    #[serde(default = "default_import_ttl")]
+    pub ttl: u32,
+    /// Import the valid lines even if others have errors
+    #[serde(default)]
+    pub skip_invalid: bool,
+    /// Only report what would be created
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Hosts file import request
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportHostsRequest {
+    pub content: String,
+    #[serde(default = "default_import_ttl")]
+    pub ttl: u32,
+    #[serde(default)]
+    pub skip_invalid: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_import_ttl() -> u32 {
+    300
+}
+
+/// Outcome of a file import
+#[derive(Debug, Serialize)]
+pub struct RecordImportResponse {
+    pub dry_run: bool,
+    /// Records read from the file
+    pub parsed: usize,
+    /// Records created, or that would be created on a dry run
+    pub created: usize,
+    /// Records already present and skipped
+    pub duplicates: usize,
+    pub errors: Vec<LineError>,
+    pub records: Vec<CreateRecordRequest>,
+}
+
+/// Query parameters for zone file export
+#[derive(Debug, Deserialize)]
+pub struct ExportZoneQuery {
+    /// Export only this zone
+    pub origin: Option<String>,
+}
+
 /// Validate a DNS record name
 fn validate_name(name: &str) -> Result<(), String> {
     if name.is_empty() {
@@ -416,6 +473,7 @@ pub async fn update_record(
         message: format!("Record with id {} not found", id),
         details: None,
     })?;
+    ensure_editable(&existing)?;
 
     // Validate request against existing record type
     if let Err(validation_errors) = request.validate(&existing.record_type) {
@@ -452,7 +510,16 @@ pub async fn delete_record(
     Path(id): Path<i64>,
 ) -> Result<impl IntoResponse, ApiError> {
     let repo = state.db.dns_records();
-    
+
+    let existing = repo.get_by_id(id).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to get record: {}", e),
+        details: None,
+    })?;
+    if let Some(ref existing) = existing {
+        ensure_editable(existing)?;
+    }
+
     let deleted = repo.delete(id).await.map_err(|e| ApiError {
         code: "INTERNAL_ERROR".to_string(),
         message: format!("Failed to delete record: {}", e),
@@ -470,12 +537,176 @@ pub async fn delete_record(
     }
 }
 
+/// Reject changes to rows owned by the config file or the hosts sync
+fn ensure_editable(record: &DnsRecord) -> Result<(), ApiError> {
+    if record.managed {
+        return Err(ApiError::managed_by_file(format!("Record {}", record.id)));
+    }
+    if let Some(ref source) = record.source {
+        return Err(ApiError {
+            code: "FORBIDDEN".to_string(),
+            message: format!("Record {} is synced from the {} file and cannot be changed here", record.id, source),
+            details: None,
+        });
+    }
+    Ok(())
+}
+
+/// Validate parsed records and create the ones not present yet
+///
+/// Nothing is written while lines have errors unless `skip_invalid` is set.
+async fn import_parsed(
+    state: &RecordsState,
+    parsed: ParsedFile,
+    skip_invalid: bool,
+    dry_run: bool,
+) -> Result<RecordImportResponse, ApiError> {
+    let existing = state.db.dns_records().list().await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to list records: {}", e),
+        details: None,
+    })?;
+    let mut seen: std::collections::HashSet<_> = existing
+        .iter()
+        .map(|r| record_key(&r.name, &r.record_type, &r.value))
+        .collect();
+
+    let parsed_count = parsed.records.len();
+    let mut errors = parsed.errors;
+    let mut records = Vec::new();
+    let mut duplicates = 0;
+    for record in parsed.records {
+        let request = CreateRecordRequest {
+            name: record.name,
+            record_type: record.record_type,
+            value: record.value,
+            ttl: record.ttl.min(i32::MAX as u32) as i32,
+            priority: record.priority as i32,
+            enabled: true,
+        };
+        if let Err(e) = request.validate() {
+            errors.push(LineError {
+                line: record.line,
+                message: e.errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>().join("; "),
+            });
+            continue;
+        }
+        if !seen.insert(record_key(&request.name, &request.record_type, &request.value)) {
+            duplicates += 1;
+            continue;
+        }
+        records.push(request);
+    }
+    errors.sort_by_key(|e| e.line);
+
+    if !dry_run && !errors.is_empty() && !skip_invalid {
+        return Err(ApiError {
+            code: "BAD_REQUEST".to_string(),
+            message: format!("{} line(s) of the file are invalid, nothing was imported", errors.len()),
+            details: Some(serde_json::json!({ "errors": errors })),
+        });
+    }
+
+    let created = if dry_run {
+        records.len()
+    } else {
+        let create = records.iter().cloned().map(CreateRecordRequest::into_create_dns_record).collect();
+        state.db.dns_records().batch_create(create).await.map_err(|e| ApiError {
+            code: "INTERNAL_ERROR".to_string(),
+            message: format!("Failed to create records: {}", e),
+            details: None,
+        })?
+    };
+
+    Ok(RecordImportResponse {
+        dry_run,
+        parsed: parsed_count,
+        created,
+        duplicates,
+        errors,
+        records,
+    })
+}
+
+/// Import records from an RFC 1035 zone file
+///
+/// POST /api/records/import/zone
+pub async fn import_zone(
+    State(state): State<RecordsState>,
+    Json(request): Json<ImportZoneRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let parsed = parse_zone(&request.content, request.origin.as_deref(), request.ttl);
+    Ok(Json(import_parsed(&state, parsed, request.skip_invalid, request.dry_run).await?))
+}
+
+/// Import A/AAAA records from a hosts file
+///
+/// POST /api/records/import/hosts
+pub async fn import_hosts(
+    State(state): State<RecordsState>,
+    Json(request): Json<ImportHostsRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let parsed = parse_hosts(&request.content, request.ttl);
+    Ok(Json(import_parsed(&state, parsed, request.skip_invalid, request.dry_run).await?))
+}
+
+/// Export local records as zone files
+///
+/// GET /api/records/export/zone?origin=example.com
+///
+/// Without `origin` every zone is written one after another, each starting
+/// with its own `$ORIGIN`.
+pub async fn export_zone(
+    State(state): State<RecordsState>,
+    Query(query): Query<ExportZoneQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let records = state.db.dns_records().list().await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to list records: {}", e),
+        details: None,
+    })?;
+
+    let origin = query.origin
+        .map(|o| o.trim().trim_end_matches('.').to_lowercase())
+        .filter(|o| !o.is_empty());
+    let body = match origin {
+        Some(ref origin) => {
+            let suffix = format!(".{}", origin);
+            let records: Vec<DnsRecord> = records
+                .into_iter()
+                .filter(|r| {
+                    let name = r.name.trim_end_matches('.').to_lowercase();
+                    name == *origin || name.ends_with(&suffix)
+                })
+                .collect();
+            render_zone(origin, &records)
+        }
+        None => group_by_zone(records)
+            .iter()
+            .map(|(zone, records)| render_zone(zone, records))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    let filename = format!("{}.zone", origin.as_deref().unwrap_or("fluxdns"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        body,
+    ))
+}
+
 /// Build the records API router
 pub fn records_router(state: RecordsState) -> axum::Router {
-    use axum::routing::get;
+    use axum::routing::{get, post};
     
     axum::Router::new()
         .route("/", get(list_records).post(create_record))
+        .route("/import/zone", post(import_zone))
+        .route("/import/hosts", post(import_hosts))
+        .route("/export/zone", get(export_zone))
         .route("/:id", get(get_record).put(update_record).delete(delete_record))
         .with_state(state)
 }
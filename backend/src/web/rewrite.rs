@@ -47,7 +47,7 @@ pub struct ValidationError {
 }
 
 /// Create rewrite rule request with validation
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateRewriteRuleRequest {
     pub pattern: String,
     pub match_type: String,
@@ -389,6 +389,9 @@ pub async fn update_rule(
         message: format!("Rewrite rule with id {} not found", id),
         details: None,
     })?;
+    if existing.managed {
+        return Err(ApiError::managed_by_file(format!("Rewrite rule {}", id)));
+    }
 
     // Validate request against existing rule
     if let Err(validation_errors) = request.validate(&existing) {
@@ -431,6 +434,15 @@ pub async fn delete_rule(
 ) -> Result<impl IntoResponse, ApiError> {
     let repo = state.db.rewrite_rules();
 
+    let existing = repo.get_by_id(id).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to get rewrite rule: {}", e),
+        details: None,
+    })?;
+    if existing.is_some_and(|r| r.managed) {
+        return Err(ApiError::managed_by_file(format!("Rewrite rule {}", id)));
+    }
+
     let deleted = repo.delete(id).await.map_err(|e| ApiError {
         code: "INTERNAL_ERROR".to_string(),
         message: format!("Failed to delete rewrite rule: {}", e),
@@ -11,13 +11,18 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::db::Database;
+use crate::db::{Database, LogSinksConfig, PassiveDnsConfig, QueryLogWriterConfig, StatsRollupConfig};
+use crate::dns::{DnsResolver, DnstapConfig, PtrSynthesisConfig, QueryPrivacy, QueryPrivacyConfig, ResponseFilter, ResponseFilterConfig, PTR_SYNTHESIS_CONFIG_KEY};
+use crate::dns::proxy::{DotPoolConfig, UdpClientConfig};
+use crate::services::hosts_sync::{self, HostsSync, HostsSyncConfig, HostsSyncStatus};
 use crate::web::ApiError;
 
 /// Application state for settings API
 #[derive(Clone)]
 pub struct SettingsState {
     pub db: Arc<Database>,
+    pub resolver: Arc<DnsResolver>,
+    pub hosts_sync: Arc<HostsSync>,
 }
 
 /// System settings response
@@ -45,6 +50,38 @@ pub struct UpdateSettingsRequest {
 /// Config key for disabled record types
 const CONFIG_KEY_DISABLED_RECORD_TYPES: &str = "disabled_record_types";
 
+/// Config key for upstream response filters
+const CONFIG_KEY_RESPONSE_FILTER: &str = "response_filter";
+const CONFIG_KEY_DOT_POOL: &str = "dot_pool";
+const CONFIG_KEY_UDP_CLIENT: &str = "udp_client";
+const CONFIG_KEY_QUERY_LOG_WRITER: &str = "query_log_writer";
+const CONFIG_KEY_PASSIVE_DNS: &str = "passive_dns";
+const CONFIG_KEY_STATS_ROLLUP: &str = "stats_rollup";
+const CONFIG_KEY_DNSTAP: &str = "dnstap";
+const CONFIG_KEY_LOG_SINKS: &str = "log_sinks";
+const CONFIG_KEY_QUERY_PRIVACY: &str = "query_privacy";
+
+/// Reject writes to settings owned by the declarative config file
+pub async fn ensure_unmanaged(db: &Database, keys: &[&str]) -> Result<(), ApiError> {
+    let managed = crate::services::config_file::managed_settings(db).await
+        .map_err(|e| ApiError::internal(format!("Failed to get settings: {}", e)))?;
+    match keys.iter().find(|key| managed.iter().any(|m| m == *key)) {
+        Some(key) => Err(ApiError::managed_by_file(format!("Setting '{}'", key))),
+        None => Ok(()),
+    }
+}
+
+/// Store a validated JSON setting in `system_config`
+///
+/// Handlers call this before applying the settings at runtime, so a failed
+/// write leaves both the database and the running service unchanged.
+pub async fn save_json_setting<T: Serialize>(db: &Database, key: &str, config: &T, label: &str) -> Result<(), ApiError> {
+    let value = serde_json::to_string(config)
+        .map_err(|e| ApiError::internal(format!("Failed to serialize settings: {}", e)))?;
+    db.system_config().set(key, &value).await
+        .map_err(|e| ApiError::internal(format!("Failed to save {}: {}", label, e)))
+}
+
 /// Get current system settings
 ///
 /// GET /api/settings
@@ -89,6 +126,21 @@ pub async fn update_settings(
     State(state): State<SettingsState>,
     Json(request): Json<UpdateSettingsRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
+    let mut keys = Vec::new();
+    if request.disabled_record_types.is_some() {
+        keys.push(CONFIG_KEY_DISABLED_RECORD_TYPES);
+    }
+    if request.alert_enabled.is_some() {
+        keys.push("alert_enabled");
+    }
+    if request.alert_webhook_url.is_some() {
+        keys.push("alert_webhook_url");
+    }
+    if request.alert_latency_threshold_ms.is_some() {
+        keys.push("alert_latency_threshold_ms");
+    }
+    ensure_unmanaged(&state.db, &keys).await?;
+
     let repo = state.db.system_config();
 
     if let Some(disabled_types) = request.disabled_record_types {
@@ -105,17 +157,7 @@ pub async fn update_settings(
             }
         }
 
-        let value = serde_json::to_string(&disabled_types).map_err(|e| ApiError {
-            code: "INTERNAL_ERROR".to_string(),
-            message: format!("Failed to serialize settings: {}", e),
-            details: None,
-        })?;
-
-        repo.set(CONFIG_KEY_DISABLED_RECORD_TYPES, &value).await.map_err(|e| ApiError {
-            code: "INTERNAL_ERROR".to_string(),
-            message: format!("Failed to save settings: {}", e),
-            details: None,
-        })?;
+        save_json_setting(&state.db, CONFIG_KEY_DISABLED_RECORD_TYPES, &disabled_types, "settings").await?;
     }
 
     if let Some(enabled) = request.alert_enabled {
@@ -146,12 +188,381 @@ pub async fn update_settings(
     get_settings(State(state)).await
 }
 
+/// Get upstream response filter settings
+///
+/// GET /api/settings/response-filter
+pub async fn get_response_filter(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let value = state.db.system_config().get(CONFIG_KEY_RESPONSE_FILTER).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to get response filter settings: {}", e),
+        details: None,
+    })?;
+
+    let config = value
+        .and_then(|v| serde_json::from_str::<ResponseFilterConfig>(&v).ok())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update upstream response filter settings
+///
+/// PUT /api/settings/response-filter
+pub async fn update_response_filter(
+    State(state): State<SettingsState>,
+    Json(config): Json<ResponseFilterConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_RESPONSE_FILTER]).await?;
+
+    // Compile first so invalid CIDRs are rejected before saving
+    ResponseFilter::from_config(&config).map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_RESPONSE_FILTER, &config, "response filter settings").await?;
+    state.resolver.set_response_filter(&config).await.map_err(ApiError::internal)?;
+
+    Ok(Json(config))
+}
+
+/// Get DoT connection pool settings
+///
+/// GET /api/settings/dot-pool
+pub async fn get_dot_pool(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.resolver.proxy().dot_pool().await))
+}
+
+/// Update DoT connection pool settings
+///
+/// PUT /api/settings/dot-pool
+pub async fn update_dot_pool(
+    State(state): State<SettingsState>,
+    Json(config): Json<DotPoolConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_DOT_POOL]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_DOT_POOL, &config, "DoT pool settings").await?;
+    state.resolver.proxy().set_dot_pool(config).await.map_err(ApiError::internal)?;
+
+    Ok(Json(config))
+}
+
+/// Get UDP upstream client settings
+///
+/// GET /api/settings/udp-client
+pub async fn get_udp_client(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.resolver.proxy().udp_client().await))
+}
+
+/// Update UDP upstream client settings
+///
+/// PUT /api/settings/udp-client
+pub async fn update_udp_client(
+    State(state): State<SettingsState>,
+    Json(config): Json<UdpClientConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_UDP_CLIENT]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_UDP_CLIENT, &config, "UDP client settings").await?;
+    state.resolver.proxy().set_udp_client(config).await.map_err(ApiError::internal)?;
+
+    Ok(Json(config))
+}
+
+/// Get query log writer settings
+///
+/// GET /api/settings/query-log-writer
+pub async fn get_query_log_writer(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = state.resolver.log_writer()
+        .map(|writer| writer.config())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update query log writer settings
+///
+/// PUT /api/settings/query-log-writer
+pub async fn update_query_log_writer(
+    State(state): State<SettingsState>,
+    Json(config): Json<QueryLogWriterConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_QUERY_LOG_WRITER]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_QUERY_LOG_WRITER, &config, "query log writer settings").await?;
+    if let Some(writer) = state.resolver.log_writer() {
+        writer.set_config(config).map_err(ApiError::internal)?;
+    }
+
+    Ok(Json(config))
+}
+
+/// Get passive DNS settings
+///
+/// GET /api/settings/passive-dns
+pub async fn get_passive_dns(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = state.resolver.passive_dns()
+        .map(|recorder| recorder.config())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update passive DNS settings
+///
+/// PUT /api/settings/passive-dns
+pub async fn update_passive_dns(
+    State(state): State<SettingsState>,
+    Json(config): Json<PassiveDnsConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_PASSIVE_DNS]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_PASSIVE_DNS, &config, "passive DNS settings").await?;
+    if let Some(recorder) = state.resolver.passive_dns() {
+        recorder.set_config(config).map_err(ApiError::internal)?;
+    }
+
+    Ok(Json(config))
+}
+
+/// Get statistics rollup retention settings
+///
+/// GET /api/settings/stats-rollup
+pub async fn get_stats_rollup(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = state.resolver.stats_rollup()
+        .map(|rollup| rollup.config())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update statistics rollup retention settings
+///
+/// PUT /api/settings/stats-rollup
+pub async fn update_stats_rollup(
+    State(state): State<SettingsState>,
+    Json(config): Json<StatsRollupConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_STATS_ROLLUP]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_STATS_ROLLUP, &config, "stats rollup settings").await?;
+    if let Some(rollup) = state.resolver.stats_rollup() {
+        rollup.set_config(config).map_err(ApiError::internal)?;
+    }
+
+    Ok(Json(config))
+}
+
+/// Get remote log sink settings
+///
+/// GET /api/settings/log-sinks
+pub async fn get_log_sinks(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = state.resolver.log_sinks()
+        .map(|sinks| sinks.config())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Replace the remote log sinks
+///
+/// PUT /api/settings/log-sinks
+pub async fn update_log_sinks(
+    State(state): State<SettingsState>,
+    Json(config): Json<LogSinksConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_LOG_SINKS]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_LOG_SINKS, &config, "log sink settings").await?;
+    if let Some(sinks) = state.resolver.log_sinks() {
+        sinks.set_config(config.clone()).await.map_err(ApiError::internal)?;
+    }
+
+    Ok(Json(config))
+}
+
+/// Get dnstap output settings
+///
+/// GET /api/settings/dnstap
+pub async fn get_dnstap(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = state.resolver.dnstap()
+        .map(|dnstap| dnstap.config())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update dnstap output settings
+///
+/// PUT /api/settings/dnstap
+pub async fn update_dnstap(
+    State(state): State<SettingsState>,
+    Json(config): Json<DnstapConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_DNSTAP]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_DNSTAP, &config, "dnstap settings").await?;
+    if let Some(dnstap) = state.resolver.dnstap() {
+        dnstap.set_config(config.clone()).await.map_err(ApiError::internal)?;
+    }
+
+    Ok(Json(config))
+}
+
+/// Get query log privacy settings
+///
+/// GET /api/settings/privacy
+pub async fn get_privacy(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.resolver.privacy().await.config().clone()))
+}
+
+/// Update query log privacy settings
+///
+/// PUT /api/settings/privacy
+pub async fn update_privacy(
+    State(state): State<SettingsState>,
+    Json(config): Json<QueryPrivacyConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[CONFIG_KEY_QUERY_PRIVACY]).await?;
+
+    // Compile first so invalid prefixes and CIDRs are rejected before saving
+    QueryPrivacy::from_config(&config).map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, CONFIG_KEY_QUERY_PRIVACY, &config, "privacy settings").await?;
+    state.resolver.set_privacy(&config).await.map_err(ApiError::internal)?;
+
+    Ok(Json(config))
+}
+
+/// Get PTR synthesis settings
+///
+/// GET /api/settings/ptr-synthesis
+pub async fn get_ptr_synthesis(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let value = state.db.system_config().get(PTR_SYNTHESIS_CONFIG_KEY).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to get settings: {}", e),
+        details: None,
+    })?;
+    let config: PtrSynthesisConfig = value
+        .and_then(|v| serde_json::from_str(&v).ok())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update PTR synthesis settings
+///
+/// PUT /api/settings/ptr-synthesis
+///
+/// The local data snapshot reloads on the write, so nothing else is applied here.
+pub async fn update_ptr_synthesis(
+    State(state): State<SettingsState>,
+    Json(config): Json<PtrSynthesisConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[PTR_SYNTHESIS_CONFIG_KEY]).await?;
+
+    save_json_setting(&state.db, PTR_SYNTHESIS_CONFIG_KEY, &config, "PTR synthesis settings").await?;
+
+    Ok(Json(config))
+}
+
+/// Hosts file sync settings with the outcome of the last sync
+#[derive(Debug, Serialize)]
+pub struct HostsSyncResponse {
+    #[serde(flatten)]
+    pub config: HostsSyncConfig,
+    pub status: HostsSyncStatus,
+}
+
+/// Get hosts file sync settings
+///
+/// GET /api/settings/hosts-sync
+pub async fn get_hosts_sync(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(HostsSyncResponse {
+        config: HostsSync::load_config(&state.db).await,
+        status: state.hosts_sync.status(),
+    }))
+}
+
+/// Update hosts file sync settings
+///
+/// PUT /api/settings/hosts-sync
+///
+/// The sync task picks the change up right away.
+pub async fn update_hosts_sync(
+    State(state): State<SettingsState>,
+    Json(config): Json<HostsSyncConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_unmanaged(&state.db, &[hosts_sync::CONFIG_KEY]).await?;
+
+    config.validate().map_err(ApiError::bad_request)?;
+    save_json_setting(&state.db, hosts_sync::CONFIG_KEY, &config, "hosts sync settings").await?;
+
+    Ok(Json(config))
+}
+
+/// Sync the hosts file now
+///
+/// POST /api/settings/hosts-sync/run
+pub async fn run_hosts_sync(
+    State(state): State<SettingsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = HostsSync::load_config(&state.db).await;
+    if !config.enabled {
+        return Err(ApiError {
+            code: "BAD_REQUEST".to_string(),
+            message: "Hosts file sync is disabled".to_string(),
+            details: None,
+        });
+    }
+
+    let report = state.hosts_sync.sync(&config).await.map_err(|e| ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: format!("{:#}", e),
+        details: None,
+    })?;
+    Ok(Json(serde_json::json!({
+        "report": report,
+        "status": state.hosts_sync.status(),
+    })))
+}
+
 /// Build the settings API router
 pub fn settings_router(state: SettingsState) -> axum::Router {
     use axum::routing::get;
 
     axum::Router::new()
         .route("/", get(get_settings).put(update_settings))
+        .route("/response-filter", get(get_response_filter).put(update_response_filter))
+        .route("/dot-pool", get(get_dot_pool).put(update_dot_pool))
+        .route("/udp-client", get(get_udp_client).put(update_udp_client))
+        .route("/query-log-writer", get(get_query_log_writer).put(update_query_log_writer))
+        .route("/passive-dns", get(get_passive_dns).put(update_passive_dns))
+        .route("/stats-rollup", get(get_stats_rollup).put(update_stats_rollup))
+        .route("/dnstap", get(get_dnstap).put(update_dnstap))
+        .route("/log-sinks", get(get_log_sinks).put(update_log_sinks))
+        .route("/privacy", get(get_privacy).put(update_privacy))
+        .route("/ptr-synthesis", get(get_ptr_synthesis).put(update_ptr_synthesis))
+        .route("/hosts-sync", get(get_hosts_sync).put(update_hosts_sync))
+        .route("/hosts-sync/run", axum::routing::post(run_hosts_sync))
         .route("/test-alert", axum::routing::post(test_alert))
         .with_state(state)
 }
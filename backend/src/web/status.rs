@@ -10,16 +10,17 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::IntoResponse,
     Json,
 };
 use serde::Serialize;
 use tokio::sync::RwLock;
 
-use crate::db::Database;
-use crate::dns::CacheManager;
+use crate::db::{Database, LogSinkStats, PassiveDnsStats, QueryLogWriterStats, QueryStreamStats};
+use crate::dns::{CacheManager, CoalesceStats, DnsResolver, DnstapStats};
 use crate::dns::proxy::{ProxyManager, UpstreamManager};
+use crate::web::logs::{load_stats_series, StatsSeriesParams};
 use crate::web::ApiError;
 
 /// Application state for status API
@@ -29,6 +30,7 @@ pub struct StatusState {
     pub cache: Arc<CacheManager>,
     pub proxy_manager: Arc<ProxyManager>,
     pub upstream_manager: Arc<UpstreamManager>,
+    pub resolver: Arc<DnsResolver>,
     pub start_time: Arc<RwLock<Instant>>,
 }
 
@@ -41,6 +43,18 @@ pub struct SystemStatusResponse {
     pub query: QueryStatusInfo,
     pub upstreams: UpstreamsStatusInfo,
     pub strategy: String,
+    /// In-flight query coalescing counters
+    pub coalescing: CoalesceStats,
+    /// Query log writer counters (absent when query logging is off)
+    pub query_log: Option<QueryLogWriterStats>,
+    /// Remote log sink counters, one entry per configured sink
+    pub log_sinks: Option<Vec<LogSinkStats>>,
+    /// Passive DNS recorder counters (absent when not running)
+    pub passive_dns: Option<PassiveDnsStats>,
+    /// Live query stream counters
+    pub query_stream: Option<QueryStreamStats>,
+    /// dnstap output counters
+    pub dnstap: Option<DnstapStats>,
 }
 
 /// Cache status information
@@ -169,9 +183,25 @@ pub async fn system_status(
             servers: upstream_servers,
         },
         strategy: strategy.as_str().to_string(),
+        coalescing: state.resolver.coalesce_stats(),
+        query_log: state.resolver.query_log_stats(),
+        log_sinks: state.resolver.log_sinks_stats(),
+        passive_dns: state.resolver.passive_dns_stats(),
+        query_stream: state.resolver.query_stream_stats(),
+        dnstap: state.resolver.dnstap_stats(),
     }))
 }
 
+/// Statistics time series at the requested granularity
+///
+/// GET /api/status/series
+pub async fn status_series(
+    State(state): State<StatusState>,
+    Query(params): Query<StatsSeriesParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(load_stats_series(&state.db, params).await?))
+}
+
 /// Health check endpoint
 ///
 /// GET /api/health
@@ -209,6 +239,7 @@ pub fn status_router(state: StatusState) -> axum::Router {
     axum::Router::new()
         .route("/", get(system_status))
         .route("/health", get(health_check))
+        .route("/series", get(status_series))
         .with_state(state)
 }
 
@@ -17,7 +17,7 @@ use axum::{
 use serde::{Deserialize, Serialize};
 
 use crate::db::Database;
-use crate::dns::proxy::{ProxyManager, QueryStrategy};
+use crate::dns::proxy::{AntiPollutionConfig, ProxyManager, QueryStrategy};
 use crate::web::ApiError;
 
 /// Application state for strategy API
@@ -28,7 +28,7 @@ pub struct StrategyState {
 }
 
 /// Valid strategy types
-const VALID_STRATEGIES: &[&str] = &["concurrent", "fastest", "round_robin", "random"];
+const VALID_STRATEGIES: &[&str] = &["concurrent", "fastest", "round_robin", "random", "hedged"];
 
 /// Strategy configuration response
 #[derive(Debug, Serialize)]
@@ -44,6 +44,7 @@ impl From<QueryStrategy> for StrategyResponse {
             QueryStrategy::Fastest => "基于历史响应时间选择最快的服务器（首次查询自动使用并发策略探测）",
             QueryStrategy::RoundRobin => "按顺序轮流使用每个上游服务器",
             QueryStrategy::Random => "每次查询随机选择一个上游服务器",
+            QueryStrategy::Hedged => "优先查询最快的服务器，超过其近期 P90 延迟未响应时向次快服务器补发请求，先到先用",
         };
         Self {
             strategy: strategy.as_str().to_string(),
@@ -143,6 +144,8 @@ pub async fn update_strategy(
         details: None,
     })?;
 
+    super::settings::ensure_unmanaged(&state.db, &["query_strategy"]).await?;
+
     // Update strategy in proxy manager
     state.proxy_manager.set_strategy(strategy).await;
 
@@ -176,11 +179,59 @@ pub async fn get_available_strategies() -> Result<impl IntoResponse, ApiError> {
             name: "random".to_string(),
             description: "每次查询随机选择一个上游服务器".to_string(),
         },
+        StrategyInfo {
+            name: "hedged".to_string(),
+            description: "优先查询最快的服务器，超过其近期 P90 延迟未响应时向次快服务器补发请求，先到先用".to_string(),
+        },
     ];
 
     Ok(Json(AvailableStrategiesResponse { strategies }))
 }
 
+/// Get anti-pollution routing settings
+///
+/// GET /api/strategy/anti-pollution
+pub async fn get_anti_pollution(
+    State(state): State<StrategyState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let value = state.db.system_config().get("anti_pollution").await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to load anti-pollution settings: {}", e),
+        details: None,
+    })?;
+
+    let config = value
+        .and_then(|v| serde_json::from_str::<AntiPollutionConfig>(&v).ok())
+        .unwrap_or_default();
+    Ok(Json(config))
+}
+
+/// Update anti-pollution routing settings
+///
+/// PUT /api/strategy/anti-pollution
+///
+/// The CIDR list and GeoIP database are loaded before saving, so invalid
+/// settings are rejected without touching the running configuration.
+pub async fn update_anti_pollution(
+    State(state): State<StrategyState>,
+    Json(config): Json<AntiPollutionConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    super::settings::ensure_unmanaged(&state.db, &["anti_pollution"]).await?;
+
+    state.proxy_manager.set_anti_pollution(&config).await.map_err(|e| ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: e.to_string(),
+        details: None,
+    })?;
+
+    let value = serde_json::to_string(&config).unwrap_or_default();
+    if let Err(e) = state.db.system_config().set("anti_pollution", &value).await {
+        tracing::warn!("Failed to persist anti-pollution settings: {}", e);
+    }
+
+    Ok(Json(config))
+}
+
 /// Build the strategy API router
 pub fn strategy_router(state: StrategyState) -> axum::Router {
     use axum::routing::get;
@@ -188,6 +239,7 @@ pub fn strategy_router(state: StrategyState) -> axum::Router {
     axum::Router::new()
         .route("/", get(get_strategy).put(update_strategy))
         .route("/available", get(get_available_strategies))
+        .route("/anti-pollution", get(get_anti_pollution).put(update_anti_pollution))
         .with_state(state)
 }
 
@@ -0,0 +1,265 @@
+//! TSIG Keys API module
+//!
+//! REST endpoints for the keys that sign dynamic updates and for the
+//! audit log of the updates received.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{CreateTsigKey, Database, DnsUpdateLog, TsigKey, UpdateTsigKey};
+use crate::dns::{decode_secret, TsigAlgorithm};
+use crate::web::zones::normalize_host;
+use crate::web::ApiError;
+
+/// Audit entries returned when no limit is given
+const DEFAULT_LOG_LIMIT: i64 = 100;
+
+/// Most audit entries returned at once
+const MAX_LOG_LIMIT: i64 = 1000;
+
+/// Application state for the TSIG keys API
+#[derive(Clone)]
+pub struct TsigKeysState {
+    pub db: Arc<Database>,
+}
+
+/// API response wrapper for a single key
+#[derive(Debug, Serialize)]
+pub struct TsigKeyResponse {
+    pub data: TsigKey,
+}
+
+/// API response wrapper for multiple keys
+#[derive(Debug, Serialize)]
+pub struct TsigKeysListResponse {
+    pub data: Vec<TsigKey>,
+    pub total: usize,
+}
+
+/// API response wrapper for audit entries
+#[derive(Debug, Serialize)]
+pub struct UpdateLogResponse {
+    pub data: Vec<DnsUpdateLog>,
+    pub total: usize,
+}
+
+/// Key fields accepted on create and update
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TsigKeyRequest {
+    /// Only used on create; clients sign with the name
+    pub name: Option<String>,
+    pub algorithm: Option<String>,
+    /// Base64; a random 256-bit secret is generated on create when missing
+    pub secret: Option<String>,
+    /// Zones the key may update
+    pub zones: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+/// Query parameters of the audit log
+#[derive(Debug, Deserialize)]
+pub struct UpdateLogQuery {
+    pub zone: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: message.into(),
+        details: None,
+    }
+}
+
+fn internal(what: &str, e: anyhow::Error) -> ApiError {
+    ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to {}: {}", what, e),
+        details: None,
+    }
+}
+
+fn not_found(id: i64) -> ApiError {
+    ApiError {
+        code: "NOT_FOUND".to_string(),
+        message: format!("TSIG key with id {} not found", id),
+        details: None,
+    }
+}
+
+/// Random 256-bit secret in base64
+fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base64::engine::general_purpose::STANDARD.encode(secret)
+}
+
+impl TsigKeyRequest {
+    /// Validate and normalize the fields present, except the name
+    fn normalize(self) -> Result<UpdateTsigKey, ApiError> {
+        let algorithm = self
+            .algorithm
+            .map(|a| {
+                a.parse::<TsigAlgorithm>()
+                    .map(|a| a.name().to_string())
+                    .map_err(|_| bad_request(format!("Unsupported algorithm '{}', expected one of {}", a, TsigAlgorithm::NAMES.join(", "))))
+            })
+            .transpose()?;
+        let secret = self
+            .secret
+            .map(|s| s.trim().to_string())
+            .map(|s| match decode_secret(&s) {
+                Some(bytes) if !bytes.is_empty() => Ok(s),
+                _ => Err(bad_request("secret must be non-empty base64")),
+            })
+            .transpose()?;
+        let zones = match self.zones {
+            Some(list) => {
+                let zones = list
+                    .into_iter()
+                    .filter(|z| !z.trim().is_empty())
+                    .map(|z| normalize_host(&z).ok_or_else(|| bad_request(format!("Invalid zone: '{}'", z))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if zones.is_empty() {
+                    return Err(bad_request("A key needs at least one zone"));
+                }
+                Some(zones.join(","))
+            }
+            None => None,
+        };
+
+        Ok(UpdateTsigKey {
+            algorithm,
+            secret,
+            zones,
+            enabled: self.enabled,
+        })
+    }
+}
+
+/// List all keys
+///
+/// GET /api/tsig-keys
+pub async fn list_keys(State(state): State<TsigKeysState>) -> Result<impl IntoResponse, ApiError> {
+    let keys = state.db.tsig_keys().list().await.map_err(|e| internal("list TSIG keys", e))?;
+    Ok(Json(TsigKeysListResponse { total: keys.len(), data: keys }))
+}
+
+/// Create a key
+///
+/// POST /api/tsig-keys
+pub async fn create_key(
+    State(state): State<TsigKeysState>,
+    Json(request): Json<TsigKeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = request.name.clone().unwrap_or_default();
+    let name = normalize_host(&name).ok_or_else(|| bad_request(format!("Invalid key name: '{}'", name)))?;
+    let key = request.normalize()?;
+    let zones = key.zones.ok_or_else(|| bad_request("A key needs at least one zone"))?;
+
+    let existing = state.db.tsig_keys().get_by_name(&name).await.map_err(|e| internal("get TSIG key", e))?;
+    if existing.is_some() {
+        return Err(bad_request(format!("TSIG key {} already exists", name)));
+    }
+
+    let create = CreateTsigKey {
+        name,
+        algorithm: key.algorithm.unwrap_or_else(|| TsigAlgorithm::HmacSha256.name().to_string()),
+        secret: key.secret.unwrap_or_else(generate_secret),
+        zones,
+        enabled: key.enabled.unwrap_or(true),
+    };
+    let key = state.db.tsig_keys().create(create).await.map_err(|e| internal("create TSIG key", e))?;
+    Ok((StatusCode::CREATED, Json(TsigKeyResponse { data: key })))
+}
+
+/// Update a key
+///
+/// PUT /api/tsig-keys/:id
+pub async fn update_key(
+    State(state): State<TsigKeysState>,
+    Path(id): Path<i64>,
+    Json(request): Json<TsigKeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let update = request.normalize()?;
+    let key = state.db.tsig_keys().update(id, update).await.map_err(|e| internal("update TSIG key", e))?;
+    key.map(|key| Json(TsigKeyResponse { data: key })).ok_or_else(|| not_found(id))
+}
+
+/// Delete a key
+///
+/// DELETE /api/tsig-keys/:id
+pub async fn delete_key(
+    State(state): State<TsigKeysState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = state.db.tsig_keys().delete(id).await.map_err(|e| internal("delete TSIG key", e))?;
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found(id))
+    }
+}
+
+/// Recent dynamic updates, newest first
+///
+/// GET /api/tsig-keys/log?zone=&limit=
+pub async fn update_log(
+    State(state): State<TsigKeysState>,
+    Query(query): Query<UpdateLogQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let zone = query.zone.as_deref().map(str::trim).filter(|z| !z.is_empty()).map(|z| z.trim_end_matches('.').to_lowercase());
+    let limit = query.limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, MAX_LOG_LIMIT);
+    let entries = state
+        .db
+        .dns_update_log()
+        .list(zone.as_deref(), limit)
+        .await
+        .map_err(|e| internal("list dynamic updates", e))?;
+    Ok(Json(UpdateLogResponse { total: entries.len(), data: entries }))
+}
+
+/// Build the TSIG keys API router
+pub fn tsig_keys_router(state: TsigKeysState) -> axum::Router {
+    use axum::routing::{get, put};
+
+    axum::Router::new()
+        .route("/", get(list_keys).post(create_key))
+        .route("/log", get(update_log))
+        .route("/:id", put(update_key).delete(delete_key))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tsig_key_request_normalize() {
+        let update = TsigKeyRequest {
+            algorithm: Some("HMAC-SHA512.".to_string()),
+            secret: Some(" c2VjcmV0 ".to_string()),
+            zones: Some(vec!["Example.COM.".to_string(), "".to_string(), "lan".to_string()]),
+            ..Default::default()
+        }
+        .normalize()
+        .unwrap();
+        assert_eq!(update.algorithm.as_deref(), Some("hmac-sha512"));
+        assert_eq!(update.secret.as_deref(), Some("c2VjcmV0"));
+        assert_eq!(update.zones.as_deref(), Some("example.com,lan"));
+
+        assert!(TsigKeyRequest { algorithm: Some("hmac-md5".to_string()), ..Default::default() }.normalize().is_err());
+        assert!(TsigKeyRequest { secret: Some("not base64!".to_string()), ..Default::default() }.normalize().is_err());
+        assert!(TsigKeyRequest { zones: Some(vec![" ".to_string()]), ..Default::default() }.normalize().is_err());
+        assert!(decode_secret(&generate_secret()).is_some_and(|s| s.len() == 32));
+    }
+}
@@ -17,7 +17,7 @@ use axum::{
 use serde::{Deserialize, Serialize};
 
 use crate::db::{CreateUpstreamServer, Database, UpdateUpstreamServer, UpstreamServer};
-use crate::dns::proxy::UpstreamManager;
+use crate::dns::proxy::{parse_root_hints, HealthPolicy, OutboundConfig, UpstreamGroup, UpstreamManager, UpstreamProtocol};
 use crate::web::ApiError;
 
 /// Application state for upstream servers API
@@ -28,7 +28,7 @@ pub struct UpstreamsState {
 }
 
 /// Valid protocol types
-const VALID_PROTOCOLS: &[&str] = &["udp", "dot", "doh", "doq", "doh3"];
+const VALID_PROTOCOLS: &[&str] = &["udp", "dot", "doh", "doq", "doh3", "recursive"];
 
 /// Validation error details
 #[derive(Debug, Serialize)]
@@ -43,7 +43,7 @@ pub struct ValidationError {
 }
 
 /// Create upstream server request with validation
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateUpstreamServerRequest {
     pub name: String,
     pub address: String,
@@ -52,6 +52,21 @@ pub struct CreateUpstreamServerRequest {
     pub timeout: i32,
     #[serde(default = "default_enabled")]
     pub enabled: bool,
+    /// Proxy URL (socks5://[user:pass@]host:port or http://[user:pass@]host:port)
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Local source address
+    #[serde(default)]
+    pub bind_address: Option<String>,
+    /// Network interface (SO_BINDTODEVICE)
+    #[serde(default)]
+    pub bind_interface: Option<String>,
+    /// Anti-pollution group ("domestic" or "trusted")
+    #[serde(default)]
+    pub group_name: Option<String>,
+    /// Suspension thresholds and active probe settings (defaults when omitted)
+    #[serde(default)]
+    pub health_policy: Option<HealthPolicy>,
 }
 
 fn default_timeout() -> i32 {
@@ -70,6 +85,16 @@ pub struct UpdateUpstreamServerRequest {
     pub protocol: Option<String>,
     pub timeout: Option<i32>,
     pub enabled: Option<bool>,
+    /// Empty string clears the proxy
+    pub proxy: Option<String>,
+    /// Empty string clears the bind address
+    pub bind_address: Option<String>,
+    /// Empty string clears the bind interface
+    pub bind_interface: Option<String>,
+    /// Empty string clears the group
+    pub group_name: Option<String>,
+    /// Replaces the health policy
+    pub health_policy: Option<HealthPolicy>,
 }
 
 /// API response wrapper for single server
@@ -120,6 +145,15 @@ pub struct ServerStatus {
     pub avg_response_time_ms: u64,
     pub suspended: bool,
     pub suspension_remaining_secs: Option<u64>,
+    pub hedges: u64,
+    pub hedge_rate: f64,
+    pub hedge_wins: u64,
+    pub mismatches: u64,
+    /// "healthy", "suspended" or "down"
+    pub health_state: String,
+    pub probe_enabled: bool,
+    pub last_probe_ms: Option<u64>,
+    pub last_probe_error: Option<String>,
 }
 
 /// API response for server status
@@ -162,6 +196,12 @@ fn validate_address(address: &str, protocol: &str) -> Result<(), String> {
                 return Err("DoH/DoH3 address should be a URL (e.g., https://dns.google/dns-query)".to_string());
             }
         }
+        "recursive" => {
+            // "root" or a list of root server IPs
+            if let Err(e) = parse_root_hints(address) {
+                return Err(format!("Recursive address should be 'root' or root server IPs: {}", e));
+            }
+        }
         _ => {}
     }
     Ok(())
@@ -179,6 +219,41 @@ fn validate_protocol(protocol: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Validate outbound settings against the protocol
+///
+/// Returns the offending field name with the error message.
+fn validate_outbound(
+    proxy: Option<&str>,
+    bind_address: Option<&str>,
+    bind_interface: Option<&str>,
+    protocol: &str,
+) -> Result<(), (&'static str, String)> {
+    if let Err(e) = OutboundConfig::from_settings(proxy, None, None) {
+        return Err(("proxy", e.to_string()));
+    }
+    if let Err(e) = OutboundConfig::from_settings(None, bind_address, None) {
+        return Err(("bind_address", e.to_string()));
+    }
+    if let Err(e) = OutboundConfig::from_settings(None, None, bind_interface) {
+        return Err(("bind_interface", e.to_string()));
+    }
+
+    let outbound = OutboundConfig::from_settings(proxy, bind_address, bind_interface)
+        .map_err(|e| ("proxy", e.to_string()))?;
+    if let Some(protocol) = UpstreamProtocol::from_str(protocol) {
+        outbound.check_protocol(protocol).map_err(|e| ("proxy", e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Validate anti-pollution group name
+fn validate_group(group: &str) -> Result<(), String> {
+    if UpstreamGroup::from_str(group).is_none() {
+        return Err("Invalid group. Must be one of: domestic, trusted".to_string());
+    }
+    Ok(())
+}
+
 /// Validate timeout
 fn validate_timeout(timeout: i32) -> Result<(), String> {
     if timeout < 100 {
@@ -231,6 +306,36 @@ impl CreateUpstreamServerRequest {
             });
         }
 
+        if let Some(ref group) = self.group_name {
+            if let Err(e) = validate_group(group) {
+                errors.push(ValidationError {
+                    field: "group_name".to_string(),
+                    message: e,
+                });
+            }
+        }
+
+        if let Some(ref policy) = self.health_policy {
+            if let Err(e) = policy.validate() {
+                errors.push(ValidationError {
+                    field: "health_policy".to_string(),
+                    message: e.to_string(),
+                });
+            }
+        }
+
+        if let Err((field, e)) = validate_outbound(
+            self.proxy.as_deref(),
+            self.bind_address.as_deref(),
+            self.bind_interface.as_deref(),
+            &self.protocol,
+        ) {
+            errors.push(ValidationError {
+                field: field.to_string(),
+                message: e,
+            });
+        }
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -246,6 +351,11 @@ impl CreateUpstreamServerRequest {
             protocol: self.protocol.to_lowercase(),
             timeout: self.timeout,
             enabled: self.enabled,
+            proxy: self.proxy,
+            bind_address: self.bind_address,
+            bind_interface: self.bind_interface,
+            group_name: self.group_name.map(|g| g.trim().to_lowercase()),
+            health_policy: self.health_policy.map(|p| serde_json::to_string(&p).unwrap_or_default()),
         }
     }
 }
@@ -295,6 +405,38 @@ impl UpdateUpstreamServerRequest {
             }
         }
 
+        if let Some(ref group) = self.group_name {
+            if let Err(e) = validate_group(group) {
+                errors.push(ValidationError {
+                    field: "group_name".to_string(),
+                    message: e,
+                });
+            }
+        }
+
+        if let Some(ref policy) = self.health_policy {
+            if let Err(e) = policy.validate() {
+                errors.push(ValidationError {
+                    field: "health_policy".to_string(),
+                    message: e.to_string(),
+                });
+            }
+        }
+
+        // Validate the resulting outbound settings (new or existing)
+        let protocol = self.protocol.as_deref().unwrap_or(&existing.protocol);
+        if let Err((field, e)) = validate_outbound(
+            self.proxy.as_deref().or(existing.proxy.as_deref()),
+            self.bind_address.as_deref().or(existing.bind_address.as_deref()),
+            self.bind_interface.as_deref().or(existing.bind_interface.as_deref()),
+            protocol,
+        ) {
+            errors.push(ValidationError {
+                field: field.to_string(),
+                message: e,
+            });
+        }
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -310,6 +452,11 @@ impl UpdateUpstreamServerRequest {
             protocol: self.protocol.map(|p| p.to_lowercase()),
             timeout: self.timeout,
             enabled: self.enabled,
+            proxy: self.proxy,
+            bind_address: self.bind_address,
+            bind_interface: self.bind_interface,
+            group_name: self.group_name.map(|g| g.trim().to_lowercase()),
+            health_policy: self.health_policy.map(|p| serde_json::to_string(&p).unwrap_or_default()),
         }
     }
 }
@@ -421,6 +568,9 @@ pub async fn update_upstream(
         message: format!("Upstream server with id {} not found", id),
         details: None,
     })?;
+    if existing.managed {
+        return Err(ApiError::managed_by_file(format!("Upstream server {}", id)));
+    }
 
     // Validate request against existing server
     if let Err(validation_errors) = request.validate(&existing) {
@@ -463,6 +613,15 @@ pub async fn delete_upstream(
 ) -> Result<impl IntoResponse, ApiError> {
     let repo = state.db.upstream_servers();
 
+    let existing = repo.get_by_id(id).await.map_err(|e| ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to get upstream server: {}", e),
+        details: None,
+    })?;
+    if existing.is_some_and(|s| s.managed) {
+        return Err(ApiError::managed_by_file(format!("Upstream server {}", id)));
+    }
+
     let deleted = repo.delete(id).await.map_err(|e| ApiError {
         code: "INTERNAL_ERROR".to_string(),
         message: format!("Failed to delete upstream server: {}", e),
@@ -518,6 +677,18 @@ pub async fn get_status(
                 avg_response_time_ms: server_stats.map(|st| st.avg_response_time_ms()).unwrap_or(0),
                 suspended: server_stats.map(|st| st.is_suspended()).unwrap_or(false),
                 suspension_remaining_secs: server_stats.and_then(|st| st.suspension_remaining_secs()),
+                hedges: server_stats.map(|st| st.hedges).unwrap_or(0),
+                hedge_rate: server_stats.map(|st| st.hedge_rate()).unwrap_or(0.0),
+                hedge_wins: server_stats.map(|st| st.hedge_wins).unwrap_or(0),
+                mismatches: server_stats.map(|st| st.mismatches).unwrap_or(0),
+                health_state: server_stats
+                    .map(|st| st.health_state().to_string())
+                    .unwrap_or_else(|| "healthy".to_string()),
+                probe_enabled: HealthPolicy::from_json(s.health_policy.as_deref())
+                    .map(|p| p.probe_enabled)
+                    .unwrap_or(false),
+                last_probe_ms: server_stats.and_then(|st| st.last_probe_ms),
+                last_probe_error: server_stats.and_then(|st| st.last_probe_error.clone()),
             }
         })
         .collect();
@@ -559,14 +730,26 @@ pub async fn reset_health(
     })))
 }
 
+/// Get recent upstream health transitions (newest first)
+///
+/// GET /api/upstreams/health-events
+pub async fn get_health_events(
+    State(state): State<UpstreamsState>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(serde_json::json!({
+        "data": state.upstream_manager.recent_health_events()
+    })))
+}
+
 /// Build the upstream servers API router
 pub fn upstreams_router(state: UpstreamsState) -> axum::Router {
     use axum::routing::{get, post};
 
     // Note: More specific routes must come before parameterized routes
-    // /status must be before /:id to avoid being matched as an id
+    // /status and /health-events must be before /:id to avoid being matched as an id
     axum::Router::new()
         .route("/status", get(get_status))
+        .route("/health-events", get(get_health_events))
         .route("/", get(list_upstreams).post(create_upstream))
         .route("/:id", get(get_upstream).put(update_upstream).delete(delete_upstream))
         .route("/:id/reset-health", post(reset_health))
@@ -617,6 +800,13 @@ mod tests {
         assert!(validate_address("dns.google", "doh").is_err());
     }
 
+    #[test]
+    fn test_validate_address_recursive() {
+        assert!(validate_address("root", "recursive").is_ok());
+        assert!(validate_address("198.41.0.4, 199.9.14.201", "recursive").is_ok());
+        assert!(validate_address("a.root-servers.net", "recursive").is_err());
+    }
+
     #[test]
     fn test_validate_timeout() {
         assert!(validate_timeout(100).is_ok());
@@ -634,6 +824,11 @@ mod tests {
             protocol: "udp".to_string(),
             timeout: 5000,
             enabled: true,
+            proxy: None,
+            bind_address: None,
+            bind_interface: None,
+            group_name: None,
+            health_policy: None,
         };
         assert!(valid_request.validate().is_ok());
 
@@ -643,6 +838,11 @@ mod tests {
             protocol: "invalid".to_string(),
             timeout: 50,
             enabled: true,
+            proxy: None,
+            bind_address: None,
+            bind_interface: None,
+            group_name: None,
+            health_policy: None,
         };
         let result = invalid_request.validate();
         assert!(result.is_err());
@@ -656,8 +856,33 @@ mod tests {
             protocol: "UDP".to_string(),
             timeout: 5000,
             enabled: true,
+            proxy: None,
+            bind_address: None,
+            bind_interface: None,
+            group_name: None,
+            health_policy: None,
         };
         let create_server = request.into_create_upstream_server();
         assert_eq!(create_server.protocol, "udp");
     }
+
+    #[test]
+    fn test_validate_group() {
+        assert!(validate_group("domestic").is_ok());
+        assert!(validate_group("Trusted").is_ok());
+        assert!(validate_group("").is_ok());
+        assert!(validate_group("foreign").is_err());
+    }
+
+    #[test]
+    fn test_validate_outbound() {
+        assert!(validate_outbound(None, None, None, "udp").is_ok());
+        assert!(validate_outbound(Some("socks5://127.0.0.1:1080"), Some("10.0.0.2"), Some("eth0"), "doq").is_ok());
+        assert!(validate_outbound(Some("http://proxy:3128"), None, None, "dot").is_ok());
+
+        assert_eq!(validate_outbound(Some("http://proxy:3128"), None, None, "udp").unwrap_err().0, "proxy");
+        assert_eq!(validate_outbound(Some("ftp://proxy"), None, None, "doh").unwrap_err().0, "proxy");
+        assert_eq!(validate_outbound(None, Some("eth0"), None, "doh").unwrap_err().0, "bind_address");
+        assert_eq!(validate_outbound(None, None, Some("this-name-is-too-long"), "doh").unwrap_err().0, "bind_interface");
+    }
 }
@@ -0,0 +1,413 @@
+//! Authoritative Zones API module
+//!
+//! REST endpoints for the zones answered authoritatively from local records
+//! or transferred in from a primary. Serials are maintained by the resolver
+//! and cannot be set here.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{CreateDnsZone, Database, DnsZone, UpdateDnsZone, ZONE_KIND_SECONDARY};
+use crate::dns::{parse_endpoint, IpRangeSet, SecondaryStatus, ZoneTransfers};
+use crate::web::ApiError;
+
+/// Application state for the zones API
+#[derive(Clone)]
+pub struct ZonesState {
+    pub db: Arc<Database>,
+    pub transfers: Arc<ZoneTransfers>,
+}
+
+/// Zone with the refresh state of secondaries
+#[derive(Debug, Serialize)]
+pub struct ZoneView {
+    #[serde(flatten)]
+    pub zone: DnsZone,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_status: Option<SecondaryStatus>,
+}
+
+impl ZonesState {
+    fn view(&self, zone: DnsZone) -> ZoneView {
+        let transfer_status = zone
+            .is_secondary()
+            .then(|| self.transfers.status(zone.id).unwrap_or_default());
+        ZoneView { zone, transfer_status }
+    }
+}
+
+/// API response wrapper for a single zone
+#[derive(Debug, Serialize)]
+pub struct ZoneResponse {
+    pub data: ZoneView,
+}
+
+/// API response wrapper for multiple zones
+#[derive(Debug, Serialize)]
+pub struct ZonesListResponse {
+    pub data: Vec<ZoneView>,
+    pub total: usize,
+}
+
+/// Zone fields accepted on create and update
+///
+/// Also the shape of zones in configuration exports and the declarative file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ZoneRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_ns: Option<String>,
+    /// SOA RNAME; `hostmaster@example.com` is accepted too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nameservers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// `primary` or `secondary`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Servers a secondary zone is transferred from (`ip` or `ip:port`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primaries: Option<Vec<String>>,
+    /// Addresses and CIDRs allowed to transfer the zone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_acl: Option<Vec<String>>,
+    /// Servers sent a NOTIFY on changes (`ip` or `ip:port`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<Vec<String>>,
+}
+
+/// Lowercase a host name and drop the trailing dot, if it is valid
+pub(crate) fn normalize_host(name: &str) -> Option<String> {
+    let name = name.trim().trim_end_matches('.').to_lowercase();
+    let valid = !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    valid.then_some(name)
+}
+
+/// Turn `user@example.com` into the SOA form `user.example.com`
+fn normalize_mailbox(mailbox: &str) -> Option<String> {
+    normalize_host(&mailbox.trim().replacen('@', ".", 1))
+}
+
+/// Validate a list field entry by entry and join it for storage
+fn normalize_list(
+    field: &str,
+    list: Option<Vec<String>>,
+    valid: impl Fn(&str) -> bool,
+) -> Result<Option<String>, ApiError> {
+    let Some(list) = list else {
+        return Ok(None);
+    };
+    let mut items = Vec::new();
+    for item in list.iter().map(|i| i.trim()).filter(|i| !i.is_empty()) {
+        if !valid(item) {
+            return Err(bad_request(format!("Invalid {} entry: '{}'", field, item)));
+        }
+        items.push(item.to_string());
+    }
+    Ok(Some(items.join(",")))
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError {
+        code: "BAD_REQUEST".to_string(),
+        message: message.into(),
+        details: None,
+    }
+}
+
+impl ZoneRequest {
+    /// Validate and normalize the fields present
+    fn normalize(self) -> Result<UpdateDnsZone, ApiError> {
+        let host = |field: &str, value: Option<String>| {
+            value
+                .map(|v| normalize_host(&v).ok_or_else(|| bad_request(format!("Invalid {}: '{}'", field, v))))
+                .transpose()
+        };
+
+        for (field, value) in [
+            ("refresh", self.refresh),
+            ("retry", self.retry),
+            ("expire", self.expire),
+            ("minimum", self.minimum),
+            ("ttl", self.ttl),
+        ] {
+            if value.is_some_and(|v| v < 0) {
+                return Err(bad_request(format!("{} cannot be negative", field)));
+            }
+        }
+
+        let admin_email = self
+            .admin_email
+            .map(|v| normalize_mailbox(&v).ok_or_else(|| bad_request(format!("Invalid admin_email: '{}'", v))))
+            .transpose()?;
+        let nameservers = match self.nameservers {
+            Some(list) => Some(
+                list.into_iter()
+                    .filter(|ns| !ns.trim().is_empty())
+                    .map(|ns| normalize_host(&ns).ok_or_else(|| bad_request(format!("Invalid name server: '{}'", ns))))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(","),
+            ),
+            None => None,
+        };
+        if let Some(ref kind) = self.kind {
+            if kind != "primary" && kind != ZONE_KIND_SECONDARY {
+                return Err(bad_request(format!("Invalid kind '{}', expected primary or secondary", kind)));
+            }
+        }
+        let endpoint = |item: &str| parse_endpoint(item).is_some();
+        let primaries = normalize_list("primaries", self.primaries, endpoint)?;
+        let notify = normalize_list("notify", self.notify, endpoint)?;
+        let transfer_acl = normalize_list("transfer_acl", self.transfer_acl, |item| IpRangeSet::parse([item]).is_ok())?;
+
+        Ok(UpdateDnsZone {
+            origin: host("origin", self.origin)?,
+            primary_ns: host("primary_ns", self.primary_ns)?,
+            admin_email,
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minimum: self.minimum,
+            ttl: self.ttl,
+            nameservers,
+            enabled: self.enabled,
+            kind: self.kind,
+            primaries,
+            transfer_acl,
+            notify,
+        })
+    }
+
+    /// Validate a new zone and fill in the defaults
+    pub(crate) fn into_create_dns_zone(self) -> Result<CreateDnsZone, ApiError> {
+        let zone = self.normalize()?;
+        let secondary = zone.kind.as_deref() == Some(ZONE_KIND_SECONDARY);
+        let (origin, primary_ns, admin_email) = match (zone.origin, zone.primary_ns, zone.admin_email) {
+            (Some(origin), Some(primary_ns), Some(admin_email)) => (origin, primary_ns, admin_email),
+            // The SOA of a secondary zone comes from its primary
+            (Some(origin), primary_ns, admin_email) if secondary => {
+                (origin, primary_ns.unwrap_or_default(), admin_email.unwrap_or_default())
+            }
+            _ => return Err(bad_request("origin, primary_ns and admin_email are required")),
+        };
+        if secondary && zone.primaries.as_deref().unwrap_or_default().is_empty() {
+            return Err(bad_request("A secondary zone needs at least one primary"));
+        }
+
+        let defaults = CreateDnsZone::new(origin, primary_ns, admin_email);
+        Ok(CreateDnsZone {
+            refresh: zone.refresh.unwrap_or(defaults.refresh),
+            retry: zone.retry.unwrap_or(defaults.retry),
+            expire: zone.expire.unwrap_or(defaults.expire),
+            minimum: zone.minimum.unwrap_or(defaults.minimum),
+            ttl: zone.ttl.unwrap_or(defaults.ttl),
+            nameservers: zone.nameservers.filter(|ns| !ns.is_empty()).unwrap_or_else(|| defaults.nameservers.clone()),
+            enabled: zone.enabled.unwrap_or(defaults.enabled),
+            kind: zone.kind.unwrap_or_else(|| defaults.kind.clone()),
+            primaries: zone.primaries.unwrap_or_default(),
+            transfer_acl: zone.transfer_acl.unwrap_or_default(),
+            notify: zone.notify.unwrap_or_default(),
+            ..defaults
+        })
+    }
+}
+
+fn internal(what: &str, e: anyhow::Error) -> ApiError {
+    ApiError {
+        code: "INTERNAL_ERROR".to_string(),
+        message: format!("Failed to {}: {}", what, e),
+        details: None,
+    }
+}
+
+fn not_found(id: i64) -> ApiError {
+    ApiError {
+        code: "NOT_FOUND".to_string(),
+        message: format!("Zone with id {} not found", id),
+        details: None,
+    }
+}
+
+/// Reject an origin already used by another zone
+async fn ensure_unique_origin(db: &Database, origin: &str, id: Option<i64>) -> Result<(), ApiError> {
+    let existing = db.dns_zones().get_by_origin(origin).await.map_err(|e| internal("get zone", e))?;
+    match existing {
+        Some(zone) if Some(zone.id) != id => Err(bad_request(format!("Zone {} already exists", origin))),
+        _ => Ok(()),
+    }
+}
+
+/// List all zones
+///
+/// GET /api/zones
+pub async fn list_zones(State(state): State<ZonesState>) -> Result<impl IntoResponse, ApiError> {
+    let zones = state.db.dns_zones().list().await.map_err(|e| internal("list zones", e))?;
+    Ok(Json(ZonesListResponse {
+        total: zones.len(),
+        data: zones.into_iter().map(|zone| state.view(zone)).collect(),
+    }))
+}
+
+/// Get a zone by ID
+///
+/// GET /api/zones/:id
+pub async fn get_zone(
+    State(state): State<ZonesState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let zone = state.db.dns_zones().get_by_id(id).await.map_err(|e| internal("get zone", e))?;
+    zone.map(|zone| Json(ZoneResponse { data: state.view(zone) })).ok_or_else(|| not_found(id))
+}
+
+/// Create a zone
+///
+/// POST /api/zones
+pub async fn create_zone(
+    State(state): State<ZonesState>,
+    Json(request): Json<ZoneRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let create = request.into_create_dns_zone()?;
+    ensure_unique_origin(&state.db, &create.origin, None).await?;
+
+    let zone = state.db.dns_zones().create(create).await.map_err(|e| internal("create zone", e))?;
+    Ok((StatusCode::CREATED, Json(ZoneResponse { data: state.view(zone) })))
+}
+
+/// Update a zone
+///
+/// PUT /api/zones/:id
+pub async fn update_zone(
+    State(state): State<ZonesState>,
+    Path(id): Path<i64>,
+    Json(request): Json<ZoneRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let update = request.normalize()?;
+    if let Some(ref origin) = update.origin {
+        ensure_unique_origin(&state.db, origin, Some(id)).await?;
+    }
+    let existing = state.db.dns_zones().get_by_id(id).await.map_err(|e| internal("get zone", e))?;
+    let existing = existing.ok_or_else(|| not_found(id))?;
+    if existing.managed {
+        return Err(ApiError::managed_by_file(format!("Zone {}", existing.origin)));
+    }
+    let secondary = update.kind.as_deref().unwrap_or(&existing.kind) == ZONE_KIND_SECONDARY;
+    if secondary && update.primaries.as_deref().unwrap_or(&existing.primaries).is_empty() {
+        return Err(bad_request("A secondary zone needs at least one primary"));
+    }
+    if !secondary && update.nameservers.as_deref() == Some("") {
+        return Err(bad_request("A zone needs at least one name server"));
+    }
+
+    let zone = state.db.dns_zones().update(id, update).await.map_err(|e| internal("update zone", e))?;
+    zone.map(|zone| Json(ZoneResponse { data: state.view(zone) })).ok_or_else(|| not_found(id))
+}
+
+/// Check a secondary zone against its primaries now
+///
+/// POST /api/zones/:id/refresh
+pub async fn refresh_zone(
+    State(state): State<ZonesState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let zone = state.db.dns_zones().get_by_id(id).await.map_err(|e| internal("get zone", e))?;
+    let zone = zone.ok_or_else(|| not_found(id))?;
+    if !zone.is_secondary() {
+        return Err(bad_request(format!("Zone {} is not a secondary zone", zone.origin)));
+    }
+    state.transfers.refresh_now(id);
+    Ok(Json(ZoneResponse { data: state.view(zone) }))
+}
+
+/// Delete a zone; its records are kept and forwarded again
+///
+/// DELETE /api/zones/:id
+pub async fn delete_zone(
+    State(state): State<ZonesState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let existing = state.db.dns_zones().get_by_id(id).await.map_err(|e| internal("get zone", e))?;
+    if let Some(zone) = existing.filter(|zone| zone.managed) {
+        return Err(ApiError::managed_by_file(format!("Zone {}", zone.origin)));
+    }
+
+    let deleted = state.db.dns_zones().delete(id).await.map_err(|e| internal("delete zone", e))?;
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found(id))
+    }
+}
+
+/// Build the zones API router
+pub fn zones_router(state: ZonesState) -> axum::Router {
+    use axum::routing::{get, post};
+
+    axum::Router::new()
+        .route("/", get(list_zones).post(create_zone))
+        .route("/:id", get(get_zone).put(update_zone).delete(delete_zone))
+        .route("/:id/refresh", post(refresh_zone))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zone_request_normalize() {
+        let update = ZoneRequest {
+            origin: Some("Example.COM.".to_string()),
+            admin_email: Some("hostmaster@example.com".to_string()),
+            nameservers: Some(vec!["ns1.example.com.".to_string(), " ".to_string(), "NS2.example.com".to_string()]),
+            ..Default::default()
+        }
+        .normalize()
+        .unwrap();
+        assert_eq!(update.origin.as_deref(), Some("example.com"));
+        assert_eq!(update.admin_email.as_deref(), Some("hostmaster.example.com"));
+        assert_eq!(update.nameservers.as_deref(), Some("ns1.example.com,ns2.example.com"));
+
+        assert!(ZoneRequest { origin: Some("*.example.com".to_string()), ..Default::default() }.normalize().is_err());
+        assert!(ZoneRequest { minimum: Some(-1), ..Default::default() }.normalize().is_err());
+
+        let update = ZoneRequest {
+            kind: Some("secondary".to_string()),
+            primaries: Some(vec!["192.0.2.1".to_string(), "[2001:db8::1]:5353".to_string()]),
+            transfer_acl: Some(vec!["10.0.0.0/8".to_string(), "".to_string()]),
+            ..Default::default()
+        }
+        .normalize()
+        .unwrap();
+        assert_eq!(update.primaries.as_deref(), Some("192.0.2.1,[2001:db8::1]:5353"));
+        assert_eq!(update.transfer_acl.as_deref(), Some("10.0.0.0/8"));
+        assert!(ZoneRequest { kind: Some("stub".to_string()), ..Default::default() }.normalize().is_err());
+        assert!(ZoneRequest { primaries: Some(vec!["ns1.example.com".to_string()]), ..Default::default() }.normalize().is_err());
+        assert!(ZoneRequest { transfer_acl: Some(vec!["10.0.0.0/33".to_string()]), ..Default::default() }.normalize().is_err());
+    }
+}
@@ -23,6 +23,16 @@ const routes: RouteRecordRaw[] = [
         name: 'DnsRecords',
         component: () => import('../views/DnsRecords.vue')
       },
+      {
+        path: 'zones',
+        name: 'Zones',
+        component: () => import('../views/Zones.vue')
+      },
+      {
+        path: 'tsig-keys',
+        name: 'TsigKeys',
+        component: () => import('../views/TsigKeys.vue')
+      },
       {
         path: 'rewrite',
         name: 'RewriteRules',
@@ -48,6 +58,16 @@ const routes: RouteRecordRaw[] = [
         name: 'QueryLogs',
         component: () => import('../views/QueryLogs.vue')
       },
+      {
+        path: 'live',
+        name: 'LiveQueries',
+        component: () => import('../views/LiveQueries.vue')
+      },
+      {
+        path: 'passive-dns',
+        name: 'PassiveDns',
+        component: () => import('../views/PassiveDns.vue')
+      },
       {
         path: 'listeners',
         name: 'Listeners',